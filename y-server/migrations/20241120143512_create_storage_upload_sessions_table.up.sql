@@ -0,0 +1,25 @@
+CREATE TABLE public.storage_upload_sessions
+(
+    id uuid NOT NULL,
+    endpoint_id integer NOT NULL,
+    target_folder bigint,
+    name character varying(256) NOT NULL,
+    extension character varying(256),
+    size_bytes bigint NOT NULL,
+    received_bytes bigint NOT NULL DEFAULT 0,
+    created_by integer NOT NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    expires_on timestamp with time zone NOT NULL,
+    PRIMARY KEY (id),
+    CONSTRAINT storage_upload_sessions_received_bytes_check CHECK (received_bytes >= 0 AND received_bytes <= size_bytes),
+    FOREIGN KEY (endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID,
+    FOREIGN KEY (created_by)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID
+);
@@ -0,0 +1,13 @@
+DROP TABLE public.storage_s3_endpoints;
+
+DELETE FROM public.storage_endpoints WHERE endpoint_type = 's3'::storage_endpoint_type;
+
+ALTER TYPE public.storage_endpoint_type RENAME TO storage_endpoint_type_old;
+
+CREATE TYPE public.storage_endpoint_type AS ENUM
+    ('local_fs');
+
+ALTER TABLE public.storage_endpoints
+    ALTER COLUMN endpoint_type TYPE storage_endpoint_type USING endpoint_type::TEXT::storage_endpoint_type;
+
+DROP TYPE public.storage_endpoint_type_old;
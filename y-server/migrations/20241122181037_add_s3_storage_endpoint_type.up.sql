@@ -0,0 +1,18 @@
+ALTER TYPE public.storage_endpoint_type ADD VALUE IF NOT EXISTS 's3';
+
+CREATE TABLE public.storage_s3_endpoints
+(
+    endpoint_id integer NOT NULL,
+    bucket character varying(255) NOT NULL,
+    prefix character varying(512),
+    region character varying(64) NOT NULL,
+    endpoint_url character varying(512),
+    access_key_id character varying(256),
+    secret_access_key character varying(256),
+    PRIMARY KEY (endpoint_id),
+    FOREIGN KEY (endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID
+);
@@ -0,0 +1,8 @@
+DELETE FROM public.config WHERE key = 'storage.trash.retention_days';
+
+DROP TABLE IF EXISTS public.storage_trash_entries;
+DROP TABLE IF EXISTS public.storage_trash;
+
+-- Enum values can not be removed, we can only make sure that nothing uses this one
+DELETE FROM public.storage_access WHERE action = 'manage_trash'::storage_access_action_type;
+DELETE FROM public.storage_access_template_rules WHERE action = 'manage_trash'::storage_access_action_type;
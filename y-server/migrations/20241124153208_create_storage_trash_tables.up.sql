@@ -0,0 +1,48 @@
+ALTER TYPE storage_access_action_type ADD VALUE IF NOT EXISTS 'manage_trash';
+
+CREATE TABLE public.storage_trash
+(
+    id bigserial NOT NULL,
+    endpoint_id integer NOT NULL,
+    entry_id bigint NOT NULL,
+    entry_type storage_entry_type NOT NULL,
+    name character varying(256) NOT NULL,
+    extension character varying(256),
+    original_parent_folder bigint,
+    original_path text NOT NULL,
+    deleted_by integer,
+    deleted_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID,
+    FOREIGN KEY (deleted_by)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE SET NULL
+        NOT VALID
+);
+
+CREATE INDEX storage_trash_deleted_at_idx ON public.storage_trash (deleted_at);
+
+-- Every storage entry that was removed together with a trashed entry (including the trashed entry itself).
+-- `entry` is a snapshot of the whole storage_entries row, so it can be put back as is.
+CREATE TABLE public.storage_trash_entries
+(
+    trash_id bigint NOT NULL,
+    entry_id bigint NOT NULL,
+    filesystem_id character(36),
+    size_bytes bigint,
+    entry jsonb NOT NULL,
+    template_ids integer[] NOT NULL DEFAULT '{}',
+    PRIMARY KEY (trash_id, entry_id),
+    FOREIGN KEY (trash_id)
+        REFERENCES public.storage_trash (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID
+);
+
+INSERT INTO public.config (key,value) VALUES ('storage.trash.retention_days','30');
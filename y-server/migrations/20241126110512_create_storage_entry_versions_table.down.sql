@@ -0,0 +1,3 @@
+DROP TABLE public.storage_entry_versions;
+
+ALTER TABLE public.storage_endpoints DROP COLUMN max_file_versions;
@@ -0,0 +1,30 @@
+ALTER TABLE public.storage_endpoints ADD COLUMN max_file_versions integer NOT NULL DEFAULT 10;
+
+-- Previous contents of overwritten files.
+-- `entry_id` does not reference storage_entries, so versions survive while their entry is in the trash.
+CREATE TABLE public.storage_entry_versions
+(
+    id bigserial NOT NULL,
+    endpoint_id integer NOT NULL,
+    entry_id bigint NOT NULL,
+    filesystem_id character(36) NOT NULL,
+    mime_type character varying(256),
+    size_bytes bigint,
+    created_by integer,
+    created_at timestamp with time zone,
+    archived_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    UNIQUE (filesystem_id),
+    FOREIGN KEY (endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID,
+    FOREIGN KEY (created_by)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE SET NULL
+        NOT VALID
+);
+
+CREATE INDEX storage_entry_versions_entry_idx ON public.storage_entry_versions (endpoint_id, entry_id);
@@ -0,0 +1,31 @@
+-- Public links that give people without an account access to a single entry.
+-- `entry_id` does not reference storage_entries, so links keep working if their entry is restored from the trash.
+CREATE TABLE public.storage_share_links
+(
+    id serial NOT NULL,
+    endpoint_id integer NOT NULL,
+    entry_id bigint NOT NULL,
+    token character varying(64) NOT NULL,
+    password text,
+    expires_at timestamp with time zone,
+    max_downloads integer,
+    downloads_count integer NOT NULL DEFAULT 0,
+    allow_browsing boolean NOT NULL DEFAULT false,
+    created_by integer NOT NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    UNIQUE (token),
+    CONSTRAINT storage_share_links_max_downloads_check CHECK (max_downloads IS NULL OR max_downloads > 0),
+    FOREIGN KEY (endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID,
+    FOREIGN KEY (created_by)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID
+);
+
+CREATE INDEX storage_share_links_entry_idx ON public.storage_share_links (endpoint_id, entry_id);
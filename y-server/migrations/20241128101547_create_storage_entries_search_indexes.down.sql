@@ -0,0 +1,2 @@
+DROP INDEX public.storage_entries_name_trgm_idx;
+DROP INDEX public.storage_entries_name_tsvector_idx;
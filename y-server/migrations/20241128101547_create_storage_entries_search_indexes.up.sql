@@ -0,0 +1,5 @@
+-- Indexes used by the search API. Search always uses the 'simple' configuration, names are not in any particular language.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX storage_entries_name_tsvector_idx ON public.storage_entries USING GIN (to_tsvector('simple', name));
+CREATE INDEX storage_entries_name_trgm_idx ON public.storage_entries USING GIN (name gin_trgm_ops);
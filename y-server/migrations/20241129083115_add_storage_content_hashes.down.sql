@@ -0,0 +1,13 @@
+-- Fails if some blobs are shared between multiple files
+DROP INDEX public.storage_entries_sha256_idx;
+DROP INDEX public.storage_trash_entries_filesystem_id_idx;
+DROP INDEX public.storage_entry_versions_filesystem_id_idx;
+DROP INDEX public.storage_entries_filesystem_id_idx;
+
+ALTER TABLE public.storage_entry_versions ADD CONSTRAINT storage_entry_versions_filesystem_id_key UNIQUE (filesystem_id);
+ALTER TABLE public.storage_entries ADD CONSTRAINT storage_entries_filesystem_id_key UNIQUE (filesystem_id);
+
+ALTER TABLE public.storage_endpoints DROP COLUMN deduplication_enabled;
+
+ALTER TABLE public.storage_entry_versions DROP COLUMN sha256;
+ALTER TABLE public.storage_entries DROP COLUMN sha256;
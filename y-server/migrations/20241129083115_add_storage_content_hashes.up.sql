@@ -0,0 +1,16 @@
+-- SHA-256 of a file's contents. NULL if unknown (files created before hashing was introduced, or modified through the VFS).
+ALTER TABLE public.storage_entries ADD COLUMN sha256 character varying(64);
+ALTER TABLE public.storage_entry_versions ADD COLUMN sha256 character varying(64);
+
+-- Files with the same contents can share a single blob if deduplication is enabled for the endpoint
+ALTER TABLE public.storage_endpoints ADD COLUMN deduplication_enabled boolean NOT NULL DEFAULT false;
+
+ALTER TABLE public.storage_entries DROP CONSTRAINT storage_entries_filesystem_id_key;
+ALTER TABLE public.storage_entry_versions DROP CONSTRAINT storage_entry_versions_filesystem_id_key;
+
+-- Used to find out whether a blob is still referenced by anything before deleting it
+CREATE INDEX storage_entries_filesystem_id_idx ON public.storage_entries (endpoint_id, filesystem_id);
+CREATE INDEX storage_entry_versions_filesystem_id_idx ON public.storage_entry_versions (endpoint_id, filesystem_id);
+CREATE INDEX storage_trash_entries_filesystem_id_idx ON public.storage_trash_entries (filesystem_id);
+
+CREATE INDEX storage_entries_sha256_idx ON public.storage_entries (endpoint_id, sha256) WHERE sha256 IS NOT NULL;
@@ -0,0 +1,3 @@
+DROP INDEX public.storage_entries_created_by_idx;
+
+ALTER TABLE public.storage_endpoints DROP COLUMN user_quota_bytes;
@@ -0,0 +1,6 @@
+-- How many bytes each user can store on the endpoint. `0` == unlimited.
+-- Group-wide quotas (across all endpoints) are set with the `storage_quota` right.
+ALTER TABLE public.storage_endpoints ADD COLUMN user_quota_bytes bigint NOT NULL DEFAULT 0;
+
+-- Usage is computed by summing the sizes of the files each user has created
+CREATE INDEX storage_entries_created_by_idx ON public.storage_entries (created_by, endpoint_id);
@@ -0,0 +1,12 @@
+DROP TABLE public.jobs;
+
+DROP TYPE job_status;
+
+DELETE FROM public.config
+  WHERE "key"='jobs.workers';
+
+DELETE FROM public.config
+  WHERE "key"='jobs.max_attempts';
+
+DELETE FROM public.config
+  WHERE "key"='jobs.retention_days';
@@ -0,0 +1,31 @@
+CREATE TYPE job_status AS ENUM ('queued', 'running', 'completed', 'failed', 'cancelled');
+
+CREATE TABLE public.jobs
+(
+    id bigserial NOT NULL,
+    job_type character varying(64) NOT NULL,
+    payload jsonb NOT NULL,
+    status job_status NOT NULL DEFAULT 'queued',
+    attempts integer NOT NULL DEFAULT 0,
+    max_attempts integer NOT NULL DEFAULT 3,
+    last_error text,
+    created_by integer,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    run_after timestamp with time zone NOT NULL DEFAULT now(),
+    started_at timestamp with time zone,
+    finished_at timestamp with time zone,
+    PRIMARY KEY (id),
+    FOREIGN KEY (created_by)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE SET NULL
+        NOT VALID
+);
+
+CREATE INDEX jobs_queued_idx ON public.jobs (run_after, id) WHERE status = 'queued';
+CREATE INDEX jobs_status_idx ON public.jobs (status, created_at);
+
+INSERT INTO public.config (key,value) VALUES
+  ('jobs.workers','2'),
+  ('jobs.max_attempts','3'),
+  ('jobs.retention_days','7');
@@ -0,0 +1 @@
+DROP TABLE public.storage_entry_paths;
@@ -0,0 +1,19 @@
+-- Where the entries of endpoints with `preserve_file_structure` are stored on disk, relative to the endpoint's base path.
+-- Rows are removed lazily: an entry that no longer exists (or a file that now has a different blob) is cleaned up
+-- the next time the endpoint's file structure is synced.
+CREATE TABLE public.storage_entry_paths
+(
+    endpoint_id integer NOT NULL,
+    entry_id bigint NOT NULL,
+    filesystem_id character(36),
+    relative_path text NOT NULL,
+    PRIMARY KEY (endpoint_id, entry_id),
+    FOREIGN KEY (endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID
+);
+
+CREATE INDEX storage_entry_paths_filesystem_id_idx ON public.storage_entry_paths (endpoint_id, filesystem_id);
+CREATE INDEX storage_entry_paths_relative_path_idx ON public.storage_entry_paths (endpoint_id, relative_path text_pattern_ops);
@@ -0,0 +1 @@
+DROP TABLE public.storage_imported_entries;
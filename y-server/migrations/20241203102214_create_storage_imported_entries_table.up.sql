@@ -0,0 +1,23 @@
+-- Entries that were created by importing an existing directory tree into an endpoint.
+-- Used to tell which files have changed or disappeared when the same directory is imported again.
+CREATE TABLE public.storage_imported_entries
+(
+    endpoint_id integer NOT NULL,
+    entry_id bigint NOT NULL,
+    -- Absolute path of the file or folder that was copied into the endpoint. NULL for entries that were adopted in place,
+    -- those are found through `storage_entry_paths`
+    source_path text,
+    size_bytes bigint,
+    modified_at timestamp with time zone,
+    PRIMARY KEY (endpoint_id, entry_id),
+    FOREIGN KEY (endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID,
+    FOREIGN KEY (entry_id)
+        REFERENCES public.storage_entries (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID
+);
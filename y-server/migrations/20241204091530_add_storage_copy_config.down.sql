@@ -0,0 +1,2 @@
+DELETE FROM public.config
+  WHERE "key"='storage.copy.background_threshold_bytes';
@@ -0,0 +1,3 @@
+-- Copies of files and folders larger than this (in bytes) run in the background
+INSERT INTO public.config (key,value) VALUES
+  ('storage.copy.background_threshold_bytes','104857600');
@@ -0,0 +1,3 @@
+DROP TABLE public.storage_move_blobs;
+DROP TABLE public.storage_moves;
+DROP TYPE storage_move_status;
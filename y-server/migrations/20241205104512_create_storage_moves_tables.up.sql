@@ -0,0 +1,49 @@
+-- Moves of entries to another endpoint that have not been finished yet. Blobs are copied first (`copying`), then all
+-- the entries are switched over to the target endpoint at once, then the blobs are removed from the source endpoint
+-- (`cleanup`). The row is removed when the move is done, so that an interrupted move can pick up where it has stopped.
+CREATE TYPE storage_move_status AS ENUM ('copying', 'cleanup');
+
+CREATE TABLE public.storage_moves
+(
+    id bigserial NOT NULL,
+    source_endpoint_id integer NOT NULL,
+    entry_ids bigint[] NOT NULL,
+    target_endpoint_id integer NOT NULL,
+    target_folder bigint,
+    keep_access_rules boolean NOT NULL,
+    status storage_move_status NOT NULL DEFAULT 'copying',
+    created_by integer,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (source_endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID,
+    FOREIGN KEY (target_endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID,
+    FOREIGN KEY (created_by)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE SET NULL
+        NOT VALID
+);
+
+-- Blobs of a move and their copies on the target endpoint. The id of the copy is chosen before anything is copied, so
+-- a copy that was interrupted is overwritten instead of being left behind.
+CREATE TABLE public.storage_move_blobs
+(
+    move_id bigint NOT NULL,
+    source_filesystem_id character(36) NOT NULL,
+    target_filesystem_id character(36) NOT NULL,
+    copied boolean NOT NULL DEFAULT false,
+    PRIMARY KEY (move_id, source_filesystem_id),
+    FOREIGN KEY (move_id)
+        REFERENCES public.storage_moves (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID
+);
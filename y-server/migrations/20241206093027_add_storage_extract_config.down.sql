@@ -0,0 +1,2 @@
+DELETE FROM public.config
+  WHERE "key" IN ('storage.extract.max_total_bytes','storage.extract.max_entries','storage.extract.max_compression_ratio');
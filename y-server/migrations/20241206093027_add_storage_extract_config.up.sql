@@ -0,0 +1,5 @@
+-- Limits for extracting archives on the server, to guard against archive bombs
+INSERT INTO public.config (key,value) VALUES
+  ('storage.extract.max_total_bytes','10737418240'),
+  ('storage.extract.max_entries','100000'),
+  ('storage.extract.max_compression_ratio','1000');
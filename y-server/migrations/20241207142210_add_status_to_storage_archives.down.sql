@@ -0,0 +1,18 @@
+DELETE FROM public.config
+  WHERE "key" IN ('storage.archives.retention_hours','storage.archives.compression_level','storage.archives.max_size_bytes');
+
+ALTER TABLE IF EXISTS public.storage_archives
+    ADD COLUMN ready boolean NOT NULL DEFAULT FALSE;
+
+UPDATE public.storage_archives SET ready = TRUE WHERE status = 'ready';
+
+ALTER TABLE IF EXISTS public.storage_archives
+    DROP COLUMN status,
+    DROP COLUMN error,
+    DROP COLUMN total_files,
+    DROP COLUMN processed_files,
+    DROP COLUMN total_bytes,
+    DROP COLUMN processed_bytes,
+    DROP COLUMN finished_at;
+
+DROP TYPE storage_archive_status;
@@ -0,0 +1,36 @@
+-- Archives are written by a job (`running`) some time after they were requested (`queued`). A failed archive keeps the
+-- reason in `error` until it expires, just like a `ready` one keeps its file.
+CREATE TYPE storage_archive_status AS ENUM ('queued', 'running', 'failed', 'ready');
+
+ALTER TABLE IF EXISTS public.storage_archives
+    ADD COLUMN status storage_archive_status NOT NULL DEFAULT 'queued';
+
+UPDATE public.storage_archives SET status = 'ready' WHERE ready;
+
+ALTER TABLE IF EXISTS public.storage_archives
+    DROP COLUMN ready;
+
+ALTER TABLE IF EXISTS public.storage_archives
+    ADD COLUMN error text;
+
+ALTER TABLE IF EXISTS public.storage_archives
+    ADD COLUMN total_files integer;
+
+ALTER TABLE IF EXISTS public.storage_archives
+    ADD COLUMN processed_files integer NOT NULL DEFAULT 0;
+
+ALTER TABLE IF EXISTS public.storage_archives
+    ADD COLUMN total_bytes bigint;
+
+ALTER TABLE IF EXISTS public.storage_archives
+    ADD COLUMN processed_bytes bigint NOT NULL DEFAULT 0;
+
+ALTER TABLE IF EXISTS public.storage_archives
+    ADD COLUMN finished_at timestamp with time zone;
+
+UPDATE public.storage_archives SET finished_at = created_at WHERE status = 'ready';
+
+INSERT INTO public.config (key,value) VALUES
+  ('storage.archives.retention_hours','12'),
+  ('storage.archives.compression_level','6'),
+  ('storage.archives.max_size_bytes','0');
@@ -0,0 +1,2 @@
+DELETE FROM public.config
+  WHERE "key" IN ('storage.transcode_videos.hls.enabled','storage.transcode_videos.hls.heights','storage.transcode_videos.hls.bitrates');
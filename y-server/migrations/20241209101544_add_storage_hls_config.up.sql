@@ -0,0 +1,5 @@
+-- HLS renditions of uploaded videos, heights (px) are paired with video bitrates (kbps) in order
+INSERT INTO public.config (key,value) VALUES
+  ('storage.transcode_videos.hls.enabled','false'),
+  ('storage.transcode_videos.hls.heights','360,720,1080'),
+  ('storage.transcode_videos.hls.bitrates','800,2800,5000');
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS public.storage_media_metadata;
@@ -0,0 +1,30 @@
+-- Metadata extracted from the contents of images, videos and audio files.
+-- Keyed by blob, files that share a blob (deduplicated files, versions) share their metadata.
+CREATE TABLE public.storage_media_metadata
+(
+    endpoint_id integer NOT NULL,
+    filesystem_id character(36) NOT NULL,
+    width integer,
+    height integer,
+    duration_seconds double precision,
+    video_codec character varying(64),
+    audio_codec character varying(64),
+    frame_rate double precision,
+    camera_make character varying(256),
+    camera_model character varying(256),
+    taken_at timestamp without time zone,
+    orientation smallint,
+    gps_latitude double precision,
+    gps_longitude double precision,
+    artist character varying(256),
+    album character varying(256),
+    title character varying(256),
+    track_number integer,
+    extracted_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (endpoint_id, filesystem_id),
+    FOREIGN KEY (endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID
+);
@@ -0,0 +1,10 @@
+ALTER TABLE IF EXISTS public.storage_trash_entries DROP COLUMN IF EXISTS properties;
+ALTER TABLE IF EXISTS public.storage_trash_entries DROP COLUMN IF EXISTS tags;
+
+DROP TABLE IF EXISTS public.storage_entry_properties;
+DROP TABLE IF EXISTS public.storage_entry_tags;
+DROP TABLE IF EXISTS public.storage_tags;
+
+-- Enum values can not be removed, we can only make sure that nothing uses this one
+DELETE FROM public.storage_access WHERE action = 'manage_tags'::storage_access_action_type;
+DELETE FROM public.storage_access_template_rules WHERE action = 'manage_tags'::storage_access_action_type;
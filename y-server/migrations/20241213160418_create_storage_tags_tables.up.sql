@@ -0,0 +1,64 @@
+ALTER TYPE storage_access_action_type ADD VALUE IF NOT EXISTS 'manage_tags';
+
+-- Tags are shared by all the entries of an endpoint. Names are unique regardless of case.
+CREATE TABLE public.storage_tags
+(
+    id serial NOT NULL,
+    endpoint_id integer NOT NULL,
+    name character varying(64) NOT NULL,
+    created_by integer,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID,
+    FOREIGN KEY (created_by)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE SET NULL
+        NOT VALID
+);
+
+CREATE UNIQUE INDEX storage_tags_name_idx ON public.storage_tags (endpoint_id, lower(name));
+
+CREATE TABLE public.storage_entry_tags
+(
+    entry_id bigint NOT NULL,
+    tag_id integer NOT NULL,
+    PRIMARY KEY (entry_id, tag_id),
+    FOREIGN KEY (entry_id)
+        REFERENCES public.storage_entries (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID,
+    FOREIGN KEY (tag_id)
+        REFERENCES public.storage_tags (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID
+);
+
+CREATE INDEX storage_entry_tags_tag_idx ON public.storage_entry_tags (tag_id);
+
+-- Free-form properties of entries
+CREATE TABLE public.storage_entry_properties
+(
+    entry_id bigint NOT NULL,
+    key character varying(64) NOT NULL,
+    value character varying(1024) NOT NULL,
+    PRIMARY KEY (entry_id, key),
+    FOREIGN KEY (entry_id)
+        REFERENCES public.storage_entries (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE
+        NOT VALID
+);
+
+-- Tags are kept by name, they might not exist anymore when the entry is restored
+ALTER TABLE IF EXISTS public.storage_trash_entries
+    ADD COLUMN tags text[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE IF EXISTS public.storage_trash_entries
+    ADD COLUMN properties jsonb NOT NULL DEFAULT '{}';
@@ -0,0 +1,31 @@
+-- Who did what, and when. Records are never updated, and they outlive the users, sessions, endpoints and entries
+-- they refer to, so none of the references are foreign keys (except the actor, which is only unlinked).
+CREATE TABLE public.audit_log
+(
+    id bigserial NOT NULL,
+    action character varying(64) NOT NULL,
+    actor_user_id integer,
+    actor_username character varying(255),
+    session_id integer,
+    ip inet,
+    forwarded_for text,
+    target_type character varying(64) NOT NULL,
+    target_ids text[] NOT NULL DEFAULT '{}',
+    endpoint_id integer,
+    before jsonb,
+    after jsonb,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (actor_user_id)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE SET NULL
+        NOT VALID
+);
+
+CREATE INDEX audit_log_created_at_idx ON public.audit_log (created_at);
+CREATE INDEX audit_log_action_idx ON public.audit_log (action);
+CREATE INDEX audit_log_actor_user_id_idx ON public.audit_log (actor_user_id);
+CREATE INDEX audit_log_target_type_idx ON public.audit_log (target_type);
+CREATE INDEX audit_log_target_ids_idx ON public.audit_log USING gin (target_ids);
+CREATE INDEX audit_log_endpoint_id_idx ON public.audit_log (endpoint_id);
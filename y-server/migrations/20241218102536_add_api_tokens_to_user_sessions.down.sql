@@ -0,0 +1,10 @@
+DELETE FROM public.user_sessions WHERE token_name IS NOT NULL;
+
+DROP INDEX public.user_sessions_user_id_idx;
+
+ALTER TABLE public.user_sessions
+    DROP CONSTRAINT user_sessions_token_check,
+    DROP COLUMN token_name,
+    DROP COLUMN token_scopes,
+    DROP COLUMN last_used_at,
+    DROP COLUMN last_used_ip;
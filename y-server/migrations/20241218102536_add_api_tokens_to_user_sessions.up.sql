@@ -0,0 +1,10 @@
+-- Personal API tokens are sessions that are created on demand, have a name and are restricted to a set of scopes.
+-- Browser sessions have no name and no scopes.
+ALTER TABLE public.user_sessions
+    ADD COLUMN token_name character varying(127),
+    ADD COLUMN token_scopes text[],
+    ADD COLUMN last_used_at timestamp without time zone,
+    ADD COLUMN last_used_ip character varying(45),
+    ADD CONSTRAINT user_sessions_token_check CHECK ((token_name IS NULL) = (token_scopes IS NULL));
+
+CREATE INDEX user_sessions_user_id_idx ON public.user_sessions (user_id);
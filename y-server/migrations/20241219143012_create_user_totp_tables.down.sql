@@ -0,0 +1,3 @@
+DROP TABLE public.user_pending_logins;
+DROP TABLE public.user_totp_recovery_codes;
+DROP TABLE public.user_totp;
@@ -0,0 +1,54 @@
+-- TOTP (RFC 6238) secrets. A row with `enabled = false` is an enrollment that was not confirmed yet.
+CREATE TABLE public.user_totp
+(
+    user_id integer NOT NULL,
+    secret character varying(64) NOT NULL,
+    enabled boolean NOT NULL DEFAULT false,
+
+    -- Time step of the last accepted code, so that a code can not be used twice
+    last_used_step bigint,
+
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    enabled_at timestamp with time zone,
+    PRIMARY KEY (user_id),
+    FOREIGN KEY (user_id)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+CREATE TABLE public.user_totp_recovery_codes
+(
+    id serial NOT NULL,
+    user_id integer NOT NULL,
+    code_hash character varying(64) NOT NULL,
+    used_at timestamp with time zone,
+    PRIMARY KEY (id),
+    FOREIGN KEY (user_id)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+CREATE INDEX user_totp_recovery_codes_user_id_idx ON public.user_totp_recovery_codes (user_id);
+
+-- Logins that passed the password check and are waiting for the second factor
+CREATE TABLE public.user_pending_logins
+(
+    id serial NOT NULL,
+    token character varying(64) NOT NULL,
+    user_id integer NOT NULL,
+
+    -- The user is required to use 2FA, but has not set it up yet
+    enrollment_required boolean NOT NULL DEFAULT false,
+
+    attempts integer NOT NULL DEFAULT 0,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    expires_at timestamp with time zone NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE (token),
+    FOREIGN KEY (user_id)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+DROP TABLE public.oidc_login_requests;
+DROP TABLE public.user_oidc_identities;
+DROP TABLE public.oidc_provider_group_mappings;
+DROP TABLE public.oidc_providers;
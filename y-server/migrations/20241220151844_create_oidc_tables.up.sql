@@ -0,0 +1,87 @@
+-- OpenID Connect identity providers that users can log in with
+CREATE TABLE public.oidc_providers
+(
+    id serial NOT NULL,
+    name character varying(127) NOT NULL,
+
+    -- Discovery is done at `<issuer>/.well-known/openid-configuration`
+    issuer character varying(255) NOT NULL,
+    client_id character varying(255) NOT NULL,
+
+    -- NULL for public clients, which rely on PKCE alone
+    client_secret character varying(512),
+
+    scopes character varying(255) NOT NULL DEFAULT 'openid profile email',
+
+    -- ID token claims. The username claim is only used when a user is created.
+    username_claim character varying(127) NOT NULL DEFAULT 'preferred_username',
+    groups_claim character varying(127),
+
+    -- Create users that log in for the first time
+    auto_create_users boolean NOT NULL DEFAULT true,
+
+    enabled boolean NOT NULL DEFAULT true,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    UNIQUE (name)
+);
+
+-- Values of the groups claim that grant membership in a group. Membership in mapped groups is synced on every login,
+-- other groups are left alone.
+CREATE TABLE public.oidc_provider_group_mappings
+(
+    provider_id integer NOT NULL,
+    claim_value character varying(255) NOT NULL,
+    group_id integer NOT NULL,
+    PRIMARY KEY (provider_id, claim_value, group_id),
+    FOREIGN KEY (provider_id)
+        REFERENCES public.oidc_providers (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE,
+    FOREIGN KEY (group_id)
+        REFERENCES public.user_groups (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+-- Users linked to an identity provider account (the `sub` claim)
+CREATE TABLE public.user_oidc_identities
+(
+    provider_id integer NOT NULL,
+    subject character varying(255) NOT NULL,
+    user_id integer NOT NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    last_login_at timestamp with time zone,
+    PRIMARY KEY (provider_id, subject),
+    FOREIGN KEY (provider_id)
+        REFERENCES public.oidc_providers (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE,
+    FOREIGN KEY (user_id)
+        REFERENCES public.users (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+CREATE INDEX user_oidc_identities_user_id_idx ON public.user_oidc_identities (user_id);
+
+-- Logins that were sent to an identity provider and are waiting for the callback
+CREATE TABLE public.oidc_login_requests
+(
+    state character varying(64) NOT NULL,
+    provider_id integer NOT NULL,
+    nonce character varying(64) NOT NULL,
+    code_verifier character varying(128) NOT NULL,
+    redirect_uri character varying(512) NOT NULL,
+
+    -- Path in the web interface to go to after logging in
+    return_to character varying(512),
+
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    expires_at timestamp with time zone NOT NULL,
+    PRIMARY KEY (state),
+    FOREIGN KEY (provider_id)
+        REFERENCES public.oidc_providers (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+DROP TABLE public.storage_share_link_downloads;
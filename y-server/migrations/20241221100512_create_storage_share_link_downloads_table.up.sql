@@ -0,0 +1,20 @@
+-- Downloads counted against `storage_share_links.max_downloads`. Requests for the same file from the same client
+-- shortly after a counted one (range requests, retries) are not counted again.
+CREATE TABLE public.storage_share_link_downloads
+(
+    id bigserial NOT NULL,
+    share_link_id integer NOT NULL,
+    entry_id bigint NOT NULL,
+
+    -- Peer address and the address reported by a reverse proxy
+    client_key character varying(255) NOT NULL,
+
+    started_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (share_link_id)
+        REFERENCES public.storage_share_links (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+CREATE INDEX storage_share_link_downloads_lookup_idx ON public.storage_share_link_downloads (share_link_id, entry_id, client_key, started_at);
@@ -0,0 +1,16 @@
+-- Existing blobs that are about to be reused by a new file (deduplication). A reserved blob is not deleted even if
+-- nothing references it yet, the reservation is removed once the file has been created.
+CREATE TABLE public.storage_blob_reservations
+(
+    id bigserial NOT NULL,
+    endpoint_id integer NOT NULL,
+    filesystem_id character(36) NOT NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (endpoint_id)
+        REFERENCES public.storage_endpoints (id) MATCH SIMPLE
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
+
+CREATE INDEX storage_blob_reservations_lookup_idx ON public.storage_blob_reservations (endpoint_id, filesystem_id);
@@ -0,0 +1 @@
+ALTER TABLE public.jobs DROP COLUMN worker_running;
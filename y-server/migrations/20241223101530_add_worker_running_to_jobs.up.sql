@@ -0,0 +1,3 @@
+-- Set while a worker is busy with the job, even if the job has been cancelled in the meantime. A job can not be
+-- retried until its worker is done with it.
+ALTER TABLE public.jobs ADD COLUMN worker_running boolean NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+DELETE FROM public.config
+  WHERE "key"='jobs.interactive_workers';
@@ -0,0 +1,2 @@
+INSERT INTO public.config (key,value) VALUES
+  ('jobs.interactive_workers','2');
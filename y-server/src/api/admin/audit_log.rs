@@ -0,0 +1,81 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use futures::join;
+use serde::{Deserialize, Serialize};
+
+use crate::audit_log::{
+    bind_audit_log_filters, AuditLogFilters, AuditLogRecord, AUDIT_LOG_FILTERS_SQL,
+    AUDIT_LOG_SELECT,
+};
+use crate::request::{error, DEFAULT_LIMIT};
+use crate::user::get_client_rights;
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct AuditLogPagination {
+    limit: Option<i64>,
+    skip: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct AuditLogOutput {
+    records: Vec<AuditLogRecord>,
+    total_count: i64,
+}
+
+/**
+ * Get records from the audit log, newest first.
+ */
+#[get("/audit-log")]
+async fn audit_log(
+    pool: web::Data<RequestPool>,
+    filters: web::Query<AuditLogFilters>,
+    pagination: web::Query<AuditLogPagination>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .find(|right| right.right_name.eq("view_audit_log"))
+        .is_some();
+
+    if !action_allowed {
+        return error("audit_log.unauthorized");
+    }
+
+    if !filters.is_valid() {
+        return error("audit_log.invalid_input");
+    }
+
+    let records_sql = format!(
+        "{} WHERE {} ORDER BY id DESC LIMIT $9 OFFSET $10",
+        AUDIT_LOG_SELECT, AUDIT_LOG_FILTERS_SQL
+    );
+
+    let count_sql = format!(
+        "SELECT COUNT(*) FROM audit_log WHERE {}",
+        AUDIT_LOG_FILTERS_SQL
+    );
+
+    let records = bind_audit_log_filters(
+        sqlx::query_as::<_, AuditLogRecord>(records_sql.as_str()),
+        &filters,
+    )
+    .bind(pagination.limit.unwrap_or(DEFAULT_LIMIT))
+    .bind(pagination.skip.unwrap_or(0))
+    .fetch_all(&**pool);
+
+    let records_count =
+        bind_audit_log_filters(sqlx::query_as::<_, (i64,)>(count_sql.as_str()), &filters)
+            .fetch_one(&**pool);
+
+    let (records, records_count) = join!(records, records_count);
+
+    match records {
+        Ok(records) => HttpResponse::Ok().json(web::Json(AuditLogOutput {
+            records,
+            total_count: records_count.map(|(count,)| count).unwrap_or(0),
+        })),
+        Err(_) => error("audit_log.internal"),
+    }
+}
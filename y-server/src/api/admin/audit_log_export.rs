@@ -0,0 +1,145 @@
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{get, web, HttpResponse, Responder};
+use chrono::Utc;
+use futures::stream;
+use log::*;
+
+use crate::audit_log::{
+    bind_audit_log_filters, write_client_audit_log, AuditEvent, AuditLogFilters, AuditLogRecord,
+    AUDIT_LOG_FILTERS_SQL, AUDIT_LOG_SELECT,
+};
+use crate::request::error;
+use crate::user::get_client_rights;
+use crate::util::RequestPool;
+
+/**
+ * How many records are fetched from the database at a time
+ */
+const EXPORT_BATCH_SIZE: i64 = 1000;
+
+/**
+ * Export records from the audit log as JSON lines (one record per line), newest first.
+ *
+ * Accepts the same filters as `/audit-log`. The records are streamed, so the whole log can be exported at once.
+ */
+#[get("/audit-log/export")]
+async fn audit_log_export(
+    pool: web::Data<RequestPool>,
+    filters: web::Query<AuditLogFilters>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .find(|right| right.right_name.eq("view_audit_log"))
+        .is_some();
+
+    if !action_allowed {
+        return error("audit_log.unauthorized");
+    }
+
+    if !filters.is_valid() {
+        return error("audit_log.invalid_input");
+    }
+
+    let filters = filters.into_inner();
+
+    write_client_audit_log(
+        &pool,
+        &req,
+        AuditEvent {
+            action: "audit_log.export",
+            target_type: "audit_log",
+            after: Some(serde_json::json!({
+                "action": filters.action,
+                "actor_user_id": filters.actor_user_id,
+                "target_type": filters.target_type,
+                "target_id": filters.target_id,
+                "endpoint_id": filters.endpoint_id,
+                "ip": filters.ip,
+                "after": filters.after,
+                "before": filters.before,
+            })),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let sql = format!(
+        "{} WHERE {} AND ($9::BIGINT IS NULL OR id < $9) ORDER BY id DESC LIMIT $10",
+        AUDIT_LOG_SELECT, AUDIT_LOG_FILTERS_SQL
+    );
+
+    // Records are fetched in batches, each one starting right after the last record of the previous one.
+    // The state is (id of the last exported record, whether there is anything left to export)
+    let body = stream::unfold(
+        (pool.get_ref().clone(), filters, None::<i64>, true),
+        move |(pool, filters, last_id, has_more)| {
+            let sql = sql.clone();
+
+            async move {
+                if !has_more {
+                    return None;
+                }
+
+                let records =
+                    bind_audit_log_filters(sqlx::query_as::<_, AuditLogRecord>(&sql), &filters)
+                        .bind(last_id)
+                        .bind(EXPORT_BATCH_SIZE)
+                        .fetch_all(&pool)
+                        .await;
+
+                let records = match records {
+                    Ok(records) => records,
+                    Err(err) => {
+                        error!("(audit log export) Could not fetch records. {}", err);
+
+                        // Break the response, so that the client does not mistake it for a complete export
+                        return Some((
+                            Err(actix_web::error::ErrorInternalServerError(
+                                "audit_log.internal",
+                            )),
+                            (pool, filters, last_id, false),
+                        ));
+                    }
+                };
+
+                if records.is_empty() {
+                    return None;
+                }
+
+                let mut lines = String::new();
+
+                for record in &records {
+                    if let Ok(line) = serde_json::to_string(record) {
+                        lines.push_str(&line);
+                        lines.push('\n');
+                    }
+                }
+
+                let next_last_id = records.last().map(|record| record.id);
+                let has_more = records.len() as i64 == EXPORT_BATCH_SIZE;
+
+                Some((
+                    Ok::<_, actix_web::Error>(web::Bytes::from(lines)),
+                    (pool, filters, next_last_id, has_more),
+                ))
+            }
+        },
+    );
+
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(format!(
+                    "audit-log-{}.jsonl",
+                    Utc::now().format("%Y%m%d%H%M%S")
+                ))],
+            },
+        ))
+        .streaming(body)
+}
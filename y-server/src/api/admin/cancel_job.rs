@@ -0,0 +1,46 @@
+use actix_web::{post, web, HttpResponse, Responder};
+
+use crate::audit_log::{write_client_audit_log, AuditEvent};
+use crate::jobs::cancel_job as cancel_queued_job;
+use crate::request::error;
+use crate::user::get_client_rights;
+use crate::util::RequestPool;
+
+#[post("/jobs/{job_id}/cancel")]
+async fn cancel_job(
+    pool: web::Data<RequestPool>,
+    path: web::Path<i64>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let job_id = path.into_inner();
+
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .find(|right| right.right_name.eq("manage_jobs"))
+        .is_some();
+
+    if !action_allowed {
+        return error("cancel_job.unauthorized");
+    }
+
+    match cancel_queued_job(job_id, &pool).await {
+        Ok(_) => {
+            write_client_audit_log(
+                &pool,
+                &req,
+                AuditEvent {
+                    action: "job.cancel",
+                    target_type: "job",
+                    target_ids: vec![job_id.to_string()],
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().body("{}")
+        }
+        Err(err) => error(err.get_code()),
+    }
+}
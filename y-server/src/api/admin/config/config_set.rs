@@ -1,11 +1,13 @@
 use std::collections::HashMap;
 
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
 use crate::config::validate_config_value;
 use crate::request::error_message;
 use crate::user::get_user_from_request;
 use crate::util::RequestPool;
 use crate::{request::error, user::get_client_rights};
 use actix_web::{patch, web, HttpRequest, HttpResponse, Responder};
+use serde_json::json;
 use sqlx::QueryBuilder;
 
 #[patch("/config")]
@@ -23,7 +25,8 @@ async fn config_set(
         return error("config.access_denined");
     }
 
-    let (client_user, _) = &client.unwrap();
+    let client = client.unwrap();
+    let (client_user, _) = &client;
     let client_rights = get_client_rights(&pool, &req).await;
 
     let action_allowed = client_rights
@@ -46,6 +49,18 @@ async fn config_set(
         }
     }
 
+    let previous_config = sqlx::query_as::<_, (String, Option<String>)>(
+        "SELECT key, value FROM config WHERE key = ANY($1)",
+    )
+    .bind(new_config.keys().cloned().collect::<Vec<String>>())
+    .fetch_all(&**pool)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .collect::<HashMap<String, Option<String>>>();
+
+    let audit_after = json!(new_config);
+
     let mut query_builder = QueryBuilder::new(
         "UPDATE config
         SET value = temp_data.value, updated_by = temp_data.updated_by, updated_at = temp_data.updated_at
@@ -70,6 +85,20 @@ async fn config_set(
 
     match result {
         Ok(_) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "config.set",
+                    target_type: "config",
+                    before: Some(json!(previous_config)),
+                    after: Some(audit_after),
+                    ..Default::default()
+                },
+            )
+            .await;
+
             return HttpResponse::Ok().body("{}");
         }
         Err(_) => {
@@ -0,0 +1,151 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::audit_log::{write_client_audit_log, AuditEvent};
+use crate::request::error;
+use crate::user::get_client_rights;
+use crate::user_oidc::{
+    get_oidc_provider_snapshot, is_valid_issuer, set_oidc_provider_group_mappings, OidcError,
+    OidcGroupMapping,
+};
+use crate::util::RequestPool;
+
+#[derive(Deserialize, Validate)]
+struct CreateOidcProviderInput {
+    /**
+     * Shown on the login page
+     */
+    #[validate(length(min = 1, max = 127))]
+    name: String,
+
+    /**
+     * Exactly as the provider reports it in its discovery document
+     */
+    #[validate(length(min = 1, max = 255))]
+    issuer: String,
+
+    #[validate(length(min = 1, max = 255))]
+    client_id: String,
+
+    /**
+     * Leave out for public clients
+     */
+    #[validate(length(min = 1, max = 512))]
+    client_secret: Option<String>,
+
+    /**
+     * Space separated, must include `openid`
+     */
+    #[validate(length(min = 1, max = 255))]
+    scopes: Option<String>,
+
+    /**
+     * Name of the ID token claim that new users get their username from. Nested claims are separated with dots.
+     */
+    #[validate(length(min = 1, max = 127))]
+    username_claim: Option<String>,
+
+    /**
+     * Name of the ID token claim with the user's groups. Group membership is not synced if this is not set.
+     */
+    #[validate(length(min = 1, max = 127))]
+    groups_claim: Option<String>,
+
+    auto_create_users: Option<bool>,
+    enabled: Option<bool>,
+
+    #[serde(default)]
+    group_mappings: Vec<OidcGroupMapping>,
+}
+
+#[derive(Serialize)]
+struct CreateOidcProviderOutput {
+    id: i32,
+}
+
+#[post("/oidc-providers")]
+async fn create_oidc_provider(
+    pool: web::Data<RequestPool>,
+    form: web::Json<CreateOidcProviderInput>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+
+    let scopes = form
+        .scopes
+        .clone()
+        .unwrap_or("openid profile email".to_string());
+
+    if form.validate().is_err()
+        || !is_valid_issuer(&form.issuer)
+        || !scopes.split_whitespace().any(|scope| scope == "openid")
+    {
+        return error("create_oidc_provider.invalid_input");
+    }
+
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .any(|right| right.right_name == "manage_oidc_providers");
+
+    if !action_allowed {
+        return error("create_oidc_provider.unauthorized");
+    }
+
+    let create_result: Result<i32, OidcError> = async {
+        let mut transaction = pool.begin().await.map_err(|_| OidcError::Internal)?;
+
+        let provider_id = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO oidc_providers (name, issuer, client_id, client_secret, scopes, username_claim, groups_claim, auto_create_users, enabled)
+            VALUES ($1, $2, $3, $4, $5, COALESCE($6, 'preferred_username'), $7, COALESCE($8, true), COALESCE($9, true))
+            ON CONFLICT (name) DO NOTHING RETURNING id",
+        )
+        .bind(&form.name)
+        .bind(&form.issuer)
+        .bind(&form.client_id)
+        .bind(&form.client_secret)
+        .bind(&scopes)
+        .bind(&form.username_claim)
+        .bind(&form.groups_claim)
+        .bind(form.auto_create_users)
+        .bind(form.enabled)
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(|_| OidcError::Internal)?
+        .ok_or(OidcError::ProviderNameTaken)?;
+
+        set_oidc_provider_group_mappings(provider_id, &form.group_mappings, &mut transaction)
+            .await?;
+
+        transaction.commit().await.map_err(|_| OidcError::Internal)?;
+
+        Ok(provider_id)
+    }
+    .await;
+
+    match create_result {
+        Ok(provider_id) => {
+            write_client_audit_log(
+                &pool,
+                &req,
+                AuditEvent {
+                    action: "oidc_provider.create",
+                    target_type: "oidc_provider",
+                    target_ids: vec![provider_id.to_string()],
+                    after: get_oidc_provider_snapshot(provider_id, &pool).await,
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().json(web::Json(CreateOidcProviderOutput { id: provider_id }))
+        }
+        Err(OidcError::ProviderNameTaken) => error("create_oidc_provider.name_taken"),
+        Err(OidcError::InvalidGroupMappings) => {
+            error("create_oidc_provider.invalid_group_mappings")
+        }
+        Err(_) => error("create_oidc_provider.internal"),
+    }
+}
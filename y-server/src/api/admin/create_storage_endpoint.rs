@@ -1,10 +1,13 @@
 use std::fs;
 
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::request::error;
+use crate::storage_backend::{S3Backend, StorageS3EndpointConfig};
 use crate::user::get_client_rights;
 use crate::util::RequestPool;
 use actix_web::{post, web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use validator::Validate;
 
 #[derive(Serialize)]
@@ -12,6 +15,33 @@ struct CreateStorageEndpointOutput {
     id: i32,
 }
 
+#[derive(Deserialize, Validate)]
+struct CreateStorageEndpointS3Input {
+    #[validate(length(min = 3, max = 63))]
+    bucket: String,
+
+    #[validate(length(min = 0, max = 511))]
+    prefix: Option<String>,
+
+    #[validate(length(min = 1, max = 63))]
+    region: String,
+
+    /**
+     * Custom S3 API url, for S3-compatible servers (MinIO, etc.). `null` == AWS.
+     */
+    #[validate(length(min = 1, max = 511))]
+    endpoint_url: Option<String>,
+
+    /**
+     * Static credentials. If `null`, the credentials are taken from the environment.
+     */
+    #[validate(length(min = 1, max = 255))]
+    access_key_id: Option<String>,
+
+    #[validate(length(min = 1, max = 255))]
+    secret_access_key: Option<String>,
+}
+
 #[derive(Deserialize, Validate)]
 struct CreateStorageEndpointInput {
     #[validate(length(min = 1, max = 127))]
@@ -20,7 +50,10 @@ struct CreateStorageEndpointInput {
     endpoint_type: String,
     access_rules_enabled: bool,
 
-    #[validate(length(min = 1, max = 511))]
+    /**
+     * Required for `local_fs` endpoints. Ignored for `s3` endpoints.
+     */
+    #[validate(length(min = 0, max = 511))]
     base_path: String,
 
     #[validate(length(min = 1, max = 511))]
@@ -28,6 +61,17 @@ struct CreateStorageEndpointInput {
 
     #[validate(length(min = 0, max = 255))]
     description: String,
+
+    /**
+     * Store files under their real paths instead of flat blobs, so the endpoint's folder can be used outside of y.
+     * Only for `local_fs` endpoints, can not be changed later.
+     */
+    preserve_file_structure: Option<bool>,
+
+    /**
+     * Required for `s3` endpoints.
+     */
+    s3: Option<CreateStorageEndpointS3Input>,
 }
 
 #[post("/storage/endpoints")]
@@ -38,10 +82,17 @@ async fn create_storage_endpoint(
 ) -> impl Responder {
     let form = form.into_inner();
 
-    if form.validate().is_err() || form.endpoint_type != "local_fs" {
+    if form.validate().is_err() {
         return error("create_storage_endpoint.invalid_input");
     }
 
+    match (form.endpoint_type.as_str(), &form.s3) {
+        ("local_fs", _) if !form.base_path.is_empty() => {}
+        ("s3", Some(s3)) if s3.validate().is_ok() && form.preserve_file_structure != Some(true) => {
+        }
+        _ => return error("create_storage_endpoint.invalid_input"),
+    }
+
     let client_rights = get_client_rights(&pool, &req).await;
 
     let action_allowed = client_rights
@@ -53,10 +104,15 @@ async fn create_storage_endpoint(
         return error("create_storage_endpoint.unauthorized");
     }
 
-    let base_path = std::path::Path::new(&form.base_path);
     let artifacts_path = std::path::Path::new(&form.artifacts_path);
 
-    for path in &[base_path, artifacts_path] {
+    let mut paths_to_check = vec![artifacts_path];
+
+    if form.endpoint_type == "local_fs" {
+        paths_to_check.push(std::path::Path::new(&form.base_path));
+    }
+
+    for path in &paths_to_check {
         if !path.exists() {
             return error("create_storage_endpoint.path_does_not_exist");
         }
@@ -70,37 +126,126 @@ async fn create_storage_endpoint(
         }
     }
 
-    let create_test_file_result = fs::write(base_path.join("test_file"), "you can delete me");
+    let s3_config = form.s3.map(|s3| StorageS3EndpointConfig {
+        bucket: s3.bucket,
+        prefix: s3
+            .prefix
+            .filter(|prefix| !prefix.trim_matches('/').is_empty()),
+        region: s3.region,
+        endpoint_url: s3.endpoint_url,
+        access_key_id: s3.access_key_id,
+        secret_access_key: s3.secret_access_key,
+    });
+
+    // S3 endpoints don't have a base path on the local filesystem. We still store something human-readable there
+    let base_path = if let Some(s3_config) = &s3_config {
+        if !S3Backend::new(s3_config).await.check_access().await {
+            return error("create_storage_endpoint.s3_access_error");
+        }
+
+        format!(
+            "s3://{}/{}",
+            s3_config.bucket,
+            s3_config.prefix.as_deref().unwrap_or("").trim_matches('/')
+        )
+    } else {
+        let base_path = std::path::Path::new(&form.base_path);
+
+        let create_test_file_result = fs::write(base_path.join("test_file"), "you can delete me");
+
+        if create_test_file_result.is_err() {
+            return error("create_storage_endpoint.os_error");
+        }
+
+        fs::remove_file(base_path.join("test_file")).unwrap();
+
+        form.base_path
+    };
+
     let create_thumbnails_dir_result = fs::create_dir(artifacts_path.join("thumbnails"));
     let create_preview_videos_dir_result = fs::create_dir(artifacts_path.join("preview_videos"));
+    let create_hls_dir_result = fs::create_dir(artifacts_path.join("hls"));
 
-    match create_thumbnails_dir_result
+    if create_thumbnails_dir_result
         .and(create_preview_videos_dir_result)
-        .and(create_test_file_result)
+        .and(create_hls_dir_result)
+        .is_err()
     {
-        Ok(_) => {
-            fs::remove_file(base_path.join("test_file")).unwrap();
-
-            let create_endpoint_result = sqlx::query_scalar("INSERT INTO storage_endpoints (name, endpoint_type, status, access_rules_enabled, base_path, artifacts_path, description) VALUES ($1, $2::storage_endpoint_type, $3::storage_endpoint_status, $4, $5, $6, $7) RETURNING id")
-            .bind(form.name)
-            .bind(form.endpoint_type)
-            .bind("active")
-            .bind(form.access_rules_enabled)
-            .bind(form.base_path)
-            .bind(&form.artifacts_path)
-            .bind(form.description)
-            .fetch_one(&**pool)
+        return error("create_storage_endpoint.os_error");
+    }
+
+    let transaction = pool.begin().await;
+
+    if transaction.is_err() {
+        return error("create_storage_endpoint.internal");
+    }
+
+    let mut transaction = transaction.unwrap();
+
+    // Credentials of S3 endpoints are not recorded
+    let audit_after = json!({
+        "name": form.name,
+        "endpoint_type": form.endpoint_type,
+        "access_rules_enabled": form.access_rules_enabled,
+        "base_path": base_path,
+        "artifacts_path": form.artifacts_path,
+        "preserve_file_structure": form.preserve_file_structure.unwrap_or(false),
+    });
+
+    let create_endpoint_result = sqlx::query_scalar::<_, i32>("INSERT INTO storage_endpoints (name, endpoint_type, status, access_rules_enabled, base_path, artifacts_path, description, preserve_file_structure) VALUES ($1, $2::storage_endpoint_type, $3::storage_endpoint_status, $4, $5, $6, $7, $8) RETURNING id")
+        .bind(form.name)
+        .bind(form.endpoint_type)
+        .bind("active")
+        .bind(form.access_rules_enabled)
+        .bind(base_path)
+        .bind(&form.artifacts_path)
+        .bind(form.description)
+        .bind(form.preserve_file_structure.unwrap_or(false))
+        .fetch_one(&mut *transaction)
+        .await;
+
+    if create_endpoint_result.is_err() {
+        return error("create_storage_endpoint.internal");
+    }
+
+    let new_endpoint_id = create_endpoint_result.unwrap();
+
+    if let Some(s3_config) = &s3_config {
+        let create_s3_config_result = sqlx::query("INSERT INTO storage_s3_endpoints (endpoint_id, bucket, prefix, region, endpoint_url, access_key_id, secret_access_key) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(new_endpoint_id)
+            .bind(&s3_config.bucket)
+            .bind(&s3_config.prefix)
+            .bind(&s3_config.region)
+            .bind(&s3_config.endpoint_url)
+            .bind(&s3_config.access_key_id)
+            .bind(&s3_config.secret_access_key)
+            .execute(&mut *transaction)
             .await;
 
-            return match create_endpoint_result {
-                Ok(new_endpoint_id) => {
-                    HttpResponse::Ok().json(web::Json(CreateStorageEndpointOutput {
-                        id: new_endpoint_id,
-                    }))
-                }
-                Err(_) => error("create_storage_endpoint.internal"),
-            };
+        if create_s3_config_result.is_err() {
+            return error("create_storage_endpoint.internal");
         }
-        Err(_) => return error("create_storage_endpoint.os_error"),
     }
+
+    if transaction.commit().await.is_err() {
+        return error("create_storage_endpoint.internal");
+    }
+
+    write_client_audit_log(
+        &pool,
+        &req,
+        AuditEvent {
+            action: "storage_endpoint.create",
+            target_type: "storage_endpoint",
+            target_ids: vec![new_endpoint_id.to_string()],
+            endpoint_id: Some(new_endpoint_id),
+            after: Some(audit_after),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    HttpResponse::Ok().json(web::Json(CreateStorageEndpointOutput {
+        id: new_endpoint_id,
+    }))
 }
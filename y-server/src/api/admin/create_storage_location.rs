@@ -1,8 +1,10 @@
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::request::error;
 use crate::user::get_client_rights;
 use crate::util::RequestPool;
 use actix_web::{post, web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use validator::Validate;
 
 #[derive(Serialize)]
@@ -42,17 +44,33 @@ async fn create_storage_location(
         return error("create_storage_location.unauthorized");
     }
 
-    let create_location_result = sqlx::query_scalar("INSERT INTO storage_locations (name, endpoint_id, entry_id) VALUES ($1, $2, $3) RETURNING id")
-            .bind(form.name)
+    let create_location_result = sqlx::query_scalar::<_, i32>("INSERT INTO storage_locations (name, endpoint_id, entry_id) VALUES ($1, $2, $3) RETURNING id")
+            .bind(&form.name)
             .bind(form.endpoint_id)
             .bind(form.entry_id)
             .fetch_one(&**pool)
             .await;
 
     return match create_location_result {
-        Ok(new_location_id) => HttpResponse::Ok().json(web::Json(CreateStorageLocationOutput {
-            id: new_location_id,
-        })),
+        Ok(new_location_id) => {
+            write_client_audit_log(
+                &pool,
+                &req,
+                AuditEvent {
+                    action: "storage_location.create",
+                    target_type: "storage_location",
+                    target_ids: vec![new_location_id.to_string()],
+                    endpoint_id: Some(form.endpoint_id),
+                    after: Some(json!({ "name": form.name, "entry_id": form.entry_id })),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().json(web::Json(CreateStorageLocationOutput {
+                id: new_location_id,
+            }))
+        }
         Err(_) => error("create_storage_location.internal"),
     };
 }
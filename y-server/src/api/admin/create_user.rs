@@ -2,8 +2,10 @@ use std::env;
 
 use actix_web::{post, web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use validator::Validate;
 
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::request::error;
 
 use crate::user::get_client_rights;
@@ -91,6 +93,19 @@ async fn create_user(
 
             match result {
                 Ok(result) => {
+                    write_client_audit_log(
+                        &pool,
+                        &req,
+                        AuditEvent {
+                            action: "user.create",
+                            target_type: "user",
+                            target_ids: vec![result.to_string()],
+                            after: Some(json!({ "username": username })),
+                            ..Default::default()
+                        },
+                    )
+                    .await;
+
                     return HttpResponse::Ok().json(web::Json(CreateUserOutput { id: result }));
                 }
                 Err(_) => return error("create_user.internal"),
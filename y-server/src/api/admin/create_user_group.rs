@@ -1,8 +1,10 @@
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::request::error;
 use crate::user::get_client_rights;
 use crate::util::RequestPool;
 use actix_web::{post, web, HttpResponse, Responder};
 use serde::Deserialize;
+use serde_json::json;
 use validator::Validate;
 
 #[derive(serde::Serialize)]
@@ -48,13 +50,27 @@ async fn create_user_group(
 
     let name = form.name;
 
-    let result = sqlx::query_scalar("INSERT INTO user_groups (name) VALUES ($1) RETURNING id")
-        .bind(name)
-        .fetch_one(&**pool)
-        .await;
+    let result =
+        sqlx::query_scalar::<_, i32>("INSERT INTO user_groups (name) VALUES ($1) RETURNING id")
+            .bind(&name)
+            .fetch_one(&**pool)
+            .await;
 
     return match result {
         Ok(new_group_id) => {
+            write_client_audit_log(
+                &pool,
+                &req,
+                AuditEvent {
+                    action: "user_group.create",
+                    target_type: "user_group",
+                    target_ids: vec![new_group_id.to_string()],
+                    after: Some(json!({ "name": name })),
+                    ..Default::default()
+                },
+            )
+            .await;
+
             HttpResponse::Ok().json(web::Json(CreateUserGroupOutput { id: new_group_id }))
         }
         Err(_) => error("create_user_group.internal"),
@@ -0,0 +1,58 @@
+use actix_web::{delete, web, HttpResponse, Responder};
+
+use crate::audit_log::{write_client_audit_log, AuditEvent};
+use crate::request::error;
+use crate::user::get_client_rights;
+use crate::user_oidc::get_oidc_provider_snapshot;
+use crate::util::RequestPool;
+
+/**
+ * Delete an identity provider. Users that were created by it are kept, but they can not log in until they are given
+ * a password.
+ */
+#[delete("/oidc-providers/{provider_id}")]
+async fn delete_oidc_provider(
+    pool: web::Data<RequestPool>,
+    path: web::Path<i32>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let provider_id = path.into_inner();
+
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .any(|right| right.right_name == "manage_oidc_providers");
+
+    if !action_allowed {
+        return error("delete_oidc_provider.unauthorized");
+    }
+
+    let audit_before = get_oidc_provider_snapshot(provider_id, &pool).await;
+
+    let delete_result = sqlx::query("DELETE FROM oidc_providers WHERE id = $1")
+        .bind(provider_id)
+        .execute(&**pool)
+        .await;
+
+    match delete_result {
+        Ok(result) if result.rows_affected() > 0 => {
+            write_client_audit_log(
+                &pool,
+                &req,
+                AuditEvent {
+                    action: "oidc_provider.delete",
+                    target_type: "oidc_provider",
+                    target_ids: vec![provider_id.to_string()],
+                    before: audit_before,
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().body("{}")
+        }
+        Ok(_) => error("delete_oidc_provider.not_found"),
+        Err(_) => error("delete_oidc_provider.internal"),
+    }
+}
@@ -1,7 +1,9 @@
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::request::error;
 use crate::user::get_client_rights;
 use crate::util::RequestPool;
 use actix_web::{delete, web, HttpResponse, Responder};
+use serde_json::json;
 
 #[delete("/storage/locations/{location_id}")]
 async fn delete_storage_location(
@@ -22,14 +24,32 @@ async fn delete_storage_location(
         return error("delete_storage_location.unauthorized");
     }
 
-    let delete_location_result = sqlx::query("DELETE FROM storage_locations WHERE id = $1")
-        .bind(location_id)
-        .execute(&**pool)
-        .await;
+    let deleted_location = sqlx::query_as::<_, (String, i32, i64)>(
+        "DELETE FROM storage_locations WHERE id = $1 RETURNING name, endpoint_id, entry_id",
+    )
+    .bind(location_id)
+    .fetch_optional(&**pool)
+    .await;
 
-    if delete_location_result.is_err() {
+    if deleted_location.is_err() {
         return error("delete_storage_location.internal");
     }
 
+    if let Some((name, endpoint_id, entry_id)) = deleted_location.unwrap() {
+        write_client_audit_log(
+            &pool,
+            &req,
+            AuditEvent {
+                action: "storage_location.delete",
+                target_type: "storage_location",
+                target_ids: vec![location_id.to_string()],
+                endpoint_id: Some(endpoint_id),
+                before: Some(json!({ "name": name, "entry_id": entry_id })),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
     HttpResponse::Ok().body("{}")
 }
@@ -0,0 +1,55 @@
+use crate::audit_log::{write_client_audit_log, AuditEvent};
+use crate::request::error;
+use crate::user::get_client_rights;
+use crate::util::RequestPool;
+use actix_web::{delete, web, HttpResponse, Responder};
+use serde_json::json;
+
+#[delete("/storage/share-links/{link_id}")]
+async fn delete_storage_share_link(
+    pool: web::Data<RequestPool>,
+    path: web::Path<i32>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let link_id = path.into_inner();
+
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .find(|right| right.right_name.eq("manage_storage_share_links"))
+        .is_some();
+
+    if !action_allowed {
+        return error("delete_storage_share_link.unauthorized");
+    }
+
+    let deleted_share_link = sqlx::query_as::<_, (i32, i64)>(
+        "DELETE FROM storage_share_links WHERE id = $1 RETURNING endpoint_id, entry_id",
+    )
+    .bind(link_id)
+    .fetch_optional(&**pool)
+    .await;
+
+    if deleted_share_link.is_err() {
+        return error("delete_storage_share_link.internal");
+    }
+
+    if let Some((endpoint_id, entry_id)) = deleted_share_link.unwrap() {
+        write_client_audit_log(
+            &pool,
+            &req,
+            AuditEvent {
+                action: "storage.share_link.delete",
+                target_type: "storage_share_link",
+                target_ids: vec![link_id.to_string()],
+                endpoint_id: Some(endpoint_id),
+                before: Some(json!({ "entry_id": entry_id })),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    HttpResponse::Ok().body("{}")
+}
@@ -1,8 +1,10 @@
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::request::error;
 use crate::user::get_client_rights;
 use crate::util::RequestPool;
 use actix_web::{delete, web, HttpResponse, Responder};
 use serde::Deserialize;
+use serde_json::json;
 
 #[derive(Deserialize)]
 struct DeleteUserInput {
@@ -28,14 +30,31 @@ async fn delete_user(
 
     let user_ids = form.into_inner().user_ids;
 
-    let delete_users_result = sqlx::query("DELETE FROM users WHERE id = ANY($1)")
-        .bind(user_ids)
-        .execute(&**pool)
-        .await;
+    let deleted_users = sqlx::query_as::<_, (i32, String)>(
+        "DELETE FROM users WHERE id = ANY($1) RETURNING id, username",
+    )
+    .bind(user_ids)
+    .fetch_all(&**pool)
+    .await;
 
-    if delete_users_result.is_err() {
+    if deleted_users.is_err() {
         return error("delete_user.internal");
-    } else {
-        return HttpResponse::Ok().body("{}");
     }
+
+    for (user_id, username) in deleted_users.unwrap() {
+        write_client_audit_log(
+            &pool,
+            &req,
+            AuditEvent {
+                action: "user.delete",
+                target_type: "user",
+                target_ids: vec![user_id.to_string()],
+                before: Some(json!({ "username": username })),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    return HttpResponse::Ok().body("{}");
 }
@@ -1,7 +1,9 @@
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::request::error;
 use crate::user::get_client_rights;
 use crate::util::RequestPool;
 use actix_web::{delete, web, HttpResponse, Responder};
+use serde_json::json;
 
 #[delete("/user-groups/{user_group_id}")]
 async fn delete_user_group(
@@ -29,16 +31,31 @@ async fn delete_user_group(
 
     let user_group_id = path.into_inner();
 
-    let delete_group_result = sqlx::query(
-        "DELETE FROM user_groups WHERE id = $1 AND (group_type NOT IN ('everyone', 'user') OR group_type IS NULL)",
+    let deleted_group_name = sqlx::query_scalar::<_, String>(
+        "DELETE FROM user_groups WHERE id = $1 AND (group_type NOT IN ('everyone', 'user') OR group_type IS NULL) RETURNING name",
     )
     .bind(user_group_id)
-    .execute(&**pool)
+    .fetch_optional(&**pool)
     .await;
 
-    if delete_group_result.is_err() {
+    if deleted_group_name.is_err() {
         return error("delete_user_group.internal");
-    } else {
-        return HttpResponse::Ok().body("{}");
     }
+
+    if let Some(name) = deleted_group_name.unwrap() {
+        write_client_audit_log(
+            &pool,
+            &req,
+            AuditEvent {
+                action: "user_group.delete",
+                target_type: "user_group",
+                target_ids: vec![user_group_id.to_string()],
+                before: Some(json!({ "name": name })),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    return HttpResponse::Ok().body("{}");
 }
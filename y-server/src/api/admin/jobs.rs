@@ -0,0 +1,82 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use futures::join;
+use serde::{Deserialize, Serialize};
+
+use crate::jobs::{JobRow, JOB_SELECT};
+use crate::request::{error, DEFAULT_LIMIT};
+use crate::user::get_client_rights;
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct JobsQuery {
+    /**
+     * "queued", "running", "completed", "failed" or "cancelled"
+     */
+    status: Option<String>,
+    job_type: Option<String>,
+
+    limit: Option<i64>,
+    skip: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct JobsOutput {
+    jobs: Vec<JobRow>,
+    total_count: i64,
+}
+
+/**
+ * Get jobs from the background job queue, newest first.
+ */
+#[get("/jobs")]
+async fn jobs(
+    pool: web::Data<RequestPool>,
+    query: web::Query<JobsQuery>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .find(|right| right.right_name.eq("manage_jobs"))
+        .is_some();
+
+    if !action_allowed {
+        return error("jobs.unauthorized");
+    }
+
+    if let Some(status) = query.status.as_deref() {
+        if !["queued", "running", "completed", "failed", "cancelled"].contains(&status) {
+            return error("jobs.invalid_input");
+        }
+    }
+
+    let sql = format!(
+        "{} WHERE ($1::TEXT IS NULL OR jobs.status::TEXT = $1) AND ($2::TEXT IS NULL OR jobs.job_type = $2) ORDER BY jobs.id DESC LIMIT $3 OFFSET $4",
+        JOB_SELECT
+    );
+
+    let jobs = sqlx::query_as::<_, JobRow>(sql.as_str())
+        .bind(&query.status)
+        .bind(&query.job_type)
+        .bind(query.limit.unwrap_or(DEFAULT_LIMIT))
+        .bind(query.skip.unwrap_or(0))
+        .fetch_all(&**pool);
+
+    let jobs_count = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM jobs WHERE ($1::TEXT IS NULL OR status::TEXT = $1) AND ($2::TEXT IS NULL OR job_type = $2)",
+    )
+    .bind(&query.status)
+    .bind(&query.job_type)
+    .fetch_one(&**pool);
+
+    let (jobs, jobs_count) = join!(jobs, jobs_count);
+
+    match jobs {
+        Ok(jobs) => HttpResponse::Ok().json(web::Json(JobsOutput {
+            jobs,
+            total_count: jobs_count.unwrap_or(0),
+        })),
+        Err(_) => error("jobs.internal"),
+    }
+}
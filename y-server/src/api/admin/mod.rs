@@ -1,17 +1,31 @@
+pub mod audit_log;
+pub mod audit_log_export;
+pub mod cancel_job;
 pub mod config;
+pub mod create_oidc_provider;
 pub mod create_storage_endpoint;
 pub mod create_storage_location;
 pub mod create_user;
 pub mod create_user_group;
+pub mod delete_oidc_provider;
 pub mod delete_storage_location;
+pub mod delete_storage_share_link;
 pub mod delete_user;
 pub mod delete_user_group;
 pub mod features;
+pub mod jobs;
+pub mod oidc_providers;
+pub mod reset_user_two_factor;
+pub mod retry_job;
 pub mod storage_endpoint;
+pub mod storage_endpoint_fsck;
+pub mod storage_endpoint_import;
 pub mod storage_endpoint_set_vfs_config;
 pub mod storage_endpoint_vfs;
 pub mod storage_endpoints;
+pub mod storage_share_links;
 pub mod update_feature;
+pub mod update_oidc_provider;
 pub mod update_password;
 pub mod update_storage_endpoint;
 pub mod update_user_group;
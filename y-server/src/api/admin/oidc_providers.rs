@@ -0,0 +1,71 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+use sqlx::{types::Json, FromRow};
+
+use crate::{request::error, user::get_client_rights, user_oidc::OidcGroupMapping};
+
+use crate::util::RequestPool;
+
+#[derive(Serialize, FromRow)]
+struct OidcProvidersItem {
+    id: i32,
+    name: String,
+    issuer: String,
+    client_id: String,
+
+    /**
+     * The secret itself is never returned
+     */
+    has_client_secret: bool,
+
+    scopes: String,
+    username_claim: String,
+    groups_claim: Option<String>,
+    auto_create_users: bool,
+    enabled: bool,
+    group_mappings: Json<Vec<OidcGroupMapping>>,
+    users_count: i64,
+    created_at: String,
+}
+
+#[derive(Serialize)]
+struct OidcProvidersOutput {
+    oidc_providers: Vec<OidcProvidersItem>,
+}
+
+#[get("/oidc-providers")]
+async fn oidc_providers(
+    pool: web::Data<RequestPool>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .any(|right| right.right_name == "manage_oidc_providers");
+
+    if !action_allowed {
+        return error("oidc_providers.unauthorized");
+    }
+
+    let providers = sqlx::query_as::<_, OidcProvidersItem>(
+        "SELECT id, name, issuer, client_id, client_secret IS NOT NULL AS has_client_secret, scopes, username_claim,
+        groups_claim, auto_create_users, enabled,
+        (
+            SELECT COALESCE(jsonb_agg(jsonb_build_object('claim_value', claim_value, 'group_id', group_id) ORDER BY claim_value, group_id), '[]'::jsonb)
+            FROM oidc_provider_group_mappings WHERE provider_id = oidc_providers.id
+        ) AS group_mappings,
+        (SELECT COUNT(*) FROM user_oidc_identities WHERE provider_id = oidc_providers.id) AS users_count,
+        created_at::TEXT
+        FROM oidc_providers ORDER BY name",
+    )
+    .fetch_all(&**pool)
+    .await;
+
+    match providers {
+        Ok(providers) => HttpResponse::Ok().json(web::Json(OidcProvidersOutput {
+            oidc_providers: providers,
+        })),
+        Err(_) => error("oidc_providers.internal"),
+    }
+}
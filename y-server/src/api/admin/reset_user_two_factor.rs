@@ -0,0 +1,52 @@
+use actix_web::{delete, web, HttpResponse, Responder};
+
+use crate::audit_log::{write_client_audit_log, AuditEvent};
+use crate::request::error;
+use crate::user::get_client_rights;
+use crate::user_totp::disable_totp;
+use crate::util::RequestPool;
+
+/**
+ * Turn off two-factor authentication for a user who lost access to their authenticator app and recovery codes.
+ *
+ * If one of the user's groups requires two-factor authentication, they will have to set it up again on their next login.
+ */
+#[delete("/users/{user_id}/two-factor")]
+async fn reset_user_two_factor(
+    pool: web::Data<RequestPool>,
+    path: web::Path<i32>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let target_user_id = path.into_inner();
+
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .find(|right| right.right_name.eq("reset_user_two_factor"))
+        .is_some();
+
+    if !action_allowed {
+        return error("reset_user_two_factor.unauthorized");
+    }
+
+    match disable_totp(target_user_id, &pool).await {
+        Ok(true) => {
+            write_client_audit_log(
+                &pool,
+                &req,
+                AuditEvent {
+                    action: "user.reset_two_factor",
+                    target_type: "user",
+                    target_ids: vec![target_user_id.to_string()],
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().body("{}")
+        }
+        Ok(false) => error("reset_user_two_factor.not_enabled"),
+        Err(_) => error("reset_user_two_factor.internal"),
+    }
+}
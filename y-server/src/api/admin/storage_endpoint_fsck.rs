@@ -0,0 +1,62 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::audit_log::{write_client_audit_log, AuditEvent};
+use crate::request::error;
+use crate::storage_fsck::check_storage_endpoint;
+use crate::user::get_client_rights;
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct StorageEndpointFsckInput {
+    repair: Option<bool>,
+}
+
+#[post("/storage/endpoints/{endpoint_id}/fsck")]
+async fn storage_endpoint_fsck(
+    pool: web::Data<RequestPool>,
+    path: web::Path<i32>,
+    form: web::Json<StorageEndpointFsckInput>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let endpoint_id = path.into_inner();
+
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .find(|right| right.right_name.eq("manage_storage_endpoints"))
+        .is_some();
+
+    if !action_allowed {
+        return error("storage_fsck.unauthorized");
+    }
+
+    // Only report the problems by default
+    let repair = form.repair.unwrap_or(false);
+
+    match check_storage_endpoint(endpoint_id, repair, &pool).await {
+        Ok(report) => {
+            // Checks without repairs don't change anything
+            if repair {
+                write_client_audit_log(
+                    &pool,
+                    &req,
+                    AuditEvent {
+                        action: "storage_endpoint.fsck_repair",
+                        target_type: "storage_endpoint",
+                        target_ids: vec![endpoint_id.to_string()],
+                        endpoint_id: Some(endpoint_id),
+                        after: Some(json!(report)),
+                        ..Default::default()
+                    },
+                )
+                .await;
+            }
+
+            HttpResponse::Ok().json(web::Json(report))
+        }
+        Err(err) => error(err.get_code()),
+    }
+}
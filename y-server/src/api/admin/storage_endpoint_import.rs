@@ -0,0 +1,89 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
+use crate::jobs::{enqueue_job, JOB_STORAGE_IMPORT};
+use crate::request::error;
+use crate::storage_import::{check_import_options, StorageImportMode, StorageImportOptions};
+use crate::user::{get_client_rights, get_user_from_request};
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct StorageEndpointImportInput {
+    mode: StorageImportMode,
+    source_path: Option<String>,
+    target_folder: Option<i64>,
+    generate_artifacts: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct StorageEndpointImportOutput {
+    job_id: i64,
+}
+
+#[post("/storage/endpoints/{endpoint_id}/import")]
+async fn storage_endpoint_import(
+    pool: web::Data<RequestPool>,
+    path: web::Path<i32>,
+    form: web::Json<StorageEndpointImportInput>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let endpoint_id = path.into_inner();
+    let form = form.into_inner();
+
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .find(|right| right.right_name.eq("manage_storage_endpoints"))
+        .is_some();
+
+    if !action_allowed {
+        return error("storage_endpoint_import.unauthorized");
+    }
+
+    if form.mode == StorageImportMode::Copy && form.source_path.is_none() {
+        return error("storage_endpoint_import.invalid_input");
+    }
+
+    let options = StorageImportOptions {
+        endpoint_id,
+        mode: form.mode,
+        source_path: form.source_path,
+        target_folder: form.target_folder,
+        generate_artifacts: form.generate_artifacts.unwrap_or(true),
+    };
+
+    if let Err(err) = check_import_options(&options, &pool).await {
+        return error(err.get_code());
+    }
+
+    let client = get_user_from_request(&**pool, &req).await;
+    let created_by = client.as_ref().map(|(user, _)| user.id);
+
+    // Walking a large tree takes a while, the import runs in the background
+    let enqueue_result = enqueue_job(JOB_STORAGE_IMPORT, &options, created_by, &pool).await;
+
+    match enqueue_result {
+        Ok(job_id) => {
+            write_audit_log(
+                &pool,
+                &req,
+                client.as_ref().map(AuditActor::from_client),
+                AuditEvent {
+                    action: "storage_endpoint.import",
+                    target_type: "storage_endpoint",
+                    target_ids: vec![endpoint_id.to_string()],
+                    endpoint_id: Some(endpoint_id),
+                    after: Some(json!({ "options": options, "job_id": job_id })),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().json(web::Json(StorageEndpointImportOutput { job_id }))
+        }
+        Err(err) => error(err.get_code()),
+    }
+}
@@ -2,7 +2,10 @@ use std::path::Path;
 
 use actix_web::{put, web, HttpResponse, Responder};
 use serde::Deserialize;
+use serde_json::json;
 
+use crate::audit_log::{write_client_audit_log, AuditEvent};
+use crate::storage_endpoint::get_storage_endpoint;
 use crate::{request::error, user::get_client_rights};
 
 use crate::util::RequestPool;
@@ -43,6 +46,17 @@ async fn storage_endpoint_set_vfs_config(
         return error("storage_vfs.unauthorized");
     }
 
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    // The VFS reads and writes the blobs on the local filesystem directly
+    if target_endpoint.unwrap().endpoint_type != "local_fs" {
+        return error("storage_vfs.unsupported_endpoint_type");
+    }
+
     let mountpoint = Path::new(&form.mountpoint);
 
     if !mountpoint.exists() {
@@ -57,6 +71,14 @@ async fn storage_endpoint_set_vfs_config(
         return error("storage_vfs.mountpoint_not_absolute");
     }
 
+    let audit_before = sqlx::query_scalar::<_, serde_json::Value>(
+        "SELECT jsonb_build_object('enabled', enabled, 'writable', writable, 'mountpoint', mountpoint) FROM storage_vfs WHERE endpoint_id = $1",
+    )
+    .bind(endpoint_id)
+    .fetch_optional(&**pool)
+    .await
+    .unwrap_or_default();
+
     let set_config = sqlx::query(
         "INSERT INTO storage_vfs (enabled, writable, mountpoint, endpoint_id) VALUES ($1, $2, $3,$4) ON CONFLICT (endpoint_id) DO UPDATE SET enabled = $1, writable = $2, mountpoint = $3",
     )
@@ -68,7 +90,27 @@ async fn storage_endpoint_set_vfs_config(
     .await;
 
     match set_config {
-        Ok(_) => HttpResponse::Ok().body("{}"),
+        Ok(_) => {
+            write_client_audit_log(
+                &pool,
+                &req,
+                AuditEvent {
+                    action: "storage_endpoint.set_vfs_config",
+                    target_type: "storage_endpoint",
+                    target_ids: vec![endpoint_id.to_string()],
+                    endpoint_id: Some(endpoint_id),
+                    before: audit_before,
+                    after: Some(json!({
+                        "enabled": form.enabled,
+                        "writable": form.writable,
+                        "mountpoint": form.mountpoint,
+                    })),
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().body("{}")
+        }
         Err(_) => error("storage_vfs.internal"),
     }
 }
@@ -0,0 +1,70 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use futures::join;
+use serde::Serialize;
+
+use crate::request::{error, TableInput};
+use crate::storage_share_links::{StorageShareLinkInfo, SHARE_LINK_INFO_SELECT};
+use crate::user::get_client_rights;
+use crate::util::RequestPool;
+
+#[derive(Serialize)]
+struct StorageShareLinksOutput {
+    share_links: Vec<StorageShareLinkInfo>,
+    total_count: i64,
+}
+
+/**
+ * Get share links created by all users. Search is done by the username of the creator.
+ */
+#[get("/storage/share-links")]
+async fn storage_share_links(
+    pool: web::Data<RequestPool>,
+    query: web::Query<TableInput>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .find(|right| right.right_name.eq("manage_storage_share_links"))
+        .is_some();
+
+    if !action_allowed {
+        return error("storage_share_links.unauthorized");
+    }
+
+    let search = query.search.clone().unwrap_or("".to_string());
+
+    let order_by = match query.order_by.as_ref() {
+        Some(order_by) => match order_by.as_str() {
+            "created_at" => "storage_share_links.created_at",
+            "expires_at" => "storage_share_links.expires_at",
+            "downloads_count" => "storage_share_links.downloads_count",
+            _ => "storage_share_links.created_at",
+        },
+        None => "storage_share_links.created_at",
+    };
+
+    let sql = format!(
+        "{} WHERE {}",
+        SHARE_LINK_INFO_SELECT,
+        query.get_where_sql("COALESCE(users.username, '')", order_by)
+    );
+
+    let share_links = sqlx::query_as::<_, StorageShareLinkInfo>(sql.as_str())
+        .bind(search)
+        .fetch_all(&**pool);
+
+    let share_links_count =
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM storage_share_links").fetch_one(&**pool);
+
+    let (share_links, share_links_count) = join!(share_links, share_links_count);
+
+    match share_links {
+        Ok(share_links) => HttpResponse::Ok().json(web::Json(StorageShareLinksOutput {
+            share_links,
+            total_count: share_links_count.unwrap_or(0),
+        })),
+        Err(_) => error("storage_share_links.internal"),
+    }
+}
@@ -1,8 +1,10 @@
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::request::error;
 use crate::user::get_client_rights;
 use crate::util::RequestPool;
 use actix_web::{put, web, HttpResponse, Responder};
 use serde::Deserialize;
+use serde_json::json;
 
 #[derive(Deserialize)]
 struct UpdateFeatureInput {
@@ -31,8 +33,15 @@ async fn update_feature(
     let target_feature = path.into_inner();
     let enabled = form.enabled;
 
+    let previously_enabled =
+        sqlx::query_scalar::<_, bool>("SELECT enabled FROM features WHERE feature = $1")
+            .bind(&target_feature)
+            .fetch_optional(&**pool)
+            .await
+            .unwrap_or_default();
+
     let result = sqlx::query("INSERT INTO features (feature, enabled) VALUES ($1, $2) ON CONFLICT (feature) DO UPDATE SET enabled = $2")
-        .bind(target_feature)
+        .bind(&target_feature)
         .bind(enabled)
         .bind(enabled)
         .execute(&**pool)
@@ -41,6 +50,20 @@ async fn update_feature(
     match result {
         Ok(result) => {
             if result.rows_affected() == 1 {
+                write_client_audit_log(
+                    &pool,
+                    &req,
+                    AuditEvent {
+                        action: "feature.update",
+                        target_type: "feature",
+                        target_ids: vec![target_feature],
+                        before: previously_enabled.map(|enabled| json!({ "enabled": enabled })),
+                        after: Some(json!({ "enabled": enabled })),
+                        ..Default::default()
+                    },
+                )
+                .await;
+
                 return HttpResponse::Ok().body("{}");
             } else {
                 return error("update_feature.user_not_found");
@@ -0,0 +1,166 @@
+use actix_web::{patch, web, HttpResponse, Responder};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::audit_log::{write_client_audit_log, AuditEvent};
+use crate::request::error;
+use crate::user::get_client_rights;
+use crate::user_oidc::{
+    get_oidc_provider_snapshot, is_valid_issuer, set_oidc_provider_group_mappings, OidcError,
+    OidcGroupMapping,
+};
+use crate::util::RequestPool;
+
+#[derive(Deserialize, Validate)]
+struct UpdateOidcProviderInput {
+    #[validate(length(min = 1, max = 127))]
+    name: Option<String>,
+    #[validate(length(min = 1, max = 255))]
+    issuer: Option<String>,
+    #[validate(length(min = 1, max = 255))]
+    client_id: Option<String>,
+
+    /**
+     * An empty string removes the secret (turns the provider into a public client)
+     */
+    #[validate(length(max = 512))]
+    client_secret: Option<String>,
+
+    #[validate(length(min = 1, max = 255))]
+    scopes: Option<String>,
+    #[validate(length(min = 1, max = 127))]
+    username_claim: Option<String>,
+
+    /**
+     * An empty string turns group membership syncing off
+     */
+    #[validate(length(max = 127))]
+    groups_claim: Option<String>,
+
+    auto_create_users: Option<bool>,
+    enabled: Option<bool>,
+
+    /**
+     * Replaces all the mappings of the provider
+     */
+    group_mappings: Option<Vec<OidcGroupMapping>>,
+}
+
+#[patch("/oidc-providers/{provider_id}")]
+async fn update_oidc_provider(
+    pool: web::Data<RequestPool>,
+    form: web::Json<UpdateOidcProviderInput>,
+    path: web::Path<i32>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+
+    let valid_issuer = form
+        .issuer
+        .as_ref()
+        .is_none_or(|issuer| is_valid_issuer(issuer));
+
+    let valid_scopes = form
+        .scopes
+        .as_ref()
+        .is_none_or(|scopes| scopes.split_whitespace().any(|scope| scope == "openid"));
+
+    if form.validate().is_err() || !valid_issuer || !valid_scopes {
+        return error("update_oidc_provider.invalid_input");
+    }
+
+    let client_rights = get_client_rights(&pool, &req).await;
+
+    let action_allowed = client_rights
+        .iter()
+        .any(|right| right.right_name == "manage_oidc_providers");
+
+    if !action_allowed {
+        return error("update_oidc_provider.unauthorized");
+    }
+
+    let provider_id = path.into_inner();
+
+    let audit_before = get_oidc_provider_snapshot(provider_id, &pool).await;
+
+    if audit_before.is_none() {
+        return error("update_oidc_provider.not_found");
+    }
+
+    let update_result: Result<(), OidcError> = async {
+        let mut transaction = pool.begin().await.map_err(|_| OidcError::Internal)?;
+
+        // `$4` and `$7` are empty strings when the secret or the groups claim are being removed
+        let updated = sqlx::query(
+            "UPDATE oidc_providers SET
+                name = COALESCE($1, name),
+                issuer = COALESCE($2, issuer),
+                client_id = COALESCE($3, client_id),
+                client_secret = CASE WHEN $4::TEXT IS NULL THEN client_secret ELSE NULLIF($4, '') END,
+                scopes = COALESCE($5, scopes),
+                username_claim = COALESCE($6, username_claim),
+                groups_claim = CASE WHEN $7::TEXT IS NULL THEN groups_claim ELSE NULLIF($7, '') END,
+                auto_create_users = COALESCE($8, auto_create_users),
+                enabled = COALESCE($9, enabled)
+            WHERE id = $10",
+        )
+        .bind(&form.name)
+        .bind(&form.issuer)
+        .bind(&form.client_id)
+        .bind(&form.client_secret)
+        .bind(&form.scopes)
+        .bind(&form.username_claim)
+        .bind(&form.groups_claim)
+        .bind(form.auto_create_users)
+        .bind(form.enabled)
+        .bind(provider_id)
+        .execute(&mut *transaction)
+        .await;
+
+        if let Err(err) = updated {
+            return Err(
+                if err
+                    .as_database_error()
+                    .is_some_and(|err| err.is_unique_violation())
+                {
+                    OidcError::ProviderNameTaken
+                } else {
+                    OidcError::Internal
+                },
+            );
+        }
+
+        if let Some(group_mappings) = &form.group_mappings {
+            set_oidc_provider_group_mappings(provider_id, group_mappings, &mut transaction)
+                .await?;
+        }
+
+        transaction.commit().await.map_err(|_| OidcError::Internal)
+    }
+    .await;
+
+    match update_result {
+        Ok(_) => {
+            write_client_audit_log(
+                &pool,
+                &req,
+                AuditEvent {
+                    action: "oidc_provider.update",
+                    target_type: "oidc_provider",
+                    target_ids: vec![provider_id.to_string()],
+                    before: audit_before,
+                    after: get_oidc_provider_snapshot(provider_id, &pool).await,
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().body("{}")
+        }
+        Err(OidcError::ProviderNameTaken) => error("update_oidc_provider.name_taken"),
+        Err(OidcError::InvalidGroupMappings) => {
+            error("update_oidc_provider.invalid_group_mappings")
+        }
+        Err(_) => error("update_oidc_provider.internal"),
+    }
+}
@@ -1,5 +1,6 @@
 use std::env;
 
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::user::get_client_rights;
 use crate::util::RequestPool;
 use crate::{request::error, user::get_user_groups};
@@ -101,6 +102,19 @@ async fn update_password(
             match result {
                 Ok(result) => {
                     if result.rows_affected() == 1 {
+                        // Neither of the passwords is recorded, only the fact that it was changed
+                        write_client_audit_log(
+                            &pool,
+                            &req,
+                            AuditEvent {
+                                action: "user.update_password",
+                                target_type: "user",
+                                target_ids: vec![target_user_id.to_string()],
+                                ..Default::default()
+                            },
+                        )
+                        .await;
+
                         return HttpResponse::Ok().body("{}");
                     } else {
                         return error("update_password.user_not_found");
@@ -1,3 +1,4 @@
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::request::error;
 use crate::user::get_client_rights;
 use crate::util::RequestPool;
@@ -13,6 +14,42 @@ struct UpdateStorageEndpointInput {
     description: Option<String>,
     status: Option<String>,
     access_rules_enabled: Option<bool>,
+    /**
+     * How many previous versions of a file to keep. `0` disables versioning.
+     */
+    #[validate(range(min = 0, max = 1000))]
+    max_file_versions: Option<i32>,
+    /**
+     * Let files with the same contents share a single blob. Not available for endpoints that preserve the file
+     * structure, a blob can only be stored under one path.
+     */
+    deduplication_enabled: Option<bool>,
+    /**
+     * How many bytes each user can store on this endpoint. `0` == unlimited.
+     */
+    #[validate(range(min = 0))]
+    user_quota_bytes: Option<i64>,
+}
+
+/**
+ * Settings of an endpoint, as they are recorded in the audit log
+ */
+async fn get_storage_endpoint_snapshot(
+    endpoint_id: i32,
+    pool: &RequestPool,
+) -> Option<serde_json::Value> {
+    sqlx::query_scalar::<_, serde_json::Value>(
+        "SELECT jsonb_build_object(
+            'name', name, 'description', description, 'status', status, 'access_rules_enabled', access_rules_enabled,
+            'max_file_versions', max_file_versions, 'deduplication_enabled', deduplication_enabled,
+            'user_quota_bytes', user_quota_bytes
+        ) FROM storage_endpoints WHERE id = $1",
+    )
+    .bind(endpoint_id)
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten()
 }
 
 #[patch("/storage/endpoints/{endpoint_id}")]
@@ -41,11 +78,16 @@ async fn update_storage_endpoint(
 
     let storage_endpoint_id = path.into_inner();
 
+    let audit_before = get_storage_endpoint_snapshot(storage_endpoint_id, &pool).await;
+
     // TODO ew..
     let has_updated_name = &form.name.is_some();
     let has_updated_description = &form.description.is_some();
     let has_updated_status = &form.status.is_some();
     let has_updated_access_rules_enabled = &form.access_rules_enabled.is_some();
+    let has_updated_max_file_versions = &form.max_file_versions.is_some();
+    let has_updated_deduplication_enabled = &form.deduplication_enabled.is_some();
+    let has_updated_user_quota_bytes = &form.user_quota_bytes.is_some();
 
     if *has_updated_name {
         let name = &form.name.unwrap();
@@ -110,5 +152,69 @@ async fn update_storage_endpoint(
         }
     }
 
+    if *has_updated_max_file_versions {
+        let max_file_versions = &form.max_file_versions.unwrap();
+
+        let result =
+            sqlx::query("UPDATE storage_endpoints SET max_file_versions = $1 WHERE id = $2")
+                .bind(max_file_versions)
+                .bind(storage_endpoint_id)
+                .execute(&**pool)
+                .await;
+
+        if result.is_err() {
+            return error("update_storage_endpoint.internal");
+        }
+    }
+
+    if *has_updated_deduplication_enabled {
+        let deduplication_enabled = &form.deduplication_enabled.unwrap();
+
+        let result = sqlx::query(
+            "UPDATE storage_endpoints SET deduplication_enabled = $1 WHERE id = $2 AND NOT ($1 AND preserve_file_structure)",
+        )
+        .bind(deduplication_enabled)
+        .bind(storage_endpoint_id)
+        .execute(&**pool)
+        .await;
+
+        if result.is_err() {
+            return error("update_storage_endpoint.internal");
+        }
+
+        if result.unwrap().rows_affected() == 0 {
+            return error("update_storage_endpoint.invalid_input");
+        }
+    }
+
+    if *has_updated_user_quota_bytes {
+        let user_quota_bytes = &form.user_quota_bytes.unwrap();
+
+        let result =
+            sqlx::query("UPDATE storage_endpoints SET user_quota_bytes = $1 WHERE id = $2")
+                .bind(user_quota_bytes)
+                .bind(storage_endpoint_id)
+                .execute(&**pool)
+                .await;
+
+        if result.is_err() {
+            return error("update_storage_endpoint.internal");
+        }
+    }
+
+    write_client_audit_log(
+        &pool,
+        &req,
+        AuditEvent {
+            action: "storage_endpoint.update",
+            target_type: "storage_endpoint",
+            target_ids: vec![storage_endpoint_id.to_string()],
+            endpoint_id: Some(storage_endpoint_id),
+            before: audit_before,
+            after: get_storage_endpoint_snapshot(storage_endpoint_id, &pool).await,
+        },
+    )
+    .await;
+
     return HttpResponse::Ok().body("{}");
 }
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::request::error;
 use crate::user::get_client_rights;
 use crate::util::RequestPool;
@@ -21,6 +22,26 @@ struct UpdateUserGroupInput {
     rights: Option<HashMap<String, Right>>,
 }
 
+/**
+ * Name and rights of a group, as they are recorded in the audit log
+ */
+async fn get_user_group_snapshot(
+    user_group_id: i32,
+    pool: &RequestPool,
+) -> Option<serde_json::Value> {
+    sqlx::query_scalar::<_, serde_json::Value>(
+        "SELECT jsonb_build_object(
+            'name', name,
+            'rights', (SELECT COALESCE(jsonb_object_agg(right_name, right_options), '{}'::jsonb) FROM user_group_rights WHERE group_id = user_groups.id)
+        ) FROM user_groups WHERE id = $1",
+    )
+    .bind(user_group_id)
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten()
+}
+
 #[patch("/user-groups/{user_group_id}")]
 async fn update_user_group(
     pool: web::Data<RequestPool>,
@@ -47,6 +68,8 @@ async fn update_user_group(
 
     let user_group_id = path.into_inner();
 
+    let audit_before = get_user_group_snapshot(user_group_id, &pool).await;
+
     let updated_name = &form.name.is_some();
     let updated_rights = &form.rights.is_some().clone();
 
@@ -116,5 +139,19 @@ async fn update_user_group(
         }
     }
 
+    write_client_audit_log(
+        &pool,
+        &req,
+        AuditEvent {
+            action: "user_group.update",
+            target_type: "user_group",
+            target_ids: vec![user_group_id.to_string()],
+            before: audit_before,
+            after: get_user_group_snapshot(user_group_id, &pool).await,
+            ..Default::default()
+        },
+    )
+    .await;
+
     return HttpResponse::Ok().body("{}");
 }
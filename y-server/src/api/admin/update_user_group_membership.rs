@@ -1,7 +1,9 @@
 use serde::Deserialize;
+use serde_json::json;
 use sqlx::QueryBuilder;
 
 use crate::{
+    audit_log::{write_client_audit_log, AuditEvent},
     request::error,
     user::{get_client_rights, get_user_groups},
 };
@@ -96,6 +98,12 @@ async fn update_user_group_membership(
         return error("update_user_group_membership.unauthorized");
     }
 
+    let audit_before = json!(target_groups
+        .iter()
+        .map(|group| group.id)
+        .collect::<Vec<i32>>());
+    let audit_after = json!(input_groups);
+
     let transaction = pool.begin().await;
 
     if let Ok(mut transaction) = transaction {
@@ -131,6 +139,20 @@ async fn update_user_group_membership(
 
         match result {
             Ok(_) => {
+                write_client_audit_log(
+                    &pool,
+                    &req,
+                    AuditEvent {
+                        action: "user.update_groups",
+                        target_type: "user",
+                        target_ids: vec![target_user_id.to_string()],
+                        before: Some(audit_before),
+                        after: Some(audit_after),
+                        ..Default::default()
+                    },
+                )
+                .await;
+
                 return HttpResponse::Ok().body("{}");
             }
             Err(_) => return error("update_user_group_membership.internal"),
@@ -0,0 +1,38 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+
+use crate::{
+    request::error,
+    user::get_user_from_request,
+    user_api_tokens::{get_user_api_tokens, UserApiToken},
+    util::RequestPool,
+};
+
+#[derive(Serialize)]
+struct ApiTokensOutput {
+    api_tokens: Vec<UserApiToken>,
+}
+
+/**
+ * Get the personal API tokens of the client, along with when and from where they were last used
+ */
+#[get("/api-tokens")]
+async fn api_tokens(pool: web::Data<RequestPool>, req: HttpRequest) -> impl Responder {
+    let client = get_user_from_request(&pool, &req).await;
+
+    if client.is_none() {
+        return error("api_tokens.unauthorized");
+    }
+
+    let (client_user, client_session) = client.unwrap();
+
+    // Tokens can not be used to manage other tokens
+    if client_session.is_api_token() {
+        return error("api_tokens.unauthorized");
+    }
+
+    match get_user_api_tokens(client_user.id, &pool).await {
+        Ok(api_tokens) => HttpResponse::Ok().json(web::Json(ApiTokensOutput { api_tokens })),
+        Err(_) => error("api_tokens.internal"),
+    }
+}
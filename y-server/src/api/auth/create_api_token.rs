@@ -0,0 +1,132 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    audit_log::{write_audit_log, AuditActor, AuditEvent},
+    request::error,
+    user::get_user_from_request,
+    user_api_tokens::{self, API_TOKEN_SCOPES},
+    util::RequestPool,
+};
+
+#[derive(Deserialize, Validate)]
+struct CreateApiTokenInput {
+    #[validate(length(min = 1, max = 127))]
+    name: String,
+
+    /**
+     * "storage_read", "storage_write" and/or "admin"
+     */
+    #[validate(length(min = 1))]
+    scopes: Vec<String>,
+
+    /**
+     * RFC 3339 timestamp, must be in the future
+     */
+    expires_at: String,
+}
+
+#[derive(Serialize)]
+struct CreateApiTokenOutput {
+    id: i32,
+
+    /**
+     * Sent in `Authorization: Bearer <token>`. Only returned once, it can not be retrieved later.
+     */
+    token: String,
+}
+
+#[post("/api-tokens")]
+async fn create_api_token(
+    pool: web::Data<RequestPool>,
+    form: web::Json<CreateApiTokenInput>,
+    req: HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+
+    if form.validate().is_err() {
+        return error("api_tokens.invalid_input");
+    }
+
+    let client = get_user_from_request(&pool, &req).await;
+
+    if client.is_none() {
+        return error("api_tokens.unauthorized");
+    }
+
+    let client = client.unwrap();
+
+    // A token could otherwise be used to mint a token with more scopes than its own
+    if client.1.is_api_token() {
+        return error("api_tokens.unauthorized");
+    }
+
+    let mut scopes: Vec<String> = Vec::new();
+
+    for scope in form.scopes {
+        if !API_TOKEN_SCOPES.contains(&scope.as_str()) {
+            return error("api_tokens.invalid_scope");
+        }
+
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    let expires_at = DateTime::parse_from_rfc3339(&form.expires_at);
+
+    if expires_at.is_err() {
+        return error("api_tokens.invalid_input");
+    }
+
+    let expires_at = expires_at.unwrap().with_timezone(&Utc);
+
+    if expires_at <= Utc::now() {
+        return error("api_tokens.invalid_expiry");
+    }
+
+    let name = form.name.trim();
+
+    if name.is_empty() {
+        return error("api_tokens.invalid_input");
+    }
+
+    let result = user_api_tokens::create_api_token(
+        client.0.id,
+        name,
+        &scopes,
+        expires_at.naive_utc(),
+        &pool,
+    )
+    .await;
+
+    match result {
+        Ok((session, token)) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "auth.api_token.create",
+                    target_type: "api_token",
+                    target_ids: vec![session.id.to_string()],
+                    after: Some(serde_json::json!({
+                        "name": name,
+                        "scopes": scopes,
+                        "expires_at": expires_at.to_rfc3339(),
+                    })),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().json(web::Json(CreateApiTokenOutput {
+                id: session.id,
+                token,
+            }))
+        }
+        Err(_) => error("api_tokens.internal"),
+    }
+}
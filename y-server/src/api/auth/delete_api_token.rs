@@ -0,0 +1,62 @@
+use actix_web::{delete, web, HttpRequest, HttpResponse, Responder};
+
+use crate::{
+    audit_log::{write_audit_log, AuditActor, AuditEvent},
+    request::error,
+    user::get_user_from_request,
+    util::RequestPool,
+};
+
+/**
+ * Revoke one of the client's personal API tokens
+ */
+#[delete("/api-tokens/{token_id}")]
+async fn delete_api_token(
+    pool: web::Data<RequestPool>,
+    path: web::Path<i32>,
+    req: HttpRequest,
+) -> impl Responder {
+    let token_id = path.into_inner();
+
+    let client = get_user_from_request(&pool, &req).await;
+
+    if client.is_none() {
+        return error("api_tokens.unauthorized");
+    }
+
+    let client = client.unwrap();
+
+    if client.1.is_api_token() {
+        return error("api_tokens.unauthorized");
+    }
+
+    let result = sqlx::query_scalar::<_, String>(
+        "DELETE FROM user_sessions WHERE id = $1 AND user_id = $2 AND token_name IS NOT NULL RETURNING token_name",
+    )
+    .bind(token_id)
+    .bind(client.0.id)
+    .fetch_optional(&**pool)
+    .await;
+
+    match result {
+        Ok(Some(token_name)) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "auth.api_token.revoke",
+                    target_type: "api_token",
+                    target_ids: vec![token_id.to_string()],
+                    before: Some(serde_json::json!({ "name": token_name })),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().body("{}")
+        }
+        Ok(None) => error("api_tokens.not_found"),
+        Err(_) => error("api_tokens.internal"),
+    }
+}
@@ -1,13 +1,16 @@
-use actix_web::{cookie::Cookie, post, web, HttpResponse, Responder};
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
 use pbkdf2::{
     password_hash::{PasswordHash, PasswordVerifier},
     Pbkdf2,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 use crate::{
+    audit_log::{write_audit_log, AuditActor, AuditEvent},
     request::error,
-    user::{create_user_session, User},
+    user::{build_session_cookie, create_user_session, User},
+    user_totp::{create_pending_login, is_two_factor_enabled, is_two_factor_required},
 };
 
 use crate::util::RequestPool;
@@ -18,8 +21,34 @@ struct LoginInput {
     password: String,
 }
 
+#[derive(Serialize)]
+struct LoginTwoFactorOutput {
+    two_factor_required: bool,
+
+    /**
+     * The user is required to use two-factor authentication, but has not set it up yet. The client has to do that
+     * (`/two-factor/enroll` and `/two-factor/confirm`) before the session is created.
+     */
+    enrollment_required: bool,
+
+    /**
+     * Sent along with the second factor. Expires after a few minutes.
+     */
+    pending_token: String,
+}
+
+/**
+ * Log in with a username and a password.
+ *
+ * Users with two-factor authentication don't get a session right away. The response contains a pending token
+ * instead, which is exchanged for a session at `/login/two-factor`.
+ */
 #[post("/login")]
-async fn login(pool: web::Data<RequestPool>, form: web::Json<LoginInput>) -> impl Responder {
+async fn login(
+    pool: web::Data<RequestPool>,
+    form: web::Json<LoginInput>,
+    req: HttpRequest,
+) -> impl Responder {
     let provided_password = form.password.clone();
 
     let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
@@ -38,25 +67,74 @@ async fn login(pool: web::Data<RequestPool>, form: web::Json<LoginInput>) -> imp
 
                     match passwords_match {
                         Ok(_) => {
+                            let two_factor_enabled = is_two_factor_enabled(user.id, &pool).await;
+
+                            if two_factor_enabled.is_err() {
+                                return error("auth.internal");
+                            }
+
+                            let two_factor_enabled = two_factor_enabled.unwrap();
+
+                            if two_factor_enabled || is_two_factor_required(user.id, &pool).await {
+                                let enrollment_required = !two_factor_enabled;
+
+                                return match create_pending_login(
+                                    user.id,
+                                    enrollment_required,
+                                    &pool,
+                                )
+                                .await
+                                {
+                                    Ok(pending_token) => {
+                                        HttpResponse::Ok().json(web::Json(LoginTwoFactorOutput {
+                                            two_factor_required: true,
+                                            enrollment_required,
+                                            pending_token,
+                                        }))
+                                    }
+                                    Err(err) => error(err.get_code()),
+                                };
+                            }
+
                             let new_session = create_user_session(&pool, user.id).await;
 
                             if let Ok(new_session) = new_session {
-                                let session_cookie_value = format!(
-                                    "{}:{}",
-                                    new_session.session_id, new_session.session_key
-                                );
-
-                                let session_cookie =
-                                    Cookie::build("y-session", session_cookie_value)
-                                        .secure(false)
-                                        .http_only(true)
-                                        .path("/")
-                                        .finish();
-
-                                return HttpResponse::Ok().cookie(session_cookie).body("{}");
+                                write_audit_log(
+                                    &pool,
+                                    &req,
+                                    Some(AuditActor {
+                                        user_id: user.id,
+                                        session_id: Some(new_session.id),
+                                    }),
+                                    AuditEvent {
+                                        action: "auth.login",
+                                        target_type: "user",
+                                        target_ids: vec![user.id.to_string()],
+                                        ..Default::default()
+                                    },
+                                )
+                                .await;
+
+                                return HttpResponse::Ok()
+                                    .cookie(build_session_cookie(&new_session))
+                                    .body("{}");
                             }
                         }
                         Err(_) => {
+                            write_audit_log(
+                                &pool,
+                                &req,
+                                None,
+                                AuditEvent {
+                                    action: "auth.login_failed",
+                                    target_type: "user",
+                                    target_ids: vec![user.id.to_string()],
+                                    after: Some(json!({ "username": user.username })),
+                                    ..Default::default()
+                                },
+                            )
+                            .await;
+
                             return error("auth.passwords_do_not_match");
                         }
                     }
@@ -69,6 +147,20 @@ async fn login(pool: web::Data<RequestPool>, form: web::Json<LoginInput>) -> imp
             }
         }
     } else {
+        // Attempts to guess usernames are worth recording as well
+        write_audit_log(
+            &pool,
+            &req,
+            None,
+            AuditEvent {
+                action: "auth.login_failed",
+                target_type: "user",
+                after: Some(json!({ "username": form.username })),
+                ..Default::default()
+            },
+        )
+        .await;
+
         return error("auth.user_does_not_exist");
     }
 
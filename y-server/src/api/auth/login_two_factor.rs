@@ -0,0 +1,105 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    audit_log::{write_audit_log, AuditActor, AuditEvent},
+    request::error,
+    user::{build_session_cookie, create_user_session},
+    user_totp::{check_second_factor, delete_pending_login, get_pending_login},
+    util::RequestPool,
+};
+
+#[derive(Deserialize)]
+struct LoginTwoFactorInput {
+    pending_token: String,
+
+    /**
+     * Code from the authenticator app. Either this or `recovery_code` is required.
+     */
+    code: Option<String>,
+    recovery_code: Option<String>,
+}
+
+/**
+ * Second step of the login for users with two-factor authentication
+ */
+#[post("/login/two-factor")]
+async fn login_two_factor(
+    pool: web::Data<RequestPool>,
+    form: web::Json<LoginTwoFactorInput>,
+    req: HttpRequest,
+) -> impl Responder {
+    let pending_login = get_pending_login(&form.pending_token, &pool).await;
+
+    if let Err(err) = pending_login {
+        return error(err.get_code());
+    }
+
+    let pending_login = pending_login.unwrap();
+
+    if pending_login.enrollment_required {
+        return error("two_factor.enrollment_required");
+    }
+
+    let user_id = pending_login.user_id;
+
+    let second_factor_result = check_second_factor(
+        user_id,
+        form.code.as_deref(),
+        form.recovery_code.as_deref(),
+        &pool,
+    )
+    .await;
+
+    if let Err(err) = second_factor_result {
+        write_audit_log(
+            &pool,
+            &req,
+            None,
+            AuditEvent {
+                action: "auth.login_failed",
+                target_type: "user",
+                target_ids: vec![user_id.to_string()],
+                after: Some(json!({ "reason": "invalid_second_factor" })),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        return error(err.get_code());
+    }
+
+    delete_pending_login(pending_login.id, &pool).await;
+
+    let new_session = create_user_session(&pool, user_id).await;
+
+    if new_session.is_err() {
+        return error("auth.internal");
+    }
+
+    let new_session = new_session.unwrap();
+
+    write_audit_log(
+        &pool,
+        &req,
+        Some(AuditActor {
+            user_id,
+            session_id: Some(new_session.id),
+        }),
+        AuditEvent {
+            action: "auth.login",
+            target_type: "user",
+            target_ids: vec![user_id.to_string()],
+            after: Some(json!({
+                "second_factor": if form.code.is_some() { "totp" } else { "recovery_code" },
+            })),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    HttpResponse::Ok()
+        .cookie(build_session_cookie(&new_session))
+        .body("{}")
+}
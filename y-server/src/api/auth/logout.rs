@@ -1,6 +1,7 @@
 use actix_web::{cookie::Cookie, post, web, HttpRequest, HttpResponse, Responder};
 
 use crate::{
+    audit_log::{write_audit_log, AuditActor, AuditEvent},
     request::error,
     user::{destroy_user_session, get_user_from_request},
     util::RequestPool,
@@ -10,10 +11,23 @@ use crate::{
 async fn logout(pool: web::Data<RequestPool>, req: HttpRequest) -> impl Responder {
     let session_info = get_user_from_request(&pool, &req).await;
 
-    if let Some((_, session)) = session_info {
-        let result = destroy_user_session(&pool, session.session_id).await;
+    if let Some(client) = session_info {
+        let result = destroy_user_session(&pool, client.1.session_id).await;
 
         if result {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "auth.logout",
+                    target_type: "user",
+                    target_ids: vec![client.0.id.to_string()],
+                    ..Default::default()
+                },
+            )
+            .await;
+
             return HttpResponse::Ok()
                 .cookie(
                     Cookie::build("y-session", "")
@@ -1,3 +1,15 @@
+pub mod api_tokens;
+pub mod create_api_token;
+pub mod delete_api_token;
 pub mod login;
+pub mod login_two_factor;
 pub mod logout;
 pub mod me;
+pub mod oidc_callback;
+pub mod oidc_login;
+pub mod oidc_providers;
+pub mod two_factor;
+pub mod two_factor_confirm;
+pub mod two_factor_disable;
+pub mod two_factor_enroll;
+pub mod two_factor_recovery_codes;
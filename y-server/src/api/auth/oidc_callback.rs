@@ -0,0 +1,223 @@
+use actix_web::{get, http::header, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    audit_log::{write_audit_log, AuditActor, AuditEvent},
+    user::{build_session_cookie, create_user_session},
+    user_oidc::{
+        build_oidc_state_removal_cookie, check_oidc_state_cookie, find_or_create_oidc_user,
+        finish_oidc_login, get_oidc_provider, oidc_error_redirect, oidc_pending_login_redirect,
+        sync_oidc_user_groups, take_oidc_login_request, OidcError,
+    },
+    user_totp::{create_pending_login, is_two_factor_enabled, is_two_factor_required},
+    util::RequestPool,
+};
+
+#[derive(Deserialize)]
+struct OidcCallbackQuery {
+    state: Option<String>,
+    code: Option<String>,
+
+    /**
+     * Set by the provider if the login was denied or cancelled
+     */
+    error: Option<String>,
+}
+
+async fn record_failed_login(
+    pool: &RequestPool,
+    req: &HttpRequest,
+    provider_id: i32,
+    err: &OidcError,
+) {
+    write_audit_log(
+        pool,
+        req,
+        None,
+        AuditEvent {
+            action: "auth.login_failed",
+            target_type: "user",
+            after: Some(
+                json!({ "method": "oidc", "provider_id": provider_id, "reason": err.get_code() }),
+            ),
+            ..Default::default()
+        },
+    )
+    .await;
+}
+
+/**
+ * Where identity providers send the browser back to after logging in. On success, the client gets a session and
+ * is sent to the web interface.
+ */
+#[get("/oidc/callback")]
+async fn oidc_callback(
+    pool: web::Data<RequestPool>,
+    query: web::Query<OidcCallbackQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let mut response = handle_oidc_callback(&pool, &query, &req).await;
+
+    // The state can only be used once either way
+    let _ = response.add_removal_cookie(&build_oidc_state_removal_cookie(&req));
+
+    response
+}
+
+async fn handle_oidc_callback(
+    pool: &RequestPool,
+    query: &OidcCallbackQuery,
+    req: &HttpRequest,
+) -> HttpResponse {
+    let login_request = match &query.state {
+        Some(state) if check_oidc_state_cookie(req, state) => {
+            take_oidc_login_request(state, pool).await
+        }
+        Some(_) => Err(OidcError::StateMismatch),
+        None => Err(OidcError::LoginRequestNotFound),
+    };
+
+    if let Err(err) = login_request {
+        return oidc_error_redirect(&err);
+    }
+
+    let login_request = login_request.unwrap();
+    let provider_id = login_request.provider_id;
+
+    let code = match (&query.code, &query.error) {
+        (Some(code), None) => code,
+        _ => {
+            record_failed_login(pool, req, provider_id, &OidcError::AccessDenied).await;
+
+            return oidc_error_redirect(&OidcError::AccessDenied);
+        }
+    };
+
+    let provider = match get_oidc_provider(provider_id, pool).await {
+        Ok(provider) if provider.enabled => provider,
+        Ok(_) => return oidc_error_redirect(&OidcError::ProviderNotFound),
+        Err(err) => return oidc_error_redirect(&err),
+    };
+
+    let identity = finish_oidc_login(&provider, &login_request, code).await;
+
+    if let Err(err) = identity {
+        record_failed_login(pool, req, provider_id, &err).await;
+
+        return oidc_error_redirect(&err);
+    }
+
+    let identity = identity.unwrap();
+
+    let user = find_or_create_oidc_user(&provider, &identity, pool).await;
+
+    if let Err(err) = user {
+        record_failed_login(pool, req, provider_id, &err).await;
+
+        return oidc_error_redirect(&err);
+    }
+
+    let (user_id, user_created) = user.unwrap();
+
+    if user_created {
+        write_audit_log(
+            pool,
+            req,
+            Some(AuditActor {
+                user_id,
+                session_id: None,
+            }),
+            AuditEvent {
+                action: "user.create",
+                target_type: "user",
+                target_ids: vec![user_id.to_string()],
+                after: Some(json!({ "username": identity.username, "provider_id": provider_id })),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    let groups_sync_result = sync_oidc_user_groups(&provider, user_id, &identity, pool).await;
+
+    if let Err(err) = groups_sync_result {
+        return oidc_error_redirect(&err);
+    }
+
+    let (added_groups, removed_groups) = groups_sync_result.unwrap();
+
+    if !added_groups.is_empty() || !removed_groups.is_empty() {
+        write_audit_log(
+            pool,
+            req,
+            Some(AuditActor {
+                user_id,
+                session_id: None,
+            }),
+            AuditEvent {
+                action: "user.update_groups",
+                target_type: "user",
+                target_ids: vec![user_id.to_string()],
+                after: Some(json!({
+                    "added": added_groups,
+                    "removed": removed_groups,
+                    "provider_id": provider_id
+                })),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    // Two-factor authentication applies to all logins, regardless of what the provider asked for
+    let two_factor_enabled = is_two_factor_enabled(user_id, pool).await;
+
+    if two_factor_enabled.is_err() {
+        return oidc_error_redirect(&OidcError::Internal);
+    }
+
+    let two_factor_enabled = two_factor_enabled.unwrap();
+
+    if two_factor_enabled || is_two_factor_required(user_id, pool).await {
+        let enrollment_required = !two_factor_enabled;
+
+        return match create_pending_login(user_id, enrollment_required, pool).await {
+            Ok(pending_token) => oidc_pending_login_redirect(&pending_token, enrollment_required),
+            Err(_) => oidc_error_redirect(&OidcError::Internal),
+        };
+    }
+
+    let new_session = create_user_session(pool, user_id).await;
+
+    if new_session.is_err() {
+        return oidc_error_redirect(&OidcError::Internal);
+    }
+
+    let new_session = new_session.unwrap();
+
+    write_audit_log(
+        pool,
+        req,
+        Some(AuditActor {
+            user_id,
+            session_id: Some(new_session.id),
+        }),
+        AuditEvent {
+            action: "auth.login",
+            target_type: "user",
+            target_ids: vec![user_id.to_string()],
+            after: Some(json!({ "method": "oidc", "provider_id": provider_id })),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    HttpResponse::Found()
+        .cookie(build_session_cookie(&new_session))
+        .insert_header((
+            header::LOCATION,
+            login_request.return_to.unwrap_or("/".to_string()),
+        ))
+        .finish()
+}
@@ -0,0 +1,53 @@
+use actix_web::{get, http::header, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+
+use crate::{
+    user_oidc::{
+        build_oidc_state_cookie, get_oidc_provider, get_oidc_redirect_uri, is_valid_return_path,
+        oidc_error_redirect, start_oidc_login, OidcError,
+    },
+    util::RequestPool,
+};
+
+#[derive(Deserialize)]
+struct OidcLoginQuery {
+    /**
+     * Path in the web interface to go to after logging in
+     */
+    #[serde(rename = "return")]
+    return_to: Option<String>,
+}
+
+/**
+ * Log in with an identity provider. The browser is sent to the provider, and comes back to `/oidc/callback`.
+ */
+#[get("/oidc/{provider_id}/login")]
+async fn oidc_login(
+    pool: web::Data<RequestPool>,
+    path: web::Path<i32>,
+    query: web::Query<OidcLoginQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let provider = get_oidc_provider(path.into_inner(), &pool).await;
+
+    let provider = match provider {
+        Ok(provider) if provider.enabled => provider,
+        Ok(_) => return oidc_error_redirect(&OidcError::ProviderNotFound),
+        Err(err) => return oidc_error_redirect(&err),
+    };
+
+    let return_to = query
+        .return_to
+        .as_deref()
+        .filter(|return_to| is_valid_return_path(return_to));
+
+    let redirect_uri = get_oidc_redirect_uri(&req);
+
+    match start_oidc_login(&provider, &redirect_uri, return_to, &pool).await {
+        Ok((authorization_url, state)) => HttpResponse::Found()
+            .cookie(build_oidc_state_cookie(&state, &req))
+            .insert_header((header::LOCATION, authorization_url))
+            .finish(),
+        Err(err) => oidc_error_redirect(&err),
+    }
+}
@@ -0,0 +1,28 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::{request::error, util::RequestPool};
+
+#[derive(Serialize, FromRow)]
+struct OidcProvidersItem {
+    id: i32,
+    name: String,
+}
+
+/**
+ * Identity providers that can be logged in with, for the login page
+ */
+#[get("/oidc/providers")]
+async fn oidc_providers(pool: web::Data<RequestPool>) -> impl Responder {
+    let providers = sqlx::query_as::<_, OidcProvidersItem>(
+        "SELECT id, name FROM oidc_providers WHERE enabled = true ORDER BY name",
+    )
+    .fetch_all(&**pool)
+    .await;
+
+    match providers {
+        Ok(providers) => HttpResponse::Ok().json(web::Json(providers)),
+        Err(_) => error("oidc.internal"),
+    }
+}
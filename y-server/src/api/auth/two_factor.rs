@@ -0,0 +1,50 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+
+use crate::{
+    request::error,
+    user::get_user_from_request,
+    user_totp::{
+        get_remaining_recovery_codes_count, is_two_factor_enabled, is_two_factor_required,
+    },
+    util::RequestPool,
+};
+
+#[derive(Serialize)]
+struct TwoFactorOutput {
+    enabled: bool,
+
+    /**
+     * One of the client's groups requires two-factor authentication, it can not be turned off
+     */
+    required: bool,
+
+    recovery_codes_remaining: i64,
+}
+
+/**
+ * Two-factor authentication status of the client
+ */
+#[get("/two-factor")]
+async fn two_factor(pool: web::Data<RequestPool>, req: HttpRequest) -> impl Responder {
+    let client = get_user_from_request(&pool, &req).await;
+
+    if client.is_none() {
+        return error("two_factor.unauthorized");
+    }
+
+    let (client_user, _) = client.unwrap();
+
+    let enabled = is_two_factor_enabled(client_user.id, &pool).await;
+    let recovery_codes_remaining = get_remaining_recovery_codes_count(client_user.id, &pool).await;
+
+    if enabled.is_err() || recovery_codes_remaining.is_err() {
+        return error("two_factor.internal");
+    }
+
+    HttpResponse::Ok().json(web::Json(TwoFactorOutput {
+        enabled: enabled.unwrap_or(false),
+        required: is_two_factor_required(client_user.id, &pool).await,
+        recovery_codes_remaining: recovery_codes_remaining.unwrap_or(0),
+    }))
+}
@@ -0,0 +1,115 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    audit_log::{write_audit_log, AuditActor, AuditEvent},
+    request::error,
+    user::{build_session_cookie, create_user_session},
+    user_totp::{confirm_totp, delete_pending_login, get_enrolling_client},
+    util::RequestPool,
+};
+
+#[derive(Deserialize)]
+struct TwoFactorConfirmInput {
+    code: String,
+
+    /**
+     * Same as in `/two-factor/enroll`. If set, the login is finished and a session is created.
+     */
+    pending_token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TwoFactorConfirmOutput {
+    /**
+     * One-time codes for when the authenticator app is not available. They can not be retrieved later.
+     */
+    recovery_codes: Vec<String>,
+}
+
+/**
+ * Turn on two-factor authentication with the first code from the authenticator app
+ */
+#[post("/two-factor/confirm")]
+async fn two_factor_confirm(
+    pool: web::Data<RequestPool>,
+    form: web::Json<TwoFactorConfirmInput>,
+    req: HttpRequest,
+) -> impl Responder {
+    let client = get_enrolling_client(form.pending_token.as_deref(), &req, &pool).await;
+
+    if let Err(err) = client {
+        return error(err.get_code());
+    }
+
+    let client = client.unwrap();
+
+    let recovery_codes = confirm_totp(client.user_id, &form.code, &pool).await;
+
+    if let Err(err) = recovery_codes {
+        return error(err.get_code());
+    }
+
+    let recovery_codes = recovery_codes.unwrap();
+
+    // Users who had to set up 2FA to log in are logged in now
+    let new_session = if let Some(pending_login_id) = client.pending_login_id {
+        delete_pending_login(pending_login_id, &pool).await;
+
+        match create_user_session(&pool, client.user_id).await {
+            Ok(new_session) => Some(new_session),
+            Err(_) => return error("auth.internal"),
+        }
+    } else {
+        None
+    };
+
+    let session_id = new_session
+        .as_ref()
+        .map(|session| session.id)
+        .or(client.session_id);
+
+    write_audit_log(
+        &pool,
+        &req,
+        Some(AuditActor {
+            user_id: client.user_id,
+            session_id,
+        }),
+        AuditEvent {
+            action: "auth.two_factor.enable",
+            target_type: "user",
+            target_ids: vec![client.user_id.to_string()],
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let output = web::Json(TwoFactorConfirmOutput { recovery_codes });
+
+    if let Some(new_session) = new_session {
+        write_audit_log(
+            &pool,
+            &req,
+            Some(AuditActor {
+                user_id: client.user_id,
+                session_id,
+            }),
+            AuditEvent {
+                action: "auth.login",
+                target_type: "user",
+                target_ids: vec![client.user_id.to_string()],
+                after: Some(json!({ "second_factor": "totp" })),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        HttpResponse::Ok()
+            .cookie(build_session_cookie(&new_session))
+            .json(output)
+    } else {
+        HttpResponse::Ok().json(output)
+    }
+}
@@ -0,0 +1,73 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+
+use crate::{
+    audit_log::{write_audit_log, AuditActor, AuditEvent},
+    request::error,
+    user::get_user_from_request,
+    user_totp::{check_second_factor, disable_totp, is_two_factor_required},
+    util::RequestPool,
+};
+
+#[derive(Deserialize)]
+struct TwoFactorDisableInput {
+    /**
+     * Either a code from the authenticator app or a recovery code is required
+     */
+    code: Option<String>,
+    recovery_code: Option<String>,
+}
+
+/**
+ * Turn off two-factor authentication for the client. Not allowed if one of their groups requires it.
+ */
+#[post("/two-factor/disable")]
+async fn two_factor_disable(
+    pool: web::Data<RequestPool>,
+    form: web::Json<TwoFactorDisableInput>,
+    req: HttpRequest,
+) -> impl Responder {
+    let client = get_user_from_request(&pool, &req).await;
+
+    if client.is_none() || client.as_ref().unwrap().1.is_api_token() {
+        return error("two_factor.unauthorized");
+    }
+
+    let client = client.unwrap();
+    let user_id = client.0.id;
+
+    if is_two_factor_required(user_id, &pool).await {
+        return error("two_factor.required");
+    }
+
+    if let Err(err) = check_second_factor(
+        user_id,
+        form.code.as_deref(),
+        form.recovery_code.as_deref(),
+        &pool,
+    )
+    .await
+    {
+        return error(err.get_code());
+    }
+
+    match disable_totp(user_id, &pool).await {
+        Ok(_) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "auth.two_factor.disable",
+                    target_type: "user",
+                    target_ids: vec![user_id.to_string()],
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().body("{}")
+        }
+        Err(err) => error(err.get_code()),
+    }
+}
@@ -0,0 +1,56 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    request::error,
+    user_totp::{enroll_totp, get_enrolling_client},
+    util::RequestPool,
+};
+
+#[derive(Deserialize)]
+struct TwoFactorEnrollInput {
+    /**
+     * Pending token from `/login`, for users who have to set up two-factor authentication before they can log in
+     */
+    pending_token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TwoFactorEnrollOutput {
+    /**
+     * Base32 secret, for entering into an authenticator app by hand
+     */
+    secret: String,
+
+    /**
+     * `otpauth://` URI, to be shown as a QR code
+     */
+    otpauth_uri: String,
+}
+
+/**
+ * Start setting up two-factor authentication. It is not turned on until the first code is confirmed
+ * at `/two-factor/confirm`.
+ */
+#[post("/two-factor/enroll")]
+async fn two_factor_enroll(
+    pool: web::Data<RequestPool>,
+    form: web::Json<TwoFactorEnrollInput>,
+    req: HttpRequest,
+) -> impl Responder {
+    let client = get_enrolling_client(form.pending_token.as_deref(), &req, &pool).await;
+
+    if let Err(err) = client {
+        return error(err.get_code());
+    }
+
+    let client = client.unwrap();
+
+    match enroll_totp(client.user_id, &client.username, &pool).await {
+        Ok((secret, otpauth_uri)) => HttpResponse::Ok().json(web::Json(TwoFactorEnrollOutput {
+            secret,
+            otpauth_uri,
+        })),
+        Err(err) => error(err.get_code()),
+    }
+}
@@ -0,0 +1,66 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit_log::{write_audit_log, AuditActor, AuditEvent},
+    request::error,
+    user::get_user_from_request,
+    user_totp::{check_totp_code, regenerate_recovery_codes},
+    util::RequestPool,
+};
+
+#[derive(Deserialize)]
+struct TwoFactorRecoveryCodesInput {
+    /**
+     * Code from the authenticator app
+     */
+    code: String,
+}
+
+#[derive(Serialize)]
+struct TwoFactorRecoveryCodesOutput {
+    recovery_codes: Vec<String>,
+}
+
+/**
+ * Replace the client's recovery codes with new ones. The old codes stop working.
+ */
+#[post("/two-factor/recovery-codes")]
+async fn two_factor_recovery_codes(
+    pool: web::Data<RequestPool>,
+    form: web::Json<TwoFactorRecoveryCodesInput>,
+    req: HttpRequest,
+) -> impl Responder {
+    let client = get_user_from_request(&pool, &req).await;
+
+    if client.is_none() || client.as_ref().unwrap().1.is_api_token() {
+        return error("two_factor.unauthorized");
+    }
+
+    let client = client.unwrap();
+    let user_id = client.0.id;
+
+    if let Err(err) = check_totp_code(user_id, &form.code, &pool).await {
+        return error(err.get_code());
+    }
+
+    match regenerate_recovery_codes(user_id, &pool).await {
+        Ok(recovery_codes) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "auth.two_factor.regenerate_recovery_codes",
+                    target_type: "user",
+                    target_ids: vec![user_id.to_string()],
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().json(web::Json(TwoFactorRecoveryCodesOutput { recovery_codes }))
+        }
+        Err(err) => error(err.get_code()),
+    }
+}
@@ -1,5 +1,7 @@
 pub mod admin;
 pub mod auth;
 pub mod instance_config;
+pub mod share;
 pub mod storage;
 pub mod user_rights;
+pub mod webdav;
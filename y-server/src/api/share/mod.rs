@@ -0,0 +1,4 @@
+pub mod share_link;
+pub mod share_link_archive;
+pub mod share_link_entries;
+pub mod share_link_get;
@@ -0,0 +1,74 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+
+use crate::request::error;
+use crate::storage_share_links::get_share_link_from_request;
+use crate::util::RequestPool;
+
+#[derive(Serialize, FromRow)]
+struct SharedEntryRow {
+    id: i64,
+    name: String,
+    extension: Option<String>,
+    entry_type: String,
+    mime_type: Option<String>,
+    size_bytes: Option<i64>,
+    created_at: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ShareLinkOutput {
+    entry: SharedEntryRow,
+    allow_browsing: bool,
+    expires_at: Option<String>,
+
+    /**
+     * How many more times the link can be used to download something. `null` == unlimited.
+     */
+    downloads_left: Option<i32>,
+}
+
+/**
+ * Get the entry a share link points to
+ */
+#[get("/{token}")]
+async fn share_link(
+    pool: web::Data<RequestPool>,
+    path: web::Path<String>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let token = path.into_inner();
+
+    let share_link = get_share_link_from_request(&token, &req, &pool).await;
+
+    if let Err(err) = share_link {
+        return error(err.get_code());
+    }
+
+    let share_link = share_link.unwrap();
+
+    let entry = sqlx::query_as::<_, SharedEntryRow>(
+        "SELECT id, name, extension, entry_type::TEXT, mime_type, size_bytes, created_at::TEXT FROM storage_entries WHERE endpoint_id = $1 AND id = $2",
+    )
+    .bind(share_link.endpoint_id)
+    .bind(share_link.entry_id)
+    .fetch_optional(&**pool)
+    .await;
+
+    match entry {
+        Ok(Some(entry)) => HttpResponse::Ok().json(web::Json(ShareLinkOutput {
+            entry,
+            allow_browsing: share_link.allow_browsing,
+            expires_at: share_link
+                .expires_at
+                .map(|expires_at| expires_at.to_rfc3339()),
+            downloads_left: share_link
+                .max_downloads
+                .map(|max_downloads| (max_downloads - share_link.downloads_count).max(0)),
+        })),
+        // The entry was moved to the trash
+        Ok(None) => error("share_link.not_found"),
+        Err(_) => error("share_link.internal"),
+    }
+}
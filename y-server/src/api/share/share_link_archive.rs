@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Query;
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Deserialize;
+
+use crate::request::error;
+use crate::storage_archive_stream::{stream_archive, StorageArchiveStreamFormat};
+use crate::storage_archives::{get_archive_settings, get_archive_total_size};
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::{get_subfolders_level_with_access_rules, resolve_entries};
+use crate::storage_share_links::{
+    check_share_link_entry_access, get_share_link_from_request, register_share_link_download,
+};
+use crate::user::get_user_groups;
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct QueryParams {
+    /**
+     * `null` == the shared folder itself. Other folders can only be downloaded if the link allows browsing.
+     */
+    folder_id: Option<i64>,
+}
+
+/**
+ * Download a shared folder as a zip archive. The archive is streamed while it's being written, nothing is staged on
+ * disk.
+ */
+#[get("/{token}/archive")]
+async fn share_link_archive(
+    pool: web::Data<RequestPool>,
+    path: web::Path<String>,
+    query: Query<QueryParams>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let token = path.into_inner();
+
+    let share_link = get_share_link_from_request(&token, &req, &pool).await;
+
+    if let Err(err) = share_link {
+        return error(err.get_code());
+    }
+
+    let share_link = share_link.unwrap();
+    let endpoint_id = share_link.endpoint_id;
+
+    let folder_id = query.folder_id.unwrap_or(share_link.entry_id);
+
+    if !check_share_link_entry_access(&share_link, folder_id, &pool).await {
+        return error("share_link.access_denied");
+    }
+
+    let folder_name = sqlx::query_scalar::<_, String>(
+        "SELECT name FROM storage_entries WHERE endpoint_id = $1 AND id = $2 AND entry_type = 'folder'::storage_entry_type",
+    )
+    .bind(endpoint_id)
+    .bind(folder_id)
+    .fetch_optional(&**pool)
+    .await;
+
+    let folder_name = match folder_name {
+        Ok(Some(folder_name)) => folder_name,
+        Ok(None) => return error("share_link.not_a_folder"),
+        Err(_) => return error("share_link.internal"),
+    };
+
+    // Access rules inside of the folder still apply to the creator of the link
+    let creator_groups = get_user_groups(&pool, share_link.created_by).await;
+    let creator_group_ids = creator_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    let mut file_filesystem_ids: Vec<String> = Vec::new();
+    let mut folder_parents: HashMap<i64, Option<i64>> = HashMap::from([(folder_id, None)]);
+
+    let traverse_down_result = get_subfolders_level_with_access_rules(
+        endpoint_id,
+        &mut folder_parents,
+        &mut file_filesystem_ids,
+        vec![folder_id],
+        (share_link.created_by, &creator_group_ids),
+        "download",
+        &pool,
+    )
+    .await;
+
+    if traverse_down_result.is_err() {
+        return error("share_link.access_denied");
+    }
+
+    let resolved_entries = resolve_entries(endpoint_id, vec![folder_id], vec![], &pool).await;
+
+    if resolved_entries.is_err() {
+        return error("share_link.internal");
+    }
+
+    let resolved_entries = resolved_entries.unwrap();
+
+    let settings = get_archive_settings(&pool).await;
+
+    if settings.max_size_bytes.is_some() {
+        let total_bytes = get_archive_total_size(endpoint_id, &resolved_entries, &pool).await;
+
+        if total_bytes.is_err() {
+            return error("share_link.internal");
+        }
+
+        if settings.check_size(total_bytes.unwrap()).is_err() {
+            return error("share_link.archive_too_large");
+        }
+    }
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return error("share_link.not_found");
+    }
+
+    let target_endpoint = target_endpoint.unwrap();
+
+    let body = stream_archive(
+        target_endpoint,
+        resolved_entries,
+        StorageArchiveStreamFormat::Zip,
+        pool.get_ref().clone(),
+    );
+
+    if body.is_none() {
+        return error("share_link.too_many_archive_streams");
+    }
+
+    let body = body.unwrap();
+
+    // Only counted once the archive is actually being sent. Dropping the stream stops writing it.
+    if let Err(err) = register_share_link_download(&share_link, None, &req, &pool).await {
+        return error(err.get_code());
+    }
+
+    HttpResponse::Ok()
+        .content_type(StorageArchiveStreamFormat::Zip.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(format!("{}.zip", folder_name))],
+            },
+        ))
+        .streaming(body)
+}
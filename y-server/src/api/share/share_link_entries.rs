@@ -0,0 +1,78 @@
+use actix_web::web::Query;
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+use crate::request::error;
+use crate::storage_share_links::{check_share_link_entry_access, get_share_link_from_request};
+use crate::util::RequestPool;
+
+#[derive(Serialize, FromRow)]
+struct SharedEntryRow {
+    id: i64,
+    parent_folder: Option<i64>,
+    name: String,
+    extension: Option<String>,
+    entry_type: String,
+    mime_type: Option<String>,
+    size_bytes: Option<i64>,
+    created_at: Option<String>,
+    transcoded_version_available: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct ShareLinkEntriesOutput {
+    entries: Vec<SharedEntryRow>,
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+    /**
+     * `null` == the shared folder itself
+     */
+    folder_id: Option<i64>,
+}
+
+/**
+ * List entries inside of a shared folder. Only available if the link allows browsing.
+ */
+#[get("/{token}/entries")]
+async fn share_link_entries(
+    pool: web::Data<RequestPool>,
+    path: web::Path<String>,
+    query: Query<QueryParams>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let token = path.into_inner();
+
+    let share_link = get_share_link_from_request(&token, &req, &pool).await;
+
+    if let Err(err) = share_link {
+        return error(err.get_code());
+    }
+
+    let share_link = share_link.unwrap();
+
+    if !share_link.allow_browsing {
+        return error("share_link.access_denied");
+    }
+
+    let folder_id = query.folder_id.unwrap_or(share_link.entry_id);
+
+    if !check_share_link_entry_access(&share_link, folder_id, &pool).await {
+        return error("share_link.access_denied");
+    }
+
+    let entries = sqlx::query_as::<_, SharedEntryRow>(
+        "SELECT id, name, parent_folder, extension, mime_type, size_bytes, created_at::TEXT, entry_type::TEXT, transcoded_version_available FROM storage_entries WHERE endpoint_id = $1 AND parent_folder = $2",
+    )
+    .bind(share_link.endpoint_id)
+    .bind(folder_id)
+    .fetch_all(&**pool)
+    .await;
+
+    match entries {
+        Ok(entries) => HttpResponse::Ok().json(web::Json(ShareLinkEntriesOutput { entries })),
+        Err(_) => error("share_link.internal"),
+    }
+}
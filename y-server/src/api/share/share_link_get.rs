@@ -0,0 +1,53 @@
+use actix_web::{get, web, Responder};
+use serde::Deserialize;
+
+use crate::request::error;
+use crate::storage_entry::serve_storage_file;
+use crate::storage_share_links::{
+    check_share_link_entry_access, get_share_link_from_request, register_share_link_download,
+};
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct ShareLinkGetQuery {
+    preview: Option<bool>,
+}
+
+/**
+ * Get a shared file, or a file inside of a shared folder (if the link allows browsing)
+ */
+#[get("/{token}/get/{file_id}")]
+async fn share_link_get(
+    pool: web::Data<RequestPool>,
+    path: web::Path<(String, i64)>,
+    query: web::Query<ShareLinkGetQuery>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (token, file_id) = path.into_inner();
+    let preview_requested = query.preview.unwrap_or(false);
+
+    let share_link = get_share_link_from_request(&token, &req, &pool).await;
+
+    if let Err(err) = share_link {
+        return error(err.get_code());
+    }
+
+    let share_link = share_link.unwrap();
+
+    if !check_share_link_entry_access(&share_link, file_id, &pool).await {
+        return error("share_link.access_denied");
+    }
+
+    if let Err(err) = register_share_link_download(&share_link, Some(file_id), &req, &pool).await {
+        return error(err.get_code());
+    }
+
+    serve_storage_file(
+        share_link.endpoint_id,
+        file_id,
+        preview_requested,
+        &req,
+        &pool,
+    )
+    .await
+}
@@ -1,25 +1,52 @@
 pub mod storage_access_rules_templates;
+pub mod storage_add_entry_tags;
+pub mod storage_cancel_archive;
+pub mod storage_copy_entries;
 pub mod storage_create_access_rules;
 pub mod storage_create_access_rules_template;
 pub mod storage_create_archive;
 pub mod storage_create_folder;
+pub mod storage_create_share_link;
+pub mod storage_create_upload_session;
 pub mod storage_create_user_pin;
 pub mod storage_delete_access_rules_template;
 pub mod storage_delete_entries;
+pub mod storage_delete_share_link;
+pub mod storage_delete_upload_session;
 pub mod storage_delete_user_pin;
 pub mod storage_download;
 pub mod storage_download_archive;
+pub mod storage_download_entry_version;
 pub mod storage_endpoints;
 pub mod storage_entries;
 pub mod storage_entry_add_access_rules_template;
+pub mod storage_entry_details;
 pub mod storage_entry_remove_access_rules_template;
 pub mod storage_entry_thumbnails;
+pub mod storage_entry_versions;
+pub mod storage_extract_archive;
 pub mod storage_get;
 pub mod storage_get_access_rules;
 pub mod storage_get_folder_path;
+pub mod storage_get_hls;
 pub mod storage_locations;
 pub mod storage_move_entries;
+pub mod storage_purge_trash_items;
+pub mod storage_quota;
+pub mod storage_remove_entry_properties;
+pub mod storage_remove_entry_tags;
 pub mod storage_rename_entry;
+pub mod storage_restore_entry_version;
+pub mod storage_restore_trash_items;
+pub mod storage_search;
+pub mod storage_set_entry_properties;
+pub mod storage_share_links;
+pub mod storage_stream_archive;
+pub mod storage_tags;
+pub mod storage_trash_items;
 pub mod storage_upload;
+pub mod storage_upload_session_append;
+pub mod storage_upload_session_offset;
+pub mod storage_upload_sessions_options;
 pub mod storage_user_archives;
 pub mod storage_user_pins;
@@ -0,0 +1,131 @@
+use std::sync::Mutex;
+
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
+use crate::request::error;
+use crate::storage_access::check_bulk_storage_entries_access_cascade_up;
+use crate::storage_tags::{add_entry_tags, normalize_tags};
+use crate::user::{get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+use crate::ws::{send_storage_location_updated, WSState};
+
+#[derive(Deserialize, Validate)]
+struct StorageAddEntryTagsInput {
+    endpoint_id: i32,
+
+    #[validate(length(min = 1, max = 1000))]
+    entry_ids: Vec<i64>,
+
+    #[validate(length(min = 1, max = 50))]
+    tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct StorageAddEntryTagsOutput {
+    added_tags: u64,
+}
+
+/**
+ * Put tags on entries. Tags are created on the endpoint when they are used for the first time.
+ */
+#[post("/entry-tags")]
+async fn storage_add_entry_tags(
+    pool: web::Data<RequestPool>,
+    ws_state: web::Data<Mutex<WSState>>,
+    form: web::Json<StorageAddEntryTagsInput>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+
+    if form.validate().is_err() {
+        return error("storage.invalid_input");
+    }
+
+    let tags = normalize_tags(&form.tags);
+
+    if tags.is_none() {
+        return error("storage.invalid_tag");
+    }
+
+    let tags = tags.unwrap();
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let client = client.unwrap();
+    let (client_user, _) = &client;
+    let user_groups = get_user_groups(&**pool, client_user.id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    let action_allowed = check_bulk_storage_entries_access_cascade_up(
+        form.endpoint_id,
+        &form.entry_ids,
+        "manage_tags",
+        client_user.id,
+        &group_ids,
+        &**pool,
+    )
+    .await;
+
+    if !action_allowed {
+        return error("storage.access_denied");
+    }
+
+    let add_result = add_entry_tags(
+        form.endpoint_id,
+        &form.entry_ids,
+        &tags,
+        Some(client_user.id),
+        &pool,
+    )
+    .await;
+
+    if add_result.is_err() {
+        return error("storage.internal");
+    }
+
+    write_audit_log(
+        &pool,
+        &req,
+        Some(AuditActor::from_client(&client)),
+        AuditEvent {
+            action: "storage.tags.add",
+            target_type: "storage_entry",
+            target_ids: form.entry_ids.iter().map(|id| id.to_string()).collect(),
+            endpoint_id: Some(form.endpoint_id),
+            after: Some(serde_json::json!({ "tags": tags })),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let parent_folders = sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT DISTINCT parent_folder FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)",
+    )
+    .bind(form.endpoint_id)
+    .bind(&form.entry_ids)
+    .fetch_all(&**pool)
+    .await;
+
+    if let Ok(parent_folders) = parent_folders {
+        send_storage_location_updated(
+            &ws_state,
+            Some(client_user.id),
+            form.endpoint_id,
+            parent_folders,
+            true,
+            false,
+        )
+        .await;
+    }
+
+    HttpResponse::Ok().json(web::Json(StorageAddEntryTagsOutput {
+        added_tags: add_result.unwrap(),
+    }))
+}
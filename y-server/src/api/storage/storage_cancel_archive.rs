@@ -0,0 +1,48 @@
+use std::fs::remove_file;
+
+use actix_web::{post, web, HttpResponse, Responder};
+
+use crate::request::error;
+use crate::storage_archives::get_archive_path;
+use crate::user::get_user_from_request;
+use crate::util::RequestPool;
+
+/**
+ * Cancel an archive that has not been written yet, or discard one that is done.
+ *
+ * A job that is writing the archive notices that it's gone and stops.
+ */
+#[post("/user-archives/{archive_id}/cancel")]
+async fn storage_cancel_archive(
+    pool: web::Data<RequestPool>,
+    path: web::Path<i32>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let archive_id = path.into_inner();
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let (client_user, _) = client.unwrap();
+
+    let filesystem_id = sqlx::query_scalar::<_, String>(
+        "DELETE FROM storage_archives WHERE id = $1 AND created_by = $2 RETURNING filesystem_id",
+    )
+    .bind(archive_id)
+    .bind(client_user.id)
+    .fetch_optional(&**pool)
+    .await;
+
+    match filesystem_id {
+        Ok(Some(filesystem_id)) => {
+            remove_file(get_archive_path(&filesystem_id)).unwrap_or(());
+
+            HttpResponse::Ok().body("{}")
+        }
+        Ok(None) => error("storage.archive_not_found"),
+        Err(_) => error("storage.internal"),
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::audit_log::{get_entries_audit_snapshot, write_audit_log, AuditActor, AuditEvent};
+use crate::config::get_config;
+use crate::jobs::{enqueue_job, JOB_STORAGE_COPY_ENTRIES};
+use crate::request::error;
+use crate::storage_access::{
+    check_bulk_storage_entries_access_cascade_up, check_endpoint_root_access,
+    check_storage_entry_access,
+};
+use crate::storage_copy::{
+    copy_entries, StorageCopyJobPayload, StorageCopyTarget, DEFAULT_COPY_BACKGROUND_THRESHOLD_BYTES,
+};
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::get_subfolders_level_with_access_rules;
+use crate::storage_quotas::{check_storage_quota, get_entries_total_size};
+use crate::user::{get_group_rights, get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+use crate::ws::{send_storage_location_updated, WSState};
+
+#[derive(Deserialize)]
+struct StorageCopyEntriesInput {
+    endpoint_id: i32,
+    entry_ids: Vec<i64>,
+
+    // Defaults to the source endpoint
+    target_endpoint_id: Option<i32>,
+    target_folder_id: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct StorageCopyEntriesOutput {
+    /**
+     * Ids of the copies. Empty if the copy runs in the background
+     */
+    new_entry_ids: Vec<i64>,
+
+    /**
+     * Set if the copy runs in the background. Progress is reported in `storage_copy_progress` WebSocket messages
+     */
+    copy_id: Option<String>,
+}
+
+#[post("/copy-entries")]
+async fn storage_copy_entries(
+    pool: web::Data<RequestPool>,
+    ws_state: web::Data<Mutex<WSState>>,
+    form: web::Json<StorageCopyEntriesInput>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+    let endpoint_id = form.endpoint_id;
+    let target_endpoint_id = form.target_endpoint_id.unwrap_or(endpoint_id);
+    let target_folder_id = form.target_folder_id;
+    let entry_ids = form.entry_ids;
+
+    if entry_ids.is_empty() {
+        return error("storage.invalid_input");
+    }
+
+    let source_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if source_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    let source_endpoint = source_endpoint.unwrap();
+
+    if source_endpoint.status == "disabled" {
+        return error("storage.endpoint_disabled");
+    }
+
+    let target_endpoint = get_storage_endpoint(target_endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    let target_endpoint = target_endpoint.unwrap();
+
+    if target_endpoint.status != "active" {
+        return error("storage.endpoint_not_active");
+    }
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let client = client.unwrap();
+    let (user, _) = &client;
+    let user_groups = get_user_groups(&**pool, user.id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    // Copying is essentially downloading and uploading somewhere else
+    let upload_allowed = if let Some(target_folder_id) = target_folder_id {
+        let target_folder_exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM storage_entries WHERE endpoint_id = $1 AND id = $2 AND entry_type = 'folder'::storage_entry_type)",
+        )
+        .bind(target_endpoint_id)
+        .bind(target_folder_id)
+        .fetch_one(&**pool)
+        .await;
+
+        match target_folder_exists {
+            Ok(true) => {}
+            Ok(false) => return error("storage.entry_not_found"),
+            Err(_) => return error("storage.internal"),
+        }
+
+        check_storage_entry_access(
+            target_endpoint_id,
+            target_folder_id,
+            "upload",
+            user.id,
+            &group_ids,
+            &**pool,
+        )
+        .await
+    } else {
+        let group_rights = get_group_rights(&pool, &group_ids).await;
+
+        check_endpoint_root_access(target_endpoint_id, group_rights)
+    };
+
+    if !upload_allowed {
+        return error("storage.access_denied");
+    }
+
+    let folder_ids = sqlx::query_as::<_, (i64, String)>(
+        "SELECT id, entry_type::TEXT FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)",
+    )
+    .bind(endpoint_id)
+    .bind(&entry_ids)
+    .fetch_all(&**pool)
+    .await;
+
+    let folder_ids = match folder_ids {
+        Ok(entries) if entries.len() == entry_ids.len() => entries
+            .into_iter()
+            .filter(|(_, entry_type)| entry_type == "folder")
+            .map(|(id, _)| id)
+            .collect::<Vec<i64>>(),
+        Ok(_) => return error("storage.entry_not_found"),
+        Err(_) => return error("storage.internal"),
+    };
+
+    // Traverse up (access check)
+    let download_allowed = check_bulk_storage_entries_access_cascade_up(
+        endpoint_id,
+        &entry_ids,
+        "download",
+        user.id,
+        &group_ids,
+        &**pool,
+    )
+    .await;
+
+    if !download_allowed {
+        return error("storage.access_denied");
+    }
+
+    // Traverse down (access check)
+    if !folder_ids.is_empty() {
+        let mut file_filesystem_ids: Vec<String> = Vec::new();
+        let mut folder_parents: HashMap<i64, Option<i64>> =
+            folder_ids.iter().map(|id| (*id, None)).collect();
+
+        let traverse_down_result = get_subfolders_level_with_access_rules(
+            endpoint_id,
+            &mut folder_parents,
+            &mut file_filesystem_ids,
+            folder_ids,
+            (user.id, &group_ids),
+            "download",
+            &**pool,
+        )
+        .await;
+
+        if traverse_down_result.is_err() {
+            return error("storage.access_denied");
+        }
+    }
+
+    // Copies count towards the quota of whoever makes them
+    let total_bytes = get_entries_total_size(endpoint_id, &entry_ids, &pool).await;
+
+    if total_bytes.is_err() {
+        return error("storage.internal");
+    }
+
+    let total_bytes = total_bytes.unwrap();
+
+    if let Err(err) = check_storage_quota(&target_endpoint, Some(user.id), total_bytes, &pool).await
+    {
+        return error(err.get_code());
+    }
+
+    let background_threshold_bytes = get_config(&pool)
+        .await
+        .get("storage.copy.background_threshold_bytes")
+        .and_then(|value| value.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_COPY_BACKGROUND_THRESHOLD_BYTES);
+
+    let audit_event = |new_entry_ids: &Vec<i64>, copy_id: Option<&String>, before| AuditEvent {
+        action: "storage.copy",
+        target_type: "storage_entry",
+        target_ids: entry_ids.iter().map(|id| id.to_string()).collect(),
+        endpoint_id: Some(endpoint_id),
+        before,
+        after: Some(json!({
+            "endpoint_id": target_endpoint_id,
+            "parent_folder": target_folder_id,
+            "new_entry_ids": new_entry_ids,
+            "copy_id": copy_id,
+        })),
+    };
+
+    let audit_before = get_entries_audit_snapshot(endpoint_id, &entry_ids, &pool).await;
+
+    // Large copies run in the background
+    if total_bytes > background_threshold_bytes {
+        let copy_id = uuid::Uuid::new_v4().to_string();
+
+        let enqueue_result = enqueue_job(
+            JOB_STORAGE_COPY_ENTRIES,
+            &StorageCopyJobPayload {
+                copy_id: copy_id.clone(),
+                user_id: user.id,
+                source_endpoint_id: endpoint_id,
+                entry_ids: entry_ids.clone(),
+                target_endpoint_id,
+                target_folder: target_folder_id,
+                total_bytes,
+            },
+            Some(user.id),
+            &pool,
+        )
+        .await;
+
+        return match enqueue_result {
+            Ok(_) => {
+                write_audit_log(
+                    &pool,
+                    &req,
+                    Some(AuditActor::from_client(&client)),
+                    audit_event(&Vec::new(), Some(&copy_id), audit_before),
+                )
+                .await;
+
+                HttpResponse::Ok().json(web::Json(StorageCopyEntriesOutput {
+                    new_entry_ids: Vec::new(),
+                    copy_id: Some(copy_id),
+                }))
+            }
+            Err(err) => error(err.get_code()),
+        };
+    }
+
+    let target = StorageCopyTarget {
+        endpoint: &target_endpoint,
+        folder: target_folder_id,
+        user_id: Some(user.id),
+        name: None,
+        recursive: true,
+    };
+
+    let copy_result = copy_entries(&source_endpoint, &entry_ids, &target, None, &pool).await;
+
+    match copy_result {
+        Ok(new_entry_ids) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                audit_event(&new_entry_ids, None, audit_before),
+            )
+            .await;
+
+            // TODO don't block the request here
+            send_storage_location_updated(
+                &ws_state,
+                Some(user.id),
+                target_endpoint_id,
+                vec![target_folder_id],
+                true,
+                false,
+            )
+            .await;
+
+            HttpResponse::Ok().json(web::Json(StorageCopyEntriesOutput {
+                new_entry_ids,
+                copy_id: None,
+            }))
+        }
+        Err(err) => error(err.get_code()),
+    }
+}
@@ -4,6 +4,7 @@ use sqlx::QueryBuilder;
 use validator::Validate;
 
 use crate::{
+    audit_log::{write_audit_log, AuditActor, AuditEvent},
     request::error,
     storage_access::check_storage_entry_access,
     user::{get_client_rights, get_user_from_request, get_user_groups},
@@ -23,6 +24,26 @@ struct StorageCreateStorageAccessRulesInput {
     rules: Vec<StorageAccessRule>,
 }
 
+/**
+ * Access rules of an entry, as they are recorded in the audit log
+ */
+async fn get_access_rules_snapshot(
+    endpoint_id: i32,
+    entry_id: i64,
+    pool: &RequestPool,
+) -> Option<serde_json::Value> {
+    sqlx::query_scalar::<_, serde_json::Value>(
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+            'access_type', access_type, 'action', action, 'executor_type', executor_type, 'executor_id', executor_id
+        ) ORDER BY id), '[]'::jsonb) FROM storage_access WHERE endpoint_id = $1 AND entry_id = $2",
+    )
+    .bind(endpoint_id)
+    .bind(entry_id)
+    .fetch_one(pool)
+    .await
+    .ok()
+}
+
 #[post("/access-rules/{endpoint_id}/{entry_id}")]
 async fn storage_create_access_rules(
     pool: web::Data<RequestPool>,
@@ -51,7 +72,7 @@ async fn storage_create_access_rules(
 
     let client = get_user_from_request(&**pool, &req).await;
 
-    let action_allowed = if let Some((client_user, _)) = client {
+    let action_allowed = if let Some((client_user, _)) = &client {
         let user_groups = get_user_groups(&**pool, client_user.id).await;
         let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
 
@@ -72,6 +93,8 @@ async fn storage_create_access_rules(
         return error("storage.access_denied");
     }
 
+    let audit_before = get_access_rules_snapshot(endpoint_id, entry_id, &pool).await;
+
     let mut transaction = pool.begin().await.unwrap();
 
     let delete_result =
@@ -117,7 +140,24 @@ async fn storage_create_access_rules(
     let transaction_result = transaction.commit().await;
 
     match transaction_result {
-        Ok(_) => HttpResponse::Ok().body("{}"),
+        Ok(_) => {
+            write_audit_log(
+                &pool,
+                &req,
+                client.as_ref().map(AuditActor::from_client),
+                AuditEvent {
+                    action: "storage.access_rules.update",
+                    target_type: "storage_entry",
+                    target_ids: vec![entry_id.to_string()],
+                    endpoint_id: Some(endpoint_id),
+                    before: audit_before,
+                    after: get_access_rules_snapshot(endpoint_id, entry_id, &pool).await,
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().body("{}")
+        }
         Err(_) => error("storage.internal"),
     }
 }
@@ -4,6 +4,7 @@ use sqlx::QueryBuilder;
 use validator::Validate;
 
 use crate::{
+    audit_log::{write_client_audit_log, AuditEvent},
     request::error,
     storage_access::check_storage_entry_access,
     user::{get_client_rights, get_user_from_request, get_user_groups},
@@ -47,6 +48,18 @@ async fn storage_create_access_rules_template(
 
     let rules = form.rules;
 
+    let audit_rules = rules
+        .iter()
+        .map(|rule| {
+            serde_json::json!({
+                "access_type": rule.access_type,
+                "action": rule.action,
+                "executor_type": rule.executor_type,
+                "executor_id": rule.executor_id,
+            })
+        })
+        .collect::<Vec<serde_json::Value>>();
+
     let client_rights = get_client_rights(&pool, &req).await;
 
     let manage_templates_allowed = client_rights
@@ -95,7 +108,7 @@ async fn storage_create_access_rules_template(
     let create_template_result: Result<i32, _> = sqlx::query_scalar(
         "INSERT INTO public.storage_access_templates(name) VALUES ($1) RETURNING id",
     )
-    .bind(form.name)
+    .bind(&form.name)
     .fetch_one(&mut *transaction)
     .await;
 
@@ -149,7 +162,27 @@ async fn storage_create_access_rules_template(
     let transaction_result = transaction.commit().await;
 
     match transaction_result {
-        Ok(_) => HttpResponse::Ok().body("{}"),
+        Ok(_) => {
+            write_client_audit_log(
+                &pool,
+                &req,
+                AuditEvent {
+                    action: "storage.access_rules_template.create",
+                    target_type: "storage_access_template",
+                    target_ids: vec![new_template_id.to_string()],
+                    endpoint_id: form.initial_entry_endpoint_id,
+                    after: Some(serde_json::json!({
+                        "name": form.name,
+                        "rules": audit_rules,
+                        "initial_entry_id": form.initial_entry_id,
+                    })),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().body("{}")
+        }
         Err(_) => error("storage.internal"),
     }
 }
@@ -3,9 +3,11 @@ use serde::{Deserialize, Serialize};
 use validator::Validate;
 
 use crate::{
+    audit_log::{get_entries_audit_snapshot, write_audit_log, AuditActor, AuditEvent},
     request::error,
     storage_access::{check_endpoint_root_access, check_storage_entry_access},
     storage_endpoint::get_storage_endpoint,
+    storage_layout::sync_endpoint_file_structure,
     user::{get_group_rights, get_user_from_request, get_user_groups},
     util::RequestPool,
 };
@@ -54,7 +56,8 @@ async fn storage_create_folder(
         return error("storage.endpoint_not_active");
     }
 
-    let (client_user, _) = client.unwrap();
+    let client = client.unwrap();
+    let (client_user, _) = &client;
 
     let user_groups = get_user_groups(&**pool, client_user.id).await;
     let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
@@ -105,6 +108,28 @@ async fn storage_create_folder(
 
     match new_folder_id {
         Ok(new_folder_id) => {
+            sync_endpoint_file_structure(form.endpoint_id, &vec![new_folder_id], &pool).await;
+
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "storage.create_folder",
+                    target_type: "storage_entry",
+                    target_ids: vec![new_folder_id.to_string()],
+                    endpoint_id: Some(form.endpoint_id),
+                    after: get_entries_audit_snapshot(
+                        form.endpoint_id,
+                        &vec![new_folder_id],
+                        &pool,
+                    )
+                    .await,
+                    ..Default::default()
+                },
+            )
+            .await;
+
             HttpResponse::Ok().json(web::Json(StorageCreateFolderOutput { new_folder_id }))
         }
         Err(_) => error("storage.internal"),
@@ -0,0 +1,171 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
+use crate::request::error;
+use crate::storage_access::check_storage_entry_access;
+use crate::storage_share_links::{generate_share_link_token, hash_share_link_password};
+use crate::user::{get_group_rights, get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+
+#[derive(Deserialize, Validate)]
+struct StorageCreateShareLinkInput {
+    endpoint_id: i32,
+    entry_id: i64,
+
+    /**
+     * Anyone who has the link will also need this password. `null` == not protected.
+     */
+    #[validate(length(min = 1, max = 255))]
+    password: Option<String>,
+
+    /**
+     * RFC 3339 timestamp. `null` == the link never expires.
+     */
+    expires_at: Option<String>,
+
+    /**
+     * How many times the link can be used to download something. `null` == unlimited.
+     */
+    #[validate(range(min = 1))]
+    max_downloads: Option<i32>,
+
+    /**
+     * Allow browsing the contents of a shared folder (read only), not just downloading it as an archive.
+     */
+    allow_browsing: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct StorageCreateShareLinkOutput {
+    id: i32,
+    token: String,
+}
+
+#[post("/share-links")]
+async fn storage_create_share_link(
+    pool: web::Data<RequestPool>,
+    form: web::Json<StorageCreateShareLinkInput>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+
+    if form.validate().is_err() {
+        return error("storage.invalid_input");
+    }
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let client = client.unwrap();
+    let (client_user, _) = &client;
+    let user_groups = get_user_groups(&**pool, client_user.id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    let group_rights = get_group_rights(&pool, &group_ids).await;
+
+    let action_allowed = group_rights
+        .iter()
+        .find(|right| right.right_name.eq("storage_create_share_links"))
+        .is_some();
+
+    if !action_allowed {
+        return error("storage.access_denied");
+    }
+
+    // Users can only share what they can download themselves
+    if !check_storage_entry_access(
+        form.endpoint_id,
+        form.entry_id,
+        "download",
+        client_user.id,
+        &group_ids,
+        &**pool,
+    )
+    .await
+    {
+        return error("storage.access_denied");
+    }
+
+    let entry_type = sqlx::query_scalar::<_, String>(
+        "SELECT entry_type::TEXT FROM storage_entries WHERE endpoint_id = $1 AND id = $2",
+    )
+    .bind(form.endpoint_id)
+    .bind(form.entry_id)
+    .fetch_optional(&**pool)
+    .await;
+
+    let entry_type = match entry_type {
+        Ok(Some(entry_type)) => entry_type,
+        Ok(None) => return error("storage.entry_not_found"),
+        Err(_) => return error("storage.internal"),
+    };
+
+    let expires_at = match &form.expires_at {
+        Some(expires_at) => match DateTime::parse_from_rfc3339(expires_at) {
+            Ok(expires_at) => Some(expires_at.with_timezone(&Utc)),
+            Err(_) => return error("storage.invalid_input"),
+        },
+        None => None,
+    };
+
+    let password_hash = match &form.password {
+        Some(password) => match hash_share_link_password(password) {
+            Ok(password_hash) => Some(password_hash),
+            Err(err) => return error(err.get_code()),
+        },
+        None => None,
+    };
+
+    // There is nothing to browse inside of a file
+    let allow_browsing = entry_type == "folder" && form.allow_browsing.unwrap_or(false);
+
+    let token = generate_share_link_token();
+
+    let share_link_id = sqlx::query_scalar::<_, i32>(
+        "INSERT INTO storage_share_links (endpoint_id, entry_id, token, password, expires_at, max_downloads, allow_browsing, created_by) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+    )
+    .bind(form.endpoint_id)
+    .bind(form.entry_id)
+    .bind(&token)
+    .bind(password_hash)
+    .bind(expires_at)
+    .bind(form.max_downloads)
+    .bind(allow_browsing)
+    .bind(client_user.id)
+    .fetch_one(&**pool)
+    .await;
+
+    match share_link_id {
+        Ok(id) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "storage.share_link.create",
+                    target_type: "storage_share_link",
+                    target_ids: vec![id.to_string()],
+                    endpoint_id: Some(form.endpoint_id),
+                    after: Some(serde_json::json!({
+                        "entry_id": form.entry_id,
+                        "password_protected": form.password.is_some(),
+                        "expires_at": expires_at.map(|expires_at| expires_at.to_rfc3339()),
+                        "max_downloads": form.max_downloads,
+                        "allow_browsing": allow_browsing,
+                    })),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().json(web::Json(StorageCreateShareLinkOutput { id, token }))
+        }
+        Err(_) => error("storage.internal"),
+    }
+}
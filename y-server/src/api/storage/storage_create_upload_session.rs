@@ -0,0 +1,174 @@
+use actix_web::http::StatusCode;
+use actix_web::{
+    post,
+    web::{self, Query},
+    Responder,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::request::error;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_quotas::check_storage_quota;
+use crate::storage_uploads::{
+    check_upload_access, get_upload_session_staging_path, parse_tus_metadata, tus_response,
+    TUS_VERSION, UPLOAD_SESSION_LIFETIME_HOURS,
+};
+use crate::user::get_user_from_request;
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct QueryParams {
+    endpoint_id: i32,
+
+    /**
+     * The folder where the file will reside. `null` == root.
+     */
+    target_folder: Option<i64>,
+}
+
+/**
+ * Create a new resumable upload session (tus.io "creation" extension).
+ *
+ * The file name is taken from the `filename` key of the `Upload-Metadata` header.
+ */
+#[post("/uploads")]
+async fn storage_create_upload_session(
+    pool: web::Data<RequestPool>,
+    query: Query<QueryParams>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let endpoint_id = query.endpoint_id;
+    let target_folder = query.target_folder;
+
+    let tus_resumable = req
+        .headers()
+        .get("Tus-Resumable")
+        .and_then(|value| value.to_str().ok());
+
+    if tus_resumable != Some(TUS_VERSION) {
+        return tus_response(StatusCode::PRECONDITION_FAILED)
+            .insert_header(("Tus-Version", TUS_VERSION))
+            .finish();
+    }
+
+    let client = get_user_from_request(&pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let (client_user, _) = client.unwrap();
+
+    let upload_length = req
+        .headers()
+        .get("Upload-Length")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<i64>().ok());
+
+    if upload_length.is_none() || upload_length.unwrap() < 0 {
+        return error("storage.invalid_input");
+    }
+
+    let upload_length = upload_length.unwrap();
+
+    let metadata = parse_tus_metadata(
+        req.headers()
+            .get("Upload-Metadata")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or(""),
+    );
+
+    let full_filename = metadata.get("filename").cloned().unwrap_or_default();
+
+    if full_filename.is_empty() {
+        return error("storage.upload.no_filename");
+    }
+
+    if full_filename.len() > 256 || full_filename.contains('/') {
+        return error("storage.invalid_input");
+    }
+
+    let name_separator = full_filename.rfind('.').unwrap_or(full_filename.len());
+
+    let (file_name, file_extension) = full_filename.split_at(name_separator);
+
+    let file_extension = if file_extension.is_empty() {
+        None
+    } else {
+        Some(file_extension.trim_start_matches('.'))
+    };
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    let target_endpoint = target_endpoint.unwrap();
+
+    if target_endpoint.status != "active" {
+        return error("storage.endpoint_not_active");
+    }
+
+    if !check_upload_access(endpoint_id, target_folder, client_user.id, &pool).await {
+        return error("storage.access_denied");
+    }
+
+    // The size of the file is known upfront, so it's checked against the quota right away
+    if let Err(err) =
+        check_storage_quota(&target_endpoint, Some(client_user.id), upload_length, &pool).await
+    {
+        return error(err.get_code());
+    }
+
+    // Don't let the user upload the whole file only to find out that it can not be saved
+    let name_taken = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM storage_entries WHERE endpoint_id = $1 AND parent_folder IS NOT DISTINCT FROM $2 AND entry_type = 'file'::storage_entry_type AND name = $3 AND extension IS NOT DISTINCT FROM $4)",
+    )
+    .bind(endpoint_id)
+    .bind(target_folder)
+    .bind(file_name)
+    .bind(file_extension)
+    .fetch_one(&**pool)
+    .await;
+
+    match name_taken {
+        Ok(false) => {}
+        Ok(true) => return error("storage.name_conflict"),
+        Err(_) => return error("storage.internal"),
+    }
+
+    let session_id = Uuid::new_v4();
+
+    if std::fs::File::create(get_upload_session_staging_path(&session_id)).is_err() {
+        return error("storage.internal");
+    }
+
+    let expires_on = chrono::Utc::now() + chrono::Duration::hours(UPLOAD_SESSION_LIFETIME_HOURS);
+
+    let create_session_result = sqlx::query(
+        "INSERT INTO storage_upload_sessions (id, endpoint_id, target_folder, name, extension, size_bytes, created_by, expires_on) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+    )
+    .bind(session_id)
+    .bind(endpoint_id)
+    .bind(target_folder)
+    .bind(file_name)
+    .bind(file_extension)
+    .bind(upload_length)
+    .bind(client_user.id)
+    .bind(expires_on)
+    .execute(&**pool)
+    .await;
+
+    if create_session_result.is_err() {
+        std::fs::remove_file(get_upload_session_staging_path(&session_id)).unwrap_or(());
+
+        return error("storage.internal");
+    }
+
+    tus_response(StatusCode::CREATED)
+        .insert_header(("Location", format!("/api/storage/uploads/{}", session_id)))
+        .insert_header(("Upload-Expires", expires_on.to_rfc2822()))
+        .finish()
+}
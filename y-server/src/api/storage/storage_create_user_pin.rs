@@ -1,3 +1,4 @@
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
 use crate::util::RequestPool;
 use crate::{request::error, user::get_user_from_request};
 use actix_web::{post, web, HttpResponse, Responder};
@@ -32,18 +33,40 @@ async fn storage_create_user_pin(
 
     let user = get_user_from_request(&**pool, &req).await;
 
-    if let Some((user, _)) = user {
-        let create_pin_result = sqlx::query_scalar("INSERT INTO storage_user_pins (user_id, endpoint_id, entry_id, name) VALUES ($1, $2, $3, $4) RETURNING pin_id")
+    if let Some(client) = user {
+        let (user, _) = &client;
+
+        let create_pin_result = sqlx::query_scalar::<_, i32>("INSERT INTO storage_user_pins (user_id, endpoint_id, entry_id, name) VALUES ($1, $2, $3, $4) RETURNING pin_id")
             .bind(user.id)
             .bind(form.endpoint_id)
             .bind(form.entry_id)
-            .bind(form.name)
+            .bind(&form.name)
             .fetch_one(&**pool)
             .await;
 
         return match create_pin_result {
-            Ok(new_pin_id) => HttpResponse::Ok()
-                .json(web::Json(CreateStorageUserPinOutput { pin_id: new_pin_id })),
+            Ok(new_pin_id) => {
+                write_audit_log(
+                    &pool,
+                    &req,
+                    Some(AuditActor::from_client(&client)),
+                    AuditEvent {
+                        action: "storage.pin.create",
+                        target_type: "storage_user_pin",
+                        target_ids: vec![new_pin_id.to_string()],
+                        endpoint_id: Some(form.endpoint_id),
+                        after: Some(serde_json::json!({
+                            "name": form.name,
+                            "entry_id": form.entry_id,
+                        })),
+                        ..Default::default()
+                    },
+                )
+                .await;
+
+                HttpResponse::Ok()
+                    .json(web::Json(CreateStorageUserPinOutput { pin_id: new_pin_id }))
+            }
             Err(_) => error("storage.internal"),
         };
     } else {
@@ -1,3 +1,4 @@
+use crate::audit_log::{write_client_audit_log, AuditEvent};
 use crate::request::error;
 use crate::user::get_client_rights;
 use crate::util::RequestPool;
@@ -35,15 +36,31 @@ async fn storage_delete_access_rules_template(
 
     let template_ids = form.into_inner().template_ids;
 
-    let delete_templates_result =
-        sqlx::query("DELETE FROM storage_access_templates WHERE id = ANY($1)")
-            .bind(template_ids)
-            .execute(&**pool)
-            .await;
+    let delete_templates_result = sqlx::query_as::<_, (i32, String)>(
+        "DELETE FROM storage_access_templates WHERE id = ANY($1) RETURNING id, name",
+    )
+    .bind(template_ids)
+    .fetch_all(&**pool)
+    .await;
 
     if delete_templates_result.is_err() {
         return error("storage.internal");
-    } else {
-        return HttpResponse::Ok().body("{}");
     }
+
+    for (template_id, template_name) in delete_templates_result.unwrap() {
+        write_client_audit_log(
+            &pool,
+            &req,
+            AuditEvent {
+                action: "storage.access_rules_template.delete",
+                target_type: "storage_access_template",
+                target_ids: vec![template_id.to_string()],
+                before: Some(serde_json::json!({ "name": template_name })),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    HttpResponse::Ok().body("{}")
 }
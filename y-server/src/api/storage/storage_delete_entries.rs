@@ -2,8 +2,10 @@ use std::sync::Mutex;
 
 use actix_web::{delete, web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 use crate::{
+    audit_log::{get_entries_audit_snapshot, write_audit_log, AuditActor, AuditEvent},
     request::error,
     storage_access::check_bulk_storage_entries_access_cascade_up,
     storage_endpoint::get_storage_endpoint,
@@ -86,6 +88,8 @@ async fn storage_delete_entries(
         .fetch_all(&**pool)
         .await;
 
+        let audit_before = get_entries_audit_snapshot(endpoint_id, &all_entries_ids, &pool).await;
+
         // TODO make sure that folderids are actually folders and fileids are actually files
         let delete_result = delete_entries(
             endpoint_id,
@@ -98,6 +102,24 @@ async fn storage_delete_entries(
 
         match delete_result {
             Ok((deleted_files, deleted_folders)) => {
+                write_audit_log(
+                    &pool,
+                    &req,
+                    client.as_ref().map(AuditActor::from_client),
+                    AuditEvent {
+                        action: "storage.delete",
+                        target_type: "storage_entry",
+                        target_ids: all_entries_ids.iter().map(|id| id.to_string()).collect(),
+                        endpoint_id: Some(endpoint_id),
+                        before: audit_before,
+                        after: Some(json!({
+                            "deleted_files": deleted_files,
+                            "deleted_folders": deleted_folders,
+                        })),
+                    },
+                )
+                .await;
+
                 if let Ok(folders_to_update) = source_parent_folders {
                     // TODO don't block the request here
                     ws_state
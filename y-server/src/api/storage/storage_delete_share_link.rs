@@ -0,0 +1,58 @@
+use actix_web::{delete, web, HttpResponse, Responder};
+
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
+use crate::request::error;
+use crate::user::get_user_from_request;
+use crate::util::RequestPool;
+
+/**
+ * Revoke a share link created by the client
+ */
+#[delete("/share-links/{link_id}")]
+async fn storage_delete_share_link(
+    pool: web::Data<RequestPool>,
+    path: web::Path<i32>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let link_id = path.into_inner();
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let client = client.unwrap();
+    let (client_user, _) = &client;
+
+    let result = sqlx::query_as::<_, (i32, i64)>(
+        "DELETE FROM storage_share_links WHERE id = $1 AND created_by = $2 RETURNING endpoint_id, entry_id",
+    )
+    .bind(link_id)
+    .bind(client_user.id)
+    .fetch_optional(&**pool)
+    .await;
+
+    match result {
+        Ok(Some((endpoint_id, entry_id))) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "storage.share_link.delete",
+                    target_type: "storage_share_link",
+                    target_ids: vec![link_id.to_string()],
+                    endpoint_id: Some(endpoint_id),
+                    before: Some(serde_json::json!({ "entry_id": entry_id })),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().body("{}")
+        }
+        Ok(None) => error("share_link.not_found"),
+        Err(_) => error("storage.internal"),
+    }
+}
@@ -0,0 +1,42 @@
+use actix_web::http::StatusCode;
+use actix_web::{delete, web, Responder};
+use uuid::Uuid;
+
+use crate::storage_uploads::{delete_upload_session, get_upload_session, tus_response};
+use crate::user::get_user_from_request;
+use crate::util::RequestPool;
+
+/// Cancel an upload (tus.io "termination" extension)
+#[delete("/uploads/{session_id}")]
+async fn storage_delete_upload_session(
+    pool: web::Data<RequestPool>,
+    path: web::Path<String>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let session_id = Uuid::parse_str(path.into_inner().as_str());
+
+    if session_id.is_err() {
+        return tus_response(StatusCode::NOT_FOUND).finish();
+    }
+
+    let session_id = session_id.unwrap();
+
+    let client = get_user_from_request(&pool, &req).await;
+
+    if client.is_none() {
+        return tus_response(StatusCode::FORBIDDEN).finish();
+    }
+
+    let (client_user, _) = client.unwrap();
+
+    if get_upload_session(&session_id, client_user.id, &pool)
+        .await
+        .is_err()
+    {
+        return tus_response(StatusCode::NOT_FOUND).finish();
+    }
+
+    delete_upload_session(&session_id, &pool).await;
+
+    tus_response(StatusCode::NO_CONTENT).finish()
+}
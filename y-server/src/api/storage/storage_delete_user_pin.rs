@@ -1,3 +1,4 @@
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
 use crate::util::RequestPool;
 use crate::{request::error, user::get_user_from_request};
 use actix_web::{delete, web, HttpResponse, Responder};
@@ -10,24 +11,46 @@ async fn storage_delete_user_pin(
 ) -> impl Responder {
     let user = get_user_from_request(&**pool, &req).await;
 
-    if let Some((user, _)) = user {
+    if let Some(client) = user {
+        let (user, _) = &client;
+
         let pin_id = path.into_inner();
 
-        let delete_pin_result =
-            sqlx::query("DELETE FROM storage_user_pins WHERE pin_id = $1 AND user_id = $2")
-                .bind(pin_id)
-                .bind(user.id)
-                .execute(&**pool)
-                .await;
+        let delete_pin_result = sqlx::query_as::<_, (i32, i64, String)>(
+            "DELETE FROM storage_user_pins WHERE pin_id = $1 AND user_id = $2 RETURNING endpoint_id, entry_id, name",
+        )
+        .bind(pin_id)
+        .bind(user.id)
+        .fetch_optional(&**pool)
+        .await;
 
         if delete_pin_result.is_err() {
             return error("storage.internal");
         }
 
-        if delete_pin_result.unwrap().rows_affected() == 0 {
+        let deleted_pin = delete_pin_result.unwrap();
+
+        if deleted_pin.is_none() {
             return error("delete_storage_user_pin.not_found");
         }
 
+        let (endpoint_id, entry_id, name) = deleted_pin.unwrap();
+
+        write_audit_log(
+            &pool,
+            &req,
+            Some(AuditActor::from_client(&client)),
+            AuditEvent {
+                action: "storage.pin.delete",
+                target_type: "storage_user_pin",
+                target_ids: vec![pin_id.to_string()],
+                endpoint_id: Some(endpoint_id),
+                before: Some(serde_json::json!({ "name": name, "entry_id": entry_id })),
+                ..Default::default()
+            },
+        )
+        .await;
+
         HttpResponse::Ok().body("{}")
     } else {
         error("storage.access_denied")
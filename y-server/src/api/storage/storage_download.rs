@@ -1,6 +1,4 @@
-use std::path::Path;
-
-use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
 use actix_web::web::Query;
 use actix_web::{get, web, Responder};
 use serde::{Deserialize, Serialize};
@@ -9,6 +7,8 @@ use sqlx::prelude::FromRow;
 use crate::request::error;
 
 use crate::storage_access::check_storage_entry_access;
+use crate::storage_backend::get_storage_backend;
+use crate::storage_endpoint::get_storage_endpoint;
 use crate::user::{get_user_from_request, get_user_groups};
 use crate::util::RequestPool;
 
@@ -17,7 +17,6 @@ struct StorageEntryAndBasePathRow {
     name: String,
     extension: Option<String>,
     filesystem_id: String,
-    base_path: String,
 }
 
 #[derive(Deserialize)]
@@ -63,7 +62,7 @@ async fn storage_download(
     }
 
     let entry = sqlx::query_as::<_, StorageEntryAndBasePathRow>(
-        "SELECT storage_entries.name, storage_entries.extension, storage_entries.filesystem_id, storage_endpoints.base_path FROM storage_entries
+        "SELECT storage_entries.name, storage_entries.extension, storage_entries.filesystem_id FROM storage_entries
         RIGHT OUTER JOIN storage_endpoints ON storage_entries.endpoint_id = storage_endpoints.id
         WHERE storage_entries.id = $1 AND endpoint_id = $2",
     )
@@ -84,9 +83,28 @@ async fn storage_download(
             .await
             .unwrap();
 
-            let file_path = Path::new(&entry.base_path).join(&entry.filesystem_id);
+            let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+            if target_endpoint.is_err() {
+                return error("storage.endpoint_not_found");
+            }
+
+            let storage_backend = get_storage_backend(&target_endpoint.unwrap(), &pool).await;
+
+            if storage_backend.is_err() {
+                return error("storage.endpoint_not_found");
+            }
 
-            let mut file = actix_files::NamedFile::open(file_path).unwrap();
+            let res = storage_backend
+                .unwrap()
+                .serve(&entry.filesystem_id, &req)
+                .await;
+
+            if res.is_err() {
+                return error("storage.internal");
+            }
+
+            let mut res = res.unwrap();
 
             let raw_filename = format!(
                 "{}.{}",
@@ -94,12 +112,18 @@ async fn storage_download(
                 entry.extension.unwrap_or("".to_string())
             );
 
-            file = file.set_content_disposition(ContentDisposition {
-                disposition: DispositionType::Attachment,
-                parameters: vec![DispositionParam::Filename(raw_filename)],
-            });
+            res.headers_mut().insert(
+                header::CONTENT_DISPOSITION,
+                ContentDisposition {
+                    disposition: DispositionType::Attachment,
+                    parameters: vec![DispositionParam::Filename(raw_filename)],
+                }
+                .to_string()
+                .parse()
+                .unwrap(),
+            );
 
-            return file.into_response(&req);
+            return res;
         }
 
         Err(_) => error("storage.internal"),
@@ -1,11 +1,10 @@
-use std::path::Path;
-
 use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
 use actix_web::{get, web, Responder};
 use serde::Serialize;
 use sqlx::prelude::FromRow;
 
 use crate::request::error;
+use crate::storage_archives::get_archive_path;
 
 use crate::user::get_user_from_request;
 use crate::util::RequestPool;
@@ -16,7 +15,7 @@ struct StorageUserArchiveRow {
     endpoint_id: i32,
     filesystem_id: String,
 
-    ready: bool,
+    status: String,
 }
 
 #[get("/user-archives/{archive_id}/download")]
@@ -36,27 +35,33 @@ async fn storage_download_archive(
     let (client_user, _) = client.unwrap();
 
     let archive = sqlx::query_as::<_, StorageUserArchiveRow>(
-        "SELECT id, filesystem_id, endpoint_id, ready FROM storage_archives WHERE id = $1 AND created_by = $2",
+        "SELECT id, filesystem_id, endpoint_id, status::TEXT FROM storage_archives WHERE id = $1 AND created_by = $2",
     )
     .bind(archive_id)
     .bind(client_user.id)
-    .fetch_one(&**pool)
+    .fetch_optional(&**pool)
     .await;
 
     match archive {
-        Ok(archive) => {
-            let file_path = Path::new("upload_staging").join(&archive.filesystem_id);
+        Ok(Some(archive)) => {
+            if archive.status != "ready" {
+                return error("storage.archive_not_ready");
+            }
+
+            let file = actix_files::NamedFile::open(get_archive_path(&archive.filesystem_id));
 
-            let mut file = actix_files::NamedFile::open(file_path).unwrap();
+            if file.is_err() {
+                return error("storage.internal");
+            }
 
-            file = file.set_content_disposition(ContentDisposition {
+            let file = file.unwrap().set_content_disposition(ContentDisposition {
                 disposition: DispositionType::Attachment,
                 parameters: vec![DispositionParam::Filename("download.zip".to_string())],
             });
 
             return file.into_response(&req);
         }
-
+        Ok(None) => error("storage.archive_not_found"),
         Err(_) => error("storage.internal"),
     }
 }
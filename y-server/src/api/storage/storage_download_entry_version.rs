@@ -0,0 +1,116 @@
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{get, web, Responder};
+use sqlx::prelude::FromRow;
+
+use crate::request::error;
+use crate::storage_access::check_storage_entry_access;
+use crate::storage_backend::get_storage_backend;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_versions::get_entry_version_blob;
+use crate::user::{get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+
+#[derive(FromRow)]
+struct StorageEntryNameRow {
+    name: String,
+    extension: Option<String>,
+}
+
+/**
+ * Download a previous version of a file.
+ */
+#[get("/entries/{endpoint_id}/versions/{file_id}/{version_id}/download")]
+async fn storage_download_entry_version(
+    pool: web::Data<RequestPool>,
+    path: web::Path<(i32, i64, i64)>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, file_id, version_id) = path.into_inner();
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    let action_allowed = if let Some((client_user, _)) = client {
+        let user_groups = get_user_groups(&**pool, client_user.id).await;
+        let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+        check_storage_entry_access(
+            endpoint_id,
+            file_id,
+            "download",
+            client_user.id,
+            &group_ids,
+            &**pool,
+        )
+        .await
+    } else {
+        false
+    };
+
+    if !action_allowed {
+        return error("storage.access_denied");
+    }
+
+    let entry = sqlx::query_as::<_, StorageEntryNameRow>(
+        "SELECT name, extension FROM storage_entries WHERE endpoint_id = $1 AND id = $2",
+    )
+    .bind(endpoint_id)
+    .bind(file_id)
+    .fetch_one(&**pool)
+    .await;
+
+    if entry.is_err() {
+        return error("storage.internal");
+    }
+
+    let entry = entry.unwrap();
+
+    let version = get_entry_version_blob(endpoint_id, file_id, version_id, &pool).await;
+
+    if version.is_err() {
+        return error("storage.version_not_found");
+    }
+
+    let version = version.unwrap();
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    let storage_backend = get_storage_backend(&target_endpoint.unwrap(), &pool).await;
+
+    if storage_backend.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    let res = storage_backend
+        .unwrap()
+        .serve(&version.filesystem_id, &req)
+        .await;
+
+    if res.is_err() {
+        return error("storage.internal");
+    }
+
+    let mut res = res.unwrap();
+
+    let raw_filename = format!(
+        "{}.{}",
+        entry.name,
+        entry.extension.unwrap_or("".to_string())
+    );
+
+    res.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(raw_filename)],
+        }
+        .to_string()
+        .parse()
+        .unwrap(),
+    );
+
+    res
+}
@@ -1,11 +1,15 @@
 use actix_web::web::Query;
 use actix_web::{get, web, HttpResponse, Responder};
+use chrono::{NaiveDate, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
-use crate::request::error;
+use crate::request::{error, escape_like_pattern};
 
 use crate::storage_access::{check_endpoint_root_access, check_storage_entry_access};
+use crate::storage_entry::StorageError;
+use crate::storage_metadata::{StorageMediaMetadata, MEDIA_METADATA_COLUMNS};
+use crate::storage_tags::ENTRY_TAGS_SQL;
 use crate::user::{get_group_rights, get_user_from_request, get_user_groups};
 use crate::util::RequestPool;
 
@@ -18,21 +22,101 @@ struct StorageEntryRow {
     entry_type: String,
     mime_type: Option<String>,
     size_bytes: Option<i64>,
+    sha256: Option<String>,
     created_by: Option<i32>,
     created_at: Option<String>,
     downloads_count: i32,
     transcoded_version_available: Option<bool>,
+    tags: Vec<String>,
+}
+
+#[derive(FromRow)]
+struct StorageEntryWithMetadataRow {
+    #[sqlx(flatten)]
+    entry: StorageEntryRow,
+
+    has_metadata: bool,
+
+    #[sqlx(flatten)]
+    metadata: StorageMediaMetadata,
+}
+
+#[derive(Serialize)]
+struct StorageEntryOutput {
+    #[serde(flatten)]
+    entry: StorageEntryRow,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<StorageMediaMetadata>,
 }
 
 #[derive(Serialize)]
 struct StorageEntriesOutput {
-    entries: Vec<StorageEntryRow>,
+    entries: Vec<StorageEntryOutput>,
 }
 
+/**
+ * Metadata filters only match files that have metadata. Text filters match substrings, case insensitively.
+ */
 #[derive(Deserialize)]
 struct QueryParams {
     endpoint_id: i32,
     folder_id: Option<i64>,
+
+    // Include the media metadata of files. Entries without any have no `metadata` field.
+    include_metadata: Option<bool>,
+
+    min_width: Option<i32>,
+    min_height: Option<i32>,
+    min_duration_seconds: Option<f64>,
+    max_duration_seconds: Option<f64>,
+
+    // Camera make or model
+    camera: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+
+    // `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS`
+    taken_after: Option<String>,
+    taken_before: Option<String>,
+
+    has_location: Option<bool>,
+}
+
+const METADATA_FILTERS_SQL: &str = "($2::INTEGER IS NULL OR storage_media_metadata.width >= $2)
+    AND ($3::INTEGER IS NULL OR storage_media_metadata.height >= $3)
+    AND ($4::FLOAT8 IS NULL OR storage_media_metadata.duration_seconds >= $4)
+    AND ($5::FLOAT8 IS NULL OR storage_media_metadata.duration_seconds <= $5)
+    AND ($6::TEXT IS NULL OR storage_media_metadata.camera_make ILIKE $6 ESCAPE '\\' OR storage_media_metadata.camera_model ILIKE $6 ESCAPE '\\')
+    AND ($7::TEXT IS NULL OR storage_media_metadata.artist ILIKE $7 ESCAPE '\\')
+    AND ($8::TEXT IS NULL OR storage_media_metadata.album ILIKE $8 ESCAPE '\\')
+    AND ($9::TIMESTAMP IS NULL OR storage_media_metadata.taken_at >= $9)
+    AND ($10::TIMESTAMP IS NULL OR storage_media_metadata.taken_at <= $10)
+    AND ($11::BOOLEAN IS NULL OR (storage_media_metadata.gps_latitude IS NOT NULL) = $11)";
+
+fn parse_taken_at_filter(value: &Option<String>) -> Result<Option<NaiveDateTime>, StorageError> {
+    let value = match value {
+        Some(value) => value.trim(),
+        None => return Ok(None),
+    };
+
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+        })
+        .map(Some)
+        .map_err(|_| StorageError::InvalidMetadataFilter)
+}
+
+/**
+ * `%value%` for the text filters, with the wildcards in the value escaped
+ */
+fn text_filter_pattern(value: &Option<String>) -> Option<String> {
+    value
+        .as_ref()
+        .map(|value| format!("%{}%", escape_like_pattern(value)))
 }
 
 #[get("/entries")]
@@ -77,32 +161,70 @@ async fn storage_entries(
         return error("storage.access_denied");
     }
 
-    let entries = if folder_id.is_some() {
-        // Entries inside of a folder
+    let taken_after = match parse_taken_at_filter(&query.taken_after) {
+        Ok(taken_after) => taken_after,
+        Err(err) => return error(err.get_code()),
+    };
 
-        sqlx::query_as::<_, StorageEntryRow>(
-            "SELECT id, name, parent_folder, extension, mime_type, size_bytes, created_by, created_at::TEXT, entry_type::TEXT, downloads_count, transcoded_version_available FROM storage_entries WHERE endpoint_id = $1 AND parent_folder = $2",
-        )
-        .bind(endpoint_id)
-        .bind(folder_id)
-        .fetch_all(&**pool)
-        .await
+    let taken_before = match parse_taken_at_filter(&query.taken_before) {
+        Ok(taken_before) => taken_before,
+        Err(err) => return error(err.get_code()),
+    };
+
+    let parent_folder_sql = if folder_id.is_some() {
+        // Entries inside of a folder
+        "storage_entries.parent_folder = $12"
     } else {
         // Entries on the root level
+        "storage_entries.parent_folder IS NULL"
+    };
+
+    let entries_sql = format!(
+        "SELECT storage_entries.id, storage_entries.name, storage_entries.parent_folder, storage_entries.extension, storage_entries.mime_type, storage_entries.size_bytes, storage_entries.sha256, storage_entries.created_by, storage_entries.created_at::TEXT, storage_entries.entry_type::TEXT, storage_entries.downloads_count, storage_entries.transcoded_version_available, {},
+            storage_media_metadata.filesystem_id IS NOT NULL AS has_metadata, {}
+        FROM storage_entries
+        LEFT JOIN storage_media_metadata ON storage_media_metadata.endpoint_id = storage_entries.endpoint_id AND storage_media_metadata.filesystem_id = storage_entries.filesystem_id
+        WHERE storage_entries.endpoint_id = $1 AND {} AND {}",
+        ENTRY_TAGS_SQL, MEDIA_METADATA_COLUMNS, parent_folder_sql, METADATA_FILTERS_SQL
+    );
 
-        sqlx::query_as::<_, StorageEntryRow>(
-            "SELECT id, name, parent_folder, extension, mime_type, size_bytes, created_by, created_at::TEXT, entry_type::TEXT, downloads_count, transcoded_version_available FROM storage_entries WHERE endpoint_id = $1 AND parent_folder IS NULL",
-        )
+    let mut entries_query = sqlx::query_as::<_, StorageEntryWithMetadataRow>(&entries_sql)
         .bind(endpoint_id)
-        .fetch_all(&**pool)
-        .await
-    };
+        .bind(query.min_width)
+        .bind(query.min_height)
+        .bind(query.min_duration_seconds)
+        .bind(query.max_duration_seconds)
+        .bind(text_filter_pattern(&query.camera))
+        .bind(text_filter_pattern(&query.artist))
+        .bind(text_filter_pattern(&query.album))
+        .bind(taken_after)
+        .bind(taken_before)
+        .bind(query.has_location);
+
+    if let Some(folder_id) = folder_id {
+        entries_query = entries_query.bind(folder_id);
+    }
+
+    let entries = entries_query.fetch_all(&**pool).await;
 
     if entries.is_err() {
         return error("storage.internal");
     }
 
-    HttpResponse::Ok().json(web::Json(StorageEntriesOutput {
-        entries: entries.unwrap(),
-    }))
+    let include_metadata = query.include_metadata.unwrap_or(false);
+
+    let entries = entries
+        .unwrap()
+        .into_iter()
+        .map(|row| StorageEntryOutput {
+            entry: row.entry,
+            metadata: if include_metadata && row.has_metadata {
+                Some(row.metadata)
+            } else {
+                None
+            },
+        })
+        .collect::<Vec<StorageEntryOutput>>();
+
+    HttpResponse::Ok().json(web::Json(StorageEntriesOutput { entries }))
 }
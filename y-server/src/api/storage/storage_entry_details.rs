@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::request::error;
+use crate::storage_access::check_storage_entry_access;
+use crate::storage_metadata::{StorageMediaMetadata, MEDIA_METADATA_COLUMNS};
+use crate::storage_tags::{get_entry_properties, ENTRY_TAGS_SQL};
+use crate::user::{get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+
+#[derive(Serialize, FromRow)]
+struct StorageEntryDetailsRow {
+    id: i64,
+    parent_folder: Option<i64>,
+    name: String,
+    extension: Option<String>,
+    entry_type: String,
+    mime_type: Option<String>,
+    size_bytes: Option<i64>,
+    sha256: Option<String>,
+    created_by: Option<i32>,
+    created_by_username: Option<String>,
+    created_at: Option<String>,
+    downloads_count: i32,
+    transcoded_version_available: Option<bool>,
+    tags: Vec<String>,
+}
+
+#[derive(FromRow)]
+struct StorageEntryMetadataRow {
+    extracted_at: String,
+
+    #[sqlx(flatten)]
+    metadata: StorageMediaMetadata,
+}
+
+#[derive(Serialize)]
+struct StorageEntryDetailsOutput {
+    entry: StorageEntryDetailsRow,
+
+    /**
+     * `null` for folders, and for files that are not images, videos or audio (or whose metadata was not extracted yet)
+     */
+    metadata: Option<StorageMediaMetadata>,
+    metadata_extracted_at: Option<String>,
+
+    properties: HashMap<String, String>,
+}
+
+/**
+ * Get an entry along with its tags, its properties and the media metadata extracted from its contents.
+ */
+#[get("/entries/{endpoint_id}/details/{entry_id}")]
+async fn storage_entry_details(
+    pool: web::Data<RequestPool>,
+    path: web::Path<(i32, i64)>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, entry_id) = path.into_inner();
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    let action_allowed = if let Some((client_user, _)) = client {
+        let user_groups = get_user_groups(&**pool, client_user.id).await;
+        let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+        check_storage_entry_access(
+            endpoint_id,
+            entry_id,
+            "list_entries",
+            client_user.id,
+            &group_ids,
+            &**pool,
+        )
+        .await
+    } else {
+        false
+    };
+
+    if !action_allowed {
+        return error("storage.access_denied");
+    }
+
+    let entry = sqlx::query_as::<_, StorageEntryDetailsRow>(&format!(
+        "SELECT storage_entries.id, storage_entries.parent_folder, storage_entries.name, storage_entries.extension, storage_entries.entry_type::TEXT, storage_entries.mime_type, storage_entries.size_bytes, storage_entries.sha256, storage_entries.created_by, users.username AS created_by_username, storage_entries.created_at::TEXT, storage_entries.downloads_count, storage_entries.transcoded_version_available, {}
+        FROM storage_entries LEFT JOIN users ON users.id = storage_entries.created_by
+        WHERE storage_entries.endpoint_id = $1 AND storage_entries.id = $2",
+        ENTRY_TAGS_SQL
+    ))
+    .bind(endpoint_id)
+    .bind(entry_id)
+    .fetch_optional(&**pool)
+    .await;
+
+    let entry = match entry {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return error("storage.entry_not_found"),
+        Err(_) => return error("storage.internal"),
+    };
+
+    let metadata = sqlx::query_as::<_, StorageEntryMetadataRow>(&format!(
+        "SELECT storage_media_metadata.extracted_at::TEXT, {} FROM storage_entries
+        INNER JOIN storage_media_metadata ON storage_media_metadata.endpoint_id = storage_entries.endpoint_id AND storage_media_metadata.filesystem_id = storage_entries.filesystem_id
+        WHERE storage_entries.endpoint_id = $1 AND storage_entries.id = $2",
+        MEDIA_METADATA_COLUMNS
+    ))
+    .bind(endpoint_id)
+    .bind(entry_id)
+    .fetch_optional(&**pool)
+    .await;
+
+    if metadata.is_err() {
+        return error("storage.internal");
+    }
+
+    let (metadata, metadata_extracted_at) = match metadata.unwrap() {
+        Some(row) => (Some(row.metadata), Some(row.extracted_at)),
+        None => (None, None),
+    };
+
+    let properties = get_entry_properties(entry.id, &pool).await;
+
+    if properties.is_err() {
+        return error("storage.internal");
+    }
+
+    HttpResponse::Ok().json(web::Json(StorageEntryDetailsOutput {
+        entry,
+        metadata,
+        metadata_extracted_at,
+        properties: properties.unwrap(),
+    }))
+}
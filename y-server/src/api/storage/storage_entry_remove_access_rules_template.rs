@@ -1,6 +1,7 @@
 use actix_web::{delete, web, HttpResponse, Responder};
 
 use crate::{
+    audit_log::{write_audit_log, AuditActor, AuditEvent},
     request::error,
     storage_access::check_storage_entry_access,
     user::{get_client_rights, get_user_from_request, get_user_groups},
@@ -27,7 +28,7 @@ async fn storage_entry_remove_access_rules_template(
 
     let client = get_user_from_request(&**pool, &req).await;
 
-    let action_allowed = if let Some((client_user, _)) = client {
+    let action_allowed = if let Some((client_user, _)) = &client {
         let user_groups = get_user_groups(&**pool, client_user.id).await;
         let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
 
@@ -57,7 +58,24 @@ async fn storage_entry_remove_access_rules_template(
             .await;
 
     match delete_result {
-        Ok(_) => HttpResponse::Ok().body("{}"),
+        Ok(_) => {
+            write_audit_log(
+                &pool,
+                &req,
+                client.as_ref().map(AuditActor::from_client),
+                AuditEvent {
+                    action: "storage.access_rules_template.detach",
+                    target_type: "storage_entry",
+                    target_ids: vec![entry_id.to_string()],
+                    endpoint_id: Some(endpoint_id),
+                    after: Some(serde_json::json!({ "template_id": template_id })),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().body("{}")
+        }
         Err(_) => error("storage.internal"),
     }
 }
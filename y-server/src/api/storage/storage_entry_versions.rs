@@ -0,0 +1,55 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+
+use crate::request::error;
+use crate::storage_access::check_storage_entry_access;
+use crate::storage_versions::{get_entry_versions, StorageEntryVersion};
+use crate::user::{get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+
+#[derive(Serialize)]
+struct StorageEntryVersionsOutput {
+    versions: Vec<StorageEntryVersion>,
+}
+
+/**
+ * Get previous versions of a file, newest first.
+ */
+#[get("/entries/{endpoint_id}/versions/{file_id}")]
+async fn storage_entry_versions(
+    pool: web::Data<RequestPool>,
+    path: web::Path<(i32, i64)>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, file_id) = path.into_inner();
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    let action_allowed = if let Some((client_user, _)) = client {
+        let user_groups = get_user_groups(&**pool, client_user.id).await;
+        let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+        check_storage_entry_access(
+            endpoint_id,
+            file_id,
+            "download",
+            client_user.id,
+            &group_ids,
+            &**pool,
+        )
+        .await
+    } else {
+        false
+    };
+
+    if !action_allowed {
+        return error("storage.access_denied");
+    }
+
+    let versions = get_entry_versions(endpoint_id, file_id, &pool).await;
+
+    match versions {
+        Ok(versions) => HttpResponse::Ok().json(web::Json(StorageEntryVersionsOutput { versions })),
+        Err(_) => error("storage.internal"),
+    }
+}
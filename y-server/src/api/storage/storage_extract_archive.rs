@@ -0,0 +1,168 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
+use crate::jobs::{enqueue_job, JOB_STORAGE_EXTRACT_ARCHIVE};
+use crate::request::error;
+use crate::storage_access::check_bulk_storage_entries_access_cascade_up;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_extract::{detect_archive_format, StorageExtractJobPayload};
+use crate::storage_uploads::check_upload_access;
+use crate::user::{get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct StorageExtractArchiveInput {
+    endpoint_id: i32,
+    entry_id: i64,
+
+    // Defaults to the folder that the archive is in
+    target_folder_id: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct StorageExtractArchiveOutput {
+    /**
+     * Progress is reported in `storage_extract_progress` WebSocket messages
+     */
+    extract_id: String,
+}
+
+#[derive(FromRow)]
+struct ArchiveEntry {
+    parent_folder: Option<i64>,
+    name: String,
+    extension: Option<String>,
+}
+
+/**
+ * Extract a zip or tar archive into a new folder, named after the archive. Runs in the background.
+ */
+#[post("/extract-archive")]
+async fn storage_extract_archive(
+    pool: web::Data<RequestPool>,
+    form: web::Json<StorageExtractArchiveInput>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+    let endpoint_id = form.endpoint_id;
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    if target_endpoint.unwrap().status != "active" {
+        return error("storage.endpoint_not_active");
+    }
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let client = client.unwrap();
+    let (user, _) = &client;
+
+    let archive = sqlx::query_as::<_, ArchiveEntry>(
+        "SELECT parent_folder, name, extension FROM storage_entries WHERE endpoint_id = $1 AND id = $2 AND entry_type = 'file'::storage_entry_type",
+    )
+    .bind(endpoint_id)
+    .bind(form.entry_id)
+    .fetch_optional(&**pool)
+    .await;
+
+    let archive = match archive {
+        Ok(Some(archive)) => archive,
+        Ok(None) => return error("storage.entry_not_found"),
+        Err(_) => return error("storage.internal"),
+    };
+
+    if detect_archive_format(&archive.name, archive.extension.as_deref()).is_none() {
+        return error("storage_extract.unsupported_format");
+    }
+
+    let target_folder_id = form.target_folder_id.or(archive.parent_folder);
+
+    if let Some(target_folder_id) = form.target_folder_id {
+        let target_folder_exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM storage_entries WHERE endpoint_id = $1 AND id = $2 AND entry_type = 'folder'::storage_entry_type)",
+        )
+        .bind(endpoint_id)
+        .bind(target_folder_id)
+        .fetch_one(&**pool)
+        .await;
+
+        match target_folder_exists {
+            Ok(true) => {}
+            Ok(false) => return error("storage.entry_not_found"),
+            Err(_) => return error("storage.internal"),
+        }
+    }
+
+    // Extracting is essentially downloading the archive and uploading its contents
+    if !check_upload_access(endpoint_id, target_folder_id, user.id, &pool).await {
+        return error("storage.access_denied");
+    }
+
+    let user_groups = get_user_groups(&**pool, user.id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    let download_allowed = check_bulk_storage_entries_access_cascade_up(
+        endpoint_id,
+        &vec![form.entry_id],
+        "download",
+        user.id,
+        &group_ids,
+        &**pool,
+    )
+    .await;
+
+    if !download_allowed {
+        return error("storage.access_denied");
+    }
+
+    let extract_id = uuid::Uuid::new_v4().to_string();
+
+    let enqueue_result = enqueue_job(
+        JOB_STORAGE_EXTRACT_ARCHIVE,
+        &StorageExtractJobPayload {
+            extract_id: extract_id.clone(),
+            user_id: user.id,
+            endpoint_id,
+            entry_id: form.entry_id,
+            target_folder: target_folder_id,
+        },
+        Some(user.id),
+        &pool,
+    )
+    .await;
+
+    match enqueue_result {
+        Ok(_) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "storage.extract_archive",
+                    target_type: "storage_entry",
+                    target_ids: vec![form.entry_id.to_string()],
+                    endpoint_id: Some(endpoint_id),
+                    after: Some(serde_json::json!({
+                        "extract_id": extract_id,
+                        "target_folder": target_folder_id,
+                    })),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().json(web::Json(StorageExtractArchiveOutput { extract_id }))
+        }
+        Err(err) => error(err.get_code()),
+    }
+}
@@ -0,0 +1,45 @@
+use actix_web::{get, web, Responder};
+
+use crate::request::error;
+
+use crate::storage_access::check_storage_entry_access;
+use crate::storage_hls::serve_hls_file;
+use crate::user::{get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+
+/**
+ * Serve the HLS renditions of a video: the master playlist (`master.m3u8`), and the playlists and segments it refers to
+ */
+#[get("/entries/{endpoint_id}/hls/{file_id}/{hls_path:.*}")]
+async fn storage_get_hls(
+    pool: web::Data<RequestPool>,
+    path: web::Path<(i32, i64, String)>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, file_id, hls_path) = path.into_inner();
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    let action_allowed = if let Some((client_user, _)) = client {
+        let user_groups = get_user_groups(&**pool, client_user.id).await;
+        let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+        check_storage_entry_access(
+            endpoint_id,
+            file_id,
+            "download",
+            client_user.id,
+            &group_ids,
+            &**pool,
+        )
+        .await
+    } else {
+        false
+    };
+
+    if !action_allowed {
+        return error("storage.access_denied");
+    }
+
+    serve_hls_file(endpoint_id, file_id, &hls_path, &req, &pool).await
+}
@@ -1,14 +1,20 @@
+use std::collections::HashMap;
 use std::sync::Mutex;
 
 use actix_web::{post, web, HttpResponse, Responder};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
+use crate::audit_log::{get_entries_audit_snapshot, write_audit_log, AuditActor, AuditEvent};
 use crate::request::error;
 use crate::storage_access::{
     check_bulk_storage_entries_access_cascade_up, check_endpoint_root_access,
     check_storage_entry_access,
 };
-use crate::storage_entry::move_entries;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::{get_subfolders_level_with_access_rules, move_entries};
+use crate::storage_move::{start_cross_endpoint_move, StorageMoveAccessRules};
+use crate::storage_quotas::{check_storage_quota, get_entries_total_size};
 use crate::user::{get_group_rights, get_user_from_request, get_user_groups};
 use crate::util::RequestPool;
 use crate::ws::WSState;
@@ -18,6 +24,20 @@ struct StorageMoveEntriesInput {
     endpoint_id: i32,
     entry_ids: Vec<i64>,
     target_folder_id: Option<i64>,
+
+    // Defaults to the source endpoint
+    target_endpoint_id: Option<i32>,
+
+    // Only used when moving to another endpoint. Defaults to keeping the rules
+    access_rules: Option<StorageMoveAccessRules>,
+}
+
+#[derive(Serialize)]
+struct StorageMoveEntriesOutput {
+    /**
+     * Moves to another endpoint happen in the background
+     */
+    move_id: i64,
 }
 
 #[post("/move-entries")]
@@ -29,6 +49,7 @@ async fn storage_move_entries(
 ) -> impl Responder {
     let form = form.into_inner();
     let endpoint_id = form.endpoint_id;
+    let target_endpoint_id = form.target_endpoint_id.unwrap_or(endpoint_id);
     let target_folder_id = form.target_folder_id;
     let entry_ids = form.entry_ids;
 
@@ -43,7 +64,7 @@ async fn storage_move_entries(
 
         target_upload_allowed = if let Some(target_folder_id) = target_folder_id {
             check_storage_entry_access(
-                endpoint_id,
+                target_endpoint_id,
                 target_folder_id,
                 "upload",
                 client_user.id,
@@ -54,7 +75,7 @@ async fn storage_move_entries(
         } else {
             let group_rights = get_group_rights(&pool, &group_ids).await;
 
-            check_endpoint_root_access(endpoint_id, group_rights)
+            check_endpoint_root_access(target_endpoint_id, group_rights)
         };
 
         move_allowed = if target_upload_allowed {
@@ -78,6 +99,48 @@ async fn storage_move_entries(
         return error("storage.access_denied");
     }
 
+    let audit_target_ids = entry_ids.iter().map(|id| id.to_string()).collect();
+    let audit_before = get_entries_audit_snapshot(endpoint_id, &entry_ids, &pool).await;
+
+    if target_endpoint_id != endpoint_id {
+        let client = client.unwrap();
+        let access_rules = form.access_rules.unwrap_or(StorageMoveAccessRules::Keep);
+
+        let response = start_move_to_endpoint(
+            &pool,
+            client.0.id,
+            endpoint_id,
+            entry_ids,
+            target_endpoint_id,
+            target_folder_id,
+            access_rules,
+        )
+        .await;
+
+        if response.status().is_success() {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "storage.move",
+                    target_type: "storage_entry",
+                    target_ids: audit_target_ids,
+                    endpoint_id: Some(endpoint_id),
+                    before: audit_before,
+                    after: Some(json!({
+                        "endpoint_id": target_endpoint_id,
+                        "parent_folder": target_folder_id,
+                        "access_rules": access_rules,
+                    })),
+                },
+            )
+            .await;
+        }
+
+        return response;
+    }
+
     let source_parent_folders = sqlx::query_scalar::<_, Option<i64>>(
         "SELECT DISTINCT parent_folder FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)",
     )
@@ -89,6 +152,24 @@ async fn storage_move_entries(
 
     match result {
         Ok(_) => {
+            write_audit_log(
+                &pool,
+                &req,
+                client.as_ref().map(AuditActor::from_client),
+                AuditEvent {
+                    action: "storage.move",
+                    target_type: "storage_entry",
+                    target_ids: audit_target_ids,
+                    endpoint_id: Some(endpoint_id),
+                    before: audit_before,
+                    after: Some(json!({
+                        "endpoint_id": endpoint_id,
+                        "parent_folder": target_folder_id,
+                    })),
+                },
+            )
+            .await;
+
             if let Ok(mut folders_to_update) = source_parent_folders {
                 // TODO don't block the request here
                 folders_to_update.push(target_folder_id);
@@ -111,3 +192,130 @@ async fn storage_move_entries(
         Err(err) => error(&err.get_code()),
     }
 }
+
+/**
+ * Everything inside of the moved folders leaves the source endpoint, so the user has to be able to move all of it.
+ * The blobs are relocated by a job.
+ */
+async fn start_move_to_endpoint(
+    pool: &web::Data<RequestPool>,
+    user_id: i32,
+    endpoint_id: i32,
+    entry_ids: Vec<i64>,
+    target_endpoint_id: i32,
+    target_folder_id: Option<i64>,
+    access_rules: StorageMoveAccessRules,
+) -> HttpResponse {
+    if entry_ids.is_empty() {
+        return error("storage.invalid_input");
+    }
+
+    let source_endpoint = get_storage_endpoint(endpoint_id, pool).await;
+
+    if source_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    if source_endpoint.unwrap().status != "active" {
+        return error("storage.endpoint_not_active");
+    }
+
+    let target_endpoint = get_storage_endpoint(target_endpoint_id, pool).await;
+
+    if target_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    let target_endpoint = target_endpoint.unwrap();
+
+    if target_endpoint.status != "active" {
+        return error("storage.endpoint_not_active");
+    }
+
+    if let Some(target_folder_id) = target_folder_id {
+        let target_folder_exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM storage_entries WHERE endpoint_id = $1 AND id = $2 AND entry_type = 'folder'::storage_entry_type)",
+        )
+        .bind(target_endpoint_id)
+        .bind(target_folder_id)
+        .fetch_one(&***pool)
+        .await;
+
+        match target_folder_exists {
+            Ok(true) => {}
+            Ok(false) => return error("storage.entry_not_found"),
+            Err(_) => return error("storage.internal"),
+        }
+    }
+
+    let folder_ids = sqlx::query_as::<_, (i64, String)>(
+        "SELECT id, entry_type::TEXT FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)",
+    )
+    .bind(endpoint_id)
+    .bind(&entry_ids)
+    .fetch_all(&***pool)
+    .await;
+
+    let folder_ids = match folder_ids {
+        Ok(entries) if entries.len() == entry_ids.len() => entries
+            .into_iter()
+            .filter(|(_, entry_type)| entry_type == "folder")
+            .map(|(id, _)| id)
+            .collect::<Vec<i64>>(),
+        Ok(_) => return error("storage.entry_not_found"),
+        Err(_) => return error("storage.internal"),
+    };
+
+    // Traverse down (access check)
+    if !folder_ids.is_empty() {
+        let user_groups = get_user_groups(pool, user_id).await;
+        let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+        let mut file_filesystem_ids: Vec<String> = Vec::new();
+        let mut folder_parents: HashMap<i64, Option<i64>> =
+            folder_ids.iter().map(|id| (*id, None)).collect();
+
+        let traverse_down_result = get_subfolders_level_with_access_rules(
+            endpoint_id,
+            &mut folder_parents,
+            &mut file_filesystem_ids,
+            folder_ids,
+            (user_id, &group_ids),
+            "move",
+            pool,
+        )
+        .await;
+
+        if traverse_down_result.is_err() {
+            return error("storage.access_denied");
+        }
+    }
+
+    let total_bytes = get_entries_total_size(endpoint_id, &entry_ids, pool).await;
+
+    if total_bytes.is_err() {
+        return error("storage.internal");
+    }
+
+    if let Err(err) =
+        check_storage_quota(&target_endpoint, Some(user_id), total_bytes.unwrap(), pool).await
+    {
+        return error(err.get_code());
+    }
+
+    let move_result = start_cross_endpoint_move(
+        endpoint_id,
+        &entry_ids,
+        target_endpoint_id,
+        target_folder_id,
+        access_rules,
+        Some(user_id),
+        pool,
+    )
+    .await;
+
+    match move_result {
+        Ok(move_id) => HttpResponse::Ok().json(web::Json(StorageMoveEntriesOutput { move_id })),
+        Err(err) => error(err.get_code()),
+    }
+}
@@ -0,0 +1,94 @@
+use actix_web::{delete, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
+use crate::request::error;
+use crate::storage_trash::{check_trash_item_access, get_trash_items, purge_trash_items};
+use crate::user::{get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct StoragePurgeTrashItemsInput {
+    endpoint_id: i32,
+    trash_ids: Vec<i64>,
+}
+
+#[derive(Serialize)]
+struct StoragePurgeTrashItemsOutput {
+    deleted_files: usize,
+}
+
+/// Permanently delete trashed entries. This can not be undone
+#[delete("/trash")]
+async fn storage_purge_trash_items(
+    pool: web::Data<RequestPool>,
+    form: web::Json<StoragePurgeTrashItemsInput>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+    let endpoint_id = form.endpoint_id;
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let client = client.unwrap();
+    let (client_user, _) = &client;
+    let user_groups = get_user_groups(&**pool, client_user.id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    let trash_items = get_trash_items(endpoint_id, &form.trash_ids, &pool).await;
+
+    if trash_items.is_err() {
+        return error("storage.internal");
+    }
+
+    let trash_items = trash_items.unwrap();
+
+    for trash_item in &trash_items {
+        if !check_trash_item_access(
+            endpoint_id,
+            trash_item.original_parent_folder,
+            client_user.id,
+            &group_ids,
+            &pool,
+        )
+        .await
+        {
+            return error("storage.access_denied");
+        }
+    }
+
+    let trash_ids = trash_items.iter().map(|item| item.id).collect::<Vec<i64>>();
+
+    match purge_trash_items(endpoint_id, &trash_ids, &pool).await {
+        Ok(deleted_files) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(AuditActor::from_client(&client)),
+                AuditEvent {
+                    action: "storage.trash.purge",
+                    target_type: "storage_trash_item",
+                    target_ids: trash_ids.iter().map(|id| id.to_string()).collect(),
+                    endpoint_id: Some(endpoint_id),
+                    before: Some(serde_json::json!(trash_items
+                        .iter()
+                        .map(|item| serde_json::json!({
+                            "id": item.id,
+                            "entry_id": item.entry_id,
+                            "original_parent_folder": item.original_parent_folder,
+                        }))
+                        .collect::<Vec<serde_json::Value>>())),
+                    after: Some(serde_json::json!({ "deleted_files": deleted_files })),
+                },
+            )
+            .await;
+
+            HttpResponse::Ok().json(web::Json(StoragePurgeTrashItemsOutput { deleted_files }))
+        }
+        Err(err) => error(err.get_code()),
+    }
+}
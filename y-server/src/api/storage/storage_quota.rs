@@ -0,0 +1,62 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Deserialize;
+
+use crate::request::error;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_quotas::get_user_storage_quota;
+use crate::user::{get_client_rights, get_user_from_request};
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct StorageQuotaQuery {
+    endpoint_id: i32,
+
+    /**
+     * Whose usage to report. `null` == the client's own. Other users require the `manage_storage_endpoints` right.
+     */
+    user_id: Option<i32>,
+}
+
+/// Report how much a user stores on an endpoint (and across all endpoints), against their quota
+#[get("/quota")]
+async fn storage_quota(
+    pool: web::Data<RequestPool>,
+    query: web::Query<StorageQuotaQuery>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let (client_user, _) = client.unwrap();
+
+    let user_id = query.user_id.unwrap_or(client_user.id);
+
+    if user_id != client_user.id {
+        let client_rights = get_client_rights(&pool, &req).await;
+
+        let action_allowed = client_rights
+            .iter()
+            .find(|right| right.right_name.eq("manage_storage_endpoints"))
+            .is_some();
+
+        if !action_allowed {
+            return error("storage.access_denied");
+        }
+    }
+
+    let target_endpoint = get_storage_endpoint(query.endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    let quota = get_user_storage_quota(&target_endpoint.unwrap(), user_id, &pool).await;
+
+    match quota {
+        Ok(quota) => HttpResponse::Ok().json(web::Json(quota)),
+        Err(err) => error(err.get_code()),
+    }
+}
@@ -4,6 +4,7 @@ use actix_web::{patch, web, HttpResponse, Responder};
 use serde::Deserialize;
 use validator::Validate;
 
+use crate::audit_log::{get_entries_audit_snapshot, write_audit_log, AuditActor, AuditEvent};
 use crate::request::error;
 use crate::storage_access::check_storage_entry_access;
 use crate::storage_entry::rename_entry;
@@ -60,10 +61,27 @@ async fn storage_rename_entry(
     let entry_id = form.entry_id;
     let name = form.name;
 
+    let audit_before = get_entries_audit_snapshot(endpoint_id, &vec![entry_id], &pool).await;
+
     let result = rename_entry(endpoint_id, entry_id, name.as_str(), &pool).await;
 
     match result {
         Ok(parent_folder) => {
+            write_audit_log(
+                &pool,
+                &req,
+                client.as_ref().map(AuditActor::from_client),
+                AuditEvent {
+                    action: "storage.rename",
+                    target_type: "storage_entry",
+                    target_ids: vec![entry_id.to_string()],
+                    endpoint_id: Some(endpoint_id),
+                    before: audit_before,
+                    after: get_entries_audit_snapshot(endpoint_id, &vec![entry_id], &pool).await,
+                },
+            )
+            .await;
+
             // TODO don't block the request
             ws_state
                 .lock()
@@ -0,0 +1,136 @@
+use std::sync::Mutex;
+
+use actix_web::{post, web, HttpResponse, Responder};
+
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
+use crate::request::error;
+use crate::storage_access::check_storage_entry_access;
+use crate::storage_artifacts::queue_uploaded_files_artifacts;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_versions::{get_entry_version_blob, restore_entry_version};
+use crate::user::{get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+use crate::ws::{send_storage_location_updated, WSState};
+
+/**
+ * Make a previous version of a file current again.
+ *
+ * The current contents of the file become a version themselves (if versioning is enabled for the endpoint).
+ */
+#[post("/entries/{endpoint_id}/versions/{file_id}/{version_id}/restore")]
+async fn storage_restore_entry_version(
+    pool: web::Data<RequestPool>,
+    ws_state: web::Data<Mutex<WSState>>,
+    path: web::Path<(i32, i64, i64)>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, file_id, version_id) = path.into_inner();
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let client = client.unwrap();
+    let (client_user, _) = &client;
+    let user_groups = get_user_groups(&**pool, client_user.id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    // Restoring a version means uploading new contents for the file
+    if !check_storage_entry_access(
+        endpoint_id,
+        file_id,
+        "upload",
+        client_user.id,
+        &group_ids,
+        &**pool,
+    )
+    .await
+    {
+        return error("storage.access_denied");
+    }
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    let target_endpoint = target_endpoint.unwrap();
+
+    if target_endpoint.status != "active" {
+        return error("storage.endpoint_not_active");
+    }
+
+    if get_entry_version_blob(endpoint_id, file_id, version_id, &pool)
+        .await
+        .is_err()
+    {
+        return error("storage.version_not_found");
+    }
+
+    let parent_folder = sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT parent_folder FROM storage_entries WHERE endpoint_id = $1 AND id = $2",
+    )
+    .bind(endpoint_id)
+    .bind(file_id)
+    .fetch_one(&**pool)
+    .await;
+
+    if parent_folder.is_err() {
+        return error("storage.internal");
+    }
+
+    let parent_folder = parent_folder.unwrap();
+
+    let restored_filesystem_id = restore_entry_version(
+        &target_endpoint,
+        file_id,
+        version_id,
+        Some(client_user.id),
+        &pool,
+    )
+    .await;
+
+    if let Err(err) = restored_filesystem_id {
+        return error(err.get_code());
+    }
+
+    // Artifacts are not kept for versions, generate them again
+    queue_uploaded_files_artifacts(
+        &target_endpoint,
+        vec![restored_filesystem_id.unwrap()],
+        Some(client_user.id),
+        &pool,
+    )
+    .await;
+
+    write_audit_log(
+        &pool,
+        &req,
+        Some(AuditActor::from_client(&client)),
+        AuditEvent {
+            action: "storage.version.restore",
+            target_type: "storage_entry",
+            target_ids: vec![file_id.to_string()],
+            endpoint_id: Some(endpoint_id),
+            after: Some(serde_json::json!({ "version_id": version_id })),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    // TODO don't block the request
+    send_storage_location_updated(
+        &ws_state,
+        Some(client_user.id),
+        endpoint_id,
+        vec![parent_folder],
+        true,
+        true,
+    )
+    .await;
+
+    HttpResponse::Ok().body("{}")
+}
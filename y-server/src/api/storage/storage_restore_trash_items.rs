@@ -0,0 +1,145 @@
+use std::sync::Mutex;
+
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::audit_log::{get_entries_audit_snapshot, write_audit_log, AuditActor, AuditEvent};
+use crate::request::error;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::StorageError;
+use crate::storage_trash::{
+    check_trash_item_access, get_trash_item_restore_target, get_trash_items, restore_trash_item,
+};
+use crate::user::{get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+use crate::ws::{send_storage_location_updated, WSState};
+
+#[derive(Deserialize)]
+struct StorageRestoreTrashItemsInput {
+    endpoint_id: i32,
+    trash_ids: Vec<i64>,
+}
+
+#[derive(Serialize)]
+struct StorageRestoreTrashItemsOutput {
+    /**
+     * Items that could not be restored because an entry with the same name already exists in the target folder
+     */
+    skipped_trash_ids: Vec<i64>,
+}
+
+/**
+ * Put trashed entries back where they were.
+ *
+ * If the folder where an entry used to reside does not exist anymore, the entry is restored into the root of the endpoint.
+ */
+#[post("/trash/restore")]
+async fn storage_restore_trash_items(
+    pool: web::Data<RequestPool>,
+    ws_state: web::Data<Mutex<WSState>>,
+    form: web::Json<StorageRestoreTrashItemsInput>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+    let endpoint_id = form.endpoint_id;
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let client = client.unwrap();
+    let (client_user, _) = &client;
+    let user_groups = get_user_groups(&**pool, client_user.id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    if target_endpoint.unwrap().status != "active" {
+        return error("storage.endpoint_not_active");
+    }
+
+    let trash_items = get_trash_items(endpoint_id, &form.trash_ids, &pool).await;
+
+    if trash_items.is_err() {
+        return error("storage.internal");
+    }
+
+    let trash_items = trash_items.unwrap();
+
+    // Check everything before restoring anything
+    for trash_item in &trash_items {
+        if !check_trash_item_access(
+            endpoint_id,
+            trash_item.original_parent_folder,
+            client_user.id,
+            &group_ids,
+            &pool,
+        )
+        .await
+        {
+            return error("storage.access_denied");
+        }
+    }
+
+    let mut restored_entry_ids: Vec<i64> = Vec::new();
+    let mut skipped_trash_ids: Vec<i64> = Vec::new();
+    let mut folders_to_update: Vec<Option<i64>> = Vec::new();
+
+    for trash_item in &trash_items {
+        let target_folder =
+            get_trash_item_restore_target(endpoint_id, trash_item.original_parent_folder, &pool)
+                .await;
+
+        match restore_trash_item(endpoint_id, trash_item, target_folder, &pool).await {
+            Ok(_) => {
+                restored_entry_ids.push(trash_item.entry_id);
+
+                if !folders_to_update.contains(&target_folder) {
+                    folders_to_update.push(target_folder);
+                }
+            }
+            Err(StorageError::NameConflict) => skipped_trash_ids.push(trash_item.id),
+            Err(_) => return error("storage.internal"),
+        }
+    }
+
+    if !restored_entry_ids.is_empty() {
+        write_audit_log(
+            &pool,
+            &req,
+            Some(AuditActor::from_client(&client)),
+            AuditEvent {
+                action: "storage.trash.restore",
+                target_type: "storage_entry",
+                target_ids: restored_entry_ids.iter().map(|id| id.to_string()).collect(),
+                endpoint_id: Some(endpoint_id),
+                after: get_entries_audit_snapshot(endpoint_id, &restored_entry_ids, &pool).await,
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    if !folders_to_update.is_empty() {
+        // TODO don't block the request
+        send_storage_location_updated(
+            &ws_state,
+            Some(client_user.id),
+            endpoint_id,
+            folders_to_update,
+            true,
+            false,
+        )
+        .await;
+    }
+
+    HttpResponse::Ok().json(web::Json(StorageRestoreTrashItemsOutput {
+        skipped_trash_ids,
+    }))
+}
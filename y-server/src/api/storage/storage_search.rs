@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+
+use actix_web::{get, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use validator::Validate;
+
+use crate::request::{error, escape_like_pattern};
+use crate::storage_access::{check_endpoint_root_access, check_storage_entry_access};
+use crate::storage_tags::{normalize_tags, ENTRY_TAGS_SQL};
+use crate::user::{get_group_rights, get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+
+/**
+ * How many matching entries are fetched from the database at once. Some of them may be filtered out by access rules,
+ * so more batches are fetched until the requested page is full.
+ */
+const SEARCH_BATCH_SIZE: i64 = 200;
+
+#[derive(Deserialize, Validate)]
+struct StorageSearchQuery {
+    endpoint_id: i32,
+
+    /**
+     * Text to look for in entry names. Matches whole words, parts of names and names with typos.
+     */
+    #[validate(length(min = 1, max = 255))]
+    query: Option<String>,
+
+    /**
+     * "file" or "folder"
+     */
+    entry_type: Option<String>,
+
+    #[validate(length(min = 1, max = 255))]
+    extension: Option<String>,
+
+    /**
+     * Full ("image/png") or partial ("image/") MIME type
+     */
+    #[validate(length(min = 1, max = 255))]
+    mime_type: Option<String>,
+
+    min_size: Option<i64>,
+    max_size: Option<i64>,
+
+    /**
+     * RFC 3339 timestamps
+     */
+    created_after: Option<String>,
+    created_before: Option<String>,
+
+    created_by: Option<i32>,
+
+    /**
+     * Comma-separated tag names. Only entries that have all of these tags are returned.
+     */
+    #[validate(length(min = 1, max = 1024))]
+    tags: Option<String>,
+
+    #[validate(range(min = 1, max = 500))]
+    limit: Option<i64>,
+
+    #[validate(range(min = 0))]
+    skip: Option<i64>,
+}
+
+#[derive(Serialize, FromRow)]
+struct StorageSearchResultRow {
+    id: i64,
+    parent_folder: Option<i64>,
+    name: String,
+    extension: Option<String>,
+    entry_type: String,
+    mime_type: Option<String>,
+    size_bytes: Option<i64>,
+    sha256: Option<String>,
+    created_by: Option<i32>,
+    created_by_username: Option<String>,
+    created_at: Option<String>,
+    downloads_count: i32,
+    transcoded_version_available: Option<bool>,
+    tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct StorageSearchOutput {
+    entries: Vec<StorageSearchResultRow>,
+
+    /**
+     * Whether there are more results after this page
+     */
+    has_more: bool,
+}
+
+fn parse_timestamp(timestamp: &Option<String>) -> Result<Option<DateTime<Utc>>, ()> {
+    match timestamp {
+        Some(timestamp) => DateTime::parse_from_rfc3339(timestamp)
+            .map(|timestamp| Some(timestamp.with_timezone(&Utc)))
+            .map_err(|_| ()),
+        None => Ok(None),
+    }
+}
+
+/// Search for entries of an endpoint by name and metadata.
+/// Only the entries that the client would be able to see while browsing the endpoint (`list_entries` on the parent folder) are returned.
+#[get("/search")]
+async fn storage_search(
+    pool: web::Data<RequestPool>,
+    query: web::Query<StorageSearchQuery>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let query = query.into_inner();
+
+    if query.validate().is_err() {
+        return error("storage.invalid_input");
+    }
+
+    let entry_type = match query.entry_type.as_deref() {
+        Some("file") | Some("folder") | None => query.entry_type.clone(),
+        _ => return error("storage.invalid_input"),
+    };
+
+    let created_after = parse_timestamp(&query.created_after);
+    let created_before = parse_timestamp(&query.created_before);
+
+    if created_after.is_err() || created_before.is_err() {
+        return error("storage.invalid_input");
+    }
+
+    let created_after = created_after.unwrap();
+    let created_before = created_before.unwrap();
+
+    let tags = match &query.tags {
+        Some(tags) => {
+            let tags = normalize_tags(
+                &tags
+                    .split(',')
+                    .map(|tag| tag.to_string())
+                    .collect::<Vec<String>>(),
+            );
+
+            if tags.is_none() {
+                return error("storage.invalid_input");
+            }
+
+            Some(
+                tags.unwrap()
+                    .iter()
+                    .map(|tag| tag.to_lowercase())
+                    .collect::<Vec<String>>(),
+            )
+        }
+        None => None,
+    };
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let (client_user, _) = client.unwrap();
+    let user_groups = get_user_groups(&**pool, client_user.id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    let search_text = query
+        .query
+        .as_ref()
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty());
+    let name_pattern = search_text
+        .as_ref()
+        .map(|text| format!("%{}%", escape_like_pattern(text)));
+    let extension = query
+        .extension
+        .as_ref()
+        .map(|extension| extension.trim_start_matches('.').to_lowercase());
+    let mime_type_pattern = query
+        .mime_type
+        .as_ref()
+        .map(|mime_type| format!("{}%", escape_like_pattern(mime_type)));
+
+    let limit = query.limit.unwrap_or(50) as usize;
+    let skip = query.skip.unwrap_or(0) as usize;
+
+    // Many results usually come from the same folder, no need to check them all separately
+    let mut parent_folder_access: HashMap<Option<i64>, bool> = HashMap::new();
+
+    let mut accessible_entries: Vec<StorageSearchResultRow> = Vec::new();
+    let mut accessible_skipped: usize = 0;
+    let mut batch_offset: i64 = 0;
+
+    'batches: loop {
+        let batch = sqlx::query_as::<_, StorageSearchResultRow>(&format!(
+            "SELECT storage_entries.id, storage_entries.parent_folder, storage_entries.name, storage_entries.extension,
+            storage_entries.entry_type::TEXT, storage_entries.mime_type, storage_entries.size_bytes, storage_entries.sha256,
+            storage_entries.created_by, users.username AS created_by_username, storage_entries.created_at::TEXT,
+            storage_entries.downloads_count, storage_entries.transcoded_version_available, {}
+            FROM storage_entries
+            LEFT JOIN users ON users.id = storage_entries.created_by
+            WHERE storage_entries.endpoint_id = $1
+            AND ($2::TEXT IS NULL OR (
+                to_tsvector('simple', storage_entries.name) @@ plainto_tsquery('simple', $2)
+                OR storage_entries.name ILIKE $3 ESCAPE '\\'
+                OR storage_entries.name % $2
+            ))
+            AND ($4::TEXT IS NULL OR storage_entries.entry_type = $4::storage_entry_type)
+            AND ($5::TEXT IS NULL OR lower(storage_entries.extension) = $5)
+            AND ($6::TEXT IS NULL OR storage_entries.mime_type ILIKE $6 ESCAPE '\\')
+            AND ($7::BIGINT IS NULL OR storage_entries.size_bytes >= $7)
+            AND ($8::BIGINT IS NULL OR storage_entries.size_bytes <= $8)
+            AND ($9::TIMESTAMPTZ IS NULL OR storage_entries.created_at >= $9)
+            AND ($10::TIMESTAMPTZ IS NULL OR storage_entries.created_at <= $10)
+            AND ($11::INTEGER IS NULL OR storage_entries.created_by = $11)
+            AND ($14::TEXT[] IS NULL OR (
+                SELECT COUNT(*) FROM storage_entry_tags
+                INNER JOIN storage_tags ON storage_tags.id = storage_entry_tags.tag_id
+                WHERE storage_entry_tags.entry_id = storage_entries.id AND lower(storage_tags.name) = ANY($14)
+            ) = cardinality($14))
+            ORDER BY
+                CASE WHEN $2::TEXT IS NULL THEN 0 ELSE
+                    (CASE WHEN lower(storage_entries.name) = lower($2) THEN 1 ELSE 0 END)
+                    + ts_rank(to_tsvector('simple', storage_entries.name), plainto_tsquery('simple', $2))
+                    + similarity(storage_entries.name, $2)
+                END DESC,
+                storage_entries.created_at DESC NULLS LAST, storage_entries.id DESC
+            LIMIT $12 OFFSET $13",
+            ENTRY_TAGS_SQL
+        ))
+        .bind(query.endpoint_id)
+        .bind(&search_text)
+        .bind(&name_pattern)
+        .bind(&entry_type)
+        .bind(&extension)
+        .bind(&mime_type_pattern)
+        .bind(query.min_size)
+        .bind(query.max_size)
+        .bind(created_after)
+        .bind(created_before)
+        .bind(query.created_by)
+        .bind(SEARCH_BATCH_SIZE)
+        .bind(batch_offset)
+        .bind(&tags)
+        .fetch_all(&**pool)
+        .await;
+
+        if batch.is_err() {
+            return error("storage.internal");
+        }
+
+        let batch = batch.unwrap();
+        let batch_len = batch.len() as i64;
+
+        for entry in batch {
+            let action_allowed = match parent_folder_access.get(&entry.parent_folder) {
+                Some(action_allowed) => *action_allowed,
+                None => {
+                    let action_allowed = match entry.parent_folder {
+                        Some(parent_folder) => {
+                            check_storage_entry_access(
+                                query.endpoint_id,
+                                parent_folder,
+                                "list_entries",
+                                client_user.id,
+                                &group_ids,
+                                &**pool,
+                            )
+                            .await
+                        }
+                        None => {
+                            // Entries on the root level of the endpoint
+                            let group_rights = get_group_rights(&pool, &group_ids).await;
+
+                            check_endpoint_root_access(query.endpoint_id, group_rights)
+                        }
+                    };
+
+                    parent_folder_access.insert(entry.parent_folder, action_allowed);
+
+                    action_allowed
+                }
+            };
+
+            if !action_allowed {
+                continue;
+            }
+
+            if accessible_skipped < skip {
+                accessible_skipped += 1;
+                continue;
+            }
+
+            accessible_entries.push(entry);
+
+            // One more result than requested, to know if there is a next page
+            if accessible_entries.len() > limit {
+                break 'batches;
+            }
+        }
+
+        if batch_len < SEARCH_BATCH_SIZE {
+            break;
+        }
+
+        batch_offset += SEARCH_BATCH_SIZE;
+    }
+
+    let has_more = accessible_entries.len() > limit;
+    accessible_entries.truncate(limit);
+
+    HttpResponse::Ok().json(web::Json(StorageSearchOutput {
+        entries: accessible_entries,
+        has_more,
+    }))
+}
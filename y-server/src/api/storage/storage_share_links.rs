@@ -0,0 +1,63 @@
+use actix_web::web::Query;
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::request::error;
+use crate::storage_share_links::{StorageShareLinkInfo, SHARE_LINK_INFO_SELECT};
+use crate::user::get_user_from_request;
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct QueryParams {
+    endpoint_id: Option<i32>,
+
+    /**
+     * Only get links to this entry. Requires `endpoint_id`.
+     */
+    entry_id: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct StorageShareLinksOutput {
+    share_links: Vec<StorageShareLinkInfo>,
+}
+
+/**
+ * Get share links created by the client
+ */
+#[get("/share-links")]
+async fn storage_share_links(
+    pool: web::Data<RequestPool>,
+    query: Query<QueryParams>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let (client_user, _) = client.unwrap();
+
+    let sql = format!(
+        "{} WHERE storage_share_links.created_by = $1
+        AND ($2::INTEGER IS NULL OR storage_share_links.endpoint_id = $2)
+        AND ($3::BIGINT IS NULL OR storage_share_links.entry_id = $3)
+        ORDER BY storage_share_links.created_at DESC",
+        SHARE_LINK_INFO_SELECT
+    );
+
+    let share_links = sqlx::query_as::<_, StorageShareLinkInfo>(sql.as_str())
+        .bind(client_user.id)
+        .bind(query.endpoint_id)
+        .bind(query.entry_id)
+        .fetch_all(&**pool)
+        .await;
+
+    match share_links {
+        Ok(share_links) => {
+            HttpResponse::Ok().json(web::Json(StorageShareLinksOutput { share_links }))
+        }
+        Err(_) => error("storage.internal"),
+    }
+}
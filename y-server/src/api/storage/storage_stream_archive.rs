@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Query;
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Deserialize;
+
+use crate::request::error;
+use crate::storage_access::check_bulk_storage_entries_access_cascade_up;
+use crate::storage_archive_stream::{stream_archive, StorageArchiveStreamFormat};
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::{get_subfolders_level_with_access_rules, resolve_entries};
+use crate::user::{get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct StorageStreamArchiveInput {
+    /**
+     * Comma-separated ids
+     */
+    folder_ids: Option<String>,
+
+    /**
+     * Comma-separated ids
+     */
+    file_ids: Option<String>,
+
+    // Defaults to an uncompressed zip archive
+    format: Option<StorageArchiveStreamFormat>,
+}
+
+fn parse_ids(ids: &Option<String>) -> Option<Vec<i64>> {
+    match ids {
+        Some(ids) if !ids.is_empty() => ids.split(',').map(|id| id.parse::<i64>().ok()).collect(),
+        _ => Some(Vec::new()),
+    }
+}
+
+/**
+ * Download files and folders as an archive that is written while it's being downloaded.
+ *
+ * Unlike `storage_create_archive`, nothing has to be prepared in advance and no space is taken up on the server.
+ */
+#[get("/entries/{endpoint_id}/stream-archive")]
+async fn storage_stream_archive(
+    pool: web::Data<RequestPool>,
+    query: Query<StorageStreamArchiveInput>,
+    path: web::Path<i32>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let endpoint_id = path.into_inner();
+    let format = query.format.unwrap_or(StorageArchiveStreamFormat::Zip);
+
+    let (folder_ids, file_ids) = match (parse_ids(&query.folder_ids), parse_ids(&query.file_ids)) {
+        (Some(folder_ids), Some(file_ids)) => (folder_ids, file_ids),
+        _ => return error("storage.invalid_input"),
+    };
+
+    if folder_ids.is_empty() && file_ids.is_empty() {
+        return error("storage.invalid_input");
+    }
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return error("storage.endpoint_not_found");
+    }
+
+    let target_endpoint = target_endpoint.unwrap();
+
+    if target_endpoint.status == "disabled" {
+        return error("storage.endpoint_disabled");
+    }
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let (user, _) = client.unwrap();
+    let user_groups = get_user_groups(&**pool, user.id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    let all_entries_ids = file_ids.iter().chain(folder_ids.iter()).copied().collect();
+
+    // Traverse up (access check)
+    let action_allowed_cascade_up = check_bulk_storage_entries_access_cascade_up(
+        endpoint_id,
+        &all_entries_ids,
+        "download",
+        user.id,
+        &group_ids,
+        &**pool,
+    )
+    .await;
+
+    if !action_allowed_cascade_up {
+        return error("storage.access_denied");
+    }
+
+    // Traverse down (access check)
+    let mut file_filesystem_ids: Vec<String> = Vec::new();
+    let mut folder_parents: HashMap<i64, Option<i64>> =
+        folder_ids.iter().map(|id| (*id, None)).collect();
+
+    let traverse_down_result = get_subfolders_level_with_access_rules(
+        endpoint_id,
+        &mut folder_parents,
+        &mut file_filesystem_ids,
+        folder_ids.clone(),
+        (user.id, &group_ids),
+        "download",
+        &**pool,
+    )
+    .await;
+
+    if traverse_down_result.is_err() {
+        return error("storage.access_denied");
+    }
+
+    let resolved_entries = resolve_entries(endpoint_id, folder_ids, file_ids, &pool).await;
+
+    if resolved_entries.is_err() {
+        return error("storage.internal");
+    }
+
+    let resolved_entries = resolved_entries.unwrap();
+
+    let filesystem_ids = resolved_entries.values().cloned().collect::<Vec<String>>();
+
+    let body = stream_archive(
+        target_endpoint,
+        resolved_entries,
+        format,
+        pool.get_ref().clone(),
+    );
+
+    if body.is_none() {
+        return error("storage.too_many_archive_streams");
+    }
+
+    let body = body.unwrap();
+
+    // Increment downloads count for each file that goes into the archive
+    let _ = sqlx::query(
+        "UPDATE storage_entries SET downloads_count = downloads_count + 1 WHERE filesystem_id = ANY($1) AND endpoint_id = $2",
+    )
+    .bind(filesystem_ids)
+    .bind(endpoint_id)
+    .execute(&**pool)
+    .await;
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(format!(
+                    "download.{}",
+                    format.file_extension()
+                ))],
+            },
+        ))
+        .streaming(body)
+}
@@ -0,0 +1,41 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::request::error;
+use crate::storage_tags::{get_endpoint_tags, StorageTag};
+use crate::user::get_user_from_request;
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct StorageTagsQuery {
+    endpoint_id: i32,
+}
+
+#[derive(Serialize)]
+struct StorageTagsOutput {
+    tags: Vec<StorageTag>,
+}
+
+/// List the tags of an endpoint. Use `/search?tags=` to find the entries that have them
+#[get("/tags")]
+async fn storage_tags(
+    pool: web::Data<RequestPool>,
+    query: web::Query<StorageTagsQuery>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let tags = get_endpoint_tags(query.endpoint_id, &pool).await;
+
+    if tags.is_err() {
+        return error("storage.internal");
+    }
+
+    HttpResponse::Ok().json(web::Json(StorageTagsOutput {
+        tags: tags.unwrap(),
+    }))
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+use crate::request::error;
+use crate::storage_trash::check_trash_item_access;
+use crate::user::{get_user_from_request, get_user_groups};
+use crate::util::RequestPool;
+
+#[derive(Deserialize)]
+struct StorageTrashItemsQuery {
+    endpoint_id: i32,
+}
+
+#[derive(Serialize, FromRow)]
+struct StorageTrashItemRow {
+    id: i64,
+    entry_id: i64,
+    entry_type: String,
+    name: String,
+    extension: Option<String>,
+
+    original_parent_folder: Option<i64>,
+    original_path: String,
+
+    /**
+     * Number of files inside of a trashed folder (1 for a trashed file)
+     */
+    files_count: i64,
+    size_bytes: i64,
+
+    deleted_by: Option<i32>,
+    deleted_by_username: Option<String>,
+    deleted_at: String,
+}
+
+#[derive(Serialize)]
+struct StorageTrashItemsOutput {
+    trash_items: Vec<StorageTrashItemRow>,
+}
+
+/// List the entries in the trash of an endpoint. Only the items that the client can restore or purge are returned
+#[get("/trash")]
+async fn storage_trash_items(
+    pool: web::Data<RequestPool>,
+    query: web::Query<StorageTrashItemsQuery>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let endpoint_id = query.endpoint_id;
+
+    let client = get_user_from_request(&**pool, &req).await;
+
+    if client.is_none() {
+        return error("storage.access_denied");
+    }
+
+    let (client_user, _) = client.unwrap();
+    let user_groups = get_user_groups(&**pool, client_user.id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    let trash_items = sqlx::query_as::<_, StorageTrashItemRow>(
+        "SELECT storage_trash.id, storage_trash.entry_id, storage_trash.entry_type::TEXT, storage_trash.name, storage_trash.extension, storage_trash.original_parent_folder, storage_trash.original_path,
+        (SELECT COUNT(filesystem_id) FROM storage_trash_entries WHERE trash_id = storage_trash.id) AS files_count,
+        (SELECT COALESCE(SUM(size_bytes), 0)::BIGINT FROM storage_trash_entries WHERE trash_id = storage_trash.id) AS size_bytes,
+        storage_trash.deleted_by, users.username AS deleted_by_username, storage_trash.deleted_at::TEXT
+        FROM storage_trash
+        LEFT JOIN users ON users.id = storage_trash.deleted_by
+        WHERE storage_trash.endpoint_id = $1
+        ORDER BY storage_trash.deleted_at DESC",
+    )
+    .bind(endpoint_id)
+    .fetch_all(&**pool)
+    .await;
+
+    if trash_items.is_err() {
+        return error("storage.internal");
+    }
+
+    // Many items usually come from the same folder, no need to check them all separately
+    let mut parent_folder_access: HashMap<Option<i64>, bool> = HashMap::new();
+    let mut accessible_trash_items: Vec<StorageTrashItemRow> = Vec::new();
+
+    for trash_item in trash_items.unwrap() {
+        let action_allowed = match parent_folder_access.get(&trash_item.original_parent_folder) {
+            Some(action_allowed) => *action_allowed,
+            None => {
+                let action_allowed = check_trash_item_access(
+                    endpoint_id,
+                    trash_item.original_parent_folder,
+                    client_user.id,
+                    &group_ids,
+                    &pool,
+                )
+                .await;
+
+                parent_folder_access.insert(trash_item.original_parent_folder, action_allowed);
+
+                action_allowed
+            }
+        };
+
+        if action_allowed {
+            accessible_trash_items.push(trash_item);
+        }
+    }
+
+    HttpResponse::Ok().json(web::Json(StorageTrashItemsOutput {
+        trash_items: accessible_trash_items,
+    }))
+}
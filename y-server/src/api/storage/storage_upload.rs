@@ -1,8 +1,7 @@
-use futures::executor::block_on;
 use log::*;
 use std::fs;
 use std::sync::Mutex;
-use std::{collections::HashMap, fs::OpenOptions, path::Path};
+use std::{collections::HashMap, fs::OpenOptions};
 use uuid::Uuid;
 
 use actix_multipart::Multipart;
@@ -13,20 +12,26 @@ use actix_web::{
 };
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use std::io::Write;
 use std::time::Instant;
 
-use crate::config::get_config;
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
 use crate::storage_access::{check_endpoint_root_access, check_storage_entry_access};
-use crate::storage_entry::{
-    generate_audio_entry_cover_thumbnail, generate_browser_friendly_video,
-    generate_image_entry_thumbnail, generate_video_entry_thumbnails,
-};
+use crate::storage_artifacts::queue_uploaded_files_artifacts;
+use crate::storage_backend::get_storage_backend;
+use crate::storage_blobs::{finalize_blob_hash, release_stored_blob, store_staged_blob};
+use crate::storage_entry::delete_storage_blobs;
+use crate::storage_layout::sync_endpoint_file_structure;
+use crate::storage_quotas::get_user_storage_quota;
+use crate::storage_versions::{replace_entry_blob, StorageEntryVersionBlob};
 use crate::user::{get_group_rights, get_user_from_request, get_user_groups};
 use crate::ws::WSState;
-use crate::{storage_endpoint::get_storage_endpoint, util::RequestPool};
-
-const MAX_FILE_SIZE_FOR_THUMNAIL_GENERATION: u64 = 50_000_000;
+use crate::{
+    storage_endpoint::{get_storage_endpoint, StorageEndpointRow},
+    util::RequestPool,
+};
 
 // Actix Multipart does not like us returning from a handler early, before the whole `Multipart` stream is consumed.
 // If we do that, the connection will be dropped and the server will panic. We definitely do not want that to happen...
@@ -56,6 +61,76 @@ struct QueryParams {
      * The folder where the files will reside. `null` == root.
      */
     target_folder: Option<i64>,
+
+    /**
+     * Replace the contents of files that already exist instead of skipping them.
+     * Previous contents are kept as versions if the endpoint has versioning enabled.
+     */
+    overwrite: Option<bool>,
+}
+
+/**
+ * Point an existing file to a freshly uploaded blob.
+ *
+ * Overwriting a file destroys its current contents (unless versioning is enabled), so that requires the right to delete it.
+ *
+ * @returns whether the file was overwritten
+ */
+async fn overwrite_existing_file(
+    target_endpoint: &StorageEndpointRow,
+    parent_folder_id: Option<i64>,
+    file_name: &str,
+    file_extension: Option<&str>,
+    new_blob: StorageEntryVersionBlob,
+    client_user_id: Option<i32>,
+    pool: &RequestPool,
+) -> bool {
+    if client_user_id.is_none() {
+        return false;
+    }
+
+    let client_user_id = client_user_id.unwrap();
+
+    let existing_file_id = sqlx::query_scalar::<_, i64>(
+        "SELECT id FROM storage_entries WHERE endpoint_id = $1 AND parent_folder IS NOT DISTINCT FROM $2 AND entry_type = 'file'::storage_entry_type AND name = $3 AND extension IS NOT DISTINCT FROM $4",
+    )
+    .bind(target_endpoint.id)
+    .bind(parent_folder_id)
+    .bind(file_name)
+    .bind(file_extension)
+    .fetch_optional(pool)
+    .await;
+
+    let existing_file_id = match existing_file_id {
+        Ok(Some(existing_file_id)) => existing_file_id,
+        _ => return false,
+    };
+
+    let user_groups = get_user_groups(pool, client_user_id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    if !check_storage_entry_access(
+        target_endpoint.id,
+        existing_file_id,
+        "delete",
+        client_user_id,
+        &group_ids,
+        pool,
+    )
+    .await
+    {
+        return false;
+    }
+
+    replace_entry_blob(
+        target_endpoint,
+        existing_file_id,
+        &new_blob,
+        Some(client_user_id),
+        pool,
+    )
+    .await
+    .is_ok()
 }
 
 #[post("/upload")]
@@ -71,6 +146,7 @@ async fn storage_upload(
 
     let target_folder_id = query.target_folder;
     let endpoint_id = query.endpoint_id;
+    let overwrite = query.overwrite.unwrap_or(false);
 
     // Check the rights
     let client = get_user_from_request(&pool, &req).await;
@@ -87,7 +163,7 @@ async fn storage_upload(
 
     // TODO @cleanup
     if let Some(target_folder_id) = target_folder_id {
-        action_allowed = if let Some((client_user, _)) = client {
+        action_allowed = if let Some((client_user, _)) = &client {
             let user_groups = get_user_groups(&**pool, client_user.id).await;
             let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
 
@@ -137,11 +213,32 @@ async fn storage_upload(
         return sink_and_error("storage.endpoint_not_active", &mut payload).await;
     }
 
-    let target_endpoint_base_path = Path::new(&target_endpoint.base_path);
+    let storage_backend = get_storage_backend(&target_endpoint, &pool).await;
+
+    if storage_backend.is_err() {
+        return sink_and_error("storage.endpoint_not_found", &mut payload).await;
+    }
+
+    let storage_backend = storage_backend.unwrap();
+
+    // Sizes of the files are not known in advance, so they are checked against the quota while being written
+    let mut quota_remaining_bytes = match client_user_id {
+        Some(client_user_id) => {
+            match get_user_storage_quota(&target_endpoint, client_user_id, &pool).await {
+                Ok(quota) => quota.remaining_bytes,
+                Err(_) => return sink_and_error("storage.internal", &mut payload).await,
+            }
+        }
+        None => None,
+    };
 
     let mut path_ids_cache: HashMap<String, i64> = HashMap::new();
     let mut skipped_files = Vec::<String>::new();
 
+    // New files and folders, they have to be put in place on endpoints that preserve the file structure
+    let mut created_entries = Vec::<i64>::new();
+    let mut overwritten_files = Vec::<String>::new();
+
     // Go through each entry in the multipart request.
     // Each entry is a file.
     while let Some(item) = payload.next().await {
@@ -150,7 +247,7 @@ async fn storage_upload(
             let full_filename = field.name().to_string();
 
             if let Some(file_relative_path) = file_relative_path {
-                let file_filesystem_id = Uuid::new_v4().to_string();
+                let staged_filesystem_id = Uuid::new_v4().to_string();
                 let mut file_relative_path = String::from(file_relative_path);
 
                 let name_separator = full_filename.rfind('.').unwrap_or(full_filename.len());
@@ -280,6 +377,7 @@ async fn storage_upload(
                                     match create_folder_result {
                                         Ok(id) => {
                                             folder_id = Some(id);
+                                            created_entries.push(id);
 
                                             let path_to_cache = path_so_far.join("/");
                                             path_ids_cache.insert(path_to_cache, id);
@@ -303,13 +401,14 @@ async fn storage_upload(
                 // Now that we have determined what the `parent_folder` for this file should be,
                 // let's actually write it onto the filesystem and create a new row in the databse for it.
 
-                // 1. Write the file onto the filesystem
-                let path = target_endpoint_base_path.join(&file_filesystem_id);
+                // 1. Write the file onto the filesystem (or into the staging folder, if the endpoint keeps its files elsewhere)
+                let path = storage_backend.staging_path(&staged_filesystem_id);
                 let file = OpenOptions::new().write(true).create_new(true).open(&path);
 
                 if let Ok(mut file) = file {
                     let mut file_kind: Option<infer::Type> = None;
                     let mut file_size_bytes: i64 = 0;
+                    let mut file_hasher = Sha256::new();
 
                     let mut first_chunk = true;
 
@@ -325,6 +424,19 @@ async fn storage_upload(
                             }
 
                             file_size_bytes += chunk.len() as i64;
+                            file_hasher.update(&chunk);
+
+                            if quota_remaining_bytes
+                                .is_some_and(|remaining_bytes| file_size_bytes > remaining_bytes)
+                            {
+                                fs::remove_file(&path).unwrap_or(());
+
+                                // The rest of the request can not be read while the current field is still alive
+                                drop(field);
+
+                                return sink_and_error("storage.quota_exceeded", &mut payload)
+                                    .await;
+                            }
                             let res = file.write(&chunk);
 
                             if res.is_err() {
@@ -339,6 +451,33 @@ async fn storage_upload(
                         }
                     }
 
+                    drop(file);
+
+                    let file_sha256 = finalize_blob_hash(file_hasher);
+
+                    // If the same contents are already stored on the endpoint, the file might end up reusing that blob
+                    let stored_blob = store_staged_blob(
+                        &target_endpoint,
+                        storage_backend.as_ref(),
+                        &staged_filesystem_id,
+                        &file_sha256,
+                        file_size_bytes,
+                        &pool,
+                    )
+                    .await;
+
+                    if stored_blob.is_err() {
+                        fs::remove_file(&path).unwrap_or(());
+
+                        return sink_and_error("storage.internal", &mut payload).await;
+                    }
+
+                    let stored_blob = stored_blob.unwrap();
+                    let file_filesystem_id = stored_blob.filesystem_id.clone();
+
+                    // Artifacts of a reused blob already exist
+                    let is_new_blob = file_filesystem_id == staged_filesystem_id;
+
                     // 2. Create a new row in the database
                     let file_mime_type = if file_kind.is_some() {
                         Some(file_kind.unwrap().mime_type())
@@ -346,7 +485,7 @@ async fn storage_upload(
                         None
                     };
 
-                    let create_file_result = sqlx::query("INSERT INTO storage_entries (endpoint_id, filesystem_id, parent_folder, name, extension, mime_type, size_bytes, created_by, created_at, entry_type) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, now(), 'file'::storage_entry_type)")
+                    let create_file_result = sqlx::query_scalar::<_, i64>("INSERT INTO storage_entries (endpoint_id, filesystem_id, parent_folder, name, extension, mime_type, size_bytes, sha256, created_by, created_at, entry_type) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now(), 'file'::storage_entry_type) RETURNING id")
                     .bind(endpoint_id)
                     .bind(&file_filesystem_id)
                     .bind(parent_folder_id)
@@ -354,24 +493,77 @@ async fn storage_upload(
                     .bind(file_extension)
                     .bind(file_mime_type)
                     .bind(file_size_bytes)
+                    .bind(&file_sha256)
                     .bind(client_user_id)
-                    .execute(&**pool).await;
+                    .fetch_one(&**pool).await;
 
                     if create_file_result.is_err() {
                         // The most likely scenario for an error here is that the
                         // file with the same filename in the same folder already exists.
-                        // This is not critical, so we will just delete the file we just wrote and
-                        // continue on to the next file in the request.
+                        // Unless the user wants to overwrite existing files, this is not critical,
+                        // so we will just delete the file we just wrote and continue on to the next file in the request.
 
                         // TODO: it's kind of dumb to upload a file only to delete it later if it already exists...
 
-                        skipped_files.push(full_filename);
+                        let overwritten = if overwrite {
+                            overwrite_existing_file(
+                                &target_endpoint,
+                                parent_folder_id,
+                                file_name,
+                                file_extension,
+                                StorageEntryVersionBlob {
+                                    filesystem_id: file_filesystem_id.clone(),
+                                    mime_type: file_mime_type
+                                        .map(|mime_type| mime_type.to_string()),
+                                    size_bytes: Some(file_size_bytes),
+                                    sha256: Some(file_sha256.clone()),
+                                },
+                                client_user_id,
+                                &pool,
+                            )
+                            .await
+                        } else {
+                            false
+                        };
+
+                        release_stored_blob(&stored_blob, &pool).await;
 
-                        warn!("{}", create_file_result.unwrap_err());
+                        if overwritten {
+                            overwritten_files.push(full_filename);
 
-                        fs::remove_file(&path).unwrap_or(());
+                            if let Some(remaining_bytes) = quota_remaining_bytes.as_mut() {
+                                *remaining_bytes -= file_size_bytes;
+                            }
+
+                            if is_new_blob {
+                                uploaded_files.push(file_filesystem_id.clone());
+                            }
+                        } else {
+                            skipped_files.push(full_filename);
+
+                            warn!("{}", create_file_result.unwrap_err());
+
+                            // A reused blob belongs to other files, it is kept
+                            delete_storage_blobs(
+                                &target_endpoint,
+                                &vec![file_filesystem_id.clone()],
+                                &pool,
+                            )
+                            .await
+                            .unwrap_or(());
+                        }
                     } else {
-                        uploaded_files.push(file_filesystem_id.clone());
+                        release_stored_blob(&stored_blob, &pool).await;
+
+                        created_entries.push(create_file_result.unwrap());
+
+                        if let Some(remaining_bytes) = quota_remaining_bytes.as_mut() {
+                            *remaining_bytes -= file_size_bytes;
+                        }
+
+                        if is_new_blob {
+                            uploaded_files.push(file_filesystem_id.clone());
+                        }
                     }
                 } else {
                     // For some reason, we could not write the file onto the filesystem.
@@ -399,210 +591,34 @@ async fn storage_upload(
 
     folders_to_update.push(target_folder_id);
 
-    // TODO no.
-    let ws_state2 = ws_state.clone();
-    let folders_to_update2 = folders_to_update.clone();
-
-    // Generate thumbnails
-    std::thread::spawn(move || {
-        let storage_config = block_on(get_config(&**pool));
-
-        let generate_image_thumbnails =
-            storage_config.get("storage.generate_thumbnails.image") == Some(&"true".to_string());
-
-        let generate_video_thumbnails =
-            storage_config.get("storage.generate_thumbnails.video") == Some(&"true".to_string());
-
-        let generate_audio_thumbnails =
-            storage_config.get("storage.generate_thumbnails.audio") == Some(&"true".to_string());
-
-        let generate_seeking_thumbnails = storage_config
-            .get("storage.generate_seeking_thumbnails.enabled")
-            == Some(&"true".to_string());
-
-        let seeking_thumbnails_frames_count = storage_config
-            .get("storage.generate_seeking_thumbnails.desired_frames")
-            .unwrap_or(&"10".to_string())
-            .parse::<u32>()
-            .unwrap_or(10);
-
-        if let Some(target_endpoint_artifacts_path) = &target_endpoint.artifacts_path {
-            for filesystem_id in &uploaded_files {
-                let path = Path::new(&target_endpoint.base_path).join(&filesystem_id);
-
-                let file_kind = infer::get_from_path(&path);
-
-                if !file_kind.is_err() {
-                    if let Some(file_kind) = file_kind.unwrap() {
-                        let mime_type = file_kind.mime_type();
-
-                        match mime_type {
-                            "image/jpeg" | "image/png" | "image/gif" | "image/webp"
-                            | "image/bmp" => {
-                                if generate_image_thumbnails {
-                                    let file_metadata =
-                                        fs::File::open(&path).unwrap().metadata().unwrap();
-
-                                    if file_metadata.len() <= MAX_FILE_SIZE_FOR_THUMNAIL_GENERATION
-                                    {
-                                        let generate_thumbnail_result =
-                                            generate_image_entry_thumbnail(
-                                                &filesystem_id,
-                                                &target_endpoint.base_path.as_str(),
-                                                &target_endpoint_artifacts_path.as_str(),
-                                            );
-
-                                        if generate_thumbnail_result.is_err() {
-                                            error!(
-                                            "Failed to create a thumbnail for an uploaded image file ({})",
-                                            &filesystem_id
-                                        );
-                                        }
-                                    }
-                                }
-                            }
-
-                            "video/mp4" | "video/webm" | "video/mov" | "video/avi"
-                            | "video/mpeg" | "video/quicktime" | "video/x-msvideo" => {
-                                if generate_video_thumbnails {
-                                    let generate_thumbnail_result = generate_video_entry_thumbnails(
-                                        &filesystem_id,
-                                        &target_endpoint.base_path.as_str(),
-                                        &target_endpoint_artifacts_path.as_str(),
-                                        if generate_seeking_thumbnails {
-                                            Some(seeking_thumbnails_frames_count)
-                                        } else {
-                                            None
-                                        },
-                                    );
-
-                                    if generate_thumbnail_result.is_err() {
-                                        error!(
-                                        "Failed to create a thumbnail for an uploaded video file ({})",
-                                        &filesystem_id
-                                    );
-                                    }
-                                }
-                            }
-
-                            "audio/mpeg" | "audio/x-flac" | "audio/x-wav" | "audio/aac" => {
-                                if generate_audio_thumbnails {
-                                    let generate_thumbnail_result =
-                                        generate_audio_entry_cover_thumbnail(
-                                            &filesystem_id,
-                                            &target_endpoint.base_path.as_str(),
-                                            &target_endpoint_artifacts_path.as_str(),
-                                        );
-
-                                    if generate_thumbnail_result.is_err() {
-                                        error!(
-                                            "Failed to create a cover image thumbnail for an uploaded audio file ({})",
-                                            &filesystem_id
-                                        );
-                                    }
-                                }
-                            }
-
-                            _ => {}
-                        }
-                    }
-                }
-            }
-
-            let transcoding_enabled =
-                storage_config.get("storage.transcode_videos.enabled") == Some(&"true".to_string());
-
-            if transcoding_enabled {
-                let target_height = storage_config
-                    .get("storage.transcode_videos.target_height")
-                    .unwrap_or(&"720".to_string())
-                    .parse::<u32>()
-                    .unwrap_or(720);
-
-                let target_bitrate = storage_config
-                    .get("storage.transcode_videos.target_bitrate")
-                    .unwrap_or(&"4000".to_string())
-                    .parse::<u32>()
-                    .unwrap_or(4000);
-
-                let mut files_to_transcode: Vec<String> = Vec::new();
-
-                for filesystem_id in &uploaded_files {
-                    let path = Path::new(&target_endpoint.base_path).join(&filesystem_id);
-
-                    let file_kind = infer::get_from_path(&path);
-
-                    if !file_kind.is_err() {
-                        if let Some(file_kind) = file_kind.unwrap() {
-                            let mime_type = file_kind.mime_type();
-
-                            match mime_type {
-                                "video/mp4" | "video/webm" | "video/mov" | "video/avi"
-                                | "video/mpeg" | "video/quicktime" | "video/x-msvideo" => {
-                                    files_to_transcode.push(filesystem_id.clone());
-                                }
-
-                                _ => {}
-                            }
-                        }
-                    }
-                }
+    sync_endpoint_file_structure(endpoint_id, &created_entries, &pool).await;
+
+    if !created_entries.is_empty() || !overwritten_files.is_empty() {
+        write_audit_log(
+            &pool,
+            &req,
+            client.as_ref().map(AuditActor::from_client),
+            AuditEvent {
+                action: "storage.upload",
+                target_type: "storage_entry",
+                target_ids: target_folder_id
+                    .map(|folder_id| folder_id.to_string())
+                    .into_iter()
+                    .collect(),
+                endpoint_id: Some(endpoint_id),
+                after: Some(json!({
+                    "created_entry_ids": created_entries,
+                    "overwritten_files": overwritten_files,
+                    "skipped_files": skipped_files,
+                })),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
 
-                let _ = block_on(sqlx::query("UPDATE storage_entries SET transcoded_version_available = FALSE WHERE endpoint_id = $1 AND filesystem_id = ANY($2)")
-                    .bind(endpoint_id)
-                    .bind(&files_to_transcode)
-                    .execute(&**pool));
-
-                // Refresh folder when thumbnails are ready
-                let _ = block_on(ws_state2.lock().unwrap().send_storage_location_updated(
-                    None,
-                    endpoint_id,
-                    // TODO stop cloning stuff...
-                    folders_to_update2.clone(),
-                    if files_to_transcode.is_empty() {
-                        false
-                    } else {
-                        true
-                    },
-                    true,
-                ));
-
-                for filesystem_id in &files_to_transcode {
-                    let generate_preview_result = generate_browser_friendly_video(
-                        &filesystem_id,
-                        &target_endpoint.base_path.as_str(),
-                        &target_endpoint_artifacts_path.as_str(),
-                        target_height,
-                        target_bitrate,
-                    );
-
-                    if generate_preview_result.is_ok() {
-                        let parent_folder = block_on(sqlx::query_scalar::<_, Option<i64>>("UPDATE storage_entries SET transcoded_version_available = TRUE WHERE endpoint_id = $1 AND filesystem_id = $2 RETURNING parent_folder")
-                            .bind(endpoint_id)
-                            .bind(filesystem_id)
-                            .fetch_one(&**pool));
-
-                        if let Ok(parent_folder) = parent_folder {
-                            // Refresh folder when a video is ready
-                            let _ =
-                                block_on(ws_state2.lock().unwrap().send_storage_location_updated(
-                                    None,
-                                    endpoint_id,
-                                    vec![parent_folder],
-                                    true,
-                                    false,
-                                ));
-                        }
-                    } else {
-                        error!(
-                            "Failed to create a browser friendly preview for an uploaded video file ({})",
-                            &filesystem_id,
-                        );
-                    }
-                }
-            }
-        }
-    });
+    // Generate thumbnails and browser friendly videos in the background
+    queue_uploaded_files_artifacts(&target_endpoint, uploaded_files, client_user_id, &pool).await;
 
     // Refresh folder after successful upload
     // TODO don't block the request
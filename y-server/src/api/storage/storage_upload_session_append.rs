@@ -0,0 +1,242 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+
+use actix_web::http::StatusCode;
+use actix_web::{patch, web, Responder};
+use futures::StreamExt;
+use log::*;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::audit_log::{write_audit_log, AuditActor, AuditEvent};
+use crate::request::error;
+use crate::storage_artifacts::queue_uploaded_files_artifacts;
+use crate::storage_uploads::{
+    check_upload_access, delete_upload_session, finalize_upload_session, get_upload_session,
+    get_upload_session_staging_path, try_lock_upload_session, tus_response, TUS_VERSION,
+};
+use crate::user::get_user_from_request;
+use crate::util::RequestPool;
+use crate::ws::{send_storage_location_updated, WSState};
+
+/**
+ * Append a chunk of bytes to an upload.
+ *
+ * Once the last byte is received, the file is moved into the endpoint and a new storage entry is created.
+ */
+#[patch("/uploads/{session_id}")]
+async fn storage_upload_session_append(
+    pool: web::Data<RequestPool>,
+    ws_state: web::Data<Mutex<WSState>>,
+    path: web::Path<String>,
+    mut payload: web::Payload,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let session_id = Uuid::parse_str(path.into_inner().as_str());
+
+    if session_id.is_err() {
+        return tus_response(StatusCode::NOT_FOUND).finish();
+    }
+
+    let session_id = session_id.unwrap();
+
+    let tus_resumable = req
+        .headers()
+        .get("Tus-Resumable")
+        .and_then(|value| value.to_str().ok());
+
+    if tus_resumable != Some(TUS_VERSION) {
+        return tus_response(StatusCode::PRECONDITION_FAILED)
+            .insert_header(("Tus-Version", TUS_VERSION))
+            .finish();
+    }
+
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok());
+
+    if content_type != Some("application/offset+octet-stream") {
+        return tus_response(StatusCode::UNSUPPORTED_MEDIA_TYPE).finish();
+    }
+
+    let client = get_user_from_request(&pool, &req).await;
+
+    if client.is_none() {
+        return tus_response(StatusCode::FORBIDDEN).finish();
+    }
+
+    let client = client.unwrap();
+    let (client_user, _) = &client;
+
+    // Held until the response is ready. Two requests with the same offset would otherwise both append their bytes.
+    let session_lock = try_lock_upload_session(&session_id);
+
+    if session_lock.is_none() {
+        return tus_response(StatusCode::CONFLICT).finish();
+    }
+
+    let _session_lock = session_lock.unwrap();
+
+    let session = get_upload_session(&session_id, client_user.id, &pool).await;
+
+    if session.is_err() {
+        return tus_response(StatusCode::NOT_FOUND).finish();
+    }
+
+    let mut session = session.unwrap();
+
+    let upload_offset = req
+        .headers()
+        .get("Upload-Offset")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<i64>().ok());
+
+    if upload_offset != Some(session.received_bytes) {
+        return tus_response(StatusCode::CONFLICT).finish();
+    }
+
+    // 1. Append the bytes to the staging file
+    let staging_file = OpenOptions::new()
+        .append(true)
+        .open(get_upload_session_staging_path(&session_id));
+
+    if staging_file.is_err() {
+        return tus_response(StatusCode::NOT_FOUND).finish();
+    }
+
+    let mut staging_file = staging_file.unwrap();
+
+    // The previous request might have been interrupted after some bytes were written, but before we were able
+    // to record them. Throw away everything we don't know about.
+    if staging_file.set_len(session.received_bytes as u64).is_err() {
+        return error("storage.internal");
+    }
+
+    let mut append_error: Option<StatusCode> = None;
+    let mut received_bytes = session.received_bytes;
+
+    while let Some(chunk) = payload.next().await {
+        if let Ok(chunk) = chunk {
+            if received_bytes + chunk.len() as i64 > session.size_bytes {
+                append_error = Some(StatusCode::PAYLOAD_TOO_LARGE);
+                break;
+            }
+
+            if staging_file.write_all(&chunk).is_err() {
+                append_error = Some(StatusCode::INTERNAL_SERVER_ERROR);
+                break;
+            }
+
+            received_bytes += chunk.len() as i64;
+        } else {
+            // The connection was most likely dropped. Save what we have, the client will resume from here
+            append_error = Some(StatusCode::BAD_REQUEST);
+            break;
+        }
+    }
+
+    if staging_file.flush().is_err() {
+        return error("storage.internal");
+    }
+
+    // 2. Record how many bytes we have received so far
+    let update_result =
+        sqlx::query("UPDATE storage_upload_sessions SET received_bytes = $1 WHERE id = $2")
+            .bind(received_bytes)
+            .bind(session_id)
+            .execute(&**pool)
+            .await;
+
+    if update_result.is_err() {
+        return error("storage.internal");
+    }
+
+    session.received_bytes = received_bytes;
+
+    if let Some(append_error) = append_error {
+        return tus_response(append_error)
+            .insert_header(("Upload-Offset", received_bytes.to_string()))
+            .finish();
+    }
+
+    // 3. Once we have the whole file, turn it into a storage entry
+    if session.received_bytes == session.size_bytes {
+        // Access rules might have changed while the file was being uploaded
+        if !check_upload_access(
+            session.endpoint_id,
+            session.target_folder,
+            client_user.id,
+            &pool,
+        )
+        .await
+        {
+            delete_upload_session(&session_id, &pool).await;
+
+            return error("storage.access_denied");
+        }
+
+        match finalize_upload_session(&session, &pool).await {
+            Ok((file_filesystem_id, target_endpoint)) => {
+                let endpoint_id = target_endpoint.id;
+
+                write_audit_log(
+                    &pool,
+                    &req,
+                    Some(AuditActor::from_client(&client)),
+                    AuditEvent {
+                        action: "storage.upload",
+                        target_type: "storage_entry",
+                        target_ids: session
+                            .target_folder
+                            .map(|folder_id| folder_id.to_string())
+                            .into_iter()
+                            .collect(),
+                        endpoint_id: Some(endpoint_id),
+                        after: Some(json!({
+                            "upload_session_id": session.id.to_string(),
+                            "name": session.name,
+                            "extension": session.extension,
+                            "size_bytes": session.size_bytes,
+                        })),
+                        ..Default::default()
+                    },
+                )
+                .await;
+
+                // Generate thumbnails and browser friendly videos in the background
+                queue_uploaded_files_artifacts(
+                    &target_endpoint,
+                    vec![file_filesystem_id],
+                    Some(client_user.id),
+                    &pool,
+                )
+                .await;
+
+                // TODO don't block the request
+                send_storage_location_updated(
+                    &ws_state,
+                    Some(client_user.id),
+                    endpoint_id,
+                    vec![session.target_folder],
+                    true,
+                    false,
+                )
+                .await;
+            }
+            Err(err) => {
+                warn!(
+                    "(storage upload session append) Could not finalize an upload session ({})",
+                    session_id
+                );
+
+                return error(err.get_code());
+            }
+        }
+    }
+
+    tus_response(StatusCode::NO_CONTENT)
+        .insert_header(("Upload-Offset", received_bytes.to_string()))
+        .finish()
+}
@@ -0,0 +1,46 @@
+use actix_web::http::StatusCode;
+use actix_web::{head, web, Responder};
+use uuid::Uuid;
+
+use crate::storage_uploads::{get_upload_session, tus_response};
+use crate::user::get_user_from_request;
+use crate::util::RequestPool;
+
+/// Tell the client how many bytes of the file we have received so far, so it knows where to resume from
+#[head("/uploads/{session_id}")]
+async fn storage_upload_session_offset(
+    pool: web::Data<RequestPool>,
+    path: web::Path<String>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let session_id = Uuid::parse_str(path.into_inner().as_str());
+
+    if session_id.is_err() {
+        return tus_response(StatusCode::NOT_FOUND).finish();
+    }
+
+    let session_id = session_id.unwrap();
+
+    let client = get_user_from_request(&pool, &req).await;
+
+    if client.is_none() {
+        return tus_response(StatusCode::FORBIDDEN).finish();
+    }
+
+    let (client_user, _) = client.unwrap();
+
+    let session = get_upload_session(&session_id, client_user.id, &pool).await;
+
+    if session.is_err() {
+        return tus_response(StatusCode::NOT_FOUND).finish();
+    }
+
+    let session = session.unwrap();
+
+    tus_response(StatusCode::OK)
+        .insert_header(("Upload-Offset", session.received_bytes.to_string()))
+        .insert_header(("Upload-Length", session.size_bytes.to_string()))
+        .insert_header(("Upload-Expires", session.expires_on.to_rfc2822()))
+        .insert_header(("Cache-Control", "no-store"))
+        .finish()
+}
@@ -0,0 +1,12 @@
+use actix_web::{route, HttpResponse, Responder};
+
+use crate::storage_uploads::{TUS_EXTENSIONS, TUS_VERSION};
+
+#[route("/uploads", method = "OPTIONS")]
+async fn storage_upload_sessions_options() -> impl Responder {
+    HttpResponse::NoContent()
+        .insert_header(("Tus-Resumable", TUS_VERSION))
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", TUS_EXTENSIONS))
+        .finish()
+}
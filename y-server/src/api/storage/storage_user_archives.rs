@@ -10,10 +10,25 @@ struct StorageUserArchiveRow {
     endpoint_id: i32,
     target_entries_ids: Vec<i64>,
 
+    /**
+     * queued, running, failed or ready
+     */
+    status: String,
     ready: bool,
     size_bytes: Option<i64>,
 
+    /**
+     * Why the archive could not be written
+     */
+    error: Option<String>,
+
+    total_files: Option<i32>,
+    processed_files: i32,
+    total_bytes: Option<i64>,
+    processed_bytes: i64,
+
     created_at: String,
+    finished_at: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -30,7 +45,9 @@ async fn storage_user_archives(
 
     if let Some((user, _)) = user {
         let user_archives = sqlx::query_as::<_, StorageUserArchiveRow>(
-            "SELECT id, endpoint_id, target_entries_ids, ready, size_bytes, created_at::TEXT FROM storage_archives WHERE created_by = $1",
+            "SELECT id, endpoint_id, target_entries_ids, status::TEXT, status = 'ready'::storage_archive_status AS ready, size_bytes, error,
+            total_files, processed_files, total_bytes, processed_bytes, created_at::TEXT, finished_at::TEXT
+            FROM storage_archives WHERE created_by = $1",
         )
         .bind(user.id)
         .fetch_all(&**pool)
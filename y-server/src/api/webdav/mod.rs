@@ -0,0 +1,8 @@
+pub mod webdav_copy;
+pub mod webdav_delete;
+pub mod webdav_get;
+pub mod webdav_mkcol;
+pub mod webdav_move;
+pub mod webdav_options;
+pub mod webdav_propfind;
+pub mod webdav_put;
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use actix_web::http::StatusCode;
+use actix_web::{route, web, Responder};
+
+use crate::audit_log::{write_audit_log, AuditEvent};
+use crate::storage_access::check_bulk_storage_entries_access_cascade_up;
+use crate::storage_copy::{copy_entries, StorageCopyTarget};
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::{get_subfolders_level_with_access_rules, StorageError};
+use crate::storage_quotas::{check_storage_quota, get_entries_total_size};
+use crate::util::RequestPool;
+use crate::webdav::{
+    webdav_finish_replace, webdav_get_client, webdav_prepare_destination, webdav_resolve_path,
+    webdav_status, webdav_unauthorized, WebDAVResource,
+};
+use crate::ws::{send_storage_location_updated, WSState};
+
+#[route("/{endpoint_id}/{path:.*}", method = "COPY")]
+async fn webdav_copy(
+    pool: web::Data<RequestPool>,
+    ws_state: web::Data<Mutex<WSState>>,
+    path: web::Path<(i32, String)>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, path) = path.into_inner();
+
+    let client = webdav_get_client(&pool, &req).await;
+
+    if client.is_none() {
+        return webdav_unauthorized();
+    }
+
+    let client = client.unwrap();
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return webdav_status(StatusCode::NOT_FOUND);
+    }
+
+    let target_endpoint = target_endpoint.unwrap();
+
+    if target_endpoint.status != "active" {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    let entry = match webdav_resolve_path(endpoint_id, &path, &pool).await {
+        Ok(WebDAVResource::Entry(entry)) => entry,
+        Ok(WebDAVResource::Root) => return webdav_status(StatusCode::FORBIDDEN),
+        Ok(_) => return webdav_status(StatusCode::NOT_FOUND),
+        Err(_) => return webdav_status(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    // `Depth: 0` copies only the folder itself, without its contents
+    let recursive = req
+        .headers()
+        .get("Depth")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value != "0")
+        .unwrap_or(true);
+
+    // Copying an entry is essentially downloading it and uploading it somewhere else
+    let download_allowed = check_bulk_storage_entries_access_cascade_up(
+        endpoint_id,
+        &vec![entry.id],
+        "download",
+        client.user_id,
+        &client.group_ids,
+        &pool,
+    )
+    .await;
+
+    if !download_allowed {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    if entry.is_folder() && recursive {
+        let mut folder_parents: HashMap<i64, Option<i64>> = HashMap::from([(entry.id, None)]);
+        let mut filesystem_ids: Vec<String> = Vec::new();
+
+        let cascade_down_result = get_subfolders_level_with_access_rules(
+            endpoint_id,
+            &mut folder_parents,
+            &mut filesystem_ids,
+            vec![entry.id],
+            (client.user_id, &client.group_ids),
+            "download",
+            &pool,
+        )
+        .await;
+
+        match cascade_down_result {
+            Ok(_) => {}
+            Err(StorageError::AccessDenied) => return webdav_status(StatusCode::FORBIDDEN),
+            Err(_) => return webdav_status(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    // Copies count towards the quota of whoever makes them
+    let copy_size_bytes = if entry.is_folder() && recursive {
+        get_entries_total_size(endpoint_id, &vec![entry.id], &pool).await
+    } else {
+        Ok(entry.size_bytes.unwrap_or(0))
+    };
+
+    if copy_size_bytes.is_err() {
+        return webdav_status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    if check_storage_quota(
+        &target_endpoint,
+        Some(client.user_id),
+        copy_size_bytes.unwrap(),
+        &pool,
+    )
+    .await
+    .is_err()
+    {
+        return webdav_status(StatusCode::INSUFFICIENT_STORAGE);
+    }
+
+    let (target_folder, new_full_name, replaced) =
+        match webdav_prepare_destination(endpoint_id, &path, None, &client, &req, &pool).await {
+            Ok(destination) => destination,
+            Err(status) => return webdav_status(status),
+        };
+
+    let target = StorageCopyTarget {
+        endpoint: &target_endpoint,
+        folder: target_folder,
+        user_id: Some(client.user_id),
+        name: Some(new_full_name.as_str()),
+        recursive,
+    };
+
+    // Everything that was copied is removed again if the copy fails halfway through
+    let copy_result = copy_entries(&target_endpoint, &[entry.id], &target, None, &pool)
+        .await
+        .and_then(|copy_ids| copy_ids.first().copied().ok_or(StorageError::Internal));
+
+    let overwritten = replaced.is_some();
+
+    webdav_finish_replace(endpoint_id, replaced, copy_result.is_ok(), &client, &pool).await;
+
+    match copy_result {
+        Ok(copy_id) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(client.audit_actor()),
+                AuditEvent {
+                    action: "storage.copy",
+                    target_type: "storage_entry",
+                    target_ids: vec![entry.id.to_string()],
+                    endpoint_id: Some(endpoint_id),
+                    after: Some(serde_json::json!({
+                        "copy_id": copy_id,
+                        "target_folder": target_folder,
+                        "name": new_full_name,
+                        "overwritten": overwritten,
+                    })),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            // TODO don't block the request
+            send_storage_location_updated(
+                &ws_state,
+                Some(client.user_id),
+                endpoint_id,
+                vec![target_folder],
+                true,
+                false,
+            )
+            .await;
+
+            if overwritten {
+                webdav_status(StatusCode::NO_CONTENT)
+            } else {
+                webdav_status(StatusCode::CREATED)
+            }
+        }
+        Err(StorageError::NameConflict) => webdav_status(StatusCode::CONFLICT),
+        Err(_) => webdav_status(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
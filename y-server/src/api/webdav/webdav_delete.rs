@@ -0,0 +1,115 @@
+use std::sync::Mutex;
+
+use actix_web::http::StatusCode;
+use actix_web::{delete, web, Responder};
+
+use crate::audit_log::{get_entries_audit_snapshot, write_audit_log, AuditEvent};
+use crate::storage_access::check_bulk_storage_entries_access_cascade_up;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::{delete_entries, StorageError};
+use crate::util::RequestPool;
+use crate::webdav::{
+    webdav_get_client, webdav_resolve_path, webdav_status, webdav_unauthorized, WebDAVResource,
+};
+use crate::ws::{send_storage_location_updated, WSState};
+
+#[delete("/{endpoint_id}/{path:.*}")]
+async fn webdav_delete(
+    pool: web::Data<RequestPool>,
+    ws_state: web::Data<Mutex<WSState>>,
+    path: web::Path<(i32, String)>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, path) = path.into_inner();
+
+    let client = webdav_get_client(&pool, &req).await;
+
+    if client.is_none() {
+        return webdav_unauthorized();
+    }
+
+    let client = client.unwrap();
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return webdav_status(StatusCode::NOT_FOUND);
+    }
+
+    if target_endpoint.unwrap().status != "active" {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    let entry = match webdav_resolve_path(endpoint_id, &path, &pool).await {
+        Ok(WebDAVResource::Entry(entry)) => entry,
+        Ok(WebDAVResource::Root) => return webdav_status(StatusCode::FORBIDDEN),
+        Ok(_) => return webdav_status(StatusCode::NOT_FOUND),
+        Err(_) => return webdav_status(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    // Cascade up check for the target entry. Cascade down check is performed inside of `delete_entries`
+    let action_allowed_cascade_up = check_bulk_storage_entries_access_cascade_up(
+        endpoint_id,
+        &vec![entry.id],
+        "delete",
+        client.user_id,
+        &client.group_ids,
+        &pool,
+    )
+    .await;
+
+    if !action_allowed_cascade_up {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    let (target_folders, target_files) = if entry.is_folder() {
+        (vec![entry.id], vec![])
+    } else {
+        (vec![], vec![entry.id])
+    };
+
+    let audit_before = get_entries_audit_snapshot(endpoint_id, &vec![entry.id], &pool).await;
+
+    let delete_result = delete_entries(
+        endpoint_id,
+        target_folders,
+        target_files,
+        Some((client.user_id, &client.group_ids)),
+        &pool,
+    )
+    .await;
+
+    match delete_result {
+        Ok(_) => {
+            write_audit_log(
+                &pool,
+                &req,
+                Some(client.audit_actor()),
+                AuditEvent {
+                    action: "storage.delete",
+                    target_type: "storage_entry",
+                    target_ids: vec![entry.id.to_string()],
+                    endpoint_id: Some(endpoint_id),
+                    before: audit_before,
+                    after: Some(serde_json::json!({ "webdav_path": path })),
+                },
+            )
+            .await;
+
+            // TODO don't block the request
+            send_storage_location_updated(
+                &ws_state,
+                Some(client.user_id),
+                endpoint_id,
+                vec![entry.parent_folder],
+                true,
+                false,
+            )
+            .await;
+
+            webdav_status(StatusCode::NO_CONTENT)
+        }
+        Err(StorageError::AccessDenied) => webdav_status(StatusCode::FORBIDDEN),
+        Err(_) => webdav_status(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
@@ -0,0 +1,91 @@
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{route, web, Responder};
+
+use crate::storage_backend::get_storage_backend;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::util::RequestPool;
+use crate::webdav::{
+    webdav_check_access, webdav_get_client, webdav_resolve_path, webdav_status,
+    webdav_unauthorized, WebDAVResource,
+};
+
+#[route("/{endpoint_id}/{path:.*}", method = "GET", method = "HEAD")]
+async fn webdav_get(
+    pool: web::Data<RequestPool>,
+    path: web::Path<(i32, String)>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, path) = path.into_inner();
+
+    let client = webdav_get_client(&pool, &req).await;
+
+    if client.is_none() {
+        return webdav_unauthorized();
+    }
+
+    let client = client.unwrap();
+
+    let entry = match webdav_resolve_path(endpoint_id, &path, &pool).await {
+        Ok(WebDAVResource::Entry(entry)) => entry,
+        Ok(WebDAVResource::Root) => return webdav_status(StatusCode::METHOD_NOT_ALLOWED),
+        Ok(_) => return webdav_status(StatusCode::NOT_FOUND),
+        Err(_) => return webdav_status(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    if entry.is_folder() {
+        return webdav_status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    if !webdav_check_access(endpoint_id, Some(entry.id), "download", &client, &pool).await {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return webdav_status(StatusCode::NOT_FOUND);
+    }
+
+    let target_endpoint = target_endpoint.unwrap();
+
+    if target_endpoint.status == "disabled" {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    let storage_backend = get_storage_backend(&target_endpoint, &pool).await;
+
+    if storage_backend.is_err() {
+        return webdav_status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let res = storage_backend
+        .unwrap()
+        .serve(entry.filesystem_id.as_deref().unwrap_or(""), &req)
+        .await;
+
+    if res.is_err() {
+        return webdav_status(StatusCode::NOT_FOUND);
+    }
+
+    if req.method() == Method::GET {
+        // TODO don't block
+        let _ = sqlx::query(
+            "UPDATE storage_entries SET downloads_count = downloads_count + 1 WHERE id = $1 AND endpoint_id = $2",
+        )
+        .bind(entry.id)
+        .bind(endpoint_id)
+        .execute(&**pool)
+        .await;
+    }
+
+    let mut res = res.unwrap();
+
+    if let Some(mime_type) = &entry.mime_type {
+        if let Ok(mime_type) = HeaderValue::from_str(mime_type) {
+            res.headers_mut().insert(header::CONTENT_TYPE, mime_type);
+        }
+    }
+
+    res
+}
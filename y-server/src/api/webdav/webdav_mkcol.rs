@@ -0,0 +1,106 @@
+use std::sync::Mutex;
+
+use actix_web::http::StatusCode;
+use actix_web::{route, web, Responder};
+
+use crate::audit_log::{get_entries_audit_snapshot, write_audit_log, AuditEvent};
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_layout::sync_endpoint_file_structure;
+use crate::util::RequestPool;
+use crate::webdav::{
+    webdav_check_access, webdav_get_client, webdav_resolve_path, webdav_status,
+    webdav_unauthorized, WebDAVResource,
+};
+use crate::ws::{send_storage_location_updated, WSState};
+
+#[route("/{endpoint_id}/{path:.*}", method = "MKCOL")]
+async fn webdav_mkcol(
+    pool: web::Data<RequestPool>,
+    ws_state: web::Data<Mutex<WSState>>,
+    path: web::Path<(i32, String)>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, path) = path.into_inner();
+
+    let client = webdav_get_client(&pool, &req).await;
+
+    if client.is_none() {
+        return webdav_unauthorized();
+    }
+
+    let client = client.unwrap();
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return webdav_status(StatusCode::NOT_FOUND);
+    }
+
+    if target_endpoint.unwrap().status != "active" {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    let (parent_folder, name) = match webdav_resolve_path(endpoint_id, &path, &pool).await {
+        Ok(WebDAVResource::Missing {
+            parent_folder,
+            name,
+        }) => (parent_folder, name),
+        Ok(WebDAVResource::MissingParent) => return webdav_status(StatusCode::CONFLICT),
+        Ok(_) => return webdav_status(StatusCode::METHOD_NOT_ALLOWED),
+        Err(_) => return webdav_status(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    if name.len() > 255 {
+        return webdav_status(StatusCode::BAD_REQUEST);
+    }
+
+    if !webdav_check_access(endpoint_id, parent_folder, "upload", &client, &pool).await {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    let new_folder_id = sqlx::query_scalar::<_, i64>(
+        "INSERT INTO storage_entries (endpoint_id, parent_folder, name, entry_type, created_by) VALUES ($1, $2, $3, 'folder'::storage_entry_type, $4) RETURNING id",
+    )
+    .bind(endpoint_id)
+    .bind(parent_folder)
+    .bind(name)
+    .bind(client.user_id)
+    .fetch_one(&**pool)
+    .await;
+
+    match new_folder_id {
+        Ok(new_folder_id) => {
+            sync_endpoint_file_structure(endpoint_id, &vec![new_folder_id], &pool).await;
+
+            write_audit_log(
+                &pool,
+                &req,
+                Some(client.audit_actor()),
+                AuditEvent {
+                    action: "storage.create_folder",
+                    target_type: "storage_entry",
+                    target_ids: vec![new_folder_id.to_string()],
+                    endpoint_id: Some(endpoint_id),
+                    after: get_entries_audit_snapshot(endpoint_id, &vec![new_folder_id], &pool)
+                        .await,
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            // TODO don't block the request
+            send_storage_location_updated(
+                &ws_state,
+                Some(client.user_id),
+                endpoint_id,
+                vec![parent_folder],
+                true,
+                false,
+            )
+            .await;
+
+            webdav_status(StatusCode::CREATED)
+        }
+        Err(_) => webdav_status(StatusCode::METHOD_NOT_ALLOWED),
+    }
+}
@@ -0,0 +1,195 @@
+use std::sync::Mutex;
+
+use actix_web::http::StatusCode;
+use actix_web::{route, web, Responder};
+
+use crate::audit_log::{get_entries_audit_snapshot, write_audit_log, AuditEvent};
+use crate::storage_access::check_bulk_storage_entries_access_cascade_up;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::{move_entries, StorageError};
+use crate::storage_layout::sync_endpoint_file_structure;
+use crate::util::RequestPool;
+use crate::webdav::{
+    split_entry_name, webdav_check_access, webdav_destination_path, webdav_finish_replace,
+    webdav_get_client, webdav_prepare_destination, webdav_resolve_path, webdav_status,
+    webdav_unauthorized, WebDAVResource,
+};
+use crate::ws::{send_storage_location_updated, WSState};
+
+#[route("/{endpoint_id}/{path:.*}", method = "MOVE")]
+async fn webdav_move(
+    pool: web::Data<RequestPool>,
+    ws_state: web::Data<Mutex<WSState>>,
+    path: web::Path<(i32, String)>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, path) = path.into_inner();
+
+    let client = webdav_get_client(&pool, &req).await;
+
+    if client.is_none() {
+        return webdav_unauthorized();
+    }
+
+    let client = client.unwrap();
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return webdav_status(StatusCode::NOT_FOUND);
+    }
+
+    if target_endpoint.unwrap().status != "active" {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    let entry = match webdav_resolve_path(endpoint_id, &path, &pool).await {
+        Ok(WebDAVResource::Entry(entry)) => entry,
+        Ok(WebDAVResource::Root) => return webdav_status(StatusCode::FORBIDDEN),
+        Ok(_) => return webdav_status(StatusCode::NOT_FOUND),
+        Err(_) => return webdav_status(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    // Find out if the client wants to move the entry, rename it, or both. We have to check the source entry
+    // before anything at the destination gets overwritten
+    let destination = match webdav_destination_path(&req, endpoint_id) {
+        Some(destination_path) => webdav_resolve_path(endpoint_id, &destination_path, &pool).await,
+        None => return webdav_status(StatusCode::BAD_REQUEST),
+    };
+
+    let (parent_changed, name_changed) = match &destination {
+        Ok(WebDAVResource::Missing {
+            parent_folder,
+            name,
+        }) => (
+            *parent_folder != entry.parent_folder,
+            *name != entry.full_name(),
+        ),
+        Ok(WebDAVResource::Entry(destination_entry)) => (
+            destination_entry.parent_folder != entry.parent_folder,
+            destination_entry.full_name() != entry.full_name(),
+        ),
+        _ => (false, false),
+    };
+
+    if parent_changed {
+        let move_allowed = check_bulk_storage_entries_access_cascade_up(
+            endpoint_id,
+            &vec![entry.id],
+            "move",
+            client.user_id,
+            &client.group_ids,
+            &pool,
+        )
+        .await;
+
+        if !move_allowed {
+            return webdav_status(StatusCode::FORBIDDEN);
+        }
+    }
+
+    if name_changed
+        && !webdav_check_access(endpoint_id, Some(entry.id), "rename", &client, &pool).await
+    {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    let (target_folder, new_full_name, replaced) = match webdav_prepare_destination(
+        endpoint_id,
+        &path,
+        Some(entry.parent_folder),
+        &client,
+        &req,
+        &pool,
+    )
+    .await
+    {
+        Ok(destination) => destination,
+        Err(status) => return webdav_status(status),
+    };
+
+    let audit_before = get_entries_audit_snapshot(endpoint_id, &vec![entry.id], &pool).await;
+
+    let move_result: Result<(), StatusCode> = async {
+        if parent_changed {
+            match move_entries(endpoint_id, &vec![entry.id], target_folder, &pool).await {
+                Ok(_) => {}
+                Err(StorageError::RecursionError) => return Err(StatusCode::FORBIDDEN),
+                Err(StorageError::NameConflict) => return Err(StatusCode::CONFLICT),
+                Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+
+        if name_changed {
+            // Folders keep their whole name, files are split into the name and the extension
+            let (new_name, new_extension) = if entry.is_folder() {
+                (new_full_name.as_str(), None)
+            } else {
+                split_entry_name(new_full_name.as_str())
+            };
+
+            let rename_result = sqlx::query(
+                "UPDATE storage_entries SET name = $1, extension = $2 WHERE id = $3 AND endpoint_id = $4",
+            )
+            .bind(new_name)
+            .bind(new_extension)
+            .bind(entry.id)
+            .bind(endpoint_id)
+            .execute(&**pool)
+            .await;
+
+            if rename_result.is_err() {
+                return Err(StatusCode::CONFLICT);
+            }
+
+            sync_endpoint_file_structure(endpoint_id, &vec![entry.id], &pool).await;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    let overwritten = replaced.is_some();
+
+    webdav_finish_replace(endpoint_id, replaced, move_result.is_ok(), &client, &pool).await;
+
+    if let Err(status) = move_result {
+        return webdav_status(status);
+    }
+
+    write_audit_log(
+        &pool,
+        &req,
+        Some(client.audit_actor()),
+        AuditEvent {
+            action: if parent_changed {
+                "storage.move"
+            } else {
+                "storage.rename"
+            },
+            target_type: "storage_entry",
+            target_ids: vec![entry.id.to_string()],
+            endpoint_id: Some(endpoint_id),
+            before: audit_before,
+            after: get_entries_audit_snapshot(endpoint_id, &vec![entry.id], &pool).await,
+        },
+    )
+    .await;
+
+    // TODO don't block the request
+    send_storage_location_updated(
+        &ws_state,
+        Some(client.user_id),
+        endpoint_id,
+        vec![entry.parent_folder, target_folder],
+        true,
+        false,
+    )
+    .await;
+
+    if overwritten {
+        webdav_status(StatusCode::NO_CONTENT)
+    } else {
+        webdav_status(StatusCode::CREATED)
+    }
+}
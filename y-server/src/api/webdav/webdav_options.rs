@@ -0,0 +1,12 @@
+use actix_web::{http::header, route, HttpResponse, Responder};
+
+#[route("/{endpoint_id}/{path:.*}", method = "OPTIONS")]
+async fn webdav_options() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(("DAV", "1"))
+        .insert_header((
+            header::ALLOW,
+            "OPTIONS, PROPFIND, GET, HEAD, PUT, MKCOL, MOVE, COPY, DELETE",
+        ))
+        .finish()
+}
@@ -0,0 +1,106 @@
+use actix_web::{http::StatusCode, route, web, Responder};
+
+use crate::util::RequestPool;
+use crate::webdav::{
+    webdav_check_access, webdav_get_client, webdav_get_folder_entries, webdav_href,
+    webdav_multistatus, webdav_propfind_response, webdav_resolve_path, webdav_status,
+    webdav_unauthorized, WebDAVResource,
+};
+
+#[route("/{endpoint_id}/{path:.*}", method = "PROPFIND")]
+async fn webdav_propfind(
+    pool: web::Data<RequestPool>,
+    path: web::Path<(i32, String)>,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, path) = path.into_inner();
+
+    let client = webdav_get_client(&pool, &req).await;
+
+    if client.is_none() {
+        return webdav_unauthorized();
+    }
+
+    let client = client.unwrap();
+
+    // We do not support `Depth: infinity`, it is treated the same way as `Depth: 1`
+    let depth_zero = req
+        .headers()
+        .get("Depth")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value == "0")
+        .unwrap_or(false);
+
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<&str>>();
+
+    let resource = webdav_resolve_path(endpoint_id, &path, &pool).await;
+
+    let (folder_id, mut responses) = match resource {
+        Ok(WebDAVResource::Root) => {
+            if !webdav_check_access(endpoint_id, None, "list_entries", &client, &pool).await {
+                return webdav_status(StatusCode::FORBIDDEN);
+            }
+
+            (
+                None,
+                vec![webdav_propfind_response(
+                    webdav_href(endpoint_id, &segments, true).as_str(),
+                    None,
+                )],
+            )
+        }
+
+        Ok(WebDAVResource::Entry(entry)) => {
+            // Files are visible to anyone who can list the contents of the folder they reside in
+            let access_target = if entry.is_folder() {
+                Some(entry.id)
+            } else {
+                entry.parent_folder
+            };
+
+            if !webdav_check_access(endpoint_id, access_target, "list_entries", &client, &pool)
+                .await
+            {
+                return webdav_status(StatusCode::FORBIDDEN);
+            }
+
+            let response = webdav_propfind_response(
+                webdav_href(endpoint_id, &segments, entry.is_folder()).as_str(),
+                Some(&entry),
+            );
+
+            if !entry.is_folder() {
+                return webdav_multistatus(vec![response]);
+            }
+
+            (Some(entry.id), vec![response])
+        }
+
+        Ok(_) => return webdav_status(StatusCode::NOT_FOUND),
+        Err(_) => return webdav_status(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    if !depth_zero {
+        let entries = match webdav_get_folder_entries(endpoint_id, folder_id, &pool).await {
+            Ok(entries) => entries,
+            Err(_) => return webdav_status(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+
+        for entry in entries {
+            let full_name = entry.full_name();
+
+            let mut entry_segments = segments.clone();
+            entry_segments.push(full_name.as_str());
+
+            responses.push(webdav_propfind_response(
+                webdav_href(endpoint_id, &entry_segments, entry.is_folder()).as_str(),
+                Some(&entry),
+            ));
+        }
+    }
+
+    webdav_multistatus(responses)
+}
@@ -0,0 +1,283 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use actix_web::http::StatusCode;
+use actix_web::{put, web, Responder};
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::audit_log::{write_audit_log, AuditEvent};
+use crate::storage_backend::get_storage_backend;
+use crate::storage_blobs::{finalize_blob_hash, release_stored_blob, store_staged_blob};
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::delete_storage_blobs;
+use crate::storage_layout::sync_endpoint_file_structure;
+use crate::storage_quotas::get_user_storage_quota;
+use crate::storage_versions::{replace_entry_blob, StorageEntryVersionBlob};
+use crate::util::RequestPool;
+use crate::webdav::{
+    split_entry_name, webdav_check_access, webdav_get_client, webdav_resolve_path, webdav_status,
+    webdav_unauthorized, WebDAVResource,
+};
+use crate::ws::{send_storage_location_updated, WSState};
+
+#[put("/{endpoint_id}/{path:.*}")]
+async fn webdav_put(
+    pool: web::Data<RequestPool>,
+    ws_state: web::Data<Mutex<WSState>>,
+    path: web::Path<(i32, String)>,
+    mut payload: web::Payload,
+    req: actix_web::HttpRequest,
+) -> impl Responder {
+    let (endpoint_id, path) = path.into_inner();
+
+    let client = webdav_get_client(&pool, &req).await;
+
+    if client.is_none() {
+        return webdav_unauthorized();
+    }
+
+    let client = client.unwrap();
+
+    let target_endpoint = get_storage_endpoint(endpoint_id, &pool).await;
+
+    if target_endpoint.is_err() {
+        return webdav_status(StatusCode::NOT_FOUND);
+    }
+
+    let target_endpoint = target_endpoint.unwrap();
+
+    if target_endpoint.status != "active" {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    // Either create a new file, or overwrite an existing one
+    let (parent_folder, full_name, existing_entry) =
+        match webdav_resolve_path(endpoint_id, &path, &pool).await {
+            Ok(WebDAVResource::Missing {
+                parent_folder,
+                name,
+            }) => (parent_folder, name, None),
+            Ok(WebDAVResource::Entry(entry)) => {
+                if entry.is_folder() {
+                    return webdav_status(StatusCode::METHOD_NOT_ALLOWED);
+                }
+
+                (entry.parent_folder, entry.full_name(), Some(entry))
+            }
+            Ok(WebDAVResource::Root) => return webdav_status(StatusCode::METHOD_NOT_ALLOWED),
+            Ok(WebDAVResource::MissingParent) => return webdav_status(StatusCode::CONFLICT),
+            Err(_) => return webdav_status(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+
+    if !webdav_check_access(endpoint_id, parent_folder, "upload", &client, &pool).await {
+        return webdav_status(StatusCode::FORBIDDEN);
+    }
+
+    // Overwriting a file destroys its previous contents, so that requires the right to delete it
+    if let Some(existing_entry) = &existing_entry {
+        if !webdav_check_access(
+            endpoint_id,
+            Some(existing_entry.id),
+            "delete",
+            &client,
+            &pool,
+        )
+        .await
+        {
+            return webdav_status(StatusCode::FORBIDDEN);
+        }
+    }
+
+    let storage_backend = get_storage_backend(&target_endpoint, &pool).await;
+
+    if storage_backend.is_err() {
+        return webdav_status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let storage_backend = storage_backend.unwrap();
+
+    // 1. Write the file onto the filesystem (or into the staging folder, if the endpoint keeps its files elsewhere)
+    let staged_filesystem_id = Uuid::new_v4().to_string();
+    let file_path = storage_backend.staging_path(&staged_filesystem_id);
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&file_path);
+
+    if file.is_err() {
+        return webdav_status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let mut file = file.unwrap();
+
+    let mut file_kind: Option<infer::Type> = None;
+    let mut file_size_bytes: i64 = 0;
+    let mut file_hasher = Sha256::new();
+
+    // Sizes of the files are not known in advance, so they are checked against the quota while being written
+    let quota_remaining_bytes =
+        match get_user_storage_quota(&target_endpoint, client.user_id, &pool).await {
+            Ok(quota) => quota.remaining_bytes,
+            Err(_) => {
+                fs::remove_file(&file_path).unwrap_or(());
+
+                return webdav_status(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+
+    while let Some(chunk) = payload.next().await {
+        if let Ok(chunk) = chunk {
+            if file_size_bytes == 0 {
+                file_kind = infer::get(&chunk);
+            }
+
+            file_size_bytes += chunk.len() as i64;
+            file_hasher.update(&chunk);
+
+            if quota_remaining_bytes
+                .is_some_and(|remaining_bytes| file_size_bytes > remaining_bytes)
+            {
+                fs::remove_file(&file_path).unwrap_or(());
+
+                return webdav_status(StatusCode::INSUFFICIENT_STORAGE);
+            }
+
+            if file.write_all(&chunk).is_err() {
+                fs::remove_file(&file_path).unwrap_or(());
+
+                return webdav_status(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        } else {
+            fs::remove_file(&file_path).unwrap_or(());
+
+            return webdav_status(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    drop(file);
+
+    let file_sha256 = finalize_blob_hash(file_hasher);
+
+    let stored_blob = store_staged_blob(
+        &target_endpoint,
+        storage_backend.as_ref(),
+        &staged_filesystem_id,
+        &file_sha256,
+        file_size_bytes,
+        &pool,
+    )
+    .await;
+
+    if stored_blob.is_err() {
+        fs::remove_file(&file_path).unwrap_or(());
+
+        return webdav_status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let stored_blob = stored_blob.unwrap();
+    let file_filesystem_id = stored_blob.filesystem_id.clone();
+
+    let file_mime_type = file_kind.map(|kind| kind.mime_type());
+
+    // 2. Create or update the row in the database
+    let mut new_file_id: Option<i64> = None;
+
+    let result = if let Some(existing_entry) = &existing_entry {
+        // The previous contents are kept as a version, if the endpoint has versioning enabled
+        replace_entry_blob(
+            &target_endpoint,
+            existing_entry.id,
+            &StorageEntryVersionBlob {
+                filesystem_id: file_filesystem_id.clone(),
+                mime_type: file_mime_type.map(|mime_type| mime_type.to_string()),
+                size_bytes: Some(file_size_bytes),
+                sha256: Some(file_sha256.clone()),
+            },
+            Some(client.user_id),
+            &pool,
+        )
+        .await
+        .is_ok()
+    } else {
+        let (file_name, file_extension) = split_entry_name(full_name.as_str());
+
+        let insert_result = sqlx::query_scalar::<_, i64>("INSERT INTO storage_entries (endpoint_id, filesystem_id, parent_folder, name, extension, mime_type, size_bytes, sha256, created_by, created_at, entry_type) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now(), 'file'::storage_entry_type) RETURNING id")
+            .bind(endpoint_id)
+            .bind(&file_filesystem_id)
+            .bind(parent_folder)
+            .bind(file_name)
+            .bind(file_extension)
+            .bind(file_mime_type)
+            .bind(file_size_bytes)
+            .bind(&file_sha256)
+            .bind(client.user_id)
+            .fetch_one(&**pool)
+            .await;
+
+        if let Ok(inserted_file_id) = insert_result {
+            sync_endpoint_file_structure(endpoint_id, &vec![inserted_file_id], &pool).await;
+
+            new_file_id = Some(inserted_file_id);
+        }
+
+        insert_result.is_ok()
+    };
+
+    release_stored_blob(&stored_blob, &pool).await;
+
+    if !result {
+        // A reused blob belongs to other files, it is kept
+        delete_storage_blobs(&target_endpoint, &vec![file_filesystem_id], &pool)
+            .await
+            .unwrap_or(());
+
+        return webdav_status(StatusCode::CONFLICT);
+    }
+
+    let file_id = existing_entry
+        .as_ref()
+        .map(|entry| entry.id)
+        .or(new_file_id)
+        .unwrap_or_default();
+
+    write_audit_log(
+        &pool,
+        &req,
+        Some(client.audit_actor()),
+        AuditEvent {
+            action: "storage.upload",
+            target_type: "storage_entry",
+            target_ids: vec![file_id.to_string()],
+            endpoint_id: Some(endpoint_id),
+            after: Some(serde_json::json!({
+                "webdav_path": path,
+                "size_bytes": file_size_bytes,
+                "sha256": file_sha256,
+                "overwritten": existing_entry.is_some(),
+            })),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    // TODO don't block the request
+    send_storage_location_updated(
+        &ws_state,
+        Some(client.user_id),
+        endpoint_id,
+        vec![parent_folder],
+        true,
+        existing_entry.is_some(),
+    )
+    .await;
+
+    if existing_entry.is_some() {
+        webdav_status(StatusCode::NO_CONTENT)
+    } else {
+        webdav_status(StatusCode::CREATED)
+    }
+}
@@ -0,0 +1,250 @@
+use std::net::IpAddr;
+
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::postgres::PgArguments;
+use sqlx::query::QueryAs;
+use sqlx::{FromRow, Postgres};
+
+use crate::user::{get_user_from_request, User, UserSession};
+use crate::util::RequestPool;
+
+/**
+ * Columns of `audit_log` as they are returned by the API
+ */
+pub const AUDIT_LOG_SELECT: &str =
+    "SELECT id, action, actor_user_id, actor_username, session_id, host(ip) AS ip, forwarded_for,
+    target_type, target_ids, endpoint_id, before, after, created_at::TEXT FROM audit_log";
+
+/**
+ * Filters shared by the audit log listing and the export. Bound as $1 - $8.
+ */
+pub const AUDIT_LOG_FILTERS_SQL: &str =
+    "($1::TEXT IS NULL OR action = $1 OR action LIKE $1 || '.%')
+    AND ($2::INTEGER IS NULL OR actor_user_id = $2)
+    AND ($3::TEXT IS NULL OR target_type = $3)
+    AND ($4::TEXT IS NULL OR $4 = ANY(target_ids))
+    AND ($5::INTEGER IS NULL OR endpoint_id = $5)
+    AND ($6::TEXT IS NULL OR ip = $6::inet)
+    AND ($7::TIMESTAMPTZ IS NULL OR created_at >= $7)
+    AND ($8::TIMESTAMPTZ IS NULL OR created_at <= $8)";
+
+#[derive(Deserialize)]
+pub struct AuditLogFilters {
+    /**
+     * Exact action ("storage.delete") or a group of actions ("storage", "storage.share_link")
+     */
+    pub action: Option<String>,
+    pub actor_user_id: Option<i32>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub endpoint_id: Option<i32>,
+    pub ip: Option<String>,
+
+    /**
+     * RFC 3339 timestamps
+     */
+    pub after: Option<String>,
+    pub before: Option<String>,
+}
+
+impl AuditLogFilters {
+    pub fn is_valid(&self) -> bool {
+        self.ip
+            .as_ref()
+            .is_none_or(|ip| ip.parse::<IpAddr>().is_ok())
+            && parse_filter_timestamp(&self.after).is_ok()
+            && parse_filter_timestamp(&self.before).is_ok()
+    }
+}
+
+fn parse_filter_timestamp(timestamp: &Option<String>) -> Result<Option<DateTime<Utc>>, ()> {
+    match timestamp {
+        Some(timestamp) => DateTime::parse_from_rfc3339(timestamp)
+            .map(|timestamp| Some(timestamp.with_timezone(&Utc)))
+            .map_err(|_| ()),
+        None => Ok(None),
+    }
+}
+
+/**
+ * Bind filters to a query that uses `AUDIT_LOG_FILTERS_SQL`. The filters must be valid (see `AuditLogFilters::is_valid`).
+ */
+pub fn bind_audit_log_filters<'q, O>(
+    query: QueryAs<'q, Postgres, O, PgArguments>,
+    filters: &'q AuditLogFilters,
+) -> QueryAs<'q, Postgres, O, PgArguments> {
+    query
+        .bind(&filters.action)
+        .bind(filters.actor_user_id)
+        .bind(&filters.target_type)
+        .bind(&filters.target_id)
+        .bind(filters.endpoint_id)
+        .bind(&filters.ip)
+        .bind(parse_filter_timestamp(&filters.after).unwrap_or(None))
+        .bind(parse_filter_timestamp(&filters.before).unwrap_or(None))
+}
+
+#[derive(Serialize, FromRow)]
+pub struct AuditLogRecord {
+    pub id: i64,
+    pub action: String,
+
+    pub actor_user_id: Option<i32>,
+
+    /**
+     * Username at the time of the action. Kept after the user is deleted or renamed.
+     */
+    pub actor_username: Option<String>,
+
+    /**
+     * `user_sessions.id` of the session (or the personal API token) the action was performed with. `None` for WebDAV clients authenticated
+     * with a password, and for failed logins.
+     */
+    pub session_id: Option<i32>,
+
+    pub ip: Option<String>,
+
+    /**
+     * Client address as reported by a reverse proxy (`Forwarded` / `X-Forwarded-For`). Not verified, may be spoofed.
+     */
+    pub forwarded_for: Option<String>,
+
+    pub target_type: String,
+
+    /**
+     * Ids of the users, groups, entries, etc. the action was performed on. Empty for actions on the whole instance.
+     */
+    pub target_ids: Vec<String>,
+    pub endpoint_id: Option<i32>,
+
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+
+    pub created_at: String,
+}
+
+/**
+ * Who performed an action
+ */
+pub struct AuditActor {
+    pub user_id: i32,
+    pub session_id: Option<i32>,
+}
+
+impl AuditActor {
+    pub fn from_client(client: &(User, UserSession)) -> Self {
+        AuditActor {
+            user_id: client.0.id,
+            session_id: Some(client.1.id),
+        }
+    }
+}
+
+/**
+ * An action to be recorded in the audit log.
+ *
+ * `before` and `after` hold whatever is needed to tell what the action changed: the old and the new name of a renamed
+ * entry, the rules of a group before and after an update, the entries that were deleted, etc.
+ */
+#[derive(Default)]
+pub struct AuditEvent {
+    pub action: &'static str,
+    pub target_type: &'static str,
+    pub target_ids: Vec<String>,
+    pub endpoint_id: Option<i32>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+/**
+ * Record an action in the audit log.
+ *
+ * Failing to write a record does not fail the action itself, the error is logged instead.
+ *
+ * @param actor `None` for anonymous clients (share links, failed logins)
+ */
+pub async fn write_audit_log(
+    pool: &RequestPool,
+    req: &HttpRequest,
+    actor: Option<AuditActor>,
+    event: AuditEvent,
+) {
+    let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+
+    let forwarded_for = req
+        .connection_info()
+        .realip_remote_addr()
+        .filter(|realip| ip.as_deref() != Some(*realip))
+        .map(|realip| realip.to_string());
+
+    let (actor_user_id, session_id) = match &actor {
+        Some(actor) => (Some(actor.user_id), actor.session_id),
+        None => (None, None),
+    };
+
+    let write_result = sqlx::query(
+        "INSERT INTO audit_log (action, actor_user_id, actor_username, session_id, ip, forwarded_for, target_type, target_ids, endpoint_id, before, after)
+        VALUES ($1, $2, (SELECT username FROM users WHERE id = $2), $3, $4::inet, $5, $6, $7, $8, $9, $10)",
+    )
+    .bind(event.action)
+    .bind(actor_user_id)
+    .bind(session_id)
+    .bind(ip)
+    .bind(forwarded_for)
+    .bind(event.target_type)
+    .bind(&event.target_ids)
+    .bind(event.endpoint_id)
+    .bind(&event.before)
+    .bind(&event.after)
+    .execute(pool)
+    .await;
+
+    if let Err(err) = write_result {
+        error!(
+            "(audit log) Could not record action {} on {} {:?}. {}",
+            event.action, event.target_type, event.target_ids, err
+        );
+    }
+}
+
+/**
+ * Record an action performed by the client of a request (the user of the `y-session` cookie).
+ *
+ * Use `write_audit_log` directly if the client is already known, or was authenticated in some other way.
+ */
+pub async fn write_client_audit_log(pool: &RequestPool, req: &HttpRequest, event: AuditEvent) {
+    let client = get_user_from_request(pool, req).await;
+
+    write_audit_log(
+        pool,
+        req,
+        client.as_ref().map(AuditActor::from_client),
+        event,
+    )
+    .await;
+}
+
+/**
+ * Names and locations of storage entries, for the `before` and `after` of actions on them. Entries that don't
+ * exist are left out.
+ */
+pub async fn get_entries_audit_snapshot(
+    endpoint_id: i32,
+    entry_ids: &Vec<i64>,
+    pool: &RequestPool,
+) -> Option<Value> {
+    sqlx::query_scalar::<_, Value>(
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+            'id', id, 'entry_type', entry_type, 'name', name, 'extension', extension, 'parent_folder', parent_folder
+        ) ORDER BY id), '[]'::jsonb) FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)",
+    )
+    .bind(endpoint_id)
+    .bind(entry_ids)
+    .fetch_one(pool)
+    .await
+    .ok()
+}
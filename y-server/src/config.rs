@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use sqlx::prelude::FromRow;
 
+use crate::storage_hls::parse_hls_ladder;
 use crate::util::RequestPool;
 
 // Config keys that should never expose their current values to clients (in other words, "write only")
@@ -47,6 +48,7 @@ pub fn validate_config_value(key: &str, value: &str) -> Result<(), &'static str>
         }
 
         "storage.transcode_videos.enabled"
+        | "storage.transcode_videos.hls.enabled"
         | "storage.generate_seeking_thumbnails.enabled"
         | "storage.generate_thumbnails.video"
         | "storage.generate_thumbnails.audio"
@@ -60,7 +62,13 @@ pub fn validate_config_value(key: &str, value: &str) -> Result<(), &'static str>
 
         "storage.transcode_videos.target_height"
         | "storage.transcode_videos.target_bitrate"
-        | "storage.generate_seeking_thumbnails.desired_frames" => {
+        | "storage.generate_seeking_thumbnails.desired_frames"
+        | "storage.trash.retention_days"
+        | "storage.extract.max_entries"
+        | "storage.extract.max_compression_ratio"
+        | "storage.archives.retention_hours"
+        | "jobs.max_attempts"
+        | "jobs.retention_days" => {
             if value.parse::<u32>().is_err() {
                 return Err("Invalid integer value");
             }
@@ -68,6 +76,36 @@ pub fn validate_config_value(key: &str, value: &str) -> Result<(), &'static str>
             Ok(())
         }
 
+        "storage.copy.background_threshold_bytes"
+        | "storage.extract.max_total_bytes"
+        | "storage.archives.max_size_bytes" => {
+            if value.parse::<u64>().is_err() {
+                return Err("Invalid integer value");
+            }
+
+            Ok(())
+        }
+
+        // Comma separated, paired with each other in order
+        "storage.transcode_videos.hls.heights" | "storage.transcode_videos.hls.bitrates" => {
+            if parse_hls_ladder(value).is_none() {
+                return Err("Invalid list of integers");
+            }
+
+            Ok(())
+        }
+
+        "storage.archives.compression_level" => match value.parse::<u32>() {
+            Ok(level) if level <= 9 => Ok(()),
+            _ => Err("Invalid compression level"),
+        },
+
+        // Takes effect after a restart
+        "jobs.workers" | "jobs.interactive_workers" => match value.parse::<u32>() {
+            Ok(workers) if workers > 0 => Ok(()),
+            _ => Err("Invalid number of workers"),
+        },
+
         _ => Err("Unknown key"),
     }
 }
@@ -0,0 +1,544 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use actix_web::web;
+use log::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::config::get_config;
+use crate::storage_archives::run_create_archive_job;
+use crate::storage_artifacts::{run_generate_artifacts_job, run_transcode_video_job};
+use crate::storage_copy::run_copy_entries_job;
+use crate::storage_extract::run_extract_archive_job;
+use crate::storage_hls::run_transcode_video_hls_job;
+use crate::storage_import::run_import_job;
+use crate::storage_move::run_move_entries_job;
+use crate::util::RequestPool;
+use crate::ws::WSState;
+
+pub const JOB_STORAGE_GENERATE_ARTIFACTS: &str = "storage.generate_artifacts";
+pub const JOB_STORAGE_TRANSCODE_VIDEO: &str = "storage.transcode_video";
+pub const JOB_STORAGE_TRANSCODE_VIDEO_HLS: &str = "storage.transcode_video_hls";
+pub const JOB_STORAGE_CREATE_ARCHIVE: &str = "storage.create_archive";
+pub const JOB_STORAGE_IMPORT: &str = "storage.import";
+pub const JOB_STORAGE_COPY_ENTRIES: &str = "storage.copy_entries";
+pub const JOB_STORAGE_MOVE_ENTRIES: &str = "storage.move_entries";
+pub const JOB_STORAGE_EXTRACT_ARCHIVE: &str = "storage.extract_archive";
+
+/**
+ * Jobs that users are waiting for. They have workers of their own, so that they are not stuck behind long
+ * transcodes or imports.
+ */
+const INTERACTIVE_JOB_TYPES: [&str; 4] = [
+    JOB_STORAGE_CREATE_ARCHIVE,
+    JOB_STORAGE_COPY_ENTRIES,
+    JOB_STORAGE_MOVE_ENTRIES,
+    JOB_STORAGE_EXTRACT_ARCHIVE,
+];
+
+// Workers for all other jobs. Used if `jobs.workers` is not set. Changing the value requires a restart
+const DEFAULT_JOB_WORKERS: u32 = 2;
+
+// Workers for `INTERACTIVE_JOB_TYPES`. Used if `jobs.interactive_workers` is not set. Changing the value requires a restart
+const DEFAULT_INTERACTIVE_JOB_WORKERS: u32 = 2;
+
+// Used if `jobs.max_attempts` is not set
+const DEFAULT_JOB_MAX_ATTEMPTS: i32 = 3;
+
+// How many days finished jobs are kept around if `jobs.retention_days` is not set
+const DEFAULT_JOB_RETENTION_DAYS: i64 = 7;
+
+// Delay before a failed job is retried, multiplied by the number of attempts made so far
+const JOB_RETRY_DELAY_SECONDS: i32 = 30;
+
+// Idle workers check the queue at least this often, even if nobody wakes them up
+const JOB_WORKER_IDLE_INTERVAL: Duration = Duration::from_secs(5);
+
+// Incremented every time new jobs are queued, wakes up idle workers
+static JOBS_QUEUED: Mutex<u64> = Mutex::new(0);
+static JOBS_QUEUED_CONDVAR: Condvar = Condvar::new();
+
+pub enum JobError {
+    NotFound,
+    InvalidStatus,
+    StillRunning,
+
+    Internal,
+}
+
+impl JobError {
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            JobError::NotFound => "jobs.not_found",
+            JobError::InvalidStatus => "jobs.invalid_status",
+            JobError::StillRunning => "jobs.still_running",
+
+            JobError::Internal => "jobs.internal",
+        }
+    }
+}
+
+#[derive(FromRow, Serialize)]
+pub struct JobRow {
+    pub id: i64,
+    pub job_type: String,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+    pub created_by: Option<i32>,
+    pub created_by_username: Option<String>,
+    pub created_at: String,
+    pub run_after: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+}
+
+pub const JOB_SELECT: &str = "SELECT jobs.id, jobs.job_type, jobs.payload, jobs.status::TEXT, jobs.attempts, jobs.max_attempts, jobs.last_error, jobs.created_by, users.username AS created_by_username, jobs.created_at::TEXT, jobs.run_after::TEXT, jobs.started_at::TEXT, jobs.finished_at::TEXT FROM jobs LEFT JOIN users ON users.id = jobs.created_by";
+
+/**
+ * Which jobs a worker takes off the queue
+ */
+#[derive(Clone, Copy)]
+enum JobQueue {
+    /**
+     * `INTERACTIVE_JOB_TYPES`
+     */
+    Interactive,
+
+    /**
+     * Everything else: thumbnails, transcodes, imports
+     */
+    Background,
+}
+
+#[derive(FromRow)]
+struct ClaimedJob {
+    id: i64,
+    job_type: String,
+    payload: serde_json::Value,
+}
+
+/**
+ * Put jobs of the same type onto the queue. One job is created for each payload.
+ *
+ * @returns ids of the new jobs
+ */
+pub async fn enqueue_jobs<T: Serialize>(
+    job_type: &str,
+    payloads: &[T],
+    created_by: Option<i32>,
+    pool: &RequestPool,
+) -> Result<Vec<i64>, JobError> {
+    if payloads.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let payloads = payloads
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<serde_json::Value>, _>>()
+        .map_err(|_| JobError::Internal)?;
+
+    let max_attempts = get_config(pool)
+        .await
+        .get("jobs.max_attempts")
+        .and_then(|value| value.parse::<i32>().ok())
+        .unwrap_or(DEFAULT_JOB_MAX_ATTEMPTS)
+        .max(1);
+
+    let job_ids = sqlx::query_scalar::<_, i64>(
+        "INSERT INTO jobs (job_type, payload, created_by, max_attempts) SELECT $1, payload, $3, $4 FROM UNNEST($2::JSONB[]) AS payload RETURNING id",
+    )
+    .bind(job_type)
+    .bind(&payloads)
+    .bind(created_by)
+    .bind(max_attempts)
+    .fetch_all(pool)
+    .await;
+
+    match job_ids {
+        Ok(job_ids) => {
+            notify_job_workers();
+
+            Ok(job_ids)
+        }
+        Err(err) => {
+            error!("(jobs) Could not queue {} jobs. {}", job_type, err);
+
+            Err(JobError::Internal)
+        }
+    }
+}
+
+pub async fn enqueue_job<T: Serialize>(
+    job_type: &str,
+    payload: &T,
+    created_by: Option<i32>,
+    pool: &RequestPool,
+) -> Result<i64, JobError> {
+    let payload = serde_json::to_value(payload).map_err(|_| JobError::Internal)?;
+
+    enqueue_jobs(job_type, &[payload], created_by, pool)
+        .await?
+        .pop()
+        .ok_or(JobError::Internal)
+}
+
+/**
+ * Stop a job from being run (again). A job that is already running is not interrupted, but it will not be retried
+ * automatically and its outcome will not be recorded. See `retry_job`.
+ */
+pub async fn cancel_job(job_id: i64, pool: &RequestPool) -> Result<(), JobError> {
+    let status = get_job_status(job_id, pool).await?;
+
+    if status != "queued" && status != "running" {
+        return Err(JobError::InvalidStatus);
+    }
+
+    let result = sqlx::query(
+        "UPDATE jobs SET status = 'cancelled', finished_at = now() WHERE id = $1 AND status IN ('queued', 'running')",
+    )
+    .bind(job_id)
+    .execute(pool)
+    .await
+    .map_err(|_| JobError::Internal)?;
+
+    // The job has finished in the meantime
+    if result.rows_affected() == 0 {
+        return Err(JobError::InvalidStatus);
+    }
+
+    Ok(())
+}
+
+/**
+ * Put a failed or cancelled job back onto the queue. The job gets a fresh set of attempts.
+ *
+ * A job that was cancelled while running can only be retried once its worker is done with it, otherwise it would run
+ * twice at the same time.
+ */
+pub async fn retry_job(job_id: i64, pool: &RequestPool) -> Result<(), JobError> {
+    let status = get_job_status(job_id, pool).await?;
+
+    if status != "failed" && status != "cancelled" {
+        return Err(JobError::InvalidStatus);
+    }
+
+    let result = sqlx::query(
+        "UPDATE jobs SET status = 'queued', attempts = 0, last_error = NULL, run_after = now(), started_at = NULL, finished_at = NULL WHERE id = $1 AND status IN ('failed', 'cancelled') AND NOT worker_running",
+    )
+    .bind(job_id)
+    .execute(pool)
+    .await
+    .map_err(|_| JobError::Internal)?;
+
+    if result.rows_affected() == 0 {
+        if get_job_worker_running(job_id, pool).await? {
+            return Err(JobError::StillRunning);
+        }
+
+        return Err(JobError::InvalidStatus);
+    }
+
+    notify_job_workers();
+
+    Ok(())
+}
+
+async fn get_job_status(job_id: i64, pool: &RequestPool) -> Result<String, JobError> {
+    let status = sqlx::query_scalar::<_, String>("SELECT status::TEXT FROM jobs WHERE id = $1")
+        .bind(job_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|_| JobError::Internal)?;
+
+    status.ok_or(JobError::NotFound)
+}
+
+async fn get_job_worker_running(job_id: i64, pool: &RequestPool) -> Result<bool, JobError> {
+    let worker_running =
+        sqlx::query_scalar::<_, bool>("SELECT worker_running FROM jobs WHERE id = $1")
+            .bind(job_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|_| JobError::Internal)?;
+
+    worker_running.ok_or(JobError::NotFound)
+}
+
+fn notify_job_workers() {
+    *JOBS_QUEUED.lock().unwrap() += 1;
+    JOBS_QUEUED_CONDVAR.notify_all();
+}
+
+/**
+ * Sleep until new jobs are queued (or until it's time to check the queue anyway).
+ *
+ * @param last_seen - `JOBS_QUEUED` at the time the queue was found empty, so that no notifications are missed
+ */
+fn wait_for_jobs(last_seen: u64) {
+    let jobs_queued = JOBS_QUEUED.lock().unwrap();
+
+    let _ = JOBS_QUEUED_CONDVAR
+        .wait_timeout_while(jobs_queued, JOB_WORKER_IDLE_INTERVAL, |jobs_queued| {
+            *jobs_queued == last_seen
+        })
+        .unwrap();
+}
+
+/**
+ * Take the next queued job off the queue. Safe to call from multiple workers at once.
+ */
+async fn claim_next_job(
+    queue: JobQueue,
+    pool: &RequestPool,
+) -> Result<Option<ClaimedJob>, sqlx::Error> {
+    sqlx::query_as::<_, ClaimedJob>(
+        "UPDATE jobs SET status = 'running', worker_running = true, attempts = attempts + 1, started_at = now(), finished_at = NULL
+        WHERE id = (
+            SELECT id FROM jobs WHERE status = 'queued' AND NOT worker_running AND run_after <= now()
+            AND (job_type = ANY($1)) = $2
+            ORDER BY run_after, id LIMIT 1 FOR UPDATE SKIP LOCKED
+        ) RETURNING id, job_type, payload",
+    )
+    .bind(&INTERACTIVE_JOB_TYPES[..])
+    .bind(matches!(queue, JobQueue::Interactive))
+    .fetch_optional(pool)
+    .await
+}
+
+/**
+ * Record the outcome of a job. Failed jobs are queued again (with a delay) until they run out of attempts.
+ *
+ * The outcome of jobs that were cancelled while running is not recorded, they are only marked as no longer running.
+ */
+async fn finish_job(job_id: i64, result: Result<(), String>, pool: &RequestPool) {
+    let update_result = match result {
+        Ok(_) => sqlx::query(
+            "UPDATE jobs SET status = 'completed', worker_running = false, last_error = NULL, finished_at = now() WHERE id = $1 AND status = 'running'",
+        )
+        .bind(job_id)
+        .execute(pool)
+        .await,
+
+        Err(job_error) => {
+            warn!("(jobs) Job {} failed. {}", job_id, job_error);
+
+            sqlx::query(
+                "UPDATE jobs SET
+                status = CASE WHEN attempts < max_attempts THEN 'queued'::job_status ELSE 'failed'::job_status END,
+                worker_running = false,
+                finished_at = CASE WHEN attempts < max_attempts THEN NULL ELSE now() END,
+                run_after = now() + make_interval(secs => attempts * $3),
+                last_error = $2
+                WHERE id = $1 AND status = 'running'",
+            )
+            .bind(job_id)
+            .bind(job_error)
+            .bind(JOB_RETRY_DELAY_SECONDS)
+            .execute(pool)
+            .await
+        }
+    };
+
+    if let Err(err) = update_result {
+        error!(
+            "(jobs) Could not record the outcome of job {}. {}",
+            job_id, err
+        );
+    }
+
+    // Cancelled while running. Not touched above, and nobody else can pick it up until this is cleared.
+    let release_result = sqlx::query(
+        "UPDATE jobs SET worker_running = false WHERE id = $1 AND status = 'cancelled'",
+    )
+    .bind(job_id)
+    .execute(pool)
+    .await;
+
+    if let Err(err) = release_result {
+        error!(
+            "(jobs) Could not mark cancelled job {} as no longer running. {}",
+            job_id, err
+        );
+    }
+}
+
+fn parse_job_payload<T: DeserializeOwned>(payload: serde_json::Value) -> Result<T, String> {
+    serde_json::from_value::<T>(payload).map_err(|err| format!("Invalid job payload. {}", err))
+}
+
+fn run_job(
+    runtime: &actix_rt::Runtime,
+    pool: &RequestPool,
+    ws_state: &web::Data<Mutex<WSState>>,
+    job: ClaimedJob,
+) -> Result<(), String> {
+    match job.job_type.as_str() {
+        JOB_STORAGE_GENERATE_ARTIFACTS => {
+            run_generate_artifacts_job(runtime, pool, ws_state, parse_job_payload(job.payload)?)
+        }
+        JOB_STORAGE_TRANSCODE_VIDEO => {
+            run_transcode_video_job(runtime, pool, ws_state, parse_job_payload(job.payload)?)
+        }
+        JOB_STORAGE_TRANSCODE_VIDEO_HLS => {
+            run_transcode_video_hls_job(runtime, pool, ws_state, parse_job_payload(job.payload)?)
+        }
+        JOB_STORAGE_CREATE_ARCHIVE => {
+            run_create_archive_job(runtime, pool, ws_state, parse_job_payload(job.payload)?)
+        }
+        JOB_STORAGE_IMPORT => {
+            run_import_job(runtime, pool, ws_state, parse_job_payload(job.payload)?)
+        }
+        JOB_STORAGE_COPY_ENTRIES => {
+            run_copy_entries_job(runtime, pool, ws_state, parse_job_payload(job.payload)?)
+        }
+        JOB_STORAGE_MOVE_ENTRIES => {
+            run_move_entries_job(runtime, pool, ws_state, parse_job_payload(job.payload)?)
+        }
+        JOB_STORAGE_EXTRACT_ARCHIVE => {
+            run_extract_archive_job(runtime, pool, ws_state, parse_job_payload(job.payload)?)
+        }
+        _ => Err(format!("Unknown job type: {}", job.job_type)),
+    }
+}
+
+fn run_job_worker(queue: JobQueue, pool: RequestPool, ws_state: web::Data<Mutex<WSState>>) {
+    // The S3 client needs a tokio reactor
+    let runtime = actix_rt::Runtime::new().unwrap();
+
+    loop {
+        let jobs_queued = *JOBS_QUEUED.lock().unwrap();
+        let job = runtime.block_on(claim_next_job(queue, &pool));
+
+        match job {
+            Ok(Some(job)) => {
+                let job_id = job.id;
+
+                debug!("(jobs) Running job {} ({})", job_id, job.job_type);
+
+                // A panicking job must not take the worker down with it
+                let result = catch_unwind(AssertUnwindSafe(|| {
+                    run_job(&runtime, &pool, &ws_state, job)
+                }))
+                .unwrap_or_else(|_| Err("The job panicked".to_string()));
+
+                runtime.block_on(finish_job(job_id, result, &pool));
+            }
+            Ok(None) => wait_for_jobs(jobs_queued),
+            Err(err) => {
+                error!("(jobs) Could not get the next job from the queue. {}", err);
+
+                wait_for_jobs(jobs_queued);
+            }
+        }
+    }
+}
+
+/**
+ * Start the workers that process the job queue. Each worker runs one job at a time in its own thread. Interactive
+ * jobs (see `INTERACTIVE_JOB_TYPES`) and all other jobs are processed by separate sets of workers.
+ *
+ * Jobs that were running when the server was stopped are queued again.
+ */
+pub async fn start_job_workers(pool: RequestPool, ws_state: web::Data<Mutex<WSState>>) {
+    let requeue_result = sqlx::query(
+        "UPDATE jobs SET
+        status = CASE WHEN attempts < max_attempts THEN 'queued'::job_status ELSE 'failed'::job_status END,
+        finished_at = CASE WHEN attempts < max_attempts THEN NULL ELSE now() END,
+        run_after = now(),
+        last_error = 'Interrupted by a server restart'
+        WHERE status = 'running'",
+    )
+    .execute(&pool)
+    .await;
+
+    match requeue_result {
+        Ok(requeue_result) if requeue_result.rows_affected() > 0 => {
+            info!(
+                "Requeued {} interrupted jobs",
+                requeue_result.rows_affected()
+            );
+        }
+        Ok(_) => {}
+        Err(err) => {
+            error!("Could not requeue interrupted jobs. {}", err);
+        }
+    }
+
+    // No worker survives a restart, including the ones of jobs that were cancelled while running
+    let _ = sqlx::query("UPDATE jobs SET worker_running = false WHERE worker_running")
+        .execute(&pool)
+        .await;
+
+    let config = get_config(&pool).await;
+
+    let get_workers_count = |key: &str, default: u32| {
+        config
+            .get(key)
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(default)
+            .max(1)
+    };
+
+    let workers = [
+        (
+            JobQueue::Background,
+            "job-worker",
+            get_workers_count("jobs.workers", DEFAULT_JOB_WORKERS),
+        ),
+        (
+            JobQueue::Interactive,
+            "interactive-job-worker",
+            get_workers_count("jobs.interactive_workers", DEFAULT_INTERACTIVE_JOB_WORKERS),
+        ),
+    ];
+
+    for (queue, thread_name, workers_count) in workers {
+        info!("Starting {} {}s", workers_count, thread_name);
+
+        for worker_index in 0..workers_count {
+            let worker_pool = pool.clone();
+            let worker_ws_state = ws_state.clone();
+
+            std::thread::Builder::new()
+                .name(format!("{}-{}", thread_name, worker_index))
+                .spawn(move || run_job_worker(queue, worker_pool, worker_ws_state))
+                .unwrap();
+        }
+    }
+}
+
+pub async fn cleanup_jobs(pool: &RequestPool) {
+    info!("[scheduled] Cleaning up finished jobs...");
+
+    let retention_days = get_config(pool)
+        .await
+        .get("jobs.retention_days")
+        .and_then(|value| value.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_JOB_RETENTION_DAYS);
+
+    // 0 == keep finished jobs forever
+    if retention_days == 0 {
+        return;
+    }
+
+    let delete_result = sqlx::query(
+        "DELETE FROM jobs WHERE status IN ('completed', 'failed', 'cancelled') AND finished_at < now() - make_interval(days => $1)",
+    )
+    .bind(retention_days as i32)
+    .execute(pool)
+    .await;
+
+    if let Err(error) = delete_result {
+        error!(
+            "Could not delete finished jobs from the database. {}",
+            error
+        );
+    }
+}
@@ -1,21 +1,50 @@
 mod api;
+mod audit_log;
 mod config;
 mod db;
+mod jobs;
 mod request;
 mod right;
 mod storage_access;
+mod storage_archive_stream;
 mod storage_archives;
+mod storage_artifacts;
+mod storage_backend;
+mod storage_blobs;
+mod storage_copy;
 mod storage_endpoint;
 mod storage_entry;
+mod storage_extract;
+mod storage_fsck;
+mod storage_hls;
+mod storage_import;
+mod storage_layout;
+mod storage_metadata;
+mod storage_move;
+mod storage_quotas;
+mod storage_share_links;
+mod storage_tags;
+mod storage_trash;
+mod storage_uploads;
+mod storage_versions;
 mod user;
+mod user_api_tokens;
 mod user_group;
+mod user_oidc;
+mod user_totp;
 mod util;
 mod vfs;
 mod vfs_manager;
 mod vfs_util;
+mod webdav;
 mod ws;
 
+use crate::jobs::{cleanup_jobs, start_job_workers};
 use crate::storage_archives::cleanup_storage_archives;
+use crate::storage_fsck::check_storage_endpoint;
+use crate::storage_import::{import_directory, StorageImportMode, StorageImportOptions};
+use crate::storage_trash::cleanup_storage_trash;
+use crate::storage_uploads::cleanup_storage_upload_sessions;
 use actix_web::{web, App, HttpServer};
 use chrono::{FixedOffset, Local};
 use dotenvy::dotenv;
@@ -68,6 +97,143 @@ async fn process_cli_arguments(pool: &RequestPool) {
                 exit(1);
             }
         }
+
+        if argument == "--import-directory" {
+            let endpoint_id = cli_arguments
+                .get(index + 1)
+                .and_then(|endpoint_id| endpoint_id.parse::<i32>().ok());
+            let mode = cli_arguments
+                .get(index + 2)
+                .and_then(|mode| StorageImportMode::from_str(mode));
+
+            // Only the `copy` mode takes a source directory
+            let (source_path, target_folder) = match mode {
+                Some(StorageImportMode::Copy) => (
+                    cli_arguments.get(index + 3).cloned(),
+                    cli_arguments.get(index + 4),
+                ),
+                _ => (None, cli_arguments.get(index + 3)),
+            };
+
+            let target_folder = target_folder
+                .map(|target_folder| target_folder.parse::<i64>())
+                .transpose();
+
+            let options = match (endpoint_id, mode, target_folder) {
+                (Some(endpoint_id), Some(mode), Ok(target_folder))
+                    if mode == StorageImportMode::Adopt || source_path.is_some() =>
+                {
+                    Some(StorageImportOptions {
+                        endpoint_id,
+                        mode,
+                        source_path,
+                        target_folder,
+                        generate_artifacts: true,
+                    })
+                }
+                _ => None,
+            };
+
+            if let Some(options) = options {
+                println!("Importing a directory and exiting...");
+
+                match import_directory(&options, pool).await {
+                    Ok(summary) => {
+                        println!("Folders added: {}", summary.added_folders);
+                        println!("Files added: {}", summary.added_files);
+                        println!("Files updated: {}", summary.updated_files);
+                        println!("Entries removed: {}", summary.removed_entries);
+                        println!("Skipped: {}", summary.skipped);
+                        exit(0);
+                    }
+                    Err(error) => {
+                        println!("{}", error.get_code());
+                        exit(1);
+                    }
+                }
+            } else {
+                println!("Usage: --import-directory <endpoint_id> adopt [target_folder_id]");
+                println!(
+                    "       --import-directory <endpoint_id> copy <source_path> [target_folder_id]"
+                );
+                println!("  adopt: index the files that are already stored in the endpoint's folder, without moving them.");
+                println!("         Only supported by endpoints that preserve the file structure.");
+                println!("  copy: copy the files from <source_path> into the endpoint.");
+                println!(
+                    "  target_folder_id: (optional) id of the folder to import the files into."
+                );
+                exit(1);
+            }
+        }
+
+        if argument == "--fsck" {
+            let endpoint_id = cli_arguments
+                .get(index + 1)
+                .and_then(|endpoint_id| endpoint_id.parse::<i32>().ok());
+            let repair = cli_arguments.get(index + 2).map(|flag| flag.as_str());
+
+            if let (Some(endpoint_id), None | Some("repair")) = (endpoint_id, repair) {
+                println!("Checking the endpoint and exiting...");
+
+                match check_storage_endpoint(endpoint_id, repair.is_some(), pool).await {
+                    Ok(report) => {
+                        for missing in &report.missing_blobs {
+                            match missing.version_id {
+                                Some(version_id) => println!(
+                                    "Missing blob: entry {}, version {} ({})",
+                                    missing.entry_id, version_id, missing.filesystem_id
+                                ),
+                                None => println!(
+                                    "Missing blob: entry {} ({})",
+                                    missing.entry_id, missing.filesystem_id
+                                ),
+                            }
+                        }
+
+                        for size_mismatch in &report.size_mismatches {
+                            println!(
+                                "Size mismatch: entry {} ({}), recorded {:?}, actual {}",
+                                size_mismatch.entry_id,
+                                size_mismatch.filesystem_id,
+                                size_mismatch.recorded_size_bytes,
+                                size_mismatch.actual_size_bytes
+                            );
+                        }
+
+                        for path in &report.untracked_files {
+                            println!("Untracked file: {}", path);
+                        }
+
+                        for path in &report.orphaned_artifacts {
+                            println!("Orphaned artifact: {}", path);
+                        }
+
+                        for path in &report.stale_staging_files {
+                            println!("Stale staging file: {}", path);
+                        }
+
+                        if !report.blobs_checked {
+                            println!("Blobs of this endpoint are not stored locally and were not checked.");
+                        }
+
+                        if report.repaired {
+                            println!("Repaired.");
+                        } else {
+                            println!("Nothing has been changed. Run with \"repair\" to fix the problems.");
+                        }
+                        exit(0);
+                    }
+                    Err(error) => {
+                        println!("{}", error.get_code());
+                        exit(1);
+                    }
+                }
+            } else {
+                println!("Usage: --fsck <endpoint_id> [repair]");
+                println!("  repair: (optional) fix the problems instead of only reporting them.");
+                exit(1);
+            }
+        }
     }
 }
 
@@ -86,6 +252,9 @@ fn setup_job_scheduler(pool: RequestPool) {
             if let Some(datetime) = upcoming.next() {
                 if datetime.timestamp() <= local.timestamp() {
                     cleanup_storage_archives(&pool).await;
+                    cleanup_storage_upload_sessions(&pool).await;
+                    cleanup_storage_trash(&pool).await;
+                    cleanup_jobs(&pool).await;
                 }
             }
         }
@@ -180,6 +349,9 @@ async fn main() -> io::Result<()> {
     let jobs_database_pool = pool.clone();
     setup_job_scheduler(jobs_database_pool);
 
+    // Start up the workers that process background jobs (thumbnails, transcoding, archives)
+    start_job_workers(pool.clone(), web::Data::clone(&ws_state)).await;
+
     // Start actix web
     info!("Starting server on {}:{}", server_address, server_port);
 
@@ -192,24 +364,59 @@ async fn main() -> io::Result<()> {
             .service(
                 web::scope("/api/auth")
                     .service(crate::api::auth::login::login)
+                    .service(crate::api::auth::login_two_factor::login_two_factor)
                     .service(crate::api::auth::me::me)
-                    .service(crate::api::auth::logout::logout),
+                    .service(crate::api::auth::logout::logout)
+                    .service(crate::api::auth::api_tokens::api_tokens)
+                    .service(crate::api::auth::create_api_token::create_api_token)
+                    .service(crate::api::auth::delete_api_token::delete_api_token)
+                    .service(crate::api::auth::two_factor::two_factor)
+                    .service(crate::api::auth::two_factor_enroll::two_factor_enroll)
+                    .service(crate::api::auth::two_factor_confirm::two_factor_confirm)
+                    .service(crate::api::auth::two_factor_disable::two_factor_disable)
+                    .service(crate::api::auth::two_factor_recovery_codes::two_factor_recovery_codes)
+                    .service(crate::api::auth::oidc_providers::oidc_providers)
+                    .service(crate::api::auth::oidc_login::oidc_login)
+                    .service(crate::api::auth::oidc_callback::oidc_callback),
             )
             .service(
                 web::scope("/api/storage")
                     .service(crate::api::storage::storage_upload::storage_upload)
+                    .service(crate::api::storage::storage_upload_sessions_options::storage_upload_sessions_options)
+                    .service(crate::api::storage::storage_create_upload_session::storage_create_upload_session)
+                    .service(crate::api::storage::storage_upload_session_offset::storage_upload_session_offset)
+                    .service(crate::api::storage::storage_upload_session_append::storage_upload_session_append)
+                    .service(crate::api::storage::storage_delete_upload_session::storage_delete_upload_session)
                     .service(crate::api::storage::storage_endpoints::storage_endpoints)
                     .service(crate::api::storage::storage_locations::storage_locations)
                     .service(crate::api::storage::storage_entries::storage_entries)
+                    .service(crate::api::storage::storage_trash_items::storage_trash_items)
+                    .service(crate::api::storage::storage_search::storage_search)
+                    .service(crate::api::storage::storage_quota::storage_quota)
+                    .service(crate::api::storage::storage_restore_trash_items::storage_restore_trash_items)
+                    .service(crate::api::storage::storage_purge_trash_items::storage_purge_trash_items)
                     .service(crate::api::storage::storage_download::storage_download)
+                    .service(crate::api::storage::storage_entry_versions::storage_entry_versions)
+                    .service(crate::api::storage::storage_entry_details::storage_entry_details)
+                    .service(crate::api::storage::storage_tags::storage_tags)
+                    .service(crate::api::storage::storage_add_entry_tags::storage_add_entry_tags)
+                    .service(crate::api::storage::storage_remove_entry_tags::storage_remove_entry_tags)
+                    .service(crate::api::storage::storage_set_entry_properties::storage_set_entry_properties)
+                    .service(crate::api::storage::storage_remove_entry_properties::storage_remove_entry_properties)
+                    .service(crate::api::storage::storage_download_entry_version::storage_download_entry_version)
+                    .service(crate::api::storage::storage_restore_entry_version::storage_restore_entry_version)
                     .service(crate::api::storage::storage_create_archive::storage_create_archive)
+                    .service(crate::api::storage::storage_extract_archive::storage_extract_archive)
+                    .service(crate::api::storage::storage_stream_archive::storage_stream_archive)
                     .service(crate::api::storage::storage_create_folder::storage_create_folder)
                     .service(crate::api::storage::storage_get_folder_path::storage_get_folder_path)
                     .service(crate::api::storage::storage_delete_entries::storage_delete_entries)
                     .service(crate::api::storage::storage_entry_thumbnails::storage_entry_thumbnails)
                     .service(crate::api::storage::storage_move_entries::storage_move_entries)
+                    .service(crate::api::storage::storage_copy_entries::storage_copy_entries)
                     .service(crate::api::storage::storage_rename_entry::storage_rename_entry)
                     .service(crate::api::storage::storage_get::storage_get)
+                    .service(crate::api::storage::storage_get_hls::storage_get_hls)
                     .service(crate::api::storage::storage_create_access_rules::storage_create_access_rules)
                     .service(crate::api::storage::storage_create_access_rules_template::storage_create_access_rules_template)
                     .service(crate::api::storage::storage_entry_add_access_rules_template::storage_entry_add_access_rules_template)
@@ -221,7 +428,11 @@ async fn main() -> io::Result<()> {
                     .service(crate::api::storage::storage_create_user_pin::storage_create_user_pin)
                     .service(crate::api::storage::storage_delete_user_pin::storage_delete_user_pin)
                     .service(crate::api::storage::storage_user_archives::storage_user_archives)
-                    .service(crate::api::storage::storage_download_archive::storage_download_archive),
+                    .service(crate::api::storage::storage_download_archive::storage_download_archive)
+                    .service(crate::api::storage::storage_cancel_archive::storage_cancel_archive)
+                    .service(crate::api::storage::storage_share_links::storage_share_links)
+                    .service(crate::api::storage::storage_create_share_link::storage_create_share_link)
+                    .service(crate::api::storage::storage_delete_share_link::storage_delete_share_link),
             )
             .service(
                 web::scope("/api/admin")
@@ -230,6 +441,7 @@ async fn main() -> io::Result<()> {
                     .service(crate::api::admin::delete_user::delete_user)
                     .service(crate::api::admin::users::users)
                     .service(crate::api::admin::update_password::update_password)
+                    .service(crate::api::admin::reset_user_two_factor::reset_user_two_factor)
                     .service(crate::api::admin::user_groups::user_groups)
                     .service(crate::api::admin::user_group::user_group)
                     .service(crate::api::admin::update_user_group::update_user_group)
@@ -245,11 +457,43 @@ async fn main() -> io::Result<()> {
                     .service(crate::api::admin::storage_endpoint::storage_enpoint)
                     .service(crate::api::admin::storage_endpoint_vfs::storage_enpoint_vfs)
                     .service(crate::api::admin::storage_endpoint_set_vfs_config::storage_endpoint_set_vfs_config)
+                    .service(crate::api::admin::storage_endpoint_import::storage_endpoint_import)
+                    .service(crate::api::admin::storage_endpoint_fsck::storage_endpoint_fsck)
                     .service(crate::api::admin::update_storage_endpoint::update_storage_endpoint)
+                    .service(crate::api::admin::storage_share_links::storage_share_links)
+                    .service(crate::api::admin::delete_storage_share_link::delete_storage_share_link)
+                    .service(crate::api::admin::jobs::jobs)
+                    .service(crate::api::admin::cancel_job::cancel_job)
+                    .service(crate::api::admin::retry_job::retry_job)
+                    .service(crate::api::admin::oidc_providers::oidc_providers)
+                    .service(crate::api::admin::create_oidc_provider::create_oidc_provider)
+                    .service(crate::api::admin::update_oidc_provider::update_oidc_provider)
+                    .service(crate::api::admin::delete_oidc_provider::delete_oidc_provider)
+                    .service(crate::api::admin::audit_log::audit_log)
+                    .service(crate::api::admin::audit_log_export::audit_log_export)
                     .service(crate::api::admin::config::config_options::config_options)
                     .service(crate::api::admin::config::config_set::config_set)
                     ,
             )
+            .service(
+                web::scope("/api/webdav")
+                    .service(crate::api::webdav::webdav_options::webdav_options)
+                    .service(crate::api::webdav::webdav_propfind::webdav_propfind)
+                    .service(crate::api::webdav::webdav_get::webdav_get)
+                    .service(crate::api::webdav::webdav_put::webdav_put)
+                    .service(crate::api::webdav::webdav_mkcol::webdav_mkcol)
+                    .service(crate::api::webdav::webdav_delete::webdav_delete)
+                    .service(crate::api::webdav::webdav_move::webdav_move)
+                    .service(crate::api::webdav::webdav_copy::webdav_copy),
+            )
+            // Share links are public, clients are not required to be logged in
+            .service(
+                web::scope("/api/share")
+                    .service(crate::api::share::share_link_entries::share_link_entries)
+                    .service(crate::api::share::share_link_get::share_link_get)
+                    .service(crate::api::share::share_link_archive::share_link_archive)
+                    .service(crate::api::share::share_link::share_link),
+            )
             .service(
                 web::scope("/api")
                     .service(crate::api::user_rights::user_rights)
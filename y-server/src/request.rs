@@ -38,6 +38,16 @@ impl TableInput {
     }
 }
 
+/**
+ * Escape LIKE wildcards, so that user input is matched literally (with `ESCAPE '\'`)
+ */
+pub fn escape_like_pattern(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 #[derive(Serialize)]
 pub struct ResponseError {
     pub code: String,
@@ -47,12 +47,24 @@ pub fn get_right_categories() -> Vec<RightCategory> {
     vec![
         RightCategory {
             name: "basic",
-            rights: vec![Right {
-                name: "create_account",
-                options: vec![],
-                tags: vec![],
-                feature: None,
-            }],
+            rights: vec![
+                Right {
+                    name: "create_account",
+                    options: vec![],
+                    tags: vec![],
+                    feature: None,
+                },
+                Right {
+                    name: "two_factor_authentication",
+                    options: vec![RightOption {
+                        name: "require_two_factor",
+                        value_type: RightValueType::Boolean,
+                        value_source: None,
+                    }],
+                    tags: vec![],
+                    feature: None,
+                },
+            ],
         },
         RightCategory {
             name: "instance_administration",
@@ -69,6 +81,24 @@ pub fn get_right_categories() -> Vec<RightCategory> {
                     tags: vec![RightTag::Administrative],
                     feature: None,
                 },
+                Right {
+                    name: "manage_jobs",
+                    options: vec![],
+                    tags: vec![RightTag::Administrative],
+                    feature: None,
+                },
+                Right {
+                    name: "view_audit_log",
+                    options: vec![],
+                    tags: vec![RightTag::Administrative],
+                    feature: None,
+                },
+                Right {
+                    name: "manage_oidc_providers",
+                    options: vec![],
+                    tags: vec![RightTag::Administrative, RightTag::Dangerous],
+                    feature: None,
+                },
             ],
         },
         RightCategory {
@@ -90,6 +120,12 @@ pub fn get_right_categories() -> Vec<RightCategory> {
                     tags: vec![RightTag::Administrative],
                     feature: None,
                 },
+                Right {
+                    name: "reset_user_two_factor",
+                    options: vec![],
+                    tags: vec![RightTag::Administrative, RightTag::Dangerous],
+                    feature: None,
+                },
             ],
         },
         RightCategory {
@@ -182,7 +218,46 @@ pub fn get_right_categories() -> Vec<RightCategory> {
                     tags: vec![RightTag::Administrative],
                     feature: Some("storage"),
                 },
+                Right {
+                    name: "storage_create_share_links",
+                    options: vec![],
+                    tags: vec![],
+                    feature: Some("storage"),
+                },
+                Right {
+                    name: "manage_storage_share_links",
+                    options: vec![],
+                    tags: vec![RightTag::Administrative],
+                    feature: Some("storage"),
+                },
+                Right {
+                    name: "storage_quota",
+                    options: vec![RightOption {
+                        name: "max_storage_bytes",
+                        value_type: RightValueType::Number,
+                        value_source: None,
+                    }],
+                    tags: vec![],
+                    feature: Some("storage"),
+                },
             ],
         },
     ]
 }
+
+/**
+ * Names of all the rights tagged as administrative
+ */
+pub fn get_administrative_right_names() -> Vec<&'static str> {
+    get_right_categories()
+        .into_iter()
+        .flat_map(|category| category.rights)
+        .filter(|right| {
+            right
+                .tags
+                .iter()
+                .any(|tag| matches!(tag, RightTag::Administrative))
+        })
+        .map(|right| right.name)
+        .collect()
+}
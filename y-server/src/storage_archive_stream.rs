@@ -0,0 +1,713 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+
+use actix_web::web::Bytes;
+use chrono::{Datelike, Timelike, Utc};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use futures::channel::mpsc;
+use futures::SinkExt;
+use log::*;
+use serde::Deserialize;
+
+use crate::storage_backend::{get_storage_backend, StorageBackend};
+use crate::storage_endpoint::StorageEndpointRow;
+use crate::util::RequestPool;
+
+// The archive is sent to the client in chunks of this size
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+// How many chunks can be waiting to be sent before the archive writer has to wait for the client
+const STREAM_CHANNEL_CAPACITY: usize = 16;
+
+const ZSTD_COMPRESSION_LEVEL: i32 = 3;
+
+// Sizes from this value up do not fit into a regular zip header
+const ZIP64_SIZE_LIMIT: u64 = 0xFFFF_FFFF;
+
+// Room for a file growing a bit between its size being looked up and it being added to the archive
+const ZIP64_SIZE_MARGIN: u64 = 16 * 1024 * 1024;
+
+// Each archive is written on a thread of its own
+// TODO configurable value
+const MAX_ARCHIVE_STREAMS: usize = 8;
+
+static ARCHIVE_STREAMS_RUNNING: Mutex<usize> = Mutex::new(0);
+
+const TAR_BLOCK_SIZE: usize = 512;
+
+// Largest size that fits into the 11 octal digits of a ustar header
+const TAR_MAX_USTAR_SIZE: u64 = 0o77777777777;
+
+/**
+ * Format of an archive that is streamed to the client
+ */
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+pub enum StorageArchiveStreamFormat {
+    /**
+     * Files are stored as they are
+     */
+    #[serde(rename = "zip")]
+    Zip,
+
+    /**
+     * Files are compressed with deflate while they are being sent
+     */
+    #[serde(rename = "zip_deflate")]
+    ZipDeflate,
+
+    #[serde(rename = "tar")]
+    Tar,
+
+    #[serde(rename = "tar.zst")]
+    TarZst,
+}
+
+impl StorageArchiveStreamFormat {
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            StorageArchiveStreamFormat::Zip | StorageArchiveStreamFormat::ZipDeflate => "zip",
+            StorageArchiveStreamFormat::Tar => "tar",
+            StorageArchiveStreamFormat::TarZst => "tar.zst",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            StorageArchiveStreamFormat::Zip | StorageArchiveStreamFormat::ZipDeflate => {
+                "application/zip"
+            }
+            StorageArchiveStreamFormat::Tar => "application/x-tar",
+            StorageArchiveStreamFormat::TarZst => "application/zstd",
+        }
+    }
+}
+
+pub type StorageArchiveStream = mpsc::Receiver<Result<Bytes, io::Error>>;
+
+/**
+ * Sends everything that is written to it to the client, in chunks. Writing fails once the client has gone away.
+ */
+struct ChannelWriter {
+    sender: mpsc::Sender<Result<Bytes, io::Error>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(STREAM_CHUNK_SIZE),
+        ));
+
+        futures::executor::block_on(self.sender.send(Ok(chunk)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The client has gone away"))
+    }
+
+    /**
+     * Make the client's download fail instead of leaving them with an archive that looks complete
+     */
+    fn abort(mut self) {
+        let _ = futures::executor::block_on(
+            self.sender
+                .send(Err(io::Error::other("Could not write the archive"))),
+        );
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        if self.buffer.len() >= STREAM_CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+/**
+ * Keeps track of how much has been written, zip headers refer to each other by offset
+ */
+struct CountingWriter<W: Write> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/**
+ * Calculates the checksum of a file while it's being read
+ */
+struct HashingReader<R: Read> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+    read: u64,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        self.hasher.update(&buf[..read]);
+        self.read += read as u64;
+
+        Ok(read)
+    }
+}
+
+struct ZipCentralDirectoryEntry {
+    name: String,
+    deflated: bool,
+    zip64: bool,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    header_offset: u64,
+}
+
+/**
+ * Writes a zip archive front to back, without seeking. Sizes and checksums of the files are only known after they
+ * have been written, so they follow the data in data descriptors.
+ */
+struct ZipStreamWriter<W: Write> {
+    inner: CountingWriter<W>,
+    deflate: bool,
+    entries: Vec<ZipCentralDirectoryEntry>,
+    dos_time: u16,
+    dos_date: u16,
+}
+
+/**
+ * Largest size that the deflated data of a file can have. Same as zlib's `deflateBound` for raw deflate data
+ * (incompressible data ends up in stored blocks, which have a few bytes of overhead each).
+ */
+fn max_deflated_size(size_bytes: u64) -> u64 {
+    size_bytes + (size_bytes >> 12) + (size_bytes >> 14) + (size_bytes >> 25) + 7
+}
+
+impl<W: Write> ZipStreamWriter<W> {
+    fn new(inner: W, deflate: bool) -> Self {
+        let now = Utc::now();
+
+        ZipStreamWriter {
+            inner: CountingWriter { inner, written: 0 },
+            deflate,
+            entries: Vec::new(),
+            dos_time: ((now.hour() << 11) | (now.minute() << 5) | (now.second() / 2)) as u16,
+            dos_date: (((now.year().max(1980) - 1980) as u32) << 9 | now.month() << 5 | now.day())
+                as u16,
+        }
+    }
+
+    fn add_file(&mut self, name: &str, size_bytes: u64, file: &mut impl Read) -> io::Result<()> {
+        let header_offset = self.inner.written;
+
+        // The sizes go into the header before the data is compressed, so the worst case has to fit
+        let max_compressed_size = if self.deflate {
+            max_deflated_size(size_bytes)
+        } else {
+            size_bytes
+        };
+
+        let zip64 = max_compressed_size + ZIP64_SIZE_MARGIN >= ZIP64_SIZE_LIMIT;
+
+        // Data descriptor follows the data, names are UTF-8
+        let flags: u16 = 0x0008 | 0x0800;
+        let method: u16 = if self.deflate { 8 } else { 0 };
+        let version_needed: u16 = if zip64 { 45 } else { 20 };
+        let unknown_size: u32 = if zip64 { 0xFFFF_FFFF } else { 0 };
+
+        let mut header: Vec<u8> = Vec::with_capacity(30 + name.len() + 20);
+        header.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        header.extend_from_slice(&version_needed.to_le_bytes());
+        header.extend_from_slice(&flags.to_le_bytes());
+        header.extend_from_slice(&method.to_le_bytes());
+        header.extend_from_slice(&self.dos_time.to_le_bytes());
+        header.extend_from_slice(&self.dos_date.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&unknown_size.to_le_bytes());
+        header.extend_from_slice(&unknown_size.to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(if zip64 { 20u16 } else { 0u16 }).to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+
+        // Tells the reader that the data descriptor has 8 byte sizes
+        if zip64 {
+            header.extend_from_slice(&0x0001u16.to_le_bytes());
+            header.extend_from_slice(&16u16.to_le_bytes());
+            header.extend_from_slice(&0u64.to_le_bytes());
+            header.extend_from_slice(&0u64.to_le_bytes());
+        }
+
+        self.inner.write_all(&header)?;
+
+        let data_offset = self.inner.written;
+        let mut file = HashingReader {
+            inner: file,
+            hasher: crc32fast::Hasher::new(),
+            read: 0,
+        };
+
+        if self.deflate {
+            let mut deflater = DeflateEncoder::new(&mut self.inner, Compression::default());
+
+            io::copy(&mut file, &mut deflater)?;
+            deflater.finish()?;
+        } else {
+            io::copy(&mut file, &mut self.inner)?;
+        }
+
+        let uncompressed_size = file.read;
+        let crc32 = file.hasher.finalize();
+        let compressed_size = self.inner.written - data_offset;
+
+        if !zip64 && (compressed_size >= ZIP64_SIZE_LIMIT || uncompressed_size >= ZIP64_SIZE_LIMIT)
+        {
+            // The file has grown since its size was looked up
+            return Err(io::Error::other("File is too large for its zip header"));
+        }
+
+        let mut descriptor: Vec<u8> = Vec::with_capacity(24);
+        descriptor.extend_from_slice(&0x0807_4b50u32.to_le_bytes());
+        descriptor.extend_from_slice(&crc32.to_le_bytes());
+
+        if zip64 {
+            descriptor.extend_from_slice(&compressed_size.to_le_bytes());
+            descriptor.extend_from_slice(&uncompressed_size.to_le_bytes());
+        } else {
+            descriptor.extend_from_slice(&(compressed_size as u32).to_le_bytes());
+            descriptor.extend_from_slice(&(uncompressed_size as u32).to_le_bytes());
+        }
+
+        self.inner.write_all(&descriptor)?;
+
+        self.entries.push(ZipCentralDirectoryEntry {
+            name: name.to_string(),
+            deflated: self.deflate,
+            zip64,
+            crc32,
+            compressed_size,
+            uncompressed_size,
+            header_offset,
+        });
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        let central_directory_offset = self.inner.written;
+
+        for entry in &self.entries {
+            // Values that don't fit are moved into the ZIP64 extra field
+            let mut zip64_extra: Vec<u8> = Vec::new();
+
+            let uncompressed_size = if entry.zip64 || entry.uncompressed_size >= 0xFFFF_FFFF {
+                zip64_extra.extend_from_slice(&entry.uncompressed_size.to_le_bytes());
+                0xFFFF_FFFF
+            } else {
+                entry.uncompressed_size as u32
+            };
+
+            let compressed_size = if entry.zip64 || entry.compressed_size >= 0xFFFF_FFFF {
+                zip64_extra.extend_from_slice(&entry.compressed_size.to_le_bytes());
+                0xFFFF_FFFF
+            } else {
+                entry.compressed_size as u32
+            };
+
+            let header_offset = if entry.header_offset >= 0xFFFF_FFFF {
+                zip64_extra.extend_from_slice(&entry.header_offset.to_le_bytes());
+                0xFFFF_FFFF
+            } else {
+                entry.header_offset as u32
+            };
+
+            let version: u16 = if zip64_extra.is_empty() { 20 } else { 45 };
+            let extra_length: u16 = if zip64_extra.is_empty() {
+                0
+            } else {
+                4 + zip64_extra.len() as u16
+            };
+
+            let mut header: Vec<u8> = Vec::with_capacity(46 + entry.name.len() + 28);
+            header.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+            // Made by: unix, so that the permissions below are used
+            header.extend_from_slice(&(0x0300 | version).to_le_bytes());
+            header.extend_from_slice(&version.to_le_bytes());
+            header.extend_from_slice(&(0x0008u16 | 0x0800).to_le_bytes());
+            header.extend_from_slice(&(if entry.deflated { 8u16 } else { 0u16 }).to_le_bytes());
+            header.extend_from_slice(&self.dos_time.to_le_bytes());
+            header.extend_from_slice(&self.dos_date.to_le_bytes());
+            header.extend_from_slice(&entry.crc32.to_le_bytes());
+            header.extend_from_slice(&compressed_size.to_le_bytes());
+            header.extend_from_slice(&uncompressed_size.to_le_bytes());
+            header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            header.extend_from_slice(&extra_length.to_le_bytes());
+            header.extend_from_slice(&0u16.to_le_bytes());
+            header.extend_from_slice(&0u16.to_le_bytes());
+            header.extend_from_slice(&0u16.to_le_bytes());
+            header.extend_from_slice(&((0o100644u32) << 16).to_le_bytes());
+            header.extend_from_slice(&header_offset.to_le_bytes());
+            header.extend_from_slice(entry.name.as_bytes());
+
+            if !zip64_extra.is_empty() {
+                header.extend_from_slice(&0x0001u16.to_le_bytes());
+                header.extend_from_slice(&(zip64_extra.len() as u16).to_le_bytes());
+                header.extend_from_slice(&zip64_extra);
+            }
+
+            self.inner.write_all(&header)?;
+        }
+
+        let central_directory_size = self.inner.written - central_directory_offset;
+        let entries_count = self.entries.len() as u64;
+
+        let mut end: Vec<u8> = Vec::with_capacity(98);
+
+        if entries_count >= 0xFFFF
+            || central_directory_size >= 0xFFFF_FFFF
+            || central_directory_offset >= 0xFFFF_FFFF
+        {
+            let zip64_end_offset = self.inner.written;
+
+            // ZIP64 end of central directory record
+            end.extend_from_slice(&0x0606_4b50u32.to_le_bytes());
+            end.extend_from_slice(&44u64.to_le_bytes());
+            end.extend_from_slice(&(0x0300u16 | 45).to_le_bytes());
+            end.extend_from_slice(&45u16.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&entries_count.to_le_bytes());
+            end.extend_from_slice(&entries_count.to_le_bytes());
+            end.extend_from_slice(&central_directory_size.to_le_bytes());
+            end.extend_from_slice(&central_directory_offset.to_le_bytes());
+
+            // ZIP64 end of central directory locator
+            end.extend_from_slice(&0x0706_4b50u32.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&zip64_end_offset.to_le_bytes());
+            end.extend_from_slice(&1u32.to_le_bytes());
+        }
+
+        end.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        end.extend_from_slice(&(entries_count.min(0xFFFF) as u16).to_le_bytes());
+        end.extend_from_slice(&(entries_count.min(0xFFFF) as u16).to_le_bytes());
+        end.extend_from_slice(&(central_directory_size.min(0xFFFF_FFFF) as u32).to_le_bytes());
+        end.extend_from_slice(&(central_directory_offset.min(0xFFFF_FFFF) as u32).to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+
+        self.inner.write_all(&end)?;
+
+        Ok(self.inner.inner)
+    }
+}
+
+/**
+ * Writes a POSIX (pax) tar archive. Paths that are too long for a ustar header and files larger than 8 GiB get an
+ * extended header.
+ */
+struct TarStreamWriter<W: Write> {
+    inner: W,
+    mtime: u64,
+}
+
+/**
+ * Cut a string to at most `max_length` bytes without splitting a character
+ */
+fn truncate_utf8(value: &str, max_length: usize) -> &str {
+    if value.len() <= max_length {
+        return value;
+    }
+
+    let mut end = max_length;
+
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &value[..end]
+}
+
+/**
+ * A pax record is prefixed with its own length, including the length itself
+ */
+fn pax_record(key: &str, value: &str) -> String {
+    let content_length = key.len() + value.len() + 3;
+    let mut length = content_length + content_length.to_string().len();
+
+    if length.to_string().len() != content_length.to_string().len() {
+        length += 1;
+    }
+
+    format!("{} {}={}\n", length, key, value)
+}
+
+impl<W: Write> TarStreamWriter<W> {
+    fn new(inner: W) -> Self {
+        TarStreamWriter {
+            inner,
+            mtime: Utc::now().timestamp().max(0) as u64,
+        }
+    }
+
+    fn write_header(&mut self, name: &str, size_bytes: u64, entry_type: u8) -> io::Result<()> {
+        let mut header = [0u8; TAR_BLOCK_SIZE];
+
+        let put = |header: &mut [u8; TAR_BLOCK_SIZE], offset: usize, value: &[u8]| {
+            header[offset..offset + value.len()].copy_from_slice(value);
+        };
+
+        put(&mut header, 0, truncate_utf8(name, 100).as_bytes());
+        put(&mut header, 100, b"0000644\0");
+        put(&mut header, 108, b"0000000\0");
+        put(&mut header, 116, b"0000000\0");
+        put(
+            &mut header,
+            124,
+            format!("{:011o}\0", size_bytes.min(TAR_MAX_USTAR_SIZE)).as_bytes(),
+        );
+        put(
+            &mut header,
+            136,
+            format!("{:011o}\0", self.mtime).as_bytes(),
+        );
+        put(&mut header, 148, b"        ");
+        header[156] = entry_type;
+        put(&mut header, 257, b"ustar\0");
+        put(&mut header, 263, b"00");
+
+        let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+        put(&mut header, 148, format!("{:06o}\0 ", checksum).as_bytes());
+
+        self.inner.write_all(&header)
+    }
+
+    fn write_padding(&mut self, size_bytes: u64) -> io::Result<()> {
+        let remainder = (size_bytes % TAR_BLOCK_SIZE as u64) as usize;
+
+        if remainder != 0 {
+            self.inner.write_all(&[0u8; TAR_BLOCK_SIZE][remainder..])?;
+        }
+
+        Ok(())
+    }
+
+    fn add_file(&mut self, name: &str, size_bytes: u64, file: &mut impl Read) -> io::Result<()> {
+        let mut pax_records = String::new();
+
+        if name.len() > 100 {
+            pax_records.push_str(&pax_record("path", name));
+        }
+
+        if size_bytes > TAR_MAX_USTAR_SIZE {
+            pax_records.push_str(&pax_record("size", &size_bytes.to_string()));
+        }
+
+        if !pax_records.is_empty() {
+            self.write_header(
+                &format!("PaxHeaders/{}", truncate_utf8(name, 89)),
+                pax_records.len() as u64,
+                b'x',
+            )?;
+            self.inner.write_all(pax_records.as_bytes())?;
+            self.write_padding(pax_records.len() as u64)?;
+        }
+
+        self.write_header(name, size_bytes, b'0')?;
+
+        // The size has already been written, a file that has changed in the meantime is cut off or padded
+        let copied = io::copy(&mut file.take(size_bytes), &mut self.inner)?;
+
+        if copied < size_bytes {
+            io::copy(
+                &mut io::repeat(0).take(size_bytes - copied),
+                &mut self.inner,
+            )?;
+        }
+
+        self.write_padding(size_bytes)
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0u8; TAR_BLOCK_SIZE * 2])?;
+
+        Ok(self.inner)
+    }
+}
+
+enum ArchiveWriter<W: Write> {
+    Zip(ZipStreamWriter<W>),
+    Tar(TarStreamWriter<W>),
+    TarZst(TarStreamWriter<zstd::Encoder<'static, W>>),
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    fn new(inner: W, format: StorageArchiveStreamFormat) -> io::Result<Self> {
+        Ok(match format {
+            StorageArchiveStreamFormat::Zip => {
+                ArchiveWriter::Zip(ZipStreamWriter::new(inner, false))
+            }
+            StorageArchiveStreamFormat::ZipDeflate => {
+                ArchiveWriter::Zip(ZipStreamWriter::new(inner, true))
+            }
+            StorageArchiveStreamFormat::Tar => ArchiveWriter::Tar(TarStreamWriter::new(inner)),
+            StorageArchiveStreamFormat::TarZst => ArchiveWriter::TarZst(TarStreamWriter::new(
+                zstd::Encoder::new(inner, ZSTD_COMPRESSION_LEVEL)?,
+            )),
+        })
+    }
+
+    fn add_file(&mut self, name: &str, size_bytes: u64, file: &mut impl Read) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(writer) => writer.add_file(name, size_bytes, file),
+            ArchiveWriter::Tar(writer) => writer.add_file(name, size_bytes, file),
+            ArchiveWriter::TarZst(writer) => writer.add_file(name, size_bytes, file),
+        }
+    }
+
+    fn finish(self) -> io::Result<W> {
+        match self {
+            ArchiveWriter::Zip(writer) => writer.finish(),
+            ArchiveWriter::Tar(writer) => writer.finish(),
+            ArchiveWriter::TarZst(writer) => writer.finish()?.finish(),
+        }
+    }
+}
+
+fn write_archive_stream(
+    runtime: &actix_rt::Runtime,
+    storage_backend: &dyn StorageBackend,
+    resolved_entries: &Vec<(String, String)>,
+    format: StorageArchiveStreamFormat,
+    writer: &mut ChannelWriter,
+) -> io::Result<()> {
+    let mut archive = ArchiveWriter::new(writer, format)?;
+
+    for (file_path_str, file_filesystem_id) in resolved_entries {
+        let local_blob = runtime
+            .block_on(storage_backend.local_copy(file_filesystem_id))
+            .map_err(|err| io::Error::other(err.get_code()))?;
+
+        let mut file = File::open(local_blob.path())?;
+        let size_bytes = file.metadata()?.len();
+
+        archive.add_file(file_path_str, size_bytes, &mut file)?;
+    }
+
+    archive.finish()?.flush()
+}
+
+/**
+ * One of the `MAX_ARCHIVE_STREAMS` slots for archives that are being written. Freed when dropped.
+ */
+struct ArchiveStreamSlot;
+
+impl ArchiveStreamSlot {
+    fn take() -> Option<ArchiveStreamSlot> {
+        let mut running = ARCHIVE_STREAMS_RUNNING.lock().unwrap();
+
+        if *running >= MAX_ARCHIVE_STREAMS {
+            return None;
+        }
+
+        *running += 1;
+
+        Some(ArchiveStreamSlot)
+    }
+}
+
+impl Drop for ArchiveStreamSlot {
+    fn drop(&mut self) {
+        *ARCHIVE_STREAMS_RUNNING.lock().unwrap() -= 1;
+    }
+}
+
+/**
+ * Stream an archive of files to the client while it's being written. Nothing is stored on disk, apart from temporary
+ * copies of blobs from endpoints that are not local (one at a time).
+ *
+ * The archive is written on a background thread, which waits for the client to catch up. It stops if the client goes
+ * away.
+ *
+ * @param resolved_entries relative path inside of the archive -> filesystem_id. See `resolve_entries`.
+ * @returns `None` if too many archives are being streamed already
+ */
+pub fn stream_archive(
+    target_endpoint: StorageEndpointRow,
+    resolved_entries: HashMap<String, String>,
+    format: StorageArchiveStreamFormat,
+    pool: RequestPool,
+) -> Option<StorageArchiveStream> {
+    let slot = ArchiveStreamSlot::take()?;
+
+    let (sender, receiver) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+
+    // Files inside of the same folder end up next to each other
+    let mut resolved_entries = resolved_entries
+        .into_iter()
+        .collect::<Vec<(String, String)>>();
+    resolved_entries.sort();
+
+    std::thread::spawn(move || {
+        let _slot = slot;
+
+        // The S3 client needs a tokio reactor
+        let runtime = actix_rt::Runtime::new().unwrap();
+
+        let mut writer = ChannelWriter {
+            sender,
+            buffer: Vec::with_capacity(STREAM_CHUNK_SIZE),
+        };
+
+        let write_result = runtime
+            .block_on(get_storage_backend(&target_endpoint, &pool))
+            .map_err(|err| io::Error::other(err.get_code()))
+            .and_then(|storage_backend| {
+                write_archive_stream(
+                    &runtime,
+                    storage_backend.as_ref(),
+                    &resolved_entries,
+                    format,
+                    &mut writer,
+                )
+            });
+
+        if let Err(err) = write_result {
+            if err.kind() != io::ErrorKind::BrokenPipe {
+                error!(
+                    "(storage archive stream) Could not write an archive. endpoint_id = {}. {}",
+                    target_endpoint.id, err
+                );
+
+                writer.abort();
+            }
+        }
+    });
+
+    Some(receiver)
+}
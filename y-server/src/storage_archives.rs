@@ -1,31 +1,583 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use std::{fs::remove_file, path::Path};
 
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use zip::ZipWriter;
+
+use crate::config::get_config;
+use crate::storage_backend::{get_storage_backend, StorageBackend};
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::{resolve_entries, StorageError};
 use crate::util::RequestPool;
+use crate::ws::WSState;
 use log::*;
 
+// TODO research what the best value would be
+const WRITE_FILE_CHUNK_SIZE: usize = 8192;
+
+// How many hours a finished archive is kept around if `storage.archives.retention_hours` is not set
+const DEFAULT_ARCHIVE_RETENTION_HOURS: i64 = 12;
+
+// Deflate level (0-9) if `storage.archives.compression_level` is not set
+const DEFAULT_ARCHIVE_COMPRESSION_LEVEL: i32 = 6;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+pub enum StorageArchiveError {
+    /// The archive has been cancelled by its owner while it was being written
+    Cancelled,
+
+    /// The files are larger than `storage.archives.max_size_bytes` (the limit is attached)
+    TooLarge(i64),
+
+    /// Details of what has gone wrong, they are shown to the owner of the archive
+    Internal(String),
+}
+
+impl StorageArchiveError {
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            StorageArchiveError::Cancelled => "storage.archive_cancelled",
+            StorageArchiveError::TooLarge(_) => "storage.archive_too_large",
+            StorageArchiveError::Internal(_) => "storage.internal",
+        }
+    }
+
+    pub fn get_details(&self) -> String {
+        match self {
+            StorageArchiveError::Cancelled => "The archive has been cancelled".to_string(),
+            StorageArchiveError::TooLarge(max_size_bytes) => format!(
+                "The files are larger than the maximum archive size ({} bytes)",
+                max_size_bytes
+            ),
+            StorageArchiveError::Internal(details) => details.clone(),
+        }
+    }
+}
+
+/// Where an archive is written to and kept until it expires
+pub fn get_archive_path(filesystem_id: &str) -> PathBuf {
+    Path::new("upload_staging").join(filesystem_id)
+}
+
+pub struct StorageArchiveSettings {
+    /**
+     * Deflate level, 0 means that the files are stored without compression
+     */
+    pub compression_level: i32,
+
+    /**
+     * Largest total size of the files that go into an archive. `None` if there is no limit
+     */
+    pub max_size_bytes: Option<i64>,
+}
+
+impl StorageArchiveSettings {
+    pub fn check_size(&self, total_bytes: i64) -> Result<(), StorageArchiveError> {
+        match self.max_size_bytes {
+            Some(max_size_bytes) if total_bytes > max_size_bytes => {
+                Err(StorageArchiveError::TooLarge(max_size_bytes))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+pub async fn get_archive_settings(pool: &RequestPool) -> StorageArchiveSettings {
+    let config = get_config(pool).await;
+
+    let compression_level = config
+        .get("storage.archives.compression_level")
+        .and_then(|value| value.parse::<i32>().ok())
+        .unwrap_or(DEFAULT_ARCHIVE_COMPRESSION_LEVEL)
+        .clamp(0, 9);
+
+    // 0 == no limit
+    let max_size_bytes = config
+        .get("storage.archives.max_size_bytes")
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|max_size_bytes| *max_size_bytes > 0);
+
+    StorageArchiveSettings {
+        compression_level,
+        max_size_bytes,
+    }
+}
+
+/**
+ * Total size of the files that would go into an archive. A file that appears under multiple paths is counted each time.
+ *
+ * @param resolved_entries relative path inside of the archive -> filesystem_id. See `resolve_entries`.
+ */
+pub async fn get_archive_total_size(
+    endpoint_id: i32,
+    resolved_entries: &HashMap<String, String>,
+    pool: &RequestPool,
+) -> Result<i64, StorageError> {
+    let filesystem_ids = resolved_entries
+        .values()
+        .map(|filesystem_id| filesystem_id.as_str())
+        .collect::<Vec<&str>>();
+
+    let blob_sizes = sqlx::query_as::<_, (String, i64)>(
+        "SELECT filesystem_id::TEXT, MAX(size_bytes) FROM storage_entries WHERE endpoint_id = $1 AND filesystem_id = ANY($2) GROUP BY filesystem_id",
+    )
+    .bind(endpoint_id)
+    .bind(filesystem_ids)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageError::Internal)?
+    .into_iter()
+    .collect::<HashMap<String, i64>>();
+
+    Ok(resolved_entries
+        .values()
+        .map(|filesystem_id| blob_sizes.get(filesystem_id).copied().unwrap_or(0))
+        .sum())
+}
+
+/**
+ * Progress of a user's archive. Stored with the archive and sent to its owner over the WebSocket.
+ *
+ * Storing the progress also tells if the archive is still wanted, cancelled archives are gone from the database.
+ */
+pub struct StorageArchiveProgress<'a> {
+    pub ws_state: &'a web::Data<Mutex<WSState>>,
+    pub pool: &'a RequestPool,
+    pub archive_id: i32,
+    pub user_id: i32,
+
+    pub total_files: i32,
+    pub total_bytes: i64,
+
+    processed_files: i32,
+    processed_bytes: i64,
+    last_sent_at: Option<Instant>,
+}
+
+impl<'a> StorageArchiveProgress<'a> {
+    pub fn new(
+        ws_state: &'a web::Data<Mutex<WSState>>,
+        pool: &'a RequestPool,
+        archive_id: i32,
+        user_id: i32,
+    ) -> Self {
+        StorageArchiveProgress {
+            ws_state,
+            pool,
+            archive_id,
+            user_id,
+            total_files: 0,
+            total_bytes: 0,
+            processed_files: 0,
+            processed_bytes: 0,
+            last_sent_at: None,
+        }
+    }
+
+    fn advance(
+        &mut self,
+        runtime: &actix_rt::Runtime,
+        processed_files: i32,
+        processed_bytes: i64,
+    ) -> Result<(), StorageArchiveError> {
+        self.processed_files += processed_files;
+        self.processed_bytes += processed_bytes;
+
+        let is_due = self
+            .last_sent_at
+            .is_none_or(|last_sent_at| last_sent_at.elapsed() >= PROGRESS_INTERVAL);
+
+        if !is_due {
+            return Ok(());
+        }
+
+        let still_running = runtime
+            .block_on(
+                sqlx::query_scalar::<_, i32>(
+                    "UPDATE storage_archives SET processed_files = $1, processed_bytes = $2 WHERE id = $3 AND status = 'running'::storage_archive_status RETURNING id",
+                )
+                .bind(self.processed_files)
+                .bind(self.processed_bytes)
+                .bind(self.archive_id)
+                .fetch_optional(self.pool),
+            )
+            .map_err(|err| StorageArchiveError::Internal(format!("Could not update the archive. {}", err)))?;
+
+        if still_running.is_none() {
+            return Err(StorageArchiveError::Cancelled);
+        }
+
+        self.send(runtime, "running", None);
+
+        Ok(())
+    }
+
+    /**
+     * @param status - "running", "failed" or "ready"
+     * @param error - Details of the failure
+     */
+    pub fn send(&mut self, runtime: &actix_rt::Runtime, status: &str, error: Option<&str>) {
+        self.last_sent_at = Some(Instant::now());
+
+        let ws_message = json!(
+            {
+                "type": "storage_user_archive_status_updated",
+                "payload": {
+                    "user_archive_id": self.archive_id,
+                    "status": status,
+                    "ready": status == "ready",
+                    "error": error,
+                    "processed_files": self.processed_files,
+                    "total_files": self.total_files,
+                    "processed_bytes": self.processed_bytes,
+                    "total_bytes": self.total_bytes
+                }
+            }
+        )
+        .to_string();
+
+        runtime.block_on(
+            self.ws_state
+                .lock()
+                .unwrap()
+                .send_to_user(self.user_id, ws_message.as_str()),
+        );
+    }
+}
+
+/**
+ * Write files into a new zip archive.
+ *
+ * Blocks, so it must be called from a background thread. The S3 client needs a tokio reactor, hence the `runtime`.
+ *
+ * @param resolved_entries relative path inside of the archive -> filesystem_id. See `resolve_entries`.
+ * @param progress - Reported for archives that are written by a job. Also used to notice that they were cancelled
+ * @returns size of the archive in bytes
+ */
+pub fn write_zip_archive(
+    runtime: &actix_rt::Runtime,
+    storage_backend: &dyn StorageBackend,
+    resolved_entries: &HashMap<String, String>,
+    zip_path: &Path,
+    settings: &StorageArchiveSettings,
+    mut progress: Option<&mut StorageArchiveProgress<'_>>,
+) -> Result<u64, StorageArchiveError> {
+    let zip_file = File::create(zip_path).map_err(|err| {
+        StorageArchiveError::Internal(format!("Could not create the archive. {}", err))
+    })?;
+
+    let mut zip = ZipWriter::new(zip_file);
+
+    let zip_options = if settings.compression_level == 0 {
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored)
+    } else {
+        zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .compression_level(Some(settings.compression_level))
+    };
+
+    // For each file that we have found
+    for (file_path_str, file_filesystem_id) in resolved_entries.iter() {
+        let could_not_read =
+            || StorageArchiveError::Internal(format!("Could not read \"{}\"", file_path_str));
+
+        let local_blob = runtime
+            .block_on(storage_backend.local_copy(file_filesystem_id))
+            .map_err(|_| could_not_read())?;
+
+        let mut file = File::open(local_blob.path()).map_err(|_| could_not_read())?;
+        let mut chunk = [0; WRITE_FILE_CHUNK_SIZE];
+
+        // Add that file to the zip archive (with a correct relative path)
+        zip.start_file(file_path_str, zip_options).map_err(|err| {
+            StorageArchiveError::Internal(format!(
+                "Could not add \"{}\" to the archive. {}",
+                file_path_str, err
+            ))
+        })?;
+
+        // And write the actual file's contents to the zip archive in chunks
+        loop {
+            let bytes_read = file.read(&mut chunk[..]).map_err(|_| could_not_read())?;
+
+            if bytes_read == 0 {
+                break;
+            }
+
+            zip.write_all(&chunk[..bytes_read]).map_err(|err| {
+                StorageArchiveError::Internal(format!("Could not write the archive. {}", err))
+            })?;
+
+            if let Some(progress) = &mut progress {
+                progress.advance(runtime, 0, bytes_read as i64)?;
+            }
+        }
+
+        if let Some(progress) = &mut progress {
+            progress.advance(runtime, 1, 0)?;
+        }
+    }
+
+    let zip_file = zip.finish().map_err(|err| {
+        StorageArchiveError::Internal(format!("Could not write the archive. {}", err))
+    })?;
+
+    let zip_file_medatada = zip_file.metadata().map_err(|err| {
+        StorageArchiveError::Internal(format!("Could not write the archive. {}", err))
+    })?;
+
+    Ok(zip_file_medatada.len())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StorageArchiveJobPayload {
+    pub archive_id: i32,
+    pub endpoint_id: i32,
+    pub user_id: i32,
+    pub folder_ids: Vec<i64>,
+    pub file_ids: Vec<i64>,
+}
+
+/**
+ * Find the files of an archive and write them.
+ *
+ * @returns size of the archive in bytes
+ */
+fn write_user_archive(
+    runtime: &actix_rt::Runtime,
+    pool: &RequestPool,
+    payload: &StorageArchiveJobPayload,
+    zip_path: &Path,
+    progress: &mut StorageArchiveProgress<'_>,
+) -> Result<u64, StorageArchiveError> {
+    let endpoint_id = payload.endpoint_id;
+
+    let target_endpoint = runtime
+        .block_on(get_storage_endpoint(endpoint_id, pool))
+        .map_err(|_| StorageArchiveError::Internal("The endpoint does not exist".to_string()))?;
+
+    let storage_backend = runtime
+        .block_on(get_storage_backend(&target_endpoint, pool))
+        .map_err(|_| {
+            StorageArchiveError::Internal("The endpoint's storage is not available".to_string())
+        })?;
+
+    // Based on the list of folder ids provided by the user, we need to find every
+    // entry that exists somewhere inside of the requested folders
+    let resolved_entries = runtime
+        .block_on(resolve_entries(
+            endpoint_id,
+            payload.folder_ids.clone(),
+            payload.file_ids.clone(),
+            pool,
+        ))
+        .map_err(|_| {
+            StorageArchiveError::Internal("Could not find the files of the archive".to_string())
+        })?;
+
+    let total_bytes = runtime
+        .block_on(get_archive_total_size(endpoint_id, &resolved_entries, pool))
+        .map_err(|_| {
+            StorageArchiveError::Internal("Could not find the files of the archive".to_string())
+        })?;
+
+    let settings = runtime.block_on(get_archive_settings(pool));
+
+    settings.check_size(total_bytes)?;
+
+    progress.total_files = resolved_entries.len() as i32;
+    progress.total_bytes = total_bytes;
+
+    runtime
+        .block_on(
+            sqlx::query(
+                "UPDATE storage_archives SET total_files = $1, total_bytes = $2 WHERE id = $3",
+            )
+            .bind(progress.total_files)
+            .bind(progress.total_bytes)
+            .bind(payload.archive_id)
+            .execute(pool),
+        )
+        .map_err(|err| {
+            StorageArchiveError::Internal(format!("Could not update the archive. {}", err))
+        })?;
+
+    progress.send(runtime, "running", None);
+
+    let zip_size_bytes = write_zip_archive(
+        runtime,
+        storage_backend.as_ref(),
+        &resolved_entries,
+        zip_path,
+        &settings,
+        Some(progress),
+    )?;
+
+    // Increment downloads count for each file that was included in the zip archive
+    let filesystem_ids = resolved_entries
+        .values()
+        .map(|filesystem_id| filesystem_id.as_str())
+        .collect::<Vec<&str>>();
+
+    let _ = runtime.block_on(
+        sqlx::query(
+            "UPDATE storage_entries SET downloads_count = downloads_count + 1 WHERE filesystem_id = ANY($1) AND endpoint_id = $2",
+        )
+        .bind(filesystem_ids)
+        .bind(endpoint_id)
+        .execute(pool),
+    );
+
+    Ok(zip_size_bytes)
+}
+
+/**
+ * Write a user's archive (see `storage_create_archive`) and notify them once it's ready.
+ *
+ * The progress is sent to the user while the archive is being written. If something goes wrong, the archive is marked
+ * as failed, along with the reason.
+ *
+ * Blocks, runs in a job worker.
+ */
+pub fn run_create_archive_job(
+    runtime: &actix_rt::Runtime,
+    pool: &RequestPool,
+    ws_state: &web::Data<Mutex<WSState>>,
+    payload: StorageArchiveJobPayload,
+) -> Result<(), String> {
+    #[derive(FromRow)]
+    struct StorageArchiveRow {
+        filesystem_id: String,
+        status: String,
+    }
+
+    let archive = runtime
+        .block_on(
+            sqlx::query_as::<_, StorageArchiveRow>(
+                "SELECT filesystem_id, status::TEXT FROM storage_archives WHERE id = $1 AND endpoint_id = $2",
+            )
+            .bind(payload.archive_id)
+            .bind(payload.endpoint_id)
+            .fetch_optional(pool),
+        )
+        .map_err(|err| format!("Could not get the archive. {}", err))?;
+
+    // The archive has been cancelled before we got to it, or it has already been written
+    let zip_file_uuid = match archive {
+        Some(archive) if archive.status != "ready" => archive.filesystem_id,
+        _ => return Ok(()),
+    };
+
+    runtime
+        .block_on(
+            sqlx::query(
+                "UPDATE storage_archives SET status = 'running'::storage_archive_status, error = NULL, processed_files = 0, processed_bytes = 0 WHERE id = $1",
+            )
+            .bind(payload.archive_id)
+            .execute(pool),
+        )
+        .map_err(|err| format!("Could not update the archive. {}", err))?;
+
+    let mut progress =
+        StorageArchiveProgress::new(ws_state, pool, payload.archive_id, payload.user_id);
+
+    let zip_path = get_archive_path(&zip_file_uuid);
+
+    let write_result = write_user_archive(runtime, pool, &payload, &zip_path, &mut progress);
+
+    let zip_size_bytes = match write_result {
+        Ok(zip_size_bytes) => zip_size_bytes,
+        Err(StorageArchiveError::Cancelled) => {
+            let _ = remove_file(&zip_path);
+
+            return Ok(());
+        }
+        Err(err) => {
+            let _ = remove_file(&zip_path);
+            let details = err.get_details();
+
+            let _ = runtime.block_on(
+                sqlx::query(
+                    "UPDATE storage_archives SET status = 'failed'::storage_archive_status, error = $1, finished_at = now() WHERE id = $2",
+                )
+                .bind(&details)
+                .bind(payload.archive_id)
+                .execute(pool),
+            );
+
+            progress.send(runtime, "failed", Some(&details));
+
+            // Archives that are too large will not get any smaller, there is no point in trying again
+            return match err {
+                StorageArchiveError::TooLarge(_) => Ok(()),
+                _ => Err(format!("Could not write the archive. {}", details)),
+            };
+        }
+    };
+
+    let is_wanted = runtime
+        .block_on(
+            sqlx::query_scalar::<_, i32>(
+                "UPDATE storage_archives SET status = 'ready'::storage_archive_status, size_bytes = $1, processed_files = total_files, processed_bytes = total_bytes, finished_at = now()
+                WHERE id = $2 AND status = 'running'::storage_archive_status RETURNING id",
+            )
+            .bind(zip_size_bytes as i64)
+            .bind(payload.archive_id)
+            .fetch_optional(pool),
+        )
+        .map_err(|err| format!("Could not update the archive. {}", err))?;
+
+    // Cancelled right before it was done
+    if is_wanted.is_none() {
+        let _ = remove_file(&zip_path);
+
+        return Ok(());
+    }
+
+    // Notify the user that the archive is ready
+    progress.processed_files = progress.total_files;
+    progress.processed_bytes = progress.total_bytes;
+    progress.send(runtime, "ready", None);
+
+    Ok(())
+}
+
 pub async fn cleanup_storage_archives(pool: &RequestPool) {
     info!("[scheduled] Cleaning up expired storage archives...");
 
-    // TODO configurable value
-    let latest_valid_date = chrono::Utc::now().naive_utc() - chrono::Duration::hours(12);
+    let retention_hours = get_config(pool)
+        .await
+        .get("storage.archives.retention_hours")
+        .and_then(|value| value.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_ARCHIVE_RETENTION_HOURS);
 
+    // 0 == keep finished archives until their owners discard them
+    if retention_hours == 0 {
+        return;
+    }
+
+    // Archives that are still being written are not expired, they might be downloaded right after they're done
     let db_delete_storage_archives = sqlx::query_scalar::<_, String>(
-        "DELETE FROM storage_archives WHERE created_at < $1 RETURNING filesystem_id",
+        "DELETE FROM storage_archives WHERE status IN ('ready'::storage_archive_status, 'failed'::storage_archive_status)
+        AND finished_at < now() - make_interval(hours => $1) RETURNING filesystem_id",
     )
-    .bind(latest_valid_date)
+    .bind(retention_hours as i32)
     .fetch_all(pool)
     .await;
 
     match db_delete_storage_archives {
         Ok(db_delete_storage_archives) => {
             for file_filesystem_id in db_delete_storage_archives.into_iter() {
-                let file_path = Path::new("upload_staging")
-                    .join(&file_filesystem_id)
-                    .to_str()
-                    .unwrap()
-                    .to_string();
-
-                let _ = remove_file(file_path);
+                let _ = remove_file(get_archive_path(&file_filesystem_id));
             }
         }
         Err(error) => {
@@ -33,8 +585,6 @@ pub async fn cleanup_storage_archives(pool: &RequestPool) {
                 "Could not delete expired storage archive rows from the database. {}",
                 error
             );
-
-            return;
         }
     }
 }
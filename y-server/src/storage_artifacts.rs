@@ -0,0 +1,418 @@
+use std::fs;
+use std::sync::Mutex;
+
+use actix_web::web;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::get_config;
+use crate::jobs::{
+    enqueue_job, enqueue_jobs, JOB_STORAGE_GENERATE_ARTIFACTS, JOB_STORAGE_TRANSCODE_VIDEO,
+    JOB_STORAGE_TRANSCODE_VIDEO_HLS,
+};
+use crate::storage_backend::get_storage_backend;
+use crate::storage_endpoint::{get_storage_endpoint, StorageEndpointRow};
+use crate::storage_entry::{
+    generate_audio_entry_cover_thumbnail, generate_browser_friendly_video,
+    generate_image_entry_thumbnail, generate_video_entry_thumbnails,
+};
+use crate::storage_metadata::{extract_media_metadata, save_media_metadata};
+use crate::util::RequestPool;
+use crate::ws::{send_storage_location_updated, WSState};
+
+const MAX_FILE_SIZE_FOR_THUMNAIL_GENERATION: u64 = 50_000_000;
+
+#[derive(Serialize, Deserialize)]
+pub struct StorageArtifactsJobPayload {
+    pub endpoint_id: i32,
+    pub filesystem_id: String,
+}
+
+/**
+ * Queue extraction of media metadata and generation of thumbnails (and browser friendly versions of videos) for
+ * freshly uploaded files. Artifacts are skipped on endpoints without an artifacts path.
+ *
+ * One job is queued for each file, the function returns immediately. See `run_generate_artifacts_job`.
+ *
+ * @param uploaded_files - Filesystem ids of the files that were uploaded
+ */
+pub async fn queue_uploaded_files_artifacts(
+    target_endpoint: &StorageEndpointRow,
+    uploaded_files: Vec<String>,
+    created_by: Option<i32>,
+    pool: &RequestPool,
+) {
+    let payloads = uploaded_files
+        .into_iter()
+        .map(|filesystem_id| StorageArtifactsJobPayload {
+            endpoint_id: target_endpoint.id,
+            filesystem_id,
+        })
+        .collect::<Vec<StorageArtifactsJobPayload>>();
+
+    if enqueue_jobs(JOB_STORAGE_GENERATE_ARTIFACTS, &payloads, created_by, pool)
+        .await
+        .is_err()
+    {
+        error!(
+            "Could not queue artifacts generation for {} uploaded files of endpoint {}",
+            payloads.len(),
+            target_endpoint.id
+        );
+    }
+}
+
+/**
+ * Folders that contain files pointing to a blob. Those are refreshed for the clients once the blob's artifacts are ready.
+ */
+pub async fn get_blob_parent_folders(
+    endpoint_id: i32,
+    filesystem_id: &str,
+    pool: &RequestPool,
+) -> Result<Vec<Option<i64>>, String> {
+    sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT DISTINCT parent_folder FROM storage_entries WHERE endpoint_id = $1 AND filesystem_id = $2::BPCHAR",
+    )
+    .bind(endpoint_id)
+    .bind(filesystem_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|err| format!("Could not get the files of the blob. {}", err))
+}
+
+/**
+ * Extract the media metadata of an uploaded file and generate its thumbnails. Videos are then queued for transcoding
+ * and HLS renditions, if those are enabled.
+ *
+ * Blocks, runs in a job worker.
+ */
+pub fn run_generate_artifacts_job(
+    runtime: &actix_rt::Runtime,
+    pool: &RequestPool,
+    ws_state: &web::Data<Mutex<WSState>>,
+    payload: StorageArtifactsJobPayload,
+) -> Result<(), String> {
+    let endpoint_id = payload.endpoint_id;
+    let filesystem_id = payload.filesystem_id.as_str();
+
+    let storage_config = runtime.block_on(get_config(pool));
+
+    let generate_image_thumbnails =
+        storage_config.get("storage.generate_thumbnails.image") == Some(&"true".to_string());
+
+    let generate_video_thumbnails =
+        storage_config.get("storage.generate_thumbnails.video") == Some(&"true".to_string());
+
+    let generate_audio_thumbnails =
+        storage_config.get("storage.generate_thumbnails.audio") == Some(&"true".to_string());
+
+    let generate_seeking_thumbnails = storage_config
+        .get("storage.generate_seeking_thumbnails.enabled")
+        == Some(&"true".to_string());
+
+    let seeking_thumbnails_frames_count = storage_config
+        .get("storage.generate_seeking_thumbnails.desired_frames")
+        .unwrap_or(&"10".to_string())
+        .parse::<u32>()
+        .unwrap_or(10);
+
+    let transcoding_enabled =
+        storage_config.get("storage.transcode_videos.enabled") == Some(&"true".to_string());
+
+    let hls_enabled =
+        storage_config.get("storage.transcode_videos.hls.enabled") == Some(&"true".to_string());
+
+    let target_endpoint = runtime
+        .block_on(get_storage_endpoint(endpoint_id, pool))
+        .map_err(|_| format!("Endpoint {} does not exist", endpoint_id))?;
+
+    let parent_folders =
+        runtime.block_on(get_blob_parent_folders(endpoint_id, filesystem_id, pool))?;
+
+    // The file was deleted before we got to it
+    if parent_folders.is_empty() {
+        return Ok(());
+    }
+
+    let storage_backend = runtime
+        .block_on(get_storage_backend(&target_endpoint, pool))
+        .map_err(|_| {
+            format!(
+                "Could not get the storage backend of endpoint {}",
+                endpoint_id
+            )
+        })?;
+
+    let local_blob = runtime
+        .block_on(storage_backend.local_copy(filesystem_id))
+        .map_err(|_| {
+            format!(
+                "Could not get a local copy of an uploaded file ({})",
+                filesystem_id
+            )
+        })?;
+
+    let path = local_blob.path();
+
+    let mime_type = infer::get_from_path(path)
+        .ok()
+        .flatten()
+        .map(|file_kind| file_kind.mime_type());
+
+    let media_metadata = extract_media_metadata(path, mime_type);
+    let media_metadata_extracted = media_metadata.is_some();
+
+    if let Some(media_metadata) = media_metadata {
+        runtime
+            .block_on(save_media_metadata(
+                endpoint_id,
+                filesystem_id,
+                &media_metadata,
+                pool,
+            ))
+            .map_err(|_| {
+                format!(
+                    "Could not save the media metadata of an uploaded file ({})",
+                    filesystem_id
+                )
+            })?;
+    }
+
+    let target_endpoint_artifacts_path = match &target_endpoint.artifacts_path {
+        Some(artifacts_path) => artifacts_path.as_str(),
+        None => {
+            if media_metadata_extracted {
+                runtime.block_on(send_storage_location_updated(
+                    ws_state,
+                    None,
+                    endpoint_id,
+                    parent_folders,
+                    true,
+                    false,
+                ));
+            }
+
+            return Ok(());
+        }
+    };
+
+    let mut transcode_video = false;
+    let mut transcode_video_hls = false;
+
+    match mime_type {
+        Some("image/jpeg" | "image/png" | "image/gif" | "image/webp" | "image/bmp")
+            if generate_image_thumbnails =>
+        {
+            let file_metadata = fs::metadata(path)
+                .map_err(|_| format!("Could not read an uploaded file ({})", filesystem_id))?;
+
+            if file_metadata.len() <= MAX_FILE_SIZE_FOR_THUMNAIL_GENERATION {
+                generate_image_entry_thumbnail(filesystem_id, path, target_endpoint_artifacts_path)
+                    .map_err(|_| {
+                        format!(
+                            "Failed to create a thumbnail for an uploaded image file ({})",
+                            filesystem_id
+                        )
+                    })?;
+            }
+        }
+
+        Some(
+            "video/mp4" | "video/webm" | "video/mov" | "video/avi" | "video/mpeg"
+            | "video/quicktime" | "video/x-msvideo",
+        ) => {
+            if generate_video_thumbnails {
+                generate_video_entry_thumbnails(
+                    filesystem_id,
+                    path,
+                    target_endpoint_artifacts_path,
+                    if generate_seeking_thumbnails {
+                        Some(seeking_thumbnails_frames_count)
+                    } else {
+                        None
+                    },
+                )
+                .map_err(|_| {
+                    format!(
+                        "Failed to create a thumbnail for an uploaded video file ({})",
+                        filesystem_id
+                    )
+                })?;
+            }
+
+            transcode_video = transcoding_enabled;
+            transcode_video_hls = hls_enabled;
+        }
+
+        Some("audio/mpeg" | "audio/x-flac" | "audio/x-wav" | "audio/aac")
+            if generate_audio_thumbnails =>
+        {
+            generate_audio_entry_cover_thumbnail(
+                filesystem_id,
+                path,
+                target_endpoint_artifacts_path,
+            )
+            .map_err(|_| {
+                format!(
+                    "Failed to create a cover image thumbnail for an uploaded audio file ({})",
+                    filesystem_id
+                )
+            })?;
+        }
+
+        _ => {}
+    }
+
+    if transcode_video {
+        runtime
+            .block_on(
+                sqlx::query("UPDATE storage_entries SET transcoded_version_available = FALSE WHERE endpoint_id = $1 AND filesystem_id = $2")
+                    .bind(endpoint_id)
+                    .bind(filesystem_id)
+                    .execute(pool),
+            )
+            .map_err(|err| format!("Could not update the files of the blob. {}", err))?;
+
+        // Transcoding takes a while, it should not hold up thumbnails of other files
+        runtime
+            .block_on(enqueue_job(
+                JOB_STORAGE_TRANSCODE_VIDEO,
+                &payload,
+                None,
+                pool,
+            ))
+            .map_err(|_| {
+                format!(
+                    "Could not queue transcoding of a video file ({})",
+                    filesystem_id
+                )
+            })?;
+    }
+
+    if transcode_video_hls {
+        runtime
+            .block_on(enqueue_job(
+                JOB_STORAGE_TRANSCODE_VIDEO_HLS,
+                &payload,
+                None,
+                pool,
+            ))
+            .map_err(|_| {
+                format!(
+                    "Could not queue HLS transcoding of a video file ({})",
+                    filesystem_id
+                )
+            })?;
+    }
+
+    // Refresh folders when thumbnails are ready
+    runtime.block_on(send_storage_location_updated(
+        ws_state,
+        None,
+        endpoint_id,
+        parent_folders,
+        transcode_video || media_metadata_extracted,
+        true,
+    ));
+
+    Ok(())
+}
+
+/**
+ * Create a browser friendly version of an uploaded video.
+ *
+ * Blocks, runs in a job worker.
+ */
+pub fn run_transcode_video_job(
+    runtime: &actix_rt::Runtime,
+    pool: &RequestPool,
+    ws_state: &web::Data<Mutex<WSState>>,
+    payload: StorageArtifactsJobPayload,
+) -> Result<(), String> {
+    let endpoint_id = payload.endpoint_id;
+    let filesystem_id = payload.filesystem_id.as_str();
+
+    let storage_config = runtime.block_on(get_config(pool));
+
+    let target_height = storage_config
+        .get("storage.transcode_videos.target_height")
+        .unwrap_or(&"720".to_string())
+        .parse::<u32>()
+        .unwrap_or(720);
+
+    let target_bitrate = storage_config
+        .get("storage.transcode_videos.target_bitrate")
+        .unwrap_or(&"4000".to_string())
+        .parse::<u32>()
+        .unwrap_or(4000);
+
+    let target_endpoint = runtime
+        .block_on(get_storage_endpoint(endpoint_id, pool))
+        .map_err(|_| format!("Endpoint {} does not exist", endpoint_id))?;
+
+    let target_endpoint_artifacts_path = match &target_endpoint.artifacts_path {
+        Some(artifacts_path) => artifacts_path.as_str(),
+        None => return Ok(()),
+    };
+
+    // The file was deleted before we got to it
+    if runtime
+        .block_on(get_blob_parent_folders(endpoint_id, filesystem_id, pool))?
+        .is_empty()
+    {
+        return Ok(());
+    }
+
+    let storage_backend = runtime
+        .block_on(get_storage_backend(&target_endpoint, pool))
+        .map_err(|_| {
+            format!(
+                "Could not get the storage backend of endpoint {}",
+                endpoint_id
+            )
+        })?;
+
+    let local_blob = runtime
+        .block_on(storage_backend.local_copy(filesystem_id))
+        .map_err(|_| {
+            format!(
+                "Could not get a local copy of an uploaded file ({})",
+                filesystem_id
+            )
+        })?;
+
+    generate_browser_friendly_video(
+        filesystem_id,
+        local_blob.path(),
+        target_endpoint_artifacts_path,
+        target_height,
+        target_bitrate,
+    )
+    .map_err(|_| {
+        format!(
+            "Failed to create a browser friendly preview for an uploaded video file ({})",
+            filesystem_id
+        )
+    })?;
+
+    let parent_folders = runtime
+        .block_on(
+            sqlx::query_scalar::<_, Option<i64>>("UPDATE storage_entries SET transcoded_version_available = TRUE WHERE endpoint_id = $1 AND filesystem_id = $2 RETURNING parent_folder")
+                .bind(endpoint_id)
+                .bind(filesystem_id)
+                .fetch_all(pool),
+        )
+        .map_err(|err| format!("Could not update the files of the blob. {}", err))?;
+
+    // Refresh folders when the video is ready
+    runtime.block_on(send_storage_location_updated(
+        ws_state,
+        None,
+        endpoint_id,
+        parent_folders,
+        true,
+        false,
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,545 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::primitives::{ByteStream, Length};
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use log::*;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::storage_endpoint::StorageEndpointRow;
+use crate::storage_entry::StorageError;
+use crate::storage_layout::LocalBlobLayout;
+use crate::util::RequestPool;
+
+// Files larger than this are uploaded to S3 in multiple parts. A single PUT request can not be larger than 5 GiB
+const S3_MULTIPART_THRESHOLD: u64 = 64 * 1024 * 1024;
+const S3_MULTIPART_PART_SIZE: u64 = 64 * 1024 * 1024;
+
+// Everything except for RFC 3986 unreserved characters and "/" gets percent-encoded inside of an S3 copy source
+const S3_COPY_SOURCE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~')
+    .remove(b'/');
+
+/**
+ * A file that can be accessed on the local filesystem.
+ *
+ * Some things (thumbnail generation, archive creation) can only work with local files. For backends that
+ * store files elsewhere, a temporary copy is downloaded. The temporary copy is removed once this is dropped.
+ */
+pub struct LocalBlob {
+    path: PathBuf,
+    temporary: bool,
+}
+
+impl LocalBlob {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for LocalBlob {
+    fn drop(&mut self) {
+        if self.temporary {
+            fs::remove_file(&self.path).unwrap_or(());
+        }
+    }
+}
+
+/**
+ * Where the blobs of a storage endpoint physically live.
+ *
+ * Blobs are addressed by the `filesystem_id` of their storage entries.
+ */
+#[async_trait(?Send)]
+pub trait StorageBackend: Send + Sync {
+    /// Local path where a new blob should be written before it is committed with `commit`
+    fn staging_path(&self, filesystem_id: &str) -> PathBuf;
+
+    /// Store a blob that has been written to `staging_path`. The staged file is consumed
+    async fn commit(&self, filesystem_id: &str) -> Result<(), StorageError>;
+
+    /// Build a response with the contents of a blob. Honors the `Range` header of the request
+    async fn serve(
+        &self,
+        filesystem_id: &str,
+        req: &HttpRequest,
+    ) -> Result<HttpResponse, StorageError>;
+
+    /// Get a copy of a blob that can be read from the local filesystem
+    async fn local_copy(&self, filesystem_id: &str) -> Result<LocalBlob, StorageError>;
+
+    async fn copy(
+        &self,
+        source_filesystem_id: &str,
+        target_filesystem_id: &str,
+    ) -> Result<(), StorageError>;
+
+    async fn delete(&self, filesystem_id: &str) -> Result<(), StorageError>;
+}
+
+pub struct LocalFsBackend {
+    layout: LocalBlobLayout,
+    pool: RequestPool,
+}
+
+impl LocalFsBackend {
+    pub fn new(endpoint: &StorageEndpointRow, pool: &RequestPool) -> Self {
+        LocalFsBackend {
+            layout: LocalBlobLayout::new(endpoint),
+            pool: pool.clone(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl StorageBackend for LocalFsBackend {
+    fn staging_path(&self, filesystem_id: &str) -> PathBuf {
+        // Files are written straight into the endpoint, there is nothing left to do on commit
+        self.layout.new_blob_path(filesystem_id)
+    }
+
+    async fn commit(&self, _filesystem_id: &str) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    async fn serve(
+        &self,
+        filesystem_id: &str,
+        req: &HttpRequest,
+    ) -> Result<HttpResponse, StorageError> {
+        let file =
+            actix_files::NamedFile::open(self.layout.blob_path(filesystem_id, &self.pool).await);
+
+        match file {
+            Ok(file) => Ok(file.into_response(req)),
+            Err(_) => Err(StorageError::Internal),
+        }
+    }
+
+    async fn local_copy(&self, filesystem_id: &str) -> Result<LocalBlob, StorageError> {
+        Ok(LocalBlob {
+            path: self.layout.blob_path(filesystem_id, &self.pool).await,
+            temporary: false,
+        })
+    }
+
+    async fn copy(
+        &self,
+        source_filesystem_id: &str,
+        target_filesystem_id: &str,
+    ) -> Result<(), StorageError> {
+        fs::copy(
+            self.layout
+                .blob_path(source_filesystem_id, &self.pool)
+                .await,
+            self.staging_path(target_filesystem_id),
+        )
+        .map(|_| ())
+        .map_err(|_| StorageError::Internal)
+    }
+
+    async fn delete(&self, filesystem_id: &str) -> Result<(), StorageError> {
+        fs::remove_file(self.layout.blob_path(filesystem_id, &self.pool).await)
+            .map_err(|_| StorageError::Internal)
+    }
+}
+
+#[derive(FromRow)]
+pub struct StorageS3EndpointConfig {
+    pub bucket: String,
+    pub prefix: Option<String>,
+    pub region: String,
+    pub endpoint_url: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+}
+
+pub struct S3Backend {
+    client: Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3Backend {
+    /**
+     * Create a client for an S3 (or S3-compatible, like MinIO) bucket.
+     *
+     * If the access keys are not set, credentials are resolved the usual AWS way (`AWS_ACCESS_KEY_ID` and
+     * `AWS_SECRET_ACCESS_KEY` env variables, etc.).
+     */
+    pub async fn new(config: &StorageS3EndpointConfig) -> Self {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(config.region.clone()));
+
+        if let (Some(access_key_id), Some(secret_access_key)) =
+            (&config.access_key_id, &config.secret_access_key)
+        {
+            loader = loader.credentials_provider(Credentials::new(
+                access_key_id,
+                secret_access_key,
+                None,
+                None,
+                "y-storage-endpoint",
+            ));
+        }
+
+        if let Some(endpoint_url) = &config.endpoint_url {
+            loader = loader.endpoint_url(endpoint_url);
+        }
+
+        let sdk_config = loader.load().await;
+
+        // Self-hosted S3-compatible servers usually don't support virtual-hosted-style requests
+        let s3_config = aws_sdk_s3::config::Builder::from(&sdk_config)
+            .force_path_style(config.endpoint_url.is_some())
+            .build();
+
+        let prefix = config
+            .prefix
+            .as_deref()
+            .unwrap_or("")
+            .trim_matches('/')
+            .to_string();
+
+        S3Backend {
+            client: Client::from_conf(s3_config),
+            bucket: config.bucket.clone(),
+            prefix: if prefix.is_empty() {
+                prefix
+            } else {
+                format!("{}/", prefix)
+            },
+        }
+    }
+
+    fn key(&self, filesystem_id: &str) -> String {
+        format!("{}{}", self.prefix, filesystem_id)
+    }
+
+    /// Make sure we can write to and delete from the bucket
+    pub async fn check_access(&self) -> bool {
+        let key = self.key("test_file");
+
+        let put_result = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .body(ByteStream::from_static(b"you can delete me"))
+            .send()
+            .await;
+
+        if let Err(error) = put_result {
+            warn!("(storage backend -> s3 check access) {}", error);
+
+            return false;
+        }
+
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .is_ok()
+    }
+
+    async fn upload_multipart(
+        &self,
+        key: &str,
+        staging_path: &Path,
+        size: u64,
+    ) -> Result<(), StorageError> {
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|_| StorageError::Internal)?;
+
+        let upload_id = upload.upload_id().ok_or(StorageError::Internal)?;
+
+        let mut completed_parts: Vec<CompletedPart> = Vec::new();
+        let mut offset: u64 = 0;
+        let mut part_number: i32 = 1;
+
+        while offset < size {
+            let part_size = S3_MULTIPART_PART_SIZE.min(size - offset);
+
+            let part_result = match ByteStream::read_from()
+                .path(staging_path)
+                .offset(offset)
+                .length(Length::Exact(part_size))
+                .build()
+                .await
+            {
+                Ok(body) => self
+                    .client
+                    .upload_part()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .upload_id(upload_id)
+                    .part_number(part_number)
+                    .body(body)
+                    .send()
+                    .await
+                    .map_err(|error| error.to_string()),
+                Err(error) => Err(error.to_string()),
+            };
+
+            match part_result {
+                Ok(part) => {
+                    completed_parts.push(
+                        CompletedPart::builder()
+                            .e_tag(part.e_tag().unwrap_or_default())
+                            .part_number(part_number)
+                            .build(),
+                    );
+                }
+                Err(error) => {
+                    error!(
+                        "(storage backend -> s3 upload multipart) Could not upload a part. {}",
+                        error
+                    );
+
+                    let _ = self
+                        .client
+                        .abort_multipart_upload()
+                        .bucket(&self.bucket)
+                        .key(key)
+                        .upload_id(upload_id)
+                        .send()
+                        .await;
+
+                    return Err(StorageError::Internal);
+                }
+            }
+
+            offset += part_size;
+            part_number += 1;
+        }
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(completed_parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|_| StorageError::Internal)
+    }
+}
+
+#[async_trait(?Send)]
+impl StorageBackend for S3Backend {
+    fn staging_path(&self, filesystem_id: &str) -> PathBuf {
+        Path::new("upload_staging").join(filesystem_id)
+    }
+
+    async fn commit(&self, filesystem_id: &str) -> Result<(), StorageError> {
+        let staging_path = self.staging_path(filesystem_id);
+        let key = self.key(filesystem_id);
+
+        let size = fs::metadata(&staging_path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+
+        let result = if size > S3_MULTIPART_THRESHOLD {
+            self.upload_multipart(&key, &staging_path, size).await
+        } else {
+            match ByteStream::from_path(&staging_path).await {
+                Ok(body) => self
+                    .client
+                    .put_object()
+                    .bucket(&self.bucket)
+                    .key(&key)
+                    .body(body)
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(|error| {
+                        error!("(storage backend -> s3 commit) {}", error);
+
+                        StorageError::Internal
+                    }),
+                Err(_) => Err(StorageError::Internal),
+            }
+        };
+
+        fs::remove_file(&staging_path).unwrap_or(());
+
+        result
+    }
+
+    async fn serve(
+        &self,
+        filesystem_id: &str,
+        req: &HttpRequest,
+    ) -> Result<HttpResponse, StorageError> {
+        // S3 understands the same `Range` header syntax, so we just pass it through. Multiple ranges are not supported
+        let range = req
+            .headers()
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with("bytes=") && !value.contains(','));
+
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(filesystem_id))
+            .set_range(range.map(|range| range.to_string()))
+            .send()
+            .await;
+
+        let object = match object {
+            Ok(object) => object,
+            Err(error) => {
+                if error
+                    .raw_response()
+                    .map(|response| response.status().as_u16())
+                    == Some(416)
+                {
+                    return Ok(HttpResponse::build(StatusCode::RANGE_NOT_SATISFIABLE).finish());
+                }
+
+                error!("(storage backend -> s3 serve) {}", error);
+
+                return Err(StorageError::Internal);
+            }
+        };
+
+        let mut response = if let Some(content_range) = object.content_range() {
+            let mut response = HttpResponse::build(StatusCode::PARTIAL_CONTENT);
+            response.insert_header((header::CONTENT_RANGE, content_range));
+
+            response
+        } else {
+            HttpResponse::Ok()
+        };
+
+        response.insert_header((header::ACCEPT_RANGES, "bytes"));
+
+        if let Some(content_length) = object.content_length() {
+            response.no_chunking(content_length as u64);
+        }
+
+        let body = futures::stream::unfold(object.body, |mut body| async move {
+            body.next().await.map(|chunk| (chunk, body))
+        });
+
+        Ok(response.streaming(body))
+    }
+
+    async fn local_copy(&self, filesystem_id: &str) -> Result<LocalBlob, StorageError> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(filesystem_id))
+            .send()
+            .await
+            .map_err(|_| StorageError::Internal)?;
+
+        let local_blob = LocalBlob {
+            path: Path::new("upload_staging").join(Uuid::new_v4().to_string()),
+            temporary: true,
+        };
+
+        let mut file = fs::File::create(local_blob.path()).map_err(|_| StorageError::Internal)?;
+        let mut body = object.body;
+
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|_| StorageError::Internal)?;
+
+            file.write_all(&chunk).map_err(|_| StorageError::Internal)?;
+        }
+
+        Ok(local_blob)
+    }
+
+    // TODO objects larger than 5 GiB can only be copied with a multipart upload (UploadPartCopy)
+    async fn copy(
+        &self,
+        source_filesystem_id: &str,
+        target_filesystem_id: &str,
+    ) -> Result<(), StorageError> {
+        let copy_source = utf8_percent_encode(
+            format!("{}/{}", self.bucket, self.key(source_filesystem_id)).as_str(),
+            S3_COPY_SOURCE_ENCODE_SET,
+        )
+        .to_string();
+
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .copy_source(copy_source)
+            .key(self.key(target_filesystem_id))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|_| StorageError::Internal)
+    }
+
+    async fn delete(&self, filesystem_id: &str) -> Result<(), StorageError> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(self.key(filesystem_id))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|_| StorageError::Internal)
+    }
+}
+
+pub async fn get_s3_endpoint_config(
+    endpoint_id: i32,
+    pool: &RequestPool,
+) -> Result<StorageS3EndpointConfig, sqlx::Error> {
+    sqlx::query_as::<_, StorageS3EndpointConfig>(
+        "SELECT bucket, prefix, region, endpoint_url, access_key_id, secret_access_key FROM storage_s3_endpoints WHERE endpoint_id = $1",
+    )
+    .bind(endpoint_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Get the backend that stores the blobs of an endpoint
+// TODO cache the backends, so we don't have to create a new S3 client for each request
+pub async fn get_storage_backend(
+    endpoint: &StorageEndpointRow,
+    pool: &RequestPool,
+) -> Result<Box<dyn StorageBackend>, StorageError> {
+    match endpoint.endpoint_type.as_str() {
+        "local_fs" => Ok(Box::new(LocalFsBackend::new(endpoint, pool))),
+        "s3" => {
+            let config = get_s3_endpoint_config(endpoint.id, pool).await;
+
+            match config {
+                Ok(config) => Ok(Box::new(S3Backend::new(&config).await)),
+                Err(_) => Err(StorageError::EndpointNotFound),
+            }
+        }
+        _ => Err(StorageError::EndpointNotFound),
+    }
+}
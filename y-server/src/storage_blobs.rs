@@ -0,0 +1,312 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use log::*;
+use sha2::{Digest, Sha256};
+use sqlx::{Postgres, Transaction};
+
+use crate::storage_backend::StorageBackend;
+use crate::storage_endpoint::StorageEndpointRow;
+use crate::storage_entry::StorageError;
+use crate::util::RequestPool;
+
+/**
+ * Hex encoded SHA-256 of everything that was fed into a hasher
+ */
+pub fn finalize_blob_hash(hasher: Sha256) -> String {
+    hex::encode(hasher.finalize())
+}
+
+/**
+ * Compute the SHA-256 of a file on the local filesystem
+ */
+pub fn hash_local_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(finalize_blob_hash(hasher))
+}
+
+/**
+ * Blob reservations older than this are ignored. They are normally removed as soon as the file that reuses the blob has
+ * been created, this only matters if that never happens.
+ */
+const BLOB_RESERVATION_LIFETIME_MINUTES: i32 = 60;
+
+/**
+ * A blob that a new file is going to point to, see `store_staged_blob` and `reuse_duplicate_blob`
+ */
+pub struct StoredBlob {
+    pub filesystem_id: String,
+
+    /**
+     * Set if an existing blob is reused. Keeps the blob from being deleted until the file has been created,
+     * see `release_stored_blob`
+     */
+    reservation_id: Option<i64>,
+}
+
+impl StoredBlob {
+    /**
+     * A blob that has just been stored and is not used by anything else yet
+     */
+    pub fn new(filesystem_id: String) -> StoredBlob {
+        StoredBlob {
+            filesystem_id,
+            reservation_id: None,
+        }
+    }
+
+    /**
+     * Whether the blob has just been stored, rather than an existing one reused
+     */
+    pub fn is_new(&self) -> bool {
+        self.reservation_id.is_none()
+    }
+}
+
+/**
+ * Find a blob with the given contents that is already used by some file on the endpoint.
+ *
+ * The file is locked (`FOR SHARE`) until the transaction ends, so that it can not be deleted along with the blob before
+ * whatever reuses the blob has been recorded in the same transaction.
+ *
+ * @returns filesystem_id of the blob. Always `None` if deduplication is disabled for the endpoint
+ */
+pub async fn find_duplicate_blob(
+    target_endpoint: &StorageEndpointRow,
+    sha256: &str,
+    size_bytes: i64,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Option<String> {
+    if !target_endpoint.deduplication_enabled {
+        return None;
+    }
+
+    let duplicate = sqlx::query_scalar::<_, String>(
+        "SELECT filesystem_id FROM storage_entries WHERE endpoint_id = $1 AND sha256 = $2 AND size_bytes = $3 AND entry_type = 'file'::storage_entry_type AND filesystem_id IS NOT NULL LIMIT 1 FOR SHARE",
+    )
+    .bind(target_endpoint.id)
+    .bind(sha256)
+    .bind(size_bytes)
+    .fetch_optional(&mut **transaction)
+    .await;
+
+    match duplicate {
+        Ok(duplicate) => duplicate,
+        Err(err) => {
+            error!(
+                "(storage blobs) Could not look for a duplicate blob. endpoint_id = {}. {}",
+                target_endpoint.id, err
+            );
+
+            None
+        }
+    }
+}
+
+/**
+ * Find a duplicate blob and reserve it, so that it is kept even if the files that use it are deleted before the new
+ * file has been created
+ */
+pub async fn reuse_duplicate_blob(
+    target_endpoint: &StorageEndpointRow,
+    sha256: &str,
+    size_bytes: i64,
+    pool: &RequestPool,
+) -> Option<StoredBlob> {
+    if !target_endpoint.deduplication_enabled {
+        return None;
+    }
+
+    let reservation: Result<Option<StoredBlob>, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+
+        let duplicate =
+            find_duplicate_blob(target_endpoint, sha256, size_bytes, &mut transaction).await;
+
+        if duplicate.is_none() {
+            return Ok(None);
+        }
+
+        let duplicate = duplicate.unwrap();
+
+        sqlx::query(
+            "DELETE FROM storage_blob_reservations WHERE created_at < now() - make_interval(mins => $1)",
+        )
+        .bind(BLOB_RESERVATION_LIFETIME_MINUTES)
+        .execute(&mut *transaction)
+        .await?;
+
+        let reservation_id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO storage_blob_reservations (endpoint_id, filesystem_id) VALUES ($1, $2) RETURNING id",
+        )
+        .bind(target_endpoint.id)
+        .bind(&duplicate)
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(Some(StoredBlob {
+            filesystem_id: duplicate,
+            reservation_id: Some(reservation_id),
+        }))
+    }
+    .await;
+
+    match reservation {
+        Ok(reservation) => reservation,
+        Err(err) => {
+            // The caller simply stores a new blob
+            error!(
+                "(storage blobs) Could not reserve a duplicate blob. endpoint_id = {}. {}",
+                target_endpoint.id, err
+            );
+
+            None
+        }
+    }
+}
+
+/**
+ * Store a blob that has been written to the backend's staging path.
+ *
+ * If the endpoint already has a blob with the same contents (and deduplication is enabled), the staged file is discarded
+ * and the existing blob is used instead. `release_stored_blob` must be called once the new file has been created
+ * (or could not be created).
+ */
+pub async fn store_staged_blob(
+    target_endpoint: &StorageEndpointRow,
+    storage_backend: &dyn StorageBackend,
+    staged_filesystem_id: &str,
+    sha256: &str,
+    size_bytes: i64,
+    pool: &RequestPool,
+) -> Result<StoredBlob, StorageError> {
+    if let Some(duplicate) = reuse_duplicate_blob(target_endpoint, sha256, size_bytes, pool).await {
+        fs::remove_file(storage_backend.staging_path(staged_filesystem_id)).unwrap_or(());
+
+        return Ok(duplicate);
+    }
+
+    storage_backend.commit(staged_filesystem_id).await?;
+
+    Ok(StoredBlob::new(staged_filesystem_id.to_string()))
+}
+
+/**
+ * Remove the reservation of a reused blob. From now on, the blob is kept only if something references it.
+ */
+pub async fn release_stored_blob(stored_blob: &StoredBlob, pool: &RequestPool) {
+    if let Some(reservation_id) = stored_blob.reservation_id {
+        let delete_result = sqlx::query("DELETE FROM storage_blob_reservations WHERE id = $1")
+            .bind(reservation_id)
+            .execute(pool)
+            .await;
+
+        if let Err(err) = delete_result {
+            error!(
+                "(storage blobs) Could not release a blob reservation. reservation_id = {}. {}",
+                reservation_id, err
+            );
+        }
+    }
+}
+
+/**
+ * Filter out the blobs that are still referenced by a file, a previous version of a file, something in the trash, a
+ * move from another endpoint that has not been finished yet or a file that is about to reuse the blob.
+ *
+ * @returns filesystem_ids of the blobs that are safe to delete
+ */
+pub async fn get_unreferenced_blobs(
+    endpoint_id: i32,
+    filesystem_ids: &Vec<String>,
+    pool: &RequestPool,
+) -> Result<Vec<String>, StorageError> {
+    if filesystem_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    sqlx::query_scalar::<_, String>(
+        "SELECT DISTINCT blob.filesystem_id FROM UNNEST($2::BPCHAR[]) AS blob(filesystem_id)
+        WHERE NOT EXISTS (SELECT 1 FROM storage_entries WHERE endpoint_id = $1 AND filesystem_id = blob.filesystem_id)
+        AND NOT EXISTS (SELECT 1 FROM storage_entry_versions WHERE endpoint_id = $1 AND filesystem_id = blob.filesystem_id)
+        AND NOT EXISTS (
+            SELECT 1 FROM storage_trash_entries
+            INNER JOIN storage_trash ON storage_trash.id = storage_trash_entries.trash_id
+            WHERE storage_trash.endpoint_id = $1 AND storage_trash_entries.filesystem_id = blob.filesystem_id
+        )
+        AND NOT EXISTS (
+            SELECT 1 FROM storage_move_blobs
+            INNER JOIN storage_moves ON storage_moves.id = storage_move_blobs.move_id
+            WHERE storage_moves.target_endpoint_id = $1 AND storage_move_blobs.target_filesystem_id = blob.filesystem_id
+        )
+        AND NOT EXISTS (
+            SELECT 1 FROM storage_blob_reservations
+            WHERE endpoint_id = $1 AND filesystem_id = blob.filesystem_id AND created_at >= now() - make_interval(mins => $3)
+        )",
+    )
+    .bind(endpoint_id)
+    .bind(filesystem_ids)
+    .bind(BLOB_RESERVATION_LIFETIME_MINUTES)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Check whether any file other than the given one (including the ones in the trash) currently points to a blob.
+ *
+ * Artifacts are shared the same way blobs are, so they must be kept until no file uses the blob.
+ */
+pub async fn is_blob_used_by_other_files(
+    endpoint_id: i32,
+    filesystem_id: &str,
+    entry_id: i64,
+    pool: &RequestPool,
+) -> Result<bool, StorageError> {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM storage_entries WHERE endpoint_id = $1 AND filesystem_id = $2::BPCHAR AND id != $3)
+        OR EXISTS(
+            SELECT 1 FROM storage_trash_entries
+            INNER JOIN storage_trash ON storage_trash.id = storage_trash_entries.trash_id
+            WHERE storage_trash.endpoint_id = $1 AND storage_trash_entries.filesystem_id = $2::BPCHAR
+        )",
+    )
+    .bind(endpoint_id)
+    .bind(filesystem_id)
+    .bind(entry_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Check whether a blob is referenced by anything other than the given file: another file, a version or something in the trash.
+ *
+ * A shared blob must never be modified in place.
+ */
+pub async fn is_blob_shared(
+    endpoint_id: i32,
+    filesystem_id: &str,
+    entry_id: i64,
+    pool: &RequestPool,
+) -> Result<bool, StorageError> {
+    if is_blob_used_by_other_files(endpoint_id, filesystem_id, entry_id, pool).await? {
+        return Ok(true);
+    }
+
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM storage_entry_versions WHERE endpoint_id = $1 AND filesystem_id = $2::BPCHAR)",
+    )
+    .bind(endpoint_id)
+    .bind(filesystem_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|_| StorageError::Internal)
+}
@@ -0,0 +1,728 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::web;
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::storage_artifacts::queue_uploaded_files_artifacts;
+use crate::storage_backend::{get_storage_backend, StorageBackend};
+use crate::storage_blobs::{release_stored_blob, reuse_duplicate_blob, StoredBlob};
+use crate::storage_endpoint::{get_storage_endpoint, StorageEndpointRow};
+use crate::storage_entry::{delete_storage_blobs, StorageError};
+use crate::storage_hls::get_hls_path;
+use crate::storage_layout::sync_endpoint_file_structure;
+use crate::storage_metadata::copy_media_metadata;
+use crate::storage_tags::copy_entry_tags;
+use crate::util::RequestPool;
+use crate::webdav::split_entry_name;
+use crate::ws::{send_storage_location_updated, send_to_user, WSState};
+
+// Copies larger than this run in the background, unless configured otherwise
+pub const DEFAULT_COPY_BACKGROUND_THRESHOLD_BYTES: i64 = 100 * 1024 * 1024;
+
+// Guards against cycles in the tree
+const MAX_TREE_DEPTH: i32 = 1000;
+
+// How many "name (copy N)" names are tried before giving up
+const MAX_COPY_NAME_ATTEMPTS: u32 = 100;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+ * An entry that is being copied, along with everything inside of it
+ */
+#[derive(FromRow)]
+struct SourceEntry {
+    id: i64,
+    parent_folder: Option<i64>,
+    entry_type: String,
+    filesystem_id: Option<String>,
+    name: String,
+    extension: Option<String>,
+    mime_type: Option<String>,
+    size_bytes: Option<i64>,
+    sha256: Option<String>,
+    transcoded_version_available: Option<bool>,
+    depth: i32,
+}
+
+/**
+ * Progress of a copy that runs in the background. Sent to the user that has started the copy over the WebSocket.
+ */
+pub struct StorageCopyProgress<'a> {
+    pub ws_state: &'a web::Data<Mutex<WSState>>,
+    pub copy_id: String,
+    pub user_id: i32,
+    pub total_bytes: i64,
+
+    copied_bytes: i64,
+    copied_entries: i64,
+    last_sent_at: Option<Instant>,
+}
+
+impl<'a> StorageCopyProgress<'a> {
+    pub fn new(
+        ws_state: &'a web::Data<Mutex<WSState>>,
+        copy_id: String,
+        user_id: i32,
+        total_bytes: i64,
+    ) -> Self {
+        StorageCopyProgress {
+            ws_state,
+            copy_id,
+            user_id,
+            total_bytes,
+            copied_bytes: 0,
+            copied_entries: 0,
+            last_sent_at: None,
+        }
+    }
+
+    async fn advance(&mut self, copied_bytes: i64) {
+        self.copied_bytes += copied_bytes;
+        self.copied_entries += 1;
+
+        let is_due = self
+            .last_sent_at
+            .is_none_or(|last_sent_at| last_sent_at.elapsed() >= PROGRESS_INTERVAL);
+
+        if is_due {
+            self.send("in_progress").await;
+        }
+    }
+
+    /**
+     * @param status - "in_progress", "done" or "failed"
+     */
+    pub async fn send(&mut self, status: &str) {
+        self.last_sent_at = Some(Instant::now());
+
+        let ws_message = json!(
+            {
+                "type": "storage_copy_progress",
+                "payload": {
+                    "copy_id": self.copy_id,
+                    "status": status,
+                    "copied_entries": self.copied_entries,
+                    "copied_bytes": self.copied_bytes,
+                    "total_bytes": self.total_bytes
+                }
+            }
+        )
+        .to_string();
+
+        send_to_user(self.ws_state, self.user_id, ws_message.as_str()).await;
+    }
+}
+
+/**
+ * The endpoints (and their storage backends) that blobs are copied between. Both can be the same endpoint.
+ */
+pub struct BlobTransfer<'a> {
+    pub source_endpoint: &'a StorageEndpointRow,
+    pub source_backend: &'a dyn StorageBackend,
+    pub target_endpoint: &'a StorageEndpointRow,
+    pub target_backend: &'a dyn StorageBackend,
+}
+
+/**
+ * Where copies of entries are created
+ */
+pub struct StorageCopyTarget<'a> {
+    pub endpoint: &'a StorageEndpointRow,
+
+    /**
+     * `None` means the root of the endpoint
+     */
+    pub folder: Option<i64>,
+
+    /**
+     * Owner of the copies
+     */
+    pub user_id: Option<i32>,
+
+    /**
+     * Name of the copy (with the extension for files), if a single entry is copied. The copy fails if the name is
+     * taken, instead of getting a "(copy)" suffix
+     */
+    pub name: Option<&'a str>,
+
+    /**
+     * Whether the contents of folders are copied as well
+     */
+    pub recursive: bool,
+}
+
+/**
+ * Copy the artifacts (thumbnails, video frames, preview videos, HLS renditions) of a blob, so they don't have to be generated again.
+ *
+ * Artifacts are never modified in place, so they are hard-linked where possible. Errors are ignored, artifacts can
+ * always be regenerated.
+ */
+fn copy_blob_artifacts(
+    source_artifacts_path: &str,
+    target_artifacts_path: &str,
+    source_filesystem_id: &str,
+    target_filesystem_id: &str,
+) {
+    let link_or_copy = |from: &Path, to: &Path| {
+        if from.is_file() {
+            let _ = fs::hard_link(from, to).or_else(|_| fs::copy(from, to).map(|_| ()));
+        }
+    };
+
+    let source_thumbnails_path = Path::new(source_artifacts_path).join("thumbnails");
+    let target_thumbnails_path = Path::new(target_artifacts_path).join("thumbnails");
+
+    link_or_copy(
+        &source_thumbnails_path
+            .join(source_filesystem_id)
+            .with_extension("webp"),
+        &target_thumbnails_path
+            .join(target_filesystem_id)
+            .with_extension("webp"),
+    );
+
+    // Video frames for seeking
+    let source_frames_path = source_thumbnails_path.join(source_filesystem_id);
+
+    if let Ok(frames) = fs::read_dir(&source_frames_path) {
+        let target_frames_path = target_thumbnails_path.join(target_filesystem_id);
+
+        if fs::create_dir_all(&target_frames_path).is_ok() {
+            for frame in frames.filter_map(|frame| frame.ok()) {
+                link_or_copy(&frame.path(), &target_frames_path.join(frame.file_name()));
+            }
+        }
+    }
+
+    let source_preview_videos_path = Path::new(source_artifacts_path).join("preview_videos");
+    let target_preview_videos_path = Path::new(target_artifacts_path).join("preview_videos");
+
+    if target_preview_videos_path.is_dir() || fs::create_dir(&target_preview_videos_path).is_ok() {
+        link_or_copy(
+            &source_preview_videos_path
+                .join(source_filesystem_id)
+                .with_extension("mp4"),
+            &target_preview_videos_path
+                .join(target_filesystem_id)
+                .with_extension("mp4"),
+        );
+    }
+
+    // HLS renditions, the master playlist and a folder per rendition
+    let source_hls_path = get_hls_path(source_artifacts_path, source_filesystem_id);
+
+    if let Ok(children) = fs::read_dir(&source_hls_path) {
+        let target_hls_path = get_hls_path(target_artifacts_path, target_filesystem_id);
+
+        if fs::create_dir_all(&target_hls_path).is_ok() {
+            for child in children.filter_map(|child| child.ok()) {
+                let target_child_path = target_hls_path.join(child.file_name());
+
+                match fs::read_dir(child.path()) {
+                    Ok(files) => {
+                        if target_child_path.is_dir() || fs::create_dir(&target_child_path).is_ok()
+                        {
+                            for file in files.filter_map(|file| file.ok()) {
+                                link_or_copy(
+                                    &file.path(),
+                                    &target_child_path.join(file.file_name()),
+                                );
+                            }
+                        }
+                    }
+                    Err(_) => link_or_copy(&child.path(), &target_child_path),
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Copy a blob (along with its artifacts and media metadata) into the blob with the given id, possibly on another
+ * endpoint. A blob that already has that id is overwritten.
+ *
+ * @param hard_link - Whether the copy may share its data with the original on disk. Only safe if the original is
+ * removed afterwards, blobs can be modified in place
+ */
+pub async fn transfer_blob(
+    transfer: &BlobTransfer<'_>,
+    source_filesystem_id: &str,
+    target_filesystem_id: &str,
+    hard_link: bool,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    let BlobTransfer {
+        source_endpoint,
+        source_backend,
+        target_endpoint,
+        target_backend,
+    } = transfer;
+
+    if source_endpoint.id == target_endpoint.id {
+        target_backend
+            .copy(source_filesystem_id, target_filesystem_id)
+            .await?;
+    } else {
+        // The blob has to pass through the local filesystem on its way to the other endpoint
+        let local_blob = source_backend.local_copy(source_filesystem_id).await?;
+        let staging_path = target_backend.staging_path(target_filesystem_id);
+
+        let staged = if hard_link {
+            fs::remove_file(&staging_path).unwrap_or(());
+
+            fs::hard_link(local_blob.path(), &staging_path).is_ok()
+                || fs::copy(local_blob.path(), &staging_path).is_ok()
+        } else {
+            fs::copy(local_blob.path(), &staging_path).is_ok()
+        };
+
+        let commit_result = if staged {
+            target_backend.commit(target_filesystem_id).await
+        } else {
+            Err(StorageError::Internal)
+        };
+
+        if commit_result.is_err() {
+            fs::remove_file(&staging_path).unwrap_or(());
+
+            return Err(StorageError::Internal);
+        }
+    }
+
+    if let (Some(source_artifacts_path), Some(target_artifacts_path)) = (
+        &source_endpoint.artifacts_path,
+        &target_endpoint.artifacts_path,
+    ) {
+        copy_blob_artifacts(
+            source_artifacts_path,
+            target_artifacts_path,
+            source_filesystem_id,
+            target_filesystem_id,
+        );
+    }
+
+    // Can be extracted again, like the artifacts
+    copy_media_metadata(
+        source_endpoint.id,
+        source_filesystem_id,
+        target_endpoint.id,
+        target_filesystem_id,
+        pool,
+    )
+    .await
+    .unwrap_or(());
+
+    Ok(())
+}
+
+/**
+ * Give a blob to a copy of a file, possibly on another endpoint.
+ *
+ * If the target endpoint deduplicates its blobs and already has the same contents, the existing blob is used.
+ * Otherwise the blob is copied, along with its artifacts. `release_stored_blob` must be called once the copy has been
+ * created (or could not be created).
+ */
+pub async fn copy_blob(
+    transfer: &BlobTransfer<'_>,
+    source_filesystem_id: &str,
+    sha256: Option<&str>,
+    size_bytes: Option<i64>,
+    pool: &RequestPool,
+) -> Result<StoredBlob, StorageError> {
+    if let (Some(sha256), Some(size_bytes)) = (sha256, size_bytes) {
+        if let Some(duplicate) =
+            reuse_duplicate_blob(transfer.target_endpoint, sha256, size_bytes, pool).await
+        {
+            return Ok(duplicate);
+        }
+    }
+
+    let new_filesystem_id = Uuid::new_v4().to_string();
+
+    transfer_blob(
+        transfer,
+        source_filesystem_id,
+        &new_filesystem_id,
+        false,
+        pool,
+    )
+    .await?;
+
+    Ok(StoredBlob::new(new_filesystem_id))
+}
+
+/**
+ * Name of the n-th attempt to find a free name for a copy: "name", "name (copy)", "name (copy 2)", ...
+ */
+fn copy_name(name: &str, attempt: u32) -> String {
+    match attempt {
+        0 => name.to_string(),
+        1 => format!("{} (copy)", name),
+        _ => format!("{} (copy {})", name, attempt),
+    }
+}
+
+/**
+ * Create the row of a copied entry. If the name is already taken in the target folder, "(copy)" is appended to it,
+ * unless the copy was given a name.
+ *
+ * @returns id of the new entry
+ */
+async fn insert_entry_copy(
+    target: &StorageCopyTarget<'_>,
+    parent_folder: Option<i64>,
+    entry: &SourceEntry,
+    filesystem_id: Option<&str>,
+    transcoded_version_available: Option<bool>,
+    pool: &RequestPool,
+) -> Result<i64, StorageError> {
+    let given_name = target.name.filter(|_| entry.depth == 0).map(|name| {
+        if entry.entry_type == "folder" {
+            (name, None)
+        } else {
+            split_entry_name(name)
+        }
+    });
+
+    let attempts = if given_name.is_some() {
+        1
+    } else {
+        MAX_COPY_NAME_ATTEMPTS
+    };
+
+    for attempt in 0..attempts {
+        let (name, extension) = match given_name {
+            Some((name, extension)) => (name.to_string(), extension),
+            None => (copy_name(&entry.name, attempt), entry.extension.as_deref()),
+        };
+
+        let new_entry_id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO storage_entries (endpoint_id, parent_folder, entry_type, filesystem_id, name, extension, mime_type, size_bytes, sha256, transcoded_version_available, created_by)
+            VALUES ($1, $2, $3::storage_entry_type, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT DO NOTHING RETURNING id",
+        )
+        .bind(target.endpoint.id)
+        .bind(parent_folder)
+        .bind(&entry.entry_type)
+        .bind(filesystem_id)
+        .bind(&name)
+        .bind(extension)
+        .bind(&entry.mime_type)
+        .bind(entry.size_bytes)
+        .bind(&entry.sha256)
+        .bind(transcoded_version_available)
+        .bind(target.user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|_| StorageError::Internal)?;
+
+        if let Some(new_entry_id) = new_entry_id {
+            return Ok(new_entry_id);
+        }
+    }
+
+    Err(StorageError::NameConflict)
+}
+
+/**
+ * What a copy has created so far. Used to undo a copy that has failed halfway through.
+ */
+#[derive(Default)]
+struct CreatedCopies {
+    entry_ids: Vec<i64>,
+    filesystem_ids: Vec<String>,
+}
+
+async fn copy_tree(
+    transfer: &BlobTransfer<'_>,
+    tree: &[SourceEntry],
+    target: &StorageCopyTarget<'_>,
+    created: &mut CreatedCopies,
+    progress: &mut Option<&mut StorageCopyProgress<'_>>,
+    pool: &RequestPool,
+) -> Result<Vec<i64>, StorageError> {
+    let BlobTransfer {
+        source_endpoint,
+        target_endpoint,
+        ..
+    } = transfer;
+
+    // Id of the source folder -> id of its copy
+    let mut folder_copies: HashMap<i64, i64> = HashMap::new();
+    let mut copied_ids: HashSet<i64> = HashSet::new();
+    let mut top_level_copies: Vec<i64> = Vec::new();
+
+    for entry in tree {
+        // An entry that was requested along with one of its parents is only copied once
+        if !copied_ids.insert(entry.id) {
+            continue;
+        }
+
+        let parent_folder = if entry.depth == 0 {
+            target.folder
+        } else {
+            match entry
+                .parent_folder
+                .and_then(|parent| folder_copies.get(&parent))
+            {
+                Some(parent_copy) => Some(*parent_copy),
+                None => continue,
+            }
+        };
+
+        let (stored_blob, transcoded_version_available) = match &entry.filesystem_id {
+            Some(source_filesystem_id) if entry.entry_type == "file" => {
+                let stored_blob = copy_blob(
+                    transfer,
+                    source_filesystem_id,
+                    entry.sha256.as_deref(),
+                    entry.size_bytes,
+                    pool,
+                )
+                .await?;
+
+                if stored_blob.is_new() {
+                    created
+                        .filesystem_ids
+                        .push(stored_blob.filesystem_id.clone());
+                }
+
+                // Preview videos are only there if the artifacts could be copied
+                let transcoded_version_available = if stored_blob.is_new()
+                    && (source_endpoint.artifacts_path.is_none()
+                        || target_endpoint.artifacts_path.is_none())
+                {
+                    None
+                } else {
+                    entry.transcoded_version_available
+                };
+
+                (Some(stored_blob), transcoded_version_available)
+            }
+            _ => (None, None),
+        };
+
+        let new_entry_id = insert_entry_copy(
+            target,
+            parent_folder,
+            entry,
+            stored_blob
+                .as_ref()
+                .map(|stored_blob| stored_blob.filesystem_id.as_str()),
+            transcoded_version_available,
+            pool,
+        )
+        .await;
+
+        if let Some(stored_blob) = &stored_blob {
+            release_stored_blob(stored_blob, pool).await;
+        }
+
+        let new_entry_id = new_entry_id?;
+
+        copy_entry_tags(
+            entry.id,
+            target_endpoint.id,
+            new_entry_id,
+            target.user_id,
+            pool,
+        )
+        .await?;
+
+        created.entry_ids.push(new_entry_id);
+
+        if entry.entry_type == "folder" {
+            folder_copies.insert(entry.id, new_entry_id);
+        }
+
+        if entry.depth == 0 {
+            top_level_copies.push(new_entry_id);
+        }
+
+        if let Some(progress) = progress {
+            progress.advance(entry.size_bytes.unwrap_or(0)).await;
+        }
+    }
+
+    Ok(top_level_copies)
+}
+
+/**
+ * Copy files and folders (with everything inside of them) into a folder, possibly on another endpoint.
+ *
+ * Copies get new blobs (unless the target endpoint deduplicates them), so they can be changed independently of the
+ * originals. Names that are already taken in the target folder get a "(copy)" suffix. If something fails halfway
+ * through, everything that has been copied so far is removed again.
+ *
+ * Access and quotas must be checked by the caller.
+ *
+ * @returns ids of the copies of `entry_ids`
+ */
+pub async fn copy_entries(
+    source_endpoint: &StorageEndpointRow,
+    entry_ids: &[i64],
+    target: &StorageCopyTarget<'_>,
+    mut progress: Option<&mut StorageCopyProgress<'_>>,
+    pool: &RequestPool,
+) -> Result<Vec<i64>, StorageError> {
+    let target_endpoint = target.endpoint;
+
+    // The whole tree is read before anything is copied, so a folder can be copied into itself
+    let tree = sqlx::query_as::<_, SourceEntry>(
+        "WITH RECURSIVE tree(id, parent_folder, entry_type, filesystem_id, name, extension, mime_type, size_bytes, sha256, transcoded_version_available, depth) AS (
+            SELECT id, parent_folder, entry_type::TEXT, filesystem_id::TEXT, name, extension, mime_type, size_bytes, sha256, transcoded_version_available, 0
+            FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)
+            UNION ALL
+            SELECT storage_entries.id, storage_entries.parent_folder, storage_entries.entry_type::TEXT, storage_entries.filesystem_id::TEXT,
+            storage_entries.name, storage_entries.extension, storage_entries.mime_type, storage_entries.size_bytes, storage_entries.sha256,
+            storage_entries.transcoded_version_available, tree.depth + 1
+            FROM storage_entries INNER JOIN tree ON storage_entries.parent_folder = tree.id
+            WHERE storage_entries.endpoint_id = $1 AND tree.depth < $3
+        ) SELECT * FROM tree ORDER BY depth, id",
+    )
+    .bind(source_endpoint.id)
+    .bind(entry_ids)
+    .bind(if target.recursive { MAX_TREE_DEPTH } else { 0 })
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    let source_backend = get_storage_backend(source_endpoint, pool).await?;
+    let target_backend = get_storage_backend(target_endpoint, pool).await?;
+
+    let transfer = BlobTransfer {
+        source_endpoint,
+        source_backend: source_backend.as_ref(),
+        target_endpoint,
+        target_backend: target_backend.as_ref(),
+    };
+
+    let mut created = CreatedCopies::default();
+
+    let copy_result = copy_tree(&transfer, &tree, target, &mut created, &mut progress, pool).await;
+
+    match copy_result {
+        Ok(top_level_copies) => {
+            sync_endpoint_file_structure(target_endpoint.id, &top_level_copies, pool).await;
+
+            // There was nothing to copy the artifacts from
+            if source_endpoint.artifacts_path.is_none() {
+                queue_uploaded_files_artifacts(
+                    target_endpoint,
+                    created.filesystem_ids,
+                    target.user_id,
+                    pool,
+                )
+                .await;
+            }
+
+            Ok(top_level_copies)
+        }
+        Err(err) => {
+            error!(
+                "(storage copy) Could not copy entries from endpoint {} to endpoint {}. Removing {} copied entries.",
+                source_endpoint.id,
+                target_endpoint.id,
+                created.entry_ids.len()
+            );
+
+            let _ =
+                sqlx::query("DELETE FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)")
+                    .bind(target_endpoint.id)
+                    .bind(&created.entry_ids)
+                    .execute(pool)
+                    .await;
+
+            delete_storage_blobs(target_endpoint, &created.filesystem_ids, pool)
+                .await
+                .unwrap_or(());
+
+            Err(err)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StorageCopyJobPayload {
+    pub copy_id: String,
+    pub user_id: i32,
+    pub source_endpoint_id: i32,
+    pub entry_ids: Vec<i64>,
+    pub target_endpoint_id: i32,
+    pub target_folder: Option<i64>,
+    pub total_bytes: i64,
+}
+
+/**
+ * Copy entries in the background (see `storage_copy_entries`), reporting the progress to the user.
+ *
+ * Blocks, runs in a job worker.
+ */
+pub fn run_copy_entries_job(
+    runtime: &actix_rt::Runtime,
+    pool: &RequestPool,
+    ws_state: &web::Data<Mutex<WSState>>,
+    payload: StorageCopyJobPayload,
+) -> Result<(), String> {
+    let source_endpoint = runtime
+        .block_on(get_storage_endpoint(payload.source_endpoint_id, pool))
+        .map_err(|_| format!("Endpoint {} does not exist", payload.source_endpoint_id))?;
+
+    let target_endpoint = runtime
+        .block_on(get_storage_endpoint(payload.target_endpoint_id, pool))
+        .map_err(|_| format!("Endpoint {} does not exist", payload.target_endpoint_id))?;
+
+    let mut progress = StorageCopyProgress::new(
+        ws_state,
+        payload.copy_id.clone(),
+        payload.user_id,
+        payload.total_bytes,
+    );
+
+    let target = StorageCopyTarget {
+        endpoint: &target_endpoint,
+        folder: payload.target_folder,
+        user_id: Some(payload.user_id),
+        name: None,
+        recursive: true,
+    };
+
+    let copy_result = runtime.block_on(copy_entries(
+        &source_endpoint,
+        &payload.entry_ids,
+        &target,
+        Some(&mut progress),
+        pool,
+    ));
+
+    match copy_result {
+        Ok(_) => {
+            runtime.block_on(progress.send("done"));
+
+            runtime.block_on(send_storage_location_updated(
+                ws_state,
+                None,
+                target_endpoint.id,
+                vec![payload.target_folder],
+                true,
+                false,
+            ));
+
+            Ok(())
+        }
+        Err(err) => {
+            runtime.block_on(progress.send("failed"));
+
+            Err(format!("Could not copy the entries. {}", err.get_code()))
+        }
+    }
+}
@@ -14,6 +14,9 @@ pub struct StorageEndpointRow {
     pub artifacts_path: Option<String>,
     pub description: Option<String>,
     pub access_rules_enabled: bool,
+    pub max_file_versions: i32,
+    pub deduplication_enabled: bool,
+    pub user_quota_bytes: i64,
     pub vfs_enabled: Option<bool>,
 }
 
@@ -21,7 +24,7 @@ pub async fn get_storage_endpoint(
     endpoint_id: i32,
     pool: &RequestPool,
 ) -> Result<StorageEndpointRow, sqlx::Error> {
-    sqlx::query_as::<_, StorageEndpointRow>("SELECT storage_endpoints.id, storage_endpoints.name, storage_endpoints.endpoint_type::TEXT, storage_endpoints.status::TEXT, storage_endpoints.preserve_file_structure, storage_endpoints.base_path, storage_endpoints.artifacts_path, storage_endpoints.description, storage_endpoints.access_rules_enabled, storage_vfs.enabled AS vfs_enabled FROM storage_endpoints LEFT JOIN storage_vfs ON storage_vfs.endpoint_id = storage_endpoints.id WHERE storage_endpoints.id = $1")
+    sqlx::query_as::<_, StorageEndpointRow>("SELECT storage_endpoints.id, storage_endpoints.name, storage_endpoints.endpoint_type::TEXT, storage_endpoints.status::TEXT, storage_endpoints.preserve_file_structure, storage_endpoints.base_path, storage_endpoints.artifacts_path, storage_endpoints.description, storage_endpoints.access_rules_enabled, storage_endpoints.max_file_versions, storage_endpoints.deduplication_enabled, storage_endpoints.user_quota_bytes, storage_vfs.enabled AS vfs_enabled FROM storage_endpoints LEFT JOIN storage_vfs ON storage_vfs.endpoint_id = storage_endpoints.id WHERE storage_endpoints.id = $1")
         .bind(endpoint_id)
         .fetch_one(pool)
         .await
@@ -7,15 +7,25 @@ use async_recursion::async_recursion;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{HttpRequest, HttpResponse};
+
 use crate::{
+    request::error,
     storage_access::{process_storage_entry, ProccessEntryRuleInput, StorageAccessType},
-    storage_endpoint::get_storage_endpoint,
+    storage_backend::get_storage_backend,
+    storage_blobs::get_unreferenced_blobs,
+    storage_endpoint::{get_storage_endpoint, StorageEndpointRow},
+    storage_hls::get_hls_path,
+    storage_layout::sync_endpoint_file_structure,
+    storage_metadata::delete_media_metadata,
+    storage_trash::move_entry_to_trash,
     util::RequestPool,
 };
 use log::*;
 
 #[allow(dead_code)]
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug)]
 pub enum StorageError {
     AccessDenied,
 
@@ -23,6 +33,8 @@ pub enum StorageError {
     RecursionError,
     EndpointNotFound,
     EndpointArtifactsDisabled,
+    QuotaExceeded,
+    InvalidMetadataFilter,
 
     ConvertError,
 
@@ -38,6 +50,8 @@ impl StorageError {
             StorageError::RecursionError => "storage.recursion_error",
             StorageError::EndpointNotFound => "storage.endpoint_not_found",
             StorageError::EndpointArtifactsDisabled => "storage.endpoint_artifacts_disabled",
+            StorageError::QuotaExceeded => "storage.quota_exceeded",
+            StorageError::InvalidMetadataFilter => "storage.invalid_metadata_filter",
 
             StorageError::ConvertError => "storage.convert_error",
 
@@ -151,66 +165,6 @@ async fn traverse_folder(
     }
 }
 
-#[async_recursion]
-async fn get_subfolders_level(
-    endpoint_id: i32,
-    folder_ids: &mut Vec<i64>,
-    filesystem_ids: &mut Vec<String>,
-    target_folder_ids: Vec<i64>,
-    pool: &RequestPool,
-) -> Result<(), StorageError> {
-    #[derive(FromRow)]
-    struct EntryRow {
-        id: i64,
-        filesystem_id: Option<String>,
-    }
-
-    let next_level_entries = sqlx::query_as::<_, EntryRow>(
-        "SELECT id, filesystem_id FROM storage_entries WHERE endpoint_id = $1 AND parent_folder = ANY($2)",
-    )
-    .bind(endpoint_id)
-    .bind(target_folder_ids)
-    .fetch_all(pool)
-    .await;
-
-    match &next_level_entries {
-        Ok(next_level_entries) => {
-            let mut next_level_folder_ids: Vec<i64> = Vec::new();
-
-            for entry in next_level_entries {
-                if entry.filesystem_id.is_some() {
-                    // File
-                    filesystem_ids.push(entry.filesystem_id.clone().unwrap());
-                } else {
-                    // Folder
-                    next_level_folder_ids.push(entry.id);
-                    folder_ids.push(entry.id);
-                }
-            }
-
-            if next_level_folder_ids.len() > 0 {
-                let next_iteration = get_subfolders_level(
-                    endpoint_id,
-                    folder_ids,
-                    filesystem_ids,
-                    next_level_folder_ids,
-                    pool,
-                )
-                .await;
-
-                if next_iteration.is_err() {
-                    return Err(next_iteration.unwrap_err());
-                }
-            }
-
-            return Ok(());
-        }
-        Err(_) => {
-            return Err(StorageError::Internal);
-        }
-    }
-}
-
 #[async_recursion]
 pub async fn get_subfolders_level_with_access_rules(
     endpoint_id: i32,
@@ -423,6 +377,9 @@ pub async fn resolve_entries(
 /**
  * Recursively delete files and folders
  *
+ * Entries are not deleted permanently, they are moved to the trash of the endpoint (see `storage_trash.rs`).
+ * The blobs are deleted once the trash is purged.
+ *
  * @param endpoint_id - Storage endpoint id
  * @param target_folders - Vector of folder ids to be deleted (can be empty)
  * @param target_files - Vector of file ids to be deleted (can be empty)
@@ -442,38 +399,25 @@ pub async fn delete_entries(
 ) -> Result<(usize, usize), StorageError> {
     let now = Instant::now();
 
-    // Find the endpoint so we know where the files we find will be stored physically
     let target_endpoint = get_storage_endpoint(endpoint_id, pool).await;
 
     if target_endpoint.is_err() {
         return Err(StorageError::EndpointNotFound);
     }
 
-    let target_endpoint = target_endpoint.unwrap();
-
-    // HashMap of folders to their parent folder id
-    // pre-populate it with the target folders. We set the parent to None (as if they are inside the endpoint's root folder)
-    // because we do not care about anything above the target folders. We just pretend that they reside inside the root
-    let mut folder_parents: HashMap<i64, Option<i64>> =
-        target_folders.iter().map(|id| (*id, None)).collect();
-
-    // Arrays of *all* the folders we will find after the recursive search
-    // These may be the arbitrarily deep inside the target folders
-    let mut all_folders: Vec<i64> = Vec::new();
-
-    // We will store the filesystem ids of all the files we are about to delete
-    // We need to know the filesystem ids to actually delete the files from
-    // the filesystem after we delete them from the database
-    let mut file_filesystem_ids: Vec<String> = Vec::new();
-
-    // Recursively find all the folders that reside inside target folders
-    if folder_parents.len() > 0 {
+    // Recursively make sure that the user is allowed to delete everything that resides inside target folders
+    if target_folders.len() > 0 {
         if let Some(access) = access {
+            // TODO we do not need this data. Allow calling get_subfolders_level_with_access_rules without these parameters
+            let mut file_filesystem_ids: Vec<String> = Vec::new();
+            let mut folder_parents: HashMap<i64, Option<i64>> =
+                target_folders.iter().map(|id| (*id, None)).collect();
+
             let get_subfolders_result = get_subfolders_level_with_access_rules(
                 endpoint_id,
                 &mut folder_parents,
                 &mut file_filesystem_ids,
-                target_folders,
+                target_folders.clone(),
                 access,
                 "delete",
                 pool,
@@ -483,29 +427,9 @@ pub async fn delete_entries(
             if get_subfolders_result.is_err() {
                 return Err(get_subfolders_result.unwrap_err());
             }
-
-            all_folders = folder_parents.keys().map(|id| *id).collect();
-        } else {
-            all_folders = target_folders.clone();
-
-            let get_subfolders_result = get_subfolders_level(
-                endpoint_id,
-                &mut all_folders,
-                &mut file_filesystem_ids,
-                target_folders,
-                pool,
-            )
-            .await;
-
-            if get_subfolders_result.is_err() {
-                return Err(get_subfolders_result.unwrap_err());
-            }
         }
     }
 
-    // Delete the files from all of the folders we have found
-    // At this point, we have travesed the storage tree and have found
-    // all the entries that reside inside the target folders, on any level
     let transaction = pool.begin().await;
 
     if transaction.is_err() {
@@ -514,172 +438,283 @@ pub async fn delete_entries(
 
     let mut transaction = transaction.unwrap();
 
-    // Delete files *inside* provided target folders (arbitrarily deep inside)
-    if all_folders.len() > 0 {
-        if access.is_some() {
-            // If access is Some, then we have previosly called `get_subfolders_level_with_access_rules`
-            // which already populated file_filesystem_ids vector with all the files that were found
-            // inside folders
+    let deleted_by = access.map(|(user_id, _)| user_id);
 
-            let delete_folder_files_result = sqlx::query(
-                "DELETE FROM storage_entries WHERE endpoint_id = $1 AND parent_folder = ANY($2) AND entry_type = 'file'::storage_entry_type",
-            )
-            .bind(endpoint_id)
-            .bind(&all_folders)
-            .fetch_all(&mut *transaction)
-            .await;
+    let mut deleted_files: i64 = 0;
+    let mut deleted_folders: i64 = 0;
 
-            if delete_folder_files_result.is_err() {
-                return Err(StorageError::Internal);
-            }
-        } else {
-            // if access is None, then we have previosly called `get_subfolders_level`, which
-            // does not populate the file_filesystem_ids vector, so we need to do that here -
-            // add each file which resides inside some folder
+    for entry_id in target_files.iter().chain(target_folders.iter()) {
+        let (files_count, folders_count) =
+            move_entry_to_trash(endpoint_id, *entry_id, deleted_by, &mut transaction).await?;
 
-            let delete_folder_files_result = sqlx::query_scalar::<_, String>(
-                "DELETE FROM storage_entries WHERE endpoint_id = $1 AND parent_folder = ANY($2) AND entry_type = 'file'::storage_entry_type RETURNING filesystem_id",
-            )
-            .bind(endpoint_id)
-            .bind(&all_folders)
-            .fetch_all(&mut *transaction)
-            .await;
+        deleted_files += files_count;
+        deleted_folders += folders_count;
+    }
+
+    if cfg!(debug_assertions) {
+        info!(
+            "delete_entries tree traversal & access checks: {}ms; {} files, {} folders",
+            now.elapsed().as_millis(),
+            deleted_files,
+            deleted_folders
+        );
+    }
+
+    let transaction_result = transaction.commit().await;
+
+    if transaction_result.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    sync_endpoint_file_structure(endpoint_id, &Vec::new(), pool).await;
+
+    return Ok((deleted_files as usize, deleted_folders as usize));
+}
 
-            match delete_folder_files_result {
-                Ok(delete_folder_files_result) => {
-                    file_filesystem_ids.extend(delete_folder_files_result.into_iter());
+#[derive(FromRow)]
+struct StorageFileAndArtifactsPathRow {
+    filesystem_id: String,
+    mime_type: Option<String>,
+    artifacts_path: Option<String>,
+}
+
+/**
+ * Serve a file's contents for viewing in the browser.
+ *
+ * @param preview_requested serve a browser friendly version of the file instead, if we have one
+ */
+pub async fn serve_storage_file(
+    endpoint_id: i32,
+    file_id: i64,
+    preview_requested: bool,
+    req: &HttpRequest,
+    pool: &RequestPool,
+) -> HttpResponse {
+    let entry = sqlx::query_as::<_, StorageFileAndArtifactsPathRow>(
+        "SELECT storage_entries.filesystem_id, storage_entries.mime_type, storage_endpoints.artifacts_path FROM storage_entries
+        RIGHT OUTER JOIN storage_endpoints ON storage_entries.endpoint_id = storage_endpoints.id
+        WHERE storage_entries.id = $1 AND endpoint_id = $2",
+    )
+    .bind(file_id)
+    .bind(endpoint_id)
+    .fetch_one(pool)
+    .await;
+
+    match entry {
+        Ok(entry) => {
+            let mut is_preview_version = false;
+
+            // TODO cleanup
+            let preview_version_path = if preview_requested {
+                if let Some(artifacts_path) = &entry.artifacts_path {
+                    let browser_friendly_version_path = Path::new(artifacts_path)
+                        .join("preview_videos")
+                        .join(&entry.filesystem_id)
+                        .with_extension("mp4");
+
+                    if browser_friendly_version_path.exists() {
+                        is_preview_version = true;
+
+                        Some(browser_friendly_version_path)
+                    } else {
+                        None
+                    }
+                } else {
+                    None
                 }
-                Err(_) => {
-                    return Err(StorageError::Internal);
+            } else {
+                None
+            };
+
+            // Preview versions always reside on the local filesystem, in the endpoint's artifacts folder
+            let mut res = if let Some(preview_version_path) = preview_version_path {
+                actix_files::NamedFile::open(preview_version_path)
+                    .unwrap()
+                    .into_response(req)
+            } else {
+                let target_endpoint = get_storage_endpoint(endpoint_id, pool).await;
+
+                if target_endpoint.is_err() {
+                    return error("storage.endpoint_not_found");
+                }
+
+                let storage_backend = get_storage_backend(&target_endpoint.unwrap(), pool).await;
+
+                if storage_backend.is_err() {
+                    return error("storage.endpoint_not_found");
+                }
+
+                let res = storage_backend
+                    .unwrap()
+                    .serve(&entry.filesystem_id, req)
+                    .await;
+
+                if res.is_err() {
+                    return error("storage.internal");
                 }
+
+                res.unwrap()
+            };
+
+            res.headers_mut().insert(
+                header::CACHE_CONTROL,
+                HeaderValue::from_static(
+                    // TODO we can also use the `immutable` directive
+                    "private, stale-while-revalidate, max-age=432000", // 432000 = 5 days
+                ),
+            );
+
+            if is_preview_version {
+                // It seems that the default value for this header is already `inline`,
+                // so this does not have any actual effect. We do this just to be more explicit.
+                res.headers_mut().insert(
+                    header::CONTENT_DISPOSITION,
+                    HeaderValue::from_static("inline"),
+                );
             }
-        }
-    }
 
-    // Delete provided target files
-    if target_files.len() > 0 {
-        let delete_target_files_result = sqlx::query_scalar::<_, String>(
-                "DELETE FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2) RETURNING filesystem_id",
-        )
-        .bind(endpoint_id)
-        .bind(target_files)
-        .fetch_all(&mut *transaction)
-        .await;
+            if let Some(src_entry_mime_type) = &entry.mime_type {
+                res.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_str(if is_preview_version {
+                        "video/mp4"
+                    } else {
+                        src_entry_mime_type.as_str()
+                    })
+                    .unwrap(),
+                );
+            }
 
-        if delete_target_files_result.is_err() {
-            return Err(StorageError::Internal);
+            return res;
         }
 
-        // Populate the vector with files that were explicitly selected for deletion (not the
-        // ones that reside inside some selected folder - we do that earlier)
-        file_filesystem_ids.extend(delete_target_files_result.unwrap());
+        Err(_) => error("storage.internal"),
     }
+}
 
-    // Delete provided target folders & their subfolders
-    if all_folders.len() > 0 {
-        let delete_folders_result =
-            sqlx::query("DELETE FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)")
-                .bind(endpoint_id)
-                .bind(&all_folders)
-                .execute(&mut *transaction)
-                .await;
+/**
+ * Permanently remove the blobs (and their artifacts and media metadata) of deleted files from the endpoint.
+ *
+ * Rows in the database must be deleted beforehand. Blobs that are still referenced by something else
+ * (deduplicated files, versions, trash) are kept. Errors are logged and otherwise ignored.
+ */
+pub async fn delete_storage_blobs(
+    target_endpoint: &StorageEndpointRow,
+    file_filesystem_ids: &Vec<String>,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    let endpoint_id = target_endpoint.id;
+    let storage_backend = get_storage_backend(target_endpoint, pool).await?;
 
-        if delete_folders_result.is_err() {
-            return Err(StorageError::Internal);
+    let unreferenced_filesystem_ids =
+        get_unreferenced_blobs(endpoint_id, file_filesystem_ids, pool).await?;
+
+    for file_filesystem_id in &unreferenced_filesystem_ids {
+        let remove_result = storage_backend.delete(file_filesystem_id).await;
+
+        if remove_result.is_err() {
+            error!(
+                "(storage entry -> delete storage blobs) Could not remove a file from the endpoint. endpoint_id = {}. filesystem_id = {}.",
+                endpoint_id,
+                file_filesystem_id,
+            );
+        }
+
+        if let Some(endpoint_artifacts_path) = &target_endpoint.artifacts_path {
+            delete_file_artifacts(endpoint_id, endpoint_artifacts_path, file_filesystem_id);
         }
     }
 
-    if cfg!(debug_assertions) {
-        info!(
-            "delete_entries tree traversal & access checks: {}ms; {} files, {} folders",
-            now.elapsed().as_millis(),
-            file_filesystem_ids.len(),
-            all_folders.len()
+    if delete_media_metadata(endpoint_id, &unreferenced_filesystem_ids, pool)
+        .await
+        .is_err()
+    {
+        error!(
+            "(storage entry -> delete storage blobs) Could not remove media metadata of deleted files. endpoint_id = {}.",
+            endpoint_id,
         );
     }
 
-    let transaction_result = transaction.commit().await;
+    Ok(())
+}
 
-    if transaction_result.is_err() {
-        return Err(StorageError::Internal);
-    }
+/**
+ * Remove all the artifacts (thumbnails, seeking frames, preview videos, HLS renditions) generated for a file.
+ *
+ * Errors are logged and otherwise ignored, artifacts can always be regenerated.
+ */
+pub fn delete_file_artifacts(
+    endpoint_id: i32,
+    endpoint_artifacts_path: &str,
+    file_filesystem_id: &str,
+) {
+    let thumbnail_path = Path::new(&endpoint_artifacts_path)
+        .join("thumbnails")
+        .join(file_filesystem_id)
+        .with_extension("webp");
+
+    let frames_path = Path::new(&endpoint_artifacts_path)
+        .join("thumbnails")
+        .join(file_filesystem_id);
 
-    // We have successfully deleted all the underlying files and folders from the database,
-    // now we can actually delete the files from the filesystem
-    let endpoint_base_path = target_endpoint.base_path;
-    let endpoint_artifacts_path = target_endpoint.artifacts_path;
-    let endpoint_files_path = Path::new(&endpoint_base_path);
+    let preview_video_path = Path::new(&endpoint_artifacts_path)
+        .join("preview_videos")
+        .join(file_filesystem_id)
+        .with_extension("mp4");
 
-    for file_filesystem_id in &file_filesystem_ids {
-        let file_path = endpoint_files_path.join(file_filesystem_id);
-        let fs_remove_result = remove_file(file_path);
+    let hls_path = get_hls_path(endpoint_artifacts_path, file_filesystem_id);
 
-        if fs_remove_result.is_err() {
+    if thumbnail_path.exists() {
+        let fs_remove_thumbnail_result = remove_file(thumbnail_path);
+
+        if fs_remove_thumbnail_result.is_err() {
             error!(
-                "(storage entry -> delete entries) Could not remove a file from the filesystem. endpoint_id = {}. filesystem_id = {}. {}",
+                "(storage entry -> delete file artifacts) Could not remove a thumbnail from the filesystem. endpoint_id = {}. filesystem_id = {}. {}",
                 endpoint_id,
                 file_filesystem_id,
-                fs_remove_result.unwrap_err()
+                fs_remove_thumbnail_result.unwrap_err()
             );
         }
+    }
 
-        if let Some(endpoint_artifacts_path) = &endpoint_artifacts_path {
-            let thumbnail_path = Path::new(&endpoint_artifacts_path)
-                .join("thumbnails")
-                .join(file_filesystem_id)
-                .with_extension("webp");
-
-            let frames_path = Path::new(&endpoint_artifacts_path)
-                .join("thumbnails")
-                .join(file_filesystem_id);
-
-            let preview_video_path = Path::new(&endpoint_artifacts_path)
-                .join("preview_videos")
-                .join(file_filesystem_id)
-                .with_extension("mp4");
-
-            if thumbnail_path.exists() {
-                let fs_remove_thumbnail_result = remove_file(thumbnail_path);
-
-                if fs_remove_thumbnail_result.is_err() {
-                    error!(
-                        "(storage entry -> delete entries) Could not remove a thumbnail from the filesystem. endpoint_id = {}. filesystem_id = {}. {}",
-                        endpoint_id,
-                        file_filesystem_id,
-                        fs_remove_thumbnail_result.unwrap_err()
-                    );
-                }
-            }
-
-            if frames_path.exists() {
-                let fs_remove_frames_result = fs::remove_dir_all(frames_path);
+    if frames_path.exists() {
+        let fs_remove_frames_result = fs::remove_dir_all(frames_path);
 
-                if fs_remove_frames_result.is_err() {
-                    error!(
-                        "(storage entry -> delete entries) Could not remove video frames folder from the filesystem. endpoint_id = {}. filesystem_id = {}. {}",
-                        endpoint_id,
-                        file_filesystem_id,
-                        fs_remove_frames_result.unwrap_err()
-                    );
-                }
-            }
+        if fs_remove_frames_result.is_err() {
+            error!(
+                "(storage entry -> delete file artifacts) Could not remove video frames folder from the filesystem. endpoint_id = {}. filesystem_id = {}. {}",
+                endpoint_id,
+                file_filesystem_id,
+                fs_remove_frames_result.unwrap_err()
+            );
+        }
+    }
 
-            if preview_video_path.exists() {
-                let fs_remove_preview_video_result = remove_file(preview_video_path);
+    if preview_video_path.exists() {
+        let fs_remove_preview_video_result = remove_file(preview_video_path);
 
-                if fs_remove_preview_video_result.is_err() {
-                    error!(
-                        "(storage entry -> delete entries) Could not remove a preview video from the filesystem. endpoint_id = {}. filesystem_id = {}. {}",
-                        endpoint_id,
-                        file_filesystem_id,
-                        fs_remove_preview_video_result.unwrap_err()
-                    );
-                }
-            }
+        if fs_remove_preview_video_result.is_err() {
+            error!(
+                "(storage entry -> delete file artifacts) Could not remove a preview video from the filesystem. endpoint_id = {}. filesystem_id = {}. {}",
+                endpoint_id,
+                file_filesystem_id,
+                fs_remove_preview_video_result.unwrap_err()
+            );
         }
     }
 
-    return Ok((file_filesystem_ids.len(), all_folders.len()));
+    if hls_path.exists() {
+        let fs_remove_hls_result = fs::remove_dir_all(hls_path);
+
+        if fs_remove_hls_result.is_err() {
+            error!(
+                "(storage entry -> delete file artifacts) Could not remove HLS renditions from the filesystem. endpoint_id = {}. filesystem_id = {}. {}",
+                endpoint_id,
+                file_filesystem_id,
+                fs_remove_hls_result.unwrap_err()
+            );
+        }
+    }
 }
 
 /**
@@ -739,6 +774,8 @@ pub async fn move_entries(
 
     transaction.commit().await.unwrap();
 
+    sync_endpoint_file_structure(endpoint_id, entry_ids, pool).await;
+
     Ok(())
 }
 
@@ -757,14 +794,18 @@ pub async fn rename_entry(
             .await;
 
     match rename_result {
-        Ok(parent_folder) => Ok(parent_folder),
+        Ok(parent_folder) => {
+            sync_endpoint_file_structure(endpoint_id, &vec![entry_id], pool).await;
+
+            Ok(parent_folder)
+        }
         Err(_) => Err(StorageError::NameConflict),
     }
 }
 
 pub fn generate_image_entry_thumbnail(
     filesystem_id: &str,
-    endpoint_path: &str,
+    file_path: &Path,
     endpoint_artifacts_path: &str,
 ) -> Result<(), StorageError> {
     let convert_bin_path = env::var("IMAGEMAGICK_BIN");
@@ -773,7 +814,6 @@ pub fn generate_image_entry_thumbnail(
         let convert_bin_path = Path::new(&convert_bin_path);
 
         if convert_bin_path.exists() {
-            let file_path = Path::new(endpoint_path).join(&filesystem_id);
             let endpoint_thumbnails_path = Path::new(endpoint_artifacts_path).join("thumbnails");
 
             let convert_result = Command::new(convert_bin_path)
@@ -812,7 +852,7 @@ pub fn generate_image_entry_thumbnail(
 
 pub fn generate_video_entry_thumbnails(
     filesystem_id: &str,
-    endpoint_path: &str,
+    file_path: &Path,
     endpoint_artifacts_path: &str,
     desired_frames_count: Option<u32>,
 ) -> Result<(), StorageError> {
@@ -831,7 +871,6 @@ pub fn generate_video_entry_thumbnails(
         return Err(StorageError::Internal);
     }
 
-    let file_path = Path::new(endpoint_path).join(&filesystem_id);
     let endpoint_thumbnails_path = Path::new(endpoint_artifacts_path).join("thumbnails");
     let file_path_string = file_path.to_str().unwrap();
 
@@ -943,7 +982,7 @@ pub fn generate_video_entry_thumbnails(
 
 pub fn generate_browser_friendly_video(
     filesystem_id: &str,
-    endpoint_path: &str,
+    file_path: &Path,
     endpoint_artifacts_path: &str,
     target_height: u32,
     target_bitrate: u32,
@@ -956,7 +995,6 @@ pub fn generate_browser_friendly_video(
         let ffmpeg_bin_path = Path::new(&ffmpeg_bin_path);
 
         if ffmpeg_bin_path.exists() {
-            let file_path = Path::new(endpoint_path).join(&filesystem_id);
             let endpoint_preview_videos_path =
                 Path::new(endpoint_artifacts_path).join("preview_videos");
 
@@ -1038,7 +1076,7 @@ pub fn generate_browser_friendly_video(
 
 pub fn generate_audio_entry_cover_thumbnail(
     filesystem_id: &str,
-    endpoint_path: &str,
+    file_path: &Path,
     endpoint_artifacts_path: &str,
 ) -> Result<(), StorageError> {
     let ffmpeg_bin_path = env::var("FFMPEG_BIN");
@@ -1047,7 +1085,6 @@ pub fn generate_audio_entry_cover_thumbnail(
         let ffmpeg_bin_path = Path::new(&ffmpeg_bin_path);
 
         if ffmpeg_bin_path.exists() {
-            let file_path = Path::new(endpoint_path).join(&filesystem_id);
             let endpoint_thumbnails_path = Path::new(endpoint_artifacts_path).join("thumbnails");
 
             let ffmpeg_result = Command::new(ffmpeg_bin_path)
@@ -0,0 +1,995 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::web;
+use flate2::read::GzDecoder;
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use uuid::Uuid;
+use zip::ZipArchive;
+
+use crate::config::get_config;
+use crate::storage_artifacts::queue_uploaded_files_artifacts;
+use crate::storage_backend::{get_storage_backend, StorageBackend};
+use crate::storage_blobs::{hash_local_file, release_stored_blob, store_staged_blob};
+use crate::storage_endpoint::{get_storage_endpoint, StorageEndpointRow};
+use crate::storage_entry::{delete_storage_blobs, StorageError};
+use crate::storage_import::split_file_name;
+use crate::storage_layout::sync_endpoint_file_structure;
+use crate::storage_quotas::{get_user_storage_quota, StorageQuota};
+use crate::util::RequestPool;
+use crate::ws::{send_storage_location_updated, send_to_user, WSState};
+
+// Archives can not be extracted into more than this many bytes, unless configured otherwise
+pub const DEFAULT_EXTRACT_MAX_TOTAL_BYTES: i64 = 10 * 1024 * 1024 * 1024;
+
+// Archives with more members than this are not extracted, unless configured otherwise
+pub const DEFAULT_EXTRACT_MAX_ENTRIES: i64 = 100_000;
+
+// How many times larger than the archive itself its contents can be, unless configured otherwise
+pub const DEFAULT_EXTRACT_MAX_COMPRESSION_RATIO: i64 = 1000;
+
+// How many "name (N)" names are tried for the folder that the archive is extracted into
+const MAX_FOLDER_NAME_ATTEMPTS: u32 = 100;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+pub enum StorageExtractError {
+    ArchiveNotFound,
+    TargetFolderNotFound,
+
+    /// Not an archive that can be extracted, judging by the file's extension
+    UnsupportedFormat,
+
+    /// The archive is damaged, encrypted or not what its extension says
+    InvalidArchive,
+
+    /// Some path inside of the archive points outside of the folder it is extracted into
+    UnsafePath,
+
+    /// The contents are larger than the configured limits allow
+    TooLarge,
+
+    TooManyEntries,
+
+    Storage(StorageError),
+}
+
+impl StorageExtractError {
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            StorageExtractError::ArchiveNotFound => "storage_extract.archive_not_found",
+            StorageExtractError::TargetFolderNotFound => "storage_extract.target_folder_not_found",
+            StorageExtractError::UnsupportedFormat => "storage_extract.unsupported_format",
+            StorageExtractError::InvalidArchive => "storage_extract.invalid_archive",
+            StorageExtractError::UnsafePath => "storage_extract.unsafe_path",
+            StorageExtractError::TooLarge => "storage_extract.too_large",
+            StorageExtractError::TooManyEntries => "storage_extract.too_many_entries",
+            StorageExtractError::Storage(err) => err.get_code(),
+        }
+    }
+}
+
+impl From<StorageError> for StorageExtractError {
+    fn from(err: StorageError) -> Self {
+        StorageExtractError::Storage(err)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum StorageArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+/**
+ * Tell the format of an archive by its name.
+ *
+ * @returns (format, name of the folder to extract the archive into)
+ */
+pub fn detect_archive_format(
+    name: &str,
+    extension: Option<&str>,
+) -> Option<(StorageArchiveFormat, String)> {
+    let extension = extension?.to_lowercase();
+
+    // "name.tar" + "gz"
+    let tar_name = name
+        .len()
+        .checked_sub(4)
+        .filter(|stem_length| name.is_char_boundary(*stem_length))
+        .map(|stem_length| name.split_at(stem_length))
+        .filter(|(_, tar_extension)| tar_extension.eq_ignore_ascii_case(".tar"))
+        .map(|(stem, _)| stem);
+
+    let (format, folder_name) = match (extension.as_str(), tar_name) {
+        ("zip", _) => (StorageArchiveFormat::Zip, name),
+        ("tar", _) => (StorageArchiveFormat::Tar, name),
+        ("tgz", _) => (StorageArchiveFormat::TarGz, name),
+        ("tzst", _) => (StorageArchiveFormat::TarZst, name),
+        ("gz", Some(stem)) => (StorageArchiveFormat::TarGz, stem),
+        ("zst", Some(stem)) => (StorageArchiveFormat::TarZst, stem),
+        _ => return None,
+    };
+
+    // "archive.zip" is extracted into "archive", ".zip" into "zip"
+    let folder_name = if folder_name.is_empty() {
+        extension
+    } else {
+        folder_name.to_string()
+    };
+
+    Some((format, folder_name))
+}
+
+/**
+ * Split a path of an archive member into the names of its folders and its own name.
+ *
+ * Absolute paths and paths that step out of the archive ("../../.bashrc") are rejected, otherwise an archive could
+ * put files next to the folder it is extracted into.
+ */
+fn split_archive_path(path: &str) -> Result<Vec<&str>, StorageExtractError> {
+    if path.starts_with('/') || path.starts_with('\\') {
+        return Err(StorageExtractError::UnsafePath);
+    }
+
+    let mut components = Vec::new();
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => continue,
+            ".." => return Err(StorageExtractError::UnsafePath),
+            _ => components.push(component),
+        }
+    }
+
+    // Windows drive letters ("C:")
+    if let Some(first) = components.first() {
+        if first.len() == 2 && first.ends_with(':') {
+            return Err(StorageExtractError::UnsafePath);
+        }
+    }
+
+    Ok(components)
+}
+
+fn is_valid_entry_name(name: &str) -> bool {
+    name.len() <= 255 && !name.contains('\0')
+}
+
+/**
+ * Name of the n-th attempt to find a free name for the folder an archive is extracted into: "name", "name (2)", ...
+ */
+fn folder_name(name: &str, attempt: u32) -> String {
+    match attempt {
+        0 => name.to_string(),
+        _ => format!("{} ({})", name, attempt + 1),
+    }
+}
+
+/**
+ * Progress of an extraction. Sent to the user that has started it over the WebSocket.
+ */
+pub struct StorageExtractProgress<'a> {
+    pub ws_state: &'a web::Data<Mutex<WSState>>,
+    pub extract_id: String,
+    pub user_id: i32,
+    pub archive_bytes: i64,
+
+    read_bytes: i64,
+    extracted_entries: i64,
+    extracted_bytes: i64,
+    last_sent_at: Option<Instant>,
+}
+
+impl<'a> StorageExtractProgress<'a> {
+    pub fn new(
+        ws_state: &'a web::Data<Mutex<WSState>>,
+        extract_id: String,
+        user_id: i32,
+        archive_bytes: i64,
+    ) -> Self {
+        StorageExtractProgress {
+            ws_state,
+            extract_id,
+            user_id,
+            archive_bytes,
+            read_bytes: 0,
+            extracted_entries: 0,
+            extracted_bytes: 0,
+            last_sent_at: None,
+        }
+    }
+
+    async fn update(&mut self, read_bytes: i64, extractor: &ArchiveExtractor<'_>) {
+        self.read_bytes = read_bytes;
+        self.extracted_entries = extractor.extracted_entries;
+        self.extracted_bytes = extractor.extracted_bytes;
+
+        let is_due = self
+            .last_sent_at
+            .is_none_or(|last_sent_at| last_sent_at.elapsed() >= PROGRESS_INTERVAL);
+
+        if is_due {
+            self.send("in_progress", None).await;
+        }
+    }
+
+    /**
+     * @param status - "in_progress", "done" or "failed"
+     * @param error - Error code, if the extraction has failed
+     */
+    pub async fn send(&mut self, status: &str, error: Option<&str>) {
+        self.last_sent_at = Some(Instant::now());
+
+        let ws_message = json!(
+            {
+                "type": "storage_extract_progress",
+                "payload": {
+                    "extract_id": self.extract_id,
+                    "status": status,
+                    "error": error,
+                    "read_bytes": self.read_bytes,
+                    "archive_bytes": self.archive_bytes,
+                    "extracted_entries": self.extracted_entries,
+                    "extracted_bytes": self.extracted_bytes
+                }
+            }
+        )
+        .to_string();
+
+        send_to_user(self.ws_state, self.user_id, ws_message.as_str()).await;
+    }
+}
+
+/**
+ * Counts the (compressed) bytes that have been read from an archive, for the progress reports
+ */
+struct CountingReader<R: Read> {
+    inner: R,
+    read_bytes: Rc<Cell<i64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        self.read_bytes.set(self.read_bytes.get() + read as i64);
+
+        Ok(read)
+    }
+}
+
+/**
+ * Write the contents of an archive member into a file.
+ *
+ * @param max_bytes - How large the file is allowed to be. Archives can lie about the sizes of their members, so this
+ * is enforced while the file is being written
+ *
+ * @returns size of the file
+ */
+fn write_archive_member(
+    contents: &mut dyn Read,
+    path: &Path,
+    max_bytes: i64,
+) -> Result<i64, StorageExtractError> {
+    let mut file = File::create(path).map_err(|_| StorageError::Internal)?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut size_bytes: i64 = 0;
+
+    loop {
+        let read = match contents.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return Err(StorageExtractError::InvalidArchive),
+        };
+
+        size_bytes += read as i64;
+
+        if size_bytes > max_bytes {
+            return Err(StorageExtractError::TooLarge);
+        }
+
+        file.write_all(&buffer[..read])
+            .map_err(|_| StorageError::Internal)?;
+    }
+
+    Ok(size_bytes)
+}
+
+/**
+ * Creates the entries for the members of an archive, one at a time, and keeps track of what has been created.
+ */
+struct ArchiveExtractor<'a> {
+    endpoint: &'a StorageEndpointRow,
+    storage_backend: Box<dyn StorageBackend>,
+    user_id: i32,
+    pool: &'a RequestPool,
+
+    /**
+     * Path of a folder inside of the archive -> id of the folder that has been created for it
+     */
+    folders: HashMap<String, i64>,
+
+    quota: StorageQuota,
+    max_total_bytes: i64,
+    max_entries: i64,
+
+    seen_entries: i64,
+    extracted_entries: i64,
+    extracted_bytes: i64,
+
+    created_entry_ids: Vec<i64>,
+    new_filesystem_ids: Vec<String>,
+}
+
+impl ArchiveExtractor<'_> {
+    /**
+     * Count a member of the archive towards the limits. Every member counts, including the ones that are skipped.
+     */
+    fn see_entry(&mut self) -> Result<(), StorageExtractError> {
+        self.seen_entries += 1;
+
+        if self.seen_entries > self.max_entries {
+            return Err(StorageExtractError::TooManyEntries);
+        }
+
+        Ok(())
+    }
+
+    async fn create_folder(
+        &mut self,
+        name: &str,
+        parent_folder: i64,
+    ) -> Result<i64, StorageExtractError> {
+        let new_folder_id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO storage_entries (endpoint_id, parent_folder, name, entry_type, created_by) VALUES ($1, $2, $3, 'folder'::storage_entry_type, $4)
+            ON CONFLICT DO NOTHING RETURNING id",
+        )
+        .bind(self.endpoint.id)
+        .bind(parent_folder)
+        .bind(name)
+        .bind(self.user_id)
+        .fetch_optional(self.pool)
+        .await
+        .map_err(|_| StorageError::Internal)?;
+
+        if let Some(new_folder_id) = new_folder_id {
+            self.created_entry_ids.push(new_folder_id);
+            self.extracted_entries += 1;
+
+            return Ok(new_folder_id);
+        }
+
+        // Created in the meantime by someone else
+        let folder_id = sqlx::query_scalar::<_, i64>(
+            "SELECT id FROM storage_entries WHERE endpoint_id = $1 AND parent_folder = $2 AND name = $3 AND entry_type = 'folder'::storage_entry_type",
+        )
+        .bind(self.endpoint.id)
+        .bind(parent_folder)
+        .bind(name)
+        .fetch_one(self.pool)
+        .await
+        .map_err(|_| StorageError::Internal)?;
+
+        Ok(folder_id)
+    }
+
+    /**
+     * Get the folder for a path inside of the archive, creating it (and its parents) if necessary.
+     * Archives do not have to list the folders, files can have paths like "a/b/c.txt" all on their own.
+     *
+     * @param components - Path of the folder, an empty path is the root folder of the extraction
+     */
+    async fn get_folder(&mut self, components: &[&str]) -> Result<i64, StorageExtractError> {
+        let mut folder_id = self.folders[""];
+
+        for depth in 1..=components.len() {
+            let key = components[..depth].join("/");
+
+            folder_id = match self.folders.get(&key) {
+                Some(existing_folder_id) => *existing_folder_id,
+                None => {
+                    let new_folder_id =
+                        self.create_folder(components[depth - 1], folder_id).await?;
+
+                    self.folders.insert(key, new_folder_id);
+
+                    new_folder_id
+                }
+            };
+        }
+
+        Ok(folder_id)
+    }
+
+    async fn add_folder(&mut self, components: &[&str]) -> Result<(), StorageExtractError> {
+        if components.iter().all(|name| is_valid_entry_name(name)) {
+            self.get_folder(components).await?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Store a file of the archive. Files that can not be named the way they are named in the archive, or that would
+     * replace another file of the archive with the same path, are skipped.
+     */
+    async fn add_file(
+        &mut self,
+        components: &[&str],
+        contents: &mut dyn Read,
+    ) -> Result<(), StorageExtractError> {
+        if !components.iter().all(|name| is_valid_entry_name(name)) {
+            return Ok(());
+        }
+
+        let (file_name, folder_components) = components.split_last().unwrap();
+
+        // Anything above the limit means that the archive is too large, the rest of the file is not even read
+        let remaining_bytes = self.max_total_bytes - self.extracted_bytes;
+
+        let staged_filesystem_id = Uuid::new_v4().to_string();
+        let staging_path = self.storage_backend.staging_path(&staged_filesystem_id);
+
+        let size_bytes = match write_archive_member(contents, &staging_path, remaining_bytes) {
+            Ok(size_bytes) => size_bytes,
+            Err(err) => {
+                fs::remove_file(&staging_path).unwrap_or(());
+
+                return Err(err);
+            }
+        };
+
+        if !self.quota.allows(self.extracted_bytes + size_bytes) {
+            fs::remove_file(&staging_path).unwrap_or(());
+
+            return Err(StorageExtractError::Storage(StorageError::QuotaExceeded));
+        }
+
+        let sha256 = hash_local_file(&staging_path);
+
+        if sha256.is_err() {
+            fs::remove_file(&staging_path).unwrap_or(());
+
+            return Err(StorageExtractError::Storage(StorageError::Internal));
+        }
+
+        let sha256 = sha256.unwrap();
+
+        let mime_type = infer::get_from_path(&staging_path)
+            .ok()
+            .flatten()
+            .map(|kind| kind.mime_type());
+
+        let parent_folder = match self.get_folder(folder_components).await {
+            Ok(parent_folder) => parent_folder,
+            Err(err) => {
+                fs::remove_file(&staging_path).unwrap_or(());
+
+                return Err(err);
+            }
+        };
+
+        let stored_blob = store_staged_blob(
+            self.endpoint,
+            self.storage_backend.as_ref(),
+            &staged_filesystem_id,
+            &sha256,
+            size_bytes,
+            self.pool,
+        )
+        .await;
+
+        if stored_blob.is_err() {
+            fs::remove_file(&staging_path).unwrap_or(());
+
+            return Err(StorageExtractError::Storage(StorageError::Internal));
+        }
+
+        let stored_blob = stored_blob.unwrap();
+        let filesystem_id = stored_blob.filesystem_id.clone();
+        let is_new_blob = filesystem_id == staged_filesystem_id;
+
+        if is_new_blob {
+            self.new_filesystem_ids.push(filesystem_id.clone());
+        }
+
+        let (name, extension) = split_file_name(file_name);
+
+        let new_file_id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO storage_entries (endpoint_id, filesystem_id, parent_folder, name, extension, mime_type, size_bytes, sha256, created_by, entry_type)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, 'file'::storage_entry_type)
+            ON CONFLICT DO NOTHING RETURNING id",
+        )
+        .bind(self.endpoint.id)
+        .bind(&filesystem_id)
+        .bind(parent_folder)
+        .bind(name)
+        .bind(extension)
+        .bind(mime_type)
+        .bind(size_bytes)
+        .bind(&sha256)
+        .bind(self.user_id)
+        .fetch_optional(self.pool)
+        .await;
+
+        release_stored_blob(&stored_blob, self.pool).await;
+
+        let new_file_id = new_file_id.map_err(|_| StorageError::Internal)?;
+
+        match new_file_id {
+            Some(new_file_id) => {
+                self.created_entry_ids.push(new_file_id);
+                self.extracted_entries += 1;
+                self.extracted_bytes += size_bytes;
+            }
+            None => {
+                // The archive has more than one file with this path, the first one wins.
+                // A reused blob belongs to other files, it is kept
+                if is_new_blob {
+                    self.new_filesystem_ids.pop();
+                }
+
+                delete_storage_blobs(self.endpoint, &vec![filesystem_id], self.pool)
+                    .await
+                    .unwrap_or(());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn extract_zip(
+    archive_file: File,
+    extractor: &mut ArchiveExtractor<'_>,
+    progress: &mut StorageExtractProgress<'_>,
+) -> Result<(), StorageExtractError> {
+    let mut archive = ZipArchive::new(BufReader::new(archive_file))
+        .map_err(|_| StorageExtractError::InvalidArchive)?;
+
+    let mut read_bytes: i64 = 0;
+
+    for index in 0..archive.len() {
+        extractor.see_entry()?;
+
+        // Fails for encrypted files too
+        let mut file = archive
+            .by_index(index)
+            .map_err(|_| StorageExtractError::InvalidArchive)?;
+
+        let path = file.name().to_string();
+        let components = split_archive_path(&path)?;
+        let is_symlink = file
+            .unix_mode()
+            .is_some_and(|mode| mode & 0o170000 == 0o120000);
+
+        read_bytes += file.compressed_size() as i64;
+
+        if components.is_empty() || is_symlink {
+            continue;
+        }
+
+        if file.is_dir() {
+            extractor.add_folder(&components).await?;
+        } else {
+            extractor.add_file(&components, &mut file).await?;
+        }
+
+        progress.update(read_bytes, extractor).await;
+    }
+
+    Ok(())
+}
+
+async fn extract_tar(
+    archive_reader: impl Read,
+    read_bytes: Rc<Cell<i64>>,
+    extractor: &mut ArchiveExtractor<'_>,
+    progress: &mut StorageExtractProgress<'_>,
+) -> Result<(), StorageExtractError> {
+    let mut archive = tar::Archive::new(archive_reader);
+
+    // Long names (GNU and PAX) are resolved by the reader, they are not entries of their own
+    let entries = archive
+        .entries()
+        .map_err(|_| StorageExtractError::InvalidArchive)?;
+
+    for entry in entries {
+        extractor.see_entry()?;
+
+        let mut entry = entry.map_err(|_| StorageExtractError::InvalidArchive)?;
+        let entry_type = entry.header().entry_type();
+
+        let path = entry.path_bytes().into_owned();
+
+        // Names that are not valid UTF-8 can not be stored
+        let path = match String::from_utf8(path) {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+
+        let components = split_archive_path(&path)?;
+
+        if components.is_empty() {
+            continue;
+        }
+
+        // Links, devices and such are skipped
+        if entry_type.is_dir() {
+            extractor.add_folder(&components).await?;
+        } else if entry_type.is_file() || entry_type.is_contiguous() {
+            extractor.add_file(&components, &mut entry).await?;
+        }
+
+        progress.update(read_bytes.get(), extractor).await;
+    }
+
+    Ok(())
+}
+
+/**
+ * Create a folder named after the archive in `target_folder` and extract the archive into it.
+ *
+ * @returns id of the new folder
+ */
+async fn extract_into_new_folder(
+    endpoint: &StorageEndpointRow,
+    archive: &ArchiveEntry,
+    format: StorageArchiveFormat,
+    folder_name_base: &str,
+    target_folder: Option<i64>,
+    extractor: &mut ArchiveExtractor<'_>,
+    progress: &mut StorageExtractProgress<'_>,
+) -> Result<i64, StorageExtractError> {
+    let mut root_folder_id = None;
+
+    for attempt in 0..MAX_FOLDER_NAME_ATTEMPTS {
+        root_folder_id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO storage_entries (endpoint_id, parent_folder, name, entry_type, created_by) VALUES ($1, $2, $3, 'folder'::storage_entry_type, $4)
+            ON CONFLICT DO NOTHING RETURNING id",
+        )
+        .bind(endpoint.id)
+        .bind(target_folder)
+        .bind(folder_name(folder_name_base, attempt))
+        .bind(extractor.user_id)
+        .fetch_optional(extractor.pool)
+        .await
+        .map_err(|_| StorageError::Internal)?;
+
+        if root_folder_id.is_some() {
+            break;
+        }
+    }
+
+    let root_folder_id = root_folder_id.ok_or(StorageError::NameConflict)?;
+
+    extractor.created_entry_ids.push(root_folder_id);
+    extractor.folders.insert(String::new(), root_folder_id);
+
+    let archive_blob = extractor
+        .storage_backend
+        .local_copy(&archive.filesystem_id)
+        .await?;
+
+    let archive_file = File::open(archive_blob.path()).map_err(|_| StorageError::Internal)?;
+
+    if format == StorageArchiveFormat::Zip {
+        extract_zip(archive_file, extractor, progress).await?;
+    } else {
+        let read_bytes = Rc::new(Cell::new(0));
+        let archive_reader = BufReader::new(CountingReader {
+            inner: archive_file,
+            read_bytes: read_bytes.clone(),
+        });
+
+        match format {
+            StorageArchiveFormat::TarGz => {
+                extract_tar(
+                    GzDecoder::new(archive_reader),
+                    read_bytes,
+                    extractor,
+                    progress,
+                )
+                .await?
+            }
+            StorageArchiveFormat::TarZst => {
+                let decoder = zstd::Decoder::with_buffer(archive_reader)
+                    .map_err(|_| StorageExtractError::InvalidArchive)?;
+
+                extract_tar(decoder, read_bytes, extractor, progress).await?
+            }
+            _ => extract_tar(archive_reader, read_bytes, extractor, progress).await?,
+        }
+    }
+
+    progress.update(archive.size_bytes, extractor).await;
+
+    Ok(root_folder_id)
+}
+
+#[derive(FromRow)]
+struct ArchiveEntry {
+    filesystem_id: String,
+    name: String,
+    extension: Option<String>,
+    size_bytes: i64,
+}
+
+/**
+ * Extract an archive (zip, tar, tar.gz, tar.zst) that is stored on an endpoint into a new folder next to it, or in
+ * another folder of the same endpoint.
+ *
+ * Extraction stops (and everything that has been extracted so far is removed) if the archive tries to write outside
+ * of its folder, goes over the configured size limits or over the user's quota. Files with the same path inside of
+ * the archive are only extracted once.
+ *
+ * Access must be checked by the caller.
+ *
+ * @param target_folder - Folder to create the new folder in. `None` means the root of the endpoint
+ * @param user_id - Owner of the extracted entries
+ *
+ * @returns id of the new folder
+ */
+pub async fn extract_archive(
+    endpoint: &StorageEndpointRow,
+    archive_entry_id: i64,
+    target_folder: Option<i64>,
+    user_id: i32,
+    progress: &mut StorageExtractProgress<'_>,
+    pool: &RequestPool,
+) -> Result<i64, StorageExtractError> {
+    let archive = sqlx::query_as::<_, ArchiveEntry>(
+        "SELECT filesystem_id::TEXT, name, extension, size_bytes FROM storage_entries
+        WHERE endpoint_id = $1 AND id = $2 AND entry_type = 'file'::storage_entry_type",
+    )
+    .bind(endpoint.id)
+    .bind(archive_entry_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| StorageError::Internal)?
+    .ok_or(StorageExtractError::ArchiveNotFound)?;
+
+    let (format, folder_name_base) =
+        detect_archive_format(&archive.name, archive.extension.as_deref())
+            .ok_or(StorageExtractError::UnsupportedFormat)?;
+
+    if let Some(target_folder) = target_folder {
+        let target_folder_exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM storage_entries WHERE endpoint_id = $1 AND id = $2 AND entry_type = 'folder'::storage_entry_type)",
+        )
+        .bind(endpoint.id)
+        .bind(target_folder)
+        .fetch_one(pool)
+        .await
+        .map_err(|_| StorageError::Internal)?;
+
+        if !target_folder_exists {
+            return Err(StorageExtractError::TargetFolderNotFound);
+        }
+    }
+
+    let config = get_config(pool).await;
+    let config_value = |key: &str, default: i64| {
+        config
+            .get(key)
+            .and_then(|value| value.parse::<i64>().ok())
+            .unwrap_or(default)
+    };
+
+    let max_compression_ratio = config_value(
+        "storage.extract.max_compression_ratio",
+        DEFAULT_EXTRACT_MAX_COMPRESSION_RATIO,
+    );
+
+    let max_total_bytes = config_value(
+        "storage.extract.max_total_bytes",
+        DEFAULT_EXTRACT_MAX_TOTAL_BYTES,
+    )
+    .min(
+        archive
+            .size_bytes
+            .max(1)
+            .saturating_mul(max_compression_ratio),
+    );
+
+    let mut extractor = ArchiveExtractor {
+        endpoint,
+        storage_backend: get_storage_backend(endpoint, pool).await?,
+        user_id,
+        pool,
+        folders: HashMap::new(),
+        quota: get_user_storage_quota(endpoint, user_id, pool).await?,
+        max_total_bytes,
+        max_entries: config_value("storage.extract.max_entries", DEFAULT_EXTRACT_MAX_ENTRIES),
+        seen_entries: 0,
+        extracted_entries: 0,
+        extracted_bytes: 0,
+        created_entry_ids: Vec::new(),
+        new_filesystem_ids: Vec::new(),
+    };
+
+    let extract_result = extract_into_new_folder(
+        endpoint,
+        &archive,
+        format,
+        &folder_name_base,
+        target_folder,
+        &mut extractor,
+        progress,
+    )
+    .await;
+
+    match extract_result {
+        Ok(root_folder_id) => {
+            sync_endpoint_file_structure(endpoint.id, &vec![root_folder_id], pool).await;
+
+            queue_uploaded_files_artifacts(
+                endpoint,
+                extractor.new_filesystem_ids,
+                Some(user_id),
+                pool,
+            )
+            .await;
+
+            Ok(root_folder_id)
+        }
+        Err(err) => {
+            error!(
+                "(storage extract) Could not extract archive {} of endpoint {}. Removing {} extracted entries. {}",
+                archive_entry_id,
+                endpoint.id,
+                extractor.created_entry_ids.len(),
+                err.get_code()
+            );
+
+            let _ =
+                sqlx::query("DELETE FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)")
+                    .bind(endpoint.id)
+                    .bind(&extractor.created_entry_ids)
+                    .execute(pool)
+                    .await;
+
+            delete_storage_blobs(endpoint, &extractor.new_filesystem_ids, pool)
+                .await
+                .unwrap_or(());
+
+            Err(err)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StorageExtractJobPayload {
+    pub extract_id: String,
+    pub user_id: i32,
+    pub endpoint_id: i32,
+    pub entry_id: i64,
+    pub target_folder: Option<i64>,
+}
+
+/**
+ * Extract an archive in the background (see `storage_extract_archive`), reporting the progress to the user.
+ *
+ * Blocks, runs in a job worker.
+ */
+pub fn run_extract_archive_job(
+    runtime: &actix_rt::Runtime,
+    pool: &RequestPool,
+    ws_state: &web::Data<Mutex<WSState>>,
+    payload: StorageExtractJobPayload,
+) -> Result<(), String> {
+    let endpoint = runtime
+        .block_on(get_storage_endpoint(payload.endpoint_id, pool))
+        .map_err(|_| format!("Endpoint {} does not exist", payload.endpoint_id))?;
+
+    let archive_bytes = runtime
+        .block_on(
+            sqlx::query_scalar::<_, Option<i64>>(
+                "SELECT size_bytes FROM storage_entries WHERE endpoint_id = $1 AND id = $2",
+            )
+            .bind(payload.endpoint_id)
+            .bind(payload.entry_id)
+            .fetch_optional(pool),
+        )
+        .map_err(|err| format!("Could not load the archive. {}", err))?
+        .ok_or_else(|| format!("Archive {} does not exist", payload.entry_id))?
+        .unwrap_or(0);
+
+    let mut progress = StorageExtractProgress::new(
+        ws_state,
+        payload.extract_id.clone(),
+        payload.user_id,
+        archive_bytes,
+    );
+
+    let extract_result = runtime.block_on(extract_archive(
+        &endpoint,
+        payload.entry_id,
+        payload.target_folder,
+        payload.user_id,
+        &mut progress,
+        pool,
+    ));
+
+    match extract_result {
+        Ok(_) => {
+            runtime.block_on(progress.send("done", None));
+
+            runtime.block_on(send_storage_location_updated(
+                ws_state,
+                None,
+                endpoint.id,
+                vec![payload.target_folder],
+                true,
+                false,
+            ));
+
+            Ok(())
+        }
+        Err(err) => {
+            runtime.block_on(progress.send("failed", Some(err.get_code())));
+
+            Err(format!("Could not extract the archive. {}", err.get_code()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_unsafe(path: &str) -> bool {
+        matches!(
+            split_archive_path(path),
+            Err(StorageExtractError::UnsafePath)
+        )
+    }
+
+    #[test]
+    fn splits_relative_paths() {
+        assert_eq!(
+            split_archive_path("docs/2024/report.pdf").ok(),
+            Some(vec!["docs", "2024", "report.pdf"])
+        );
+        assert_eq!(
+            split_archive_path("./docs//report.pdf").ok(),
+            Some(vec!["docs", "report.pdf"])
+        );
+        assert_eq!(split_archive_path("docs/").ok(), Some(vec!["docs"]));
+    }
+
+    #[test]
+    fn rejects_parent_folder_components() {
+        assert!(is_unsafe(".."));
+        assert!(is_unsafe("../.bashrc"));
+        assert!(is_unsafe("docs/../../.bashrc"));
+        assert!(is_unsafe("docs/.."));
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        assert!(is_unsafe("/etc/passwd"));
+        assert!(is_unsafe("//server/share/file"));
+        assert!(is_unsafe("\\Windows\\win.ini"));
+    }
+
+    #[test]
+    fn rejects_drive_letter_paths() {
+        assert!(is_unsafe("C:"));
+        assert!(is_unsafe("C:/Windows/win.ini"));
+        assert!(is_unsafe("./d:/file"));
+    }
+}
@@ -0,0 +1,586 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use log::*;
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::storage_endpoint::{get_storage_endpoint, StorageEndpointRow};
+use crate::storage_entry::{delete_file_artifacts, delete_storage_blobs};
+use crate::storage_layout::{sync_file_structure, LocalBlobLayout, DETACHED_BLOBS_FOLDER};
+use crate::util::RequestPool;
+
+// Files that were modified recently might still be written to (uploads, archives), they are never reported
+const STALE_FILE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+const UPLOAD_STAGING_FOLDER: &str = "upload_staging";
+
+pub enum StorageFsckError {
+    EndpointNotFound,
+
+    /// The endpoint's folder can not be read. Most likely the disk is not mounted, everything would look missing
+    BasePathUnavailable,
+
+    Internal,
+}
+
+impl StorageFsckError {
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            StorageFsckError::EndpointNotFound => "storage_fsck.endpoint_not_found",
+            StorageFsckError::BasePathUnavailable => "storage_fsck.base_path_unavailable",
+            StorageFsckError::Internal => "storage_fsck.internal",
+        }
+    }
+}
+
+/**
+ * A file (or a previous version of a file) whose blob is gone
+ */
+#[derive(Serialize)]
+pub struct StorageFsckMissingBlob {
+    pub entry_id: i64,
+    pub version_id: Option<i64>,
+    pub filesystem_id: String,
+}
+
+#[derive(Serialize)]
+pub struct StorageFsckSizeMismatch {
+    pub entry_id: i64,
+    pub version_id: Option<i64>,
+    pub filesystem_id: String,
+    pub recorded_size_bytes: Option<i64>,
+    pub actual_size_bytes: i64,
+}
+
+#[derive(Serialize)]
+pub struct StorageFsckReport {
+    pub endpoint_id: i32,
+
+    /**
+     * Whether the problems have been fixed, or only reported
+     */
+    pub repaired: bool,
+
+    /**
+     * Blobs of S3 endpoints are not checked, only their artifacts
+     */
+    pub blobs_checked: bool,
+
+    /**
+     * Files and versions whose blob does not exist. Repair removes them, there is nothing to serve.
+     */
+    pub missing_blobs: Vec<StorageFsckMissingBlob>,
+
+    /**
+     * Files inside of the endpoint's folder that do not belong to any file, version or trashed file.
+     *
+     * Repair removes the blobs y has written itself. Files stored under the tree of an endpoint that preserves the
+     * file structure are only reported, they can be imported instead.
+     */
+    pub untracked_files: Vec<String>,
+
+    /**
+     * Thumbnails, video frames and preview videos of blobs that no longer exist. Repair removes them.
+     */
+    pub orphaned_artifacts: Vec<String>,
+
+    /**
+     * Files whose recorded size differs from the size of their blob. Repair records the actual size.
+     */
+    pub size_mismatches: Vec<StorageFsckSizeMismatch>,
+
+    /**
+     * Leftovers in the upload staging folder that no upload session or archive is using. Repair removes them.
+     */
+    pub stale_staging_files: Vec<String>,
+}
+
+#[derive(FromRow)]
+struct StoredBlob {
+    entry_id: i64,
+    version_id: Option<i64>,
+    filesystem_id: String,
+    size_bytes: Option<i64>,
+}
+
+fn is_stale(path: &Path) -> bool {
+    fs::symlink_metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > STALE_FILE_AGE)
+}
+
+fn display_relative(path: &Path, base_path: &Path) -> String {
+    path.strip_prefix(base_path)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+/**
+ * Blobs used by anything in the given endpoints: files, previous versions, trashed files, blobs that are being
+ * moved in from another endpoint and blobs that are about to be reused by a new file
+ */
+async fn get_referenced_blobs(
+    endpoint_ids: &Vec<i32>,
+    pool: &RequestPool,
+) -> Result<HashSet<String>, StorageFsckError> {
+    sqlx::query_scalar::<_, String>(
+        "SELECT filesystem_id::TEXT FROM storage_entries WHERE endpoint_id = ANY($1) AND filesystem_id IS NOT NULL
+        UNION SELECT filesystem_id::TEXT FROM storage_entry_versions WHERE endpoint_id = ANY($1)
+        UNION SELECT storage_trash_entries.filesystem_id::TEXT FROM storage_trash_entries
+        INNER JOIN storage_trash ON storage_trash.id = storage_trash_entries.trash_id
+        WHERE storage_trash.endpoint_id = ANY($1) AND storage_trash_entries.filesystem_id IS NOT NULL
+        UNION SELECT storage_move_blobs.target_filesystem_id::TEXT FROM storage_move_blobs
+        INNER JOIN storage_moves ON storage_moves.id = storage_move_blobs.move_id
+        WHERE storage_moves.target_endpoint_id = ANY($1)
+        UNION SELECT filesystem_id::TEXT FROM storage_blob_reservations WHERE endpoint_id = ANY($1)",
+    )
+    .bind(endpoint_ids)
+    .fetch_all(pool)
+    .await
+    .map(|filesystem_ids| filesystem_ids.into_iter().collect())
+    .map_err(|_| StorageFsckError::Internal)
+}
+
+/**
+ * Endpoints that share a folder see each other's files
+ */
+async fn get_endpoints_sharing_path(
+    column: &str,
+    path: &str,
+    pool: &RequestPool,
+) -> Result<Vec<i32>, StorageFsckError> {
+    sqlx::query_scalar::<_, i32>(&format!(
+        "SELECT id FROM storage_endpoints WHERE {} = $1",
+        column
+    ))
+    .bind(path)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageFsckError::Internal)
+}
+
+/**
+ * Find the files and versions whose blobs are missing or have a different size
+ */
+async fn check_stored_blobs(
+    layout: &LocalBlobLayout,
+    placed_blobs: &HashMap<String, String>,
+    report: &mut StorageFsckReport,
+    pool: &RequestPool,
+) -> Result<(), StorageFsckError> {
+    let stored_blobs = sqlx::query_as::<_, StoredBlob>(
+        "SELECT id AS entry_id, NULL::BIGINT AS version_id, filesystem_id::TEXT, size_bytes FROM storage_entries
+        WHERE endpoint_id = $1 AND entry_type = 'file'::storage_entry_type AND filesystem_id IS NOT NULL
+        UNION ALL SELECT entry_id, id AS version_id, filesystem_id::TEXT, size_bytes FROM storage_entry_versions WHERE endpoint_id = $1",
+    )
+    .bind(layout.endpoint_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageFsckError::Internal)?;
+
+    for stored_blob in stored_blobs {
+        let blob_path = match placed_blobs.get(&stored_blob.filesystem_id) {
+            Some(relative_path) => layout.base_path.join(relative_path),
+            None => layout.unplaced_blob_path(&stored_blob.filesystem_id),
+        };
+
+        match fs::metadata(&blob_path) {
+            Ok(metadata) if metadata.is_file() => {
+                let actual_size_bytes = metadata.len() as i64;
+
+                if stored_blob.size_bytes != Some(actual_size_bytes) {
+                    report.size_mismatches.push(StorageFsckSizeMismatch {
+                        entry_id: stored_blob.entry_id,
+                        version_id: stored_blob.version_id,
+                        filesystem_id: stored_blob.filesystem_id,
+                        recorded_size_bytes: stored_blob.size_bytes,
+                        actual_size_bytes,
+                    });
+                }
+            }
+            _ => report.missing_blobs.push(StorageFsckMissingBlob {
+                entry_id: stored_blob.entry_id,
+                version_id: stored_blob.version_id,
+                filesystem_id: stored_blob.filesystem_id,
+            }),
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Find the files inside of the endpoint's folder that nothing uses
+ *
+ * @returns paths of the untracked files that can be safely removed
+ */
+fn check_untracked_files(
+    layout: &LocalBlobLayout,
+    placed_blobs: &HashMap<String, String>,
+    referenced_blobs: &HashSet<String>,
+    report: &mut StorageFsckReport,
+) -> Vec<PathBuf> {
+    let mut removable: Vec<PathBuf> = Vec::new();
+
+    let placed_paths = placed_blobs
+        .values()
+        .map(|relative_path| layout.base_path.join(relative_path))
+        .collect::<HashSet<PathBuf>>();
+
+    // Folders that hold blobs named by their filesystem_id
+    let mut blob_folders = vec![layout.base_path.clone()];
+
+    if layout.preserve_file_structure {
+        blob_folders.push(layout.base_path.join(DETACHED_BLOBS_FOLDER).join("blobs"));
+    }
+
+    // The tree of an endpoint that preserves the file structure
+    let mut folders = vec![layout.base_path.clone()];
+
+    while let Some(folder) = folders.pop() {
+        let children = match fs::read_dir(&folder) {
+            Ok(children) => children,
+            Err(_) => continue,
+        };
+
+        let is_blob_folder = blob_folders.contains(&folder);
+
+        for child in children.filter_map(|child| child.ok()) {
+            let path = child.path();
+
+            let file_type = match child.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+
+            if file_type.is_dir() {
+                if !layout.preserve_file_structure {
+                    continue;
+                }
+
+                if folder == layout.base_path && child.file_name() == DETACHED_BLOBS_FOLDER {
+                    folders.push(path.join("blobs"));
+                } else {
+                    folders.push(path);
+                }
+
+                continue;
+            }
+
+            if placed_paths.contains(&path) || !is_stale(&path) {
+                continue;
+            }
+
+            let file_name = child.file_name();
+            let file_name = file_name.to_str();
+
+            if is_blob_folder && file_name.is_some_and(|name| referenced_blobs.contains(name)) {
+                continue;
+            }
+
+            report
+                .untracked_files
+                .push(display_relative(&path, &layout.base_path));
+
+            // The root of a preserved tree holds the user's files, only the blobs written before the endpoint
+            // started preserving the file structure are y's
+            let is_blob = is_blob_folder
+                && (!layout.preserve_file_structure
+                    || folder != layout.base_path
+                    || file_name.is_some_and(|name| Uuid::parse_str(name).is_ok()));
+
+            if is_blob {
+                removable.push(path);
+            }
+        }
+    }
+
+    removable
+}
+
+/**
+ * Find the artifacts generated for blobs that no longer exist
+ *
+ * @returns paths of the orphaned artifacts
+ */
+fn check_orphaned_artifacts(
+    artifacts_path: &Path,
+    referenced_blobs: &HashSet<String>,
+    report: &mut StorageFsckReport,
+) -> Vec<PathBuf> {
+    let mut orphaned: Vec<PathBuf> = Vec::new();
+
+    for artifacts_folder in ["thumbnails", "preview_videos", "hls"] {
+        let children = match fs::read_dir(artifacts_path.join(artifacts_folder)) {
+            Ok(children) => children,
+            Err(_) => continue,
+        };
+
+        for child in children.filter_map(|child| child.ok()) {
+            let path = child.path();
+
+            // <filesystem_id>.webp, <filesystem_id>.mp4 or a <filesystem_id> folder with video frames or HLS renditions
+            let filesystem_id = child
+                .file_name()
+                .to_str()
+                .map(|name| name.split('.').next().unwrap_or(name).to_string());
+
+            let is_orphaned = filesystem_id
+                .is_some_and(|filesystem_id| !referenced_blobs.contains(&filesystem_id));
+
+            if is_orphaned && is_stale(&path) {
+                report
+                    .orphaned_artifacts
+                    .push(display_relative(&path, artifacts_path));
+
+                orphaned.push(path);
+            }
+        }
+    }
+
+    orphaned
+}
+
+/**
+ * Find the files in the upload staging folder that are not used by an upload session or an archive
+ */
+async fn check_stale_staging_files(
+    report: &mut StorageFsckReport,
+    pool: &RequestPool,
+) -> Result<Vec<PathBuf>, StorageFsckError> {
+    let in_use = sqlx::query_scalar::<_, String>(
+        "SELECT id::TEXT FROM storage_upload_sessions UNION SELECT filesystem_id::TEXT FROM storage_archives",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageFsckError::Internal)?
+    .into_iter()
+    .collect::<HashSet<String>>();
+
+    let mut stale: Vec<PathBuf> = Vec::new();
+
+    let children = match fs::read_dir(UPLOAD_STAGING_FOLDER) {
+        Ok(children) => children,
+        Err(_) => return Ok(stale),
+    };
+
+    for child in children.filter_map(|child| child.ok()) {
+        let path = child.path();
+
+        let is_in_use = child
+            .file_name()
+            .to_str()
+            .is_some_and(|name| in_use.contains(name));
+
+        if !is_in_use && path.is_file() && is_stale(&path) {
+            report
+                .stale_staging_files
+                .push(display_relative(&path, Path::new(UPLOAD_STAGING_FOLDER)));
+
+            stale.push(path);
+        }
+    }
+
+    Ok(stale)
+}
+
+async fn repair_stored_blobs(
+    endpoint: &StorageEndpointRow,
+    report: &StorageFsckReport,
+    pool: &RequestPool,
+) -> Result<(), StorageFsckError> {
+    let (missing_versions, missing_files): (
+        Vec<&StorageFsckMissingBlob>,
+        Vec<&StorageFsckMissingBlob>,
+    ) = report
+        .missing_blobs
+        .iter()
+        .partition(|missing| missing.version_id.is_some());
+
+    let missing_file_ids = missing_files
+        .iter()
+        .map(|missing| missing.entry_id)
+        .collect::<Vec<i64>>();
+
+    let missing_version_ids = missing_versions
+        .iter()
+        .filter_map(|missing| missing.version_id)
+        .collect::<Vec<i64>>();
+
+    let mut transaction = pool.begin().await.map_err(|_| StorageFsckError::Internal)?;
+
+    sqlx::query("DELETE FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)")
+        .bind(endpoint.id)
+        .bind(&missing_file_ids)
+        .execute(&mut *transaction)
+        .await
+        .map_err(|_| StorageFsckError::Internal)?;
+
+    // Previous versions of the removed files have nothing to belong to
+    let orphaned_version_blobs = sqlx::query_scalar::<_, String>(
+        "DELETE FROM storage_entry_versions WHERE endpoint_id = $1 AND (id = ANY($2) OR entry_id = ANY($3)) RETURNING filesystem_id::TEXT",
+    )
+    .bind(endpoint.id)
+    .bind(&missing_version_ids)
+    .bind(&missing_file_ids)
+    .fetch_all(&mut *transaction)
+    .await
+    .map_err(|_| StorageFsckError::Internal)?;
+
+    for size_mismatch in &report.size_mismatches {
+        // The hash was computed for different contents
+        let update_query = match size_mismatch.version_id {
+            Some(_) => "UPDATE storage_entry_versions SET size_bytes = $1, sha256 = NULL WHERE endpoint_id = $2 AND id = $3",
+            None => "UPDATE storage_entries SET size_bytes = $1, sha256 = NULL WHERE endpoint_id = $2 AND id = $3",
+        };
+
+        sqlx::query(update_query)
+            .bind(size_mismatch.actual_size_bytes)
+            .bind(endpoint.id)
+            .bind(size_mismatch.version_id.unwrap_or(size_mismatch.entry_id))
+            .execute(&mut *transaction)
+            .await
+            .map_err(|_| StorageFsckError::Internal)?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .map_err(|_| StorageFsckError::Internal)?;
+
+    delete_storage_blobs(endpoint, &orphaned_version_blobs, pool)
+        .await
+        .unwrap_or(());
+
+    if !missing_file_ids.is_empty() {
+        if let Some(artifacts_path) = &endpoint.artifacts_path {
+            for missing in &missing_files {
+                delete_file_artifacts(endpoint.id, artifacts_path, &missing.filesystem_id);
+            }
+        }
+
+        // Forget where the removed files used to be
+        sync_file_structure(&LocalBlobLayout::new(endpoint), &Vec::new(), pool)
+            .await
+            .map_err(|_| StorageFsckError::Internal)?;
+    }
+
+    Ok(())
+}
+
+fn remove_paths(paths: Vec<PathBuf>) {
+    for path in paths {
+        let remove_result = if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+
+        if let Err(err) = remove_result {
+            warn!(
+                "(storage fsck) Could not remove {}. {}",
+                path.display(),
+                err
+            );
+        }
+    }
+}
+
+/**
+ * Check that the database and the files on disk agree with each other, optionally fixing what they disagree on.
+ *
+ * Files that were modified in the last 24 hours are left alone, they might still be in use. Only the blobs of local
+ * endpoints are checked.
+ */
+pub async fn check_storage_endpoint(
+    endpoint_id: i32,
+    repair: bool,
+    pool: &RequestPool,
+) -> Result<StorageFsckReport, StorageFsckError> {
+    let endpoint = get_storage_endpoint(endpoint_id, pool)
+        .await
+        .map_err(|_| StorageFsckError::EndpointNotFound)?;
+
+    let mut report = StorageFsckReport {
+        endpoint_id,
+        repaired: repair,
+        blobs_checked: endpoint.endpoint_type == "local_fs",
+        missing_blobs: Vec::new(),
+        untracked_files: Vec::new(),
+        orphaned_artifacts: Vec::new(),
+        size_mismatches: Vec::new(),
+        stale_staging_files: Vec::new(),
+    };
+
+    let mut removable: Vec<PathBuf> = Vec::new();
+
+    if report.blobs_checked {
+        let layout = LocalBlobLayout::new(&endpoint);
+
+        if fs::read_dir(&layout.base_path).is_err() {
+            return Err(StorageFsckError::BasePathUnavailable);
+        }
+
+        let placed_blobs = sqlx::query_as::<_, (String, String)>(
+            "SELECT filesystem_id::TEXT, relative_path FROM storage_entry_paths WHERE endpoint_id = $1 AND filesystem_id IS NOT NULL",
+        )
+        .bind(endpoint_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|_| StorageFsckError::Internal)?
+        .into_iter()
+        .collect::<HashMap<String, String>>();
+
+        check_stored_blobs(&layout, &placed_blobs, &mut report, pool).await?;
+
+        let endpoint_ids =
+            get_endpoints_sharing_path("base_path", &endpoint.base_path, pool).await?;
+        let referenced_blobs = get_referenced_blobs(&endpoint_ids, pool).await?;
+
+        removable.extend(check_untracked_files(
+            &layout,
+            &placed_blobs,
+            &referenced_blobs,
+            &mut report,
+        ));
+    }
+
+    if let Some(artifacts_path) = &endpoint.artifacts_path {
+        let endpoint_ids =
+            get_endpoints_sharing_path("artifacts_path", artifacts_path, pool).await?;
+        let referenced_blobs = get_referenced_blobs(&endpoint_ids, pool).await?;
+
+        removable.extend(check_orphaned_artifacts(
+            Path::new(artifacts_path),
+            &referenced_blobs,
+            &mut report,
+        ));
+    }
+
+    removable.extend(check_stale_staging_files(&mut report, pool).await?);
+
+    info!(
+        "(storage fsck) Endpoint {}: {} missing blobs, {} untracked files, {} orphaned artifacts, {} size mismatches, {} stale staging files{}",
+        endpoint_id,
+        report.missing_blobs.len(),
+        report.untracked_files.len(),
+        report.orphaned_artifacts.len(),
+        report.size_mismatches.len(),
+        report.stale_staging_files.len(),
+        if repair { " (repairing)" } else { "" }
+    );
+
+    if repair {
+        repair_stored_blobs(&endpoint, &report, pool).await?;
+        remove_paths(removable);
+    }
+
+    Ok(report)
+}
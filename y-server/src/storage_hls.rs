@@ -0,0 +1,548 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::FromRow;
+
+use crate::config::get_config;
+use crate::request::error;
+use crate::storage_artifacts::{get_blob_parent_folders, StorageArtifactsJobPayload};
+use crate::storage_backend::get_storage_backend;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::StorageError;
+use crate::util::RequestPool;
+use crate::ws::{send_storage_location_updated, WSState};
+
+/// Name of the playlist that lists all the renditions of a video
+pub const HLS_MASTER_PLAYLIST: &str = "master.m3u8";
+
+/// Maximum number of renditions in a ladder
+pub const MAX_HLS_RENDITIONS: usize = 8;
+
+const HLS_SEGMENT_SECONDS: u32 = 6;
+
+/// Audio bitrate of every rendition, in kbps
+const HLS_AUDIO_BITRATE: u32 = 128;
+
+const DEFAULT_HLS_HEIGHTS: &str = "360,720,1080";
+const DEFAULT_HLS_BITRATES: &str = "800,2800,5000";
+
+#[derive(Clone, Copy)]
+pub struct HlsRendition {
+    pub height: u32,
+
+    /// Video bitrate, in kbps
+    pub bitrate: u32,
+}
+
+impl HlsRendition {
+    fn folder_name(&self) -> String {
+        format!("{}p", self.height)
+    }
+}
+
+#[derive(FromRow)]
+struct HlsEntryRow {
+    filesystem_id: String,
+    artifacts_path: Option<String>,
+}
+
+/**
+ * Parse a comma separated list of positive integers, like `360,720,1080`
+ */
+pub fn parse_hls_ladder(value: &str) -> Option<Vec<u32>> {
+    let ladder = value
+        .split(',')
+        .map(|step| step.trim().parse::<u32>().ok().filter(|step| *step > 0))
+        .collect::<Option<Vec<u32>>>()?;
+
+    if ladder.is_empty() || ladder.len() > MAX_HLS_RENDITIONS {
+        return None;
+    }
+
+    Some(ladder)
+}
+
+/**
+ * Get the renditions to generate from the height and bitrate ladders in the config. Heights and bitrates are paired
+ * in order, extra steps in the longer ladder are ignored.
+ */
+pub fn get_hls_renditions(storage_config: &HashMap<String, String>) -> Vec<HlsRendition> {
+    let parse_config_ladder = |key: &str, default: &str| {
+        storage_config
+            .get(key)
+            .and_then(|value| parse_hls_ladder(value))
+            .unwrap_or_else(|| parse_hls_ladder(default).unwrap())
+    };
+
+    let heights = parse_config_ladder("storage.transcode_videos.hls.heights", DEFAULT_HLS_HEIGHTS);
+    let bitrates = parse_config_ladder(
+        "storage.transcode_videos.hls.bitrates",
+        DEFAULT_HLS_BITRATES,
+    );
+
+    let mut renditions = heights
+        .into_iter()
+        .zip(bitrates)
+        .map(|(height, bitrate)| HlsRendition { height, bitrate })
+        .collect::<Vec<HlsRendition>>();
+
+    renditions.sort_by_key(|rendition| rendition.height);
+    renditions.dedup_by_key(|rendition| rendition.height);
+
+    renditions
+}
+
+/**
+ * Folder with the playlists and segments of a video, `<artifacts>/hls/<filesystem_id>`
+ */
+pub fn get_hls_path(endpoint_artifacts_path: &str, filesystem_id: &str) -> PathBuf {
+    Path::new(endpoint_artifacts_path)
+        .join("hls")
+        .join(filesystem_id)
+}
+
+/**
+ * Get the dimensions of the first video stream of a file. `None` if ffprobe is not available.
+ */
+fn probe_video_dimensions(file_path: &Path) -> Option<(u32, u32)> {
+    let ffprobe_bin_path = env::var("FFPROBE_BIN").ok()?;
+    let ffprobe_bin_path = Path::new(&ffprobe_bin_path);
+
+    if !ffprobe_bin_path.exists() {
+        return None;
+    }
+
+    let ffprobe_result = Command::new(ffprobe_bin_path)
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=width,height")
+        .arg("-of")
+        .arg("csv=s=x:p=0")
+        .arg(file_path.to_str().unwrap())
+        .output()
+        .ok()?;
+
+    if !ffprobe_result.status.success() {
+        return None;
+    }
+
+    let output = String::from_utf8_lossy(&ffprobe_result.stdout);
+    let (width, height) = output.trim().split_once('x')?;
+
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+fn transcode_hls_rendition(
+    ffmpeg_bin_path: &Path,
+    file_path: &Path,
+    rendition_path: &Path,
+    rendition: &HlsRendition,
+) -> Result<(), StorageError> {
+    let ffmpeg_hwaccel_nvenc =
+        env::var("FFMPEG_HWACCEL_NVENC").unwrap_or("false".to_string()) == "true";
+
+    fs::create_dir_all(rendition_path).map_err(|_| StorageError::Internal)?;
+
+    let mut command = Command::new(ffmpeg_bin_path);
+
+    command.arg("-y");
+
+    if ffmpeg_hwaccel_nvenc {
+        command
+            .arg("-hwaccel")
+            .arg("cuda")
+            .arg("-hwaccel_output_format")
+            .arg("cuda");
+    }
+
+    command
+        .arg("-i")
+        .arg(file_path.to_str().unwrap())
+        .arg("-map")
+        .arg("0:v:0")
+        // Videos without sound are fine
+        .arg("-map")
+        .arg("0:a:0?")
+        .arg("-vf")
+        .arg(if ffmpeg_hwaccel_nvenc {
+            format!("scale_cuda=-2:{}", rendition.height)
+        } else {
+            format!("scale=-2:{}", rendition.height)
+        })
+        .arg("-c:v")
+        .arg(if ffmpeg_hwaccel_nvenc {
+            "h264_nvenc"
+        } else {
+            "libx264"
+        })
+        .arg("-b:v")
+        .arg(format!("{}k", rendition.bitrate))
+        .arg("-maxrate")
+        .arg(format!("{}k", rendition.bitrate))
+        .arg("-bufsize")
+        .arg(format!("{}k", rendition.bitrate * 2))
+        // Keyframes at the same timestamps in every rendition, so players can switch between them at segment boundaries
+        .arg("-force_key_frames")
+        .arg(format!("expr:gte(t,n_forced*{})", HLS_SEGMENT_SECONDS))
+        .arg("-c:a")
+        .arg("aac")
+        .arg("-b:a")
+        .arg(format!("{}k", HLS_AUDIO_BITRATE))
+        .arg("-ac")
+        .arg("2")
+        .arg("-f")
+        .arg("hls")
+        .arg("-hls_time")
+        .arg(HLS_SEGMENT_SECONDS.to_string())
+        .arg("-hls_playlist_type")
+        .arg("vod")
+        .arg("-hls_segment_filename")
+        .arg(rendition_path.join("segment_%04d.ts").to_str().unwrap())
+        .arg(rendition_path.join("index.m3u8").to_str().unwrap());
+
+    match command.output() {
+        Ok(ffmpeg_result) if ffmpeg_result.status.success() => Ok(()),
+        _ => Err(StorageError::ConvertError),
+    }
+}
+
+fn write_master_playlist(
+    hls_path: &Path,
+    renditions: &[HlsRendition],
+    source_dimensions: Option<(u32, u32)>,
+) -> Result<(), StorageError> {
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+
+    for rendition in renditions {
+        let bandwidth = (rendition.bitrate as u64 + HLS_AUDIO_BITRATE as u64) * 1000;
+
+        let resolution = match source_dimensions {
+            // Same as ffmpeg's `scale=-2:<height>`, the width is rounded to an even number
+            Some((width, height)) if height > 0 => format!(
+                ",RESOLUTION={}x{}",
+                ((width as u64 * rendition.height as u64 / height as u64) / 2 * 2),
+                rendition.height
+            ),
+            _ => String::new(),
+        };
+
+        playlist.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={}{}\n{}/index.m3u8\n",
+            bandwidth,
+            resolution,
+            rendition.folder_name()
+        ));
+    }
+
+    fs::write(hls_path.join(HLS_MASTER_PLAYLIST), playlist).map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Transcode a video into an HLS rendition ladder: a segment playlist per rendition and a master playlist that lists
+ * them, in `<artifacts>/hls/<filesystem_id>`. Renditions taller than the source video are skipped, but at least one
+ * is always generated.
+ *
+ * Previously generated renditions are only replaced once all the new ones are ready. Blocks, runs in a job worker.
+ */
+pub fn generate_hls_renditions(
+    filesystem_id: &str,
+    file_path: &Path,
+    endpoint_artifacts_path: &str,
+    renditions: &[HlsRendition],
+) -> Result<(), StorageError> {
+    let ffmpeg_bin_path = env::var("FFMPEG_BIN");
+
+    let ffmpeg_bin_path = match &ffmpeg_bin_path {
+        Ok(ffmpeg_bin_path) => Path::new(ffmpeg_bin_path),
+        Err(_) => return Ok(()),
+    };
+
+    if !ffmpeg_bin_path.exists() {
+        return Err(StorageError::Internal);
+    }
+
+    let source_dimensions = probe_video_dimensions(file_path);
+
+    let mut fitting_renditions = renditions
+        .iter()
+        .filter(|rendition| {
+            source_dimensions.is_none_or(|(_, source_height)| rendition.height <= source_height)
+        })
+        .copied()
+        .collect::<Vec<HlsRendition>>();
+
+    if fitting_renditions.is_empty() {
+        if let Some(smallest_rendition) = renditions.iter().min_by_key(|rendition| rendition.height)
+        {
+            fitting_renditions.push(*smallest_rendition);
+        }
+    }
+
+    let renditions = fitting_renditions;
+
+    let hls_path = get_hls_path(endpoint_artifacts_path, filesystem_id);
+    let partial_hls_path = hls_path.with_extension("partial");
+
+    if partial_hls_path.exists() {
+        fs::remove_dir_all(&partial_hls_path).map_err(|_| StorageError::Internal)?;
+    }
+
+    let transcode_result = renditions.iter().try_for_each(|rendition| {
+        transcode_hls_rendition(
+            ffmpeg_bin_path,
+            file_path,
+            &partial_hls_path.join(rendition.folder_name()),
+            rendition,
+        )
+    });
+
+    let result = transcode_result
+        .and_then(|_| write_master_playlist(&partial_hls_path, &renditions, source_dimensions))
+        .and_then(|_| {
+            if hls_path.exists() {
+                fs::remove_dir_all(&hls_path).map_err(|_| StorageError::Internal)?;
+            }
+
+            fs::rename(&partial_hls_path, &hls_path).map_err(|_| StorageError::Internal)
+        });
+
+    if result.is_err() {
+        fs::remove_dir_all(&partial_hls_path).unwrap_or(());
+    }
+
+    result
+}
+
+/**
+ * Generate HLS renditions of an uploaded video.
+ *
+ * Blocks, runs in a job worker.
+ */
+pub fn run_transcode_video_hls_job(
+    runtime: &actix_rt::Runtime,
+    pool: &RequestPool,
+    ws_state: &web::Data<Mutex<WSState>>,
+    payload: StorageArtifactsJobPayload,
+) -> Result<(), String> {
+    let endpoint_id = payload.endpoint_id;
+    let filesystem_id = payload.filesystem_id.as_str();
+
+    let storage_config = runtime.block_on(get_config(pool));
+    let renditions = get_hls_renditions(&storage_config);
+
+    let target_endpoint = runtime
+        .block_on(get_storage_endpoint(endpoint_id, pool))
+        .map_err(|_| format!("Endpoint {} does not exist", endpoint_id))?;
+
+    let target_endpoint_artifacts_path = match &target_endpoint.artifacts_path {
+        Some(artifacts_path) => artifacts_path.as_str(),
+        None => return Ok(()),
+    };
+
+    let parent_folders =
+        runtime.block_on(get_blob_parent_folders(endpoint_id, filesystem_id, pool))?;
+
+    // The file was deleted before we got to it
+    if parent_folders.is_empty() {
+        return Ok(());
+    }
+
+    let storage_backend = runtime
+        .block_on(get_storage_backend(&target_endpoint, pool))
+        .map_err(|_| {
+            format!(
+                "Could not get the storage backend of endpoint {}",
+                endpoint_id
+            )
+        })?;
+
+    let local_blob = runtime
+        .block_on(storage_backend.local_copy(filesystem_id))
+        .map_err(|_| {
+            format!(
+                "Could not get a local copy of an uploaded file ({})",
+                filesystem_id
+            )
+        })?;
+
+    generate_hls_renditions(
+        filesystem_id,
+        local_blob.path(),
+        target_endpoint_artifacts_path,
+        &renditions,
+    )
+    .map_err(|_| {
+        format!(
+            "Failed to create HLS renditions for an uploaded video file ({})",
+            filesystem_id
+        )
+    })?;
+
+    // Refresh folders when the renditions are ready
+    runtime.block_on(send_storage_location_updated(
+        ws_state,
+        None,
+        endpoint_id,
+        parent_folders,
+        true,
+        false,
+    ));
+
+    Ok(())
+}
+
+/**
+ * Resolve a path requested by a player (`master.m3u8`, `720p/index.m3u8`, `720p/segment_0000.ts`) inside the HLS
+ * folder of a video. Anything that could point outside of it is rejected.
+ *
+ * @returns the path on disk and the content type to serve it with
+ */
+fn resolve_hls_file(hls_path: &Path, requested_path: &str) -> Option<(PathBuf, &'static str)> {
+    let requested_path = Path::new(requested_path);
+
+    let components_valid = requested_path.components().count() <= 2
+        && requested_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+
+    if !components_valid {
+        return None;
+    }
+
+    let content_type = match requested_path.extension().and_then(|ext| ext.to_str()) {
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("ts") => "video/mp2t",
+        _ => return None,
+    };
+
+    Some((hls_path.join(requested_path), content_type))
+}
+
+/**
+ * Serve a playlist or a segment of the HLS renditions of a file. Access has to be checked beforehand.
+ *
+ * @param requested_path path relative to the video's HLS folder. Playlists reference each other and the segments with
+ * relative paths, so a player can be pointed at the master playlist
+ */
+pub async fn serve_hls_file(
+    endpoint_id: i32,
+    file_id: i64,
+    requested_path: &str,
+    req: &HttpRequest,
+    pool: &RequestPool,
+) -> HttpResponse {
+    let entry = sqlx::query_as::<_, HlsEntryRow>(
+        "SELECT storage_entries.filesystem_id, storage_endpoints.artifacts_path FROM storage_entries
+        INNER JOIN storage_endpoints ON storage_entries.endpoint_id = storage_endpoints.id
+        WHERE storage_entries.id = $1 AND storage_entries.endpoint_id = $2 AND storage_entries.entry_type = 'file'::storage_entry_type",
+    )
+    .bind(file_id)
+    .bind(endpoint_id)
+    .fetch_optional(pool)
+    .await;
+
+    let entry = match entry {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return error("storage.entry_not_found"),
+        Err(_) => return error("storage.internal"),
+    };
+
+    let artifacts_path = match &entry.artifacts_path {
+        Some(artifacts_path) => artifacts_path,
+        None => return error("storage.hls_not_available"),
+    };
+
+    let hls_path = get_hls_path(artifacts_path, &entry.filesystem_id);
+
+    let (file_path, content_type) = match resolve_hls_file(&hls_path, requested_path) {
+        Some(hls_file) => hls_file,
+        None => return error("storage.invalid_hls_path"),
+    };
+
+    // HLS renditions always reside on the local filesystem, in the endpoint's artifacts folder
+    let file = actix_files::NamedFile::open(file_path);
+
+    if file.is_err() {
+        return error("storage.hls_not_available");
+    }
+
+    let mut res = file.unwrap().into_response(req);
+
+    res.headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+
+    res.headers_mut().insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(if content_type == "video/mp2t" {
+            "private, stale-while-revalidate, max-age=432000" // 432000 = 5 days
+        } else {
+            // Playlists change when the video is transcoded again
+            "private, no-cache"
+        }),
+    );
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(requested_path: &str) -> Option<(PathBuf, &'static str)> {
+        resolve_hls_file(Path::new("/artifacts/hls/abc"), requested_path)
+    }
+
+    #[test]
+    fn resolves_playlists_and_segments() {
+        assert_eq!(
+            resolve("master.m3u8"),
+            Some((
+                PathBuf::from("/artifacts/hls/abc/master.m3u8"),
+                "application/vnd.apple.mpegurl"
+            ))
+        );
+        assert_eq!(
+            resolve("720p/index.m3u8"),
+            Some((
+                PathBuf::from("/artifacts/hls/abc/720p/index.m3u8"),
+                "application/vnd.apple.mpegurl"
+            ))
+        );
+        assert_eq!(
+            resolve("720p/segment_0000.ts"),
+            Some((
+                PathBuf::from("/artifacts/hls/abc/720p/segment_0000.ts"),
+                "video/mp2t"
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_paths_outside_of_the_hls_folder() {
+        assert_eq!(resolve("../master.m3u8"), None);
+        assert_eq!(resolve("720p/../../master.m3u8"), None);
+        assert_eq!(resolve("./master.m3u8"), None);
+        assert_eq!(resolve("/etc/master.m3u8"), None);
+    }
+
+    #[test]
+    fn rejects_deeper_paths() {
+        assert_eq!(resolve("720p/extra/segment_0000.ts"), None);
+        assert_eq!(resolve("a/b/c/index.m3u8"), None);
+    }
+
+    #[test]
+    fn rejects_other_file_types() {
+        assert_eq!(resolve("720p/segment_0000.mp4"), None);
+        assert_eq!(resolve("master"), None);
+    }
+}
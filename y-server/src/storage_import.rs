@@ -0,0 +1,1032 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use actix_web::web;
+use chrono::{DateTime, SubsecRound, Utc};
+use log::*;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::storage_artifacts::queue_uploaded_files_artifacts;
+use crate::storage_backend::{get_storage_backend, StorageBackend};
+use crate::storage_blobs::{hash_local_file, release_stored_blob, store_staged_blob, StoredBlob};
+use crate::storage_endpoint::{get_storage_endpoint, StorageEndpointRow};
+use crate::storage_entry::{delete_entries, delete_file_artifacts, delete_storage_blobs};
+use crate::storage_layout::{
+    sync_endpoint_file_structure, sync_file_structure, uses_preserved_layout, LocalBlobLayout,
+    DETACHED_BLOBS_FOLDER,
+};
+use crate::storage_versions::{replace_entry_blob, StorageEntryVersionBlob};
+use crate::util::RequestPool;
+use crate::webdav::split_entry_name;
+use crate::ws::{send_storage_location_updated, WSState};
+
+pub enum StorageImportError {
+    EndpointNotFound,
+    TargetFolderNotFound,
+
+    /// The source directory does not exist, or it overlaps with the endpoint's own folder
+    InvalidSource,
+
+    /// Files can only be adopted in place by endpoints that preserve the file structure
+    AdoptNotSupported,
+
+    Internal,
+}
+
+impl StorageImportError {
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            StorageImportError::EndpointNotFound => "storage_import.endpoint_not_found",
+            StorageImportError::TargetFolderNotFound => "storage_import.target_folder_not_found",
+            StorageImportError::InvalidSource => "storage_import.invalid_source",
+            StorageImportError::AdoptNotSupported => "storage_import.adopt_not_supported",
+            StorageImportError::Internal => "storage_import.internal",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageImportMode {
+    /// Index the files that are already stored inside of the endpoint's folder, without moving them
+    Adopt,
+
+    /// Copy the files from some other directory into new blobs
+    Copy,
+}
+
+impl StorageImportMode {
+    pub fn from_str(mode: &str) -> Option<Self> {
+        match mode {
+            "adopt" => Some(StorageImportMode::Adopt),
+            "copy" => Some(StorageImportMode::Copy),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * What to import and where. Also the payload of the import job.
+ */
+#[derive(Serialize, Deserialize)]
+pub struct StorageImportOptions {
+    pub endpoint_id: i32,
+    pub mode: StorageImportMode,
+
+    /**
+     * Directory to copy the files from. Only used (and required) by the `copy` mode, `adopt` always scans the folder
+     * that the target folder is stored in.
+     */
+    pub source_path: Option<String>,
+
+    /**
+     * Folder to import the files into. `None` means the root of the endpoint.
+     */
+    pub target_folder: Option<i64>,
+
+    /**
+     * Queue generation of thumbnails for the new and the changed files
+     */
+    pub generate_artifacts: bool,
+}
+
+#[derive(Serialize, Default)]
+pub struct StorageImportSummary {
+    pub added_folders: u64,
+    pub added_files: u64,
+    pub updated_files: u64,
+    pub removed_entries: u64,
+
+    /**
+     * Files and folders that could not be imported: unreadable, named in a way y can not store, or conflicting with
+     * an existing entry
+     */
+    pub skipped: u64,
+}
+
+/**
+ * An entry that already exists at some path of the scanned directory
+ */
+#[derive(FromRow)]
+struct KnownEntry {
+    entry_id: i64,
+    filesystem_id: Option<String>,
+    path: String,
+    imported: bool,
+    size_bytes: Option<i64>,
+    modified_at: Option<DateTime<Utc>>,
+}
+
+/**
+ * Where an import starts
+ */
+struct ImportRoot {
+    /**
+     * Directory on disk that is walked
+     */
+    directory: PathBuf,
+
+    /**
+     * Key of the root directory. Entries are keyed by their path relative to the endpoint's base path when adopting,
+     * and by their absolute source path when copying.
+     */
+    key: String,
+}
+
+fn join_key(parent_key: &str, name: &str) -> String {
+    if parent_key.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent_key, name)
+    }
+}
+
+/**
+ * Split a file name the way `storage_layout` joins it back together, so adopted files keep their exact names
+ */
+pub fn split_file_name(file_name: &str) -> (&str, Option<&str>) {
+    match split_entry_name(file_name) {
+        // "name." would lose its trailing dot
+        (_, Some("")) => (file_name, None),
+        split => split,
+    }
+}
+
+async fn get_folder_relative_path(
+    layout: &LocalBlobLayout,
+    folder_id: i64,
+    pool: &RequestPool,
+) -> Result<Option<String>, StorageImportError> {
+    // Folders that were never stored on disk (e.g. created while the folder was not reachable) are put in place first
+    sync_file_structure(layout, &vec![folder_id], pool)
+        .await
+        .map_err(|_| StorageImportError::Internal)?;
+
+    sqlx::query_scalar::<_, String>(
+        "SELECT relative_path FROM storage_entry_paths WHERE endpoint_id = $1 AND entry_id = $2 AND filesystem_id IS NULL",
+    )
+    .bind(layout.endpoint_id)
+    .bind(folder_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| StorageImportError::Internal)
+}
+
+/**
+ * Check the import options and find out which directory is going to be imported
+ */
+async fn get_import_root(
+    endpoint: &StorageEndpointRow,
+    options: &StorageImportOptions,
+    pool: &RequestPool,
+) -> Result<ImportRoot, StorageImportError> {
+    if let Some(target_folder) = options.target_folder {
+        let target_folder_exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM storage_entries WHERE endpoint_id = $1 AND id = $2 AND entry_type = 'folder'::storage_entry_type)",
+        )
+        .bind(endpoint.id)
+        .bind(target_folder)
+        .fetch_one(pool)
+        .await
+        .map_err(|_| StorageImportError::Internal)?;
+
+        if !target_folder_exists {
+            return Err(StorageImportError::TargetFolderNotFound);
+        }
+    }
+
+    match options.mode {
+        StorageImportMode::Adopt => {
+            if !uses_preserved_layout(endpoint) {
+                return Err(StorageImportError::AdoptNotSupported);
+            }
+
+            let layout = LocalBlobLayout::new(endpoint);
+
+            let key = match options.target_folder {
+                Some(target_folder) => get_folder_relative_path(&layout, target_folder, pool)
+                    .await?
+                    .ok_or(StorageImportError::TargetFolderNotFound)?,
+                None => String::new(),
+            };
+
+            let directory = if key.is_empty() {
+                layout.base_path.clone()
+            } else {
+                layout.base_path.join(&key)
+            };
+
+            if !directory.is_dir() {
+                return Err(StorageImportError::InvalidSource);
+            }
+
+            Ok(ImportRoot { directory, key })
+        }
+        StorageImportMode::Copy => {
+            let directory = options
+                .source_path
+                .as_ref()
+                .and_then(|source_path| fs::canonicalize(source_path).ok())
+                .filter(|directory| directory.is_dir())
+                .ok_or(StorageImportError::InvalidSource)?;
+
+            // Importing the endpoint's own blobs (or a directory that contains them) would never end
+            if endpoint.endpoint_type == "local_fs" {
+                if let Ok(base_path) = fs::canonicalize(&endpoint.base_path) {
+                    if directory.starts_with(&base_path) || base_path.starts_with(&directory) {
+                        return Err(StorageImportError::InvalidSource);
+                    }
+                }
+            }
+
+            let key = directory
+                .to_str()
+                .ok_or(StorageImportError::InvalidSource)?
+                .trim_end_matches('/')
+                .to_string();
+
+            Ok(ImportRoot { directory, key })
+        }
+    }
+}
+
+/**
+ * Make sure that an import can be started with the given options. Used to report invalid options before the import
+ * is queued.
+ */
+pub async fn check_import_options(
+    options: &StorageImportOptions,
+    pool: &RequestPool,
+) -> Result<(), StorageImportError> {
+    let endpoint = get_storage_endpoint(options.endpoint_id, pool)
+        .await
+        .map_err(|_| StorageImportError::EndpointNotFound)?;
+
+    get_import_root(&endpoint, options, pool).await.map(|_| ())
+}
+
+struct Importer<'a> {
+    endpoint: &'a StorageEndpointRow,
+    layout: LocalBlobLayout,
+    storage_backend: Box<dyn StorageBackend>,
+    mode: StorageImportMode,
+    pool: &'a RequestPool,
+
+    known: HashMap<String, KnownEntry>,
+    seen: HashSet<String>,
+
+    // Directories that could not be read. What's inside of them is left alone
+    unreadable: Vec<String>,
+
+    // Blobs that need thumbnails
+    new_blobs: Vec<String>,
+
+    // Entries created by the `copy` mode, they have to be put in place on endpoints that preserve the file structure
+    created_entries: Vec<i64>,
+
+    summary: StorageImportSummary,
+}
+
+impl<'a> Importer<'a> {
+    async fn load_known_entries(&mut self, root: &ImportRoot) -> Result<(), StorageImportError> {
+        let known = match self.mode {
+            StorageImportMode::Adopt => {
+                sqlx::query_as::<_, KnownEntry>(
+                    "SELECT storage_entry_paths.entry_id, storage_entry_paths.filesystem_id::TEXT, storage_entry_paths.relative_path AS path,
+                    storage_imported_entries.entry_id IS NOT NULL AS imported, storage_imported_entries.size_bytes, storage_imported_entries.modified_at
+                    FROM storage_entry_paths
+                    LEFT JOIN storage_imported_entries ON storage_imported_entries.endpoint_id = $1 AND storage_imported_entries.entry_id = storage_entry_paths.entry_id
+                    WHERE storage_entry_paths.endpoint_id = $1 AND ($2 = '' OR starts_with(storage_entry_paths.relative_path, $2 || '/'))",
+                )
+                .bind(self.endpoint.id)
+                .bind(&root.key)
+                .fetch_all(self.pool)
+                .await
+            }
+            StorageImportMode::Copy => {
+                sqlx::query_as::<_, KnownEntry>(
+                    "SELECT storage_imported_entries.entry_id, storage_entries.filesystem_id::TEXT, storage_imported_entries.source_path AS path,
+                    TRUE AS imported, storage_imported_entries.size_bytes, storage_imported_entries.modified_at
+                    FROM storage_imported_entries
+                    INNER JOIN storage_entries ON storage_entries.id = storage_imported_entries.entry_id
+                    WHERE storage_imported_entries.endpoint_id = $1 AND starts_with(storage_imported_entries.source_path, $2 || '/')",
+                )
+                .bind(self.endpoint.id)
+                .bind(&root.key)
+                .fetch_all(self.pool)
+                .await
+            }
+        };
+
+        match known {
+            Ok(known) => {
+                self.known = known
+                    .into_iter()
+                    .map(|entry| (entry.path.clone(), entry))
+                    .collect();
+
+                Ok(())
+            }
+            Err(err) => {
+                error!(
+                    "(storage import) Could not get the entries that were imported before. endpoint_id = {}. {}",
+                    self.endpoint.id, err
+                );
+
+                Err(StorageImportError::Internal)
+            }
+        }
+    }
+
+    /**
+     * Remember the state of an imported entry, so the next import can tell whether it has changed
+     */
+    async fn record_entry(
+        &self,
+        entry_id: i64,
+        key: &str,
+        size_bytes: Option<i64>,
+        modified_at: Option<DateTime<Utc>>,
+    ) -> Result<(), StorageImportError> {
+        let source_path = match self.mode {
+            StorageImportMode::Adopt => None,
+            StorageImportMode::Copy => Some(key),
+        };
+
+        sqlx::query(
+            "INSERT INTO storage_imported_entries (endpoint_id, entry_id, source_path, size_bytes, modified_at) VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (endpoint_id, entry_id) DO UPDATE SET source_path = $3, size_bytes = $4, modified_at = $5",
+        )
+        .bind(self.endpoint.id)
+        .bind(entry_id)
+        .bind(source_path)
+        .bind(size_bytes)
+        .bind(modified_at)
+        .execute(self.pool)
+        .await
+        .map(|_| ())
+        .map_err(|_| StorageImportError::Internal)
+    }
+
+    /**
+     * Adopted entries stay where they are, `storage_layout` just has to know about them
+     */
+    async fn record_entry_path(
+        &self,
+        entry_id: i64,
+        filesystem_id: Option<&str>,
+        key: &str,
+    ) -> Result<(), StorageImportError> {
+        sqlx::query(
+            "INSERT INTO storage_entry_paths (endpoint_id, entry_id, filesystem_id, relative_path) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (endpoint_id, entry_id) DO NOTHING",
+        )
+        .bind(self.endpoint.id)
+        .bind(entry_id)
+        .bind(filesystem_id)
+        .bind(key)
+        .execute(self.pool)
+        .await
+        .map(|_| ())
+        .map_err(|_| StorageImportError::Internal)
+    }
+
+    /**
+     * Copy a file into a new blob of the endpoint
+     *
+     * @returns (the blob, sha256). `release_stored_blob` must be called once the file has been created or updated
+     */
+    async fn copy_into_blob(
+        &self,
+        path: &Path,
+        size_bytes: i64,
+    ) -> Result<(StoredBlob, String), StorageImportError> {
+        let staged_filesystem_id = Uuid::new_v4().to_string();
+        let staging_path = self.storage_backend.staging_path(&staged_filesystem_id);
+
+        let copy_result = fs::copy(path, &staging_path)
+            .map_err(|_| StorageImportError::InvalidSource)
+            .and_then(|_| hash_local_file(&staging_path).map_err(|_| StorageImportError::Internal));
+
+        let sha256 = match copy_result {
+            Ok(sha256) => sha256,
+            Err(err) => {
+                fs::remove_file(&staging_path).unwrap_or(());
+
+                return Err(err);
+            }
+        };
+
+        let stored_blob = store_staged_blob(
+            self.endpoint,
+            self.storage_backend.as_ref(),
+            &staged_filesystem_id,
+            &sha256,
+            size_bytes,
+            self.pool,
+        )
+        .await
+        .map_err(|_| StorageImportError::Internal)?;
+
+        Ok((stored_blob, sha256))
+    }
+
+    /**
+     * @returns id of the folder, if its contents should be imported
+     */
+    async fn import_folder(
+        &mut self,
+        name: &str,
+        parent_folder: Option<i64>,
+        key: &str,
+        modified_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<i64>, StorageImportError> {
+        self.seen.insert(key.to_string());
+
+        if let Some(known) = self.known.get(key) {
+            if known.filesystem_id.is_some() {
+                // A file has been replaced with a folder. Leave it to the next import, once the file is removed
+                self.summary.skipped += 1;
+
+                return Ok(None);
+            }
+
+            return Ok(Some(known.entry_id));
+        }
+
+        let new_folder_id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO storage_entries (endpoint_id, parent_folder, name, entry_type, created_at) VALUES ($1, $2, $3, 'folder'::storage_entry_type, $4)
+            ON CONFLICT DO NOTHING RETURNING id",
+        )
+        .bind(self.endpoint.id)
+        .bind(parent_folder)
+        .bind(name)
+        .bind(modified_at)
+        .fetch_optional(self.pool)
+        .await
+        .map_err(|_| StorageImportError::Internal)?;
+
+        let folder_id = match new_folder_id {
+            Some(new_folder_id) => {
+                self.summary.added_folders += 1;
+                self.created_entries.push(new_folder_id);
+
+                new_folder_id
+            }
+            None => {
+                // A folder with the same name already exists, the files are imported into it
+                sqlx::query_scalar::<_, i64>(
+                    "SELECT id FROM storage_entries WHERE endpoint_id = $1 AND parent_folder IS NOT DISTINCT FROM $2 AND name = $3 AND entry_type = 'folder'::storage_entry_type",
+                )
+                .bind(self.endpoint.id)
+                .bind(parent_folder)
+                .bind(name)
+                .fetch_one(self.pool)
+                .await
+                .map_err(|_| StorageImportError::Internal)?
+            }
+        };
+
+        if self.mode == StorageImportMode::Adopt {
+            self.record_entry_path(folder_id, None, key).await?;
+        }
+
+        self.record_entry(folder_id, key, None, modified_at).await?;
+
+        Ok(Some(folder_id))
+    }
+
+    async fn update_file(
+        &mut self,
+        path: &Path,
+        entry_id: i64,
+        filesystem_id: &str,
+        size_bytes: i64,
+        modified_at: Option<DateTime<Utc>>,
+    ) -> Result<(), StorageImportError> {
+        match self.mode {
+            StorageImportMode::Adopt => {
+                // The blob itself has changed, there is nothing to copy
+                let mime_type = infer::get_from_path(path)
+                    .ok()
+                    .flatten()
+                    .map(|kind| kind.mime_type());
+
+                sqlx::query(
+                    "UPDATE storage_entries SET size_bytes = $1, mime_type = $2, sha256 = NULL, created_at = $3, transcoded_version_available = NULL
+                    WHERE endpoint_id = $4 AND id = $5",
+                )
+                .bind(size_bytes)
+                .bind(mime_type)
+                .bind(modified_at)
+                .bind(self.endpoint.id)
+                .bind(entry_id)
+                .execute(self.pool)
+                .await
+                .map_err(|_| StorageImportError::Internal)?;
+
+                if let Some(artifacts_path) = &self.endpoint.artifacts_path {
+                    delete_file_artifacts(self.endpoint.id, artifacts_path, filesystem_id);
+                }
+
+                self.new_blobs.push(filesystem_id.to_string());
+            }
+            StorageImportMode::Copy => {
+                let (stored_blob, sha256) = self.copy_into_blob(path, size_bytes).await?;
+                let new_filesystem_id = stored_blob.filesystem_id.clone();
+
+                let mime_type = infer::get_from_path(path)
+                    .ok()
+                    .flatten()
+                    .map(|kind| kind.mime_type().to_string());
+
+                // The previous contents are kept as a version, if the endpoint has versioning enabled
+                let replace_result = replace_entry_blob(
+                    self.endpoint,
+                    entry_id,
+                    &StorageEntryVersionBlob {
+                        filesystem_id: new_filesystem_id.clone(),
+                        mime_type,
+                        size_bytes: Some(size_bytes),
+                        sha256: Some(sha256),
+                    },
+                    None,
+                    self.pool,
+                )
+                .await;
+
+                release_stored_blob(&stored_blob, self.pool).await;
+
+                if replace_result.is_err() {
+                    delete_storage_blobs(self.endpoint, &vec![new_filesystem_id], self.pool)
+                        .await
+                        .unwrap_or(());
+
+                    return Err(StorageImportError::Internal);
+                }
+
+                // Reused blobs already have their thumbnails
+                if stored_blob.is_new() {
+                    self.new_blobs.push(new_filesystem_id);
+                }
+            }
+        }
+
+        self.summary.updated_files += 1;
+
+        Ok(())
+    }
+
+    async fn create_file(
+        &mut self,
+        path: &Path,
+        file_name: &str,
+        parent_folder: Option<i64>,
+        key: &str,
+        size_bytes: i64,
+        modified_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<i64>, StorageImportError> {
+        let (name, extension) = split_file_name(file_name);
+
+        let mime_type = infer::get_from_path(path)
+            .ok()
+            .flatten()
+            .map(|kind| kind.mime_type());
+
+        let (stored_blob, sha256) = match self.mode {
+            // The file already is where it belongs, it just needs an id
+            StorageImportMode::Adopt => (StoredBlob::new(Uuid::new_v4().to_string()), None),
+            StorageImportMode::Copy => {
+                let (stored_blob, sha256) = self.copy_into_blob(path, size_bytes).await?;
+
+                (stored_blob, Some(sha256))
+            }
+        };
+
+        let filesystem_id = stored_blob.filesystem_id.clone();
+
+        let new_file_id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO storage_entries (endpoint_id, filesystem_id, parent_folder, name, extension, mime_type, size_bytes, sha256, created_at, entry_type)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, 'file'::storage_entry_type)
+            ON CONFLICT DO NOTHING RETURNING id",
+        )
+        .bind(self.endpoint.id)
+        .bind(&filesystem_id)
+        .bind(parent_folder)
+        .bind(name)
+        .bind(extension)
+        .bind(mime_type)
+        .bind(size_bytes)
+        .bind(&sha256)
+        .bind(modified_at)
+        .fetch_optional(self.pool)
+        .await;
+
+        release_stored_blob(&stored_blob, self.pool).await;
+
+        let new_file_id = match new_file_id {
+            Ok(Some(new_file_id)) => new_file_id,
+            result => {
+                // Most likely a file with the same name already exists in the folder
+                if self.mode == StorageImportMode::Copy {
+                    delete_storage_blobs(self.endpoint, &vec![filesystem_id], self.pool)
+                        .await
+                        .unwrap_or(());
+                }
+
+                return match result {
+                    Ok(_) => Ok(None),
+                    Err(_) => Err(StorageImportError::Internal),
+                };
+            }
+        };
+
+        match self.mode {
+            StorageImportMode::Adopt => {
+                self.record_entry_path(new_file_id, Some(&filesystem_id), key)
+                    .await?;
+            }
+            StorageImportMode::Copy => self.created_entries.push(new_file_id),
+        }
+
+        // Reused blobs already have their thumbnails
+        if stored_blob.is_new() {
+            self.new_blobs.push(filesystem_id);
+        }
+
+        self.summary.added_files += 1;
+
+        Ok(Some(new_file_id))
+    }
+
+    async fn import_file(
+        &mut self,
+        path: &Path,
+        file_name: &str,
+        parent_folder: Option<i64>,
+        key: &str,
+        size_bytes: i64,
+        modified_at: Option<DateTime<Utc>>,
+    ) -> Result<(), StorageImportError> {
+        self.seen.insert(key.to_string());
+
+        let known = self.known.get(key).map(|known| {
+            (
+                known.entry_id,
+                known.filesystem_id.clone(),
+                known.imported,
+                known.size_bytes,
+                known.modified_at,
+            )
+        });
+
+        let entry_id = match known {
+            Some((entry_id, filesystem_id, imported, known_size_bytes, known_modified_at)) => {
+                let filesystem_id = match filesystem_id {
+                    Some(filesystem_id) => filesystem_id,
+                    None => {
+                        // A folder has been replaced with a file
+                        self.summary.skipped += 1;
+
+                        return Ok(());
+                    }
+                };
+
+                let unchanged =
+                    known_size_bytes == Some(size_bytes) && known_modified_at == modified_at;
+
+                if unchanged {
+                    return Ok(());
+                }
+
+                // Files that were stored through y are only remembered the first time they are seen
+                if imported {
+                    self.update_file(path, entry_id, &filesystem_id, size_bytes, modified_at)
+                        .await?;
+                }
+
+                entry_id
+            }
+            None => {
+                match self
+                    .create_file(path, file_name, parent_folder, key, size_bytes, modified_at)
+                    .await?
+                {
+                    Some(new_file_id) => new_file_id,
+                    None => {
+                        self.summary.skipped += 1;
+
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        self.record_entry(entry_id, key, Some(size_bytes), modified_at)
+            .await
+    }
+
+    async fn walk(&mut self, root: &ImportRoot, target_folder: Option<i64>) {
+        let mut folders: Vec<(PathBuf, String, Option<i64>)> =
+            vec![(root.directory.clone(), root.key.clone(), target_folder)];
+
+        while let Some((directory, key, folder_id)) = folders.pop() {
+            let children = match fs::read_dir(&directory) {
+                Ok(children) => children,
+                Err(err) => {
+                    warn!(
+                        "(storage import) Could not read a directory. path = {}. {}",
+                        directory.display(),
+                        err
+                    );
+
+                    self.unreadable.push(key);
+                    self.summary.skipped += 1;
+
+                    continue;
+                }
+            };
+
+            let mut children = children
+                .filter_map(|child| child.ok())
+                .collect::<Vec<fs::DirEntry>>();
+
+            children.sort_by_key(|child| child.file_name());
+
+            for child in children {
+                let name = match child.file_name().into_string() {
+                    Ok(name) if name.len() <= 255 && !name.contains('\0') => name,
+                    _ => {
+                        self.summary.skipped += 1;
+
+                        continue;
+                    }
+                };
+
+                // y's own blobs
+                if self.mode == StorageImportMode::Adopt
+                    && key.is_empty()
+                    && name == DETACHED_BLOBS_FOLDER
+                {
+                    continue;
+                }
+
+                let child_key = join_key(&key, &name);
+
+                // Symlinks are not followed, they could lead outside of the directory or into a loop
+                let metadata = match child.metadata() {
+                    Ok(metadata) if !metadata.file_type().is_symlink() => metadata,
+                    _ => {
+                        self.seen.insert(child_key);
+                        self.summary.skipped += 1;
+
+                        continue;
+                    }
+                };
+
+                // The database stores timestamps with microsecond precision
+                let modified_at = metadata
+                    .modified()
+                    .ok()
+                    .map(|modified_at| DateTime::<Utc>::from(modified_at).trunc_subsecs(6));
+
+                let result = if metadata.is_dir() {
+                    match self
+                        .import_folder(&name, folder_id, &child_key, modified_at)
+                        .await
+                    {
+                        Ok(Some(child_folder_id)) => {
+                            folders.push((child.path(), child_key.clone(), Some(child_folder_id)));
+
+                            Ok(())
+                        }
+                        Ok(None) => {
+                            self.unreadable.push(child_key.clone());
+
+                            Ok(())
+                        }
+                        Err(err) => Err(err),
+                    }
+                } else if metadata.is_file() {
+                    self.import_file(
+                        &child.path(),
+                        &name,
+                        folder_id,
+                        &child_key,
+                        metadata.len() as i64,
+                        modified_at,
+                    )
+                    .await
+                } else {
+                    self.summary.skipped += 1;
+
+                    Ok(())
+                };
+
+                if result.is_err() {
+                    warn!(
+                        "(storage import) Could not import {}. endpoint_id = {}",
+                        child.path().display(),
+                        self.endpoint.id
+                    );
+
+                    self.unreadable.push(child_key.clone());
+                    self.seen.insert(child_key);
+                    self.summary.skipped += 1;
+                }
+            }
+        }
+    }
+
+    /**
+     * Remove the entries whose files are gone from the scanned directory
+     */
+    async fn remove_missing_entries(&mut self) -> Result<(), StorageImportError> {
+        let missing = self
+            .known
+            .values()
+            .filter(|known| !self.seen.contains(&known.path))
+            .filter(|known| {
+                !self.unreadable.iter().any(|unreadable| {
+                    known.path.starts_with(&format!("{}/", unreadable)) || known.path == *unreadable
+                })
+            })
+            .collect::<Vec<&KnownEntry>>();
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let missing_count = missing.len() as u64;
+
+        let (missing_files, missing_folders): (Vec<&KnownEntry>, Vec<&KnownEntry>) = missing
+            .into_iter()
+            .partition(|known| known.filesystem_id.is_some());
+
+        let missing_file_ids = missing_files
+            .iter()
+            .map(|known| known.entry_id)
+            .collect::<Vec<i64>>();
+
+        let missing_folder_ids = missing_folders
+            .iter()
+            .map(|known| known.entry_id)
+            .collect::<Vec<i64>>();
+
+        match self.mode {
+            StorageImportMode::Adopt => {
+                // The files themselves are already gone, so there is nothing to put in the trash
+                let all_ids = missing_file_ids
+                    .iter()
+                    .chain(missing_folder_ids.iter())
+                    .copied()
+                    .collect::<Vec<i64>>();
+
+                let version_filesystem_ids = sqlx::query_scalar::<_, String>(
+                    "WITH RECURSIVE subtree AS (
+                        SELECT id FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)
+                        UNION
+                        SELECT storage_entries.id FROM storage_entries
+                        INNER JOIN subtree ON storage_entries.parent_folder = subtree.id
+                        WHERE storage_entries.endpoint_id = $1
+                    ), deleted_versions AS (
+                        DELETE FROM storage_entry_versions WHERE endpoint_id = $1 AND entry_id IN (SELECT id FROM subtree) RETURNING filesystem_id
+                    ), deleted_entries AS (
+                        DELETE FROM storage_entries WHERE endpoint_id = $1 AND id IN (SELECT id FROM subtree) RETURNING id
+                    ) SELECT filesystem_id::TEXT FROM deleted_versions",
+                )
+                .bind(self.endpoint.id)
+                .bind(&all_ids)
+                .fetch_all(self.pool)
+                .await
+                .map_err(|_| StorageImportError::Internal)?;
+
+                delete_storage_blobs(self.endpoint, &version_filesystem_ids, self.pool)
+                    .await
+                    .unwrap_or(());
+
+                if let Some(artifacts_path) = &self.endpoint.artifacts_path {
+                    for known in &missing_files {
+                        if let Some(filesystem_id) = &known.filesystem_id {
+                            delete_file_artifacts(self.endpoint.id, artifacts_path, filesystem_id);
+                        }
+                    }
+                }
+
+                // Forget where the removed entries used to be
+                sync_file_structure(&self.layout, &Vec::new(), self.pool)
+                    .await
+                    .map_err(|_| StorageImportError::Internal)?;
+            }
+            StorageImportMode::Copy => {
+                // The copies are still intact, they can be restored from the trash
+                delete_entries(
+                    self.endpoint.id,
+                    missing_folder_ids,
+                    missing_file_ids,
+                    None,
+                    self.pool,
+                )
+                .await
+                .map_err(|_| StorageImportError::Internal)?;
+            }
+        }
+
+        self.summary.removed_entries += missing_count;
+
+        Ok(())
+    }
+}
+
+/**
+ * Import a directory tree into an endpoint: create folders and files for everything inside of it.
+ *
+ * Importing the same directory again is incremental. New files are added, files that have changed (by size or
+ * modification time) are updated and entries whose files are gone are removed. Imported files do not belong to
+ * anyone, so they do not count towards user quotas.
+ *
+ * Blocks for as long as it takes to walk the directory, large trees should be imported in a job (see `run_import_job`).
+ */
+pub async fn import_directory(
+    options: &StorageImportOptions,
+    pool: &RequestPool,
+) -> Result<StorageImportSummary, StorageImportError> {
+    let endpoint = get_storage_endpoint(options.endpoint_id, pool)
+        .await
+        .map_err(|_| StorageImportError::EndpointNotFound)?;
+
+    let root = get_import_root(&endpoint, options, pool).await?;
+
+    let storage_backend = get_storage_backend(&endpoint, pool)
+        .await
+        .map_err(|_| StorageImportError::Internal)?;
+
+    let mut importer = Importer {
+        endpoint: &endpoint,
+        layout: LocalBlobLayout::new(&endpoint),
+        storage_backend,
+        mode: options.mode,
+        pool,
+
+        known: HashMap::new(),
+        seen: HashSet::new(),
+        unreadable: Vec::new(),
+        new_blobs: Vec::new(),
+        created_entries: Vec::new(),
+
+        summary: StorageImportSummary::default(),
+    };
+
+    importer.load_known_entries(&root).await?;
+    importer.walk(&root, options.target_folder).await;
+    importer.remove_missing_entries().await?;
+
+    if options.mode == StorageImportMode::Copy {
+        sync_endpoint_file_structure(endpoint.id, &importer.created_entries, pool).await;
+    }
+
+    if options.generate_artifacts {
+        queue_uploaded_files_artifacts(&endpoint, importer.new_blobs, None, pool).await;
+    }
+
+    Ok(importer.summary)
+}
+
+/**
+ * Import a directory tree. Blocks, runs in a job worker.
+ */
+pub fn run_import_job(
+    runtime: &actix_rt::Runtime,
+    pool: &RequestPool,
+    ws_state: &web::Data<Mutex<WSState>>,
+    payload: StorageImportOptions,
+) -> Result<(), String> {
+    let summary = runtime
+        .block_on(import_directory(&payload, pool))
+        .map_err(|err| format!("Import failed: {}", err.get_code()))?;
+
+    info!(
+        "(storage import) Imported into endpoint {}: {} folders and {} files added, {} files updated, {} entries removed, {} skipped",
+        payload.endpoint_id,
+        summary.added_folders,
+        summary.added_files,
+        summary.updated_files,
+        summary.removed_entries,
+        summary.skipped
+    );
+
+    runtime.block_on(send_storage_location_updated(
+        ws_state,
+        None,
+        payload.endpoint_id,
+        vec![payload.target_folder],
+        true,
+        false,
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,535 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::*;
+use sqlx::{FromRow, Postgres, Transaction};
+
+use crate::storage_endpoint::{get_storage_endpoint, StorageEndpointRow};
+use crate::storage_entry::StorageError;
+use crate::util::RequestPool;
+
+/**
+ * Folder inside of the endpoint's `base_path` where y keeps the blobs that are not used by any file (previous versions,
+ * trashed files) and the blobs that have just been written. Only used by endpoints that preserve the file structure.
+ */
+pub const DETACHED_BLOBS_FOLDER: &str = ".y";
+
+// Used to serialize layout changes of an endpoint (`pg_advisory_xact_lock(key, endpoint_id)`)
+const LAYOUT_LOCK_KEY: i32 = 0x7973_6c74;
+
+// Guards against cycles in the tree
+const MAX_TREE_DEPTH: i32 = 1000;
+
+/**
+ * Where the blobs of a local endpoint are on disk.
+ *
+ * Blobs of regular endpoints are stored flat, named by their `filesystem_id`. Endpoints with `preserve_file_structure`
+ * mirror the logical tree instead: every file is stored under its real path, and `storage_entry_paths` keeps track of
+ * where each entry currently is.
+ */
+#[derive(Clone)]
+pub struct LocalBlobLayout {
+    pub endpoint_id: i32,
+    pub base_path: PathBuf,
+    pub preserve_file_structure: bool,
+}
+
+impl LocalBlobLayout {
+    pub fn new(endpoint: &StorageEndpointRow) -> Self {
+        LocalBlobLayout {
+            endpoint_id: endpoint.id,
+            base_path: PathBuf::from(&endpoint.base_path),
+            preserve_file_structure: uses_preserved_layout(endpoint),
+        }
+    }
+
+    /**
+     * Where a blob is kept while no file is placed on top of it. New blobs are written here.
+     */
+    pub fn detached_path(&self, filesystem_id: &str) -> PathBuf {
+        if self.preserve_file_structure {
+            self.base_path
+                .join(DETACHED_BLOBS_FOLDER)
+                .join("blobs")
+                .join(filesystem_id)
+        } else {
+            self.base_path.join(filesystem_id)
+        }
+    }
+
+    /**
+     * Where a new blob should be written. Makes sure that the folder exists.
+     */
+    pub fn new_blob_path(&self, filesystem_id: &str) -> PathBuf {
+        let path = self.detached_path(filesystem_id);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap_or(());
+        }
+
+        path
+    }
+
+    /**
+     * Location of a blob that is not stored under the path of any file
+     */
+    pub fn unplaced_blob_path(&self, filesystem_id: &str) -> PathBuf {
+        let detached_path = self.detached_path(filesystem_id);
+
+        // Blobs written before the endpoint started preserving the file structure
+        let flat_path = self.base_path.join(filesystem_id);
+
+        if !detached_path.exists() && flat_path.exists() {
+            flat_path
+        } else {
+            detached_path
+        }
+    }
+
+    /**
+     * Current location of a blob on disk
+     */
+    pub async fn blob_path(&self, filesystem_id: &str, pool: &RequestPool) -> PathBuf {
+        if !self.preserve_file_structure {
+            return self.base_path.join(filesystem_id);
+        }
+
+        let relative_path = sqlx::query_scalar::<_, String>(
+            "SELECT relative_path FROM storage_entry_paths WHERE endpoint_id = $1 AND filesystem_id = $2::BPCHAR LIMIT 1",
+        )
+        .bind(self.endpoint_id)
+        .bind(filesystem_id)
+        .fetch_optional(pool)
+        .await;
+
+        match relative_path {
+            Ok(Some(relative_path)) => self.base_path.join(relative_path),
+            Ok(None) => self.unplaced_blob_path(filesystem_id),
+            Err(err) => {
+                error!(
+                    "(storage layout) Could not look up the path of a blob. endpoint_id = {}. {}",
+                    self.endpoint_id, err
+                );
+
+                self.detached_path(filesystem_id)
+            }
+        }
+    }
+}
+
+/**
+ * Only local endpoints can mirror the tree, S3 keys are always flat
+ */
+pub fn uses_preserved_layout(endpoint: &StorageEndpointRow) -> bool {
+    endpoint.preserve_file_structure && endpoint.endpoint_type == "local_fs"
+}
+
+/**
+ * A single path component can not contain separators or point outside of its parent
+ */
+fn is_valid_path_component(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/') && !name.contains('\0')
+}
+
+fn is_valid_relative_path(relative_path: &str) -> bool {
+    let mut components = relative_path.split('/');
+
+    // The first component must not collide with y's own folder
+    components
+        .next()
+        .is_some_and(|first| is_valid_path_component(first) && first != DETACHED_BLOBS_FOLDER)
+        && components.all(is_valid_path_component)
+}
+
+#[derive(FromRow)]
+struct OrphanedEntryPath {
+    entry_id: i64,
+    filesystem_id: Option<String>,
+    relative_path: String,
+}
+
+#[derive(FromRow)]
+struct TreeEntryPath {
+    start_id: i64,
+    id: i64,
+    entry_type: String,
+    filesystem_id: Option<String>,
+    path: String,
+    current_path: Option<String>,
+}
+
+/**
+ * Move a file or a folder on disk, creating the missing parent folders of the target
+ */
+fn move_on_disk(from: &Path, to: &Path) -> bool {
+    if let Some(parent) = to.parent() {
+        if fs::create_dir_all(parent).is_err() {
+            return false;
+        }
+    }
+
+    fs::rename(from, to).is_ok()
+}
+
+/**
+ * Remove the folders that became empty, from the deepest one up. Stops at the endpoint's `base_path` and at folders
+ * that still belong to the tree.
+ */
+fn remove_empty_folders(layout: &LocalBlobLayout, vacated: Vec<String>, keep: &HashSet<String>) {
+    let mut candidates: Vec<String> = Vec::new();
+
+    for relative_path in vacated {
+        let mut current = relative_path.as_str();
+
+        while let Some(separator) = current.rfind('/') {
+            current = &current[..separator];
+            candidates.push(current.to_string());
+        }
+    }
+
+    candidates.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+    candidates.dedup();
+
+    for relative_path in candidates {
+        if keep.contains(&relative_path) {
+            continue;
+        }
+
+        // Fails if the folder is not empty, which is fine
+        let _ = fs::remove_dir(layout.base_path.join(&relative_path));
+    }
+}
+
+async fn get_parent_paths(
+    endpoint_id: i32,
+    entry_ids: &Vec<i64>,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<HashMap<i64, String>, sqlx::Error> {
+    sqlx::query_as::<_, (i64, String)>(
+        "WITH RECURSIVE ancestors(start_id, id, parent_folder, name, depth) AS (
+            SELECT storage_entries.id, parent.id, parent.parent_folder, parent.name, 1 FROM storage_entries
+            INNER JOIN storage_entries AS parent ON parent.id = storage_entries.parent_folder
+            WHERE storage_entries.endpoint_id = $1 AND storage_entries.id = ANY($2)
+            UNION ALL
+            SELECT ancestors.start_id, parent.id, parent.parent_folder, parent.name, ancestors.depth + 1 FROM ancestors
+            INNER JOIN storage_entries AS parent ON parent.id = ancestors.parent_folder
+            WHERE ancestors.depth < $3
+        ) SELECT start_id, string_agg(name, '/' ORDER BY depth DESC) FROM ancestors GROUP BY start_id",
+    )
+    .bind(endpoint_id)
+    .bind(entry_ids)
+    .bind(MAX_TREE_DEPTH)
+    .fetch_all(&mut **transaction)
+    .await
+    .map(|rows| rows.into_iter().collect())
+}
+
+async fn sync_file_structure_locked(
+    layout: &LocalBlobLayout,
+    entry_ids: &Vec<i64>,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    let endpoint_id = layout.endpoint_id;
+
+    // Paths that are no longer occupied. Their parent folders might have to be removed
+    let mut vacated: Vec<String> = Vec::new();
+
+    // 1. Entries that were removed, and files that now point to a different blob. Their blobs are moved out of the way
+    let orphaned = sqlx::query_as::<_, OrphanedEntryPath>(
+        "SELECT storage_entry_paths.entry_id, storage_entry_paths.filesystem_id::TEXT, storage_entry_paths.relative_path
+        FROM storage_entry_paths
+        LEFT JOIN storage_entries ON storage_entries.id = storage_entry_paths.entry_id AND storage_entries.endpoint_id = $1
+        WHERE storage_entry_paths.endpoint_id = $1
+        AND (storage_entries.id IS NULL OR storage_entries.filesystem_id IS DISTINCT FROM storage_entry_paths.filesystem_id)",
+    )
+    .bind(endpoint_id)
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    for orphan in &orphaned {
+        if let Some(filesystem_id) = &orphan.filesystem_id {
+            let current_path = layout.base_path.join(&orphan.relative_path);
+
+            // The blob might have been deleted already
+            if current_path.is_file()
+                && !move_on_disk(&current_path, &layout.detached_path(filesystem_id))
+            {
+                warn!(
+                    "(storage layout) Could not move a blob out of the tree. endpoint_id = {}. path = {}",
+                    endpoint_id, orphan.relative_path
+                );
+            }
+        }
+
+        if orphan.filesystem_id.is_some() {
+            vacated.push(orphan.relative_path.clone());
+        } else {
+            vacated.push(format!("{}/", orphan.relative_path));
+        }
+    }
+
+    let orphaned_entry_ids = orphaned
+        .iter()
+        .map(|orphan| orphan.entry_id)
+        .collect::<Vec<i64>>();
+
+    if !orphaned_entry_ids.is_empty() {
+        sqlx::query(
+            "DELETE FROM storage_entry_paths WHERE endpoint_id = $1 AND entry_id = ANY($2)",
+        )
+        .bind(endpoint_id)
+        .bind(&orphaned_entry_ids)
+        .execute(&mut **transaction)
+        .await?;
+    }
+
+    // 2. Put the given entries (and everything inside of them) where they belong
+    let mut placed_folders: HashSet<String> = HashSet::new();
+
+    if !entry_ids.is_empty() {
+        let parent_paths = get_parent_paths(endpoint_id, entry_ids, transaction).await?;
+
+        let mut tree = sqlx::query_as::<_, TreeEntryPath>(
+            "WITH RECURSIVE tree(start_id, id, entry_type, filesystem_id, path, depth) AS (
+                SELECT id, id, entry_type::TEXT, filesystem_id::TEXT,
+                name || COALESCE('.' || NULLIF(extension, ''), '')::TEXT, 0
+                FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)
+                UNION ALL
+                SELECT tree.start_id, storage_entries.id, storage_entries.entry_type::TEXT, storage_entries.filesystem_id::TEXT,
+                tree.path || '/' || storage_entries.name || COALESCE('.' || NULLIF(storage_entries.extension, ''), ''), tree.depth + 1
+                FROM storage_entries INNER JOIN tree ON storage_entries.parent_folder = tree.id
+                WHERE storage_entries.endpoint_id = $1 AND tree.depth < $3
+            ) SELECT tree.start_id, tree.id, tree.entry_type, tree.filesystem_id, tree.path, storage_entry_paths.relative_path AS current_path
+            FROM tree LEFT JOIN storage_entry_paths ON storage_entry_paths.endpoint_id = $1 AND storage_entry_paths.entry_id = tree.id",
+        )
+        .bind(endpoint_id)
+        .bind(entry_ids)
+        .bind(MAX_TREE_DEPTH)
+        .fetch_all(&mut **transaction)
+        .await?
+        .into_iter()
+        .map(|mut entry| {
+            if let Some(parent_path) = parent_paths.get(&entry.start_id) {
+                entry.path = format!("{}/{}", parent_path, entry.path);
+            }
+
+            entry
+        })
+        .collect::<Vec<TreeEntryPath>>();
+
+        // Parents first, so that moving a folder moves everything inside of it at once
+        tree.sort_by_key(|entry| entry.path.matches('/').count());
+
+        let mut seen: HashSet<i64> = HashSet::new();
+        let mut moved_folders: Vec<(String, String)> = Vec::new();
+
+        for entry in tree {
+            if !seen.insert(entry.id) {
+                continue;
+            }
+
+            // Anything inside of a folder that was moved has been moved along with it
+            let current_path = entry.current_path.map(|current_path| {
+                moved_folders
+                    .iter()
+                    .rev()
+                    .find(|(from, _)| current_path.starts_with(&format!("{}/", from)))
+                    .map(|(from, to)| format!("{}{}", to, &current_path[from.len()..]))
+                    .unwrap_or(current_path)
+            });
+
+            if current_path.as_ref() == Some(&entry.path) {
+                if entry.entry_type == "folder" {
+                    placed_folders.insert(entry.path);
+                }
+
+                continue;
+            }
+
+            if !is_valid_relative_path(&entry.path) {
+                warn!(
+                    "(storage layout) Entry {} can not be stored under its path ({})",
+                    entry.id, entry.path
+                );
+
+                continue;
+            }
+
+            let target_path = layout.base_path.join(&entry.path);
+
+            if entry.entry_type == "folder" {
+                let moved = match &current_path {
+                    Some(current_path) => {
+                        let source_path = layout.base_path.join(current_path);
+
+                        // An empty leftover folder can be replaced
+                        if target_path.is_dir() {
+                            let _ = fs::remove_dir(&target_path);
+                        }
+
+                        source_path.is_dir()
+                            && !target_path.exists()
+                            && move_on_disk(&source_path, &target_path)
+                    }
+                    None => false,
+                };
+
+                if moved {
+                    let current_path = current_path.unwrap();
+
+                    sqlx::query(
+                        "UPDATE storage_entry_paths SET relative_path = $3 || substr(relative_path, length($2) + 1)
+                        WHERE endpoint_id = $1 AND starts_with(relative_path, $2 || '/')",
+                    )
+                    .bind(endpoint_id)
+                    .bind(&current_path)
+                    .bind(&entry.path)
+                    .execute(&mut **transaction)
+                    .await?;
+
+                    vacated.push(format!("{}/", current_path));
+                    moved_folders.push((current_path, entry.path.clone()));
+                } else {
+                    if fs::create_dir_all(&target_path).is_err() {
+                        warn!(
+                            "(storage layout) Could not create a folder. endpoint_id = {}. path = {}",
+                            endpoint_id, entry.path
+                        );
+
+                        continue;
+                    }
+
+                    // Whatever was inside of the old folder will be moved one by one
+                    if let Some(current_path) = current_path {
+                        vacated.push(format!("{}/", current_path));
+                    }
+                }
+
+                placed_folders.insert(entry.path.clone());
+            } else {
+                let filesystem_id = match &entry.filesystem_id {
+                    Some(filesystem_id) => filesystem_id,
+                    None => continue,
+                };
+
+                let source_path = match &current_path {
+                    Some(current_path) => layout.base_path.join(current_path),
+                    None => layout.unplaced_blob_path(filesystem_id),
+                };
+
+                if target_path.exists() {
+                    warn!(
+                        "(storage layout) Something else is already stored under the path of entry {} ({})",
+                        entry.id, entry.path
+                    );
+
+                    continue;
+                }
+
+                if !move_on_disk(&source_path, &target_path) {
+                    warn!(
+                        "(storage layout) Could not move a blob into the tree. endpoint_id = {}. path = {}",
+                        endpoint_id, entry.path
+                    );
+
+                    continue;
+                }
+
+                if let Some(current_path) = current_path {
+                    vacated.push(current_path);
+                }
+            }
+
+            sqlx::query(
+                "INSERT INTO storage_entry_paths (endpoint_id, entry_id, filesystem_id, relative_path) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (endpoint_id, entry_id) DO UPDATE SET filesystem_id = $3, relative_path = $4",
+            )
+            .bind(endpoint_id)
+            .bind(entry.id)
+            .bind(&entry.filesystem_id)
+            .bind(&entry.path)
+            .execute(&mut **transaction)
+            .await?;
+        }
+    }
+
+    // 3. Clean up the folders that nothing is stored in anymore
+    if !vacated.is_empty() {
+        let mut keep = sqlx::query_scalar::<_, String>(
+            "SELECT relative_path FROM storage_entry_paths WHERE endpoint_id = $1 AND filesystem_id IS NULL",
+        )
+        .bind(endpoint_id)
+        .fetch_all(&mut **transaction)
+        .await?
+        .into_iter()
+        .collect::<HashSet<String>>();
+
+        keep.extend(placed_folders);
+
+        remove_empty_folders(layout, vacated, &keep);
+    }
+
+    Ok(())
+}
+
+/**
+ * Bring the files on disk in line with the logical tree of an endpoint that preserves its file structure.
+ *
+ * Must be called after entries are created, renamed, moved or removed, or after a file gets a new blob.
+ * Removed entries are picked up automatically, `entry_ids` are the entries that might need to be put in a new place
+ * (including everything inside of them). Does nothing for endpoints that store their blobs flat.
+ */
+pub async fn sync_file_structure(
+    layout: &LocalBlobLayout,
+    entry_ids: &Vec<i64>,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    if !layout.preserve_file_structure {
+        return Ok(());
+    }
+
+    let mut transaction = pool.begin().await.map_err(|_| StorageError::Internal)?;
+
+    // Only one request at a time can move things around
+    sqlx::query("SELECT pg_advisory_xact_lock($1, $2)")
+        .bind(LAYOUT_LOCK_KEY)
+        .bind(layout.endpoint_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(|_| StorageError::Internal)?;
+
+    let sync_result = sync_file_structure_locked(layout, entry_ids, &mut transaction).await;
+
+    if let Err(err) = sync_result {
+        error!(
+            "(storage layout) Could not sync the file structure of endpoint {}. {}",
+            layout.endpoint_id, err
+        );
+
+        return Err(StorageError::Internal);
+    }
+
+    transaction
+        .commit()
+        .await
+        .map_err(|_| StorageError::Internal)
+}
+
+/**
+ * `sync_file_structure` for the code that changes entries. The entries have already been changed at this point,
+ * so a failure is only logged.
+ */
+pub async fn sync_endpoint_file_structure(
+    endpoint_id: i32,
+    entry_ids: &Vec<i64>,
+    pool: &RequestPool,
+) {
+    let endpoint = get_storage_endpoint(endpoint_id, pool).await;
+
+    if let Ok(endpoint) = endpoint {
+        if uses_preserved_layout(&endpoint) {
+            let _ = sync_file_structure(&LocalBlobLayout::new(&endpoint), entry_ids, pool).await;
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::process::Command;
+
+use chrono::{DateTime, NaiveDate};
+use exif::{In, Tag, Value};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use sqlx::FromRow;
+
+use crate::storage_entry::StorageError;
+use crate::util::RequestPool;
+
+/// Format of `taken_at`, the same as Postgres uses for `TIMESTAMP::TEXT`
+const TAKEN_AT_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/**
+ * Properties of an image, video or audio file, extracted from its contents. Stored per blob, so files that share a
+ * blob share their metadata.
+ */
+#[derive(Serialize, FromRow, Default, Debug)]
+pub struct StorageMediaMetadata {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+
+    pub duration_seconds: Option<f64>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub frame_rate: Option<f64>,
+
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+
+    /// As recorded by the device. EXIF timestamps are in the camera's local time, videos usually record UTC.
+    pub taken_at: Option<String>,
+
+    /// EXIF orientation, 1-8
+    pub orientation: Option<i16>,
+    pub gps_latitude: Option<f64>,
+    pub gps_longitude: Option<f64>,
+
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub title: Option<String>,
+    pub track_number: Option<i32>,
+}
+
+impl StorageMediaMetadata {
+    fn is_empty(&self) -> bool {
+        self.width.is_none()
+            && self.height.is_none()
+            && self.duration_seconds.is_none()
+            && self.video_codec.is_none()
+            && self.audio_codec.is_none()
+            && self.camera_make.is_none()
+            && self.camera_model.is_none()
+            && self.taken_at.is_none()
+            && self.artist.is_none()
+            && self.album.is_none()
+            && self.title.is_none()
+    }
+}
+
+/**
+ * `SELECT` list of `StorageMediaMetadata` columns of the `storage_media_metadata` table
+ */
+pub const MEDIA_METADATA_COLUMNS: &str = "storage_media_metadata.width, storage_media_metadata.height,
+    storage_media_metadata.duration_seconds, storage_media_metadata.video_codec, storage_media_metadata.audio_codec,
+    storage_media_metadata.frame_rate, storage_media_metadata.camera_make, storage_media_metadata.camera_model,
+    storage_media_metadata.taken_at::TEXT, storage_media_metadata.orientation, storage_media_metadata.gps_latitude,
+    storage_media_metadata.gps_longitude, storage_media_metadata.artist, storage_media_metadata.album,
+    storage_media_metadata.title, storage_media_metadata.track_number";
+
+/**
+ * Run ffprobe on a file and get its streams and container format as JSON. `None` if ffprobe is not available or
+ * could not read the file.
+ */
+fn probe_media_file(file_path: &Path) -> Option<JsonValue> {
+    let ffprobe_bin_path = env::var("FFPROBE_BIN").ok()?;
+    let ffprobe_bin_path = Path::new(&ffprobe_bin_path);
+
+    if !ffprobe_bin_path.exists() {
+        return None;
+    }
+
+    let ffprobe_result = Command::new(ffprobe_bin_path)
+        .arg("-v")
+        .arg("error")
+        .arg("-show_format")
+        .arg("-show_streams")
+        .arg("-of")
+        .arg("json")
+        .arg(file_path.to_str().unwrap())
+        .output()
+        .ok()?;
+
+    if !ffprobe_result.status.success() {
+        return None;
+    }
+
+    serde_json::from_slice(&ffprobe_result.stdout).ok()
+}
+
+/**
+ * Get a tag of a stream or the container. Tag names differ in case between formats (`artist` in mp3, `ARTIST` in flac).
+ */
+fn get_probe_tag(probed: &JsonValue, name: &str) -> Option<String> {
+    probed["tags"]
+        .as_object()?
+        .iter()
+        .find(|(tag, _)| tag.eq_ignore_ascii_case(name))
+        .and_then(|(_, value)| value.as_str())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/**
+ * Numbers are strings in ffprobe's output
+ */
+fn get_probe_number(probed: &JsonValue, name: &str) -> Option<f64> {
+    match &probed[name] {
+        JsonValue::String(value) => value.parse::<f64>().ok(),
+        value => value.as_f64(),
+    }
+    .filter(|value| value.is_finite())
+}
+
+/**
+ * Frame rates are fractions, like `30000/1001`
+ */
+fn parse_frame_rate(value: &str) -> Option<f64> {
+    let (numerator, denominator) = value.split_once('/').unwrap_or((value, "1"));
+    let numerator = numerator.parse::<f64>().ok()?;
+    let denominator = denominator.parse::<f64>().ok()?;
+
+    if numerator <= 0.0 || denominator <= 0.0 {
+        return None;
+    }
+
+    Some(numerator / denominator)
+}
+
+fn apply_probed_metadata(probed: &JsonValue, metadata: &mut StorageMediaMetadata) {
+    let streams = probed["streams"].as_array().cloned().unwrap_or_default();
+
+    // Cover art of audio files is a video stream too
+    let video_stream = streams.iter().find(|stream| {
+        stream["codec_type"] == "video" && stream["disposition"]["attached_pic"] != 1
+    });
+
+    let audio_stream = streams
+        .iter()
+        .find(|stream| stream["codec_type"] == "audio");
+
+    if let Some(video_stream) = video_stream {
+        metadata.width = video_stream["width"].as_i64().map(|width| width as i32);
+        metadata.height = video_stream["height"].as_i64().map(|height| height as i32);
+        metadata.video_codec = video_stream["codec_name"].as_str().map(str::to_string);
+        metadata.frame_rate = video_stream["avg_frame_rate"]
+            .as_str()
+            .and_then(parse_frame_rate);
+    }
+
+    if let Some(audio_stream) = audio_stream {
+        metadata.audio_codec = audio_stream["codec_name"].as_str().map(str::to_string);
+    }
+
+    let format = &probed["format"];
+
+    metadata.duration_seconds = get_probe_number(format, "duration");
+
+    metadata.artist = get_probe_tag(format, "artist");
+    metadata.album = get_probe_tag(format, "album");
+    metadata.title = get_probe_tag(format, "title");
+
+    // `3` or `3/12`
+    metadata.track_number = get_probe_tag(format, "track").and_then(|track| {
+        track
+            .split('/')
+            .next()
+            .and_then(|number| number.trim().parse::<i32>().ok())
+    });
+
+    metadata.taken_at = get_probe_tag(format, "creation_time").and_then(|creation_time| {
+        DateTime::parse_from_rfc3339(&creation_time)
+            .ok()
+            .map(|creation_time| {
+                creation_time
+                    .naive_utc()
+                    .format(TAKEN_AT_FORMAT)
+                    .to_string()
+            })
+    });
+}
+
+fn get_exif_string(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|value| String::from_utf8_lossy(value).trim().to_string())
+            .filter(|value| !value.is_empty()),
+        _ => None,
+    }
+}
+
+/**
+ * GPS coordinates are stored as degrees, minutes and seconds, plus a reference (N/S, E/W) for the sign
+ */
+fn get_exif_coordinate(
+    exif: &exif::Exif,
+    tag: Tag,
+    ref_tag: Tag,
+    negative_ref: &str,
+) -> Option<f64> {
+    let parts = match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(parts) if parts.len() == 3 => {
+            parts.iter().map(|part| part.to_f64()).collect::<Vec<f64>>()
+        }
+        _ => return None,
+    };
+
+    let coordinate = parts[0] + parts[1] / 60.0 + parts[2] / 3600.0;
+
+    if !coordinate.is_finite() {
+        return None;
+    }
+
+    if get_exif_string(exif, ref_tag).as_deref() == Some(negative_ref) {
+        Some(-coordinate)
+    } else {
+        Some(coordinate)
+    }
+}
+
+fn apply_exif_metadata(file_path: &Path, metadata: &mut StorageMediaMetadata) {
+    let file = match File::open(file_path) {
+        Ok(file) => file,
+        Err(_) => return,
+    };
+
+    let exif = match exif::Reader::new().read_from_container(&mut BufReader::new(file)) {
+        Ok(exif) => exif,
+        Err(_) => return,
+    };
+
+    metadata.camera_make = get_exif_string(&exif, Tag::Make);
+    metadata.camera_model = get_exif_string(&exif, Tag::Model);
+
+    metadata.orientation = exif
+        .get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .filter(|orientation| (1..=8).contains(orientation))
+        .map(|orientation| orientation as i16);
+
+    metadata.taken_at = [Tag::DateTimeOriginal, Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Ascii(values) => exif::DateTime::from_ascii(values.first()?).ok(),
+            _ => None,
+        })
+        .and_then(|taken_at| {
+            NaiveDate::from_ymd_opt(
+                taken_at.year as i32,
+                taken_at.month as u32,
+                taken_at.day as u32,
+            )?
+            .and_hms_opt(
+                taken_at.hour as u32,
+                taken_at.minute as u32,
+                taken_at.second as u32,
+            )
+        })
+        .map(|taken_at| taken_at.format(TAKEN_AT_FORMAT).to_string());
+
+    metadata.gps_latitude = get_exif_coordinate(&exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S");
+    metadata.gps_longitude =
+        get_exif_coordinate(&exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W");
+
+    // In case ffprobe is not available
+    if metadata.width.is_none() || metadata.height.is_none() {
+        let get_dimension = |tag: Tag| {
+            exif.get_field(tag, In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+                .map(|dimension| dimension as i32)
+        };
+
+        metadata.width = get_dimension(Tag::PixelXDimension);
+        metadata.height = get_dimension(Tag::PixelYDimension);
+    }
+}
+
+/**
+ * Extract the metadata of an image, video or audio file. Dimensions, durations, codecs and tags are read with ffprobe,
+ * EXIF fields of images are parsed directly.
+ *
+ * @returns `None` for other types of files, or when nothing could be extracted
+ */
+pub fn extract_media_metadata(
+    file_path: &Path,
+    mime_type: Option<&str>,
+) -> Option<StorageMediaMetadata> {
+    let mime_type = mime_type?;
+
+    let is_image = mime_type.starts_with("image/");
+
+    if !is_image && !mime_type.starts_with("video/") && !mime_type.starts_with("audio/") {
+        return None;
+    }
+
+    let mut metadata = StorageMediaMetadata::default();
+
+    if let Some(probed) = probe_media_file(file_path) {
+        apply_probed_metadata(&probed, &mut metadata);
+
+        // ffprobe reports images as single frame videos
+        if is_image {
+            metadata.duration_seconds = None;
+            metadata.frame_rate = None;
+        }
+    }
+
+    if is_image {
+        apply_exif_metadata(file_path, &mut metadata);
+    }
+
+    if metadata.is_empty() {
+        return None;
+    }
+
+    // Same limits as the columns
+    for (value, max_length) in [
+        (&mut metadata.video_codec, 64),
+        (&mut metadata.audio_codec, 64),
+        (&mut metadata.camera_make, 256),
+        (&mut metadata.camera_model, 256),
+        (&mut metadata.artist, 256),
+        (&mut metadata.album, 256),
+        (&mut metadata.title, 256),
+    ] {
+        if let Some(value) = value {
+            if let Some((index, _)) = value.char_indices().nth(max_length) {
+                value.truncate(index);
+            }
+        }
+    }
+
+    Some(metadata)
+}
+
+/**
+ * Store the metadata of a blob, replacing what was extracted before
+ */
+pub async fn save_media_metadata(
+    endpoint_id: i32,
+    filesystem_id: &str,
+    metadata: &StorageMediaMetadata,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    sqlx::query(
+        "INSERT INTO storage_media_metadata (endpoint_id, filesystem_id, width, height, duration_seconds, video_codec, audio_codec, frame_rate, camera_make, camera_model, taken_at, orientation, gps_latitude, gps_longitude, artist, album, title, track_number)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11::TIMESTAMP, $12, $13, $14, $15, $16, $17, $18)
+        ON CONFLICT (endpoint_id, filesystem_id) DO UPDATE SET
+            width = excluded.width, height = excluded.height, duration_seconds = excluded.duration_seconds,
+            video_codec = excluded.video_codec, audio_codec = excluded.audio_codec, frame_rate = excluded.frame_rate,
+            camera_make = excluded.camera_make, camera_model = excluded.camera_model, taken_at = excluded.taken_at,
+            orientation = excluded.orientation, gps_latitude = excluded.gps_latitude, gps_longitude = excluded.gps_longitude,
+            artist = excluded.artist, album = excluded.album, title = excluded.title, track_number = excluded.track_number,
+            extracted_at = now()",
+    )
+    .bind(endpoint_id)
+    .bind(filesystem_id)
+    .bind(metadata.width)
+    .bind(metadata.height)
+    .bind(metadata.duration_seconds)
+    .bind(&metadata.video_codec)
+    .bind(&metadata.audio_codec)
+    .bind(metadata.frame_rate)
+    .bind(&metadata.camera_make)
+    .bind(&metadata.camera_model)
+    .bind(&metadata.taken_at)
+    .bind(metadata.orientation)
+    .bind(metadata.gps_latitude)
+    .bind(metadata.gps_longitude)
+    .bind(&metadata.artist)
+    .bind(&metadata.album)
+    .bind(&metadata.title)
+    .bind(metadata.track_number)
+    .execute(pool)
+    .await
+    .map(|_| ())
+    .map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Give a copy of a blob (possibly on another endpoint) the metadata of the original, so it doesn't have to be
+ * extracted again
+ */
+pub async fn copy_media_metadata(
+    source_endpoint_id: i32,
+    source_filesystem_id: &str,
+    target_endpoint_id: i32,
+    target_filesystem_id: &str,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    sqlx::query(
+        "INSERT INTO storage_media_metadata (endpoint_id, filesystem_id, width, height, duration_seconds, video_codec, audio_codec, frame_rate, camera_make, camera_model, taken_at, orientation, gps_latitude, gps_longitude, artist, album, title, track_number, extracted_at)
+        SELECT $3, $4, width, height, duration_seconds, video_codec, audio_codec, frame_rate, camera_make, camera_model, taken_at, orientation, gps_latitude, gps_longitude, artist, album, title, track_number, extracted_at
+        FROM storage_media_metadata WHERE endpoint_id = $1 AND filesystem_id = $2
+        ON CONFLICT (endpoint_id, filesystem_id) DO NOTHING",
+    )
+    .bind(source_endpoint_id)
+    .bind(source_filesystem_id)
+    .bind(target_endpoint_id)
+    .bind(target_filesystem_id)
+    .execute(pool)
+    .await
+    .map(|_| ())
+    .map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Remove the metadata of blobs that were deleted from the endpoint
+ */
+pub async fn delete_media_metadata(
+    endpoint_id: i32,
+    filesystem_ids: &Vec<String>,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    sqlx::query(
+        "DELETE FROM storage_media_metadata WHERE endpoint_id = $1 AND filesystem_id = ANY($2::BPCHAR[])",
+    )
+    .bind(endpoint_id)
+    .bind(filesystem_ids)
+    .execute(pool)
+    .await
+    .map(|_| ())
+    .map_err(|_| StorageError::Internal)
+}
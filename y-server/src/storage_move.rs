@@ -0,0 +1,607 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use actix_web::web;
+use log::*;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::jobs::{enqueue_job, JOB_STORAGE_MOVE_ENTRIES};
+use crate::storage_artifacts::queue_uploaded_files_artifacts;
+use crate::storage_backend::get_storage_backend;
+use crate::storage_blobs::find_duplicate_blob;
+use crate::storage_copy::{transfer_blob, BlobTransfer};
+use crate::storage_endpoint::{get_storage_endpoint, StorageEndpointRow};
+use crate::storage_entry::{delete_storage_blobs, StorageError};
+use crate::storage_layout::sync_endpoint_file_structure;
+use crate::storage_versions::prune_entry_versions;
+use crate::util::RequestPool;
+use crate::ws::{send_storage_location_updated, WSState};
+
+// Guards against cycles in the tree
+const MAX_TREE_DEPTH: i32 = 1000;
+
+// How many times the blobs are copied again if the entries change while they are being moved
+const MAX_COPY_PASSES: u32 = 3;
+
+// The moved entries, along with everything inside of them. Binds $1 (endpoint id), $2 (entry ids) and $3 (max depth)
+const MOVED_TREE_CTE: &str = "WITH RECURSIVE tree(id, depth) AS (
+    SELECT id, 0 FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)
+    UNION
+    SELECT storage_entries.id, tree.depth + 1 FROM storage_entries INNER JOIN tree ON storage_entries.parent_folder = tree.id
+    WHERE storage_entries.endpoint_id = $1 AND tree.depth < $3
+)";
+
+/**
+ * What happens to the access rules of the moved entries
+ */
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageMoveAccessRules {
+    /**
+     * The rules (and access rule templates) are carried over to the target endpoint
+     */
+    Keep,
+
+    /**
+     * The rules are removed, the entries inherit the access rules of the target folder
+     */
+    Drop,
+}
+
+#[derive(FromRow)]
+struct StorageMoveRow {
+    id: i64,
+    source_endpoint_id: i32,
+    entry_ids: Vec<i64>,
+    target_endpoint_id: i32,
+    target_folder: Option<i64>,
+    keep_access_rules: bool,
+    status: String,
+    created_by: Option<i32>,
+}
+
+#[derive(FromRow)]
+struct MovedBlob {
+    filesystem_id: String,
+    sha256: Option<String>,
+    size_bytes: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StorageMoveJobPayload {
+    pub move_id: i64,
+}
+
+/**
+ * Start moving files and folders (with everything inside of them) into a folder on another endpoint.
+ *
+ * The move runs in the background. The entries keep their ids, so pins, share links and bookmarks keep working.
+ * Access and quotas must be checked by the caller.
+ *
+ * @param target_folder - Folder to move the entries into. `None` means the root of the target endpoint
+ *
+ * @returns id of the move
+ */
+pub async fn start_cross_endpoint_move(
+    source_endpoint_id: i32,
+    entry_ids: &Vec<i64>,
+    target_endpoint_id: i32,
+    target_folder: Option<i64>,
+    access_rules: StorageMoveAccessRules,
+    created_by: Option<i32>,
+    pool: &RequestPool,
+) -> Result<i64, StorageError> {
+    let move_id = sqlx::query_scalar::<_, i64>(
+        "INSERT INTO storage_moves (source_endpoint_id, entry_ids, target_endpoint_id, target_folder, keep_access_rules, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+    )
+    .bind(source_endpoint_id)
+    .bind(entry_ids)
+    .bind(target_endpoint_id)
+    .bind(target_folder)
+    .bind(access_rules == StorageMoveAccessRules::Keep)
+    .bind(created_by)
+    .fetch_one(pool)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    let enqueue_result = enqueue_job(
+        JOB_STORAGE_MOVE_ENTRIES,
+        &StorageMoveJobPayload { move_id },
+        created_by,
+        pool,
+    )
+    .await;
+
+    if enqueue_result.is_err() {
+        let _ = sqlx::query("DELETE FROM storage_moves WHERE id = $1")
+            .bind(move_id)
+            .execute(pool)
+            .await;
+
+        return Err(StorageError::Internal);
+    }
+
+    Ok(move_id)
+}
+
+/**
+ * Blobs used by the moved entries and their previous versions
+ */
+async fn get_moved_blobs(
+    storage_move: &StorageMoveRow,
+    pool: &RequestPool,
+) -> Result<Vec<MovedBlob>, StorageError> {
+    sqlx::query_as::<_, MovedBlob>(&format!(
+        "{} SELECT filesystem_id::TEXT, sha256, size_bytes FROM storage_entries
+        WHERE id IN (SELECT id FROM tree) AND filesystem_id IS NOT NULL
+        UNION SELECT filesystem_id::TEXT, sha256, size_bytes FROM storage_entry_versions
+        WHERE endpoint_id = $1 AND entry_id IN (SELECT id FROM tree)",
+        MOVED_TREE_CTE
+    ))
+    .bind(storage_move.source_endpoint_id)
+    .bind(&storage_move.entry_ids)
+    .bind(MAX_TREE_DEPTH)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Copy the blobs of the moved entries to the target endpoint. Blobs that have already been copied are skipped.
+ *
+ * Nothing is visible on the target endpoint yet, the entries still use the blobs on the source endpoint.
+ */
+async fn copy_moved_blobs(
+    storage_move: &StorageMoveRow,
+    source_endpoint: &StorageEndpointRow,
+    target_endpoint: &StorageEndpointRow,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    let source_backend = get_storage_backend(source_endpoint, pool).await?;
+    let target_backend = get_storage_backend(target_endpoint, pool).await?;
+
+    let transfer = BlobTransfer {
+        source_endpoint,
+        source_backend: source_backend.as_ref(),
+        target_endpoint,
+        target_backend: target_backend.as_ref(),
+    };
+
+    // Source filesystem_id -> (target filesystem_id, whether the blob has been copied)
+    let journal = sqlx::query_as::<_, (String, String, bool)>(
+        "SELECT source_filesystem_id::TEXT, target_filesystem_id::TEXT, copied FROM storage_move_blobs WHERE move_id = $1",
+    )
+    .bind(storage_move.id)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageError::Internal)?
+    .into_iter()
+    .map(|(source, target, copied)| (source, (target, copied)))
+    .collect::<HashMap<String, (String, bool)>>();
+
+    let mut seen: HashSet<String> = HashSet::new();
+
+    for blob in get_moved_blobs(storage_move, pool).await? {
+        if !seen.insert(blob.filesystem_id.clone()) {
+            continue;
+        }
+
+        let target_filesystem_id = match journal.get(&blob.filesystem_id) {
+            Some((_, true)) => continue,
+            Some((target_filesystem_id, false)) => target_filesystem_id.clone(),
+            None => {
+                // The duplicate is recorded in the same transaction it is found in, so it can not be deleted in between
+                let target: Result<(String, bool), sqlx::Error> = async {
+                    let mut transaction = pool.begin().await?;
+
+                    let duplicate = match (&blob.sha256, blob.size_bytes) {
+                        (Some(sha256), Some(size_bytes)) => {
+                            find_duplicate_blob(target_endpoint, sha256, size_bytes, &mut transaction)
+                                .await
+                        }
+                        _ => None,
+                    };
+
+                    // The id of the copy is recorded first, an interrupted copy is simply done again
+                    let (target_filesystem_id, copied) = match duplicate {
+                        Some(duplicate) => (duplicate, true),
+                        None => (Uuid::new_v4().to_string(), false),
+                    };
+
+                    sqlx::query(
+                        "INSERT INTO storage_move_blobs (move_id, source_filesystem_id, target_filesystem_id, copied) VALUES ($1, $2, $3, $4)",
+                    )
+                    .bind(storage_move.id)
+                    .bind(&blob.filesystem_id)
+                    .bind(&target_filesystem_id)
+                    .bind(copied)
+                    .execute(&mut *transaction)
+                    .await?;
+
+                    transaction.commit().await?;
+
+                    Ok((target_filesystem_id, copied))
+                }
+                .await;
+
+                let (target_filesystem_id, copied) = target.map_err(|_| StorageError::Internal)?;
+
+                if copied {
+                    continue;
+                }
+
+                target_filesystem_id
+            }
+        };
+
+        // The originals are removed once the move is done, so the data can be shared on disk
+        transfer_blob(
+            &transfer,
+            &blob.filesystem_id,
+            &target_filesystem_id,
+            true,
+            pool,
+        )
+        .await?;
+
+        sqlx::query(
+            "UPDATE storage_move_blobs SET copied = true WHERE move_id = $1 AND source_filesystem_id = $2",
+        )
+        .bind(storage_move.id)
+        .bind(&blob.filesystem_id)
+        .execute(pool)
+        .await
+        .map_err(|_| StorageError::Internal)?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Switch all the moved entries over to the target endpoint at once.
+ *
+ * @returns folders on the source endpoint that the entries were moved out of. `None` if some of the entries now use
+ * blobs that have not been copied yet (the entries were changed during the move)
+ */
+async fn switch_moved_entries(
+    storage_move: &StorageMoveRow,
+    source_endpoint: &StorageEndpointRow,
+    target_endpoint: &StorageEndpointRow,
+    pool: &RequestPool,
+) -> Result<Option<Vec<Option<i64>>>, StorageError> {
+    let switch_result: Result<Option<Vec<Option<i64>>>, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+
+        let tree_ids = sqlx::query_scalar::<_, i64>(&format!(
+            "{} SELECT id FROM storage_entries WHERE id IN (SELECT id FROM tree) FOR UPDATE",
+            MOVED_TREE_CTE
+        ))
+        .bind(source_endpoint.id)
+        .bind(&storage_move.entry_ids)
+        .bind(MAX_TREE_DEPTH)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        let uncopied_blobs = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM (
+                SELECT filesystem_id FROM storage_entries WHERE id = ANY($2) AND filesystem_id IS NOT NULL
+                UNION SELECT filesystem_id FROM storage_entry_versions WHERE endpoint_id = $1 AND entry_id = ANY($2)
+            ) AS blob WHERE NOT EXISTS (
+                SELECT 1 FROM storage_move_blobs WHERE move_id = $3 AND source_filesystem_id = blob.filesystem_id AND copied
+            )",
+        )
+        .bind(source_endpoint.id)
+        .bind(&tree_ids)
+        .bind(storage_move.id)
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        if uncopied_blobs > 0 {
+            return Ok(None);
+        }
+
+        let source_parent_folders = sqlx::query_scalar::<_, Option<i64>>(
+            "SELECT DISTINCT parent_folder FROM storage_entries WHERE id = ANY($1) AND NOT (parent_folder IS NOT NULL AND parent_folder = ANY($1))",
+        )
+        .bind(&tree_ids)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        // Preview videos only exist if the artifacts could be carried over
+        let artifacts_moved =
+            source_endpoint.artifacts_path.is_some() && target_endpoint.artifacts_path.is_some();
+
+        // Entries that were requested along with one of their parents stay inside of it
+        sqlx::query(
+            "UPDATE storage_entries SET endpoint_id = $1,
+            parent_folder = CASE WHEN parent_folder = ANY($2) THEN parent_folder ELSE $3 END,
+            filesystem_id = (
+                SELECT target_filesystem_id FROM storage_move_blobs
+                WHERE move_id = $4 AND source_filesystem_id = storage_entries.filesystem_id
+            ),
+            transcoded_version_available = CASE WHEN $5 THEN transcoded_version_available ELSE NULL END
+            WHERE id = ANY($2)",
+        )
+        .bind(target_endpoint.id)
+        .bind(&tree_ids)
+        .bind(storage_move.target_folder)
+        .bind(storage_move.id)
+        .bind(artifacts_moved)
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query(
+            "UPDATE storage_entry_versions SET endpoint_id = $1, filesystem_id = (
+                SELECT target_filesystem_id FROM storage_move_blobs
+                WHERE move_id = $4 AND source_filesystem_id = storage_entry_versions.filesystem_id
+            ) WHERE endpoint_id = $2 AND entry_id = ANY($3)",
+        )
+        .bind(target_endpoint.id)
+        .bind(source_endpoint.id)
+        .bind(&tree_ids)
+        .bind(storage_move.id)
+        .execute(&mut *transaction)
+        .await?;
+
+        // Whatever refers to the entries follows them
+        let mut follow_queries = vec![
+            "UPDATE storage_share_links SET endpoint_id = $1 WHERE endpoint_id = $2 AND entry_id = ANY($3)",
+            "UPDATE storage_user_pins SET endpoint_id = $1 WHERE endpoint_id = $2 AND entry_id = ANY($3)",
+            "UPDATE storage_locations SET endpoint_id = $1 WHERE endpoint_id = $2 AND entry_id = ANY($3)",
+            // Tags belong to an endpoint, switch the entries over to the tags with the same names on the target
+            "INSERT INTO storage_tags (endpoint_id, name)
+            SELECT DISTINCT $1, storage_tags.name FROM storage_entry_tags
+            INNER JOIN storage_tags ON storage_tags.id = storage_entry_tags.tag_id
+            WHERE storage_tags.endpoint_id = $2 AND storage_entry_tags.entry_id = ANY($3)
+            ON CONFLICT DO NOTHING",
+            "UPDATE storage_entry_tags SET tag_id = target_tags.id
+            FROM storage_tags AS source_tags, storage_tags AS target_tags
+            WHERE source_tags.id = storage_entry_tags.tag_id AND source_tags.endpoint_id = $2
+            AND target_tags.endpoint_id = $1 AND lower(target_tags.name) = lower(source_tags.name)
+            AND storage_entry_tags.entry_id = ANY($3)",
+        ];
+
+        let mut drop_queries = vec![
+            "DELETE FROM storage_imported_entries WHERE endpoint_id = $1 AND entry_id = ANY($2)",
+        ];
+
+        if storage_move.keep_access_rules {
+            follow_queries.push(
+                "UPDATE storage_access SET endpoint_id = $1 WHERE endpoint_id = $2 AND entry_id = ANY($3)",
+            );
+            follow_queries.push(
+                "UPDATE storage_access_template_entries SET entry_endpoint_id = $1 WHERE entry_endpoint_id = $2 AND entry_id = ANY($3)",
+            );
+        } else {
+            drop_queries.push(
+                "DELETE FROM storage_access WHERE endpoint_id = $1 AND entry_id = ANY($2)",
+            );
+            drop_queries.push(
+                "DELETE FROM storage_access_template_entries WHERE entry_endpoint_id = $1 AND entry_id = ANY($2)",
+            );
+        }
+
+        for query in follow_queries {
+            sqlx::query(query)
+                .bind(target_endpoint.id)
+                .bind(source_endpoint.id)
+                .bind(&tree_ids)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        for query in drop_queries {
+            sqlx::query(query)
+                .bind(source_endpoint.id)
+                .bind(&tree_ids)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        sqlx::query("UPDATE storage_moves SET status = 'cleanup' WHERE id = $1")
+            .bind(storage_move.id)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(Some(source_parent_folders))
+    }
+    .await;
+
+    switch_result.map_err(|err| match err {
+        sqlx::Error::Database(err) if err.is_unique_violation() => StorageError::NameConflict,
+        err => {
+            error!(
+                "(storage move) Could not switch the entries of move {} over to endpoint {}. {}",
+                storage_move.id, target_endpoint.id, err
+            );
+
+            StorageError::Internal
+        }
+    })
+}
+
+/**
+ * Remove what the moved entries have left behind on the source endpoint and put them in place on the target endpoint.
+ * Safe to run more than once.
+ */
+async fn clean_up_move(
+    storage_move: &StorageMoveRow,
+    source_endpoint: &StorageEndpointRow,
+    target_endpoint: &StorageEndpointRow,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    let (source_filesystem_ids, target_filesystem_ids): (Vec<String>, Vec<String>) =
+        sqlx::query_as::<_, (String, String)>(
+            "SELECT source_filesystem_id::TEXT, target_filesystem_id::TEXT FROM storage_move_blobs WHERE move_id = $1",
+        )
+        .bind(storage_move.id)
+        .fetch_all(pool)
+        .await
+        .map_err(|_| StorageError::Internal)?
+        .into_iter()
+        .unzip();
+
+    // Takes the blobs out of the tree first, if the source endpoint preserves its file structure
+    sync_endpoint_file_structure(source_endpoint.id, &Vec::new(), pool).await;
+    delete_storage_blobs(source_endpoint, &source_filesystem_ids, pool).await?;
+
+    sync_endpoint_file_structure(target_endpoint.id, &storage_move.entry_ids, pool).await;
+
+    // The target endpoint might keep fewer versions
+    let over_limit_entry_ids = sqlx::query_scalar::<_, i64>(&format!(
+        "{} SELECT entry_id FROM storage_entry_versions WHERE endpoint_id = $1 AND entry_id IN (SELECT id FROM tree)
+        GROUP BY entry_id HAVING COUNT(*) > $4",
+        MOVED_TREE_CTE
+    ))
+    .bind(target_endpoint.id)
+    .bind(&storage_move.entry_ids)
+    .bind(MAX_TREE_DEPTH)
+    .bind(target_endpoint.max_file_versions as i64)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    for entry_id in over_limit_entry_ids {
+        prune_entry_versions(target_endpoint, entry_id, pool).await?;
+    }
+
+    // There was nothing to carry the artifacts over from
+    if source_endpoint.artifacts_path.is_none() {
+        queue_uploaded_files_artifacts(
+            target_endpoint,
+            target_filesystem_ids,
+            storage_move.created_by,
+            pool,
+        )
+        .await;
+    }
+
+    sqlx::query("DELETE FROM storage_moves WHERE id = $1")
+        .bind(storage_move.id)
+        .execute(pool)
+        .await
+        .map_err(|_| StorageError::Internal)?;
+
+    Ok(())
+}
+
+async fn run_move(
+    storage_move: &StorageMoveRow,
+    source_endpoint: &StorageEndpointRow,
+    target_endpoint: &StorageEndpointRow,
+    pool: &RequestPool,
+) -> Result<Vec<Option<i64>>, StorageError> {
+    let mut source_parent_folders: Vec<Option<i64>> = Vec::new();
+
+    if storage_move.status == "copying" {
+        let mut switched = false;
+
+        for _ in 0..MAX_COPY_PASSES {
+            copy_moved_blobs(storage_move, source_endpoint, target_endpoint, pool).await?;
+
+            if let Some(parent_folders) =
+                switch_moved_entries(storage_move, source_endpoint, target_endpoint, pool).await?
+            {
+                source_parent_folders = parent_folders;
+                switched = true;
+
+                break;
+            }
+        }
+
+        if !switched {
+            return Err(StorageError::Internal);
+        }
+    }
+
+    clean_up_move(storage_move, source_endpoint, target_endpoint, pool).await?;
+
+    Ok(source_parent_folders)
+}
+
+/**
+ * Move entries to another endpoint (see `start_cross_endpoint_move`).
+ *
+ * The blobs are copied first, then all the entries are switched over at once, then the originals are removed. Until
+ * the switch the entries stay usable on the source endpoint. A move that was interrupted picks up where it has
+ * stopped when the job is run again.
+ *
+ * Blocks, runs in a job worker.
+ */
+pub fn run_move_entries_job(
+    runtime: &actix_rt::Runtime,
+    pool: &RequestPool,
+    ws_state: &web::Data<Mutex<WSState>>,
+    payload: StorageMoveJobPayload,
+) -> Result<(), String> {
+    let storage_move = runtime
+        .block_on(
+            sqlx::query_as::<_, StorageMoveRow>(
+                "SELECT id, source_endpoint_id, entry_ids, target_endpoint_id, target_folder, keep_access_rules, status::TEXT, created_by
+                FROM storage_moves WHERE id = $1",
+            )
+            .bind(payload.move_id)
+            .fetch_optional(pool),
+        )
+        .map_err(|err| format!("Could not load move {}. {}", payload.move_id, err))?;
+
+    // Already done
+    let storage_move = match storage_move {
+        Some(storage_move) => storage_move,
+        None => return Ok(()),
+    };
+
+    let source_endpoint = runtime
+        .block_on(get_storage_endpoint(storage_move.source_endpoint_id, pool))
+        .map_err(|_| {
+            format!(
+                "Endpoint {} does not exist",
+                storage_move.source_endpoint_id
+            )
+        })?;
+
+    let target_endpoint = runtime
+        .block_on(get_storage_endpoint(storage_move.target_endpoint_id, pool))
+        .map_err(|_| {
+            format!(
+                "Endpoint {} does not exist",
+                storage_move.target_endpoint_id
+            )
+        })?;
+
+    let source_parent_folders = runtime
+        .block_on(run_move(
+            &storage_move,
+            &source_endpoint,
+            &target_endpoint,
+            pool,
+        ))
+        .map_err(|err| format!("Could not move the entries. {}", err.get_code()))?;
+
+    if !source_parent_folders.is_empty() {
+        runtime.block_on(send_storage_location_updated(
+            ws_state,
+            None,
+            source_endpoint.id,
+            source_parent_folders,
+            true,
+            false,
+        ));
+    }
+
+    runtime.block_on(send_storage_location_updated(
+        ws_state,
+        None,
+        target_endpoint.id,
+        vec![storage_move.target_folder],
+        true,
+        false,
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use serde::Serialize;
+
+use crate::storage_endpoint::StorageEndpointRow;
+use crate::storage_entry::StorageError;
+use crate::user::{get_group_rights, get_user_groups, UserRight};
+use crate::util::RequestPool;
+
+/**
+ * How much a user stores and how much they are allowed to store.
+ *
+ * Usage is the total size of the files that the user has created. `None` limits mean unlimited.
+ */
+#[derive(Serialize)]
+pub struct StorageQuota {
+    pub endpoint_id: i32,
+
+    /**
+     * Usage on this endpoint, limited by the endpoint's `user_quota_bytes`
+     */
+    pub endpoint_used_bytes: i64,
+    pub endpoint_limit_bytes: Option<i64>,
+
+    /**
+     * Usage across all endpoints, limited by the `storage_quota` right of the user's groups
+     */
+    pub total_used_bytes: i64,
+    pub total_limit_bytes: Option<i64>,
+
+    /**
+     * How many more bytes the user can store on this endpoint
+     */
+    pub remaining_bytes: Option<i64>,
+}
+
+impl StorageQuota {
+    pub fn allows(&self, additional_bytes: i64) -> bool {
+        match self.remaining_bytes {
+            Some(remaining_bytes) => additional_bytes <= remaining_bytes,
+            None => true,
+        }
+    }
+}
+
+/**
+ * Find the quota that the user's groups set with the `storage_quota` right.
+ * If multiple groups set one, the most generous one wins, just like with any other right.
+ *
+ * @returns `None` if there is no limit
+ */
+pub fn get_group_quota_limit(group_rights: &Vec<UserRight>) -> Option<i64> {
+    let mut limit: Option<i64> = None;
+
+    for right in group_rights {
+        if !right.right_name.eq("storage_quota") {
+            continue;
+        }
+
+        let right_limit = right
+            .right_options
+            .get("max_storage_bytes")
+            .and_then(|value| {
+                value
+                    .as_i64()
+                    .or_else(|| value.as_str().and_then(|value| value.parse::<i64>().ok()))
+            });
+
+        match right_limit {
+            Some(right_limit) => limit = Some(limit.map_or(right_limit, |l| l.max(right_limit))),
+            // The right is granted without a limit
+            None => return None,
+        }
+    }
+
+    limit
+}
+
+pub async fn get_user_storage_quota(
+    target_endpoint: &StorageEndpointRow,
+    user_id: i32,
+    pool: &RequestPool,
+) -> Result<StorageQuota, StorageError> {
+    let user_groups = get_user_groups(pool, user_id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+    let group_rights = get_group_rights(pool, &group_ids).await;
+
+    let total_limit_bytes = get_group_quota_limit(&group_rights);
+
+    let endpoint_limit_bytes = if target_endpoint.user_quota_bytes > 0 {
+        Some(target_endpoint.user_quota_bytes)
+    } else {
+        None
+    };
+
+    let (endpoint_used_bytes, total_used_bytes) = sqlx::query_as::<_, (i64, i64)>(
+        "SELECT COALESCE(SUM(size_bytes) FILTER (WHERE endpoint_id = $2), 0)::BIGINT, COALESCE(SUM(size_bytes), 0)::BIGINT
+        FROM storage_entries WHERE created_by = $1 AND entry_type = 'file'::storage_entry_type",
+    )
+    .bind(user_id)
+    .bind(target_endpoint.id)
+    .fetch_one(pool)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    let remaining_bytes = [
+        endpoint_limit_bytes.map(|limit| limit - endpoint_used_bytes),
+        total_limit_bytes.map(|limit| limit - total_used_bytes),
+    ]
+    .into_iter()
+    .flatten()
+    .min()
+    .map(|remaining_bytes| remaining_bytes.max(0));
+
+    Ok(StorageQuota {
+        endpoint_id: target_endpoint.id,
+        endpoint_used_bytes,
+        endpoint_limit_bytes,
+        total_used_bytes,
+        total_limit_bytes,
+        remaining_bytes,
+    })
+}
+
+/**
+ * Make sure that a user can store `additional_bytes` more on an endpoint.
+ *
+ * Files that are created without a user (`user_id == None`) are not subject to quotas.
+ */
+pub async fn check_storage_quota(
+    target_endpoint: &StorageEndpointRow,
+    user_id: Option<i32>,
+    additional_bytes: i64,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    let user_id = match user_id {
+        Some(user_id) => user_id,
+        None => return Ok(()),
+    };
+
+    let quota = get_user_storage_quota(target_endpoint, user_id, pool).await?;
+
+    if quota.allows(additional_bytes) {
+        Ok(())
+    } else {
+        Err(StorageError::QuotaExceeded)
+    }
+}
+
+/**
+ * Total size of the given entries, including everything inside of the folders among them. Used to check copies against quotas.
+ */
+pub async fn get_entries_total_size(
+    endpoint_id: i32,
+    entry_ids: &Vec<i64>,
+    pool: &RequestPool,
+) -> Result<i64, StorageError> {
+    sqlx::query_scalar::<_, i64>(
+        "WITH RECURSIVE tree AS (
+            SELECT id, size_bytes FROM storage_entries WHERE endpoint_id = $1 AND id = ANY($2)
+            UNION
+            SELECT storage_entries.id, storage_entries.size_bytes FROM storage_entries
+            INNER JOIN tree ON storage_entries.parent_folder = tree.id
+            WHERE storage_entries.endpoint_id = $1
+        ) SELECT COALESCE(SUM(size_bytes), 0)::BIGINT FROM tree",
+    )
+    .bind(endpoint_id)
+    .bind(entry_ids)
+    .fetch_one(pool)
+    .await
+    .map_err(|_| StorageError::Internal)
+}
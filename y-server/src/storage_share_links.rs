@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpRequest};
+use chrono::{DateTime, Utc};
+use log::*;
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::storage_access::check_storage_entry_access;
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::user::get_user_groups;
+use crate::util::RequestPool;
+
+const SHARE_LINK_TOKEN_LENGTH: usize = 32;
+
+/**
+ * How long requests for a file from a client count as the same download
+ */
+const SHARE_LINK_DOWNLOAD_WINDOW_MINUTES: i32 = 60;
+
+#[derive(PartialEq, Debug)]
+pub enum ShareLinkError {
+    NotFound,
+    Expired,
+    DownloadLimitReached,
+    PasswordRequired,
+    InvalidPassword,
+
+    Internal,
+}
+
+impl ShareLinkError {
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            ShareLinkError::NotFound => "share_link.not_found",
+            ShareLinkError::Expired => "share_link.expired",
+            ShareLinkError::DownloadLimitReached => "share_link.download_limit_reached",
+            ShareLinkError::PasswordRequired => "share_link.password_required",
+            ShareLinkError::InvalidPassword => "share_link.invalid_password",
+
+            ShareLinkError::Internal => "share_link.internal",
+        }
+    }
+}
+
+#[derive(FromRow)]
+pub struct StorageShareLink {
+    pub id: i32,
+    pub endpoint_id: i32,
+    pub entry_id: i64,
+    pub password: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_downloads: Option<i32>,
+    pub downloads_count: i32,
+    pub allow_browsing: bool,
+    pub created_by: i32,
+}
+
+/**
+ * A share link, as seen by the people who manage it
+ */
+#[derive(FromRow, Serialize)]
+pub struct StorageShareLinkInfo {
+    pub id: i32,
+    pub endpoint_id: i32,
+    pub entry_id: i64,
+    pub entry_name: Option<String>,
+    pub entry_extension: Option<String>,
+    pub entry_type: Option<String>,
+    pub token: String,
+    pub password_protected: bool,
+    pub expires_at: Option<String>,
+    pub max_downloads: Option<i32>,
+    pub downloads_count: i32,
+    pub allow_browsing: bool,
+    pub created_by: i32,
+    pub created_by_username: Option<String>,
+    pub created_at: String,
+}
+
+/**
+ * SELECT part of a query that returns `StorageShareLinkInfo` rows.
+ * Entries of trashed links are not found, so their columns are NULL.
+ */
+pub const SHARE_LINK_INFO_SELECT: &str = "SELECT storage_share_links.id, storage_share_links.endpoint_id, storage_share_links.entry_id,
+    storage_entries.name AS entry_name, storage_entries.extension AS entry_extension, storage_entries.entry_type::TEXT AS entry_type,
+    storage_share_links.token, storage_share_links.password IS NOT NULL AS password_protected, storage_share_links.expires_at::TEXT,
+    storage_share_links.max_downloads, storage_share_links.downloads_count, storage_share_links.allow_browsing,
+    storage_share_links.created_by, users.username AS created_by_username, storage_share_links.created_at::TEXT
+    FROM storage_share_links
+    LEFT JOIN storage_entries ON storage_entries.endpoint_id = storage_share_links.endpoint_id AND storage_entries.id = storage_share_links.entry_id
+    LEFT JOIN users ON users.id = storage_share_links.created_by";
+
+pub fn generate_share_link_token() -> String {
+    use rand::distributions::Alphanumeric;
+    use rand::{thread_rng, Rng};
+
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SHARE_LINK_TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+pub fn hash_share_link_password(password: &str) -> Result<String, ShareLinkError> {
+    use pbkdf2::{
+        password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+        Pbkdf2,
+    };
+
+    let password_salt = SaltString::generate(&mut OsRng);
+
+    Pbkdf2
+        .hash_password(password.as_bytes(), &password_salt)
+        .map(|password_hash| password_hash.to_string())
+        .map_err(|_| ShareLinkError::Internal)
+}
+
+fn verify_share_link_password(password_hash: &str, password: &str) -> bool {
+    use pbkdf2::{
+        password_hash::{PasswordHash, PasswordVerifier},
+        Pbkdf2,
+    };
+
+    match PasswordHash::new(password_hash) {
+        Ok(parsed_password_hash) => Pbkdf2
+            .verify_password(password.as_bytes(), &parsed_password_hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/**
+ * Find a share link by its token and make sure it can still be used.
+ *
+ * The password of a protected link is taken from the `Share-Password` header, or the `password` query parameter
+ * (for plain links, where headers can not be set).
+ * The link stops working as soon as the user who created it loses access to the shared entry.
+ */
+pub async fn get_share_link_from_request(
+    token: &str,
+    req: &HttpRequest,
+    pool: &RequestPool,
+) -> Result<StorageShareLink, ShareLinkError> {
+    let share_link = sqlx::query_as::<_, StorageShareLink>(
+        "SELECT id, endpoint_id, entry_id, password, expires_at, max_downloads, downloads_count, allow_browsing, created_by FROM storage_share_links WHERE token = $1",
+    )
+    .bind(token)
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| ShareLinkError::Internal)?;
+
+    if share_link.is_none() {
+        return Err(ShareLinkError::NotFound);
+    }
+
+    let share_link = share_link.unwrap();
+
+    if let Some(expires_at) = share_link.expires_at {
+        if Utc::now() > expires_at {
+            return Err(ShareLinkError::Expired);
+        }
+    }
+
+    if let Some(password_hash) = &share_link.password {
+        let password = req
+            .headers()
+            .get("Share-Password")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+            .or_else(|| {
+                web::Query::<HashMap<String, String>>::from_query(req.query_string())
+                    .ok()
+                    .and_then(|query| query.get("password").cloned())
+            });
+
+        match password {
+            Some(password) => {
+                if !verify_share_link_password(password_hash, &password) {
+                    return Err(ShareLinkError::InvalidPassword);
+                }
+            }
+            None => return Err(ShareLinkError::PasswordRequired),
+        }
+    }
+
+    let target_endpoint = get_storage_endpoint(share_link.endpoint_id, pool).await;
+
+    if target_endpoint.is_err() || target_endpoint.unwrap().status == "disabled" {
+        return Err(ShareLinkError::NotFound);
+    }
+
+    if !check_share_link_creator_access(&share_link, share_link.entry_id, pool).await {
+        return Err(ShareLinkError::NotFound);
+    }
+
+    Ok(share_link)
+}
+
+async fn check_share_link_creator_access(
+    share_link: &StorageShareLink,
+    entry_id: i64,
+    pool: &RequestPool,
+) -> bool {
+    let user_groups = get_user_groups(pool, share_link.created_by).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    check_storage_entry_access(
+        share_link.endpoint_id,
+        entry_id,
+        "download",
+        share_link.created_by,
+        &group_ids,
+        pool,
+    )
+    .await
+}
+
+/**
+ * Check whether an entry can be accessed through a share link.
+ *
+ * That is the shared entry itself or, if the link allows browsing, anything inside of the shared folder.
+ */
+pub async fn check_share_link_entry_access(
+    share_link: &StorageShareLink,
+    entry_id: i64,
+    pool: &RequestPool,
+) -> bool {
+    if entry_id == share_link.entry_id {
+        return true;
+    }
+
+    if !share_link.allow_browsing {
+        return false;
+    }
+
+    // Walk up from the entry, looking for the shared folder
+    let inside_shared_folder = sqlx::query_scalar::<_, bool>(
+        "WITH RECURSIVE parents AS (
+            SELECT id, parent_folder FROM storage_entries WHERE endpoint_id = $1 AND id = $2
+            UNION
+            SELECT storage_entries.id, storage_entries.parent_folder FROM storage_entries
+            INNER JOIN parents ON storage_entries.id = parents.parent_folder
+            WHERE storage_entries.endpoint_id = $1
+        ) SELECT EXISTS(SELECT 1 FROM parents WHERE parent_folder = $3)",
+    )
+    .bind(share_link.endpoint_id)
+    .bind(entry_id)
+    .bind(share_link.entry_id)
+    .fetch_one(pool)
+    .await
+    .unwrap_or(false);
+
+    // Access rules inside of the shared folder still apply to the creator of the link
+    inside_shared_folder && check_share_link_creator_access(share_link, entry_id, pool).await
+}
+
+/**
+ * Count a download made through a share link.
+ *
+ * Files are counted once per client within `SHARE_LINK_DOWNLOAD_WINDOW_MINUTES`, so that players and download managers
+ * that fetch a file in chunks only use up one download. Once the limit is reached, only clients with a download in
+ * progress can keep fetching chunks of that file.
+ *
+ * @param entry_id downloaded file. `None` for archives, which are counted every time.
+ * @returns `DownloadLimitReached` if the link has already been used as many times as it's allowed to
+ */
+pub async fn register_share_link_download(
+    share_link: &StorageShareLink,
+    entry_id: Option<i64>,
+    req: &HttpRequest,
+    pool: &RequestPool,
+) -> Result<(), ShareLinkError> {
+    let client_key = format!(
+        "{}|{}",
+        req.peer_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default(),
+        req.connection_info()
+            .realip_remote_addr()
+            .unwrap_or_default()
+    );
+
+    let register_result: Result<bool, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+
+        // Downloads of the same link are counted one at a time
+        sqlx::query("SELECT id FROM storage_share_links WHERE id = $1 FOR UPDATE")
+            .bind(share_link.id)
+            .execute(&mut *transaction)
+            .await?;
+
+        if let Some(entry_id) = entry_id {
+            let in_progress = sqlx::query_scalar::<_, bool>(
+                "SELECT EXISTS(
+                    SELECT 1 FROM storage_share_link_downloads WHERE share_link_id = $1 AND entry_id = $2 AND client_key = $3
+                    AND started_at > now() - make_interval(mins => $4)
+                )",
+            )
+            .bind(share_link.id)
+            .bind(entry_id)
+            .bind(&client_key)
+            .bind(SHARE_LINK_DOWNLOAD_WINDOW_MINUTES)
+            .fetch_one(&mut *transaction)
+            .await?;
+
+            if in_progress {
+                transaction.commit().await?;
+
+                return Ok(true);
+            }
+        }
+
+        let counted = sqlx::query(
+            "UPDATE storage_share_links SET downloads_count = downloads_count + 1 WHERE id = $1 AND (max_downloads IS NULL OR downloads_count < max_downloads)",
+        )
+        .bind(share_link.id)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected()
+            == 1;
+
+        if counted {
+            if let Some(entry_id) = entry_id {
+                sqlx::query(
+                    "DELETE FROM storage_share_link_downloads WHERE share_link_id = $1 AND started_at < now() - make_interval(mins => $2)",
+                )
+                .bind(share_link.id)
+                .bind(SHARE_LINK_DOWNLOAD_WINDOW_MINUTES)
+                .execute(&mut *transaction)
+                .await?;
+
+                sqlx::query(
+                    "INSERT INTO storage_share_link_downloads (share_link_id, entry_id, client_key) VALUES ($1, $2, $3)",
+                )
+                .bind(share_link.id)
+                .bind(entry_id)
+                .bind(&client_key)
+                .execute(&mut *transaction)
+                .await?;
+            }
+        }
+
+        transaction.commit().await?;
+
+        Ok(counted)
+    }
+    .await;
+
+    match register_result {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(ShareLinkError::DownloadLimitReached),
+        Err(err) => {
+            error!(
+                "Could not update the downloads count of a share link. {}",
+                err
+            );
+
+            Err(ShareLinkError::Internal)
+        }
+    }
+}
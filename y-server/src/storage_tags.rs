@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::storage_entry::StorageError;
+use crate::util::RequestPool;
+
+pub const MAX_TAG_LENGTH: usize = 64;
+pub const MAX_PROPERTY_KEY_LENGTH: usize = 64;
+pub const MAX_PROPERTY_VALUE_LENGTH: usize = 1024;
+
+/**
+ * `SELECT` expression with the names of the tags of a `storage_entries` row, sorted by name
+ */
+pub const ENTRY_TAGS_SQL: &str = "ARRAY(
+    SELECT storage_tags.name::TEXT FROM storage_entry_tags
+    INNER JOIN storage_tags ON storage_tags.id = storage_entry_tags.tag_id
+    WHERE storage_entry_tags.entry_id = storage_entries.id ORDER BY lower(storage_tags.name)
+) AS tags";
+
+#[derive(Serialize, FromRow)]
+pub struct StorageTag {
+    pub id: i32,
+    pub name: String,
+    pub entries_count: i64,
+}
+
+#[derive(FromRow)]
+struct StorageEntryPropertyRow {
+    key: String,
+    value: String,
+}
+
+/**
+ * Trim tag names and drop duplicates (names are compared regardless of case).
+ *
+ * @returns `None` if any of the names is empty or too long
+ */
+pub fn normalize_tags(tags: &[String]) -> Option<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+
+    for tag in tags {
+        let tag = tag.trim();
+
+        if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH {
+            return None;
+        }
+
+        if !normalized
+            .iter()
+            .any(|existing| existing.to_lowercase() == tag.to_lowercase())
+        {
+            normalized.push(tag.to_string());
+        }
+    }
+
+    Some(normalized)
+}
+
+/**
+ * Check the keys and values of properties. Keys are trimmed.
+ *
+ * @returns `None` if a key is empty or too long, or a value is too long
+ */
+pub fn normalize_properties(properties: &HashMap<String, String>) -> Option<Vec<(String, String)>> {
+    properties
+        .iter()
+        .map(|(key, value)| {
+            let key = key.trim();
+
+            if key.is_empty()
+                || key.chars().count() > MAX_PROPERTY_KEY_LENGTH
+                || value.chars().count() > MAX_PROPERTY_VALUE_LENGTH
+            {
+                None
+            } else {
+                Some((key.to_string(), value.clone()))
+            }
+        })
+        .collect()
+}
+
+/**
+ * Get all the tags of an endpoint, along with the number of entries that have them
+ */
+pub async fn get_endpoint_tags(
+    endpoint_id: i32,
+    pool: &RequestPool,
+) -> Result<Vec<StorageTag>, StorageError> {
+    sqlx::query_as::<_, StorageTag>(
+        "SELECT storage_tags.id, storage_tags.name, COUNT(storage_entry_tags.entry_id) AS entries_count FROM storage_tags
+        LEFT JOIN storage_entry_tags ON storage_entry_tags.tag_id = storage_tags.id
+        WHERE storage_tags.endpoint_id = $1
+        GROUP BY storage_tags.id ORDER BY lower(storage_tags.name)",
+    )
+    .bind(endpoint_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Put tags on entries. Tags that don't exist on the endpoint yet are created.
+ *
+ * @returns number of tags that were put on entries (tags that entries already had are not counted)
+ */
+pub async fn add_entry_tags(
+    endpoint_id: i32,
+    entry_ids: &Vec<i64>,
+    tags: &Vec<String>,
+    created_by: Option<i32>,
+    pool: &RequestPool,
+) -> Result<u64, StorageError> {
+    let add_result: Result<u64, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+
+        sqlx::query(
+            "INSERT INTO storage_tags (endpoint_id, name, created_by) SELECT $1, name, $3 FROM unnest($2::TEXT[]) AS name
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(endpoint_id)
+        .bind(tags)
+        .bind(created_by)
+        .execute(&mut *transaction)
+        .await?;
+
+        let added = sqlx::query(
+            "INSERT INTO storage_entry_tags (entry_id, tag_id)
+            SELECT storage_entries.id, storage_tags.id FROM storage_entries
+            CROSS JOIN storage_tags
+            WHERE storage_entries.endpoint_id = $1 AND storage_entries.id = ANY($2)
+            AND storage_tags.endpoint_id = $1 AND lower(storage_tags.name) IN (SELECT lower(name) FROM unnest($3::TEXT[]) AS name)
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(endpoint_id)
+        .bind(entry_ids)
+        .bind(tags)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+
+        transaction.commit().await?;
+
+        Ok(added)
+    }
+    .await;
+
+    add_result.map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Take tags off entries. Tags that are not used by any entry anymore are removed from the endpoint.
+ *
+ * @returns number of tags that were taken off entries
+ */
+pub async fn remove_entry_tags(
+    endpoint_id: i32,
+    entry_ids: &Vec<i64>,
+    tags: &Vec<String>,
+    pool: &RequestPool,
+) -> Result<u64, StorageError> {
+    let remove_result: Result<u64, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+
+        let removed = sqlx::query(
+            "DELETE FROM storage_entry_tags USING storage_tags
+            WHERE storage_tags.id = storage_entry_tags.tag_id AND storage_tags.endpoint_id = $1
+            AND storage_entry_tags.entry_id = ANY($2)
+            AND lower(storage_tags.name) IN (SELECT lower(name) FROM unnest($3::TEXT[]) AS name)",
+        )
+        .bind(endpoint_id)
+        .bind(entry_ids)
+        .bind(tags)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+
+        sqlx::query(
+            "DELETE FROM storage_tags WHERE endpoint_id = $1
+            AND lower(name) IN (SELECT lower(name) FROM unnest($2::TEXT[]) AS name)
+            AND NOT EXISTS(SELECT 1 FROM storage_entry_tags WHERE tag_id = storage_tags.id)",
+        )
+        .bind(endpoint_id)
+        .bind(tags)
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(removed)
+    }
+    .await;
+
+    remove_result.map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Get the properties of an entry
+ */
+pub async fn get_entry_properties(
+    entry_id: i64,
+    pool: &RequestPool,
+) -> Result<HashMap<String, String>, StorageError> {
+    let rows = sqlx::query_as::<_, StorageEntryPropertyRow>(
+        "SELECT key, value FROM storage_entry_properties WHERE entry_id = $1",
+    )
+    .bind(entry_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    Ok(rows.into_iter().map(|row| (row.key, row.value)).collect())
+}
+
+/**
+ * Set properties of entries, overwriting the values of properties that already exist
+ */
+pub async fn set_entry_properties(
+    endpoint_id: i32,
+    entry_ids: &Vec<i64>,
+    properties: &[(String, String)],
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    let (keys, values): (Vec<String>, Vec<String>) = properties.iter().cloned().unzip();
+
+    sqlx::query(
+        "INSERT INTO storage_entry_properties (entry_id, key, value)
+        SELECT storage_entries.id, property.key, property.value FROM storage_entries
+        CROSS JOIN unnest($3::TEXT[], $4::TEXT[]) AS property(key, value)
+        WHERE storage_entries.endpoint_id = $1 AND storage_entries.id = ANY($2)
+        ON CONFLICT (entry_id, key) DO UPDATE SET value = excluded.value",
+    )
+    .bind(endpoint_id)
+    .bind(entry_ids)
+    .bind(keys)
+    .bind(values)
+    .execute(pool)
+    .await
+    .map(|_| ())
+    .map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Remove properties of entries
+ *
+ * @returns number of properties that were removed
+ */
+pub async fn remove_entry_properties(
+    endpoint_id: i32,
+    entry_ids: &Vec<i64>,
+    keys: &Vec<String>,
+    pool: &RequestPool,
+) -> Result<u64, StorageError> {
+    sqlx::query(
+        "DELETE FROM storage_entry_properties USING storage_entries
+        WHERE storage_entries.id = storage_entry_properties.entry_id AND storage_entries.endpoint_id = $1
+        AND storage_entry_properties.entry_id = ANY($2) AND storage_entry_properties.key = ANY($3)",
+    )
+    .bind(endpoint_id)
+    .bind(entry_ids)
+    .bind(keys)
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected())
+    .map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Give a copy of an entry (possibly on another endpoint) the tags and properties of the original. Tags that don't
+ * exist on the target endpoint are created.
+ */
+pub async fn copy_entry_tags(
+    source_entry_id: i64,
+    target_endpoint_id: i32,
+    target_entry_id: i64,
+    created_by: Option<i32>,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    let tags = sqlx::query_scalar::<_, String>(
+        "SELECT storage_tags.name::TEXT FROM storage_entry_tags
+        INNER JOIN storage_tags ON storage_tags.id = storage_entry_tags.tag_id
+        WHERE storage_entry_tags.entry_id = $1",
+    )
+    .bind(source_entry_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    if !tags.is_empty() {
+        add_entry_tags(
+            target_endpoint_id,
+            &vec![target_entry_id],
+            &tags,
+            created_by,
+            pool,
+        )
+        .await?;
+    }
+
+    sqlx::query(
+        "INSERT INTO storage_entry_properties (entry_id, key, value)
+        SELECT $2, key, value FROM storage_entry_properties WHERE entry_id = $1
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(source_entry_id)
+    .bind(target_entry_id)
+    .execute(pool)
+    .await
+    .map(|_| ())
+    .map_err(|_| StorageError::Internal)
+}
@@ -0,0 +1,492 @@
+use std::collections::HashMap;
+
+use log::*;
+use sqlx::{FromRow, Postgres, Transaction};
+
+use crate::config::get_config;
+use crate::storage_access::{check_endpoint_root_access, check_storage_entry_access};
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_entry::{delete_storage_blobs, StorageError};
+use crate::storage_layout::sync_endpoint_file_structure;
+use crate::user::get_group_rights;
+use crate::util::RequestPool;
+
+// How many days a trashed entry is kept around if `storage.trash.retention_days` is not set
+const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+
+#[derive(FromRow)]
+struct TrashedEntryRow {
+    entry_type: String,
+    name: String,
+    extension: Option<String>,
+    parent_folder: Option<i64>,
+}
+
+#[derive(FromRow)]
+pub struct StorageTrashItem {
+    pub id: i64,
+    pub entry_id: i64,
+    pub original_parent_folder: Option<i64>,
+}
+
+/**
+ * Move an entry (and everything inside of it, if it's a folder) to the trash of its endpoint.
+ *
+ * The rows are removed from `storage_entries`, but the blobs are left untouched, so the entry can be restored later.
+ * Must be called inside of a transaction.
+ *
+ * @returns (number of files, number of folders) that were moved to the trash. Zeroes if the entry does not exist
+ * (for example, it resides inside of a folder that was moved to the trash earlier in the same transaction).
+ */
+pub async fn move_entry_to_trash(
+    endpoint_id: i32,
+    entry_id: i64,
+    deleted_by: Option<i32>,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(i64, i64), StorageError> {
+    let entry = sqlx::query_as::<_, TrashedEntryRow>(
+        "SELECT entry_type::TEXT, name, extension, parent_folder FROM storage_entries WHERE endpoint_id = $1 AND id = $2",
+    )
+    .bind(endpoint_id)
+    .bind(entry_id)
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    let entry = match entry {
+        Some(entry) => entry,
+        None => return Ok((0, 0)),
+    };
+
+    // Remember where the entry used to be, so the user knows what they are restoring
+    let original_path = if let Some(parent_folder) = entry.parent_folder {
+        sqlx::query_scalar::<_, String>(
+            "WITH RECURSIVE folder_path AS (
+                SELECT id, parent_folder, name, 0 AS depth FROM storage_entries WHERE endpoint_id = $1 AND id = $2
+                UNION ALL
+                SELECT storage_entries.id, storage_entries.parent_folder, storage_entries.name, folder_path.depth + 1 FROM storage_entries
+                INNER JOIN folder_path ON storage_entries.id = folder_path.parent_folder
+                WHERE storage_entries.endpoint_id = $1 AND folder_path.depth < 1024
+            )
+            SELECT '/' || COALESCE(string_agg(name, '/' ORDER BY depth DESC), '') FROM folder_path",
+        )
+        .bind(endpoint_id)
+        .bind(parent_folder)
+        .fetch_one(&mut **transaction)
+        .await
+        .map_err(|_| StorageError::Internal)?
+    } else {
+        "/".to_string()
+    };
+
+    let trash_id = sqlx::query_scalar::<_, i64>(
+        "INSERT INTO storage_trash (endpoint_id, entry_id, entry_type, name, extension, original_parent_folder, original_path, deleted_by) VALUES ($1, $2, $3::storage_entry_type, $4, $5, $6, $7, $8) RETURNING id",
+    )
+    .bind(endpoint_id)
+    .bind(entry_id)
+    .bind(&entry.entry_type)
+    .bind(&entry.name)
+    .bind(&entry.extension)
+    .bind(entry.parent_folder)
+    .bind(original_path)
+    .bind(deleted_by)
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    // Take a snapshot of the entry and everything that resides inside of it
+    let trashed_count = sqlx::query_as::<_, (i64, i64)>(
+        "WITH RECURSIVE subtree AS (
+            SELECT storage_entries.* FROM storage_entries WHERE endpoint_id = $1 AND id = $2
+            UNION ALL
+            SELECT storage_entries.* FROM storage_entries
+            INNER JOIN subtree ON storage_entries.parent_folder = subtree.id
+            WHERE storage_entries.endpoint_id = $1
+        ), trashed AS (
+            INSERT INTO storage_trash_entries (trash_id, entry_id, filesystem_id, size_bytes, entry, template_ids, tags, properties)
+            SELECT $3, subtree.id, subtree.filesystem_id, subtree.size_bytes, to_jsonb(subtree),
+                ARRAY(SELECT template_id FROM storage_access_template_entries WHERE entry_endpoint_id = $1 AND entry_id = subtree.id),
+                ARRAY(
+                    SELECT storage_tags.name::TEXT FROM storage_entry_tags
+                    INNER JOIN storage_tags ON storage_tags.id = storage_entry_tags.tag_id
+                    WHERE storage_entry_tags.entry_id = subtree.id
+                ),
+                (SELECT COALESCE(jsonb_object_agg(key, value), '{}'::jsonb) FROM storage_entry_properties WHERE entry_id = subtree.id)
+            FROM subtree
+            RETURNING filesystem_id
+        )
+        SELECT COUNT(filesystem_id), COUNT(*) - COUNT(filesystem_id) FROM trashed",
+    )
+    .bind(endpoint_id)
+    .bind(entry_id)
+    .bind(trash_id)
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    sqlx::query(
+        "DELETE FROM storage_entries WHERE endpoint_id = $1 AND id IN (SELECT entry_id FROM storage_trash_entries WHERE trash_id = $2)",
+    )
+    .bind(endpoint_id)
+    .bind(trash_id)
+    .execute(&mut **transaction)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    Ok(trashed_count)
+}
+
+/**
+ * Check if a user can restore or purge a trashed entry.
+ *
+ * The `manage_trash` action is checked on the folder where the entry used to reside. If that folder does not exist
+ * anymore, the entry will be restored into the root of the endpoint, so the user needs to have access to the root.
+ */
+pub async fn check_trash_item_access(
+    endpoint_id: i32,
+    original_parent_folder: Option<i64>,
+    user_id: i32,
+    user_groups: &Vec<i32>,
+    pool: &RequestPool,
+) -> bool {
+    let restore_target =
+        get_trash_item_restore_target(endpoint_id, original_parent_folder, pool).await;
+
+    match restore_target {
+        Some(parent_folder) => {
+            check_storage_entry_access(
+                endpoint_id,
+                parent_folder,
+                "manage_trash",
+                user_id,
+                user_groups,
+                pool,
+            )
+            .await
+        }
+        None => {
+            let group_rights = get_group_rights(pool, user_groups).await;
+
+            check_endpoint_root_access(endpoint_id, group_rights)
+        }
+    }
+}
+
+/**
+ * Find the folder a trashed entry will be restored into.
+ *
+ * @returns the original parent folder if it still exists. None (root of the endpoint) otherwise.
+ */
+pub async fn get_trash_item_restore_target(
+    endpoint_id: i32,
+    original_parent_folder: Option<i64>,
+    pool: &RequestPool,
+) -> Option<i64> {
+    let parent_folder = original_parent_folder?;
+
+    let parent_folder_exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM storage_entries WHERE endpoint_id = $1 AND id = $2 AND entry_type = 'folder'::storage_entry_type)",
+    )
+    .bind(endpoint_id)
+    .bind(parent_folder)
+    .fetch_one(pool)
+    .await
+    .unwrap_or(false);
+
+    if parent_folder_exists {
+        Some(parent_folder)
+    } else {
+        None
+    }
+}
+
+pub async fn get_trash_items(
+    endpoint_id: i32,
+    trash_ids: &Vec<i64>,
+    pool: &RequestPool,
+) -> Result<Vec<StorageTrashItem>, sqlx::Error> {
+    sqlx::query_as::<_, StorageTrashItem>(
+        "SELECT id, entry_id, original_parent_folder FROM storage_trash WHERE endpoint_id = $1 AND id = ANY($2)",
+    )
+    .bind(endpoint_id)
+    .bind(trash_ids)
+    .fetch_all(pool)
+    .await
+}
+
+/**
+ * Put a trashed entry (and everything that was inside of it) back.
+ *
+ * Access rule templates that were attached to the entries are reattached (unless they were deleted in the meantime).
+ * Access rules themselves are never removed from the database when an entry is trashed, so they are preserved as is.
+ *
+ * @param target_folder folder where the entry will be restored into. See `get_trash_item_restore_target`.
+ */
+pub async fn restore_trash_item(
+    endpoint_id: i32,
+    item: &StorageTrashItem,
+    target_folder: Option<i64>,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    let transaction = pool.begin().await;
+
+    if transaction.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    let mut transaction = transaction.unwrap();
+
+    let restore_entries_result = sqlx::query(
+        "INSERT INTO storage_entries
+        SELECT (jsonb_populate_record(
+            NULL::storage_entries,
+            CASE WHEN entry_id = $2 THEN entry || jsonb_build_object('parent_folder', $3::bigint) ELSE entry END
+        )).* FROM storage_trash_entries WHERE trash_id = $1",
+    )
+    .bind(item.id)
+    .bind(item.entry_id)
+    .bind(target_folder)
+    .execute(&mut *transaction)
+    .await;
+
+    if restore_entries_result.is_err() {
+        // Most likely an entry with the same name already exists in the target folder
+        return Err(StorageError::NameConflict);
+    }
+
+    let restore_templates_result = sqlx::query(
+        "INSERT INTO storage_access_template_entries (entry_endpoint_id, entry_id, template_id)
+        SELECT $2, trashed_templates.entry_id, trashed_templates.template_id FROM (
+            SELECT entry_id, unnest(template_ids) AS template_id FROM storage_trash_entries WHERE trash_id = $1
+        ) AS trashed_templates
+        WHERE EXISTS(SELECT 1 FROM storage_access_templates WHERE id = trashed_templates.template_id)
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(item.id)
+    .bind(endpoint_id)
+    .execute(&mut *transaction)
+    .await;
+
+    if restore_templates_result.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    // Tags are stored by name, they might have been removed from the endpoint in the meantime
+    let restore_tags_result: Result<(), sqlx::Error> = async {
+        sqlx::query(
+            "INSERT INTO storage_tags (endpoint_id, name)
+            SELECT DISTINCT $2, unnest(tags) FROM storage_trash_entries WHERE trash_id = $1
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(item.id)
+        .bind(endpoint_id)
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO storage_entry_tags (entry_id, tag_id)
+            SELECT trashed_tags.entry_id, storage_tags.id FROM (
+                SELECT entry_id, unnest(tags) AS name FROM storage_trash_entries WHERE trash_id = $1
+            ) AS trashed_tags
+            INNER JOIN storage_tags ON storage_tags.endpoint_id = $2 AND lower(storage_tags.name) = lower(trashed_tags.name)
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(item.id)
+        .bind(endpoint_id)
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO storage_entry_properties (entry_id, key, value)
+            SELECT storage_trash_entries.entry_id, properties.key, properties.value
+            FROM storage_trash_entries, jsonb_each_text(storage_trash_entries.properties) AS properties
+            WHERE storage_trash_entries.trash_id = $1",
+        )
+        .bind(item.id)
+        .execute(&mut *transaction)
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    if restore_tags_result.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    let delete_trash_item_result = sqlx::query("DELETE FROM storage_trash WHERE id = $1")
+        .bind(item.id)
+        .execute(&mut *transaction)
+        .await;
+
+    if delete_trash_item_result.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    transaction
+        .commit()
+        .await
+        .map_err(|_| StorageError::Internal)?;
+
+    sync_endpoint_file_structure(endpoint_id, &vec![item.entry_id], pool).await;
+
+    Ok(())
+}
+
+/**
+ * Permanently delete trashed entries, their blobs, artifacts and previous versions.
+ *
+ * @returns number of files that were deleted.
+ */
+pub async fn purge_trash_items(
+    endpoint_id: i32,
+    trash_ids: &Vec<i64>,
+    pool: &RequestPool,
+) -> Result<usize, StorageError> {
+    let target_endpoint = get_storage_endpoint(endpoint_id, pool).await;
+
+    if target_endpoint.is_err() {
+        return Err(StorageError::EndpointNotFound);
+    }
+
+    let target_endpoint = target_endpoint.unwrap();
+
+    let transaction = pool.begin().await;
+
+    if transaction.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    let mut transaction = transaction.unwrap();
+
+    // Access rules and share links are kept while an entry is in the trash, now they are not needed anymore
+    let delete_access_rules_result = sqlx::query(
+        "DELETE FROM storage_access WHERE endpoint_id = $1 AND entry_id IN (SELECT entry_id FROM storage_trash_entries WHERE trash_id = ANY($2))",
+    )
+    .bind(endpoint_id)
+    .bind(trash_ids)
+    .execute(&mut *transaction)
+    .await;
+
+    if delete_access_rules_result.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    let delete_share_links_result = sqlx::query(
+        "DELETE FROM storage_share_links WHERE endpoint_id = $1 AND entry_id IN (SELECT entry_id FROM storage_trash_entries WHERE trash_id = ANY($2))",
+    )
+    .bind(endpoint_id)
+    .bind(trash_ids)
+    .execute(&mut *transaction)
+    .await;
+
+    if delete_share_links_result.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    let file_filesystem_ids = sqlx::query_scalar::<_, String>(
+        "SELECT filesystem_id FROM storage_trash_entries WHERE trash_id IN (SELECT id FROM storage_trash WHERE endpoint_id = $1 AND id = ANY($2)) AND filesystem_id IS NOT NULL",
+    )
+    .bind(endpoint_id)
+    .bind(trash_ids)
+    .fetch_all(&mut *transaction)
+    .await;
+
+    if file_filesystem_ids.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    let file_filesystem_ids = file_filesystem_ids.unwrap();
+
+    // Previous versions of the purged files are not needed anymore either
+    let version_filesystem_ids = sqlx::query_scalar::<_, String>(
+        "DELETE FROM storage_entry_versions WHERE endpoint_id = $1 AND entry_id IN (SELECT entry_id FROM storage_trash_entries WHERE trash_id IN (SELECT id FROM storage_trash WHERE endpoint_id = $1 AND id = ANY($2))) RETURNING filesystem_id",
+    )
+    .bind(endpoint_id)
+    .bind(trash_ids)
+    .fetch_all(&mut *transaction)
+    .await;
+
+    if version_filesystem_ids.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    let version_filesystem_ids = version_filesystem_ids.unwrap();
+
+    let delete_trash_items_result =
+        sqlx::query("DELETE FROM storage_trash WHERE endpoint_id = $1 AND id = ANY($2)")
+            .bind(endpoint_id)
+            .bind(trash_ids)
+            .execute(&mut *transaction)
+            .await;
+
+    if delete_trash_items_result.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    if transaction.commit().await.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    delete_storage_blobs(&target_endpoint, &file_filesystem_ids, pool).await?;
+    delete_storage_blobs(&target_endpoint, &version_filesystem_ids, pool).await?;
+
+    Ok(file_filesystem_ids.len())
+}
+
+pub async fn cleanup_storage_trash(pool: &RequestPool) {
+    info!("[scheduled] Purging expired trash...");
+
+    let storage_config = get_config(pool).await;
+
+    let retention_days = storage_config
+        .get("storage.trash.retention_days")
+        .and_then(|value| value.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS);
+
+    // 0 == keep trashed entries until they are purged manually
+    if retention_days == 0 {
+        return;
+    }
+
+    #[derive(FromRow)]
+    struct ExpiredTrashItem {
+        id: i64,
+        endpoint_id: i32,
+    }
+
+    let expired_items = sqlx::query_as::<_, ExpiredTrashItem>(
+        "SELECT id, endpoint_id FROM storage_trash WHERE deleted_at < now() - make_interval(days => $1)",
+    )
+    .bind(retention_days as i32)
+    .fetch_all(pool)
+    .await;
+
+    match expired_items {
+        Ok(expired_items) => {
+            let mut endpoint_items: HashMap<i32, Vec<i64>> = HashMap::new();
+
+            for item in expired_items {
+                endpoint_items
+                    .entry(item.endpoint_id)
+                    .or_default()
+                    .push(item.id);
+            }
+
+            for (endpoint_id, trash_ids) in endpoint_items {
+                if purge_trash_items(endpoint_id, &trash_ids, pool)
+                    .await
+                    .is_err()
+                {
+                    error!("Could not purge expired trash of endpoint {}", endpoint_id);
+                }
+            }
+        }
+        Err(error) => {
+            error!(
+                "Could not get expired trash items from the database. {}",
+                error
+            );
+        }
+    }
+}
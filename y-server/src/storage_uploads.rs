@@ -0,0 +1,331 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use log::*;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::storage_access::{check_endpoint_root_access, check_storage_entry_access};
+use crate::storage_backend::get_storage_backend;
+use crate::storage_blobs::{hash_local_file, release_stored_blob, store_staged_blob};
+use crate::storage_endpoint::{get_storage_endpoint, StorageEndpointRow};
+use crate::storage_entry::{delete_storage_blobs, StorageError};
+use crate::storage_layout::sync_endpoint_file_structure;
+use crate::user::{get_group_rights, get_user_groups};
+use crate::util::RequestPool;
+
+/// Version of the tus.io resumable upload protocol we implement
+pub const TUS_VERSION: &str = "1.0.0";
+
+/// Extensions of the tus.io protocol we support
+pub const TUS_EXTENSIONS: &str = "creation,termination,expiration";
+
+/// Upload sessions that a request is currently appending to. The staged file lives on this server, so there is no need to
+/// lock anything in the database.
+static UPLOAD_SESSIONS_IN_USE: Mutex<BTreeSet<Uuid>> = Mutex::new(BTreeSet::new());
+
+// TODO configurable value
+pub const UPLOAD_SESSION_LIFETIME_HOURS: i64 = 24;
+
+#[allow(dead_code)]
+#[derive(FromRow)]
+pub struct StorageUploadSession {
+    pub id: Uuid,
+
+    pub endpoint_id: i32,
+    pub target_folder: Option<i64>,
+
+    pub name: String,
+    pub extension: Option<String>,
+
+    pub size_bytes: i64,
+    pub received_bytes: i64,
+
+    pub created_by: i32,
+    pub created_at: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+}
+
+/// Where the parts of a file are stored while it's being uploaded
+pub fn get_upload_session_staging_path(session_id: &Uuid) -> PathBuf {
+    Path::new("upload_staging").join(session_id.to_string())
+}
+
+/// Start a tus.io response. Every response (except for OPTIONS) must have the `Tus-Resumable` header
+pub fn tus_response(status: StatusCode) -> HttpResponseBuilder {
+    let mut response = HttpResponse::build(status);
+    response.insert_header(("Tus-Resumable", TUS_VERSION));
+
+    response
+}
+
+/**
+ * Parse the `Upload-Metadata` header of a tus.io request.
+ *
+ * The header is a comma separated list of key-value pairs. Keys and values are separated by a space,
+ * values are base64 encoded. Values can be omitted.
+ */
+pub fn parse_tus_metadata(header_value: &str) -> HashMap<String, String> {
+    let mut metadata: HashMap<String, String> = HashMap::new();
+
+    for pair in header_value.split(',') {
+        let mut pair = pair.trim().splitn(2, ' ');
+
+        if let Some(key) = pair.next() {
+            if key.is_empty() {
+                continue;
+            }
+
+            let value = pair
+                .next()
+                .and_then(|value| BASE64_STANDARD.decode(value.trim()).ok())
+                .and_then(|value| String::from_utf8(value).ok())
+                .unwrap_or_default();
+
+            metadata.insert(key.to_string(), value);
+        }
+    }
+
+    metadata
+}
+
+/**
+ * Check if a user can upload files into a folder.
+ *
+ * @param target_folder - Folder id. `None` means the root of the endpoint
+ */
+pub async fn check_upload_access(
+    endpoint_id: i32,
+    target_folder: Option<i64>,
+    user_id: i32,
+    pool: &RequestPool,
+) -> bool {
+    let user_groups = get_user_groups(pool, user_id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    if let Some(target_folder) = target_folder {
+        check_storage_entry_access(
+            endpoint_id,
+            target_folder,
+            "upload",
+            user_id,
+            &group_ids,
+            pool,
+        )
+        .await
+    } else {
+        let group_rights = get_group_rights(pool, &group_ids).await;
+
+        check_endpoint_root_access(endpoint_id, group_rights)
+    }
+}
+
+/// Find an active (not expired) upload session that belongs to a user
+pub async fn get_upload_session(
+    session_id: &Uuid,
+    user_id: i32,
+    pool: &RequestPool,
+) -> Result<StorageUploadSession, sqlx::Error> {
+    sqlx::query_as::<_, StorageUploadSession>(
+        "SELECT * FROM storage_upload_sessions WHERE id = $1 AND created_by = $2 AND expires_on > now()",
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Makes sure only one request at a time appends to an upload session, see `try_lock_upload_session`
+pub struct UploadSessionLock {
+    session_id: Uuid,
+}
+
+impl Drop for UploadSessionLock {
+    fn drop(&mut self) {
+        UPLOAD_SESSIONS_IN_USE
+            .lock()
+            .unwrap()
+            .remove(&self.session_id);
+    }
+}
+
+/// Lock an upload session for as long as the returned value is alive.
+///
+/// Returns `None` if another request is already holding the lock.
+pub fn try_lock_upload_session(session_id: &Uuid) -> Option<UploadSessionLock> {
+    if !UPLOAD_SESSIONS_IN_USE.lock().unwrap().insert(*session_id) {
+        return None;
+    }
+
+    Some(UploadSessionLock {
+        session_id: *session_id,
+    })
+}
+
+/// Delete an upload session, along with all the parts of the file that were uploaded so far
+pub async fn delete_upload_session(session_id: &Uuid, pool: &RequestPool) {
+    let delete_result = sqlx::query("DELETE FROM storage_upload_sessions WHERE id = $1")
+        .bind(session_id)
+        .execute(pool)
+        .await;
+
+    if let Err(error) = delete_result {
+        error!(
+            "(storage uploads -> delete upload session) Could not delete an upload session. {}",
+            error
+        );
+    }
+
+    fs::remove_file(get_upload_session_staging_path(session_id)).unwrap_or(());
+}
+
+/**
+ * Turn a fully uploaded file into a storage entry.
+ *
+ * The file is moved from the staging folder into the endpoint's storage backend, a new storage entry is created for it and
+ * the upload session gets deleted (even if we fail to create the entry, as there is no way to recover from that).
+ *
+ * @returns (filesystem id of the new file, target endpoint)
+ */
+pub async fn finalize_upload_session(
+    session: &StorageUploadSession,
+    pool: &RequestPool,
+) -> Result<(String, StorageEndpointRow), StorageError> {
+    let target_endpoint = get_storage_endpoint(session.endpoint_id, pool).await;
+
+    if target_endpoint.is_err() {
+        delete_upload_session(&session.id, pool).await;
+
+        return Err(StorageError::EndpointNotFound);
+    }
+
+    let target_endpoint = target_endpoint.unwrap();
+
+    let storage_backend = get_storage_backend(&target_endpoint, pool).await;
+
+    if storage_backend.is_err() {
+        delete_upload_session(&session.id, pool).await;
+
+        return Err(StorageError::EndpointNotFound);
+    }
+
+    let storage_backend = storage_backend.unwrap();
+
+    let staging_path = get_upload_session_staging_path(&session.id);
+    let staged_filesystem_id = Uuid::new_v4().to_string();
+    let file_path = storage_backend.staging_path(&staged_filesystem_id);
+
+    // The staging folder might reside on a different filesystem, in which case we can not just rename the file
+    if fs::rename(&staging_path, &file_path).is_err() {
+        if fs::copy(&staging_path, &file_path).is_err() {
+            fs::remove_file(&file_path).unwrap_or(());
+            delete_upload_session(&session.id, pool).await;
+
+            return Err(StorageError::Internal);
+        }
+
+        fs::remove_file(&staging_path).unwrap_or(());
+    }
+
+    let file_mime_type = infer::get_from_path(&file_path)
+        .ok()
+        .flatten()
+        .map(|kind| kind.mime_type());
+
+    // The file was uploaded in many separate requests, so it's hashed only once it's complete
+    let file_sha256 = hash_local_file(&file_path);
+
+    if file_sha256.is_err() {
+        fs::remove_file(&file_path).unwrap_or(());
+        delete_upload_session(&session.id, pool).await;
+
+        return Err(StorageError::Internal);
+    }
+
+    let file_sha256 = file_sha256.unwrap();
+
+    let stored_blob = store_staged_blob(
+        &target_endpoint,
+        storage_backend.as_ref(),
+        &staged_filesystem_id,
+        &file_sha256,
+        session.size_bytes,
+        pool,
+    )
+    .await;
+
+    if stored_blob.is_err() {
+        fs::remove_file(&file_path).unwrap_or(());
+        delete_upload_session(&session.id, pool).await;
+
+        return Err(StorageError::Internal);
+    }
+
+    let stored_blob = stored_blob.unwrap();
+    let file_filesystem_id = stored_blob.filesystem_id.clone();
+
+    let create_file_result = sqlx::query_scalar::<_, i64>("INSERT INTO storage_entries (endpoint_id, filesystem_id, parent_folder, name, extension, mime_type, size_bytes, sha256, created_by, created_at, entry_type) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now(), 'file'::storage_entry_type) RETURNING id")
+        .bind(session.endpoint_id)
+        .bind(&file_filesystem_id)
+        .bind(session.target_folder)
+        .bind(&session.name)
+        .bind(&session.extension)
+        .bind(file_mime_type)
+        .bind(session.size_bytes)
+        .bind(&file_sha256)
+        .bind(session.created_by)
+        .fetch_one(pool)
+        .await;
+
+    release_stored_blob(&stored_blob, pool).await;
+    delete_upload_session(&session.id, pool).await;
+
+    if create_file_result.is_err() {
+        // Most likely a file with the same name already exists in the target folder.
+        // A reused blob belongs to other files, it is kept
+        delete_storage_blobs(&target_endpoint, &vec![file_filesystem_id], pool)
+            .await
+            .unwrap_or(());
+
+        return Err(StorageError::NameConflict);
+    }
+
+    sync_endpoint_file_structure(
+        session.endpoint_id,
+        &vec![create_file_result.unwrap()],
+        pool,
+    )
+    .await;
+
+    Ok((file_filesystem_id, target_endpoint))
+}
+
+pub async fn cleanup_storage_upload_sessions(pool: &RequestPool) {
+    info!("[scheduled] Cleaning up expired upload sessions...");
+
+    let expired_sessions = sqlx::query_scalar::<_, Uuid>(
+        "DELETE FROM storage_upload_sessions WHERE expires_on < now() RETURNING id",
+    )
+    .fetch_all(pool)
+    .await;
+
+    match expired_sessions {
+        Ok(expired_sessions) => {
+            for session_id in expired_sessions.iter() {
+                fs::remove_file(get_upload_session_staging_path(session_id)).unwrap_or(());
+            }
+        }
+        Err(error) => {
+            error!(
+                "Could not delete expired upload sessions from the database. {}",
+                error
+            );
+        }
+    }
+}
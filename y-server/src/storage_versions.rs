@@ -0,0 +1,387 @@
+use log::*;
+use serde::Serialize;
+use sqlx::{FromRow, Postgres, Transaction};
+
+use crate::storage_blobs::is_blob_used_by_other_files;
+use crate::storage_endpoint::StorageEndpointRow;
+use crate::storage_entry::{delete_file_artifacts, delete_storage_blobs, StorageError};
+use crate::storage_layout::sync_endpoint_file_structure;
+use crate::util::RequestPool;
+
+#[derive(FromRow, Serialize)]
+pub struct StorageEntryVersion {
+    pub id: i64,
+    pub mime_type: Option<String>,
+    pub size_bytes: Option<i64>,
+    pub sha256: Option<String>,
+    pub created_by: Option<i32>,
+    pub created_by_username: Option<String>,
+    pub created_at: Option<String>,
+    pub archived_at: String,
+}
+
+#[derive(FromRow)]
+pub struct StorageEntryVersionBlob {
+    pub filesystem_id: String,
+    pub mime_type: Option<String>,
+    pub size_bytes: Option<i64>,
+    pub sha256: Option<String>,
+}
+
+/**
+ * Record a blob as a previous version of a file. Mime type, size and authorship are copied from the file as it is right now.
+ *
+ * Must be called inside of a transaction.
+ */
+async fn insert_entry_version(
+    endpoint_id: i32,
+    entry_id: i64,
+    filesystem_id: &str,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), StorageError> {
+    let insert_result = sqlx::query(
+        "INSERT INTO storage_entry_versions (endpoint_id, entry_id, filesystem_id, mime_type, size_bytes, sha256, created_by, created_at)
+        SELECT endpoint_id, id, $3, mime_type, size_bytes, sha256, created_by, created_at FROM storage_entries
+        WHERE endpoint_id = $1 AND id = $2 AND entry_type = 'file'::storage_entry_type",
+    )
+    .bind(endpoint_id)
+    .bind(entry_id)
+    .bind(filesystem_id)
+    .execute(&mut **transaction)
+    .await;
+
+    match insert_result {
+        Ok(result) if result.rows_affected() == 1 => Ok(()),
+        _ => Err(StorageError::Internal),
+    }
+}
+
+/**
+ * Replace the contents of a file with a new blob.
+ *
+ * If versioning is enabled for the endpoint, the previous blob is kept as a version of the file.
+ * Otherwise, it is deleted from the endpoint. Old versions that exceed the endpoint's limit are deleted as well.
+ * Artifacts of the previous blob are always deleted, they have to be generated for the new one.
+ *
+ * The new blob must already be committed to the endpoint.
+ */
+pub async fn replace_entry_blob(
+    target_endpoint: &StorageEndpointRow,
+    entry_id: i64,
+    new_blob: &StorageEntryVersionBlob,
+    created_by: Option<i32>,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    let transaction = pool.begin().await;
+
+    if transaction.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    let mut transaction = transaction.unwrap();
+
+    let previous_filesystem_id = replace_entry_blob_in_transaction(
+        target_endpoint,
+        entry_id,
+        new_blob,
+        created_by,
+        &mut transaction,
+    )
+    .await?;
+
+    if transaction.commit().await.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    finish_entry_blob_replacement(
+        target_endpoint,
+        entry_id,
+        &previous_filesystem_id,
+        &new_blob.filesystem_id,
+        pool,
+    )
+    .await;
+
+    Ok(())
+}
+
+/**
+ * Point a file to a new blob, keeping the previous one as a version if versioning is enabled for the endpoint.
+ *
+ * @returns filesystem_id of the blob that was replaced
+ */
+async fn replace_entry_blob_in_transaction(
+    target_endpoint: &StorageEndpointRow,
+    entry_id: i64,
+    new_blob: &StorageEntryVersionBlob,
+    created_by: Option<i32>,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<String, StorageError> {
+    let endpoint_id = target_endpoint.id;
+
+    let previous_filesystem_id = sqlx::query_scalar::<_, String>(
+        "SELECT filesystem_id FROM storage_entries WHERE endpoint_id = $1 AND id = $2 AND entry_type = 'file'::storage_entry_type FOR UPDATE",
+    )
+    .bind(endpoint_id)
+    .bind(entry_id)
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    if previous_filesystem_id.is_none() {
+        return Err(StorageError::Internal);
+    }
+
+    let previous_filesystem_id = previous_filesystem_id.unwrap();
+
+    // The same contents were uploaded again and deduplicated into the blob the file already has
+    let blob_changed = previous_filesystem_id != new_blob.filesystem_id;
+
+    if target_endpoint.max_file_versions > 0 && blob_changed {
+        insert_entry_version(endpoint_id, entry_id, &previous_filesystem_id, transaction).await?;
+    }
+
+    let update_result = sqlx::query(
+        "UPDATE storage_entries SET filesystem_id = $1, mime_type = $2, size_bytes = $3, sha256 = $4, created_by = $5, created_at = now(), transcoded_version_available = CASE WHEN filesystem_id = $1 THEN transcoded_version_available ELSE NULL END WHERE endpoint_id = $6 AND id = $7",
+    )
+    .bind(&new_blob.filesystem_id)
+    .bind(&new_blob.mime_type)
+    .bind(new_blob.size_bytes)
+    .bind(&new_blob.sha256)
+    .bind(created_by)
+    .bind(endpoint_id)
+    .bind(entry_id)
+    .execute(&mut **transaction)
+    .await;
+
+    if update_result.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    Ok(previous_filesystem_id)
+}
+
+/**
+ * Clean up after a file's blob was replaced. Must be called once the transaction is committed.
+ *
+ * Errors are logged and otherwise ignored, the file itself is already updated at this point.
+ */
+async fn finish_entry_blob_replacement(
+    target_endpoint: &StorageEndpointRow,
+    entry_id: i64,
+    previous_filesystem_id: &str,
+    new_filesystem_id: &str,
+    pool: &RequestPool,
+) {
+    if previous_filesystem_id == new_filesystem_id {
+        return;
+    }
+
+    // The previous blob has to be moved out of the file's path before it can be kept or deleted
+    sync_endpoint_file_structure(target_endpoint.id, &vec![entry_id], pool).await;
+
+    let cleanup_result = if target_endpoint.max_file_versions > 0 {
+        // Versions don't have artifacts, they are generated again if a version is restored.
+        // Deduplicated blobs share their artifacts with other files though
+        if let Some(artifacts_path) = &target_endpoint.artifacts_path {
+            let blob_in_use = is_blob_used_by_other_files(
+                target_endpoint.id,
+                previous_filesystem_id,
+                entry_id,
+                pool,
+            )
+            .await
+            .unwrap_or(true);
+
+            if !blob_in_use {
+                delete_file_artifacts(target_endpoint.id, artifacts_path, previous_filesystem_id);
+            }
+        }
+
+        prune_entry_versions(target_endpoint, entry_id, pool).await
+    } else {
+        // Versioning is disabled, nothing references the previous blob anymore
+        delete_storage_blobs(
+            target_endpoint,
+            &vec![previous_filesystem_id.to_string()],
+            pool,
+        )
+        .await
+    };
+
+    if cleanup_result.is_err() {
+        error!(
+            "(storage versions) Could not clean up after replacing the contents of a file. endpoint_id = {}. entry_id = {}.",
+            target_endpoint.id, entry_id
+        );
+    }
+}
+
+/**
+ * Keep a copy of a file's current contents as a version. Used when a file is about to be modified in place.
+ *
+ * @param filesystem_id blob that holds the copy. Must already be committed to the endpoint.
+ */
+pub async fn snapshot_entry_version(
+    target_endpoint: &StorageEndpointRow,
+    entry_id: i64,
+    filesystem_id: &str,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    let transaction = pool.begin().await;
+
+    if transaction.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    let mut transaction = transaction.unwrap();
+
+    insert_entry_version(
+        target_endpoint.id,
+        entry_id,
+        filesystem_id,
+        &mut transaction,
+    )
+    .await?;
+
+    if transaction.commit().await.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    if prune_entry_versions(target_endpoint, entry_id, pool)
+        .await
+        .is_err()
+    {
+        error!(
+            "(storage versions) Could not prune old versions of a file. endpoint_id = {}. entry_id = {}.",
+            target_endpoint.id, entry_id
+        );
+    }
+
+    Ok(())
+}
+
+/**
+ * Make a previous version of a file current again.
+ *
+ * The contents the file has right now are kept as a version (if versioning is enabled), so restoring can be undone.
+ */
+pub async fn restore_entry_version(
+    target_endpoint: &StorageEndpointRow,
+    entry_id: i64,
+    version_id: i64,
+    restored_by: Option<i32>,
+    pool: &RequestPool,
+) -> Result<String, StorageError> {
+    let transaction = pool.begin().await;
+
+    if transaction.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    let mut transaction = transaction.unwrap();
+
+    let version = sqlx::query_as::<_, StorageEntryVersionBlob>(
+        "DELETE FROM storage_entry_versions WHERE endpoint_id = $1 AND entry_id = $2 AND id = $3 RETURNING filesystem_id, mime_type, size_bytes, sha256",
+    )
+    .bind(target_endpoint.id)
+    .bind(entry_id)
+    .bind(version_id)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    if version.is_none() {
+        return Err(StorageError::Internal);
+    }
+
+    let version = version.unwrap();
+
+    let previous_filesystem_id = replace_entry_blob_in_transaction(
+        target_endpoint,
+        entry_id,
+        &version,
+        restored_by,
+        &mut transaction,
+    )
+    .await?;
+
+    if transaction.commit().await.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    finish_entry_blob_replacement(
+        target_endpoint,
+        entry_id,
+        &previous_filesystem_id,
+        &version.filesystem_id,
+        pool,
+    )
+    .await;
+
+    Ok(version.filesystem_id)
+}
+
+/**
+ * Delete the oldest versions of a file that exceed the endpoint's limit, together with their blobs.
+ */
+pub async fn prune_entry_versions(
+    target_endpoint: &StorageEndpointRow,
+    entry_id: i64,
+    pool: &RequestPool,
+) -> Result<(), StorageError> {
+    let pruned_filesystem_ids = sqlx::query_scalar::<_, String>(
+        "DELETE FROM storage_entry_versions WHERE id IN (
+            SELECT id FROM storage_entry_versions WHERE endpoint_id = $1 AND entry_id = $2
+            ORDER BY archived_at DESC, id DESC OFFSET $3
+        ) RETURNING filesystem_id",
+    )
+    .bind(target_endpoint.id)
+    .bind(entry_id)
+    .bind(target_endpoint.max_file_versions.max(0) as i64)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageError::Internal)?;
+
+    if pruned_filesystem_ids.is_empty() {
+        return Ok(());
+    }
+
+    delete_storage_blobs(target_endpoint, &pruned_filesystem_ids, pool).await
+}
+
+pub async fn get_entry_versions(
+    endpoint_id: i32,
+    entry_id: i64,
+    pool: &RequestPool,
+) -> Result<Vec<StorageEntryVersion>, sqlx::Error> {
+    sqlx::query_as::<_, StorageEntryVersion>(
+        "SELECT storage_entry_versions.id, storage_entry_versions.mime_type, storage_entry_versions.size_bytes, storage_entry_versions.sha256,
+        storage_entry_versions.created_by, users.username AS created_by_username,
+        storage_entry_versions.created_at::TEXT, storage_entry_versions.archived_at::TEXT
+        FROM storage_entry_versions
+        LEFT JOIN users ON users.id = storage_entry_versions.created_by
+        WHERE storage_entry_versions.endpoint_id = $1 AND storage_entry_versions.entry_id = $2
+        ORDER BY storage_entry_versions.archived_at DESC, storage_entry_versions.id DESC",
+    )
+    .bind(endpoint_id)
+    .bind(entry_id)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_entry_version_blob(
+    endpoint_id: i32,
+    entry_id: i64,
+    version_id: i64,
+    pool: &RequestPool,
+) -> Result<StorageEntryVersionBlob, sqlx::Error> {
+    sqlx::query_as::<_, StorageEntryVersionBlob>(
+        "SELECT filesystem_id, mime_type, size_bytes, sha256 FROM storage_entry_versions WHERE endpoint_id = $1 AND entry_id = $2 AND id = $3",
+    )
+    .bind(endpoint_id)
+    .bind(entry_id)
+    .bind(version_id)
+    .fetch_one(pool)
+    .await
+}
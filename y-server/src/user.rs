@@ -1,9 +1,12 @@
+use actix_web::cookie::Cookie;
+use actix_web::http::header;
 use actix_web::HttpRequest;
 use chrono::NaiveDateTime as Timestamp;
 use chrono::Utc;
 use serde_json::Value;
 use uuid::Uuid;
 
+use crate::user_api_tokens::{check_api_token_scopes, filter_rights_by_scopes, touch_api_token};
 use crate::user_group::UserGroup;
 use crate::util::RequestPool;
 use log::*;
@@ -36,9 +39,24 @@ pub struct UserSession {
 
     pub created_at: Timestamp,
     pub expires_on: Option<Timestamp>,
+
+    /**
+     * Set for personal API tokens, `None` for browser sessions
+     */
+    pub token_name: Option<String>,
+    pub token_scopes: Option<Vec<String>>,
+
+    pub last_used_at: Option<Timestamp>,
+    pub last_used_ip: Option<String>,
+}
+
+impl UserSession {
+    pub fn is_api_token(&self) -> bool {
+        self.token_name.is_some()
+    }
 }
 
-fn generate_session_secrets() -> (Uuid, String) {
+pub fn generate_session_secrets() -> (Uuid, String) {
     use rand::distributions::Alphanumeric;
     use rand::{thread_rng, Rng};
 
@@ -76,6 +94,20 @@ pub async fn create_user_session(
         .await
 }
 
+/**
+ * The `y-session` cookie that browsers are authenticated with
+ */
+pub fn build_session_cookie(session: &UserSession) -> Cookie<'static> {
+    Cookie::build(
+        "y-session",
+        format!("{}:{}", session.session_id, session.session_key),
+    )
+    .secure(false)
+    .http_only(true)
+    .path("/")
+    .finish()
+}
+
 #[derive(sqlx::FromRow)]
 struct UserWithSession {
     pub id: i32,
@@ -88,53 +120,154 @@ struct UserWithSession {
     pub session_key: String,
     pub session_created_at: Timestamp,
     pub session_expires_on: Option<Timestamp>,
+
+    pub token_name: Option<String>,
+    pub token_scopes: Option<Vec<String>>,
+    pub last_used_at: Option<Timestamp>,
+    pub last_used_ip: Option<String>,
 }
 
+/**
+ * Get the client of a request.
+ *
+ * Browsers are authenticated with the `y-session` cookie, scripts with a personal API token in the
+ * `Authorization: Bearer` header. Requests that are outside of the scopes of their token are treated as anonymous.
+ */
 pub async fn get_user_from_request(
     pool: &RequestPool,
     req: &HttpRequest,
 ) -> Option<(User, UserSession)> {
-    let session_cookie = req.cookie("y-session");
-
-    if let Some(session_cookie) = session_cookie {
-        let cookie_value = session_cookie.value();
-        let cookie_parts: Vec<&str> = cookie_value.split(':').collect();
-
-        if cookie_parts.len() == 2 {
-            let (session_id, session_key) = (Uuid::parse_str(cookie_parts[0]), cookie_parts[1]);
-
-            if let Ok(session_id) = session_id {
-                let user_with_session = sqlx::query_as::<_, UserWithSession>(
-                    "SELECT user_sessions.id, user_sessions.session_id, user_sessions.session_key, user_sessions.user_id, user_sessions.created_at as session_created_at, user_sessions.expires_on as session_expires_on, users.username, users.created_at as user_created_at FROM user_sessions INNER JOIN users ON user_sessions.user_id = users.id WHERE user_sessions.session_id = $1 AND user_sessions.session_key = $2"
-                )
-                .bind(session_id)
-                .bind(session_key)
-                .fetch_one(pool)
-                .await;
-
-                if let Ok(user_with_session) = user_with_session {
-                    if let Some(expires_on) = user_with_session.session_expires_on {
-                        if Utc::now().naive_utc() > expires_on {
-                            return None;
-                        }
+    if let Some(session_cookie) = req.cookie("y-session") {
+        return get_user_from_session_secrets(pool, session_cookie.value(), false).await;
+    }
+
+    let api_token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    if let Some(api_token) = api_token {
+        return get_user_from_api_token(pool, api_token, req).await;
+    }
+
+    None
+}
+
+/**
+ * Get the client of a personal API token. Requests that are outside of the scopes of the token are treated as
+ * anonymous.
+ *
+ * @param api_token `<session_id>:<session_key>`
+ */
+pub async fn get_user_from_api_token(
+    pool: &RequestPool,
+    api_token: &str,
+    req: &HttpRequest,
+) -> Option<(User, UserSession)> {
+    let client = get_user_from_session_secrets(pool, api_token.trim(), true).await;
+
+    if let Some((_, session)) = &client {
+        if !check_api_token_scopes(session.token_scopes.as_deref().unwrap_or(&[]), req) {
+            return None;
+        }
+
+        touch_api_token(session, req, pool).await;
+    }
+
+    client
+}
+
+/**
+ * @param secrets `<session_id>:<session_key>`
+ * @param api_token look for a personal API token instead of a browser session
+ */
+async fn get_user_from_session_secrets(
+    pool: &RequestPool,
+    secrets: &str,
+    api_token: bool,
+) -> Option<(User, UserSession)> {
+    let secrets_parts: Vec<&str> = secrets.split(':').collect();
+
+    if secrets_parts.len() == 2 {
+        let (session_id, session_key) = (Uuid::parse_str(secrets_parts[0]), secrets_parts[1]);
+
+        if let Ok(session_id) = session_id {
+            let user_with_session = sqlx::query_as::<_, UserWithSession>(
+                "SELECT user_sessions.id, user_sessions.session_id, user_sessions.session_key, user_sessions.user_id, user_sessions.created_at as session_created_at, user_sessions.expires_on as session_expires_on, user_sessions.token_name, user_sessions.token_scopes, user_sessions.last_used_at, user_sessions.last_used_ip, users.username, users.created_at as user_created_at FROM user_sessions INNER JOIN users ON user_sessions.user_id = users.id WHERE user_sessions.session_id = $1 AND user_sessions.session_key = $2 AND (user_sessions.token_name IS NOT NULL) = $3"
+            )
+            .bind(session_id)
+            .bind(session_key)
+            .bind(api_token)
+            .fetch_one(pool)
+            .await;
+
+            if let Ok(user_with_session) = user_with_session {
+                if let Some(expires_on) = user_with_session.session_expires_on {
+                    if Utc::now().naive_utc() > expires_on {
+                        return None;
                     }
+                }
+
+                return Some((
+                    User {
+                        created_at: user_with_session.user_created_at,
+                        id: user_with_session.user_id,
+                        password: None,
+                        username: user_with_session.username,
+                    },
+                    UserSession {
+                        id: user_with_session.id,
+                        created_at: user_with_session.session_created_at,
+                        expires_on: user_with_session.session_expires_on,
+                        session_id: user_with_session.session_id,
+                        session_key: user_with_session.session_key,
+                        user_id: user_with_session.user_id,
+                        token_name: user_with_session.token_name,
+                        token_scopes: user_with_session.token_scopes,
+                        last_used_at: user_with_session.last_used_at,
+                        last_used_ip: user_with_session.last_used_ip,
+                    },
+                ));
+            }
+        }
+    }
 
-                    return Some((
-                        User {
-                            created_at: user_with_session.user_created_at,
-                            id: user_with_session.user_id,
-                            password: None,
-                            username: user_with_session.username,
-                        },
-                        UserSession {
-                            id: user_with_session.id,
-                            created_at: user_with_session.session_created_at,
-                            expires_on: user_with_session.session_expires_on,
-                            session_id: user_with_session.session_id,
-                            session_key: user_with_session.session_key,
-                            user_id: user_with_session.user_id,
-                        },
-                    ));
+    None
+}
+
+/**
+ * Find a user by their username and verify the provided password against the stored PBKDF2 hash.
+ *
+ * Used by clients that can not hold a `y-session` cookie and authenticate on every request instead
+ * (HTTP Basic authentication in WebDAV clients, for example).
+ *
+ * @returns the user if the password matches. None if the user does not exist, has no password set
+ * (authentication forbidden) or the password does not match.
+ */
+pub async fn verify_user_password(
+    pool: &RequestPool,
+    username: &str,
+    password: &str,
+) -> Option<User> {
+    use pbkdf2::{
+        password_hash::{PasswordHash, PasswordVerifier},
+        Pbkdf2,
+    };
+
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_one(pool)
+        .await;
+
+    if let Ok(user) = user {
+        if let Some(password_hash) = &user.password {
+            if let Ok(parsed_password_hash) = PasswordHash::new(password_hash) {
+                if Pbkdf2
+                    .verify_password(password.as_bytes(), &parsed_password_hash)
+                    .is_ok()
+                {
+                    return Some(user);
                 }
             }
         }
@@ -188,6 +321,10 @@ pub async fn get_group_rights(pool: &RequestPool, group_ids: &Vec<i32>) -> Vec<U
 pub async fn get_client_rights(pool: &RequestPool, req: &HttpRequest) -> Vec<UserRight> {
     let client_session = get_user_from_request(&pool, &req).await;
 
+    let token_scopes = client_session
+        .as_ref()
+        .and_then(|(_, session)| session.token_scopes.clone());
+
     // ! TODO refactor the query
     let right_rows = if let Some((user, _)) = client_session {
         sqlx::query_as::<_, UserRight>("SELECT DISTINCT ON (user_group_rights.right_name, user_group_rights.right_options) user_group_rights.right_name, user_group_rights.right_options FROM user_groups
@@ -212,6 +349,11 @@ pub async fn get_client_rights(pool: &RequestPool, req: &HttpRequest) -> Vec<Use
 
     match right_rows {
         Ok(right_rows) => {
+            // Tokens can never do more than their scopes allow
+            if let Some(token_scopes) = token_scopes {
+                return filter_rights_by_scopes(right_rows, &token_scopes);
+            }
+
             return right_rows;
         }
         Err(err) => {
@@ -0,0 +1,311 @@
+use actix_web::http::Method;
+use actix_web::HttpRequest;
+use chrono::NaiveDateTime as Timestamp;
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::right::get_administrative_right_names;
+use crate::user::{generate_session_secrets, UserRight, UserSession};
+use crate::util::RequestPool;
+
+/**
+ * Browse and download storage entries
+ */
+pub const API_TOKEN_SCOPE_STORAGE_READ: &str = "storage_read";
+
+/**
+ * Everything `storage_read` allows, plus uploading, moving, deleting, etc.
+ */
+pub const API_TOKEN_SCOPE_STORAGE_WRITE: &str = "storage_write";
+
+/**
+ * Administrative rights and the admin API
+ */
+pub const API_TOKEN_SCOPE_ADMIN: &str = "admin";
+
+pub const API_TOKEN_SCOPES: [&str; 3] = [
+    API_TOKEN_SCOPE_STORAGE_READ,
+    API_TOKEN_SCOPE_STORAGE_WRITE,
+    API_TOKEN_SCOPE_ADMIN,
+];
+
+/**
+ * `last_used_at` is only updated once in a while, so that tokens don't cost a write on every request
+ */
+const LAST_USED_UPDATE_INTERVAL_SECONDS: i64 = 60;
+
+/**
+ * Storage routes that don't modify anything, even though they are not `GET` (downloading a bunch of entries as an archive)
+ */
+const STORAGE_READ_ROUTES: [&str; 2] = [
+    "/api/storage/entries/{endpoint_id}/create-archive",
+    "/api/storage/user-archives/{archive_id}/cancel",
+];
+
+/**
+ * Routes outside of the storage and admin APIs that a token may use regardless of its scopes. Everything else
+ * (like minting or revoking tokens) is denied.
+ */
+const API_TOKEN_COMMON_ROUTES: [&str; 3] =
+    ["/api/auth/me", "/api/user-rights", "/api/instance-config"];
+
+/**
+ * Public share links, they don't need a token but should still work when one is sent
+ */
+const API_TOKEN_SHARE_ROUTES_PREFIX: &str = "/api/share/";
+
+#[derive(Serialize, FromRow)]
+pub struct UserApiToken {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+
+    pub created_at: String,
+    pub expires_on: Option<String>,
+
+    pub last_used_at: Option<String>,
+    pub last_used_ip: Option<String>,
+}
+
+/**
+ * Check if a request can be made with a token that has the given scopes.
+ *
+ * Storage routes (including WebDAV) require one of the storage scopes, anything that is not reading requires
+ * `storage_write`. The admin API requires `admin`. Any other route is denied unless it is in `API_TOKEN_COMMON_ROUTES`
+ * or a share link. Rights are narrowed separately, see `filter_rights_by_scopes`.
+ */
+pub fn check_api_token_scopes(scopes: &[String], req: &HttpRequest) -> bool {
+    let has_scope = |scope: &str| scopes.iter().any(|token_scope| token_scope == scope);
+
+    let path = req.path();
+
+    if path.starts_with("/api/admin") {
+        return has_scope(API_TOKEN_SCOPE_ADMIN);
+    }
+
+    if path.starts_with("/api/storage") || path.starts_with("/api/webdav") {
+        let is_read = [Method::GET, Method::HEAD, Method::OPTIONS].contains(req.method())
+            || req.method().as_str() == "PROPFIND"
+            || req
+                .match_pattern()
+                .is_some_and(|pattern| STORAGE_READ_ROUTES.contains(&pattern.as_str()));
+
+        return has_scope(API_TOKEN_SCOPE_STORAGE_WRITE)
+            || (is_read && has_scope(API_TOKEN_SCOPE_STORAGE_READ));
+    }
+
+    API_TOKEN_COMMON_ROUTES.contains(&path) || path.starts_with(API_TOKEN_SHARE_ROUTES_PREFIX)
+}
+
+/**
+ * Take away the rights that a token with the given scopes can not use. Administrative rights require the `admin` scope.
+ */
+pub fn filter_rights_by_scopes(rights: Vec<UserRight>, scopes: &[String]) -> Vec<UserRight> {
+    if scopes.iter().any(|scope| scope == API_TOKEN_SCOPE_ADMIN) {
+        return rights;
+    }
+
+    let administrative_rights = get_administrative_right_names();
+
+    rights
+        .into_iter()
+        .filter(|right| !administrative_rights.contains(&right.right_name.as_str()))
+        .collect()
+}
+
+/**
+ * Remember when and from where a token was last used
+ */
+pub async fn touch_api_token(session: &UserSession, req: &HttpRequest, pool: &RequestPool) {
+    let now = Utc::now().naive_utc();
+
+    if session.last_used_at.is_some_and(|last_used_at| {
+        (now - last_used_at).num_seconds() < LAST_USED_UPDATE_INTERVAL_SECONDS
+    }) {
+        return;
+    }
+
+    let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+
+    let _ =
+        sqlx::query("UPDATE user_sessions SET last_used_at = $1, last_used_ip = $2 WHERE id = $3")
+            .bind(now)
+            .bind(ip)
+            .bind(session.id)
+            .execute(pool)
+            .await;
+}
+
+/**
+ * Create a personal API token
+ *
+ * @returns the new token (a session) and the value to be sent in `Authorization: Bearer`
+ */
+pub async fn create_api_token(
+    user_id: i32,
+    name: &str,
+    scopes: &[String],
+    expires_on: Timestamp,
+    pool: &RequestPool,
+) -> Result<(UserSession, String), sqlx::Error> {
+    let (session_id, session_key) = generate_session_secrets();
+
+    let session = sqlx::query_as::<_, UserSession>(
+        "INSERT INTO user_sessions (session_id, session_key, user_id, created_at, expires_on, token_name, token_scopes) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+    )
+    .bind(session_id)
+    .bind(&session_key)
+    .bind(user_id)
+    .bind(Utc::now().naive_utc())
+    .bind(expires_on)
+    .bind(name)
+    .bind(scopes)
+    .fetch_one(pool)
+    .await?;
+
+    Ok((session, format!("{}:{}", session_id, session_key)))
+}
+
+/**
+ * Get all the API tokens of a user, newest first. Expired tokens are included.
+ */
+pub async fn get_user_api_tokens(
+    user_id: i32,
+    pool: &RequestPool,
+) -> Result<Vec<UserApiToken>, sqlx::Error> {
+    sqlx::query_as::<_, UserApiToken>(
+        "SELECT id, token_name AS name, token_scopes AS scopes, created_at::TEXT, expires_on::TEXT, last_used_at::TEXT, last_used_ip FROM user_sessions
+        WHERE user_id = $1 AND token_name IS NOT NULL ORDER BY id DESC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_and_read_body, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    use super::*;
+
+    fn scopes(scopes: &[&str]) -> Vec<String> {
+        scopes.iter().map(|scope| scope.to_string()).collect()
+    }
+
+    fn check(token_scopes: &[&str], method: Method, path: &str) -> bool {
+        let req = TestRequest::default()
+            .method(method)
+            .uri(path)
+            .to_http_request();
+
+        check_api_token_scopes(&scopes(token_scopes), &req)
+    }
+
+    #[test]
+    fn read_scope_only_allows_reading_storage() {
+        let read = [API_TOKEN_SCOPE_STORAGE_READ];
+
+        assert!(check(&read, Method::GET, "/api/storage/entries/1"));
+        assert!(check(&read, Method::HEAD, "/api/webdav/1/file.txt"));
+        assert!(check(
+            &read,
+            Method::from_bytes(b"PROPFIND").unwrap(),
+            "/api/webdav/1/"
+        ));
+
+        assert!(!check(&read, Method::POST, "/api/storage/upload/1"));
+        assert!(!check(&read, Method::PUT, "/api/webdav/1/file.txt"));
+        assert!(!check(&read, Method::DELETE, "/api/webdav/1/file.txt"));
+        assert!(!check(
+            &read,
+            Method::from_bytes(b"MKCOL").unwrap(),
+            "/api/webdav/1/folder/"
+        ));
+    }
+
+    #[test]
+    fn write_scope_allows_reading_and_writing_storage() {
+        let write = [API_TOKEN_SCOPE_STORAGE_WRITE];
+
+        assert!(check(&write, Method::GET, "/api/storage/entries/1"));
+        assert!(check(&write, Method::POST, "/api/storage/upload/1"));
+        assert!(check(&write, Method::PUT, "/api/webdav/1/file.txt"));
+    }
+
+    #[test]
+    fn storage_requires_a_storage_scope() {
+        assert!(!check(&[], Method::GET, "/api/storage/entries/1"));
+        assert!(!check(
+            &[API_TOKEN_SCOPE_ADMIN],
+            Method::GET,
+            "/api/webdav/1/"
+        ));
+    }
+
+    #[test]
+    fn admin_api_requires_admin_scope() {
+        assert!(check(
+            &[API_TOKEN_SCOPE_ADMIN],
+            Method::GET,
+            "/api/admin/users"
+        ));
+        assert!(!check(
+            &[API_TOKEN_SCOPE_STORAGE_READ, API_TOKEN_SCOPE_STORAGE_WRITE],
+            Method::GET,
+            "/api/admin/users"
+        ));
+    }
+
+    #[test]
+    fn other_routes_are_denied_by_default() {
+        assert!(check(&[], Method::GET, "/api/auth/me"));
+        assert!(check(&[], Method::GET, "/api/share/abc"));
+
+        let all = [
+            API_TOKEN_SCOPE_STORAGE_READ,
+            API_TOKEN_SCOPE_STORAGE_WRITE,
+            API_TOKEN_SCOPE_ADMIN,
+        ];
+
+        assert!(!check(&all, Method::POST, "/api/auth/api-tokens"));
+        assert!(!check(&all, Method::GET, "/api/unknown"));
+    }
+
+    #[actix_web::test]
+    async fn read_scope_allows_read_only_post_routes() {
+        let app = init_service(
+            App::new()
+                .route(
+                    STORAGE_READ_ROUTES[0],
+                    web::post().to(|req: HttpRequest| async move {
+                        let allowed =
+                            check_api_token_scopes(&scopes(&[API_TOKEN_SCOPE_STORAGE_READ]), &req);
+
+                        HttpResponse::Ok().body(allowed.to_string())
+                    }),
+                )
+                .route(
+                    "/api/storage/entries/{endpoint_id}/delete",
+                    web::post().to(|req: HttpRequest| async move {
+                        let allowed =
+                            check_api_token_scopes(&scopes(&[API_TOKEN_SCOPE_STORAGE_READ]), &req);
+
+                        HttpResponse::Ok().body(allowed.to_string())
+                    }),
+                ),
+        )
+        .await;
+
+        let archive = TestRequest::post()
+            .uri("/api/storage/entries/1/create-archive")
+            .to_request();
+        assert_eq!(call_and_read_body(&app, archive).await, "true");
+
+        let delete = TestRequest::post()
+            .uri("/api/storage/entries/1/delete")
+            .to_request();
+        assert_eq!(call_and_read_body(&app, delete).await, "false");
+    }
+}
@@ -0,0 +1,695 @@
+use std::time::Duration;
+
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+
+use crate::util::RequestPool;
+
+/**
+ * How long a client has to log in at the identity provider
+ */
+const LOGIN_REQUEST_LIFETIME_MINUTES: i32 = 10;
+
+/**
+ * Timeout for requests to identity providers
+ */
+const PROVIDER_REQUEST_TIMEOUT_SECONDS: u64 = 10;
+
+/**
+ * Length of the PKCE code verifier. RFC 7636 allows 43 - 128 characters.
+ */
+const CODE_VERIFIER_LENGTH: usize = 64;
+
+pub const OIDC_CALLBACK_PATH: &str = "/api/auth/oidc/callback";
+
+/**
+ * Ties a login to the browser that started it, so that a callback URL can not be used to log someone else in.
+ * Holds a hash of the login's `state`.
+ */
+const OIDC_STATE_COOKIE: &str = "y-oidc-state";
+
+/**
+ * Login page of the web interface. Errors and pending logins are passed to it in the query string.
+ */
+const WEB_LOGIN_PATH: &str = "/login";
+
+#[derive(PartialEq, Debug)]
+pub enum OidcError {
+    ProviderNotFound,
+    ProviderNameTaken,
+    ProviderUnavailable,
+    LoginRequestNotFound,
+    StateMismatch,
+    AccessDenied,
+    InvalidIdToken,
+    MissingClaim,
+    UserNotFound,
+    UsernameTaken,
+    InvalidGroupMappings,
+
+    Internal,
+}
+
+impl OidcError {
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            OidcError::ProviderNotFound => "oidc.provider_not_found",
+            OidcError::ProviderNameTaken => "oidc.provider_name_taken",
+            OidcError::ProviderUnavailable => "oidc.provider_unavailable",
+            OidcError::LoginRequestNotFound => "oidc.login_request_not_found",
+            OidcError::StateMismatch => "oidc.state_mismatch",
+            OidcError::AccessDenied => "oidc.access_denied",
+            OidcError::InvalidIdToken => "oidc.invalid_id_token",
+            OidcError::MissingClaim => "oidc.missing_claim",
+            OidcError::UserNotFound => "oidc.user_not_found",
+            OidcError::UsernameTaken => "oidc.username_taken",
+            OidcError::InvalidGroupMappings => "oidc.invalid_group_mappings",
+
+            OidcError::Internal => "oidc.internal",
+        }
+    }
+}
+
+#[derive(FromRow)]
+pub struct OidcProvider {
+    pub id: i32,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scopes: String,
+    pub username_claim: String,
+    pub groups_claim: Option<String>,
+    pub auto_create_users: bool,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OidcGroupMapping {
+    /**
+     * Value of the groups claim
+     */
+    pub claim_value: String,
+    pub group_id: i32,
+}
+
+#[derive(FromRow)]
+pub struct OidcLoginRequest {
+    pub provider_id: i32,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub redirect_uri: String,
+    pub return_to: Option<String>,
+}
+
+/**
+ * The parts of the provider's discovery document that we use
+ */
+#[derive(Deserialize)]
+struct OidcProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct OidcTokenResponse {
+    id_token: String,
+}
+
+/**
+ * Who the identity provider says the client is
+ */
+pub struct OidcIdentity {
+    pub subject: String,
+    pub username: Option<String>,
+    pub groups: Vec<String>,
+}
+
+/**
+ * Issuers have to use https. Plain http is only allowed on the loopback interface, for local identity providers.
+ */
+pub fn is_valid_issuer(issuer: &str) -> bool {
+    match Url::parse(issuer) {
+        Ok(url) => match url.scheme() {
+            "https" => url.host().is_some(),
+            "http" => matches!(
+                url.host_str(),
+                Some("localhost") | Some("127.0.0.1") | Some("[::1]")
+            ),
+            _ => false,
+        },
+        Err(_) => false,
+    }
+}
+
+/**
+ * Only paths on this instance are allowed to be returned to after logging in, so that the login can not be used
+ * to send users elsewhere.
+ */
+pub fn is_valid_return_path(path: &str) -> bool {
+    path.starts_with('/') && !path.starts_with("//") && !path.contains('\\')
+}
+
+/**
+ * Send the browser to the login page of the web interface with an error
+ */
+pub fn oidc_error_redirect(err: &OidcError) -> HttpResponse {
+    HttpResponse::Found()
+        .insert_header((
+            header::LOCATION,
+            format!("{}?error={}", WEB_LOGIN_PATH, err.get_code()),
+        ))
+        .finish()
+}
+
+/**
+ * Send the browser to the login page of the web interface, to enter the second factor (or set it up)
+ */
+pub fn oidc_pending_login_redirect(pending_token: &str, enrollment_required: bool) -> HttpResponse {
+    HttpResponse::Found()
+        .insert_header((
+            header::LOCATION,
+            format!(
+                "{}?pending_token={}&enrollment_required={}",
+                WEB_LOGIN_PATH,
+                utf8_percent_encode(pending_token, NON_ALPHANUMERIC),
+                enrollment_required
+            ),
+        ))
+        .finish()
+}
+
+/**
+ * Callback URL registered at identity providers. The same one is used for all providers.
+ */
+pub fn get_oidc_redirect_uri(req: &HttpRequest) -> String {
+    let connection_info = req.connection_info();
+
+    format!(
+        "{}://{}{}",
+        connection_info.scheme(),
+        connection_info.host(),
+        OIDC_CALLBACK_PATH
+    )
+}
+
+fn hash_oidc_state(state: &str) -> String {
+    hex::encode(Sha256::digest(state.as_bytes()))
+}
+
+/**
+ * Cookie that is set when a login is started, and checked by the callback. It is only marked secure when the
+ * request came over HTTPS, browsers drop secure cookies on plain HTTP.
+ */
+pub fn build_oidc_state_cookie(state: &str, req: &HttpRequest) -> Cookie<'static> {
+    let secure = req.connection_info().scheme() == "https";
+
+    Cookie::build(OIDC_STATE_COOKIE, hash_oidc_state(state))
+        .path(OIDC_CALLBACK_PATH)
+        .max_age(time::Duration::minutes(
+            LOGIN_REQUEST_LIFETIME_MINUTES.into(),
+        ))
+        .secure(secure)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+/**
+ * Removes the state cookie once the callback is done with it
+ */
+pub fn build_oidc_state_removal_cookie(req: &HttpRequest) -> Cookie<'static> {
+    let mut cookie = build_oidc_state_cookie("", req);
+    cookie.make_removal();
+
+    cookie
+}
+
+/**
+ * Check that the callback came to the browser that started the login
+ */
+pub fn check_oidc_state_cookie(req: &HttpRequest, state: &str) -> bool {
+    req.cookie(OIDC_STATE_COOKIE)
+        .is_some_and(|cookie| cookie.value() == hash_oidc_state(state))
+}
+
+fn generate_random_string(length: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+fn build_http_client() -> Result<reqwest::Client, OidcError> {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(PROVIDER_REQUEST_TIMEOUT_SECONDS))
+        .build()
+        .map_err(|_| OidcError::Internal)
+}
+
+/**
+ * Look a claim up by name. Nested claims are separated with dots (`realm_access.roles`).
+ */
+fn get_claim<'a>(claims: &'a Value, name: &str) -> Option<&'a Value> {
+    claims.pointer(&format!("/{}", name.replace('.', "/")))
+}
+
+pub async fn get_oidc_provider(
+    provider_id: i32,
+    pool: &RequestPool,
+) -> Result<OidcProvider, OidcError> {
+    sqlx::query_as::<_, OidcProvider>(
+        "SELECT id, issuer, client_id, client_secret, scopes, username_claim, groups_claim, auto_create_users, enabled
+        FROM oidc_providers WHERE id = $1",
+    )
+    .bind(provider_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| OidcError::Internal)?
+    .ok_or(OidcError::ProviderNotFound)
+}
+
+async fn discover_provider(
+    provider: &OidcProvider,
+    client: &reqwest::Client,
+) -> Result<OidcProviderMetadata, OidcError> {
+    let metadata = client
+        .get(format!(
+            "{}/.well-known/openid-configuration",
+            provider.issuer.trim_end_matches('/')
+        ))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|_| OidcError::ProviderUnavailable)?
+        .json::<OidcProviderMetadata>()
+        .await
+        .map_err(|_| OidcError::ProviderUnavailable)?;
+
+    // OpenID Connect Discovery 1.0, section 4.3
+    if metadata.issuer != provider.issuer {
+        return Err(OidcError::ProviderUnavailable);
+    }
+
+    Ok(metadata)
+}
+
+/**
+ * Start logging in with an identity provider (authorization code flow with PKCE)
+ *
+ * @returns URL of the provider's authorization endpoint to send the client to, and the `state` of the login
+ */
+pub async fn start_oidc_login(
+    provider: &OidcProvider,
+    redirect_uri: &str,
+    return_to: Option<&str>,
+    pool: &RequestPool,
+) -> Result<(String, String), OidcError> {
+    let client = build_http_client()?;
+    let metadata = discover_provider(provider, &client).await?;
+
+    let state = generate_random_string(43);
+    let nonce = generate_random_string(43);
+    let code_verifier = generate_random_string(CODE_VERIFIER_LENGTH);
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+    let _ = sqlx::query("DELETE FROM oidc_login_requests WHERE expires_at < now()")
+        .execute(pool)
+        .await;
+
+    sqlx::query(
+        "INSERT INTO oidc_login_requests (state, provider_id, nonce, code_verifier, redirect_uri, return_to, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, now() + make_interval(mins => $7))",
+    )
+    .bind(&state)
+    .bind(provider.id)
+    .bind(&nonce)
+    .bind(&code_verifier)
+    .bind(redirect_uri)
+    .bind(return_to)
+    .bind(LOGIN_REQUEST_LIFETIME_MINUTES)
+    .execute(pool)
+    .await
+    .map_err(|_| OidcError::Internal)?;
+
+    let mut authorization_url =
+        Url::parse(&metadata.authorization_endpoint).map_err(|_| OidcError::ProviderUnavailable)?;
+
+    authorization_url
+        .query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", redirect_uri)
+        .append_pair("scope", &provider.scopes)
+        .append_pair("state", &state)
+        .append_pair("nonce", &nonce)
+        .append_pair("code_challenge", &code_challenge)
+        .append_pair("code_challenge_method", "S256");
+
+    Ok((authorization_url.to_string(), state))
+}
+
+/**
+ * Get a login request by its `state`. A request can only be taken once.
+ */
+pub async fn take_oidc_login_request(
+    state: &str,
+    pool: &RequestPool,
+) -> Result<OidcLoginRequest, OidcError> {
+    sqlx::query_as::<_, OidcLoginRequest>(
+        "DELETE FROM oidc_login_requests WHERE state = $1 AND expires_at > now()
+        RETURNING provider_id, nonce, code_verifier, redirect_uri, return_to",
+    )
+    .bind(state)
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| OidcError::Internal)?
+    .ok_or(OidcError::LoginRequestNotFound)
+}
+
+/**
+ * Exchange an authorization code for an ID token, verify it and read the client's identity from it
+ */
+pub async fn finish_oidc_login(
+    provider: &OidcProvider,
+    login_request: &OidcLoginRequest,
+    code: &str,
+) -> Result<OidcIdentity, OidcError> {
+    let client = build_http_client()?;
+    let metadata = discover_provider(provider, &client).await?;
+
+    let mut token_params = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", login_request.redirect_uri.as_str()),
+        ("client_id", provider.client_id.as_str()),
+        ("code_verifier", login_request.code_verifier.as_str()),
+    ];
+
+    if let Some(client_secret) = &provider.client_secret {
+        token_params.push(("client_secret", client_secret.as_str()));
+    }
+
+    let token_response = client
+        .post(&metadata.token_endpoint)
+        .form(&token_params)
+        .send()
+        .await
+        .map_err(|_| OidcError::ProviderUnavailable)?;
+
+    if token_response.status().is_client_error() {
+        return Err(OidcError::AccessDenied);
+    }
+
+    let id_token = token_response
+        .error_for_status()
+        .map_err(|_| OidcError::ProviderUnavailable)?
+        .json::<OidcTokenResponse>()
+        .await
+        .map_err(|_| OidcError::ProviderUnavailable)?
+        .id_token;
+
+    let token_header = decode_header(&id_token).map_err(|_| OidcError::InvalidIdToken)?;
+
+    // Providers sign ID tokens with their published keys, HMAC would mean the client secret is used as the key
+    if matches!(
+        token_header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(OidcError::InvalidIdToken);
+    }
+
+    let jwks = client
+        .get(&metadata.jwks_uri)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|_| OidcError::ProviderUnavailable)?
+        .json::<JwkSet>()
+        .await
+        .map_err(|_| OidcError::ProviderUnavailable)?;
+
+    let jwk = match &token_header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or(OidcError::InvalidIdToken)?;
+
+    let decoding_key = DecodingKey::from_jwk(jwk).map_err(|_| OidcError::InvalidIdToken)?;
+
+    let mut validation = Validation::new(token_header.alg);
+    validation.set_audience(&[&provider.client_id]);
+    validation.set_issuer(&[&metadata.issuer]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let claims = decode::<Value>(&id_token, &decoding_key, &validation)
+        .map_err(|_| OidcError::InvalidIdToken)?
+        .claims;
+
+    if claims.get("nonce").and_then(Value::as_str) != Some(login_request.nonce.as_str()) {
+        return Err(OidcError::InvalidIdToken);
+    }
+
+    let subject = claims
+        .get("sub")
+        .and_then(Value::as_str)
+        .filter(|subject| !subject.is_empty())
+        .ok_or(OidcError::MissingClaim)?
+        .to_string();
+
+    let username = get_claim(&claims, &provider.username_claim)
+        .and_then(Value::as_str)
+        .map(|username| username.trim().to_string())
+        .filter(|username| !username.is_empty());
+
+    // A missing groups claim means no groups, so that mapped groups are taken away rather than kept
+    let groups = match provider
+        .groups_claim
+        .as_ref()
+        .and_then(|groups_claim| get_claim(&claims, groups_claim))
+    {
+        Some(Value::Array(groups)) => groups
+            .iter()
+            .filter_map(|group| group.as_str().map(str::to_string))
+            .collect(),
+        Some(Value::String(group)) => vec![group.clone()],
+        _ => vec![],
+    };
+
+    Ok(OidcIdentity {
+        subject,
+        username,
+        groups,
+    })
+}
+
+/**
+ * Find the user linked to an identity. Users that log in for the first time are created, if the provider allows it.
+ * They don't get a password, so they can only log in through the provider.
+ *
+ * @returns id of the user, and whether they were just created
+ */
+pub async fn find_or_create_oidc_user(
+    provider: &OidcProvider,
+    identity: &OidcIdentity,
+    pool: &RequestPool,
+) -> Result<(i32, bool), OidcError> {
+    let existing_user_id = sqlx::query_scalar::<_, i32>(
+        "UPDATE user_oidc_identities SET last_login_at = now() WHERE provider_id = $1 AND subject = $2 RETURNING user_id",
+    )
+    .bind(provider.id)
+    .bind(&identity.subject)
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| OidcError::Internal)?;
+
+    if let Some(user_id) = existing_user_id {
+        return Ok((user_id, false));
+    }
+
+    if !provider.auto_create_users {
+        return Err(OidcError::UserNotFound);
+    }
+
+    let username = identity
+        .username
+        .as_ref()
+        .filter(|username| username.chars().count() <= 127)
+        .ok_or(OidcError::MissingClaim)?;
+
+    let create_result: Result<Option<i32>, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+
+        // Existing local users are never linked automatically, the provider could be vouching for someone else
+        let user_id = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO users (username, password) VALUES ($1, NULL) ON CONFLICT (username) DO NOTHING RETURNING id",
+        )
+        .bind(username)
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+        if let Some(user_id) = user_id {
+            sqlx::query(
+                "INSERT INTO user_oidc_identities (provider_id, subject, user_id, last_login_at) VALUES ($1, $2, $3, now())",
+            )
+            .bind(provider.id)
+            .bind(&identity.subject)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+
+            transaction.commit().await?;
+        }
+
+        Ok(user_id)
+    }
+    .await;
+
+    match create_result {
+        Ok(Some(user_id)) => Ok((user_id, true)),
+        Ok(None) => Err(OidcError::UsernameTaken),
+        Err(_) => Err(OidcError::Internal),
+    }
+}
+
+/**
+ * Make the user's membership in mapped groups match the groups claim. Groups that are not mapped to anything
+ * are left alone, so they can still be managed by hand.
+ *
+ * @returns ids of the groups the user was added to and removed from
+ */
+pub async fn sync_oidc_user_groups(
+    provider: &OidcProvider,
+    user_id: i32,
+    identity: &OidcIdentity,
+    pool: &RequestPool,
+) -> Result<(Vec<i32>, Vec<i32>), OidcError> {
+    if provider.groups_claim.is_none() {
+        return Ok((vec![], vec![]));
+    }
+
+    let sync_result: Result<(Vec<i32>, Vec<i32>), sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+
+        let removed = sqlx::query_scalar::<_, i32>(
+            "DELETE FROM user_group_membership WHERE user_id = $1
+            AND group_id IN (SELECT group_id FROM oidc_provider_group_mappings WHERE provider_id = $2)
+            AND group_id NOT IN (SELECT group_id FROM oidc_provider_group_mappings WHERE provider_id = $2 AND claim_value = ANY($3))
+            RETURNING group_id",
+        )
+        .bind(user_id)
+        .bind(provider.id)
+        .bind(&identity.groups)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        let added = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO user_group_membership (user_id, group_id)
+            SELECT DISTINCT $1, group_id FROM oidc_provider_group_mappings WHERE provider_id = $2 AND claim_value = ANY($3)
+            ON CONFLICT DO NOTHING RETURNING group_id",
+        )
+        .bind(user_id)
+        .bind(provider.id)
+        .bind(&identity.groups)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok((added, removed))
+    }
+    .await;
+
+    sync_result.map_err(|_| OidcError::Internal)
+}
+
+/**
+ * Replace the group mappings of a provider. Only regular groups can be mapped, membership in system groups
+ * (`everyone`, `user`) is implicit.
+ */
+pub async fn set_oidc_provider_group_mappings(
+    provider_id: i32,
+    mappings: &[OidcGroupMapping],
+    transaction: &mut sqlx::PgConnection,
+) -> Result<(), OidcError> {
+    let (claim_values, group_ids): (Vec<String>, Vec<i32>) = mappings
+        .iter()
+        .map(|mapping| (mapping.claim_value.clone(), mapping.group_id))
+        .unzip();
+
+    if claim_values
+        .iter()
+        .any(|claim_value| claim_value.is_empty() || claim_value.chars().count() > 255)
+    {
+        return Err(OidcError::InvalidGroupMappings);
+    }
+
+    sqlx::query("DELETE FROM oidc_provider_group_mappings WHERE provider_id = $1")
+        .bind(provider_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(|_| OidcError::Internal)?;
+
+    let inserted = sqlx::query(
+        "INSERT INTO oidc_provider_group_mappings (provider_id, claim_value, group_id)
+        SELECT DISTINCT $1, mapping.claim_value, mapping.group_id FROM unnest($2::TEXT[], $3::INTEGER[]) AS mapping(claim_value, group_id)
+        INNER JOIN user_groups ON user_groups.id = mapping.group_id AND user_groups.group_type IS NULL",
+    )
+    .bind(provider_id)
+    .bind(&claim_values)
+    .bind(&group_ids)
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| OidcError::Internal)?
+    .rows_affected();
+
+    let mut unique_mappings = claim_values
+        .iter()
+        .zip(group_ids.iter())
+        .collect::<Vec<_>>();
+    unique_mappings.sort();
+    unique_mappings.dedup();
+
+    if inserted != unique_mappings.len() as u64 {
+        return Err(OidcError::InvalidGroupMappings);
+    }
+
+    Ok(())
+}
+
+/**
+ * Settings of a provider, as they are recorded in the audit log. The client secret is left out.
+ */
+pub async fn get_oidc_provider_snapshot(provider_id: i32, pool: &RequestPool) -> Option<Value> {
+    sqlx::query_scalar::<_, Value>(
+        "SELECT jsonb_build_object(
+            'name', name, 'issuer', issuer, 'client_id', client_id, 'has_client_secret', client_secret IS NOT NULL,
+            'scopes', scopes, 'username_claim', username_claim, 'groups_claim', groups_claim,
+            'auto_create_users', auto_create_users, 'enabled', enabled,
+            'group_mappings', (
+                SELECT COALESCE(jsonb_agg(jsonb_build_object('claim_value', claim_value, 'group_id', group_id) ORDER BY claim_value, group_id), '[]'::jsonb)
+                FROM oidc_provider_group_mappings WHERE provider_id = oidc_providers.id
+            )
+        ) FROM oidc_providers WHERE id = $1",
+    )
+    .bind(provider_id)
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten()
+}
@@ -0,0 +1,669 @@
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng, RngCore};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+
+use actix_web::HttpRequest;
+
+use crate::config::get_config;
+use crate::user::{get_group_rights, get_user_from_request, get_user_groups, UserRight};
+use crate::util::RequestPool;
+
+/**
+ * RFC 6238 defaults, these are the only parameters most authenticator apps support
+ */
+const TOTP_STEP_SECONDS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+
+/**
+ * How many time steps before and after the current one are accepted, to make up for clock drift
+ */
+const TOTP_ALLOWED_DRIFT_STEPS: i64 = 1;
+
+/**
+ * 160 bits, as recommended by RFC 4226
+ */
+const TOTP_SECRET_BYTES: usize = 20;
+
+pub const RECOVERY_CODES_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+/**
+ * How long a client has to enter the second factor after entering their password
+ */
+const PENDING_LOGIN_LIFETIME_MINUTES: i32 = 5;
+
+/**
+ * Wrong codes allowed per pending login. After that the client has to start over with their password.
+ */
+const PENDING_LOGIN_MAX_ATTEMPTS: i32 = 5;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(PartialEq, Debug)]
+pub enum TotpError {
+    Unauthorized,
+    NotEnrolled,
+    AlreadyEnabled,
+    InvalidCode,
+    PendingLoginNotFound,
+
+    Internal,
+}
+
+impl TotpError {
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            TotpError::Unauthorized => "two_factor.unauthorized",
+            TotpError::NotEnrolled => "two_factor.not_enrolled",
+            TotpError::AlreadyEnabled => "two_factor.already_enabled",
+            TotpError::InvalidCode => "two_factor.invalid_code",
+            TotpError::PendingLoginNotFound => "two_factor.pending_login_not_found",
+
+            TotpError::Internal => "two_factor.internal",
+        }
+    }
+}
+
+#[derive(FromRow)]
+pub struct UserTotp {
+    pub user_id: i32,
+    pub secret: String,
+    pub enabled: bool,
+    pub last_used_step: Option<i64>,
+}
+
+#[derive(FromRow)]
+pub struct UserPendingLogin {
+    pub id: i32,
+    pub user_id: i32,
+    pub enrollment_required: bool,
+}
+
+/**
+ * A user who is setting up two-factor authentication
+ */
+pub struct TotpEnrollingClient {
+    pub user_id: i32,
+    pub username: String,
+
+    /**
+     * Set if the client is logged in
+     */
+    pub session_id: Option<i32>,
+
+    /**
+     * Set if the client is in the middle of a login that requires them to set up two-factor authentication first
+     */
+    pub pending_login_id: Option<i32>,
+}
+
+/**
+ * RFC 4648 base32 without padding, as expected in `otpauth://` URIs
+ */
+fn encode_base32(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    let mut buffer: u32 = 0;
+    let mut buffer_bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        buffer_bits += 8;
+
+        while buffer_bits >= 5 {
+            buffer_bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> buffer_bits) & 0x1f) as usize] as char);
+        }
+    }
+
+    if buffer_bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - buffer_bits)) & 0x1f) as usize] as char);
+    }
+
+    output
+}
+
+/**
+ * Generate the code for a time step (RFC 4226 HOTP, with the time step as the counter)
+ */
+fn generate_code(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = ((digest[offset] as u32 & 0x7f) << 24)
+        | ((digest[offset + 1] as u32) << 16)
+        | ((digest[offset + 2] as u32) << 8)
+        | (digest[offset + 3] as u32);
+
+    binary % 10_u32.pow(TOTP_DIGITS)
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized = code.trim().replace('-', "").to_lowercase();
+
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+/**
+ * Check a code against a secret.
+ *
+ * @returns the time step the code belongs to
+ */
+fn verify_code(totp: &UserTotp, code: &str) -> Option<i64> {
+    verify_code_at(
+        totp,
+        code,
+        chrono::Utc::now().timestamp() / TOTP_STEP_SECONDS,
+    )
+}
+
+fn verify_code_at(totp: &UserTotp, code: &str, current_step: i64) -> Option<i64> {
+    let code = code.trim().replace(' ', "");
+
+    if code.len() != TOTP_DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let code = code.parse::<u32>().ok()?;
+    let secret = hex::decode(&totp.secret).ok()?;
+
+    (current_step - TOTP_ALLOWED_DRIFT_STEPS..=current_step + TOTP_ALLOWED_DRIFT_STEPS)
+        .find(|step| generate_code(&secret, *step) == code)
+        // A code that was already accepted once can not be used again
+        .filter(|step| {
+            totp.last_used_step
+                .is_none_or(|last_used_step| *step > last_used_step)
+        })
+}
+
+/**
+ * Whether any of the groups a user is in requires their members to use two-factor authentication
+ */
+pub fn check_two_factor_required(group_rights: &[UserRight]) -> bool {
+    group_rights.iter().any(|right| {
+        right.right_name.eq("two_factor_authentication")
+            && right
+                .right_options
+                .get("require_two_factor")
+                .and_then(|value| value.as_bool())
+                .unwrap_or(false)
+    })
+}
+
+pub async fn is_two_factor_required(user_id: i32, pool: &RequestPool) -> bool {
+    let user_groups = get_user_groups(pool, user_id).await;
+    let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+    check_two_factor_required(&get_group_rights(pool, &group_ids).await)
+}
+
+pub async fn get_user_totp(
+    user_id: i32,
+    pool: &RequestPool,
+) -> Result<Option<UserTotp>, TotpError> {
+    sqlx::query_as::<_, UserTotp>(
+        "SELECT user_id, secret, enabled, last_used_step FROM user_totp WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| TotpError::Internal)
+}
+
+pub async fn is_two_factor_enabled(user_id: i32, pool: &RequestPool) -> Result<bool, TotpError> {
+    Ok(get_user_totp(user_id, pool)
+        .await?
+        .is_some_and(|totp| totp.enabled))
+}
+
+/**
+ * Start setting up two-factor authentication for a user. Replaces an unconfirmed enrollment, if there is one.
+ *
+ * @returns (base32 secret, `otpauth://` URI for QR codes)
+ */
+pub async fn enroll_totp(
+    user_id: i32,
+    username: &str,
+    pool: &RequestPool,
+) -> Result<(String, String), TotpError> {
+    if is_two_factor_enabled(user_id, pool).await? {
+        return Err(TotpError::AlreadyEnabled);
+    }
+
+    let mut secret = [0u8; TOTP_SECRET_BYTES];
+    thread_rng().fill_bytes(&mut secret);
+
+    sqlx::query(
+        "INSERT INTO user_totp (user_id, secret) VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET secret = excluded.secret, last_used_step = NULL, created_at = now()",
+    )
+    .bind(user_id)
+    .bind(hex::encode(secret))
+    .execute(pool)
+    .await
+    .map_err(|_| TotpError::Internal)?;
+
+    let issuer = get_config(pool)
+        .await
+        .get("instance.name")
+        .cloned()
+        .unwrap_or("y".to_string());
+
+    let encoded_secret = encode_base32(&secret);
+
+    let uri = format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        utf8_percent_encode(&issuer, NON_ALPHANUMERIC),
+        utf8_percent_encode(username, NON_ALPHANUMERIC),
+        encoded_secret,
+        utf8_percent_encode(&issuer, NON_ALPHANUMERIC),
+        TOTP_DIGITS,
+        TOTP_STEP_SECONDS
+    );
+
+    Ok((encoded_secret, uri))
+}
+
+/**
+ * Check a TOTP code of a user. Accepted codes can not be used again.
+ */
+pub async fn check_totp_code(
+    user_id: i32,
+    code: &str,
+    pool: &RequestPool,
+) -> Result<(), TotpError> {
+    let totp = get_user_totp(user_id, pool)
+        .await?
+        .filter(|totp| totp.enabled)
+        .ok_or(TotpError::NotEnrolled)?;
+
+    consume_totp_code(&totp, code, pool).await
+}
+
+async fn consume_totp_code(
+    totp: &UserTotp,
+    code: &str,
+    pool: &RequestPool,
+) -> Result<(), TotpError> {
+    let step = verify_code(totp, code).ok_or(TotpError::InvalidCode)?;
+
+    // Two requests with the same code could both pass `verify_code`, the condition makes sure only one of them wins
+    let updated = sqlx::query(
+        "UPDATE user_totp SET last_used_step = $2 WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+    )
+    .bind(totp.user_id)
+    .bind(step)
+    .execute(pool)
+    .await
+    .map_err(|_| TotpError::Internal)?
+    .rows_affected();
+
+    if updated == 0 {
+        return Err(TotpError::InvalidCode);
+    }
+
+    Ok(())
+}
+
+/**
+ * Use up one of the recovery codes of a user
+ */
+pub async fn use_recovery_code(
+    user_id: i32,
+    code: &str,
+    pool: &RequestPool,
+) -> Result<(), TotpError> {
+    let used = sqlx::query(
+        "UPDATE user_totp_recovery_codes SET used_at = now() WHERE id = (
+            SELECT id FROM user_totp_recovery_codes WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL LIMIT 1
+        ) AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(hash_recovery_code(code))
+    .execute(pool)
+    .await
+    .map_err(|_| TotpError::Internal)?
+    .rows_affected();
+
+    if used == 0 {
+        return Err(TotpError::InvalidCode);
+    }
+
+    Ok(())
+}
+
+/**
+ * Check the second factor of a user: either a TOTP code, or one of the recovery codes
+ */
+pub async fn check_second_factor(
+    user_id: i32,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+    pool: &RequestPool,
+) -> Result<(), TotpError> {
+    match (code, recovery_code) {
+        (Some(code), _) => check_totp_code(user_id, code, pool).await,
+        (None, Some(recovery_code)) => use_recovery_code(user_id, recovery_code, pool).await,
+        (None, None) => Err(TotpError::InvalidCode),
+    }
+}
+
+/**
+ * Replace all the recovery codes of a user with new ones
+ *
+ * @returns the new codes. They are only stored hashed, so this is the only time they can be shown.
+ */
+pub async fn regenerate_recovery_codes(
+    user_id: i32,
+    pool: &RequestPool,
+) -> Result<Vec<String>, TotpError> {
+    let codes = (0..RECOVERY_CODES_COUNT)
+        .map(|_| {
+            let code: String = thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(RECOVERY_CODE_LENGTH)
+                .map(char::from)
+                .collect::<String>()
+                .to_lowercase();
+
+            format!(
+                "{}-{}",
+                &code[..RECOVERY_CODE_LENGTH / 2],
+                &code[RECOVERY_CODE_LENGTH / 2..]
+            )
+        })
+        .collect::<Vec<String>>();
+
+    let code_hashes = codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect::<Vec<String>>();
+
+    let regenerate_result: Result<(), sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+
+        sqlx::query("DELETE FROM user_totp_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO user_totp_recovery_codes (user_id, code_hash) SELECT $1, code_hash FROM unnest($2::TEXT[]) AS code_hash",
+        )
+        .bind(user_id)
+        .bind(&code_hashes)
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await
+    }
+    .await;
+
+    regenerate_result.map_err(|_| TotpError::Internal)?;
+
+    Ok(codes)
+}
+
+/**
+ * Finish setting up two-factor authentication with the first code from the authenticator app
+ *
+ * @returns recovery codes
+ */
+pub async fn confirm_totp(
+    user_id: i32,
+    code: &str,
+    pool: &RequestPool,
+) -> Result<Vec<String>, TotpError> {
+    let totp = get_user_totp(user_id, pool)
+        .await?
+        .ok_or(TotpError::NotEnrolled)?;
+
+    if totp.enabled {
+        return Err(TotpError::AlreadyEnabled);
+    }
+
+    consume_totp_code(&totp, code, pool).await?;
+
+    sqlx::query("UPDATE user_totp SET enabled = true, enabled_at = now() WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|_| TotpError::Internal)?;
+
+    regenerate_recovery_codes(user_id, pool).await
+}
+
+/**
+ * Turn two-factor authentication off for a user, removing the secret and the recovery codes
+ *
+ * @returns whether the user had anything to remove
+ */
+pub async fn disable_totp(user_id: i32, pool: &RequestPool) -> Result<bool, TotpError> {
+    let disable_result: Result<bool, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+
+        let removed = sqlx::query("DELETE FROM user_totp WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
+
+        sqlx::query("DELETE FROM user_totp_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(removed > 0)
+    }
+    .await;
+
+    disable_result.map_err(|_| TotpError::Internal)
+}
+
+pub async fn get_remaining_recovery_codes_count(
+    user_id: i32,
+    pool: &RequestPool,
+) -> Result<i64, TotpError> {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM user_totp_recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|_| TotpError::Internal)
+}
+
+/**
+ * Remember that a user passed the password check and has to provide the second factor
+ *
+ * @returns the pending token that the client has to send along with the second factor
+ */
+pub async fn create_pending_login(
+    user_id: i32,
+    enrollment_required: bool,
+    pool: &RequestPool,
+) -> Result<String, TotpError> {
+    let token: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect();
+
+    // Expired pending logins are not useful to anyone
+    let _ = sqlx::query("DELETE FROM user_pending_logins WHERE expires_at < now()")
+        .execute(pool)
+        .await;
+
+    sqlx::query(
+        "INSERT INTO user_pending_logins (token, user_id, enrollment_required, expires_at) VALUES ($1, $2, $3, now() + make_interval(mins => $4))",
+    )
+    .bind(&token)
+    .bind(user_id)
+    .bind(enrollment_required)
+    .bind(PENDING_LOGIN_LIFETIME_MINUTES)
+    .execute(pool)
+    .await
+    .map_err(|_| TotpError::Internal)?;
+
+    Ok(token)
+}
+
+/**
+ * Find a pending login that has not expired, counting this as an attempt. Pending logins with too many attempts are
+ * not returned.
+ */
+pub async fn get_pending_login(
+    token: &str,
+    pool: &RequestPool,
+) -> Result<UserPendingLogin, TotpError> {
+    sqlx::query_as::<_, UserPendingLogin>(
+        "UPDATE user_pending_logins SET attempts = attempts + 1
+        WHERE token = $1 AND expires_at > now() AND attempts < $2
+        RETURNING id, user_id, enrollment_required",
+    )
+    .bind(token)
+    .bind(PENDING_LOGIN_MAX_ATTEMPTS)
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| TotpError::Internal)?
+    .ok_or(TotpError::PendingLoginNotFound)
+}
+
+pub async fn delete_pending_login(pending_login_id: i32, pool: &RequestPool) {
+    let _ = sqlx::query("DELETE FROM user_pending_logins WHERE id = $1")
+        .bind(pending_login_id)
+        .execute(pool)
+        .await;
+}
+
+/**
+ * Find out who is setting up two-factor authentication: either a logged in user (API tokens are not accepted), or
+ * a user who has to set it up to finish logging in.
+ */
+pub async fn get_enrolling_client(
+    pending_token: Option<&str>,
+    req: &HttpRequest,
+    pool: &RequestPool,
+) -> Result<TotpEnrollingClient, TotpError> {
+    if let Some(pending_token) = pending_token {
+        let pending_login = get_pending_login(pending_token, pool).await?;
+
+        if !pending_login.enrollment_required {
+            return Err(TotpError::PendingLoginNotFound);
+        }
+
+        let username = sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = $1")
+            .bind(pending_login.user_id)
+            .fetch_one(pool)
+            .await
+            .map_err(|_| TotpError::Internal)?;
+
+        return Ok(TotpEnrollingClient {
+            user_id: pending_login.user_id,
+            username,
+            session_id: None,
+            pending_login_id: Some(pending_login.id),
+        });
+    }
+
+    match get_user_from_request(pool, req).await {
+        Some((user, session)) if !session.is_api_token() => Ok(TotpEnrollingClient {
+            user_id: user.id,
+            username: user.username,
+            session_id: Some(session.id),
+            pending_login_id: None,
+        }),
+        _ => Err(TotpError::Unauthorized),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA-1 secret from the RFC 6238 test vectors ("12345678901234567890")
+    const RFC_6238_SECRET: &str = "3132333435363738393031323334353637383930";
+
+    fn test_totp(last_used_step: Option<i64>) -> UserTotp {
+        UserTotp {
+            user_id: 1,
+            secret: RFC_6238_SECRET.to_string(),
+            enabled: true,
+            last_used_step,
+        }
+    }
+
+    #[test]
+    fn generates_rfc_6238_codes() {
+        let secret = hex::decode(RFC_6238_SECRET).unwrap();
+
+        // The RFC lists 8 digit codes, we use the last 6 of them
+        let vectors = [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+            (20000000000, 353130),
+        ];
+
+        for (time, code) in vectors {
+            assert_eq!(generate_code(&secret, time / TOTP_STEP_SECONDS), code);
+        }
+    }
+
+    #[test]
+    fn accepts_codes_within_the_drift_window() {
+        let totp = test_totp(None);
+        let step = 1234567890 / TOTP_STEP_SECONDS;
+
+        assert_eq!(verify_code_at(&totp, "005924", step), Some(step));
+        assert_eq!(verify_code_at(&totp, "005924", step - 1), Some(step));
+        assert_eq!(verify_code_at(&totp, "005924", step + 1), Some(step));
+        assert_eq!(verify_code_at(&totp, "005924", step - 2), None);
+        assert_eq!(verify_code_at(&totp, "005924", step + 2), None);
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let totp = test_totp(None);
+        let step = 1234567890 / TOTP_STEP_SECONDS;
+
+        assert_eq!(verify_code_at(&totp, " 005 924 ", step), Some(step));
+        assert_eq!(verify_code_at(&totp, "5924", step), None);
+        assert_eq!(verify_code_at(&totp, "0005924", step), None);
+        assert_eq!(verify_code_at(&totp, "+05924", step), None);
+    }
+
+    #[test]
+    fn rejects_replayed_codes() {
+        let step = 1234567890 / TOTP_STEP_SECONDS;
+
+        assert_eq!(verify_code_at(&test_totp(Some(step)), "005924", step), None);
+        assert_eq!(
+            verify_code_at(&test_totp(Some(step + 1)), "005924", step),
+            None
+        );
+        assert_eq!(
+            verify_code_at(&test_totp(Some(step - 1)), "005924", step),
+            Some(step)
+        );
+    }
+
+    #[test]
+    fn normalizes_recovery_codes_before_hashing() {
+        let hash = hash_recovery_code("abcde-12345");
+
+        assert_eq!(hash, hex::encode(Sha256::digest("abcde12345".as_bytes())));
+        assert_eq!(hash_recovery_code(" ABCDE12345 "), hash);
+        assert_ne!(hash_recovery_code("abcde12346"), hash);
+    }
+}
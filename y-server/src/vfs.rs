@@ -3,21 +3,22 @@ use fuser::{
     ReplyDirectory, ReplyEntry, Request,
 };
 use futures::executor::block_on;
-use libc::{EEXIST, ENOENT, ENOSYS, RENAME_NOREPLACE};
+use libc::{EDQUOT, EEXIST, ENOENT, ENOSYS, RENAME_NOREPLACE};
 use log::*;
 use rand::Rng;
 use rustix::path::Arg;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
-use std::fs::{self, File, OpenOptions};
-use std::path::Path;
+use std::fs::{File, OpenOptions};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_layout::{sync_file_structure, LocalBlobLayout};
 use crate::util::RequestPool;
 use crate::vfs_util::{
-    vfs_create_entry, vfs_delete_entry_from_db, vfs_file_get_attr, vfs_file_read,
-    vfs_file_set_times, vfs_file_write, vfs_find_entry, vfs_get_entry,
+    vfs_create_entry, vfs_delete_blobs, vfs_delete_entry_from_db, vfs_delete_file_versions,
+    vfs_detach_shared_blob, vfs_file_get_attr, vfs_file_read, vfs_file_set_times, vfs_file_write,
+    vfs_find_entry, vfs_get_entry, vfs_get_file_size_limit, vfs_snapshot_file_version,
 };
 
 const TTL: Duration = Duration::from_secs(60 * 60); // 1 hour
@@ -28,15 +29,34 @@ struct YFS {
     db_pool: RequestPool,
 
     endpoint_id: i32,
-    endpoint_base_path: String,
+    blob_layout: LocalBlobLayout,
 
     // TODO Research a better way of storing file handles. Vec?
     file_handles: HashMap<u64, File>,
 
+    // Handles that were already used to write to a file. The contents of a file are saved as a version
+    // before the first write through each handle, not before every single write
+    written_file_handles: HashSet<u64>,
+
+    // How large the files opened for writing can grow before their owners run out of quota.
+    // Computed once per handle, so that writes don't have to query the database
+    file_size_limits: HashMap<u64, u64>,
+
     uid: u32,
     gid: u32,
 }
 
+impl YFS {
+    /// Keep the files on disk in line with the tree, if the endpoint preserves its file structure
+    fn sync_file_structure(&self, entry_ids: Vec<i64>) {
+        let _ = block_on(sync_file_structure(
+            &self.blob_layout,
+            &entry_ids,
+            &self.db_pool,
+        ));
+    }
+}
+
 // TODO! most input parameters are currently ignored!
 // TODO fix branch misses
 // and not all cases are handled. It's not robust at all, edge cases will just break the fs.
@@ -56,7 +76,7 @@ impl Filesystem for YFS {
         if let Ok(name) = name.as_str() {
             let attr = vfs_create_entry(
                 self.endpoint_id,
-                self.endpoint_base_path.as_str(),
+                &self.blob_layout,
                 name,
                 parent_folder_ino,
                 &mut self.db_pool,
@@ -85,22 +105,29 @@ impl Filesystem for YFS {
             );
 
             if let Ok(deleted_entry) = db_delete_result {
+                self.sync_file_structure(vec![]);
+
                 // Delete from the filesystem
                 if deleted_entry.filesystem_id.is_none() {
                     return reply.ok();
                 }
 
-                let file_path = Path::new(self.endpoint_base_path.as_str())
-                    .join(&deleted_entry.filesystem_id.unwrap());
+                vfs_delete_file_versions(
+                    self.endpoint_id,
+                    &self.blob_layout,
+                    deleted_entry.id,
+                    &mut self.db_pool,
+                );
 
-                let fs_delete_result = fs::remove_file(&file_path);
+                // The blob might be shared with other files, it's only removed once nothing references it
+                vfs_delete_blobs(
+                    self.endpoint_id,
+                    &self.blob_layout,
+                    &vec![deleted_entry.filesystem_id.unwrap()],
+                    &mut self.db_pool,
+                );
 
-                if fs_delete_result.is_ok() {
-                    reply.ok();
-                } else {
-                    // TODO not really a correct error code for this case
-                    reply.error(ENOSYS);
-                }
+                reply.ok();
             } else {
                 reply.error(ENOENT);
             }
@@ -121,6 +148,8 @@ impl Filesystem for YFS {
             );
 
             if delete_result.is_ok() {
+                self.sync_file_structure(vec![]);
+
                 reply.ok();
             } else {
                 reply.error(ENOENT);
@@ -130,7 +159,7 @@ impl Filesystem for YFS {
         }
     }
 
-    fn open(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: fuser::ReplyOpen) {
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
         let adjusted_ino = ino as i64 - 1;
 
         let entry = vfs_get_entry(self.endpoint_id, adjusted_ino, &mut self.db_pool);
@@ -141,8 +170,23 @@ impl Filesystem for YFS {
                 return reply.error(ENOENT);
             }
 
-            let file_path =
-                Path::new(self.endpoint_base_path.as_str()).join(&entry.filesystem_id.unwrap());
+            let mut filesystem_id = entry.filesystem_id.unwrap();
+
+            // Deduplicated blobs are shared between files, a file that is about to be modified needs its own copy
+            if flags & libc::O_ACCMODE != libc::O_RDONLY {
+                match vfs_detach_shared_blob(
+                    self.endpoint_id,
+                    &self.blob_layout,
+                    adjusted_ino,
+                    &filesystem_id,
+                    &mut self.db_pool,
+                ) {
+                    Ok(detached_filesystem_id) => filesystem_id = detached_filesystem_id,
+                    Err(_) => return reply.error(ENOSYS),
+                }
+            }
+
+            let file_path = block_on(self.blob_layout.blob_path(&filesystem_id, &self.db_pool));
             let file = OpenOptions::new()
                 .read(true)
                 .write(true)
@@ -153,6 +197,19 @@ impl Filesystem for YFS {
             if let Ok(file) = file {
                 let fh = rand::thread_rng().gen::<u64>();
 
+                if flags & libc::O_ACCMODE != libc::O_RDONLY {
+                    let current_size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+
+                    if let Some(size_limit) = vfs_get_file_size_limit(
+                        self.endpoint_id,
+                        adjusted_ino,
+                        current_size,
+                        &mut self.db_pool,
+                    ) {
+                        self.file_size_limits.insert(fh, size_limit);
+                    }
+                }
+
                 self.file_handles.insert(fh, file);
 
                 reply.opened(fh, 0);
@@ -179,6 +236,8 @@ impl Filesystem for YFS {
         }
 
         let file = self.file_handles.remove(&fh);
+        self.written_file_handles.remove(&fh);
+        self.file_size_limits.remove(&fh);
 
         // TODO not sure we need to sync here. OS *should* call flush before release
         if let Some(file) = file {
@@ -236,7 +295,7 @@ impl Filesystem for YFS {
             if let Ok(entry) = entry {
                 if let Some(filesystem_id) = entry.filesystem_id {
                     let file_path =
-                        Path::new(self.endpoint_base_path.as_str()).join(&filesystem_id);
+                        block_on(self.blob_layout.blob_path(&filesystem_id, &self.db_pool));
                     let mut file = File::open(&file_path).unwrap();
 
                     vfs_file_read(reply, &mut file, offset, size);
@@ -268,6 +327,30 @@ impl Filesystem for YFS {
             let file = self.file_handles.get_mut(&fh);
 
             if let Some(mut file) = file {
+                if let Some(size_limit) = self.file_size_limits.get(&fh) {
+                    if offset as u64 + data.len() as u64 > *size_limit {
+                        return reply.error(EDQUOT);
+                    }
+                }
+
+                // Keep the previous contents around. Empty files (e.g. ones that were just created) are not worth it
+                if !self.written_file_handles.contains(&fh)
+                    && file.metadata().map(|metadata| metadata.len()).unwrap_or(0) > 0
+                {
+                    let snapshot_result = vfs_snapshot_file_version(
+                        self.endpoint_id,
+                        &self.blob_layout,
+                        adjusted_ino,
+                        &mut self.db_pool,
+                    );
+
+                    if snapshot_result.is_err() {
+                        warn!("[VFS] Could not save a version of a file before writing to it. ino = {adjusted_ino}");
+                    }
+                }
+
+                self.written_file_handles.insert(fh);
+
                 vfs_file_write(
                     reply,
                     self.endpoint_id,
@@ -289,8 +372,22 @@ impl Filesystem for YFS {
                     return reply.error(ENOENT);
                 }
 
-                let file_path =
-                    Path::new(self.endpoint_base_path.as_str()).join(&entry.filesystem_id.unwrap());
+                let filesystem_id = vfs_detach_shared_blob(
+                    self.endpoint_id,
+                    &self.blob_layout,
+                    adjusted_ino,
+                    &entry.filesystem_id.unwrap(),
+                    &mut self.db_pool,
+                );
+
+                if filesystem_id.is_err() {
+                    return reply.error(ENOSYS);
+                }
+
+                let file_path = block_on(
+                    self.blob_layout
+                        .blob_path(&filesystem_id.unwrap(), &self.db_pool),
+                );
                 let mut file = OpenOptions::new()
                     .write(true)
                     .create(false)
@@ -298,6 +395,19 @@ impl Filesystem for YFS {
                     .open(&file_path)
                     .unwrap();
 
+                let current_size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+
+                if let Some(size_limit) = vfs_get_file_size_limit(
+                    self.endpoint_id,
+                    adjusted_ino,
+                    current_size,
+                    &mut self.db_pool,
+                ) {
+                    if offset as u64 + data.len() as u64 > size_limit {
+                        return reply.error(EDQUOT);
+                    }
+                }
+
                 vfs_file_write(
                     reply,
                     self.endpoint_id,
@@ -364,7 +474,7 @@ impl Filesystem for YFS {
                         // A file
 
                         let file_path =
-                            Path::new(self.endpoint_base_path.as_str()).join(&filesystem_id);
+                            block_on(self.blob_layout.blob_path(&filesystem_id, &self.db_pool));
                         let mut file = File::open(&file_path).unwrap();
 
                         let attr = vfs_file_get_attr(&mut file, ino);
@@ -448,7 +558,7 @@ impl Filesystem for YFS {
             if let Ok(entry) = entry {
                 if let Some(filesystem_id) = entry.filesystem_id {
                     let file_path =
-                        Path::new(self.endpoint_base_path.as_str()).join(&filesystem_id);
+                        block_on(self.blob_layout.blob_path(&filesystem_id, &self.db_pool));
                     let mut file = File::open(&file_path).unwrap();
 
                     let mut attr = vfs_file_get_attr(&mut file, ino);
@@ -488,7 +598,7 @@ impl Filesystem for YFS {
             if let Some(filesystem_id) = entry.filesystem_id {
                 // A file
 
-                let file_path = Path::new(self.endpoint_base_path.as_str()).join(&filesystem_id);
+                let file_path = block_on(self.blob_layout.blob_path(&filesystem_id, &self.db_pool));
                 let mut file = File::open(&file_path).unwrap();
 
                 let attr = vfs_file_get_attr(&mut file, entry.id as u64 + 1);
@@ -632,6 +742,8 @@ impl Filesystem for YFS {
         );
 
         if let Ok(new_dir_ino) = mkdir_result {
+            self.sync_file_structure(vec![new_dir_ino]);
+
             reply.entry(
                 &TTL,
                 &FileAttr {
@@ -727,8 +839,10 @@ impl Filesystem for YFS {
         let rename_result = block_on(rename_query.fetch_one(&self.db_pool));
 
         match rename_result {
-            Ok(_) => {
+            Ok(renamed_entry_id) => {
                 // Rename successful
+                self.sync_file_structure(renamed_entry_id.into_iter().collect());
+
                 reply.ok();
             }
             Err(_) => {
@@ -750,19 +864,16 @@ impl Filesystem for YFS {
                     );
 
                     if let Ok(conflicting_entry) = db_delete_conflicting_result {
-                        // Delete from the filesystem
-                        if conflicting_entry.filesystem_id.is_some() {
-                            let conflicting_file_path = Path::new(self.endpoint_base_path.as_str())
-                                .join(&conflicting_entry.filesystem_id.unwrap());
-
-                            let fs_delete_result = fs::remove_file(&conflicting_file_path);
+                        self.sync_file_structure(vec![]);
 
-                            if fs_delete_result.is_err() {
-                                error!(
-                                    "[VFS] Could not delete a rename conflicting file from the filesystem: {:?}",
-                                    fs_delete_result
-                                );
-                            }
+                        // Delete from the filesystem
+                        if let Some(conflicting_filesystem_id) = conflicting_entry.filesystem_id {
+                            vfs_delete_blobs(
+                                self.endpoint_id,
+                                &self.blob_layout,
+                                &vec![conflicting_filesystem_id],
+                                &mut self.db_pool,
+                            );
                         }
 
                         // TODO! duplicated code
@@ -788,7 +899,9 @@ impl Filesystem for YFS {
                             block_on(retry_rename_query.fetch_one(&self.db_pool));
 
                         match retry_rename_result {
-                            Ok(_) => {
+                            Ok(renamed_entry_id) => {
+                                self.sync_file_structure(renamed_entry_id.into_iter().collect());
+
                                 reply.ok();
                             }
                             Err(_) => {
@@ -823,9 +936,11 @@ pub fn vfs_mount(
             db_pool,
 
             endpoint_id,
-            endpoint_base_path: endpoint.base_path,
+            blob_layout: LocalBlobLayout::new(&endpoint),
 
             file_handles,
+            written_file_handles: HashSet::new(),
+            file_size_limits: HashMap::new(),
 
             uid,
             gid,
@@ -20,7 +20,10 @@ pub async fn mount_vfs_endpoints(state: &mut VFSState, pool: &mut RequestPool) {
     }
 
     let endpoints = sqlx::query_as::<_, EndpointConfig>(
-        "SELECT endpoint_id, writable, mountpoint FROM storage_vfs WHERE enabled IS TRUE",
+        // The VFS works with the blobs on the local filesystem directly, so only local endpoints can be mounted
+        "SELECT storage_vfs.endpoint_id, storage_vfs.writable, storage_vfs.mountpoint FROM storage_vfs
+        INNER JOIN storage_endpoints ON storage_endpoints.id = storage_vfs.endpoint_id
+        WHERE storage_vfs.enabled IS TRUE AND storage_endpoints.endpoint_type = 'local_fs'::storage_endpoint_type",
     )
     .fetch_all(&*pool)
     .await;
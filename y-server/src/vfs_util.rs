@@ -4,13 +4,17 @@ use libc::ENOENT;
 use log::*;
 use std::os::unix::fs::MetadataExt;
 use std::{
-    fs::{File, FileTimes},
+    fs::{self, File, FileTimes},
     io::{Read, Seek, SeekFrom, Write},
-    path::Path,
     time::SystemTime,
 };
 use uuid::Uuid;
 
+use crate::storage_blobs::{get_unreferenced_blobs, is_blob_shared};
+use crate::storage_endpoint::get_storage_endpoint;
+use crate::storage_layout::{sync_file_structure, LocalBlobLayout};
+use crate::storage_quotas::get_user_storage_quota;
+use crate::storage_versions::snapshot_entry_version;
 use crate::vfs::{BLOCKSIZE, PERM};
 use crate::{storage_entry::StorageError, util::RequestPool};
 
@@ -74,7 +78,7 @@ pub fn vfs_find_entry(
 /// Create a new storage entry. Add it to the database and create a new file on the disk
 pub fn vfs_create_entry(
     endpoint_id: i32,
-    endpoint_base_path: &str,
+    blob_layout: &LocalBlobLayout,
     name: &str,
     parent_folder: i64,
     pool: &mut RequestPool,
@@ -89,7 +93,7 @@ pub fn vfs_create_entry(
 
     let filesystem_id = Uuid::new_v4().to_string();
 
-    let file_path = Path::new(endpoint_base_path).join(&filesystem_id);
+    let file_path = blob_layout.new_blob_path(&filesystem_id);
     let file = File::create(&file_path);
 
     if let Ok(mut file) = file {
@@ -117,6 +121,9 @@ pub fn vfs_create_entry(
 
         match entry_result {
             Ok(entry_id) => {
+                // The file stays open while it's moved under its real path
+                let _ = block_on(sync_file_structure(blob_layout, &vec![entry_id], &*pool));
+
                 let attr = vfs_file_get_attr(&mut file, entry_id as u64 + 1);
 
                 Ok(attr)
@@ -298,7 +305,7 @@ pub fn vfs_file_write(
 
         let _ = block_on(
             sqlx::query(
-                "UPDATE storage_entries SET size_bytes = $1 WHERE endpoint_id = $2 AND id = $3",
+                "UPDATE storage_entries SET size_bytes = $1, sha256 = NULL WHERE endpoint_id = $2 AND id = $3",
             )
             .bind(new_size as i64)
             .bind(endpoint_id)
@@ -309,3 +316,193 @@ pub fn vfs_file_write(
         reply.error(ENOENT);
     }
 }
+
+/// Keep a copy of a file's current contents as a version, before it gets modified in place.
+///
+/// Does nothing if versioning is disabled for the endpoint.
+pub fn vfs_snapshot_file_version(
+    endpoint_id: i32,
+    blob_layout: &LocalBlobLayout,
+    ino: i64,
+    pool: &mut RequestPool,
+) -> Result<(), StorageError> {
+    let endpoint = block_on(get_storage_endpoint(endpoint_id, &*pool));
+
+    if endpoint.is_err() {
+        return Err(StorageError::EndpointNotFound);
+    }
+
+    let endpoint = endpoint.unwrap();
+
+    if endpoint.max_file_versions <= 0 {
+        return Ok(());
+    }
+
+    let entry = vfs_get_entry(endpoint_id, ino, pool);
+
+    if entry.is_err() {
+        return Err(StorageError::Internal);
+    }
+
+    let filesystem_id = entry.unwrap().filesystem_id;
+
+    if filesystem_id.is_none() {
+        return Err(StorageError::Internal);
+    }
+
+    let snapshot_filesystem_id = Uuid::new_v4().to_string();
+    let snapshot_path = blob_layout.new_blob_path(&snapshot_filesystem_id);
+
+    let copy_result = fs::copy(
+        block_on(blob_layout.blob_path(&filesystem_id.unwrap(), &*pool)),
+        &snapshot_path,
+    );
+
+    if let Err(err) = copy_result {
+        error!("[VFS] Failed to copy a file to create a version of it: {err}");
+        return Err(StorageError::Internal);
+    }
+
+    let snapshot_result = block_on(snapshot_entry_version(
+        &endpoint,
+        ino,
+        &snapshot_filesystem_id,
+        &*pool,
+    ));
+
+    if snapshot_result.is_err() {
+        fs::remove_file(&snapshot_path).unwrap_or(());
+    }
+
+    snapshot_result
+}
+
+/// Delete all previous versions of a file, together with their blobs on the disk
+pub fn vfs_delete_file_versions(
+    endpoint_id: i32,
+    blob_layout: &LocalBlobLayout,
+    ino: i64,
+    pool: &mut RequestPool,
+) {
+    let delete_result = block_on(
+        sqlx::query_scalar::<_, String>(
+            "DELETE FROM storage_entry_versions WHERE endpoint_id = $1 AND entry_id = $2 RETURNING filesystem_id",
+        )
+        .bind(endpoint_id)
+        .bind(ino)
+        .fetch_all(&*pool),
+    );
+
+    match delete_result {
+        Ok(filesystem_ids) => {
+            vfs_delete_blobs(endpoint_id, blob_layout, &filesystem_ids, pool);
+        }
+        Err(err) => {
+            error!("[VFS] Failed to delete versions of a file from the database: {err}");
+        }
+    }
+}
+
+/// Remove blobs of deleted files from the disk, unless they are still referenced by something else (deduplicated files, versions, trash)
+pub fn vfs_delete_blobs(
+    endpoint_id: i32,
+    blob_layout: &LocalBlobLayout,
+    filesystem_ids: &Vec<String>,
+    pool: &mut RequestPool,
+) {
+    match block_on(get_unreferenced_blobs(endpoint_id, filesystem_ids, &*pool)) {
+        Ok(unreferenced_filesystem_ids) => {
+            for filesystem_id in unreferenced_filesystem_ids {
+                fs::remove_file(block_on(blob_layout.blob_path(&filesystem_id, &*pool)))
+                    .unwrap_or(());
+            }
+        }
+        Err(_) => {
+            error!("[VFS] Could not check whether blobs of deleted files are still in use");
+        }
+    }
+}
+
+/// Give a file its own copy of a blob that is shared with other files (or versions), so that it can be modified in place.
+///
+/// @returns filesystem_id of the blob that the file points to now
+pub fn vfs_detach_shared_blob(
+    endpoint_id: i32,
+    blob_layout: &LocalBlobLayout,
+    ino: i64,
+    filesystem_id: &str,
+    pool: &mut RequestPool,
+) -> Result<String, StorageError> {
+    if !block_on(is_blob_shared(endpoint_id, filesystem_id, ino, &*pool))? {
+        return Ok(filesystem_id.to_string());
+    }
+
+    let detached_filesystem_id = Uuid::new_v4().to_string();
+    let detached_path = blob_layout.new_blob_path(&detached_filesystem_id);
+
+    if let Err(err) = fs::copy(
+        block_on(blob_layout.blob_path(filesystem_id, &*pool)),
+        &detached_path,
+    ) {
+        error!("[VFS] Failed to copy a shared blob: {err}");
+        return Err(StorageError::Internal);
+    }
+
+    let update_result = block_on(
+        sqlx::query(
+            "UPDATE storage_entries SET filesystem_id = $1 WHERE endpoint_id = $2 AND id = $3 AND filesystem_id = $4",
+        )
+        .bind(&detached_filesystem_id)
+        .bind(endpoint_id)
+        .bind(ino)
+        .bind(filesystem_id)
+        .execute(&*pool),
+    );
+
+    match update_result {
+        Ok(result) if result.rows_affected() == 1 => {
+            // The copy takes the place of the shared blob under the file's path
+            let _ = block_on(sync_file_structure(blob_layout, &vec![ino], &*pool));
+
+            Ok(detached_filesystem_id)
+        }
+        _ => {
+            fs::remove_file(&detached_path).unwrap_or(());
+
+            Err(StorageError::Internal)
+        }
+    }
+}
+
+/// Find out how large a file can grow before its owner runs out of quota
+///
+/// @returns `None` if there is no limit (including files that were created through the VFS, which have no owner)
+pub fn vfs_get_file_size_limit(
+    endpoint_id: i32,
+    ino: i64,
+    current_size: u64,
+    pool: &mut RequestPool,
+) -> Option<u64> {
+    let owner = block_on(
+        sqlx::query_scalar::<_, Option<i32>>(
+            "SELECT created_by FROM storage_entries WHERE endpoint_id = $1 AND id = $2",
+        )
+        .bind(endpoint_id)
+        .bind(ino)
+        .fetch_one(&*pool),
+    )
+    .ok()
+    .flatten()?;
+
+    let endpoint = block_on(get_storage_endpoint(endpoint_id, &*pool)).ok()?;
+
+    match block_on(get_user_storage_quota(&endpoint, owner, &*pool)) {
+        Ok(quota) => quota
+            .remaining_bytes
+            .map(|remaining_bytes| current_size + remaining_bytes as u64),
+        Err(_) => {
+            error!("[VFS] Could not get the storage quota of a file's owner. ino = {ino}");
+            None
+        }
+    }
+}
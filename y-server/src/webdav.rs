@@ -0,0 +1,741 @@
+use actix_web::{http::header, http::StatusCode, HttpRequest, HttpResponse};
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use log::*;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::{
+    audit_log::AuditActor,
+    storage_access::{
+        check_bulk_storage_entries_access_cascade_up, check_endpoint_root_access,
+        check_storage_entry_access,
+    },
+    storage_entry::{delete_entries, StorageError},
+    storage_layout::sync_endpoint_file_structure,
+    user::{
+        get_group_rights, get_user_from_api_token, get_user_from_request, get_user_groups,
+        verify_user_password,
+    },
+    user_totp::{is_two_factor_enabled, is_two_factor_required},
+    util::RequestPool,
+};
+
+pub const WEBDAV_BASE_PATH: &str = "/api/webdav";
+
+// Everything except RFC 3986 unreserved characters gets percent-encoded inside of a path segment
+const PATH_SEGMENT_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+pub struct WebDAVClient {
+    pub user_id: i32,
+    pub group_ids: Vec<i32>,
+
+    /**
+     * `None` if the client was authenticated with HTTP Basic authentication and a password
+     */
+    pub session_id: Option<i32>,
+}
+
+impl WebDAVClient {
+    pub fn audit_actor(&self) -> AuditActor {
+        AuditActor {
+            user_id: self.user_id,
+            session_id: self.session_id,
+        }
+    }
+}
+
+#[derive(FromRow)]
+pub struct WebDAVEntry {
+    pub id: i64,
+    pub parent_folder: Option<i64>,
+    pub entry_type: String,
+    pub filesystem_id: Option<String>,
+    pub name: String,
+    pub extension: Option<String>,
+    pub mime_type: Option<String>,
+    pub size_bytes: Option<i64>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl WebDAVEntry {
+    pub fn is_folder(&self) -> bool {
+        self.entry_type == "folder"
+    }
+
+    /// Name of the entry as it is presented to WebDAV clients (with the extension, if any)
+    pub fn full_name(&self) -> String {
+        match &self.extension {
+            Some(extension) if !self.is_folder() => format!("{}.{}", self.name, extension),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+pub enum WebDAVResource {
+    /// Root of the endpoint
+    Root,
+
+    /// Existing file or folder
+    Entry(WebDAVEntry),
+
+    /// The last segment of the path does not exist, but its parent folder does
+    Missing {
+        parent_folder: Option<i64>,
+        name: String,
+    },
+
+    /// One of the intermediate segments of the path does not exist (or is not a folder)
+    MissingParent,
+}
+
+/// Split a full file name into the name and the extension, the same way uploaded files are split
+pub fn split_entry_name(full_name: &str) -> (&str, Option<&str>) {
+    let name_separator = full_name.rfind('.').unwrap_or(full_name.len());
+    let (name, extension) = full_name.split_at(name_separator);
+
+    if !extension.is_empty() {
+        (name, Some(extension.trim_start_matches('.')))
+    } else {
+        (name, None)
+    }
+}
+
+struct WebDAVLoginFailures {
+    count: u32,
+    first_failed_at: Instant,
+}
+
+/**
+ * Failed HTTP Basic logins, keyed by `ip:<address>` and `user:<username>`
+ */
+static WEBDAV_LOGIN_FAILURES: Mutex<BTreeMap<String, WebDAVLoginFailures>> =
+    Mutex::new(BTreeMap::new());
+
+/**
+ * Recently verified HTTP Basic credentials. The key is the SHA-256 of `<username>:<password>`, the value is the id
+ * of the user and the time when the password was verified.
+ */
+static WEBDAV_VERIFIED_CREDENTIALS: Mutex<BTreeMap<String, (i32, Instant)>> =
+    Mutex::new(BTreeMap::new());
+
+// Failed logins that are allowed per client address and per username before HTTP Basic authentication is refused
+const WEBDAV_MAX_LOGIN_FAILURES: u32 = 10;
+const WEBDAV_LOGIN_FAILURES_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+// File managers send the credentials with every request, so we don't want to run PBKDF2 for each one of them
+const WEBDAV_VERIFIED_CREDENTIALS_LIFETIME: Duration = Duration::from_secs(2 * 60);
+
+fn webdav_login_blocked(failure_keys: &[String]) -> bool {
+    let failures = WEBDAV_LOGIN_FAILURES.lock().unwrap();
+
+    failure_keys.iter().any(|key| {
+        failures.get(key).is_some_and(|f| {
+            f.count >= WEBDAV_MAX_LOGIN_FAILURES
+                && f.first_failed_at.elapsed() < WEBDAV_LOGIN_FAILURES_WINDOW
+        })
+    })
+}
+
+fn webdav_record_login_failure(failure_keys: &[String]) {
+    let mut failures = WEBDAV_LOGIN_FAILURES.lock().unwrap();
+
+    failures.retain(|_, f| f.first_failed_at.elapsed() < WEBDAV_LOGIN_FAILURES_WINDOW);
+
+    for key in failure_keys {
+        failures
+            .entry(key.clone())
+            .or_insert(WebDAVLoginFailures {
+                count: 0,
+                first_failed_at: Instant::now(),
+            })
+            .count += 1;
+    }
+}
+
+/**
+ * Authenticate a WebDAV client with HTTP Basic credentials.
+ *
+ * The password can be either the user's password or one of their personal API tokens. Users with two-factor
+ * authentication can only use API tokens.
+ *
+ * @returns id of the user and the id of the API token session, if an API token was used
+ */
+async fn webdav_basic_login(
+    pool: &RequestPool,
+    req: &HttpRequest,
+    username: &str,
+    password: &str,
+) -> Option<(i32, Option<i32>)> {
+    let client_address = req
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or_default()
+        .to_string();
+    let failure_keys = [
+        format!("ip:{}", client_address),
+        format!("user:{}", username),
+    ];
+
+    if webdav_login_blocked(&failure_keys) {
+        warn!(
+            "Refusing WebDAV login of \"{}\" from {}: too many failed attempts",
+            username, client_address
+        );
+        return None;
+    }
+
+    if let Some((user, session)) = get_user_from_api_token(pool, password, req).await {
+        if user.username == username {
+            return Some((user.id, Some(session.id)));
+        }
+    }
+
+    let credentials_hash = hex::encode(Sha256::digest(format!("{}:{}", username, password)));
+    let cached_user_id = WEBDAV_VERIFIED_CREDENTIALS
+        .lock()
+        .unwrap()
+        .get(&credentials_hash)
+        .filter(|(_, verified_at)| verified_at.elapsed() < WEBDAV_VERIFIED_CREDENTIALS_LIFETIME)
+        .map(|(user_id, _)| *user_id);
+
+    if let Some(user_id) = cached_user_id {
+        return Some((user_id, None));
+    }
+
+    let user_id = verify_user_password(pool, username, password)
+        .await
+        .map(|user| user.id);
+
+    if user_id.is_none() {
+        webdav_record_login_failure(&failure_keys);
+        return None;
+    }
+
+    let user_id = user_id.unwrap();
+
+    if is_two_factor_required(user_id, pool).await
+        || is_two_factor_enabled(user_id, pool).await.unwrap_or(true)
+    {
+        return None;
+    }
+
+    let mut verified_credentials = WEBDAV_VERIFIED_CREDENTIALS.lock().unwrap();
+
+    verified_credentials
+        .retain(|_, (_, verified_at)| verified_at.elapsed() < WEBDAV_VERIFIED_CREDENTIALS_LIFETIME);
+    verified_credentials.insert(credentials_hash, (user_id, Instant::now()));
+
+    Some((user_id, None))
+}
+
+/**
+ * Authenticate a WebDAV client.
+ *
+ * Browsers (and anything else that can hold a cookie) are authenticated with the regular `y-session` cookie.
+ * Desktop file managers and sync tools can not log in through the web interface, so we also accept
+ * HTTP Basic authentication with the user's username and either their password or a personal API token. Users with
+ * two-factor authentication have to use an API token, since a password alone is not enough for them.
+ *
+ * Verified passwords are remembered for a short while, and HTTP Basic authentication is refused for a client address
+ * or a username after too many failed attempts.
+ */
+pub async fn webdav_get_client(pool: &RequestPool, req: &HttpRequest) -> Option<WebDAVClient> {
+    let client = if let Some((user, session)) = get_user_from_request(pool, req).await {
+        Some((user.id, Some(session.id)))
+    } else {
+        let credentials = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|value| BASE64_STANDARD.decode(value.trim()).ok())
+            .and_then(|value| String::from_utf8(value).ok());
+
+        match credentials.as_deref().and_then(|c| c.split_once(':')) {
+            Some((username, password)) => webdav_basic_login(pool, req, username, password).await,
+            None => None,
+        }
+    };
+
+    if let Some((user_id, session_id)) = client {
+        let user_groups = get_user_groups(pool, user_id).await;
+        let group_ids = user_groups.iter().map(|g| g.id).collect::<Vec<i32>>();
+
+        Some(WebDAVClient {
+            user_id,
+            group_ids,
+            session_id,
+        })
+    } else {
+        None
+    }
+}
+
+/**
+ * Check if a WebDAV client can perform an action on an entry.
+ *
+ * @param entry_id id of the target entry. `None` means the root of the endpoint.
+ */
+pub async fn webdav_check_access(
+    endpoint_id: i32,
+    entry_id: Option<i64>,
+    action: &str,
+    client: &WebDAVClient,
+    pool: &RequestPool,
+) -> bool {
+    if let Some(entry_id) = entry_id {
+        check_storage_entry_access(
+            endpoint_id,
+            entry_id,
+            action,
+            client.user_id,
+            &client.group_ids,
+            pool,
+        )
+        .await
+    } else {
+        let group_rights = get_group_rights(pool, &client.group_ids).await;
+
+        check_endpoint_root_access(endpoint_id, group_rights)
+    }
+}
+
+/**
+ * Find an entry that a WebDAV path points to.
+ *
+ * WebDAV clients address entries by their full path (`/folder/subfolder/file.txt`), so we have to walk
+ * the path segment by segment, starting from the root of the endpoint.
+ *
+ * @param path path relative to the root of the endpoint. Already percent-decoded.
+ */
+pub async fn webdav_resolve_path(
+    endpoint_id: i32,
+    path: &str,
+    pool: &RequestPool,
+) -> Result<WebDAVResource, StorageError> {
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<&str>>();
+
+    if segments.is_empty() {
+        return Ok(WebDAVResource::Root);
+    }
+
+    let mut parent_folder: Option<i64> = None;
+
+    for (i, segment) in segments.iter().enumerate() {
+        let is_last_segment = i == segments.len() - 1;
+        let (name, extension) = split_entry_name(segment);
+
+        // A segment can either be a folder (the whole segment is the folder's name) or a file
+        // (the segment is split into the name and the extension). Folders take precedence.
+        let entry = sqlx::query_as::<_, WebDAVEntry>(
+            "SELECT id, parent_folder, entry_type::TEXT, filesystem_id, name, extension, mime_type, size_bytes, created_at FROM storage_entries
+            WHERE endpoint_id = $1
+            AND parent_folder IS NOT DISTINCT FROM $2
+            AND (
+                (entry_type = 'folder'::storage_entry_type AND name = $3)
+                OR
+                (entry_type = 'file'::storage_entry_type AND name = $4 AND extension IS NOT DISTINCT FROM $5)
+            )
+            ORDER BY entry_type = 'folder'::storage_entry_type DESC
+            LIMIT 1",
+        )
+        .bind(endpoint_id)
+        .bind(parent_folder)
+        .bind(segment)
+        .bind(name)
+        .bind(extension)
+        .fetch_optional(pool)
+        .await;
+
+        match entry {
+            Ok(Some(entry)) => {
+                if is_last_segment {
+                    return Ok(WebDAVResource::Entry(entry));
+                }
+
+                if !entry.is_folder() {
+                    return Ok(WebDAVResource::MissingParent);
+                }
+
+                parent_folder = Some(entry.id);
+            }
+            Ok(None) => {
+                if is_last_segment {
+                    return Ok(WebDAVResource::Missing {
+                        parent_folder,
+                        name: segment.to_string(),
+                    });
+                }
+
+                return Ok(WebDAVResource::MissingParent);
+            }
+            Err(_) => return Err(StorageError::Internal),
+        }
+    }
+
+    Ok(WebDAVResource::Root)
+}
+
+/// List entries inside of a folder. `None` means the root of the endpoint
+pub async fn webdav_get_folder_entries(
+    endpoint_id: i32,
+    folder_id: Option<i64>,
+    pool: &RequestPool,
+) -> Result<Vec<WebDAVEntry>, StorageError> {
+    sqlx::query_as::<_, WebDAVEntry>(
+        "SELECT id, parent_folder, entry_type::TEXT, filesystem_id, name, extension, mime_type, size_bytes, created_at FROM storage_entries WHERE endpoint_id = $1 AND parent_folder IS NOT DISTINCT FROM $2",
+    )
+    .bind(endpoint_id)
+    .bind(folder_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|_| StorageError::Internal)
+}
+
+/**
+ * Extract the target path from the `Destination` header of a MOVE or COPY request.
+ *
+ * @returns path relative to the root of the endpoint (percent-decoded). None if the header is missing
+ * or points to a different endpoint (or outside of WebDAV altogether).
+ */
+pub fn webdav_destination_path(req: &HttpRequest, endpoint_id: i32) -> Option<String> {
+    let destination = req
+        .headers()
+        .get("Destination")
+        .and_then(|value| value.to_str().ok())?;
+
+    let endpoint_prefix = format!("{}/{}/", WEBDAV_BASE_PATH, endpoint_id);
+    let prefix_start = destination.find(&endpoint_prefix)?;
+
+    let path = &destination[prefix_start + endpoint_prefix.len()..];
+
+    percent_decode_str(path)
+        .decode_utf8()
+        .ok()
+        .map(|path| path.to_string())
+}
+
+/**
+ * An entry that used to be at the destination of a MOVE or COPY request. It is only renamed until the request is done,
+ * see `webdav_finish_replace`.
+ */
+pub struct WebDAVReplacedEntry {
+    entry: WebDAVEntry,
+}
+
+/**
+ * Resolve the destination of a MOVE or COPY request and make room for the source entry there.
+ *
+ * If something already exists at the destination (and the client allows overwriting and has the rights to delete it),
+ * it is renamed to a temporary name. `webdav_finish_replace` must be called once the request is done.
+ *
+ * @param source_path path of the source entry relative to the root of the endpoint.
+ * @param rename_parent_folder for MOVE requests, the folder the source entry currently resides in. Renaming
+ * an entry inside of its own folder does not require the right to upload into that folder. None for COPY requests.
+ *
+ * @returns (target folder, new full name of the entry, the entry that is being overwritten) or the
+ * status code that should be returned to the client.
+ */
+pub async fn webdav_prepare_destination(
+    endpoint_id: i32,
+    source_path: &str,
+    rename_parent_folder: Option<Option<i64>>,
+    client: &WebDAVClient,
+    req: &HttpRequest,
+    pool: &RequestPool,
+) -> Result<(Option<i64>, String, Option<WebDAVReplacedEntry>), StatusCode> {
+    let destination_path = webdav_destination_path(req, endpoint_id);
+
+    if destination_path.is_none() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let destination_path = destination_path.unwrap();
+
+    // An entry can not be moved or copied inside of itself, and can not replace one of its own parents
+    let source_path = source_path.trim_matches('/');
+    let trimmed_destination_path = destination_path.trim_matches('/');
+
+    if trimmed_destination_path == source_path
+        || trimmed_destination_path.starts_with(format!("{}/", source_path).as_str())
+        || source_path.starts_with(format!("{}/", trimmed_destination_path).as_str())
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let destination = webdav_resolve_path(endpoint_id, &destination_path, pool).await;
+
+    let target_folder = match &destination {
+        Ok(WebDAVResource::Missing { parent_folder, .. }) => *parent_folder,
+        Ok(WebDAVResource::Entry(entry)) => entry.parent_folder,
+        Ok(WebDAVResource::Root) => return Err(StatusCode::FORBIDDEN),
+        Ok(WebDAVResource::MissingParent) => return Err(StatusCode::CONFLICT),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    // Check the target folder before we touch anything at the destination
+    if rename_parent_folder != Some(target_folder)
+        && !webdav_check_access(endpoint_id, target_folder, "upload", client, pool).await
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    match destination {
+        Ok(WebDAVResource::Missing {
+            parent_folder,
+            name,
+        }) => Ok((parent_folder, name, None)),
+
+        Ok(WebDAVResource::Entry(entry)) => {
+            if !webdav_overwrite_allowed(req) {
+                return Err(StatusCode::PRECONDITION_FAILED);
+            }
+
+            let delete_allowed = check_bulk_storage_entries_access_cascade_up(
+                endpoint_id,
+                &vec![entry.id],
+                "delete",
+                client.user_id,
+                &client.group_ids,
+                pool,
+            )
+            .await;
+
+            if !delete_allowed {
+                return Err(StatusCode::FORBIDDEN);
+            }
+
+            // Kept out of the way (and out of sight of most clients) until the request is done
+            let temporary_name = format!(".y-replaced-{}", Uuid::new_v4());
+
+            let rename_result = sqlx::query(
+                "UPDATE storage_entries SET name = $1, extension = NULL WHERE id = $2 AND endpoint_id = $3",
+            )
+            .bind(&temporary_name)
+            .bind(entry.id)
+            .bind(endpoint_id)
+            .execute(pool)
+            .await;
+
+            if rename_result.is_err() {
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+
+            sync_endpoint_file_structure(endpoint_id, &vec![entry.id], pool).await;
+
+            Ok((
+                entry.parent_folder,
+                entry.full_name(),
+                Some(WebDAVReplacedEntry { entry }),
+            ))
+        }
+
+        _ => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/**
+ * Get rid of the entry that a MOVE or COPY request has overwritten. If the request has failed, the entry gets its
+ * name back instead, as if nothing happened.
+ *
+ * @param succeeded whether the source entry is now at the destination
+ */
+pub async fn webdav_finish_replace(
+    endpoint_id: i32,
+    replaced: Option<WebDAVReplacedEntry>,
+    succeeded: bool,
+    client: &WebDAVClient,
+    pool: &RequestPool,
+) {
+    let Some(WebDAVReplacedEntry { entry }) = replaced else {
+        return;
+    };
+
+    if !succeeded {
+        let restore_result = sqlx::query(
+            "UPDATE storage_entries SET name = $1, extension = $2 WHERE id = $3 AND endpoint_id = $4",
+        )
+        .bind(&entry.name)
+        .bind(&entry.extension)
+        .bind(entry.id)
+        .bind(endpoint_id)
+        .execute(pool)
+        .await;
+
+        if let Err(err) = restore_result {
+            error!(
+                "(webdav) Could not restore an entry that was going to be overwritten. endpoint_id = {}, entry_id = {}. {}",
+                endpoint_id, entry.id, err
+            );
+        }
+
+        sync_endpoint_file_structure(endpoint_id, &vec![entry.id], pool).await;
+
+        return;
+    }
+
+    let (target_folders, target_files) = if entry.is_folder() {
+        (vec![entry.id], vec![])
+    } else {
+        (vec![], vec![entry.id])
+    };
+
+    let delete_result = delete_entries(
+        endpoint_id,
+        target_folders,
+        target_files,
+        Some((client.user_id, &client.group_ids)),
+        pool,
+    )
+    .await;
+
+    if delete_result.is_err() {
+        error!(
+            "(webdav) Could not delete an overwritten entry. endpoint_id = {}, entry_id = {}.",
+            endpoint_id, entry.id
+        );
+    }
+}
+
+/// Whether the client allows the destination of a MOVE or COPY to be overwritten (`Overwrite` header, defaults to `T`)
+pub fn webdav_overwrite_allowed(req: &HttpRequest) -> bool {
+    req.headers()
+        .get("Overwrite")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| !value.eq_ignore_ascii_case("F"))
+        .unwrap_or(true)
+}
+
+/// Build an href for a resource inside of an endpoint. Each path segment is percent-encoded
+pub fn webdav_href(endpoint_id: i32, segments: &[&str], is_folder: bool) -> String {
+    let mut href = format!("{}/{}/", WEBDAV_BASE_PATH, endpoint_id);
+
+    let encoded_segments = segments
+        .iter()
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT_ENCODE_SET).to_string())
+        .collect::<Vec<String>>();
+
+    href.push_str(encoded_segments.join("/").as_str());
+
+    if is_folder && !encoded_segments.is_empty() {
+        href.push('/');
+    }
+
+    href
+}
+
+pub fn webdav_xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/**
+ * Build a single `<D:response>` element of a PROPFIND multistatus response.
+ *
+ * @param entry None means the root of the endpoint.
+ */
+pub fn webdav_propfind_response(href: &str, entry: Option<&WebDAVEntry>) -> String {
+    let mut props = String::new();
+
+    match entry {
+        Some(entry) => {
+            props.push_str(
+                format!(
+                    "<D:displayname>{}</D:displayname>",
+                    webdav_xml_escape(entry.full_name().as_str())
+                )
+                .as_str(),
+            );
+
+            if entry.is_folder() {
+                props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+            } else {
+                props.push_str("<D:resourcetype/>");
+                props.push_str(
+                    format!(
+                        "<D:getcontentlength>{}</D:getcontentlength>",
+                        entry.size_bytes.unwrap_or(0)
+                    )
+                    .as_str(),
+                );
+                props.push_str(
+                    format!(
+                        "<D:getcontenttype>{}</D:getcontenttype>",
+                        webdav_xml_escape(
+                            entry
+                                .mime_type
+                                .as_deref()
+                                .unwrap_or("application/octet-stream")
+                        )
+                    )
+                    .as_str(),
+                );
+                props.push_str(format!("<D:getetag>\"{}\"</D:getetag>", entry.id).as_str());
+            }
+
+            if let Some(created_at) = entry.created_at {
+                props.push_str(
+                    format!(
+                        "<D:creationdate>{}</D:creationdate><D:getlastmodified>{}</D:getlastmodified>",
+                        created_at.to_rfc3339(),
+                        created_at.format("%a, %d %b %Y %H:%M:%S GMT")
+                    )
+                    .as_str(),
+                );
+            }
+        }
+        None => {
+            props.push_str("<D:displayname/><D:resourcetype><D:collection/></D:resourcetype>");
+        }
+    }
+
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        webdav_xml_escape(href),
+        props
+    )
+}
+
+pub fn webdav_multistatus(responses: Vec<String>) -> HttpResponse {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?><D:multistatus xmlns:D=\"DAV:\">{}</D:multistatus>",
+        responses.join("")
+    );
+
+    HttpResponse::build(StatusCode::MULTI_STATUS)
+        .content_type("application/xml; charset=utf-8")
+        .body(body)
+}
+
+pub fn webdav_status(status: StatusCode) -> HttpResponse {
+    HttpResponse::build(status).finish()
+}
+
+pub fn webdav_unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((
+            header::WWW_AUTHENTICATE,
+            "Basic realm=\"y\", charset=\"UTF-8\"",
+        ))
+        .finish()
+}
@@ -40,19 +40,25 @@ pub struct WSState {
 #[allow(dead_code)]
 impl WSState {
     pub async fn send_to_user(&mut self, user_id: i32, message: &str) -> u32 {
-        let mut receivers = 0;
+        let messages = self.get_user_messages(user_id, message);
 
-        for session in self.ws_connections.values_mut() {
-            if session.user_id == Some(user_id) {
-                let result = session.ws_session.text(message).await;
-
-                if result.is_ok() {
-                    receivers += 1;
-                }
-            }
-        }
+        send_messages(messages).await
+    }
 
-        receivers
+    /**
+     * Collects clones of the user's sessions, so the message can be sent after the state
+     * lock is released.
+     */
+    pub fn get_user_messages(
+        &self,
+        user_id: i32,
+        message: &str,
+    ) -> Vec<(actix_ws::Session, String)> {
+        self.ws_connections
+            .values()
+            .filter(|session| session.user_id == Some(user_id))
+            .map(|session| (session.ws_session.clone(), message.to_string()))
+            .collect()
     }
 
     pub async fn send_storage_location_updated(
@@ -63,9 +69,32 @@ impl WSState {
         invalidate_entries: bool,
         invalidate_thumbs: bool,
     ) -> u32 {
-        let mut receivers = 0;
+        let messages = self.get_storage_location_updated_messages(
+            executor_user_id,
+            endpoint_id,
+            folder_ids,
+            invalidate_entries,
+            invalidate_thumbs,
+        );
+
+        send_messages(messages).await
+    }
 
-        for session in self.ws_connections.values_mut() {
+    /**
+     * Collects the "storage_location_updated" messages together with clones of the
+     * target sessions, so they can be sent after the state lock is released.
+     */
+    pub fn get_storage_location_updated_messages(
+        &self,
+        executor_user_id: Option<i32>,
+        endpoint_id: i32,
+        folder_ids: Vec<Option<i64>>,
+        invalidate_entries: bool,
+        invalidate_thumbs: bool,
+    ) -> Vec<(actix_ws::Session, String)> {
+        let mut messages = Vec::new();
+
+        for session in self.ws_connections.values() {
             // Don't notify the executor
             // TODO bug-prone check. Won't work how we expect for anonymous clients.
             if session.user_id.is_none()
@@ -81,36 +110,78 @@ impl WSState {
                         // menas that the user is currently in one of the target folders,
                         // so there is no reason to check it again on the client side -
                         // that check will always be true.
-                        let result = session
-                            .ws_session
-                            .text(
-                                json!(
-                                    {
-                                        "type": "storage_location_updated",
-                                        "payload": {
-                                            "endpoint_id": endpoint_id,
-                                            "folder_id": location.folder_id,
-                                            "invalidate_entries": invalidate_entries,
-                                            "invalidate_thumbs": invalidate_thumbs
-                                        }
+                        messages.push((
+                            session.ws_session.clone(),
+                            json!(
+                                {
+                                    "type": "storage_location_updated",
+                                    "payload": {
+                                        "endpoint_id": endpoint_id,
+                                        "folder_id": location.folder_id,
+                                        "invalidate_entries": invalidate_entries,
+                                        "invalidate_thumbs": invalidate_thumbs
                                     }
-                                )
-                                .to_string(),
+                                }
                             )
-                            .await;
-
-                        if result.is_ok() {
-                            receivers += 1;
-                        }
+                            .to_string(),
+                        ));
                     }
                 }
             }
         }
 
-        receivers
+        messages
     }
 }
 
+/**
+ * Same as WSState::send_storage_location_updated, but the state lock is only held
+ * while collecting the receivers, not while sending.
+ */
+pub async fn send_storage_location_updated(
+    ws_state: &Mutex<WSState>,
+    executor_user_id: Option<i32>,
+    endpoint_id: i32,
+    folder_ids: Vec<Option<i64>>,
+    invalidate_entries: bool,
+    invalidate_thumbs: bool,
+) -> u32 {
+    let messages = ws_state
+        .lock()
+        .unwrap()
+        .get_storage_location_updated_messages(
+            executor_user_id,
+            endpoint_id,
+            folder_ids,
+            invalidate_entries,
+            invalidate_thumbs,
+        );
+
+    send_messages(messages).await
+}
+
+/**
+ * Same as WSState::send_to_user, but the state lock is only held while collecting the
+ * user's sessions, not while sending.
+ */
+pub async fn send_to_user(ws_state: &Mutex<WSState>, user_id: i32, message: &str) -> u32 {
+    let messages = ws_state.lock().unwrap().get_user_messages(user_id, message);
+
+    send_messages(messages).await
+}
+
+async fn send_messages(messages: Vec<(actix_ws::Session, String)>) -> u32 {
+    let mut receivers = 0;
+
+    for (mut ws_session, message) in messages {
+        if ws_session.text(message).await.is_ok() {
+            receivers += 1;
+        }
+    }
+
+    receivers
+}
+
 #[derive(Serialize)]
 struct WSResponse {
     #[serde(rename = "type")]